@@ -0,0 +1,279 @@
+//! Parsing of BIP-21 payment URIs.
+//!
+//! Wallets encode payment requests, e.g., in QR codes, as URIs of the form
+//! `bitcoin:<address>?amount=<btc>&label=<label>`.
+//! See https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki.
+
+use crate::address::BitcoinAddress;
+use candid::{CandidType, Deserialize};
+use ic_btc_types::Network;
+use std::fmt;
+
+const SCHEME: &str = "bitcoin:";
+
+// The number of satoshis in one bitcoin.
+const SATOSHIS_PER_BTC: u64 = 100_000_000;
+
+// The number of decimal places of a satoshi amount expressed in bitcoins.
+const BTC_DECIMALS: usize = 8;
+
+/// A payment request decoded from a BIP-21 URI.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaymentUri {
+    /// The address to which the payment should go.
+    pub address: BitcoinAddress,
+    /// The requested amount in satoshis, if the URI specifies one.
+    pub amount: Option<u64>,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum ParseUriError {
+    /// The URI does not contain an address.
+    MissingAddress,
+    /// The address in the URI is not valid.
+    MalformedAddress(String),
+    /// The `amount` parameter is not a valid bitcoin amount.
+    MalformedAmount(String),
+    /// A parameter name or value is not a valid percent-encoded UTF-8 string.
+    MalformedEncoding(String),
+    /// The URI specifies the parameter more than once.
+    DuplicateParameter(String),
+    /// The URI contains a required parameter (one with the `req-` prefix)
+    /// that the minter does not understand.
+    UnsupportedRequiredParameter(String),
+}
+
+impl fmt::Display for ParseUriError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingAddress => write!(fmt, "the URI contains no address"),
+            Self::MalformedAddress(msg) => write!(fmt, "malformed address: {}", msg),
+            Self::MalformedAmount(amount) => write!(fmt, "malformed amount {}", amount),
+            Self::MalformedEncoding(s) => write!(fmt, "malformed percent-encoding {}", s),
+            Self::DuplicateParameter(name) => write!(fmt, "duplicate parameter {}", name),
+            Self::UnsupportedRequiredParameter(name) => {
+                write!(fmt, "unsupported required parameter {}", name)
+            }
+        }
+    }
+}
+
+/// Returns true if `destination` looks like a BIP-21 URI rather than a plain
+/// address. The scheme is case-insensitive.
+pub fn is_bip21_uri(destination: &str) -> bool {
+    destination
+        .get(..SCHEME.len())
+        .map_or(false, |scheme| scheme.eq_ignore_ascii_case(SCHEME))
+}
+
+impl PaymentUri {
+    /// Parses a BIP-21 URI and checks that its address belongs to the
+    /// specified network.
+    pub fn parse(uri: &str, network: Network) -> Result<PaymentUri, ParseUriError> {
+        if !is_bip21_uri(uri) {
+            return Err(ParseUriError::MissingAddress);
+        }
+        let rest = &uri[SCHEME.len()..];
+        let (address, query) = match rest.split_once('?') {
+            Some((address, query)) => (address, Some(query)),
+            None => (rest, None),
+        };
+        if address.is_empty() {
+            return Err(ParseUriError::MissingAddress);
+        }
+        let address = BitcoinAddress::parse(address, network)
+            .map_err(|e| ParseUriError::MalformedAddress(e.to_string()))?;
+
+        let mut amount = None;
+        for param in query.into_iter().flat_map(|q| q.split('&')) {
+            if param.is_empty() {
+                continue;
+            }
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            let name = percent_decode(name)?;
+            if name == "amount" {
+                if amount.is_some() {
+                    return Err(ParseUriError::DuplicateParameter(name));
+                }
+                amount = Some(parse_btc_amount(&percent_decode(value)?)?);
+            } else if name.starts_with("req-") {
+                return Err(ParseUriError::UnsupportedRequiredParameter(name));
+            }
+            // Other parameters, such as `label` and `message`, are informational.
+        }
+        Ok(PaymentUri { address, amount })
+    }
+}
+
+/// Decodes the percent-encoded octets of a URI parameter, e.g., "%20" to a
+/// space. Unlike in HTML forms, "+" stands for itself.
+fn percent_decode(s: &str) -> Result<String, ParseUriError> {
+    let malformed = || ParseUriError::MalformedEncoding(s.to_string());
+    let mut bytes = s.bytes();
+    let mut decoded = Vec::with_capacity(s.len());
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            decoded.push(b);
+            continue;
+        }
+        let high = bytes.next().and_then(|b| char::from(b).to_digit(16));
+        let low = bytes.next().and_then(|b| char::from(b).to_digit(16));
+        match (high, low) {
+            (Some(high), Some(low)) => decoded.push((high * 16 + low) as u8),
+            _ => return Err(malformed()),
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| malformed())
+}
+
+/// Converts a decimal bitcoin amount, e.g., "0.015", to satoshis.
+fn parse_btc_amount(amount: &str) -> Result<u64, ParseUriError> {
+    let malformed = || ParseUriError::MalformedAmount(amount.to_string());
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if whole.is_empty() || !is_digits(whole) || !is_digits(fraction) {
+        return Err(malformed());
+    }
+    if fraction.len() > BTC_DECIMALS || (amount.ends_with('.') && fraction.is_empty()) {
+        return Err(malformed());
+    }
+    let whole: u64 = whole.parse().map_err(|_| malformed())?;
+    let fraction: u64 = format!("{:0<width$}", fraction, width = BTC_DECIMALS)
+        .parse()
+        .map_err(|_| malformed())?;
+    whole
+        .checked_mul(SATOSHIS_PER_BTC)
+        .and_then(|sats| sats.checked_add(fraction))
+        .ok_or_else(malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_bip21_uri, ParseUriError, PaymentUri};
+    use crate::address::BitcoinAddress;
+    use ic_btc_types::Network;
+
+    const ADDRESS: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+
+    fn parse(uri: &str) -> Result<PaymentUri, ParseUriError> {
+        PaymentUri::parse(uri, Network::Mainnet)
+    }
+
+    fn amount(uri_amount: &str) -> Result<Option<u64>, ParseUriError> {
+        parse(&format!("bitcoin:{}?amount={}", ADDRESS, uri_amount))
+            .map(|uri| uri.amount)
+    }
+
+    #[test]
+    fn test_parse_uri() {
+        let address = BitcoinAddress::parse(ADDRESS, Network::Mainnet).unwrap();
+        assert!(is_bip21_uri("BITCOIN:x"));
+        assert!(!is_bip21_uri(ADDRESS));
+
+        assert_eq!(
+            Ok(PaymentUri {
+                address: address.clone(),
+                amount: None,
+            }),
+            parse(&format!("bitcoin:{}", ADDRESS))
+        );
+        assert_eq!(
+            Ok(PaymentUri {
+                address: address.clone(),
+                amount: Some(1_500_000),
+            }),
+            parse(&format!(
+                "bitcoin:{}?label=Luke-Jr&amount=0.015&message=Donation",
+                ADDRESS
+            ))
+        );
+        // QR codes use the uppercase form to fit the alphanumeric mode.
+        assert_eq!(
+            Ok(PaymentUri {
+                address,
+                amount: Some(100_000_000),
+            }),
+            parse(&format!("BITCOIN:{}?amount=1", ADDRESS.to_uppercase()))
+        );
+
+        assert_eq!(
+            Err(ParseUriError::MissingAddress),
+            parse("bitcoin:?amount=1")
+        );
+        assert!(matches!(
+            parse("bitcoin:tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"),
+            Err(ParseUriError::MalformedAddress(_))
+        ));
+        assert_eq!(
+            Err(ParseUriError::DuplicateParameter("amount".to_string())),
+            parse(&format!("bitcoin:{}?amount=1&amount=2", ADDRESS))
+        );
+        assert_eq!(
+            Err(ParseUriError::UnsupportedRequiredParameter(
+                "req-somethingyoudontunderstand".to_string()
+            )),
+            parse(&format!("bitcoin:{}?req-somethingyoudontunderstand=50", ADDRESS))
+        );
+        assert!(parse(&format!("bitcoin:{}?somethingyoudontunderstand=50", ADDRESS)).is_ok());
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(Ok(Some(5_000_000_000)), amount("50"));
+        assert_eq!(Ok(Some(2_030_000_000)), amount("20.3"));
+        assert_eq!(Ok(Some(1)), amount("0.00000001"));
+        assert_eq!(Ok(Some(0)), amount("0"));
+
+        for malformed in [
+            "",
+            ".1",
+            "1.",
+            "0.000000001",
+            "-1",
+            "1e3",
+            "1,5",
+            "0x10",
+            "184467440738",
+        ] {
+            assert_eq!(
+                Err(ParseUriError::MalformedAmount(malformed.to_string())),
+                amount(malformed),
+                "amount {:?}",
+                malformed
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_percent_encoded_params() {
+        assert_eq!(Ok(Some(1_500_000)), amount("0%2E015"));
+        assert_eq!(
+            Ok(Some(100_000_000)),
+            parse(&format!("bitcoin:{}?%61mount=1&label=Luke%20Jr", ADDRESS))
+                .map(|uri| uri.amount)
+        );
+        assert_eq!(
+            Err(ParseUriError::MalformedAmount("1 ".to_string())),
+            amount("1%20")
+        );
+        assert_eq!(
+            Err(ParseUriError::DuplicateParameter("amount".to_string())),
+            parse(&format!("bitcoin:{}?amount=1&%61mount=2", ADDRESS))
+        );
+        assert_eq!(
+            Err(ParseUriError::UnsupportedRequiredParameter(
+                "req-label".to_string()
+            )),
+            parse(&format!("bitcoin:{}?req%2Dlabel=x", ADDRESS))
+        );
+
+        for malformed in ["%", "1%2", "%G0", "%+1", "%C3%28"] {
+            assert_eq!(
+                Err(ParseUriError::MalformedEncoding(malformed.to_string())),
+                amount(malformed),
+                "amount {:?}",
+                malformed
+            );
+        }
+    }
+}
@@ -193,7 +193,7 @@ pub fn build_metadata() -> String {
                 .unwrap_or_default(),
             s.min_confirmations,
             s.ledger_id,
-            s.retrieve_btc_min_amount
+            s.fee_based_retrieve_btc_min_amount
         )
     })
 }
@@ -3,6 +3,7 @@ use crate::state::{
     CkBtcMinterState, FinalizedBtcRetrieval, FinalizedStatus, RetrieveBtcRequest,
     SubmittedBtcTransaction,
 };
+use candid::Principal;
 use ic_btc_types::Utxo;
 use ic_icrc1::Account;
 use serde::{Deserialize, Serialize};
@@ -22,6 +23,17 @@ pub enum Event {
         to_account: Account,
         #[serde(rename = "utxos")]
         utxos: Vec<Utxo>,
+        /// The index of the ledger block in which the minter minted ckBTC for
+        /// the UTXOs. The minter does not mint ckBTC for the change of its
+        /// own transactions. Events recorded before the minter tracked mint
+        /// block indices do not have this field even if the minter minted
+        /// ckBTC for the UTXOs.
+        #[serde(
+            rename = "mint_block_index",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        mint_block_index: Option<u64>,
     },
 
     /// Indicates that the minter accepted a new retrieve_btc request.
@@ -63,6 +75,46 @@ pub enum Event {
         #[serde(rename = "txid")]
         txid: [u8; 32],
     },
+
+    /// Indicates that a canister registered interest in mints to an account.
+    #[serde(rename = "subscribed_to_deposits")]
+    SubscribedToDeposits {
+        #[serde(rename = "subscriber")]
+        subscriber: Principal,
+        #[serde(rename = "account")]
+        account: Account,
+    },
+
+    /// Indicates that a canister is no longer interested in mints to an
+    /// account.
+    #[serde(rename = "unsubscribed_from_deposits")]
+    UnsubscribedFromDeposits {
+        #[serde(rename = "subscriber")]
+        subscriber: Principal,
+        #[serde(rename = "account")]
+        account: Account,
+    },
+
+    /// Indicates that the minter delivered a deposit notification or gave up
+    /// delivering it.
+    #[serde(rename = "removed_deposit_notification")]
+    RemovedDepositNotification {
+        #[serde(rename = "subscriber")]
+        subscriber: Principal,
+        #[serde(rename = "mint_block_index")]
+        mint_block_index: u64,
+        /// True if the subscriber accepted the notification.
+        #[serde(rename = "delivered")]
+        delivered: bool,
+    },
+
+    /// Indicates that the minter changed the minimum retrieve_btc amount
+    /// because the Bitcoin network fees changed.
+    #[serde(rename = "updated_retrieve_btc_min_amount")]
+    UpdatedRetrieveBtcMinAmount {
+        #[serde(rename = "min_amount")]
+        min_amount: u64,
+    },
 }
 
 #[derive(Debug)]
@@ -74,7 +126,19 @@ pub enum ReplayLogError {
 }
 
 /// Reconstructs the minter state from an event log.
-pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<CkBtcMinterState, ReplayLogError> {
+///
+/// The minter only receives change to its main account (the account of
+/// `minter_id` without a subaccount). A [Event::ReceivedUtxos] event without a
+/// mint block index for any other account comes from a version of the minter
+/// that did not record mint block indices and still accounts for minted ckBTC.
+pub fn replay(
+    mut events: impl Iterator<Item = Event>,
+    minter_id: Principal,
+) -> Result<CkBtcMinterState, ReplayLogError> {
+    let main_account = Account {
+        owner: minter_id.into(),
+        subaccount: None,
+    };
     let mut state = match events.next() {
         Some(Event::Init(args)) => CkBtcMinterState::from(args),
         Some(evt) => {
@@ -91,8 +155,21 @@ pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<CkBtcMinterStat
             Event::Init(args) => {
                 state.reinit(args);
             }
-            Event::ReceivedUtxos { to_account, utxos } => state.add_utxos(to_account, utxos),
+            Event::ReceivedUtxos {
+                to_account,
+                utxos,
+                mint_block_index,
+            } => {
+                let amount = utxos.iter().map(|u| u.value).sum();
+                match mint_block_index {
+                    Some(block_index) => state.record_mint(&to_account, block_index, amount),
+                    None if to_account != main_account => state.record_unindexed_mint(amount),
+                    None => (),
+                }
+                state.add_utxos(to_account, utxos);
+            }
             Event::AcceptedRetrieveBtcRequest(req) => {
+                state.record_burn(req.block_index, req.amount);
                 state.pending_retrieve_btc_requests.push_back(req);
             }
             Event::RemovedRetrieveBtcRequest { block_index } => {
@@ -137,6 +214,28 @@ pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<CkBtcMinterStat
             Event::ConfirmedBtcTransaction { txid } => {
                 state.finalize_transaction(&txid);
             }
+            Event::SubscribedToDeposits {
+                subscriber,
+                account,
+            } => {
+                state.subscribe_to_deposits(subscriber, account);
+            }
+            Event::UnsubscribedFromDeposits {
+                subscriber,
+                account,
+            } => {
+                state.unsubscribe_from_deposits(subscriber, &account);
+            }
+            Event::RemovedDepositNotification {
+                subscriber,
+                mint_block_index,
+                ..
+            } => {
+                state.remove_deposit_notification(subscriber, mint_block_index);
+            }
+            Event::UpdatedRetrieveBtcMinAmount { min_amount } => {
+                state.fee_based_retrieve_btc_min_amount = min_amount;
+            }
         }
     }
 
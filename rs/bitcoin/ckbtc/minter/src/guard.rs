@@ -92,6 +92,30 @@ impl Drop for HeartbeatGuard {
     }
 }
 
+/// Ensures that the minter delivers at most one deposit notification at a
+/// time to each subscriber.
+#[must_use]
+pub struct DepositNotificationGuard {
+    subscriber: Principal,
+}
+
+impl DepositNotificationGuard {
+    pub fn new(subscriber: Principal) -> Option<Self> {
+        mutate_state(|s| {
+            if !s.deposit_notifications_in_flight.insert(subscriber) {
+                return None;
+            }
+            Some(Self { subscriber })
+        })
+    }
+}
+
+impl Drop for DepositNotificationGuard {
+    fn drop(&mut self) {
+        mutate_state(|s| s.deposit_notifications_in_flight.remove(&self.subscriber));
+    }
+}
+
 pub fn balance_update_guard(p: Principal) -> Result<Guard<PendingBalanceUpdates>, GuardError> {
     Guard::new(p)
 }
@@ -7,6 +7,7 @@ use serde_bytes::ByteBuf;
 use std::collections::{BTreeMap, BTreeSet};
 
 pub mod address;
+pub mod bip21;
 pub mod dashboard;
 pub mod eventlog;
 pub mod guard;
@@ -14,6 +15,7 @@ pub mod lifecycle;
 pub mod management;
 pub mod metrics;
 pub mod queries;
+pub mod reserves;
 pub mod signature;
 pub mod state;
 pub mod storage;
@@ -29,6 +31,30 @@ pub struct ECDSAPublicKey {
     pub chain_code: Vec<u8>,
 }
 
+/// The number of times the minter tries to deliver a deposit notification
+/// before giving up.
+pub const MAX_DEPOSIT_NOTIFICATION_ATTEMPTS: u32 = 10;
+
+/// The maximum number of deposit notifications that the minter delivers
+/// concurrently.
+const MAX_CONCURRENT_DEPOSIT_NOTIFICATIONS: usize = 10;
+
+/// The estimated virtual size in vbytes of a retrieve_btc transaction with
+/// three P2WPKH inputs and two outputs.
+const RETRIEVE_BTC_TX_VSIZE_ESTIMATE: u64 = 280;
+
+/// The fee-based minimum retrieve_btc amount is a multiple of this amount, so
+/// that small fee fluctuations do not change it.
+const RETRIEVE_BTC_MIN_AMOUNT_STEP: u64 = 10_000;
+
+/// How often the minter refreshes the fee-based minimum retrieve_btc amount
+/// if there are no retrieve_btc requests to serve.
+const MIN_AMOUNT_REFRESH_INTERVAL_NANOS: u64 = 10 * 60 * 1_000_000_000;
+
+/// The maximum number of Bitcoin transactions that the minter signs and sends
+/// concurrently.
+const MAX_CONCURRENT_TRANSACTIONS: usize = 5;
+
 struct SignTxRequest {
     key_name: String,
     network: Network,
@@ -90,26 +116,78 @@ async fn fetch_main_utxos(main_account: &Account, main_address: &BitcoinAddress)
     storage::record_event(&eventlog::Event::ReceivedUtxos {
         to_account: main_account.clone(),
         utxos: new_utxos.clone(),
+        mint_block_index: None,
     });
 
     state::mutate_state(|s| s.add_utxos(main_account.clone(), new_utxos));
 }
 
+/// Returns the minimum retrieve_btc amount for the given median fee.
+///
+/// The minimum is `retrieve_btc_min_amount` as long as it exceeds the
+/// estimated fees of a retrieve_btc transaction. Otherwise, it is the fees
+/// rounded up to the next multiple of [RETRIEVE_BTC_MIN_AMOUNT_STEP], so that
+/// the fees never exceed the withdrawn amount.
+pub fn compute_min_amount(median_fee: MillisatoshiPerByte, retrieve_btc_min_amount: u64) -> u64 {
+    let fee = RETRIEVE_BTC_TX_VSIZE_ESTIMATE.saturating_mul(median_fee) / 1000;
+    if fee < retrieve_btc_min_amount {
+        return retrieve_btc_min_amount;
+    }
+    (fee / RETRIEVE_BTC_MIN_AMOUNT_STEP + 1).saturating_mul(RETRIEVE_BTC_MIN_AMOUNT_STEP)
+}
+
+/// Adjusts the fee-based minimum retrieve_btc amount to the given median fee
+/// and records the change in the event log.
+fn update_retrieve_btc_min_amount(median_fee: MillisatoshiPerByte) {
+    let (current_min_amount, retrieve_btc_min_amount) =
+        state::read_state(|s| (s.fee_based_retrieve_btc_min_amount, s.retrieve_btc_min_amount));
+    let new_min_amount = compute_min_amount(median_fee, retrieve_btc_min_amount);
+    if new_min_amount == current_min_amount {
+        return;
+    }
+
+    ic_cdk::print(format!(
+        "[heartbeat]: changing the minimum retrieve_btc amount from {} to {} (median fee: {} millisatoshi/vbyte)",
+        current_min_amount,
+        new_min_amount,
+        median_fee
+    ));
+    storage::record_event(&eventlog::Event::UpdatedRetrieveBtcMinAmount {
+        min_amount: new_min_amount,
+    });
+    state::mutate_state(|s| s.fee_based_retrieve_btc_min_amount = new_min_amount);
+}
+
+/// Refreshes the fee-based minimum retrieve_btc amount if the minter did not
+/// estimate the fees for [MIN_AMOUNT_REFRESH_INTERVAL_NANOS].
+async fn refresh_retrieve_btc_min_amount() {
+    let last_refresh = state::read_state(|s| s.last_min_amount_refresh);
+    if ic_cdk::api::time().saturating_sub(last_refresh) < MIN_AMOUNT_REFRESH_INTERVAL_NANOS {
+        return;
+    }
+    estimate_fee_per_vbyte().await;
+}
+
 /// Returns an estimate for transaction fees in millisatoshi per vbyte.  Returns
 /// None if the bitcoin canister is unavailable or does not have enough data for
 /// an estimate yet.
+///
+/// Every estimate based on the fee percentiles also adjusts the fee-based
+/// minimum retrieve_btc amount.
 async fn estimate_fee_per_vbyte() -> Option<MillisatoshiPerByte> {
     /// The default fee we use on regtest networks if there are not enough data
     /// to compute the median fee.
     const DEFAULT_FEE: MillisatoshiPerByte = 5_000;
 
     let btc_network = state::read_state(|s| s.btc_network);
+    state::mutate_state(|s| s.last_min_amount_refresh = ic_cdk::api::time());
     match management::get_current_fees(btc_network).await {
         Ok(fees) => {
             if btc_network == Network::Regtest {
                 return Some(DEFAULT_FEE);
             }
             if fees.len() >= 100 {
+                update_retrieve_btc_min_amount(fees[49]);
                 Some(fees[49])
             } else {
                 ic_cdk::print(format!(
@@ -165,7 +243,34 @@ async fn submit_pending_requests() {
 
     fetch_main_utxos(&main_account, &main_address).await;
 
-    let maybe_sign_request = state::mutate_state(|s| {
+    // Each transaction takes its inputs out of the available UTXO set, so
+    // transactions in flight never spend the same UTXO. We look at every
+    // pending request at most once per heartbeat because requests we cannot
+    // serve go back to the end of the queue.
+    let pending_count = state::read_state(|s| s.pending_retrieve_btc_requests.len());
+    for _ in 0..pending_count {
+        if state::read_state(|s| s.transactions_in_flight()) >= MAX_CONCURRENT_TRANSACTIONS {
+            break;
+        }
+
+        if let Some(req) = build_sign_request(
+            main_address.clone(),
+            ecdsa_public_key.clone(),
+            fee_millisatoshi_per_vbyte,
+        ) {
+            ic_cdk::spawn(sign_and_send_transaction(req));
+        }
+    }
+}
+
+/// Takes the next pending retrieve_btc request and builds a transaction
+/// serving it. Returns None if there are no requests that the minter can serve.
+fn build_sign_request(
+    main_address: BitcoinAddress,
+    ecdsa_public_key: ECDSAPublicKey,
+    fee_millisatoshi_per_vbyte: MillisatoshiPerByte,
+) -> Option<SignTxRequest> {
+    state::mutate_state(|s| {
         match s.pending_retrieve_btc_requests.pop_front() {
             Some(req) => {
                 match build_unsigned_transaction(
@@ -175,7 +280,7 @@ async fn submit_pending_requests() {
                     fee_millisatoshi_per_vbyte,
                 ) {
                     Ok((unsigned_tx, utxos)) => {
-                        s.push_in_flight_request(req.block_index, state::InFlightStatus::Signing);
+                        s.push_in_flight_request(&req, state::InFlightStatus::Signing);
 
                         Some(SignTxRequest {
                             key_name: s.ecdsa_key_name.clone(),
@@ -218,80 +323,78 @@ async fn submit_pending_requests() {
             }
             None => None,
         }
-    });
+    })
+}
 
-    if let Some(req) = maybe_sign_request {
-        ic_cdk::print(format!(
-            "[heartbeat]: signing a new transaction: {}",
-            hex::encode(tx::encode_into(&req.unsigned_tx, Vec::new()))
-        ));
-
-        let txid = req.unsigned_tx.txid();
-
-        match sign_transaction(
-            req.key_name,
-            &req.ecdsa_public_key,
-            &req.outpoint_account,
-            req.unsigned_tx,
-        )
-        .await
-        {
-            Ok(signed_tx) => {
-                state::mutate_state(|s| {
-                    for retrieve_req in req.requests.iter() {
-                        s.push_in_flight_request(
-                            retrieve_req.block_index,
-                            state::InFlightStatus::Sending { txid },
-                        );
-                    }
-                });
+/// Signs the transaction and sends it to the Bitcoin network. Puts the
+/// requests and the UTXOs back if either step fails.
+async fn sign_and_send_transaction(req: SignTxRequest) {
+    ic_cdk::print(format!(
+        "[heartbeat]: signing a new transaction: {}",
+        hex::encode(tx::encode_into(&req.unsigned_tx, Vec::new()))
+    ));
+
+    let txid = req.unsigned_tx.txid();
+
+    match sign_transaction(
+        req.key_name,
+        &req.ecdsa_public_key,
+        &req.outpoint_account,
+        req.unsigned_tx,
+    )
+    .await
+    {
+        Ok(signed_tx) => {
+            state::mutate_state(|s| {
+                for retrieve_req in req.requests.iter() {
+                    s.push_in_flight_request(
+                        retrieve_req,
+                        state::InFlightStatus::Sending { txid },
+                    );
+                }
+            });
 
-                ic_cdk::print(format!(
-                    "[heartbeat]: sending a signed transaction {}",
-                    hex::encode(tx::encode_into(&signed_tx, Vec::new()))
-                ));
-                match management::send_transaction(&signed_tx, req.network).await {
-                    Ok(()) => {
-                        ic_cdk::print(format!(
-                            "[heartbeat]: successfully sent transaction {}",
-                            hex::encode(txid)
-                        ));
-                        let submitted_at = ic_cdk::api::time();
-                        storage::record_event(&eventlog::Event::SentBtcTransaction {
-                            request_block_indices: req
-                                .requests
-                                .iter()
-                                .map(|r| r.block_index)
-                                .collect(),
+            ic_cdk::print(format!(
+                "[heartbeat]: sending a signed transaction {}",
+                hex::encode(tx::encode_into(&signed_tx, Vec::new()))
+            ));
+            match management::send_transaction(&signed_tx, req.network).await {
+                Ok(()) => {
+                    ic_cdk::print(format!(
+                        "[heartbeat]: successfully sent transaction {}",
+                        hex::encode(txid)
+                    ));
+                    let submitted_at = ic_cdk::api::time();
+                    storage::record_event(&eventlog::Event::SentBtcTransaction {
+                        request_block_indices: req.requests.iter().map(|r| r.block_index).collect(),
+                        txid,
+                        utxos: req.utxos.clone(),
+                        submitted_at,
+                    });
+                    state::mutate_state(|s| {
+                        s.push_submitted_transaction(state::SubmittedBtcTransaction {
+                            requests: req.requests,
                             txid,
-                            utxos: req.utxos.clone(),
+                            used_utxos: req.utxos,
                             submitted_at,
                         });
-                        state::mutate_state(|s| {
-                            s.push_submitted_transaction(state::SubmittedBtcTransaction {
-                                requests: req.requests,
-                                txid,
-                                used_utxos: req.utxos,
-                                submitted_at,
-                            });
-                        });
-                    }
-                    Err(err) => {
-                        ic_cdk::print(format!(
-                            "[heartbeat]: failed to send a bitcoin transaction: {}",
-                            err
-                        ));
-                        undo_sign_request(req.requests, req.utxos);
-                    }
+                    });
+                }
+                Err(err) => {
+                    ic_cdk::print(format!(
+                        "[heartbeat]: failed to send a bitcoin transaction: {}",
+                        err
+                    ));
+                    undo_sign_request(req.requests, req.utxos);
                 }
             }
-            Err(err) => {
-                ic_cdk::print(format!(
-                    "[heartbeat]: failed to sign a BTC transaction: {}",
-                    err
-                ));
-                undo_sign_request(req.requests, req.utxos);
-            }
+        }
+        Err(err) => {
+            ic_cdk::print(format!(
+                "[heartbeat]: failed to sign a BTC transaction: {}",
+                err
+            ));
+            undo_sign_request(req.requests, req.utxos);
         }
     }
 }
@@ -390,12 +493,81 @@ async fn finalize_requests() {
     }
 }
 
+/// Starts delivering pending deposit notifications to subscriber canisters.
+///
+/// Every notification is delivered by its own task, so that a subscriber
+/// that does not reply does not hold up the heartbeat. The minter retries
+/// failed deliveries in later heartbeats and gives up after
+/// [MAX_DEPOSIT_NOTIFICATION_ATTEMPTS] attempts.
+fn notify_deposit_subscribers() {
+    let in_flight = state::read_state(|s| s.deposit_notifications_in_flight.len());
+    let max = MAX_CONCURRENT_DEPOSIT_NOTIFICATIONS.saturating_sub(in_flight);
+    for notification in state::read_state(|s| s.deliverable_deposit_notifications(max)) {
+        if let Some(guard) = guard::DepositNotificationGuard::new(notification.subscriber) {
+            ic_cdk::spawn(deliver_deposit_notification(notification, guard));
+        }
+    }
+}
+
+async fn deliver_deposit_notification(
+    notification: state::PendingDepositNotification,
+    _guard: guard::DepositNotificationGuard,
+) {
+    let arg = updates::subscribe_to_deposits::DepositNotification {
+        account: notification.account.clone(),
+        block_index: notification.mint_block_index,
+        amount: notification.amount,
+    };
+    let result: Result<(), _> = ic_cdk::api::call::call(
+        notification.subscriber,
+        updates::subscribe_to_deposits::DEPOSIT_NOTIFICATION_METHOD,
+        (arg,),
+    )
+    .await;
+
+    let delivered = match result {
+        Ok(()) => true,
+        Err((code, msg)) => {
+            let failed_attempts = state::mutate_state(|s| {
+                s.record_failed_deposit_notification(
+                    notification.subscriber,
+                    notification.mint_block_index,
+                )
+            });
+            ic_cdk::print(format!(
+                "[heartbeat]: failed to notify {} about mint {} (attempt {}): {:?} {}",
+                notification.subscriber,
+                notification.mint_block_index,
+                failed_attempts,
+                code,
+                msg
+            ));
+            if failed_attempts < MAX_DEPOSIT_NOTIFICATION_ATTEMPTS {
+                return;
+            }
+            false
+        }
+    };
+
+    storage::record_event(&eventlog::Event::RemovedDepositNotification {
+        subscriber: notification.subscriber,
+        mint_block_index: notification.mint_block_index,
+        delivered,
+    });
+    state::mutate_state(|s| {
+        s.remove_deposit_notification(notification.subscriber, notification.mint_block_index)
+    });
+}
+
 pub async fn heartbeat() {
+    notify_deposit_subscribers();
+
     let _heartbeat_guard = match guard::HeartbeatGuard::new() {
         Some(guard) => guard,
         None => return,
     };
 
+    refresh_retrieve_btc_min_amount().await;
     submit_pending_requests().await;
     finalize_requests().await;
 }
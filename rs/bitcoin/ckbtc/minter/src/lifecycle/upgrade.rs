@@ -7,7 +7,7 @@ pub fn post_upgrade() {
 
     let start = ic_cdk::api::instruction_counter();
 
-    replace_state(replay(events()).unwrap_or_else(|e| {
+    replace_state(replay(events(), ic_cdk::id()).unwrap_or_else(|e| {
         ic_cdk::trap(&format!(
             "[upgrade]: failed to replay the event log: {:?}",
             e
@@ -4,12 +4,16 @@ use ic_cdk_macros::{heartbeat, init, post_upgrade, query, update};
 use ic_ckbtc_minter::dashboard::build_dashboard;
 use ic_ckbtc_minter::lifecycle::{self, init::InitArgs};
 use ic_ckbtc_minter::metrics::encode_metrics;
-use ic_ckbtc_minter::queries::RetrieveBtcStatusRequest;
+use ic_ckbtc_minter::queries::{MinterInfo, RetrieveBtcStatusRequest};
+use ic_ckbtc_minter::reserves::{
+    self, GetReservesSummaryArgs, ReservesSummaryError, ReservesSummaryResponse,
+};
 use ic_ckbtc_minter::state::{read_state, RetrieveBtcStatus};
 use ic_ckbtc_minter::updates::retrieve_btc::{RetrieveBtcArgs, RetrieveBtcError, RetrieveBtcOk};
 use ic_ckbtc_minter::updates::{
     self,
     get_btc_address::GetBtcAddressArgs,
+    subscribe_to_deposits::SubscribeError,
     update_balance::{UpdateBalanceArgs, UpdateBalanceError, UpdateBalanceResult},
 };
 use ic_ckbtc_minter::{eventlog::Event, storage};
@@ -19,6 +23,7 @@ use ic_icrc1::Account;
 fn init(args: InitArgs) {
     storage::record_event(&Event::Init(args.clone()));
     lifecycle::init::init(args);
+    reserves::certify_reserves_summary();
 
     #[cfg(feature = "self_check")]
     ok_or_die(check_invariants())
@@ -41,13 +46,14 @@ fn check_invariants() -> Result<(), String> {
         s.check_invariants()?;
 
         let events: Vec<_> = storage::events().collect();
-        let recovered_state = replay(events.clone().into_iter())
+        let recovered_state = replay(events.clone().into_iter(), ic_cdk::id())
             .unwrap_or_else(|e| panic!("failed to replay log {:?}: {:?}", events, e));
 
         recovered_state.check_invariants()?;
 
-        // A running heartbeat can temporarily violate invariants.
-        if !s.is_heartbeat_running {
+        // A running heartbeat or a transaction in flight can temporarily
+        // violate invariants.
+        if !s.is_heartbeat_running && s.transactions_in_flight() == 0 {
             s.check_semantically_eq(&recovered_state)?;
         }
 
@@ -56,6 +62,8 @@ fn check_invariants() -> Result<(), String> {
 }
 
 fn check_postcondition<T>(t: T) -> T {
+    reserves::certify_reserves_summary();
+
     #[cfg(feature = "self_check")]
     ok_or_die(check_invariants());
     t
@@ -67,11 +75,13 @@ async fn heartbeat() {
     ok_or_die(check_invariants());
 
     ic_ckbtc_minter::heartbeat().await;
+    reserves::certify_reserves_summary();
 }
 
 #[post_upgrade]
 fn post_upgrade() {
-    lifecycle::upgrade::post_upgrade()
+    lifecycle::upgrade::post_upgrade();
+    reserves::certify_reserves_summary();
 }
 
 #[candid_method(update)]
@@ -98,6 +108,36 @@ fn retrieve_btc_status(req: RetrieveBtcStatusRequest) -> RetrieveBtcStatus {
     read_state(|s| s.retrieve_btc_status(req.block_index))
 }
 
+#[candid_method(update)]
+#[update]
+fn subscribe_to_deposits(account: Account) -> Result<(), SubscribeError> {
+    check_postcondition(updates::subscribe_to_deposits(account))
+}
+
+#[candid_method(update)]
+#[update]
+fn unsubscribe_from_deposits(account: Account) {
+    updates::unsubscribe_from_deposits(account);
+    check_postcondition(())
+}
+
+#[candid_method(query)]
+#[query]
+fn get_minter_info() -> MinterInfo {
+    read_state(|s| MinterInfo {
+        min_confirmations: s.min_confirmations,
+        retrieve_btc_min_amount: s.fee_based_retrieve_btc_min_amount,
+    })
+}
+
+#[candid_method(query)]
+#[query]
+fn get_reserves_summary(
+    args: GetReservesSummaryArgs,
+) -> Result<ReservesSummaryResponse, ReservesSummaryError> {
+    reserves::get_reserves_summary(args)
+}
+
 #[candid_method(update)]
 #[update]
 async fn update_balance(
@@ -129,6 +169,15 @@ fn http_request(req: HttpRequest) -> HttpResponse {
             .header("Content-Type", "text/html; charset=utf-8")
             .with_body_and_content_length(dashboard)
             .build()
+    } else if req.path() == "/reserves" {
+        match reserves::certified_reserves_summary() {
+            Some(summary) => HttpResponseBuilder::ok()
+                .header("Content-Type", "application/json")
+                .with_body_and_content_length(summary.to_json())
+                .build(),
+            None => HttpResponseBuilder::server_error("The reserves summary is not certified")
+                .build(),
+        }
     } else {
         HttpResponseBuilder::not_found().build()
     }
@@ -65,9 +65,21 @@ pub fn encode_metrics(
         "Total number of finalized retrieve_btc requests.",
     )?;
 
+    metrics.encode_gauge(
+        "ckbtc_minter_pending_deposit_notifications",
+        state::read_state(|s| s.pending_deposit_notifications.len()) as f64,
+        "Total number of deposit notifications the minter did not deliver yet.",
+    )?;
+
+    metrics.encode_counter(
+        "ckbtc_minter_dropped_deposit_notifications",
+        state::read_state(|s| s.dropped_deposit_notifications) as f64,
+        "Total number of deposit notifications the minter dropped because the queue was full.",
+    )?;
+
     metrics.encode_gauge(
         "ckbtc_minter_min_retrievable_amount",
-        state::read_state(|s| s.retrieve_btc_min_amount) as f64,
+        state::read_state(|s| s.fee_based_retrieve_btc_min_amount) as f64,
         "Minimum number of ckBTC a user can withdraw.",
     )?;
 
@@ -5,3 +5,13 @@ use serde::Deserialize;
 pub struct RetrieveBtcStatusRequest {
     pub block_index: u64,
 }
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MinterInfo {
+    /// The minimum number of confirmations on the Bitcoin chain.
+    pub min_confirmations: u32,
+    /// The minimum amount that retrieve_btc currently accepts. The minter
+    /// raises the configured minimum while the Bitcoin network fees are
+    /// high.
+    pub retrieve_btc_min_amount: u64,
+}
@@ -0,0 +1,289 @@
+//! A certified summary of the reserves backing ckBTC.
+//!
+//! The minter sets its certified data to the SHA-256 hash of the Candid
+//! encoding of the [ReservesSummary]. Proof-of-reserves verifiers fetch the
+//! summary with the `get_reserves_summary` query, check the certificate
+//! against the IC root key, and compare the hash of the last summary in the
+//! response with the certified data of the minter.
+//!
+//! The minter keeps the last [MAX_CERTIFIED_SUMMARIES] summaries it
+//! certified. Every summary contains the hash of the summary certified before
+//! it, so verifiers can check an older summary against the certified data by
+//! following the chain of hashes. The minter does not persist the summaries
+//! across upgrades: the first summary after an upgrade starts a new chain.
+use crate::state::{read_state, CkBtcMinterState, InFlightStatus, RetrieveBtcRequest};
+use candid::{CandidType, Deserialize};
+use ic_btc_types::Utxo;
+use ic_crypto_sha::Sha256;
+use serde::Serialize;
+use serde_bytes::ByteBuf;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt::Write;
+
+thread_local! {
+    static CERTIFIED_SUMMARIES: RefCell<VecDeque<ReservesSummary>> = RefCell::default();
+}
+
+/// The version of the [ReservesSummary] schema. We bump the version on every
+/// change that verifiers must be aware of.
+pub const RESERVES_SUMMARY_VERSION: u32 = 2;
+
+/// The maximum number of certified summaries that the minter keeps.
+pub const MAX_CERTIFIED_SUMMARIES: usize = 100;
+
+/// A retrieve_btc request for which the minter burned ckBTC but did not yet
+/// finalize the Bitcoin transaction.
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PendingWithdrawal {
+    /// The index of the ledger block that burned the ckBTC.
+    pub block_index: u64,
+    /// The amount of the withdrawal in satoshi.
+    pub amount: u64,
+    /// The destination Bitcoin address.
+    pub address: String,
+    /// The identifier of the Bitcoin transaction paying out the withdrawal,
+    /// if the minter already submitted it.
+    pub txid: Option<ByteBuf>,
+}
+
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReservesSummary {
+    pub version: u32,
+    /// The SHA-256 hash of the Candid encoding of the summary that the minter
+    /// certified before this one, if any.
+    pub previous_summary_hash: Option<ByteBuf>,
+    /// The index of the last ledger block in which the minter minted or
+    /// burned ckBTC. The summary reflects the ledger state at this block.
+    pub ledger_block_index: Option<u64>,
+    /// All UTXOs that the minter manages, including UTXOs spent by
+    /// transactions that are not finalized yet.
+    pub utxos: Vec<Utxo>,
+    pub total_utxo_value: u64,
+    /// The total amount of ckBTC minted minus the total amount burned.
+    pub total_supply: u64,
+    pub pending_withdrawals: Vec<PendingWithdrawal>,
+    pub total_pending_withdrawal_amount: u64,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize)]
+pub struct ReservesSummaryResponse {
+    pub summary: ReservesSummary,
+    /// The Candid encoding of `summary`.
+    pub encoded_summary: ByteBuf,
+    /// The Candid encodings of the summaries that the minter certified after
+    /// `summary`, oldest first. The certified data of the minter is the
+    /// SHA-256 hash of the last of these summaries, or of `encoded_summary`
+    /// if there are none.
+    pub later_summaries: Vec<ByteBuf>,
+    /// The certificate of the certified data. Only available in queries.
+    pub certificate: Option<ByteBuf>,
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize)]
+pub struct GetReservesSummaryArgs {
+    /// The ledger block index at which to report the reserves. Defaults to
+    /// the last certified summary.
+    pub ledger_block_index: Option<u64>,
+}
+
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, Deserialize)]
+pub enum ReservesSummaryError {
+    /// The minter did not certify any summary yet.
+    NotCertified,
+    /// The minter no longer keeps a summary for the requested block index.
+    BlockIndexTooOld {
+        /// The ledger block index of the oldest summary that the minter keeps.
+        oldest_ledger_block_index: Option<u64>,
+    },
+}
+
+impl ReservesSummary {
+    /// Builds the summary of the current minter state without a link to the
+    /// previous summary. Fails if the minter burned more ckBTC than it
+    /// minted, which means that its event log is incomplete.
+    pub fn from_state(s: &CkBtcMinterState) -> Result<Self, String> {
+        let utxos: BTreeSet<&Utxo> = s.utxos_state_addresses.values().flatten().collect();
+        let utxos: Vec<Utxo> = utxos.into_iter().cloned().collect();
+
+        let to_withdrawal = |req: &RetrieveBtcRequest, txid: Option<&[u8; 32]>| {
+            PendingWithdrawal {
+                block_index: req.block_index,
+                amount: req.amount,
+                address: req.address.display(s.btc_network),
+                txid: txid.map(|id| ByteBuf::from(id.to_vec())),
+            }
+        };
+
+        let mut pending_withdrawals: Vec<PendingWithdrawal> = s
+            .pending_retrieve_btc_requests
+            .iter()
+            .map(|req| to_withdrawal(req, None))
+            .chain(
+                s.in_flight_retrieve_btc_requests
+                    .iter()
+                    .map(|(block_index, req)| {
+                        let txid = match s.requests_in_flight.get(block_index) {
+                            Some(InFlightStatus::Sending { txid }) => Some(txid),
+                            _ => None,
+                        };
+                        to_withdrawal(req, txid)
+                    }),
+            )
+            .chain(s.submitted_transactions.iter().flat_map(|tx| {
+                tx.requests
+                    .iter()
+                    .map(move |req| to_withdrawal(req, Some(&tx.txid)))
+            }))
+            .collect();
+        pending_withdrawals.sort_by_key(|w| w.block_index);
+
+        let total_supply = s.tokens_minted.checked_sub(s.tokens_burned).ok_or_else(|| {
+            format!(
+                "burned {} ckBTC but minted only {}",
+                s.tokens_burned, s.tokens_minted
+            )
+        })?;
+
+        Ok(Self {
+            version: RESERVES_SUMMARY_VERSION,
+            previous_summary_hash: None,
+            ledger_block_index: s.last_ledger_block_index,
+            total_utxo_value: utxos.iter().map(|u| u.value).sum(),
+            utxos,
+            total_supply,
+            total_pending_withdrawal_amount: pending_withdrawals.iter().map(|w| w.amount).sum(),
+            pending_withdrawals,
+        })
+    }
+
+    /// Returns the Candid encoding of the summary that the certified data
+    /// commits to.
+    pub fn encode(&self) -> Vec<u8> {
+        candid::encode_one(self).expect("failed to encode the reserves summary")
+    }
+
+    /// Returns the summary as a JSON object with the same field names as the
+    /// Candid type. Transaction identifiers are hex-encoded.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        write!(
+            json,
+            "{{\"version\":{},\"previous_summary_hash\":{},\"ledger_block_index\":{},\"utxos\":[",
+            self.version,
+            json_opt(
+                self.previous_summary_hash
+                    .as_ref()
+                    .map(|h| format!("\"{}\"", hex::encode(h)))
+            ),
+            json_opt(self.ledger_block_index)
+        )
+        .unwrap();
+        for (i, utxo) in self.utxos.iter().enumerate() {
+            write!(
+                json,
+                "{}{{\"txid\":\"{}\",\"vout\":{},\"value\":{},\"height\":{}}}",
+                if i == 0 { "" } else { "," },
+                hex::encode(&utxo.outpoint.txid),
+                utxo.outpoint.vout,
+                utxo.value,
+                utxo.height
+            )
+            .unwrap();
+        }
+        write!(
+            json,
+            "],\"total_utxo_value\":{},\"total_supply\":{},\"pending_withdrawals\":[",
+            self.total_utxo_value, self.total_supply
+        )
+        .unwrap();
+        for (i, w) in self.pending_withdrawals.iter().enumerate() {
+            write!(
+                json,
+                "{}{{\"block_index\":{},\"amount\":{},\"address\":\"{}\",\"txid\":{}}}",
+                if i == 0 { "" } else { "," },
+                w.block_index,
+                w.amount,
+                w.address,
+                json_opt(w.txid.as_ref().map(|id| format!("\"{}\"", hex::encode(id))))
+            )
+            .unwrap();
+        }
+        write!(
+            json,
+            "],\"total_pending_withdrawal_amount\":{}}}",
+            self.total_pending_withdrawal_amount
+        )
+        .unwrap();
+        json
+    }
+}
+
+fn json_opt<T: std::fmt::Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "null".to_string(), |v| v.to_string())
+}
+
+/// Certifies the reserves summary of the current minter state if it differs
+/// from the last certified summary. Must be called after every state change
+/// affecting the summary.
+pub fn certify_reserves_summary() {
+    let mut summary = match read_state(ReservesSummary::from_state) {
+        Ok(summary) => summary,
+        Err(err) => {
+            ic_cdk::println!("[reserves]: cannot certify the reserves summary: {}", err);
+            return;
+        }
+    };
+    CERTIFIED_SUMMARIES.with(|c| {
+        let mut summaries = c.borrow_mut();
+        if let Some(last) = summaries.back() {
+            summary.previous_summary_hash = last.previous_summary_hash.clone();
+            if &summary == last {
+                return;
+            }
+            let last_hash = Sha256::hash(&last.encode());
+            summary.previous_summary_hash = Some(ByteBuf::from(last_hash.to_vec()));
+        }
+        ic_cdk::api::set_certified_data(&Sha256::hash(&summary.encode()));
+        if summaries.len() >= MAX_CERTIFIED_SUMMARIES {
+            summaries.pop_front();
+        }
+        summaries.push_back(summary);
+    });
+}
+
+/// Returns the last certified reserves summary.
+pub fn certified_reserves_summary() -> Option<ReservesSummary> {
+    CERTIFIED_SUMMARIES.with(|c| c.borrow().back().cloned())
+}
+
+/// Returns the last certified summary at or before the specified ledger block
+/// index together with the summaries certified after it.
+pub fn get_reserves_summary(
+    args: GetReservesSummaryArgs,
+) -> Result<ReservesSummaryResponse, ReservesSummaryError> {
+    CERTIFIED_SUMMARIES.with(|c| {
+        let summaries = c.borrow();
+        let oldest = summaries.front().ok_or(ReservesSummaryError::NotCertified)?;
+        let pos = match args.ledger_block_index {
+            Some(block_index) => summaries
+                .iter()
+                .rposition(|s| s.ledger_block_index.map_or(true, |i| i <= block_index))
+                .ok_or(ReservesSummaryError::BlockIndexTooOld {
+                    oldest_ledger_block_index: oldest.ledger_block_index,
+                })?,
+            None => summaries.len() - 1,
+        };
+        let summary = summaries[pos].clone();
+        Ok(ReservesSummaryResponse {
+            encoded_summary: ByteBuf::from(summary.encode()),
+            summary,
+            later_summaries: summaries
+                .iter()
+                .skip(pos + 1)
+                .map(|s| ByteBuf::from(s.encode()))
+                .collect(),
+            certificate: ic_cdk::api::data_certificate().map(ByteBuf::from),
+        })
+    })
+}
@@ -41,6 +41,10 @@ macro_rules! ensure {
 /// history.
 const MAX_FINALIZED_REQUESTS: usize = 100;
 
+/// The maximum number of deposit notifications that the minter keeps in the
+/// queue. The minter drops notifications about mints if the queue is full.
+pub const MAX_PENDING_DEPOSIT_NOTIFICATIONS: usize = 10_000;
+
 thread_local! {
     static __STATE: RefCell<Option<CkBtcMinterState>> = RefCell::default();
 }
@@ -91,6 +95,22 @@ pub enum InFlightStatus {
     Sending { txid: [u8; 32] },
 }
 
+/// A notification about a completed mint that the minter owes to a subscriber
+/// canister.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingDepositNotification {
+    /// The canister that registered interest in the account.
+    pub subscriber: Principal,
+    /// The account that received the minted ckBTC.
+    pub account: Account,
+    /// The index of the mint block on the ckBTC ledger.
+    pub mint_block_index: u64,
+    /// The amount of minted ckBTC.
+    pub amount: u64,
+    /// The number of failed delivery attempts since the last upgrade.
+    pub failed_attempts: u32,
+}
+
 #[derive(candid::CandidType, Clone, Debug, PartialEq, Eq, Deserialize)]
 pub enum RetrieveBtcStatus {
     Unknown,
@@ -129,6 +149,10 @@ pub struct CkBtcMinterState {
     /// Minimum amount of bitcoin that can be retrieved
     pub retrieve_btc_min_amount: u64,
 
+    /// Minimum amount of bitcoin that can be retrieved given the current
+    /// Bitcoin network fees. Never below `retrieve_btc_min_amount`.
+    pub fee_based_retrieve_btc_min_amount: u64,
+
     /// Retrieve_btc requests that are waiting to be served
     pub pending_retrieve_btc_requests: VecDeque<RetrieveBtcRequest>,
 
@@ -136,6 +160,10 @@ pub struct CkBtcMinterState {
     /// transaction or sending to the Bitcoin network.
     pub requests_in_flight: BTreeMap<u64, InFlightStatus>,
 
+    /// The retrieve_btc requests in flight indexed by their identifiers. Has
+    /// the same keys as `requests_in_flight`.
+    pub in_flight_retrieve_btc_requests: BTreeMap<u64, RetrieveBtcRequest>,
+
     /// BTC transactions waiting for finalization.
     pub submitted_transactions: Vec<SubmittedBtcTransaction>,
 
@@ -158,9 +186,39 @@ pub struct CkBtcMinterState {
     /// The map of known addresses to their utxos.
     pub utxos_state_addresses: BTreeMap<Account, BTreeSet<Utxo>>,
 
+    /// The total amount of ckBTC that the minter minted on the ledger.
+    pub tokens_minted: u64,
+
+    /// The total amount of ckBTC that the minter burned on the ledger.
+    pub tokens_burned: u64,
+
+    /// The index of the last ledger block in which the minter minted or burned
+    /// ckBTC.
+    pub last_ledger_block_index: Option<u64>,
+
+    /// The canisters to notify about mints to each account.
+    pub deposit_subscriptions: BTreeMap<Account, BTreeSet<Principal>>,
+
+    /// Deposit notifications that the minter did not deliver yet.
+    pub pending_deposit_notifications: VecDeque<PendingDepositNotification>,
+
+    /// The number of deposit notifications that the minter dropped because
+    /// the queue of pending notifications was full.
+    pub dropped_deposit_notifications: u64,
+
+    /// The subscribers that the minter is currently notifying. The minter
+    /// delivers one notification at a time to each subscriber.
+    #[serde(skip)]
+    pub deposit_notifications_in_flight: BTreeSet<Principal>,
+
     /// Process one heartbeat at a time
     #[serde(skip)]
     pub is_heartbeat_running: bool,
+
+    /// The IC time at which the minter last refreshed the fee-based minimum
+    /// retrieve_btc amount.
+    #[serde(skip)]
+    pub last_min_amount_refresh: u64,
 }
 
 impl CkBtcMinterState {
@@ -176,6 +234,7 @@ impl CkBtcMinterState {
         self.btc_network = btc_network;
         self.ecdsa_key_name = ecdsa_key_name;
         self.retrieve_btc_min_amount = retrieve_btc_min_amount;
+        self.fee_based_retrieve_btc_min_amount = retrieve_btc_min_amount;
         self.ledger_id = ledger_id;
     }
 
@@ -232,6 +291,124 @@ impl CkBtcMinterState {
             .expect("state invariants are violated");
     }
 
+    /// Records that the minter minted `amount` ckBTC to the specified account
+    /// in the ledger block with the specified index and schedules
+    /// notifications for the subscribers of the account.
+    pub fn record_mint(&mut self, account: &Account, block_index: u64, amount: u64) {
+        self.tokens_minted += amount;
+        self.observe_ledger_block(block_index);
+
+        if let Some(subscribers) = self.deposit_subscriptions.get(account) {
+            for subscriber in subscribers {
+                if self.pending_deposit_notifications.len() >= MAX_PENDING_DEPOSIT_NOTIFICATIONS {
+                    self.dropped_deposit_notifications += 1;
+                    continue;
+                }
+                self.pending_deposit_notifications.push_back(PendingDepositNotification {
+                    subscriber: *subscriber,
+                    account: account.clone(),
+                    mint_block_index: block_index,
+                    amount,
+                    failed_attempts: 0,
+                });
+            }
+        }
+    }
+
+    /// Records that the minter minted `amount` ckBTC in a ledger block it did
+    /// not record. Such mints come from event logs written before the minter
+    /// tracked mint block indices, so there is no one left to notify.
+    pub fn record_unindexed_mint(&mut self, amount: u64) {
+        self.tokens_minted += amount;
+    }
+
+    /// Records that the minter burned `amount` ckBTC in the ledger block with
+    /// the specified index.
+    pub fn record_burn(&mut self, block_index: u64, amount: u64) {
+        self.tokens_burned += amount;
+        self.observe_ledger_block(block_index);
+    }
+
+    pub fn subscribe_to_deposits(&mut self, subscriber: Principal, account: Account) {
+        self.deposit_subscriptions
+            .entry(account)
+            .or_default()
+            .insert(subscriber);
+    }
+
+    pub fn unsubscribe_from_deposits(&mut self, subscriber: Principal, account: &Account) {
+        if let Some(subscribers) = self.deposit_subscriptions.get_mut(account) {
+            subscribers.remove(&subscriber);
+            if subscribers.is_empty() {
+                self.deposit_subscriptions.remove(account);
+            }
+        }
+    }
+
+    /// Returns the number of accounts the subscriber registered interest in.
+    pub fn count_deposit_subscriptions(&self, subscriber: Principal) -> usize {
+        self.deposit_subscriptions
+            .values()
+            .filter(|subscribers| subscribers.contains(&subscriber))
+            .count()
+    }
+
+    /// Returns the number of subscriptions of all subscribers.
+    pub fn total_deposit_subscriptions(&self) -> usize {
+        self.deposit_subscriptions.values().map(BTreeSet::len).sum()
+    }
+
+    /// Returns up to `max` pending notifications to subscribers that the
+    /// minter is not notifying yet, at most one per subscriber.
+    pub fn deliverable_deposit_notifications(
+        &self,
+        max: usize,
+    ) -> Vec<PendingDepositNotification> {
+        let mut subscribers = BTreeSet::new();
+        self.pending_deposit_notifications
+            .iter()
+            .filter(|n| {
+                !self.deposit_notifications_in_flight.contains(&n.subscriber)
+                    && subscribers.insert(n.subscriber)
+            })
+            .take(max)
+            .cloned()
+            .collect()
+    }
+
+    /// Counts a failed delivery of the notification about the specified mint
+    /// and returns the number of failed attempts so far.
+    pub fn record_failed_deposit_notification(
+        &mut self,
+        subscriber: Principal,
+        mint_block_index: u64,
+    ) -> u32 {
+        match self
+            .pending_deposit_notifications
+            .iter_mut()
+            .find(|n| n.subscriber == subscriber && n.mint_block_index == mint_block_index)
+        {
+            Some(notification) => {
+                notification.failed_attempts += 1;
+                notification.failed_attempts
+            }
+            None => 0,
+        }
+    }
+
+    /// Removes the notification about the specified mint from the queue.
+    pub fn remove_deposit_notification(&mut self, subscriber: Principal, mint_block_index: u64) {
+        self.pending_deposit_notifications
+            .retain(|n| n.subscriber != subscriber || n.mint_block_index != mint_block_index);
+    }
+
+    fn observe_ledger_block(&mut self, block_index: u64) {
+        self.last_ledger_block_index = Some(
+            self.last_ledger_block_index
+                .map_or(block_index, |last| last.max(block_index)),
+        );
+    }
+
     /// Returns the status of the retrieve_btc request with the specified
     /// identifier.
     pub fn retrieve_btc_status(&self, block_index: u64) -> RetrieveBtcStatus {
@@ -283,6 +460,27 @@ impl CkBtcMinterState {
                 .sum::<usize>()
     }
 
+    /// Returns the number of Bitcoin transactions that the minter is signing
+    /// or sending.
+    ///
+    /// The count comes from the statuses of the in-flight requests, so it
+    /// cannot drift from them. A request that is being signed belongs to a
+    /// transaction of its own, and the requests being sent share the
+    /// transaction with the same txid.
+    pub fn transactions_in_flight(&self) -> usize {
+        let mut signing = 0;
+        let mut sending = BTreeSet::new();
+        for status in self.requests_in_flight.values() {
+            match status {
+                InFlightStatus::Signing => signing += 1,
+                InFlightStatus::Sending { txid } => {
+                    sending.insert(txid);
+                }
+            }
+        }
+        signing + sending.len()
+    }
+
     /// Returns true if there is a pending retrieve_btc request with the given
     /// identifier.
     fn has_pending_request(&self, block_index: u64) -> bool {
@@ -344,10 +542,12 @@ impl CkBtcMinterState {
     ///
     /// This function panics if there is a pending retrieve_btc request with the
     /// same identifier.
-    pub fn push_in_flight_request(&mut self, block_index: u64, status: InFlightStatus) {
-        assert!(!self.has_pending_request(block_index));
+    pub fn push_in_flight_request(&mut self, req: &RetrieveBtcRequest, status: InFlightStatus) {
+        assert!(!self.has_pending_request(req.block_index));
 
-        self.requests_in_flight.insert(block_index, status);
+        self.requests_in_flight.insert(req.block_index, status);
+        self.in_flight_retrieve_btc_requests
+            .insert(req.block_index, req.clone());
     }
 
     /// Adds a new retrieve_btc request to the back of the queue.
@@ -360,6 +560,8 @@ impl CkBtcMinterState {
         assert!(!self.has_pending_request(req.block_index));
 
         self.requests_in_flight.remove(&req.block_index);
+        self.in_flight_retrieve_btc_requests
+            .remove(&req.block_index);
         self.pending_retrieve_btc_requests.push_back(req);
     }
 
@@ -374,6 +576,8 @@ impl CkBtcMinterState {
         for req in tx.requests.iter() {
             assert!(!self.has_pending_request(req.block_index));
             self.requests_in_flight.remove(&req.block_index);
+            self.in_flight_retrieve_btc_requests
+                .remove(&req.block_index);
         }
         self.submitted_transactions.push(tx);
     }
@@ -413,6 +617,11 @@ impl CkBtcMinterState {
             "min_confirmations does not match"
         );
         ensure_eq!(self.ledger_id, other.ledger_id, "ledger_id does not match");
+        ensure_eq!(
+            self.fee_based_retrieve_btc_min_amount,
+            other.fee_based_retrieve_btc_min_amount,
+            "fee_based_retrieve_btc_min_amount does not match"
+        );
         ensure_eq!(
             self.finalized_requests,
             other.finalized_requests,
@@ -423,6 +632,11 @@ impl CkBtcMinterState {
             other.requests_in_flight,
             "requests_in_flight do not match"
         );
+        ensure_eq!(
+            self.in_flight_retrieve_btc_requests,
+            other.in_flight_retrieve_btc_requests,
+            "in_flight_retrieve_btc_requests do not match"
+        );
         ensure_eq!(
             self.available_utxos,
             other.available_utxos,
@@ -433,6 +647,49 @@ impl CkBtcMinterState {
             other.utxos_state_addresses,
             "utxos_state_addresses do not match"
         );
+        ensure_eq!(
+            self.tokens_minted,
+            other.tokens_minted,
+            "tokens_minted does not match"
+        );
+        ensure_eq!(
+            self.tokens_burned,
+            other.tokens_burned,
+            "tokens_burned does not match"
+        );
+        ensure_eq!(
+            self.last_ledger_block_index,
+            other.last_ledger_block_index,
+            "last_ledger_block_index does not match"
+        );
+        ensure_eq!(
+            self.deposit_subscriptions,
+            other.deposit_subscriptions,
+            "deposit_subscriptions do not match"
+        );
+        ensure_eq!(
+            self.dropped_deposit_notifications,
+            other.dropped_deposit_notifications,
+            "dropped_deposit_notifications does not match"
+        );
+
+        // The minter does not persist failed delivery attempts.
+        let notification_key = |n: &PendingDepositNotification| (n.subscriber, n.mint_block_index);
+        let my_notifications: BTreeSet<_> = self
+            .pending_deposit_notifications
+            .iter()
+            .map(notification_key)
+            .collect();
+        let other_notifications: BTreeSet<_> = other
+            .pending_deposit_notifications
+            .iter()
+            .map(notification_key)
+            .collect();
+        ensure_eq!(
+            my_notifications,
+            other_notifications,
+            "pending_deposit_notifications do not match"
+        );
 
         let my_txs = as_sorted_vec(self.submitted_transactions.iter().cloned(), |tx| tx.txid);
         let other_txs = as_sorted_vec(other.submitted_transactions.iter().cloned(), |tx| tx.txid);
@@ -471,8 +728,10 @@ impl From<InitArgs> for CkBtcMinterState {
             update_balance_principals: Default::default(),
             retrieve_btc_principals: Default::default(),
             retrieve_btc_min_amount: args.retrieve_btc_min_amount,
+            fee_based_retrieve_btc_min_amount: args.retrieve_btc_min_amount,
             pending_retrieve_btc_requests: Default::default(),
             requests_in_flight: Default::default(),
+            in_flight_retrieve_btc_requests: Default::default(),
             submitted_transactions: Default::default(),
             finalized_requests: VecDeque::with_capacity(MAX_FINALIZED_REQUESTS),
             finalized_requests_count: 0,
@@ -480,7 +739,15 @@ impl From<InitArgs> for CkBtcMinterState {
             available_utxos: Default::default(),
             outpoint_account: Default::default(),
             utxos_state_addresses: Default::default(),
+            tokens_minted: 0,
+            tokens_burned: 0,
+            last_ledger_block_index: None,
+            deposit_subscriptions: Default::default(),
+            pending_deposit_notifications: Default::default(),
+            dropped_deposit_notifications: 0,
+            deposit_notifications_in_flight: Default::default(),
             is_heartbeat_running: false,
+            last_min_amount_refresh: 0,
         }
     }
 }
@@ -156,6 +156,407 @@ fn greedy_smoke_test() {
     assert_eq!(res[1].value, 6_u64);
 }
 
+#[test]
+fn reserves_summary_accounts_for_mints_and_burns() {
+    use crate::{
+        lifecycle::init::InitArgs,
+        reserves::{PendingWithdrawal, ReservesSummary},
+        state::{CkBtcMinterState, InFlightStatus, RetrieveBtcRequest},
+    };
+
+    let mut state = CkBtcMinterState::from(InitArgs {
+        btc_network: Network::Mainnet,
+        ecdsa_key_name: "".to_string(),
+        retrieve_btc_min_amount: 0,
+        ledger_id: CanisterId::from_u64(42),
+    });
+    let account = Account {
+        owner: PrincipalId::new_user_test_id(1),
+        subaccount: None,
+    };
+    let utxos = vec![dummy_utxo_from_value(50_000), dummy_utxo_from_value(30_000)];
+
+    state.record_mint(&account, 1, 80_000);
+    state.add_utxos(account, utxos.clone());
+
+    let address = BitcoinAddress::P2wpkhV0([1; 20]);
+    state.record_burn(2, 20_000);
+    state.push_pending_request(RetrieveBtcRequest {
+        amount: 20_000,
+        address: address.clone(),
+        block_index: 2,
+        received_at: 0,
+    });
+
+    let summary = ReservesSummary::from_state(&state).unwrap();
+    assert_eq!(summary.ledger_block_index, Some(2));
+    assert_eq!(summary.utxos, vec![utxos[1].clone(), utxos[0].clone()]);
+    assert_eq!(summary.total_utxo_value, 80_000);
+    assert_eq!(summary.total_supply, 60_000);
+    assert_eq!(
+        summary.pending_withdrawals,
+        vec![PendingWithdrawal {
+            block_index: 2,
+            amount: 20_000,
+            address: address.display(Network::Mainnet),
+            txid: None,
+        }]
+    );
+    assert_eq!(summary.total_pending_withdrawal_amount, 20_000);
+
+    assert_eq!(
+        candid::decode_one::<ReservesSummary>(&summary.encode()).unwrap(),
+        summary
+    );
+    assert!(summary.to_json().starts_with(
+        "{\"version\":2,\"previous_summary_hash\":null,\"ledger_block_index\":2,\"utxos\":[{"
+    ));
+
+    // Requests in flight remain pending withdrawals.
+    let txid = [7; 32];
+    let req = state.pending_retrieve_btc_requests.pop_front().unwrap();
+    state.push_in_flight_request(&req, InFlightStatus::Sending { txid });
+    let summary = ReservesSummary::from_state(&state).unwrap();
+    assert_eq!(
+        summary.pending_withdrawals,
+        vec![PendingWithdrawal {
+            block_index: 2,
+            amount: 20_000,
+            address: address.display(Network::Mainnet),
+            txid: Some(ByteBuf::from(txid.to_vec())),
+        }]
+    );
+    assert_eq!(summary.total_pending_withdrawal_amount, 20_000);
+
+    // Burning more than the minter minted does not underflow the supply.
+    state.record_burn(3, 100_000);
+    assert!(ReservesSummary::from_state(&state).is_err());
+}
+
+#[test]
+fn mints_notify_deposit_subscribers() {
+    use crate::{lifecycle::init::InitArgs, state::CkBtcMinterState};
+    use candid::Principal;
+
+    let mut state = CkBtcMinterState::from(InitArgs {
+        btc_network: Network::Regtest,
+        ecdsa_key_name: "".to_string(),
+        retrieve_btc_min_amount: 0,
+        ledger_id: CanisterId::from_u64(42),
+    });
+    let account = Account {
+        owner: PrincipalId::new_user_test_id(1),
+        subaccount: None,
+    };
+    let other_account = Account {
+        owner: PrincipalId::new_user_test_id(2),
+        subaccount: None,
+    };
+    let subscriber = Principal::from(CanisterId::from_u64(7).get());
+
+    state.subscribe_to_deposits(subscriber, account.clone());
+    assert_eq!(state.count_deposit_subscriptions(subscriber), 1);
+
+    state.record_mint(&other_account, 1, 1_000);
+    assert!(state.pending_deposit_notifications.is_empty());
+
+    state.record_mint(&account, 2, 2_000);
+    assert_eq!(state.pending_deposit_notifications.len(), 1);
+    let notification = &state.pending_deposit_notifications[0];
+    assert_eq!(notification.subscriber, subscriber);
+    assert_eq!(notification.account, account);
+    assert_eq!(notification.mint_block_index, 2);
+    assert_eq!(notification.amount, 2_000);
+
+    state.unsubscribe_from_deposits(subscriber, &account);
+    assert!(state.deposit_subscriptions.is_empty());
+    state.record_mint(&account, 3, 3_000);
+    assert_eq!(state.pending_deposit_notifications.len(), 1);
+
+    state.remove_deposit_notification(subscriber, 2);
+    assert!(state.pending_deposit_notifications.is_empty());
+}
+
+#[test]
+fn deposit_notifications_are_delivered_one_at_a_time_per_subscriber() {
+    use crate::{lifecycle::init::InitArgs, state::CkBtcMinterState};
+    use candid::Principal;
+
+    let mut state = CkBtcMinterState::from(InitArgs {
+        btc_network: Network::Regtest,
+        ecdsa_key_name: "".to_string(),
+        retrieve_btc_min_amount: 0,
+        ledger_id: CanisterId::from_u64(42),
+    });
+    let subscriber = |id: u64| Principal::from(CanisterId::from_u64(id).get());
+    let account = |id: u64| Account {
+        owner: CanisterId::from_u64(id).get(),
+        subaccount: None,
+    };
+    for id in [7, 8] {
+        state.subscribe_to_deposits(subscriber(id), account(id));
+    }
+    state.record_mint(&account(7), 1, 1_000);
+    state.record_mint(&account(7), 2, 2_000);
+    state.record_mint(&account(8), 3, 3_000);
+
+    let keys = |state: &CkBtcMinterState, max: usize| {
+        state
+            .deliverable_deposit_notifications(max)
+            .iter()
+            .map(|n| (n.subscriber, n.mint_block_index))
+            .collect::<Vec<_>>()
+    };
+    let all = vec![(subscriber(7), 1), (subscriber(8), 3)];
+    assert_eq!(keys(&state, 10), all);
+    assert_eq!(keys(&state, 1), vec![(subscriber(7), 1)]);
+
+    state.deposit_notifications_in_flight.insert(subscriber(7));
+    assert_eq!(keys(&state, 10), vec![(subscriber(8), 3)]);
+
+    // A failed delivery stays in the queue.
+    let s7 = subscriber(7);
+    assert_eq!(state.record_failed_deposit_notification(s7, 1), 1);
+    assert_eq!(state.record_failed_deposit_notification(s7, 1), 2);
+    assert_eq!(state.record_failed_deposit_notification(s7, 4), 0);
+    state.deposit_notifications_in_flight.remove(&s7);
+    assert_eq!(keys(&state, 10), all);
+}
+
+#[test]
+fn deposit_notifications_are_dropped_when_the_queue_is_full() {
+    use crate::{
+        lifecycle::init::InitArgs,
+        state::{CkBtcMinterState, MAX_PENDING_DEPOSIT_NOTIFICATIONS},
+    };
+    use candid::Principal;
+
+    let mut state = CkBtcMinterState::from(InitArgs {
+        btc_network: Network::Regtest,
+        ecdsa_key_name: "".to_string(),
+        retrieve_btc_min_amount: 0,
+        ledger_id: CanisterId::from_u64(42),
+    });
+    let account = Account {
+        owner: CanisterId::from_u64(7).get(),
+        subaccount: None,
+    };
+    state.subscribe_to_deposits(Principal::from(account.owner), account.clone());
+
+    let mints = MAX_PENDING_DEPOSIT_NOTIFICATIONS as u64 + 2;
+    for block_index in 0..mints {
+        state.record_mint(&account, block_index, 1_000);
+    }
+    assert_eq!(
+        state.pending_deposit_notifications.len(),
+        MAX_PENDING_DEPOSIT_NOTIFICATIONS
+    );
+    assert_eq!(state.dropped_deposit_notifications, 2);
+    assert_eq!(state.tokens_minted, mints * 1_000);
+}
+
+#[test]
+fn min_amount_covers_fees() {
+    use crate::compute_min_amount;
+
+    // The configured minimum covers the fees.
+    assert_eq!(compute_min_amount(1_000, 50_000), 50_000);
+    // The fees (140_000) exceed the configured minimum.
+    assert_eq!(compute_min_amount(500_000, 50_000), 150_000);
+    assert_eq!(compute_min_amount(500_000, 0), 150_000);
+    // The minimum does not change with small fee fluctuations.
+    assert_eq!(compute_min_amount(510_000, 50_000), 150_000);
+    assert_eq!(compute_min_amount(u64::MAX, 0), u64::MAX);
+}
+
+#[test]
+fn replay_restores_fee_based_min_amount() {
+    use crate::{
+        eventlog::{replay, Event},
+        lifecycle::init::InitArgs,
+    };
+
+    let init_args = InitArgs {
+        btc_network: Network::Mainnet,
+        ecdsa_key_name: "".to_string(),
+        retrieve_btc_min_amount: 50_000,
+        ledger_id: CanisterId::from_u64(42),
+    };
+    let minter_id = CanisterId::from_u64(1).get().0;
+
+    let state = replay(
+        vec![
+            Event::Init(init_args.clone()),
+            Event::UpdatedRetrieveBtcMinAmount {
+                min_amount: 150_000,
+            },
+        ]
+        .into_iter(),
+        minter_id,
+    )
+    .unwrap();
+    assert_eq!(state.retrieve_btc_min_amount, 50_000);
+    assert_eq!(state.fee_based_retrieve_btc_min_amount, 150_000);
+
+    // Reinitializing the minter resets the minimum until the next fee update.
+    let state = replay(
+        vec![
+            Event::Init(init_args.clone()),
+            Event::UpdatedRetrieveBtcMinAmount {
+                min_amount: 150_000,
+            },
+            Event::Init(init_args),
+        ]
+        .into_iter(),
+        minter_id,
+    )
+    .unwrap();
+    assert_eq!(state.fee_based_retrieve_btc_min_amount, 50_000);
+}
+
+#[test]
+fn replay_accounts_for_mints_without_block_index() {
+    use crate::{
+        eventlog::{replay, Event},
+        lifecycle::init::InitArgs,
+    };
+
+    let minter_id = CanisterId::from_u64(1).get().0;
+    let user_account = Account {
+        owner: PrincipalId::new_user_test_id(1),
+        subaccount: None,
+    };
+    let main_account = Account {
+        owner: minter_id.into(),
+        subaccount: None,
+    };
+
+    let state = replay(
+        vec![
+            Event::Init(InitArgs {
+                btc_network: Network::Mainnet,
+                ecdsa_key_name: "".to_string(),
+                retrieve_btc_min_amount: 0,
+                ledger_id: CanisterId::from_u64(42),
+            }),
+            // A mint recorded before the minter tracked mint block indices.
+            Event::ReceivedUtxos {
+                to_account: user_account.clone(),
+                utxos: vec![dummy_utxo_from_value(50_000)],
+                mint_block_index: None,
+            },
+            Event::ReceivedUtxos {
+                to_account: user_account,
+                utxos: vec![dummy_utxo_from_value(30_000)],
+                mint_block_index: Some(3),
+            },
+            // Change does not increase the supply.
+            Event::ReceivedUtxos {
+                to_account: main_account,
+                utxos: vec![dummy_utxo_from_value(10_000)],
+                mint_block_index: None,
+            },
+        ]
+        .into_iter(),
+        minter_id,
+    )
+    .unwrap();
+    assert_eq!(state.tokens_minted, 80_000);
+    assert_eq!(state.last_ledger_block_index, Some(3));
+}
+
+/// Initializes the minter state with UTXOs of the specified values and
+/// `num_requests` pending retrieve_btc requests for 50_000 satoshi each.
+fn init_state_with_requests(utxo_values: &[u64], num_requests: u64) {
+    use crate::{
+        lifecycle::init::{init, InitArgs},
+        state::{mutate_state, RetrieveBtcRequest},
+    };
+
+    init(InitArgs {
+        btc_network: Network::Regtest,
+        ecdsa_key_name: "".to_string(),
+        retrieve_btc_min_amount: 0,
+        ledger_id: CanisterId::from_u64(42),
+    });
+    let account = Account {
+        owner: PrincipalId::new_user_test_id(1),
+        subaccount: None,
+    };
+    mutate_state(|s| {
+        s.add_utxos(
+            account,
+            utxo_values.iter().map(|v| dummy_utxo_from_value(*v)).collect(),
+        );
+        for block_index in 0..num_requests {
+            s.push_pending_request(RetrieveBtcRequest {
+                amount: 50_000,
+                address: BitcoinAddress::P2wpkhV0([block_index as u8; 20]),
+                block_index,
+                received_at: 0,
+            });
+        }
+    });
+}
+
+fn test_sign_request() -> Option<crate::SignTxRequest> {
+    crate::build_sign_request(
+        BitcoinAddress::P2wpkhV0([0xff; 20]),
+        crate::ECDSAPublicKey {
+            public_key: vec![2; 33],
+            chain_code: vec![0; 32],
+        },
+        1_000,
+    )
+}
+
+#[test]
+fn concurrent_transactions_spend_disjoint_utxos() {
+    use crate::state::{read_state, InFlightStatus};
+
+    init_state_with_requests(&[100_000, 200_000, 300_000], 3);
+
+    let first = test_sign_request().expect("failed to build the first transaction");
+    let second = test_sign_request().expect("failed to build the second transaction");
+
+    let first_utxos: BTreeSet<_> = first.utxos.iter().cloned().collect();
+    assert!(!first_utxos.is_empty());
+    assert!(!second.utxos.is_empty());
+    assert!(second.utxos.iter().all(|utxo| !first_utxos.contains(utxo)));
+
+    read_state(|s| {
+        for req in [&first, &second] {
+            assert!(req.utxos.iter().all(|utxo| !s.available_utxos.contains(utxo)));
+            assert_eq!(
+                s.requests_in_flight.get(&req.requests[0].block_index),
+                Some(&InFlightStatus::Signing)
+            );
+        }
+        assert_eq!(s.transactions_in_flight(), 2);
+        assert_eq!(s.pending_retrieve_btc_requests.len(), 1);
+    });
+}
+
+#[test]
+fn failed_signature_returns_utxos() {
+    use crate::{state::read_state, undo_sign_request};
+
+    init_state_with_requests(&[100_000, 200_000], 1);
+    let available_utxos = read_state(|s| s.available_utxos.clone());
+
+    let req = test_sign_request().expect("failed to build a transaction");
+    assert_eq!(read_state(|s| s.transactions_in_flight()), 1);
+
+    undo_sign_request(req.requests, req.utxos);
+    read_state(|s| {
+        assert_eq!(s.available_utxos, available_utxos);
+        assert!(s.requests_in_flight.is_empty());
+        assert!(s.in_flight_retrieve_btc_requests.is_empty());
+        assert_eq!(s.transactions_in_flight(), 0);
+        assert_eq!(s.pending_retrieve_btc_requests.len(), 1);
+    });
+}
+
 fn arb_amount() -> impl Strategy<Value = Satoshi> {
     1..10_000_000_000u64
 }
@@ -1,9 +1,11 @@
 pub mod get_btc_address;
 pub mod get_withdrawal_account;
 pub mod retrieve_btc;
+pub mod subscribe_to_deposits;
 pub mod update_balance;
 
 pub use get_btc_address::get_btc_address;
 pub use get_withdrawal_account::get_withdrawal_account;
 pub use retrieve_btc::retrieve_btc;
+pub use subscribe_to_deposits::{subscribe_to_deposits, unsubscribe_from_deposits};
 pub use update_balance::update_balance;
@@ -2,6 +2,7 @@ use crate::eventlog::Event;
 use crate::storage::record_event;
 use candid::{CandidType, Deserialize, Nat, Principal};
 use ic_base_types::PrincipalId;
+use ic_btc_types::Network;
 use ic_icrc1::{
     endpoints::{TransferArg, TransferError},
     Account,
@@ -12,6 +13,7 @@ use num_traits::cast::ToPrimitive;
 use super::{get_btc_address::init_ecdsa_public_key, get_withdrawal_account::compute_subaccount};
 use crate::{
     address::{BitcoinAddress, ParseAddressError},
+    bip21::{is_bip21_uri, ParseUriError, PaymentUri},
     guard::{retrieve_btc_guard, GuardError},
     state::{mutate_state, read_state, RetrieveBtcRequest},
 };
@@ -23,7 +25,7 @@ pub struct RetrieveBtcArgs {
     // amount to retrieve in satoshi
     pub amount: u64,
 
-    // address where to send bitcoins
+    // address where to send bitcoins, or a BIP-21 URI containing it
     pub address: String,
 }
 
@@ -44,6 +46,12 @@ pub enum RetrieveBtcError {
     /// The bitcoin address is not valid.
     MalformedAddress(String),
 
+    /// The destination is a BIP-21 URI that the minter cannot parse.
+    MalformedUri(ParseUriError),
+
+    /// The amount in the BIP-21 URI differs from the withdrawal amount.
+    UriAmountMismatch { uri_amount: u64, amount: u64 },
+
     /// The withdrawal account does not hold the requested ckBTC amount.
     InsufficientFunds { balance: u64 },
 
@@ -74,15 +82,44 @@ impl From<ParseAddressError> for RetrieveBtcError {
     }
 }
 
+impl From<ParseUriError> for RetrieveBtcError {
+    fn from(e: ParseUriError) -> Self {
+        Self::MalformedUri(e)
+    }
+}
+
+/// Parses the destination of a withdrawal, which is either a bitcoin address
+/// or a BIP-21 URI. If the URI specifies an amount, it must match the
+/// withdrawal amount.
+fn parse_destination(
+    args: &RetrieveBtcArgs,
+    network: Network,
+) -> Result<BitcoinAddress, RetrieveBtcError> {
+    if !is_bip21_uri(&args.address) {
+        return Ok(BitcoinAddress::parse(&args.address, network)?);
+    }
+    let uri = PaymentUri::parse(&args.address, network)?;
+    if let Some(uri_amount) = uri.amount {
+        if uri_amount != args.amount {
+            return Err(RetrieveBtcError::UriAmountMismatch {
+                uri_amount,
+                amount: args.amount,
+            });
+        }
+    }
+    Ok(uri.address)
+}
+
 pub async fn retrieve_btc(args: RetrieveBtcArgs) -> Result<RetrieveBtcOk, RetrieveBtcError> {
     let caller = ic_cdk::caller();
     init_ecdsa_public_key().await;
     let _guard = retrieve_btc_guard(caller)?;
-    let (min_amount, btc_network) = read_state(|s| (s.retrieve_btc_min_amount, s.btc_network));
+    let (min_amount, btc_network) =
+        read_state(|s| (s.fee_based_retrieve_btc_min_amount, s.btc_network));
     if args.amount < min_amount {
         return Err(RetrieveBtcError::AmountTooLow(min_amount));
     }
-    let parsed_address = BitcoinAddress::parse(&args.address, btc_network)?;
+    let parsed_address = parse_destination(&args, btc_network)?;
     if read_state(|s| s.count_incomplete_retrieve_btc_requests() >= MAX_CONCURRENT_PENDING_REQUESTS)
     {
         return Err(RetrieveBtcError::TemporarilyUnavailable(
@@ -100,7 +137,10 @@ pub async fn retrieve_btc(args: RetrieveBtcArgs) -> Result<RetrieveBtcOk, Retrie
 
     record_event(&Event::AcceptedRetrieveBtcRequest(request.clone()));
 
-    mutate_state(|s| s.pending_retrieve_btc_requests.push_back(request));
+    mutate_state(|s| {
+        s.record_burn(block_index, args.amount);
+        s.pending_retrieve_btc_requests.push_back(request);
+    });
 
     assert_eq!(
         crate::state::RetrieveBtcStatus::Pending,
@@ -173,3 +213,59 @@ async fn burn_ckbtcs(user: Principal, amount: u64) -> Result<u64, RetrieveBtcErr
         )),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_destination, RetrieveBtcArgs, RetrieveBtcError};
+    use crate::address::BitcoinAddress;
+    use crate::bip21::ParseUriError;
+    use ic_btc_types::Network;
+
+    const ADDRESS: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+
+    fn destination(address: &str, amount: u64) -> Result<BitcoinAddress, RetrieveBtcError> {
+        parse_destination(
+            &RetrieveBtcArgs {
+                amount,
+                address: address.to_string(),
+            },
+            Network::Mainnet,
+        )
+    }
+
+    #[test]
+    fn test_parse_destination() {
+        let address = BitcoinAddress::parse(ADDRESS, Network::Mainnet).unwrap();
+        assert_eq!(Ok(address.clone()), destination(ADDRESS, 1_000));
+        assert_eq!(
+            Ok(address.clone()),
+            destination(&format!("bitcoin:{}", ADDRESS), 1_000)
+        );
+        assert_eq!(
+            Ok(address),
+            destination(&format!("bitcoin:{}?amount=0.00001", ADDRESS), 1_000)
+        );
+
+        assert_eq!(
+            Err(RetrieveBtcError::UriAmountMismatch {
+                uri_amount: 2_000,
+                amount: 1_000,
+            }),
+            destination(&format!("bitcoin:{}?amount=0.00002", ADDRESS), 1_000)
+        );
+        assert_eq!(
+            Err(RetrieveBtcError::MalformedUri(ParseUriError::MissingAddress)),
+            destination("bitcoin:?amount=0.00001", 1_000)
+        );
+        assert_eq!(
+            Err(RetrieveBtcError::MalformedUri(
+                ParseUriError::MalformedEncoding("0.00001%".to_string())
+            )),
+            destination(&format!("bitcoin:{}?amount=0.00001%", ADDRESS), 1_000)
+        );
+        assert!(matches!(
+            destination("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx", 1_000),
+            Err(RetrieveBtcError::MalformedAddress(_))
+        ));
+    }
+}
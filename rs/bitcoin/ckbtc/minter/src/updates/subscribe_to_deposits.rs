@@ -0,0 +1,128 @@
+use crate::eventlog::Event;
+use crate::state::{mutate_state, read_state};
+use crate::storage::record_event;
+use candid::{CandidType, Deserialize, Principal};
+use ic_base_types::PrincipalId;
+use ic_icrc1::Account;
+use serde::Serialize;
+
+/// The maximum number of accounts a single canister can subscribe to.
+pub const MAX_SUBSCRIPTIONS_PER_SUBSCRIBER: usize = 1_000;
+
+/// The maximum number of subscriptions of all canisters.
+pub const MAX_SUBSCRIPTIONS: usize = 10_000;
+
+/// The method that the minter calls on subscriber canisters.
+pub const DEPOSIT_NOTIFICATION_METHOD: &str = "on_ckbtc_deposit";
+
+/// The argument of the [DEPOSIT_NOTIFICATION_METHOD] call.
+///
+/// The minter delivers a notification at least once as long as the
+/// subscriber accepts one of [crate::MAX_DEPOSIT_NOTIFICATION_ATTEMPTS]
+/// attempts and the queue of pending notifications is not full, see
+/// [crate::state::MAX_PENDING_DEPOSIT_NOTIFICATIONS]. Subscribers must
+/// deduplicate notifications by `block_index`.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DepositNotification {
+    /// The account that received the minted ckBTC.
+    pub account: Account,
+    /// The index of the mint block on the ckBTC ledger.
+    pub block_index: u64,
+    /// The amount of minted ckBTC.
+    pub amount: u64,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum SubscribeError {
+    /// Only canisters can receive notifications.
+    CallerNotCanister,
+    /// The caller does not own the account.
+    CallerNotAccountOwner,
+    /// The caller reached the subscription limit.
+    TooManySubscriptions { limit: u64 },
+    /// The minter reached the limit on the number of subscriptions of all
+    /// canisters.
+    SubscriptionCapacityReached { limit: u64 },
+}
+
+// Canister ids are opaque principals, while users have self-authenticating
+// principals.
+fn is_canister(principal: &Principal) -> bool {
+    principal.as_slice().last() == Some(&0x01)
+}
+
+/// Registers the caller canister for notifications about mints to the
+/// specified account, which the caller must own.
+pub fn subscribe_to_deposits(account: Account) -> Result<(), SubscribeError> {
+    let caller = ic_cdk::caller();
+    if !is_canister(&caller) {
+        return Err(SubscribeError::CallerNotCanister);
+    }
+    if account.owner != PrincipalId::from(caller) {
+        return Err(SubscribeError::CallerNotAccountOwner);
+    }
+
+    let already_subscribed = read_state(|s| {
+        s.deposit_subscriptions
+            .get(&account)
+            .map_or(false, |subscribers| subscribers.contains(&caller))
+    });
+    if already_subscribed {
+        return Ok(());
+    }
+
+    if read_state(|s| s.count_deposit_subscriptions(caller)) >= MAX_SUBSCRIPTIONS_PER_SUBSCRIBER {
+        return Err(SubscribeError::TooManySubscriptions {
+            limit: MAX_SUBSCRIPTIONS_PER_SUBSCRIBER as u64,
+        });
+    }
+    if read_state(|s| s.total_deposit_subscriptions()) >= MAX_SUBSCRIPTIONS {
+        return Err(SubscribeError::SubscriptionCapacityReached {
+            limit: MAX_SUBSCRIPTIONS as u64,
+        });
+    }
+
+    record_event(&Event::SubscribedToDeposits {
+        subscriber: caller,
+        account: account.clone(),
+    });
+    mutate_state(|s| s.subscribe_to_deposits(caller, account));
+    Ok(())
+}
+
+/// Stops notifying the caller about mints to the specified account.
+/// Notifications about earlier mints are still delivered.
+pub fn unsubscribe_from_deposits(account: Account) {
+    let caller = ic_cdk::caller();
+
+    let subscribed = read_state(|s| {
+        s.deposit_subscriptions
+            .get(&account)
+            .map_or(false, |subscribers| subscribers.contains(&caller))
+    });
+    if !subscribed {
+        return;
+    }
+
+    record_event(&Event::UnsubscribedFromDeposits {
+        subscriber: caller,
+        account: account.clone(),
+    });
+    mutate_state(|s| s.unsubscribe_from_deposits(caller, &account));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_canister;
+    use candid::Principal;
+    use ic_base_types::{CanisterId, PrincipalId};
+
+    #[test]
+    fn test_is_canister() {
+        assert!(is_canister(&Principal::from(CanisterId::from_u64(7).get())));
+        assert!(!is_canister(&Principal::anonymous()));
+        assert!(!is_canister(&Principal::from(
+            PrincipalId::new_self_authenticating(&[1, 2, 3])
+        )));
+    }
+}
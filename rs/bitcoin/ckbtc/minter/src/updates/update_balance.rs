@@ -131,9 +131,13 @@ pub async fn update_balance(
     record_event(&Event::ReceivedUtxos {
         to_account: caller_account.clone(),
         utxos: new_utxos.clone(),
+        mint_block_index: Some(block_index),
     });
 
-    state::mutate_state(|s| s.add_utxos(caller_account, new_utxos));
+    state::mutate_state(|s| {
+        s.record_mint(&caller_account, block_index, satoshis_to_mint);
+        s.add_utxos(caller_account, new_utxos);
+    });
 
     Ok(UpdateBalanceResult {
         amount: satoshis_to_mint,
@@ -1,7 +1,8 @@
-use candid::Encode;
+use candid::{Decode, Encode};
 use ic_base_types::CanisterId;
 use ic_btc_types::Network;
 use ic_ckbtc_minter::lifecycle::init::InitArgs as CkbtcMinterInitArgs;
+use ic_ckbtc_minter::queries::MinterInfo;
 use ic_icrc1::Account;
 use ic_icrc1_ledger::InitArgs as LedgerInitArgs;
 use ic_state_machine_tests::StateMachine;
@@ -50,7 +51,12 @@ fn install_ledger(env: &StateMachine) -> CanisterId {
             controller_id: Default::default(),
             cycles_for_archive_creation: None,
             max_transactions_per_response: None,
+            max_archive_nodes: None,
+            cycles_for_archive_top_up: None,
+            cycles_for_archive_top_ups_pool: None,
         },
+        fee_collector_account: None,
+        decimals: None,
     };
     env.install_canister(ledger_wasm(), Encode!(&args).unwrap(), None)
         .unwrap()
@@ -84,3 +90,21 @@ fn test_upgrade() {
     env.upgrade_canister(minter_id, minter_wasm(), Encode!().unwrap())
         .expect("Failed to upgrade the minter canister");
 }
+
+#[test]
+fn test_get_minter_info() {
+    let env = StateMachine::new();
+    let ledger_id = install_ledger(&env);
+    let minter_id = install_minter(&env, ledger_id);
+
+    let info = Decode!(
+        &env.query(minter_id, "get_minter_info", Encode!().unwrap())
+            .expect("failed to query get_minter_info")
+            .bytes(),
+        MinterInfo
+    )
+    .unwrap();
+    // The minter cannot fetch the Bitcoin network fees in this environment,
+    // so the configured minimum applies.
+    assert_eq!(info.retrieve_btc_min_amount, 0);
+}
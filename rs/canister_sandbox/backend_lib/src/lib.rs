@@ -72,6 +72,10 @@ pub fn run_canister_sandbox(
     };
     let (log, _log_guard) = new_replica_logger_from_config(&logger_config);
 
+    ic_replicated_state::page_map::configure_page_allocators(
+        &embedder_config.page_allocator_config,
+    );
+
     let socket = Arc::new(socket);
 
     let out_stream =
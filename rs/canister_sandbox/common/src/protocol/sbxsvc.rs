@@ -143,6 +143,9 @@ impl EnumerateInnerFileDescriptors for MappingSerialization {
 impl EnumerateInnerFileDescriptors for PageAllocatorSerialization {
     fn enumerate_fds<'a>(&'a mut self, fds: &mut Vec<&'a mut std::os::unix::io::RawFd>) {
         fds.push(&mut self.fd.fd);
+        for fd in self.additional_fds.iter_mut() {
+            fds.push(&mut fd.fd);
+        }
     }
 }
 
@@ -3,4 +3,5 @@ pub mod controller_service_impl;
 pub mod launch_as_process;
 mod process_exe_and_args;
 pub mod process_os_metrics;
+mod sandbox_process_eviction;
 pub mod sandboxed_execution_controller;
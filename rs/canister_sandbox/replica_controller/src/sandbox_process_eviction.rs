@@ -0,0 +1,177 @@
+use ic_types::{CanisterId, NumBytes};
+use std::time::{Duration, Instant};
+
+/// An active sandbox process that may be evicted from the pool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct EvictionCandidate {
+    pub id: CanisterId,
+    pub last_used: Instant,
+    /// The last measured resident set size of the sandbox process.
+    pub rss: NumBytes,
+}
+
+/// The reason why a sandbox process was evicted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EvictionReason {
+    /// The process was not used for longer than the idle time limit.
+    Idle,
+    /// The pool had more active processes than allowed.
+    Count,
+    /// The total RSS of the active processes exceeded the budget.
+    Rss,
+}
+
+impl EvictionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EvictionReason::Idle => "idle",
+            EvictionReason::Count => "count",
+            EvictionReason::Rss => "rss",
+        }
+    }
+}
+
+/// Selects the sandbox processes to evict from the pool of active processes.
+///
+/// All processes that were idle for longer than `max_idle_time` are evicted.
+/// Afterwards, the least recently used processes are evicted until at most
+/// `max_count` processes remain and their total RSS fits into `max_rss`.
+///
+/// Returns the evicted candidates in the order of eviction.
+pub(crate) fn evict(
+    mut candidates: Vec<EvictionCandidate>,
+    now: Instant,
+    max_idle_time: Duration,
+    max_count: usize,
+    max_rss: NumBytes,
+) -> Vec<(EvictionCandidate, EvictionReason)> {
+    candidates.sort_by_key(|candidate| candidate.last_used);
+
+    let mut remaining_count = candidates.len();
+    let mut remaining_rss: u64 = candidates.iter().map(|c| c.rss.get()).sum();
+    let mut evicted = vec![];
+    for candidate in candidates {
+        let reason = if now.saturating_duration_since(candidate.last_used) > max_idle_time {
+            EvictionReason::Idle
+        } else if remaining_count > max_count {
+            EvictionReason::Count
+        } else if remaining_rss > max_rss.get() {
+            EvictionReason::Rss
+        } else {
+            // The candidates are sorted by their last usage, so all remaining
+            // candidates are more recent and none of the limits is exceeded.
+            break;
+        };
+        remaining_count -= 1;
+        remaining_rss -= candidate.rss.get();
+        evicted.push((candidate, reason));
+    }
+    evicted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_test_utilities::types::ids::canister_test_id;
+
+    const MAX_IDLE_TIME: Duration = Duration::from_secs(60);
+
+    fn candidate(id: u64, now: Instant, idle_secs: u64, rss: u64) -> EvictionCandidate {
+        EvictionCandidate {
+            id: canister_test_id(id),
+            last_used: now - Duration::from_secs(idle_secs),
+            rss: NumBytes::new(rss),
+        }
+    }
+
+    fn evicted_ids(
+        evicted: &[(EvictionCandidate, EvictionReason)],
+    ) -> Vec<(CanisterId, EvictionReason)> {
+        evicted
+            .iter()
+            .map(|(candidate, reason)| (candidate.id, *reason))
+            .collect()
+    }
+
+    #[test]
+    fn evict_nothing_within_limits() {
+        let now = Instant::now();
+        let candidates = vec![candidate(0, now, 10, 100), candidate(1, now, 20, 100)];
+        let evicted = evict(candidates, now, MAX_IDLE_TIME, 2, NumBytes::new(200));
+        assert!(evicted.is_empty());
+    }
+
+    #[test]
+    fn evict_idle_processes() {
+        let now = Instant::now();
+        let candidates = vec![
+            candidate(0, now, 10, 100),
+            candidate(1, now, 120, 100),
+            candidate(2, now, 61, 100),
+        ];
+        let evicted = evict(candidates, now, MAX_IDLE_TIME, 10, NumBytes::new(1000));
+        assert_eq!(
+            evicted_ids(&evicted),
+            vec![
+                (canister_test_id(1), EvictionReason::Idle),
+                (canister_test_id(2), EvictionReason::Idle)
+            ]
+        );
+    }
+
+    #[test]
+    fn evict_least_recently_used_above_max_count() {
+        let now = Instant::now();
+        let candidates = vec![
+            candidate(0, now, 10, 100),
+            candidate(1, now, 30, 100),
+            candidate(2, now, 20, 100),
+        ];
+        let evicted = evict(candidates, now, MAX_IDLE_TIME, 1, NumBytes::new(1000));
+        assert_eq!(
+            evicted_ids(&evicted),
+            vec![
+                (canister_test_id(1), EvictionReason::Count),
+                (canister_test_id(2), EvictionReason::Count)
+            ]
+        );
+    }
+
+    #[test]
+    fn evict_least_recently_used_above_rss_budget() {
+        let now = Instant::now();
+        let candidates = vec![
+            candidate(0, now, 10, 300),
+            candidate(1, now, 30, 100),
+            candidate(2, now, 20, 500),
+        ];
+        let evicted = evict(candidates, now, MAX_IDLE_TIME, 10, NumBytes::new(400));
+        assert_eq!(
+            evicted_ids(&evicted),
+            vec![
+                (canister_test_id(1), EvictionReason::Rss),
+                (canister_test_id(2), EvictionReason::Rss)
+            ]
+        );
+    }
+
+    #[test]
+    fn evict_idle_before_enforcing_limits() {
+        let now = Instant::now();
+        let candidates = vec![
+            candidate(0, now, 10, 100),
+            candidate(1, now, 90, 100),
+            candidate(2, now, 30, 100),
+            candidate(3, now, 20, 100),
+        ];
+        let evicted = evict(candidates, now, MAX_IDLE_TIME, 2, NumBytes::new(150));
+        assert_eq!(
+            evicted_ids(&evicted),
+            vec![
+                (canister_test_id(1), EvictionReason::Idle),
+                (canister_test_id(2), EvictionReason::Count),
+                (canister_test_id(3), EvictionReason::Rss)
+            ]
+        );
+    }
+}
@@ -23,16 +23,15 @@ use ic_replicated_state::canister_state::execution_state::{
     SandboxMemory, SandboxMemoryHandle, SandboxMemoryOwner, WasmBinary,
 };
 use ic_replicated_state::{EmbedderCache, ExecutionState, ExportedFunctions, Memory, PageMap};
-use ic_types::{CanisterId, NumInstructions};
+use ic_types::{CanisterId, NumBytes, NumInstructions};
 use ic_wasm_types::CanisterModule;
-#[cfg(target_os = "linux")]
-use prometheus::IntGauge;
-use prometheus::{Histogram, HistogramVec, IntCounter, IntCounterVec};
+use prometheus::{Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge};
 use std::collections::{HashMap, VecDeque};
 #[cfg(target_os = "linux")]
 use std::convert::TryInto;
 use std::path::PathBuf;
 use std::process::ExitStatus;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Weak;
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -44,8 +43,8 @@ use crate::launch_as_process::{create_sandbox_process, spawn_launcher_process};
 use crate::process_exe_and_args::{create_launcher_argv, create_sandbox_argv};
 #[cfg(target_os = "linux")]
 use crate::process_os_metrics;
+use crate::sandbox_process_eviction::{self, EvictionCandidate};
 
-const SANDBOX_PROCESS_INACTIVE_TIME_BEFORE_EVICTION: Duration = Duration::from_secs(60);
 const SANDBOX_PROCESS_UPDATE_INTERVAL: Duration = Duration::from_secs(10);
 
 const SANDBOXED_EXECUTION_INVALID_MEMORY_SIZE: &str = "sandboxed_execution_invalid_memory_size";
@@ -60,6 +59,12 @@ const COMPILATION_CACHE_HIT: &str = "compilation_cache_hit";
 const COMPILATION_CACHE_HIT_COMPILATION_ERROR: &str = "compilation_cache_hit_compilation_error";
 const CACHE_MISS: &str = "cache_miss";
 
+// Metric labels for the different ways of obtaining a sandbox process. Stored
+// in the metric [`SandboxedExecutionMetrics::sandboxed_execution_get_sandbox_process`].
+const SANDBOX_PROCESS_REUSED_ACTIVE: &str = "reused_active";
+const SANDBOX_PROCESS_REUSED_EVICTED: &str = "reused_evicted";
+const SANDBOX_PROCESS_SPAWNED: &str = "spawned";
+
 struct SandboxedExecutionMetrics {
     sandboxed_execution_replica_execute_duration: HistogramVec,
     sandboxed_execution_replica_execute_prepare_duration: HistogramVec,
@@ -68,6 +73,9 @@ struct SandboxedExecutionMetrics {
     sandboxed_execution_sandbox_execute_duration: HistogramVec,
     sandboxed_execution_sandbox_execute_run_duration: HistogramVec,
     sandboxed_execution_spawn_process: Histogram,
+    sandboxed_execution_get_sandbox_process: IntCounterVec,
+    sandboxed_execution_subprocess_evictions: IntCounterVec,
+    sandboxed_execution_subprocess_active_count: IntGauge,
     #[cfg(target_os = "linux")]
     sandboxed_execution_subprocess_anon_rss_total: IntGauge,
     #[cfg(target_os = "linux")]
@@ -148,6 +156,20 @@ impl SandboxedExecutionMetrics {
                 "The time to spawn a sandbox process",
                 decimal_buckets_with_zero(-4, 1),
             ),
+            sandboxed_execution_get_sandbox_process: metrics_registry.int_counter_vec(
+                "sandboxed_execution_get_sandbox_process_total",
+                "The number of sandbox process lookups by whether a process was reused or spawned",
+                &["outcome"],
+            ),
+            sandboxed_execution_subprocess_evictions: metrics_registry.int_counter_vec(
+                "sandboxed_execution_subprocess_evictions_total",
+                "The number of evicted sandbox processes by eviction reason",
+                &["reason"],
+            ),
+            sandboxed_execution_subprocess_active_count: metrics_registry.int_gauge(
+                "sandboxed_execution_subprocess_active_count",
+                "The number of active sandbox processes in the warm pool",
+            ),
             #[cfg(target_os = "linux")]
             sandboxed_execution_subprocess_anon_rss_total: metrics_registry.int_gauge(
                 "sandboxed_execution_subprocess_anon_rss_total_kib",
@@ -322,6 +344,38 @@ pub struct SandboxProcess {
     /// History of operations sent to sandbox process (for crash
     /// diagnostics).
     history: SandboxProcessRequestHistory,
+
+    /// The page maps of the memories that are open in the sandbox process.
+    /// The sandbox process has write access to their pages, so they are
+    /// revalidated if the process dies unexpectedly.
+    open_memories: Mutex<HashMap<MemoryId, PageMap>>,
+
+    /// The last measured resident set size of the process in bytes. It is
+    /// updated periodically by the monitoring thread and used to enforce the
+    /// RSS budget of the active sandbox processes.
+    rss: AtomicU64,
+}
+
+impl SandboxProcess {
+    /// Revalidates the pages of all memories open in this sandbox process
+    /// after the process died unexpectedly and logs the pages that failed
+    /// revalidation and were quarantined.
+    fn revalidate_open_memories(&self, logger: &ReplicaLogger, canister_id: CanisterId) {
+        let guard = self.open_memories.lock().unwrap();
+        for (memory_id, page_map) in guard.iter() {
+            let suspect_pages = page_map.revalidate_pages();
+            if !suspect_pages.is_empty() {
+                error!(
+                    logger,
+                    "Quarantined pages {:?} of memory {} of canister {} with pid {}",
+                    suspect_pages,
+                    memory_id,
+                    canister_id,
+                    self.pid
+                );
+            }
+        }
+    }
 }
 
 impl Drop for SandboxProcess {
@@ -398,6 +452,11 @@ impl SandboxMemoryOwner for OpenedMemory {
 
 impl Drop for OpenedMemory {
     fn drop(&mut self) {
+        self.sandbox_process
+            .open_memories
+            .lock()
+            .unwrap()
+            .remove(&self.memory_id);
         self.sandbox_process
             .history
             .record(format!("CloseMemory(memory_id={})", self.memory_id));
@@ -440,6 +499,14 @@ struct SandboxProcessStats {
     status: SandboxProcessStatus,
 }
 
+/// Limits of the pool of active sandbox processes. Processes above the limits
+/// are evicted in least recently used order.
+struct SandboxEvictionPolicy {
+    max_sandbox_count: usize,
+    max_sandbox_idle_time: Duration,
+    max_sandboxes_rss: NumBytes,
+}
+
 // Represent a paused sandbox execution.
 struct PausedSandboxExecution {
     canister_id: CanisterId,
@@ -526,6 +593,8 @@ pub struct SandboxedExecutionController {
     sandbox_exec_argv: Vec<String>,
     metrics: Arc<SandboxedExecutionMetrics>,
     launcher_service: Box<dyn LauncherService>,
+    /// Sandbox processes that are not used for longer than this are evicted.
+    max_sandbox_idle_time: Duration,
 }
 
 impl WasmExecutor for SandboxedExecutionController {
@@ -778,8 +847,11 @@ impl WasmExecutor for SandboxedExecutionController {
         wasm_memory
             .page_map
             .deserialize_delta(memory_modifications.page_delta);
-        wasm_memory.sandbox_memory =
-            SandboxMemory::synced(wrap_remote_memory(&sandbox_process, next_wasm_memory_id));
+        wasm_memory.sandbox_memory = SandboxMemory::synced(wrap_remote_memory(
+            &sandbox_process,
+            next_wasm_memory_id,
+            &wasm_memory.page_map,
+        ));
         if let Err(err) = wasm_memory.verify_size() {
             error!(
                 self.logger,
@@ -872,18 +944,27 @@ impl SandboxedExecutionController {
         let backends_copy = Arc::clone(&backends);
         let metrics_copy = Arc::clone(&metrics);
         let logger_copy = logger.clone();
+        let eviction_policy = SandboxEvictionPolicy {
+            max_sandbox_count: embedder_config.max_sandbox_count,
+            max_sandbox_idle_time: embedder_config.max_sandbox_idle_time,
+            max_sandboxes_rss: embedder_config.max_sandboxes_rss,
+        };
 
         std::thread::spawn(move || {
             SandboxedExecutionController::monitor_and_evict_sandbox_processes(
                 logger_copy,
                 backends_copy,
                 metrics_copy,
+                eviction_policy,
             );
         });
 
         let exit_watcher = Arc::new(ExitWatcher {
             logger: logger.clone(),
             backends: Arc::clone(&backends),
+            revalidate_pages_after_crash: embedder_config
+                .page_allocator_config
+                .revalidate_pages_after_sandbox_crash,
         });
 
         let (launcher_service, mut child) = spawn_launcher_process(
@@ -907,20 +988,31 @@ impl SandboxedExecutionController {
             sandbox_exec_argv,
             metrics,
             launcher_service,
+            max_sandbox_idle_time: embedder_config.max_sandbox_idle_time,
         })
     }
 
     // Periodically walk through all the backend processes and:
-    // - evict inactive processes,
+    // - evict inactive processes and processes exceeding the pool limits,
     // - update memory usage metrics.
     fn monitor_and_evict_sandbox_processes(
         // `logger` isn't used on MacOS.
         #[allow(unused_variables)] logger: ReplicaLogger,
         backends: Arc<Mutex<HashMap<CanisterId, Backend>>>,
         metrics: Arc<SandboxedExecutionMetrics>,
+        eviction_policy: SandboxEvictionPolicy,
     ) {
         loop {
-            let sandbox_processes = scavenge_sandbox_processes(&backends);
+            let sandbox_processes =
+                scavenge_sandbox_processes(&backends, &eviction_policy, &metrics);
+
+            let active_count = sandbox_processes
+                .iter()
+                .filter(|(_, stats)| matches!(stats.status, SandboxProcessStatus::Active))
+                .count();
+            metrics
+                .sandboxed_execution_subprocess_active_count
+                .set(active_count as i64);
 
             #[cfg(target_os = "linux")]
             {
@@ -953,6 +1045,9 @@ impl SandboxedExecutionController {
                     metrics
                         .sandboxed_execution_subprocess_rss
                         .observe(process_rss as f64);
+                    sandbox_process
+                        .rss
+                        .store(process_rss * 1024, Ordering::Relaxed);
                     match stats.status {
                         SandboxProcessStatus::Active => {
                             metrics
@@ -1016,15 +1111,21 @@ impl SandboxedExecutionController {
             let sandbox_process = match old {
                 Backend::Active {
                     sandbox_process, ..
-                } => Some(sandbox_process),
+                } => Some((sandbox_process, SANDBOX_PROCESS_REUSED_ACTIVE)),
                 Backend::Evicted {
                     sandbox_process, ..
-                } => sandbox_process.upgrade(),
+                } => sandbox_process
+                    .upgrade()
+                    .map(|sandbox_process| (sandbox_process, SANDBOX_PROCESS_REUSED_EVICTED)),
                 Backend::Empty => None,
             };
-            if let Some(sandbox_process) = sandbox_process {
+            if let Some((sandbox_process, outcome)) = sandbox_process {
+                self.metrics
+                    .sandboxed_execution_get_sandbox_process
+                    .with_label_values(&[outcome])
+                    .inc();
                 let now = std::time::Instant::now();
-                if SANDBOX_PROCESS_INACTIVE_TIME_BEFORE_EVICTION.as_secs() > 0 {
+                if self.max_sandbox_idle_time.as_secs() > 0 {
                     *backend = Backend::Active {
                         sandbox_process: Arc::clone(&sandbox_process),
                         last_used: now,
@@ -1039,6 +1140,10 @@ impl SandboxedExecutionController {
             }
         }
 
+        self.metrics
+            .sandboxed_execution_get_sandbox_process
+            .with_label_values(&[SANDBOX_PROCESS_SPAWNED])
+            .inc();
         let _timer = self.metrics.sandboxed_execution_spawn_process.start_timer();
         // No sandbox process found for this canister. Start a new one and register it.
         let reg = Arc::new(ActiveExecutionStateRegistry::new());
@@ -1057,6 +1162,8 @@ impl SandboxedExecutionController {
             sandbox_service,
             pid,
             history: SandboxProcessRequestHistory::new(),
+            open_memories: Mutex::new(HashMap::new()),
+            rss: AtomicU64::new(0),
         });
 
         let now = std::time::Instant::now();
@@ -1156,6 +1263,7 @@ impl SandboxedExecutionController {
                 wasm_memory.sandbox_memory = SandboxMemory::synced(wrap_remote_memory(
                     &sandbox_process,
                     next_wasm_memory_id,
+                    &wasm_memory.page_map,
                 ));
                 if let Err(err) = wasm_memory.verify_size() {
                     error!(
@@ -1177,6 +1285,7 @@ impl SandboxedExecutionController {
                 stable_memory.sandbox_memory = SandboxMemory::synced(wrap_remote_memory(
                     &sandbox_process,
                     next_stable_memory_id,
+                    &stable_memory.page_map,
                 ));
                 if let Err(err) = stable_memory.verify_size() {
                     error!(
@@ -1334,27 +1443,84 @@ fn open_remote_memory(
                     memory: serialized_memory,
                 })
                 .on_completion(|_| {});
-            let handle = wrap_remote_memory(sandbox_process, memory_id);
+            let handle = wrap_remote_memory(sandbox_process, memory_id, &memory.page_map);
             *guard = SandboxMemory::Synced(handle.clone());
             handle
         }
     }
 }
 
+// Returns a handle of the remote memory with the given id, which holds the
+// given page map. The page map is recorded for revalidation in case the
+// sandbox process dies unexpectedly.
 fn wrap_remote_memory(
     sandbox_process: &Arc<SandboxProcess>,
     memory_id: MemoryId,
+    page_map: &PageMap,
 ) -> SandboxMemoryHandle {
+    sandbox_process
+        .open_memories
+        .lock()
+        .unwrap()
+        .insert(memory_id, page_map.clone());
     let opened_memory = OpenedMemory::new(Arc::clone(sandbox_process), memory_id);
     SandboxMemoryHandle::new(Arc::new(opened_memory))
 }
 
-// Evicts inactive process and returns all processes that are still alive.
+// Evicts inactive processes as well as the least recently used processes
+// exceeding the limits of the pool and returns all processes that are still
+// alive.
 fn scavenge_sandbox_processes(
     backends: &Arc<Mutex<HashMap<CanisterId, Backend>>>,
+    eviction_policy: &SandboxEvictionPolicy,
+    metrics: &SandboxedExecutionMetrics,
 ) -> Vec<(Arc<SandboxProcess>, SandboxProcessStats)> {
     let mut guard = backends.lock().unwrap();
     let now = std::time::Instant::now();
+
+    let candidates: Vec<_> = guard
+        .iter()
+        .filter_map(|(id, backend)| match backend {
+            Backend::Active {
+                sandbox_process,
+                last_used,
+            } => Some(EvictionCandidate {
+                id: *id,
+                last_used: *last_used,
+                rss: NumBytes::new(sandbox_process.rss.load(Ordering::Relaxed)),
+            }),
+            Backend::Evicted { .. } | Backend::Empty => None,
+        })
+        .collect();
+
+    let evicted = sandbox_process_eviction::evict(
+        candidates,
+        now,
+        eviction_policy.max_sandbox_idle_time,
+        eviction_policy.max_sandbox_count,
+        eviction_policy.max_sandboxes_rss,
+    );
+
+    for (candidate, reason) in evicted {
+        if let Some(backend) = guard.get_mut(&candidate.id) {
+            let old = std::mem::replace(backend, Backend::Empty);
+            *backend = match old {
+                Backend::Active {
+                    sandbox_process,
+                    last_used,
+                } => Backend::Evicted {
+                    sandbox_process: Arc::downgrade(&sandbox_process),
+                    last_used,
+                },
+                other => other,
+            };
+        }
+        metrics
+            .sandboxed_execution_subprocess_evictions
+            .with_label_values(&[reason.as_str()])
+            .inc();
+    }
+
     let mut result = vec![];
     for backend in guard.values_mut() {
         let old = std::mem::replace(backend, Backend::Empty);
@@ -1366,30 +1532,16 @@ fn scavenge_sandbox_processes(
                 let inactive_time = now
                     .checked_duration_since(last_used)
                     .unwrap_or_else(|| std::time::Duration::from_secs(0));
-                if inactive_time > SANDBOX_PROCESS_INACTIVE_TIME_BEFORE_EVICTION {
-                    result.push((
-                        Arc::clone(&sandbox_process),
-                        SandboxProcessStats {
-                            time_since_last_usage: inactive_time,
-                            status: SandboxProcessStatus::Evicted,
-                        },
-                    ));
-                    Backend::Evicted {
-                        sandbox_process: Arc::downgrade(&sandbox_process),
-                        last_used,
-                    }
-                } else {
-                    result.push((
-                        Arc::clone(&sandbox_process),
-                        SandboxProcessStats {
-                            time_since_last_usage: inactive_time,
-                            status: SandboxProcessStatus::Active,
-                        },
-                    ));
-                    Backend::Active {
-                        sandbox_process,
-                        last_used,
-                    }
+                result.push((
+                    Arc::clone(&sandbox_process),
+                    SandboxProcessStats {
+                        time_since_last_usage: inactive_time,
+                        status: SandboxProcessStatus::Active,
+                    },
+                ));
+                Backend::Active {
+                    sandbox_process,
+                    last_used,
                 }
             }
             Backend::Evicted {
@@ -1531,10 +1683,13 @@ mod tests {
 }
 
 /// Service responsible for printing the history of a canister's activity when
-/// it unexpectedly exits.
+/// it unexpectedly exits. It also revalidates the pages that the sandbox
+/// process had access to if page revalidation after sandbox crashes is
+/// enabled.
 struct ExitWatcher {
     logger: ReplicaLogger,
     backends: Arc<Mutex<HashMap<CanisterId, Backend>>>,
+    revalidate_pages_after_crash: bool,
 }
 
 impl ControllerLauncherService for ExitWatcher {
@@ -1559,6 +1714,9 @@ impl ControllerLauncherService for ExitWatcher {
         sandbox_process
             .history
             .replay(&self.logger, req.canister_id, sandbox_process.pid);
+        if self.revalidate_pages_after_crash {
+            sandbox_process.revalidate_open_memories(&self.logger, req.canister_id);
+        }
         rpc::Call::new_resolved(Ok(protocol::ctllaunchersvc::SandboxExitedReply))
     }
 }
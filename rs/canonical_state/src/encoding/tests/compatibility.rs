@@ -22,6 +22,7 @@ use ic_test_utilities::types::{
 use ic_types::{
     crypto::CryptoHash,
     messages::{CallbackId, Payload, RejectContext, Request, RequestOrResponse, Response},
+    time::CoarseTime,
     xnet::StreamHeader,
     CryptoHashOfPartialState, Cycles, Funds,
 };
@@ -177,6 +178,48 @@ fn canonical_encoding_request() {
     );
 }
 
+/// Canonical CBOR encoding of a best-effort request, i.e. the request in
+/// `canonical_encoding_request()` with a deadline of 7 seconds.
+///
+/// Expected, starting with certification version 12:
+///
+/// ```text
+/// A1                            # map(1)
+///    00                         # field_index(RequestOrResponse::request)
+///    A7                         # map(7)
+///       ...                     # same as in `canonical_encoding_request()`
+///       07                      # field_index(Request::deadline)
+///       07                      # unsigned(7)
+/// ```
+///
+/// The deadline is not encoded by earlier certification versions.
+#[test]
+fn canonical_encoding_request_with_deadline() {
+    let request: RequestOrResponse = RequestBuilder::new()
+        .receiver(canister_test_id(1))
+        .sender(canister_test_id(2))
+        .sender_reply_callback(CallbackId::from(3))
+        .payment(Cycles::new(4))
+        .method_name("test".to_string())
+        .method_payload(vec![6])
+        .deadline(CoarseTime::from_secs_since_unix_epoch(7))
+        .build()
+        .into();
+
+    for certification_version in all_supported_versions() {
+        let expected = if certification_version >= CertificationVersion::V12 {
+            "A1 00 A7 00 4A 00 00 00 00 00 00 00 01 01 01 01 4A 00 00 00 00 00 00 00 02 01 01 02 03 03 A1 00 A1 00 04 04 64 74 65 73 74 05 41 06 07 07"
+        } else {
+            "A1 00 A6 00 4A 00 00 00 00 00 00 00 01 01 01 01 4A 00 00 00 00 00 00 00 02 01 01 02 03 03 A1 00 A1 00 04 04 64 74 65 73 74 05 41 06"
+        };
+        assert_eq!(expected, as_hex(&encode_message(&request, certification_version)));
+    }
+    assert_eq!(
+        request,
+        decode_message(&encode_message(&request, CertificationVersion::V12)).unwrap()
+    );
+}
+
 /// Canonical CBOR encoding of:
 ///
 /// ```no_run
@@ -65,6 +65,9 @@ pub struct Request {
     pub method_payload: Bytes,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cycles_payment: Option<Cycles>,
+    /// Deadline of a best-effort request, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<u32>,
 }
 
 /// Canonical representation of `ic_types::messages::Response`.
@@ -80,6 +83,9 @@ pub struct Response {
     pub response_payload: Payload,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cycles_refund: Option<Cycles>,
+    /// Deadline of a best-effort response, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<u32>,
 }
 
 /// Canonical representation of `ic_types::funds::Cycles`.
@@ -247,6 +253,7 @@ impl From<(&ic_types::messages::Request, CertificationVersion)> for Request {
             method_name: request.method_name.clone(),
             method_payload: request.method_payload.clone(),
             cycles_payment: None,
+            deadline: encode_deadline(request.deadline, certification_version),
         }
     }
 }
@@ -268,6 +275,7 @@ impl TryFrom<Request> for ic_types::messages::Request {
             payment,
             method_name: request.method_name,
             method_payload: request.method_payload,
+            deadline: decode_deadline(request.deadline),
         })
     }
 }
@@ -287,6 +295,7 @@ impl From<(&ic_types::messages::Response, CertificationVersion)> for Response {
             refund: funds,
             response_payload: (&response.response_payload, certification_version).into(),
             cycles_refund: None,
+            deadline: encode_deadline(response.deadline, certification_version),
         }
     }
 }
@@ -307,10 +316,33 @@ impl TryFrom<Response> for ic_types::messages::Response {
             originator_reply_callback: response.originator_reply_callback.into(),
             refund,
             response_payload: response.response_payload.try_into()?,
+            deadline: decode_deadline(response.deadline),
         })
     }
 }
 
+/// Encodes the deadline of a best-effort message. Deadlines are only encoded
+/// starting with certification version 12.
+fn encode_deadline(
+    deadline: ic_types::time::CoarseTime,
+    certification_version: CertificationVersion,
+) -> Option<u32> {
+    if deadline != ic_types::time::NO_DEADLINE
+        && certification_version >= CertificationVersion::V12
+    {
+        Some(deadline.as_secs_since_unix_epoch())
+    } else {
+        None
+    }
+}
+
+fn decode_deadline(deadline: Option<u32>) -> ic_types::time::CoarseTime {
+    deadline.map_or(
+        ic_types::time::NO_DEADLINE,
+        ic_types::time::CoarseTime::from_secs_since_unix_epoch,
+    )
+}
+
 impl From<(&ic_types::funds::Cycles, CertificationVersion)> for Cycles {
     fn from(
         (cycles, _certification_version): (&ic_types::funds::Cycles, CertificationVersion),
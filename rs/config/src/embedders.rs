@@ -2,6 +2,8 @@ use ic_base_types::NumBytes;
 use ic_sys::PAGE_SIZE;
 use ic_types::{NumInstructions, NumPages};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
 
 use crate::flag_status::FlagStatus;
 
@@ -37,6 +39,65 @@ const GiB: u64 = KiB * KiB * KiB;
 // is allowed to produce.
 const STABLE_MEMORY_DIRTY_PAGE_LIMIT: u64 = 8 * GiB / (PAGE_SIZE as u64);
 
+/// The maximum number of active sandbox processes kept in the warm pool.
+const DEFAULT_MAX_SANDBOX_COUNT: usize = 1_000;
+
+/// Sandbox processes that were not used for this long are evicted.
+const DEFAULT_MAX_SANDBOX_IDLE_TIME: Duration = Duration::from_secs(60);
+
+/// The total resident memory budget of all active sandbox processes.
+const DEFAULT_MAX_SANDBOXES_RSS: NumBytes = NumBytes::new(40 * GiB);
+
+// The maximum size of a single backing file of a page allocator. Larger page
+// allocators continue in additional backing files.
+const MAX_PAGE_ALLOCATOR_FILE_SIZE: NumBytes = NumBytes::new(16 * GiB);
+
+/// The configuration of the page allocators backing canister memory.
+///
+/// Page allocators are shared between the replica and the sandbox processes,
+/// so both apply the same configuration at startup.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(default)]
+pub struct PageAllocatorConfig {
+    /// Whether to back the memory of the page allocators with transparent
+    /// huge pages. Reduces TLB pressure when executing canisters with large
+    /// heaps at the cost of allocating memory in 2MiB chunks.
+    pub transparent_huge_pages: bool,
+    /// Whether to record a checksum of every page and verify it when the
+    /// page is transferred to another process, to detect corruption of the
+    /// backing files at the cost of hashing every written page.
+    pub page_checksums: bool,
+    /// The size after which a page allocator continues in a new backing
+    /// file instead of growing the current one.
+    pub max_backing_file_size: NumBytes,
+    /// Whether page allocators share the memory of identical pages, e.g. the
+    /// static data of many canisters instantiated from the same Wasm module,
+    /// at the cost of hashing every written page.
+    pub page_deduplication: bool,
+    /// Whether to revalidate the pages shared with a sandbox process after
+    /// the process dies unexpectedly and quarantine the pages that may have
+    /// been left half-written, at the cost of reading all these pages.
+    pub revalidate_pages_after_sandbox_crash: bool,
+    /// The directory for the backing files, e.g. a tmpfs mount or a dedicated
+    /// disk partition. If it is not set or a file cannot be created in it,
+    /// the backing files are in-memory files on Linux and temporary files
+    /// elsewhere.
+    pub backing_file_directory: Option<PathBuf>,
+}
+
+impl Default for PageAllocatorConfig {
+    fn default() -> Self {
+        Self {
+            transparent_huge_pages: false,
+            page_checksums: false,
+            max_backing_file_size: MAX_PAGE_ALLOCATOR_FILE_SIZE,
+            page_deduplication: false,
+            revalidate_pages_after_sandbox_crash: false,
+            backing_file_directory: None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 pub struct FeatureFlags {
     pub rate_limiting_of_debug_prints: FlagStatus,
@@ -45,6 +106,13 @@ pub struct FeatureFlags {
     pub new_wasm_transform_lib: FlagStatus,
     /// Track dirty pages with a write barrier instead of the signal handler.
     pub write_barrier: FlagStatus,
+    /// Count the instructions executed by each function of a canister and
+    /// report them as a profile after each message execution. Requires the
+    /// new instrumentation (`new_wasm_transform_lib`).
+    pub instruction_profiling: FlagStatus,
+    /// Allow best-effort calls with `ic0.call_with_best_effort_response`.
+    /// Calling it traps while this is disabled.
+    pub best_effort_responses: FlagStatus,
 }
 
 impl Default for FeatureFlags {
@@ -53,6 +121,8 @@ impl Default for FeatureFlags {
             rate_limiting_of_debug_prints: FlagStatus::Enabled,
             new_wasm_transform_lib: FlagStatus::Enabled,
             write_barrier: FlagStatus::Disabled,
+            instruction_profiling: FlagStatus::Disabled,
+            best_effort_responses: FlagStatus::Disabled,
         }
     }
 }
@@ -87,6 +157,23 @@ pub struct Config {
     // Maximum number of stable memory dirty pages that a single message execution
     // is allowed to produce.
     pub stable_memory_dirty_page_limit: NumPages,
+
+    /// The maximum number of active sandbox processes. The least recently
+    /// used processes are evicted when this number is exceeded.
+    pub max_sandbox_count: usize,
+
+    /// Active sandbox processes that were not used for longer than this are
+    /// evicted.
+    pub max_sandbox_idle_time: Duration,
+
+    /// The budget for the total resident memory of all active sandbox
+    /// processes. The least recently used processes are evicted when the
+    /// budget is exceeded.
+    pub max_sandboxes_rss: NumBytes,
+
+    /// The configuration of the page allocators, which the sandbox processes
+    /// apply at startup.
+    pub page_allocator_config: PageAllocatorConfig,
 }
 
 impl Config {
@@ -102,6 +189,10 @@ impl Config {
             num_rayon_compilation_threads: DEFAULT_WASMTIME_RAYON_COMPILATION_THREADS,
             feature_flags: FeatureFlags::default(),
             stable_memory_dirty_page_limit: NumPages::from(STABLE_MEMORY_DIRTY_PAGE_LIMIT),
+            max_sandbox_count: DEFAULT_MAX_SANDBOX_COUNT,
+            max_sandbox_idle_time: DEFAULT_MAX_SANDBOX_IDLE_TIME,
+            max_sandboxes_rss: DEFAULT_MAX_SANDBOXES_RSS,
+            page_allocator_config: PageAllocatorConfig::default(),
         }
     }
 }
@@ -1,5 +1,5 @@
 use crate::{
-    embedders::{self, QUERY_EXECUTION_THREADS},
+    embedders::{self, PageAllocatorConfig, QUERY_EXECUTION_THREADS},
     flag_status::FlagStatus,
     subnet_config::MAX_INSTRUCTIONS_PER_MESSAGE_WITHOUT_DTS,
 };
@@ -9,8 +9,10 @@ use ic_types::{
 };
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
+use std::time::Duration;
 
-const GB: u64 = 1024 * 1024 * 1024;
+const MB: u64 = 1024 * 1024;
+const GB: u64 = 1024 * MB;
 
 /// This is the upper limit on how much logical storage canisters can request to
 /// be store on a given subnet.
@@ -24,6 +26,11 @@ const GB: u64 = 1024 * 1024 * 1024;
 /// canister's data and the deltas.
 const SUBNET_MEMORY_CAPACITY: NumBytes = NumBytes::new(450 * GB);
 
+/// Once the memory usage of the subnet exceeds this threshold, canisters that
+/// allocate memory reserve cycles for future storage payments. The amount
+/// grows linearly with the usage between the threshold and the capacity.
+const SUBNET_MEMORY_THRESHOLD: NumBytes = NumBytes::new(300 * GB);
+
 /// This is the upper limit on how much memory can be used by all canister
 /// messages on a given subnet.
 ///
@@ -59,6 +66,21 @@ pub(crate) const MAX_INSTRUCTIONS_PER_COMPOSITE_QUERY_CALL: u64 = 5_000_000_000;
 /// This would allow 100 calls with the current MAX_INSTRUCTIONS_PER_COMPOSITE_QUERY_CALL
 pub const INSTRUCTION_OVERHEAD_PER_QUERY_CALL: u64 = 50_000_000;
 
+/// The upper limit on the memory used by the cached results of user queries.
+const QUERY_CACHE_CAPACITY: NumBytes = NumBytes::new(200 * MB);
+
+/// The maximum length of a reject message produced by a canister or by the
+/// system. Longer messages are truncated, so that a single reject cannot
+/// take up megabytes of a stream. The limit is well above the length of the
+/// reject messages that the system produces.
+pub const MAX_REJECT_MESSAGE_LEN_BYTES: usize = 8 * 1024;
+
+/// The maximum lifetime of an open call context. Once it is exceeded, the
+/// outstanding guaranteed response calls of the call context are rejected, so
+/// that a callee that never responds doesn't keep its callers from stopping
+/// and upgrading forever.
+pub const MAX_CALL_CONTEXT_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
 // The ID of the Bitcoin testnet canister.
 const BITCOIN_TESTNET_CANISTER_ID: &str = "g4xu7-jiaaa-aaaan-aaaaq-cai";
 
@@ -87,12 +109,17 @@ pub struct Config {
     /// the subnet.
     pub subnet_memory_capacity: NumBytes,
 
+    /// The memory usage of the subnet above which memory allocations reserve
+    /// cycles, see `SUBNET_MEMORY_THRESHOLD`.
+    pub subnet_memory_threshold: NumBytes,
+
     /// The maximum amount of logical storage available to canister messages
     /// across the whole subnet.
     pub subnet_message_memory_capacity: NumBytes,
 
     /// The maximum amount of logical storage available to the ingress history
-    /// across the whole subnet.
+    /// across the whole subnet. When it is exceeded, the oldest `Completed` and
+    /// `Failed` statuses are transitioned to `Done`, which forgets their replies.
     pub ingress_history_memory_capacity: NumBytes,
 
     /// The maximum amount of memory that can be utilized by a single canister.
@@ -117,6 +144,11 @@ pub struct Config {
     /// The maximum depth of the query call tree.
     pub max_query_call_depth: usize,
 
+    /// The maximum number of instructions that a single query can execute, in
+    /// both replicated and non-replicated mode. It cannot exceed the limit
+    /// for messages without deterministic time slicing.
+    pub max_instructions_per_query: NumInstructions,
+
     /// Maximum total number of cycles allowed for composite queries.
     pub max_instructions_per_composite_query_call: NumInstructions,
 
@@ -155,6 +187,48 @@ pub struct Config {
 
     /// Indicates whether composite queries are available or not.
     pub composite_queries: FlagStatus,
+
+    /// Indicates whether the results of user queries are cached or not.
+    pub query_caching: FlagStatus,
+
+    /// The maximum total size of the cached query results.
+    pub query_cache_capacity: NumBytes,
+
+    /// The configuration of the page allocators backing canister memory.
+    pub page_allocator_config: PageAllocatorConfig,
+
+    /// If this flag is enabled, then the instructions executed by each
+    /// function of a canister are counted and kept as the instruction profile
+    /// of the last message execution. Meant for tests and local development.
+    pub instruction_profiling: FlagStatus,
+
+    /// If set, faults are injected into the execution of canisters to flush
+    /// out canister code that relies on favorable interleavings. Meant for
+    /// tests only.
+    pub chaos_mode: Option<ChaosConfig>,
+
+    /// Indicates whether reject messages longer than
+    /// `max_reject_message_len_bytes` are truncated.
+    pub reject_message_truncation: FlagStatus,
+
+    /// The maximum length of a reject message, including the suffix that
+    /// marks a truncated message.
+    pub max_reject_message_len_bytes: usize,
+
+    /// Indicates whether canisters can make best-effort calls with
+    /// `ic0.call_with_best_effort_response`. Must stay disabled until the
+    /// subnet certifies its state with version 12, the first one that encodes
+    /// the deadlines of messages in streams.
+    pub best_effort_responses: FlagStatus,
+
+    /// Indicates whether the outstanding guaranteed response calls of call
+    /// contexts older than `max_call_context_lifetime` are rejected with
+    /// `SYS_UNKNOWN`.
+    pub stuck_call_timeouts: FlagStatus,
+
+    /// The maximum lifetime of an open call context, see
+    /// `stuck_call_timeouts`.
+    pub max_call_context_lifetime: Duration,
 }
 
 impl Default for Config {
@@ -173,6 +247,7 @@ impl Default for Config {
             create_funds_whitelist: String::default(),
             max_instructions_for_message_acceptance_calls: MAX_INSTRUCTIONS_PER_MESSAGE_WITHOUT_DTS,
             subnet_memory_capacity: SUBNET_MEMORY_CAPACITY,
+            subnet_memory_threshold: SUBNET_MEMORY_THRESHOLD,
             subnet_message_memory_capacity: SUBNET_MESSAGE_MEMORY_CAPACITY,
             ingress_history_memory_capacity: INGRESS_HISTORY_MEMORY_CAPACITY,
             max_canister_memory_size: NumBytes::new(
@@ -187,6 +262,7 @@ impl Default for Config {
             canister_sandboxing_flag: FlagStatus::Enabled,
             query_execution_threads: QUERY_EXECUTION_THREADS,
             max_query_call_depth: MAX_QUERY_CALL_DEPTH,
+            max_instructions_per_query: MAX_INSTRUCTIONS_PER_MESSAGE_WITHOUT_DTS,
             max_instructions_per_composite_query_call: NumInstructions::from(
                 MAX_INSTRUCTIONS_PER_COMPOSITE_QUERY_CALL,
             ),
@@ -211,10 +287,44 @@ impl Default for Config {
                 mainnet_canister_id: Some(bitcoin_mainnet_canister_id),
             },
             composite_queries: FlagStatus::Disabled,
+            query_caching: FlagStatus::Disabled,
+            query_cache_capacity: QUERY_CACHE_CAPACITY,
+            page_allocator_config: PageAllocatorConfig::default(),
+            instruction_profiling: FlagStatus::Disabled,
+            chaos_mode: None,
+            reject_message_truncation: FlagStatus::Disabled,
+            max_reject_message_len_bytes: MAX_REJECT_MESSAGE_LEN_BYTES,
+            best_effort_responses: FlagStatus::Disabled,
+            stuck_call_timeouts: FlagStatus::Disabled,
+            max_call_context_lifetime: MAX_CALL_CONTEXT_LIFETIME,
         }
     }
 }
 
+/// The configuration of the chaos mode, in which faults are injected into the
+/// execution of canisters.
+///
+/// Whether a fault is injected is derived from `seed` and the affected message,
+/// so a test run can be replayed by using the same seed. The probabilities are
+/// given in parts per million.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize, Default)]
+#[serde(default)]
+pub struct ChaosConfig {
+    pub seed: u64,
+
+    /// The probability of reordering messages that are not required to be
+    /// delivered in order, i.e. messages from different senders.
+    pub reorder_probability_ppm: u32,
+
+    /// The probability of holding back a response to a later round when
+    /// inducting it on the same subnet.
+    pub delay_response_probability_ppm: u32,
+
+    /// The probability of making the execution of an update call or a
+    /// response callback trap.
+    pub trap_probability_ppm: u32,
+}
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Default)]
 pub struct BitcoinConfig {
     /// Canisters that have access to privileged bitcoin API (e.g. `bitcoin_get_successors`)
@@ -4,6 +4,7 @@
 use std::time::Duration;
 
 use crate::execution_environment::SUBNET_HEAP_DELTA_CAPACITY;
+use crate::flag_status::FlagStatus;
 use ic_base_types::NumBytes;
 use ic_registry_subnet_type::SubnetType;
 use ic_types::{Cycles, NumInstructions};
@@ -109,6 +110,11 @@ const NUMBER_OF_EXECUTION_THREADS: usize = 4;
 ///
 const MAX_PAUSED_EXECUTIONS: usize = 4;
 
+/// The time after which a due global timer gets scheduled ahead of the other
+/// canisters. With a round every second, two seconds leave the regular
+/// scheduling a round to run the timer before it is prioritized.
+const MAX_GLOBAL_TIMER_DELAY: Duration = Duration::from_secs(2);
+
 /// 10B cycles corresponds to 1 SDR cent. Assuming we can create 1 signature per
 /// second, that would come to  26k SDR per month if we spent the whole time
 /// creating signatures. At 13 nodes and 2k SDR per node per month this would
@@ -125,6 +131,16 @@ const DEFAULT_REFERENCE_SUBNET_SIZE: usize = 13;
 const DEFAULT_DIRTY_PAGE_OVERHEAD: NumInstructions = NumInstructions::new(1_000);
 const SYSTEM_SUBNET_DIRTY_PAGE_OVERHEAD: NumInstructions = NumInstructions::new(0);
 
+/// The order in which the scheduler runs the heartbeat and the global timer
+/// of a canister if both are due in the same round.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SystemTaskPriority {
+    /// The global timer runs before the heartbeat.
+    GlobalTimerFirst,
+    /// The heartbeat runs before the global timer.
+    HeartbeatFirst,
+}
+
 /// The per subnet type configuration for the scheduler component
 #[derive(Clone)]
 pub struct SchedulerConfig {
@@ -208,6 +224,22 @@ pub struct SchedulerConfig {
 
     /// Cost for each newly created dirty page in stable memory.
     pub dirty_page_overhead: NumInstructions,
+
+    /// Decides whether the heartbeat or the global timer of a canister runs
+    /// first if both are due in the same round.
+    pub system_task_priority: SystemTaskPriority,
+
+    /// If enabled, the NNS registry, governance, and ledger canisters are
+    /// scheduled ahead of all other canisters with new executions, so that
+    /// their messages are executed in the first round in which they are
+    /// pending, even if the subnet is saturated by other canisters.
+    pub system_canister_priority_lane: FlagStatus,
+
+    /// Canisters whose global timer has been due for at least this long are
+    /// scheduled ahead of the other canisters with new executions, so that
+    /// timers don't fall behind by more than this delay and a round when the
+    /// subnet is saturated.
+    pub max_global_timer_delay: Duration,
 }
 
 impl SchedulerConfig {
@@ -231,6 +263,9 @@ impl SchedulerConfig {
             heap_delta_rate_limit: NumBytes::from(75 * 1024 * 1024),
             install_code_rate_limit: MAX_INSTRUCTIONS_PER_SLICE,
             dirty_page_overhead: DEFAULT_DIRTY_PAGE_OVERHEAD,
+            system_task_priority: SystemTaskPriority::GlobalTimerFirst,
+            system_canister_priority_lane: FlagStatus::Disabled,
+            max_global_timer_delay: MAX_GLOBAL_TIMER_DELAY,
         }
     }
 
@@ -264,6 +299,9 @@ impl SchedulerConfig {
             // rate-limiting for the system subnets.
             install_code_rate_limit: NumInstructions::from(1_000_000_000_000_000),
             dirty_page_overhead: SYSTEM_SUBNET_DIRTY_PAGE_OVERHEAD,
+            system_task_priority: SystemTaskPriority::GlobalTimerFirst,
+            system_canister_priority_lane: FlagStatus::Enabled,
+            max_global_timer_delay: MAX_GLOBAL_TIMER_DELAY,
         }
     }
 
@@ -287,6 +325,9 @@ impl SchedulerConfig {
             heap_delta_rate_limit: NumBytes::from(75 * 1024 * 1024),
             install_code_rate_limit: MAX_INSTRUCTIONS_PER_SLICE,
             dirty_page_overhead: DEFAULT_DIRTY_PAGE_OVERHEAD,
+            system_task_priority: SystemTaskPriority::GlobalTimerFirst,
+            system_canister_priority_lane: FlagStatus::Disabled,
+            max_global_timer_delay: MAX_GLOBAL_TIMER_DELAY,
         }
     }
 
@@ -341,6 +382,11 @@ pub struct CyclesAccountManagerConfig {
     /// How often to charge canisters for memory and compute allocations.
     pub duration_between_allocation_charges: Duration,
 
+    /// The duration of storage that is paid for by the cycles reserved when a
+    /// canister allocates memory on a fully saturated subnet, see
+    /// `CyclesAccountManager::storage_reservation_cycles`.
+    pub max_storage_reservation_period: Duration,
+
     /// Amount to charge for an ECDSA signature.
     pub ecdsa_signature_fee: Cycles,
 
@@ -374,6 +420,7 @@ impl CyclesAccountManagerConfig {
             // 4 SDR per GiB per year => 4e12 Cycles per year
             gib_storage_per_second_fee: Cycles::new(127_000),
             duration_between_allocation_charges: Duration::from_secs(10),
+            max_storage_reservation_period: Duration::from_secs(300_000_000),
             ecdsa_signature_fee: ECDSA_SIGNATURE_FEE,
             http_request_baseline_fee: Cycles::new(400_000_000),
             http_request_per_byte_fee: Cycles::new(100_000),
@@ -394,6 +441,7 @@ impl CyclesAccountManagerConfig {
             ingress_byte_reception_fee: Cycles::new(0),
             gib_storage_per_second_fee: Cycles::new(0),
             duration_between_allocation_charges: Duration::from_secs(10),
+            max_storage_reservation_period: Duration::from_secs(0),
             /// The ECDSA signature fee is the fee charged when creating a
             /// signature on this subnet. The request likely came from a
             /// different subnet which is not a system subnet. There is an
@@ -324,6 +324,7 @@ mod tests {
             NiDkgId, NiDkgTag, NiDkgTargetId, NiDkgTargetSubnet, NiDkgTranscript,
         },
         messages::{CallbackId, Request},
+        time::NO_DEADLINE,
     };
     use std::collections::BTreeMap;
     use std::{collections::BTreeSet, str::FromStr, sync::Arc};
@@ -546,6 +547,7 @@ mod tests {
                     payment: Cycles::zero(),
                     method_name: "".to_string(),
                     method_payload: vec![],
+                    deadline: NO_DEADLINE,
                 },
                 nodes_in_target_subnet: BTreeSet::new(),
                 target_id: TARGET_ID,
@@ -954,6 +954,7 @@ pub(crate) fn get_signing_requests<'a>(
                     code: RejectCode::CanisterReject,
                     message: format!("Invalid key_id in signature request: {:?}", context.key_id),
                 }),
+                deadline: context.request.deadline,
             };
             ecdsa_payload.signature_agreements.insert(
                 context.pseudo_random_id,
@@ -1010,6 +1011,7 @@ pub(crate) fn get_signing_requests<'a>(
                         code: RejectCode::CanisterReject,
                         message: "Signature request expired".to_string(),
                     }),
+                    deadline: context.request.deadline,
                 };
                 ecdsa_payload.signature_agreements.insert(
                     context.pseudo_random_id,
@@ -1093,6 +1095,7 @@ pub(crate) fn update_signature_agreements(
                 }
                 .encode(),
             ),
+            deadline: context.request.deadline,
         };
         completed.insert(*request_id, ecdsa::CompletedSignature::Unreported(response));
     }
@@ -1556,6 +1559,7 @@ fn make_reshare_dealings_response(
                             }
                             .encode(),
                         ),
+                        deadline: context.request.deadline,
                     });
                 }
             }
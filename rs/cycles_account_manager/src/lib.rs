@@ -60,6 +60,55 @@ impl std::fmt::Display for CyclesAccountManagerError {
     }
 }
 
+/// The saturation of a subnet resource, e.g. the subnet memory. Once the usage
+/// exceeds the threshold, allocations of the resource reserve cycles, see
+/// `CyclesAccountManager::storage_reservation_cycles()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceSaturation {
+    usage: u64,
+    threshold: u64,
+    capacity: u64,
+}
+
+impl ResourceSaturation {
+    /// Creates the saturation of a resource with the given usage. A threshold
+    /// above the capacity is capped at the capacity.
+    pub fn new(usage: u64, threshold: u64, capacity: u64) -> Self {
+        Self {
+            usage,
+            threshold: threshold.min(capacity),
+            capacity,
+        }
+    }
+
+    /// Returns the usage of the resource above the threshold.
+    pub fn usage_above_threshold(&self) -> u64 {
+        self.usage.saturating_sub(self.threshold)
+    }
+
+    /// Returns the saturation after allocating `amount` more of the resource.
+    pub fn add(&self, amount: u64) -> Self {
+        Self {
+            usage: self.usage.saturating_add(amount),
+            ..*self
+        }
+    }
+
+    /// Returns the part of an allocation of `amount` that reserves cycles.
+    /// Every allocated unit is weighted linearly by its position between the
+    /// threshold (weight 0) and the capacity (weight 1).
+    pub fn reservation_weighted_amount(&self, amount: u64) -> u64 {
+        if self.capacity == self.threshold {
+            return 0;
+        }
+        let before = self.usage_above_threshold() as u128;
+        let after = self.add(amount).usage_above_threshold() as u128;
+        let range = (self.capacity - self.threshold) as u128;
+        let weighted = (after * after - before * before) / (2 * range);
+        weighted.min(amount as u128) as u64
+    }
+}
+
 /// Handles any operation related to cycles accounting, such as charging (due to
 /// using system resources) or refunding unused cycles.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -500,6 +549,9 @@ impl CyclesAccountManager {
     /// stable memory (among other things). This will be revised in the future
     /// to take into account charging for dirty/read pages by the canister.
     ///
+    /// The cycles reserved for storage are used first, the rest is taken from
+    /// the main balance.
+    ///
     /// # Errors
     ///
     /// Returns a `CanisterOutOfCyclesError` if there's
@@ -513,8 +565,14 @@ impl CyclesAccountManager {
     ) -> Result<(), CanisterOutOfCyclesError> {
         let cycles_amount = self.memory_cost(bytes, duration, subnet_size);
 
+        let reserved_cycles = cycles_amount.min(system_state.reserved_balance());
+
         // Can charge all the way to the empty account (zero cycles)
-        self.consume_with_threshold(system_state, cycles_amount, Cycles::zero())
+        self.consume_with_threshold(system_state, cycles_amount - reserved_cycles, Cycles::zero())?;
+
+        let reserved_cycles = system_state.take_reserved_cycles(reserved_cycles);
+        self.observe_consumed_cycles(system_state, reserved_cycles);
+        Ok(())
     }
 
     /// The cost of using `bytes` worth of memory.
@@ -530,6 +588,26 @@ impl CyclesAccountManager {
         self.scale_cost(cycles, subnet_size)
     }
 
+    /// Returns the cycles to reserve for allocating `allocated_bytes` of memory
+    /// given the saturation of the subnet memory. The reserved cycles pay for
+    /// the storage of the allocated memory for up to
+    /// `max_storage_reservation_period`, depending on how saturated the subnet
+    /// memory is, see `ResourceSaturation::reservation_weighted_amount()`.
+    pub fn storage_reservation_cycles(
+        &self,
+        allocated_bytes: NumBytes,
+        subnet_memory_saturation: &ResourceSaturation,
+        subnet_size: usize,
+    ) -> Cycles {
+        let weighted_bytes =
+            subnet_memory_saturation.reservation_weighted_amount(allocated_bytes.get());
+        self.memory_cost(
+            NumBytes::new(weighted_bytes),
+            self.config.max_storage_reservation_period,
+            subnet_size,
+        )
+    }
+
     ////////////////////////////////////////////////////////////////////////////
     //
     // Request
@@ -570,11 +648,8 @@ impl CyclesAccountManager {
         // response) + the fee to send the request + the fee for the largest
         // possible response + the fee for executing the largest allowed
         // response when it eventually arrives.
-        let fee = self.scale_cost(
-            self.config.xnet_call_fee
-                + self.config.xnet_byte_transmission_fee * request.payload_size_bytes().get(),
-            subnet_size,
-        ) + prepayment_for_response_transmission
+        let fee = self.xnet_call_and_request_fee(request.payload_size_bytes(), subnet_size)
+            + prepayment_for_response_transmission
             + prepayment_for_response_execution;
         self.withdraw_with_threshold(
             canister_id,
@@ -590,6 +665,24 @@ impl CyclesAccountManager {
         )
     }
 
+    /// Returns the amount of cycles that a canister pays for an xnet call with
+    /// a request of the given size, including the prepayment for the largest
+    /// possible response and its execution, see `withdraw_request_cycles()`.
+    pub fn xnet_call_total_fee(&self, payload_size: NumBytes, subnet_size: usize) -> Cycles {
+        self.xnet_call_and_request_fee(payload_size, subnet_size)
+            + self.prepayment_for_response_transmission(subnet_size)
+            + self.prepayment_for_response_execution(subnet_size)
+    }
+
+    /// Returns the fee for performing an xnet call and for sending its request
+    /// of the given size.
+    fn xnet_call_and_request_fee(&self, payload_size: NumBytes, subnet_size: usize) -> Cycles {
+        self.scale_cost(
+            self.config.xnet_call_fee + self.config.xnet_byte_transmission_fee * payload_size.get(),
+            subnet_size,
+        )
+    }
+
     /// Returns the amount of cycles required for executing the longest-running
     /// response callback.
     pub fn prepayment_for_response_execution(&self, subnet_size: usize) -> Cycles {
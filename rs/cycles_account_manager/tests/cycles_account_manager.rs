@@ -1,7 +1,7 @@
 use ic_base_types::NumSeconds;
 use ic_config::subnet_config::SubnetConfigs;
 use ic_constants::SMALL_APP_SUBNET_MAX_SIZE;
-use ic_cycles_account_manager::IngressInductionCost;
+use ic_cycles_account_manager::{IngressInductionCost, ResourceSaturation};
 use ic_ic00_types::{CanisterIdRecord, Payload, IC_00};
 use ic_interfaces::execution_environment::CanisterOutOfCyclesError;
 use ic_logger::replica_logger::no_op_logger;
@@ -344,6 +344,83 @@ fn canister_charge_for_memory_until_zero_works() {
         .is_err());
 }
 
+#[test]
+fn canister_charge_for_memory_uses_reserved_cycles_first() {
+    let subnet_size = SMALL_APP_SUBNET_MAX_SIZE;
+    let mut system_state = SystemStateBuilder::new().build();
+    let cycles_account_manager = CyclesAccountManagerBuilder::new()
+        .with_subnet_type(SubnetType::Application)
+        .build();
+    let bytes = NumBytes::from(1 << 30);
+    let fee = cycles_account_manager.memory_cost(bytes, Duration::from_secs(1), subnet_size);
+    let half_fee = Cycles::new(fee.get() / 2);
+    system_state.reserve_cycles(fee + half_fee).unwrap();
+    let balance = system_state.balance();
+
+    // The first charge is paid entirely from the reserved balance.
+    cycles_account_manager
+        .charge_for_memory(&mut system_state, bytes, Duration::from_secs(1), subnet_size)
+        .unwrap();
+    assert_eq!(system_state.reserved_balance(), half_fee);
+    assert_eq!(system_state.balance(), balance);
+
+    // The second charge uses the rest of the reserved balance.
+    cycles_account_manager
+        .charge_for_memory(&mut system_state, bytes, Duration::from_secs(1), subnet_size)
+        .unwrap();
+    assert_eq!(system_state.reserved_balance(), Cycles::zero());
+    assert_eq!(system_state.balance(), balance - (fee - half_fee));
+    assert_eq!(
+        system_state
+            .canister_metrics
+            .consumed_cycles_since_replica_started,
+        NominalCycles::from_cycles(fee + fee)
+    );
+}
+
+#[test]
+fn storage_reservation_cycles_grow_with_subnet_memory_saturation() {
+    let cycles_account_manager = CyclesAccountManagerBuilder::new()
+        .with_subnet_type(SubnetType::Application)
+        .build();
+    let subnet_size = SMALL_APP_SUBNET_MAX_SIZE;
+    let gib = 1 << 30;
+    let reserve = |usage: u64, allocated: u64| {
+        cycles_account_manager.storage_reservation_cycles(
+            NumBytes::from(allocated),
+            &ResourceSaturation::new(usage, 100 * gib, 200 * gib),
+            subnet_size,
+        )
+    };
+    let full_reservation = |allocated: u64| {
+        cycles_account_manager.memory_cost(
+            NumBytes::from(allocated),
+            SubnetConfigs::default()
+                .own_subnet_config(SubnetType::Application)
+                .cycles_account_manager_config
+                .max_storage_reservation_period,
+            subnet_size,
+        )
+    };
+
+    // Nothing is reserved below the threshold.
+    assert_eq!(reserve(0, 100 * gib), Cycles::zero());
+    // Allocations between the threshold and the capacity reserve cycles
+    // proportionally to the saturation.
+    assert_eq!(reserve(100 * gib, 100 * gib), full_reservation(50 * gib));
+    assert_eq!(reserve(150 * gib, 10 * gib), full_reservation(11 * gib / 2));
+    assert!(reserve(150 * gib, gib) < reserve(190 * gib, gib));
+    // Nothing is reserved if the threshold is at the capacity.
+    assert_eq!(
+        cycles_account_manager.storage_reservation_cycles(
+            NumBytes::from(gib),
+            &ResourceSaturation::new(100 * gib, 200 * gib, 100 * gib),
+            subnet_size,
+        ),
+        Cycles::zero()
+    );
+}
+
 #[test]
 fn ingress_induction_cost_valid_subnet_message() {
     let subnet_id = subnet_test_id(0);
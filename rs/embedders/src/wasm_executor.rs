@@ -17,8 +17,8 @@ use crate::{
 };
 use ic_config::flag_status::FlagStatus;
 use ic_interfaces::execution_environment::{
-    HypervisorError, HypervisorResult, InstanceStats, OutOfInstructionsHandler,
-    SubnetAvailableMemory, SystemApi, WasmExecutionOutput,
+    HypervisorError, HypervisorResult, InstanceStats, InstructionProfile,
+    OutOfInstructionsHandler, SubnetAvailableMemory, SystemApi, WasmExecutionOutput,
 };
 use ic_logger::{warn, ReplicaLogger};
 use ic_metrics::MetricsRegistry;
@@ -472,6 +472,8 @@ pub fn wasm_execution_error(
                 accessed_pages: 0,
                 dirty_pages: 0,
             },
+            canister_log_records: vec![],
+            instruction_profile: InstructionProfile::default(),
         },
         None,
     )
@@ -634,6 +636,8 @@ pub fn process(
                         accessed_pages: 0,
                         dirty_pages: 0,
                     },
+                    canister_log_records: vec![],
+                    instruction_profile: InstructionProfile::default(),
                 },
                 None,
                 Err(system_api),
@@ -675,6 +679,11 @@ pub fn process(
         .store_data_mut()
         .system_api
         .take_execution_result(run_result.as_ref().err());
+    let canister_log_records = instance
+        .store_data_mut()
+        .system_api
+        .take_canister_log_records();
+    let instruction_profile = instance.instruction_profile();
 
     let wasm_heap_size_after = instance.heap_size();
     let wasm_heap_limit =
@@ -733,6 +742,8 @@ pub fn process(
             allocated_bytes,
             allocated_message_bytes,
             instance_stats,
+            canister_log_records,
+            instruction_profile,
         },
         wasm_state_changes,
         Ok(instance),
@@ -93,6 +93,25 @@
 //! blocks to optimize for performance. The maximal overflow in that case is
 //! bound by the length of the longest execution path consisting of
 //! non-reentrant basic blocks.
+//!
+//! If instruction profiling is enabled, every function of the module gets its
+//! own (exported) counter global which is incremented by the same static costs
+//! at the injection points of that function:
+//!
+//! ```wasm
+//! global.get 0
+//! i64.const 2
+//! i64.sub
+//! global.set 0
+//! global.get 7          # the profiling counter of this function
+//! i64.const 2
+//! i64.add
+//! global.set 7
+//! ```
+//!
+//! The counters are exported under the [`INSTRUCTION_PROFILE_EXPORT_PREFIX`]
+//! followed by the function index and name. They are not persisted, so they
+//! start from zero for every message execution.
 
 use super::{InstrumentationOutput, Segments};
 use ic_config::flag_status::FlagStatus;
@@ -154,6 +173,12 @@ const TABLE_STR: &str = "table";
 const CANISTER_COUNTER_INSTRUCTIONS_STR: &str = "canister counter_instructions";
 const CANISTER_START_STR: &str = "canister_start";
 
+/// The prefix of the exported globals that count the instructions executed by
+/// each function when instruction profiling is enabled. The prefix is followed
+/// by the index of the function in the original module, a space, and the name
+/// of the function.
+pub(crate) const INSTRUCTION_PROFILE_EXPORT_PREFIX: &str = "canister profile_instructions ";
+
 /// There is one byte for each OS page in the wasm heap.
 const BYTEMAP_SIZE_IN_WASM_PAGES: u64 =
     MAX_WASM_MEMORY_IN_BYTES / (PAGE_SIZE as u64) / (WASM_PAGE_SIZE as u64);
@@ -248,6 +273,7 @@ pub(super) fn instrument(
     module: Module<'_>,
     cost_to_compile_wasm_instruction: NumInstructions,
     write_barrier: FlagStatus,
+    instruction_profiling: FlagStatus,
 ) -> Result<InstrumentationOutput, WasmInstrumentationError> {
     let mut module = inject_helper_functions(module);
     module = export_table(module);
@@ -273,6 +299,13 @@ pub(super) fn instrument(
     let num_functions = (module.functions.len() + num_imported_functions) as u32;
     let num_globals = (module.globals.len() + num_imported_globals) as u32;
 
+    // The profiling counters are added right after the instructions counter.
+    let profile_counters_start_ix = match instruction_profiling {
+        FlagStatus::Enabled => Some(num_globals + 1),
+        FlagStatus::Disabled => None,
+    };
+    let num_profiled_functions = module.code_sections.len();
+
     let export_module_data = ExportModuleData {
         instructions_counter_ix: num_globals,
         decr_instruction_counter_fn: num_functions,
@@ -284,8 +317,13 @@ pub(super) fn instrument(
     }
 
     // inject instructions counter decrementation
-    for func_body in &mut module.code_sections {
-        inject_metering(&mut func_body.instructions, &export_module_data);
+    for (func_ix, func_body) in module.code_sections.iter_mut().enumerate() {
+        let profile_counter_ix = profile_counters_start_ix.map(|start| start + func_ix as u32);
+        inject_metering(
+            &mut func_body.instructions,
+            &export_module_data,
+            profile_counter_ix,
+        );
     }
 
     // Collect all the function types of the locally defined functions inside the
@@ -312,6 +350,19 @@ pub(super) fn instrument(
     let mut extra_data: Option<Vec<u8>> = None;
     module = export_additional_symbols(module, &export_module_data, &mut extra_data);
 
+    let mut profile_export_names: Vec<String> = Vec::new();
+    let mut profile_init_data: Vec<u8> = Vec::new();
+    if let Some(start_ix) = profile_counters_start_ix {
+        module = export_profile_counters(
+            module,
+            start_ix,
+            num_imported_functions,
+            num_profiled_functions,
+            &mut profile_export_names,
+            &mut profile_init_data,
+        );
+    }
+
     let exported_functions = module
         .exports
         .iter()
@@ -452,6 +503,61 @@ pub fn export_additional_symbols<'a>(
     module
 }
 
+// Adds a zero-initialized counter global for each of the first
+// `num_profiled_functions` locally defined functions and exports it under the
+// [`INSTRUCTION_PROFILE_EXPORT_PREFIX`]. The counters must be added after all
+// other globals so that their indices start at `start_ix`.
+fn export_profile_counters<'a>(
+    mut module: Module<'a>,
+    start_ix: u32,
+    num_imported_functions: usize,
+    num_profiled_functions: usize,
+    export_names: &'a mut Vec<String>,
+    init_data: &'a mut Vec<u8>,
+) -> Module<'a> {
+    let function_names: std::collections::BTreeMap<u32, &str> = module
+        .exports
+        .iter()
+        .filter(|export| matches!(export.kind, ExternalKind::Func))
+        .map(|export| (export.index, export.name))
+        .collect();
+
+    for local_ix in 0..num_profiled_functions {
+        let func_ix = (num_imported_functions + local_ix) as u32;
+        // Report the index the function had before the helper imports were
+        // injected, so that it matches the original module.
+        let original_ix = func_ix - InjectedImports::Count as u32;
+        let name = match function_names.get(&func_ix) {
+            Some(name) => name.to_string(),
+            None => format!("func_{}", original_ix),
+        };
+        export_names.push(format!(
+            "{}{} {}",
+            INSTRUCTION_PROFILE_EXPORT_PREFIX, original_ix, name
+        ));
+    }
+
+    use wasm_encoder::Encode;
+    wasm_encoder::ConstExpr::i64_const(0).encode(init_data);
+
+    for (local_ix, name) in export_names.iter().enumerate() {
+        module.globals.push(Global {
+            ty: GlobalType {
+                content_type: ValType::I64,
+                mutable: true,
+            },
+            init_expr: ConstExpr::new(init_data, 0),
+        });
+        module.exports.push(Export {
+            name: name.as_str(),
+            kind: ExternalKind::Global,
+            index: start_ix + local_ix as u32,
+        });
+    }
+
+    module
+}
+
 // Represents a hint about the context of each static cost injection point in
 // wasm.
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -514,7 +620,13 @@ impl InjectionPoint {
 // - we insert a function call before each dynamic cost instruction which
 //   performs an overflow check and then decrements the counter by the value at
 //   the top of the stack.
-fn inject_metering(code: &mut Vec<Operator>, export_data_module: &ExportModuleData) {
+// If `profile_counter_ix` is given, the static costs are also added to that
+// profiling counter of the function.
+fn inject_metering(
+    code: &mut Vec<Operator>,
+    export_data_module: &ExportModuleData,
+    profile_counter_ix: Option<u32>,
+) {
     let points = injections(code);
     let points = points.iter().filter(|point| match point.cost_detail {
         InjectionPointCostDetail::StaticCost {
@@ -544,6 +656,16 @@ fn inject_metering(code: &mut Vec<Operator>, export_data_module: &ExportModuleDa
                         global_index: export_data_module.instructions_counter_ix,
                     },
                 ]);
+                if let Some(global_index) = profile_counter_ix {
+                    if cost > 0 {
+                        elems.extend_from_slice(&[
+                            GlobalGet { global_index },
+                            I64Const { value: cost as i64 },
+                            I64Add,
+                            GlobalSet { global_index },
+                        ]);
+                    }
+                }
                 if scope == Scope::ReentrantBlockStart {
                     elems.extend_from_slice(&[
                         GlobalGet {
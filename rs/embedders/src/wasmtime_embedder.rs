@@ -15,7 +15,7 @@ use wasmtime::{unix::StoreExt, Engine, Memory, Module, Mutability, OptLevel, Sto
 pub use host_memory::WasmtimeMemoryCreator;
 use ic_config::{embedders::Config as EmbeddersConfig, flag_status::FlagStatus};
 use ic_interfaces::execution_environment::{
-    HypervisorError, HypervisorResult, InstanceStats, SystemApi, TrapCode,
+    HypervisorError, HypervisorResult, InstanceStats, InstructionProfile, SystemApi, TrapCode,
 };
 use ic_logger::{debug, error, fatal, ReplicaLogger};
 use ic_replicated_state::{
@@ -24,13 +24,18 @@ use ic_replicated_state::{
 use ic_sys::PAGE_SIZE;
 use ic_types::{
     methods::{FuncRef, WasmMethod},
-    CanisterId,
+    CanisterId, NumInstructions,
 };
 use ic_wasm_types::{BinaryEncodedWasm, WasmEngineError};
 use memory_tracker::{DirtyPageTracking, SigsegvMemoryTracker};
 use signal_stack::WasmtimeSignalStack;
 
-use crate::{serialized_module::SerializedModuleBytes, wasm_utils::validation::ensure_determinism};
+use crate::{
+    serialized_module::SerializedModuleBytes,
+    wasm_utils::{
+        new_instrumentation::INSTRUCTION_PROFILE_EXPORT_PREFIX, validation::ensure_determinism,
+    },
+};
 
 use super::InstanceRunResult;
 
@@ -228,6 +233,7 @@ impl WasmtimeEmbedder {
             canister_id,
             &store,
             self.config.feature_flags.rate_limiting_of_debug_prints,
+            self.config.feature_flags.best_effort_responses,
             self.config.stable_memory_dirty_page_limit,
         );
 
@@ -694,11 +700,13 @@ impl<S: SystemApi> WasmtimeInstance<S> {
         NumWasmPages::from(self.memory().map_or(0, |mem| mem.size(&self.store)) as usize)
     }
 
-    /// Returns a list of exported globals.
+    /// Returns a list of exported globals. The instruction profiling counters
+    /// are not included because they are not persisted.
     pub fn get_exported_globals(&mut self) -> Vec<Global> {
         let globals: Vec<_> = self
             .instance
             .exports(&mut self.store)
+            .filter(|e| !e.name().starts_with(INSTRUCTION_PROFILE_EXPORT_PREFIX))
             .filter_map(|e| e.into_global())
             .collect();
         globals
@@ -713,6 +721,34 @@ impl<S: SystemApi> WasmtimeInstance<S> {
             .collect()
     }
 
+    /// Returns the number of instructions executed by each function since the
+    /// instance was created. The profile is empty unless the module was
+    /// instrumented with instruction profiling enabled.
+    pub fn instruction_profile(&mut self) -> InstructionProfile {
+        let counters: Vec<_> = self
+            .instance
+            .exports(&mut self.store)
+            .filter_map(|e| {
+                let (_, name) = e
+                    .name()
+                    .strip_prefix(INSTRUCTION_PROFILE_EXPORT_PREFIX)?
+                    .split_once(' ')?;
+                let name = name.to_string();
+                Some((name, e.into_global()?))
+            })
+            .collect();
+        let functions = counters
+            .into_iter()
+            .filter_map(|(name, counter)| match counter.get(&mut self.store) {
+                Val::I64(instructions) if instructions > 0 => {
+                    Some((name, NumInstructions::from(instructions as u64)))
+                }
+                _ => None,
+            })
+            .collect();
+        InstructionProfile { functions }
+    }
+
     /// Return the heap address. If the Instance does not contain any memory,
     /// the pointer is null.
     ///
@@ -329,6 +329,7 @@ pub(crate) fn syscalls<S: SystemApi>(
     canister_id: CanisterId,
     store: &Store<StoreData<S>>,
     rate_limiting_of_debug_prints: FlagStatus,
+    best_effort_responses: FlagStatus,
     stable_memory_dirty_page_limit: NumPages,
 ) -> Linker<StoreData<S>> {
     fn with_system_api<S, T>(caller: &mut Caller<'_, StoreData<S>>, f: impl Fn(&mut S) -> T) -> T {
@@ -533,6 +534,15 @@ pub(crate) fn syscalls<S: SystemApi>(
         })
         .unwrap();
 
+    linker
+        .func_wrap("ic0", "msg_deadline", {
+            move |mut caller: Caller<'_, StoreData<S>>| {
+                with_system_api(&mut caller, |s| s.ic0_msg_deadline())
+                    .map_err(|e| process_err(&mut caller, e))
+            }
+        })
+        .unwrap();
+
     linker
         .func_wrap("ic0", "msg_reject", {
             let log = log.clone();
@@ -696,9 +706,15 @@ pub(crate) fn syscalls<S: SystemApi>(
                     caller.data().system_api.subnet_type(),
                     rate_limiting_of_debug_prints,
                 ) {
-                    // Debug print is a no-op on non-system subnets with rate limiting.
-                    (SubnetType::Application, FlagStatus::Enabled) => Ok(()),
-                    (SubnetType::VerifiedApplication, FlagStatus::Enabled) => Ok(()),
+                    // Debug print produces no output on non-system subnets with rate
+                    // limiting, but it still goes into the canister log.
+                    (SubnetType::Application, FlagStatus::Enabled)
+                    | (SubnetType::VerifiedApplication, FlagStatus::Enabled) => {
+                        with_memory_and_system_api(&mut caller, |system_api, memory| {
+                            system_api.save_debug_print(offset as u32, length as u32, memory);
+                            Ok(())
+                        })
+                    }
                     // If rate limiting is disabled or the subnet is a system subnet, then
                     // debug print produces output.
                     (_, FlagStatus::Disabled) | (SubnetType::System, FlagStatus::Enabled) => {
@@ -855,6 +871,25 @@ pub(crate) fn syscalls<S: SystemApi>(
         })
         .unwrap();
 
+    linker
+        .func_wrap("ic0", "call_with_best_effort_response", {
+            move |mut caller: Caller<'_, StoreData<S>>, timeout_seconds: i32| {
+                match best_effort_responses {
+                    FlagStatus::Enabled => with_system_api(&mut caller, |s| {
+                        s.ic0_call_with_best_effort_response(timeout_seconds as u32)
+                    }),
+                    // Streams only carry the deadlines of best-effort messages
+                    // starting with certification version 12.
+                    FlagStatus::Disabled => Err(HypervisorError::ContractViolation(
+                        "ic0.call_with_best_effort_response is not enabled on this subnet"
+                            .to_string(),
+                    )),
+                }
+                .map_err(|e| process_err(&mut caller, e))
+            }
+        })
+        .unwrap();
+
     linker
         .func_wrap("ic0", "call_cycles_add", {
             move |mut caller: Caller<'_, StoreData<S>>, amount: i64| {
@@ -898,6 +933,52 @@ pub(crate) fn syscalls<S: SystemApi>(
         })
         .unwrap();
 
+    linker
+        .func_wrap("ic0", "cost_call", {
+            move |mut caller: Caller<'_, StoreData<S>>,
+                  method_name_size: i64,
+                  payload_size: i64,
+                  dst: u32| {
+                with_memory_and_system_api(&mut caller, |system_api, memory| {
+                    system_api.ic0_cost_call(
+                        method_name_size as u64,
+                        payload_size as u64,
+                        dst,
+                        memory,
+                    )
+                })
+            }
+        })
+        .unwrap();
+
+    linker
+        .func_wrap("ic0", "cost_create_canister", {
+            move |mut caller: Caller<'_, StoreData<S>>, dst: u32| {
+                with_memory_and_system_api(&mut caller, |system_api, memory| {
+                    system_api.ic0_cost_create_canister(dst, memory)
+                })
+            }
+        })
+        .unwrap();
+
+    linker
+        .func_wrap("ic0", "cost_http_request", {
+            move |mut caller: Caller<'_, StoreData<S>>,
+                  request_size: i64,
+                  max_res_bytes: i64,
+                  dst: u32| {
+                with_memory_and_system_api(&mut caller, |system_api, memory| {
+                    system_api.ic0_cost_http_request(
+                        request_size as u64,
+                        max_res_bytes as u64,
+                        dst,
+                        memory,
+                    )
+                })
+            }
+        })
+        .unwrap();
+
     linker
         .func_wrap("ic0", "stable_size", {
             move |mut caller: Caller<'_, StoreData<S>>| {
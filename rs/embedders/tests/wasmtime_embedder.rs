@@ -14,7 +14,7 @@ mod test {
     use ic_interfaces::execution_environment::HypervisorError;
     use ic_registry_subnet_type::SubnetType;
     use ic_test_utilities::wasmtime_instance::DEFAULT_NUM_INSTRUCTIONS;
-    use ic_types::{methods::WasmClosure, PrincipalId};
+    use ic_types::{methods::WasmClosure, time::NO_DEADLINE, PrincipalId};
 
     use super::*;
 
@@ -284,6 +284,7 @@ mod test {
                 Cycles::zero(),
                 PrincipalId::new_user_test_id(0),
                 0.into(),
+                NO_DEADLINE,
             ))
             .build();
 
@@ -328,6 +329,7 @@ mod test {
                 Cycles::zero(),
                 PrincipalId::new_user_test_id(0),
                 0.into(),
+                NO_DEADLINE,
             ))
             .with_num_instructions((expected_cpu_complexity - 1).into())
             .with_subnet_type(subnet_type)
@@ -358,6 +360,7 @@ mod test {
                 Cycles::zero(),
                 PrincipalId::new_user_test_id(0),
                 0.into(),
+                NO_DEADLINE,
             ))
             .with_num_instructions((expected_cpu_complexity - 1).into())
             .with_subnet_type(subnet_type)
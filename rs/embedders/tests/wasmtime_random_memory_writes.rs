@@ -1,6 +1,7 @@
 use ic_config::embedders::Config;
 use ic_config::flag_status::FlagStatus;
 use ic_config::subnet_config::SchedulerConfig;
+use ic_cycles_account_manager::ResourceSaturation;
 use ic_embedders::wasm_utils::compile;
 use ic_embedders::WasmtimeEmbedder;
 use ic_interfaces::execution_environment::{ExecutionMode, SubnetAvailableMemory};
@@ -19,6 +20,7 @@ use ic_test_utilities::{
 use ic_test_utilities_logger::with_test_replica_logger;
 use ic_types::{
     methods::{FuncRef, WasmMethod},
+    time::NO_DEADLINE,
     ComputeAllocation, Cycles, NumBytes, NumInstructions, PrincipalId,
 };
 use ic_wasm_types::BinaryEncodedWasm;
@@ -65,6 +67,7 @@ fn test_api_for_update(
             Cycles::zero(),
             caller,
             call_context_test_id(13),
+            NO_DEADLINE,
         ),
         static_system_state,
         canister_current_memory_usage,
@@ -78,6 +81,7 @@ fn test_api_for_update(
             compute_allocation: ComputeAllocation::default(),
             subnet_type: SubnetType::Application,
             execution_mode: ExecutionMode::Replicated,
+            subnet_memory_saturation: ResourceSaturation::default(),
         },
         *MAX_SUBNET_AVAILABLE_MEMORY,
         Memory::default(),
@@ -12,10 +12,12 @@ use crate::{
 use ic_base_types::NumSeconds;
 use ic_config::flag_status::FlagStatus;
 use ic_cycles_account_manager::CyclesAccountManager;
-use ic_error_types::{ErrorCode, RejectCode, UserError};
+use ic_error_types::{ErrorCode, UserError};
 use ic_ic00_types::{
-    CanisterInstallMode, CanisterStatusResultV2, CanisterStatusType, InstallCodeArgs,
-    Method as Ic00Method,
+    BlockingCallContext, CanisterInstallMode, CanisterStatusResultV2, CanisterStatusType,
+    CanisterStoppingStatusResponse, FetchCanisterLogsResponse, InstallChunkedCodeArgs,
+    InstallCodeArgs, InstructionMetrics, LogVisibility, MemoryMetrics, Method as Ic00Method,
+    MethodQueryStats,
 };
 use ic_interfaces::execution_environment::{
     CanisterOutOfCyclesError, HypervisorError, IngressHistoryWriter, SubnetAvailableMemory,
@@ -25,8 +27,8 @@ use ic_logger::{error, fatal, info, ReplicaLogger};
 use ic_registry_provisional_whitelist::ProvisionalWhitelist;
 use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::{
-    CallOrigin, CanisterState, CanisterStatus, NetworkTopology, ReplicatedState, SchedulerState,
-    SystemState,
+    wasm_chunk_hash, CallContextManager, CallOrigin, CanisterState, CanisterStatus,
+    NetworkTopology, ReplicatedState, SchedulerState, SystemState, WasmChunkHash,
 };
 use ic_system_api::ExecutionParameters;
 use ic_types::messages::{MessageId, SignedIngressContent};
@@ -44,13 +46,21 @@ use num_traits::cast::ToPrimitive;
 use prometheus::IntCounter;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
 use std::{collections::BTreeSet, convert::TryFrom, str::FromStr, sync::Arc};
 
+/// How long the oldest call context that keeps a canister from stopping must
+/// have been open before the canister can be stopped forcefully.
+pub(crate) const STOP_CANISTER_FORCE_DEADLINE: Duration = Duration::from_secs(5 * 60);
+
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) struct InstallCodeResult {
     pub heap_delta: NumBytes,
     pub old_wasm_hash: Option<[u8; 32]>,
     pub new_wasm_hash: Option<[u8; 32]>,
+    /// The rejects of the call contexts that a reinstall deleted. They need to
+    /// be sent out to their callers.
+    pub call_context_rejects: Vec<Response>,
 }
 
 /// The result of executing a single slice of `install_code` message (i.e
@@ -329,6 +339,11 @@ impl CanisterManager {
             | Ok(Ic00Method::DeleteCanister) |
             Ok(Ic00Method::UpdateSettings)|
             Ok(Ic00Method::InstallCode) |
+            Ok(Ic00Method::InstallChunkedCode) |
+            Ok(Ic00Method::UploadChunk) |
+            Ok(Ic00Method::ClearChunkStore) |
+            Ok(Ic00Method::StoredChunks) |
+            Ok(Ic00Method::CanisterStoppingStatus) |
             Ok(Ic00Method::SetController) => {
                 match effective_canister_id {
                     Some(canister_id) => {
@@ -354,6 +369,30 @@ impl CanisterManager {
                 }
             },
 
+            // The logs of a canister with public log visibility can be fetched
+            // by anyone, otherwise only by the controllers of the canister.
+            Ok(Ic00Method::FetchCanisterLogs) => {
+                let canister_id = effective_canister_id.ok_or_else(|| UserError::new(
+                    ErrorCode::InvalidManagementPayload,
+                    format!("Failed to decode payload for ic00 method: {}", method_name),
+                ))?;
+                let canister = state.canister_state(&canister_id).ok_or_else(|| UserError::new(
+                    ErrorCode::CanisterNotFound,
+                    format!("Canister {} not found", canister_id),
+                ))?;
+                let is_controller = canister.controllers().contains(&sender.get());
+                match (canister.system_state.log_visibility, is_controller) {
+                    (LogVisibility::Public, _) | (LogVisibility::Controllers, true) => Ok(()),
+                    (LogVisibility::Controllers, false) => Err(UserError::new(
+                        ErrorCode::CanisterInvalidController,
+                        format!(
+                            "Only controllers of canister {} can call ic00 method {}",
+                            canister_id, method_name,
+                        ),
+                    )),
+                }
+            }
+
             Ok(Ic00Method::ProvisionalCreateCanisterWithCycles)
             | Ok(Ic00Method::BitcoinGetSuccessors)
             | Ok(Ic00Method::ProvisionalTopUpCanister) => {
@@ -450,6 +489,24 @@ impl CanisterManager {
         if let Some(freezing_threshold) = settings.freezing_threshold {
             canister.system_state.freeze_threshold = freezing_threshold;
         }
+        if let Some(log_visibility) = settings.log_visibility {
+            canister.system_state.log_visibility = log_visibility;
+        }
+        if let Some(wasm_memory_threshold) = settings.wasm_memory_threshold {
+            canister.system_state.wasm_memory_threshold = wasm_memory_threshold;
+            // The hook may run again for the new threshold.
+            canister.system_state.on_low_wasm_memory_hook_executed = false;
+        }
+        if let Some(reserved_cycles_limit) = settings.reserved_cycles_limit {
+            canister
+                .system_state
+                .set_reserved_balance_limit(Some(reserved_cycles_limit));
+        }
+        if let Some(wasm_memory_limit) = settings.wasm_memory_limit {
+            // A limit of zero removes the limit.
+            canister.system_state.wasm_memory_limit =
+                (wasm_memory_limit.get() > 0).then_some(wasm_memory_limit);
+        }
     }
 
     /// Tries to apply the requested settings on the canister identified by
@@ -475,6 +532,18 @@ impl CanisterManager {
             settings.memory_allocation(),
             &self.config,
         )?;
+        if let Some(reserved_cycles_limit) = settings.reserved_cycles_limit() {
+            let reserved_balance = canister.system_state.reserved_balance();
+            if reserved_cycles_limit < reserved_balance {
+                return Err(CanisterManagerError::InvalidSettings {
+                    message: format!(
+                        "Invalid settings: 'reserved_cycles_limit' {} is below the {} cycles \
+                        that are already reserved",
+                        reserved_cycles_limit, reserved_balance
+                    ),
+                });
+            }
+        }
 
         let validated_settings =
             ValidatedCanisterSettings::try_from((settings, self.config.max_controllers))?;
@@ -787,7 +856,8 @@ impl CanisterManager {
             validate_controller(canister, &sender)?
         }
 
-        let rejects = uninstall_canister(&self.log, canister, time);
+        let rejects =
+            uninstall_canister(&self.log, canister, time, CallContextRejectReason::Uninstall);
         crate::util::process_responses(
             rejects,
             state,
@@ -797,6 +867,127 @@ impl CanisterManager {
         Ok(())
     }
 
+    /// Uploads a chunk to the Wasm chunk store of the canister and returns the
+    /// hash of the chunk. Only the controllers of the canister can do this.
+    ///
+    /// The chunk counts towards the memory usage of the canister, so it must
+    /// fit into the memory limit of the canister and, unless the canister has
+    /// reserved enough memory, into the available memory of the subnet.
+    /// Uploading a chunk that is already in the store doesn't take any memory.
+    pub(crate) fn upload_chunk(
+        &self,
+        sender: PrincipalId,
+        canister: &mut CanisterState,
+        chunk: Vec<u8>,
+        max_canister_memory_size: NumBytes,
+        round_limits: &mut RoundLimits,
+    ) -> Result<WasmChunkHash, CanisterManagerError> {
+        validate_controller(canister, &sender)?;
+
+        let canister_id = canister.canister_id();
+        let new_memory = canister
+            .system_state
+            .wasm_chunk_store
+            .can_insert(&chunk)
+            .map_err(|err| CanisterManagerError::WasmChunkStoreError {
+                canister_id,
+                message: err.to_string(),
+            })?;
+
+        let old_usage = canister.memory_usage(self.config.own_subnet_type);
+        let new_usage = old_usage + new_memory;
+        let memory_limit = canister.memory_limit(max_canister_memory_size);
+        if new_usage > memory_limit {
+            return Err(CanisterManagerError::WasmChunkStoreOutOfMemory {
+                canister_id,
+                memory_needed: new_usage,
+                memory_limit,
+            });
+        }
+
+        // Only the memory beyond the memory allocation of the canister is
+        // taken from the subnet.
+        let allocation = canister.memory_allocation().bytes();
+        let old_mem = allocation.max(old_usage);
+        let new_mem = allocation.max(new_usage);
+        round_limits
+            .subnet_available_memory
+            .try_decrement(new_mem - old_mem, NumBytes::from(0))
+            .map_err(|_| CanisterManagerError::SubnetMemoryCapacityOverSubscribed {
+                requested: new_mem - old_mem,
+                available: NumBytes::from(
+                    round_limits.subnet_available_memory.get_total_memory().max(0) as u64,
+                ),
+            })?;
+
+        let hash = canister
+            .system_state
+            .wasm_chunk_store
+            .insert(chunk)
+            .expect("The chunk was validated before inserting it");
+        Ok(hash)
+    }
+
+    /// Removes all chunks from the Wasm chunk store of the canister. Only the
+    /// controllers of the canister can do this.
+    pub(crate) fn clear_chunk_store(
+        &self,
+        sender: PrincipalId,
+        canister: &mut CanisterState,
+        round_limits: &mut RoundLimits,
+    ) -> Result<(), CanisterManagerError> {
+        validate_controller(canister, &sender)?;
+
+        let allocation = canister.memory_allocation().bytes();
+        let old_mem = allocation.max(canister.memory_usage(self.config.own_subnet_type));
+        canister.system_state.wasm_chunk_store.clear();
+        let new_mem = allocation.max(canister.memory_usage(self.config.own_subnet_type));
+        round_limits
+            .subnet_available_memory
+            .increment(old_mem - new_mem, NumBytes::from(0));
+        Ok(())
+    }
+
+    /// Returns the hashes of the chunks in the Wasm chunk store of the
+    /// canister. Only the controllers of the canister can do this.
+    pub(crate) fn stored_chunks(
+        &self,
+        sender: PrincipalId,
+        canister: &CanisterState,
+    ) -> Result<Vec<WasmChunkHash>, CanisterManagerError> {
+        validate_controller(canister, &sender)?;
+
+        Ok(canister
+            .system_state
+            .wasm_chunk_store
+            .hashes()
+            .copied()
+            .collect())
+    }
+
+    /// Returns the records of the canister log. Only the controllers of the
+    /// canister can do this, unless the log visibility of the canister is
+    /// public.
+    pub(crate) fn fetch_canister_logs(
+        &self,
+        sender: PrincipalId,
+        canister: &CanisterState,
+    ) -> Result<FetchCanisterLogsResponse, CanisterManagerError> {
+        if canister.system_state.log_visibility == LogVisibility::Controllers {
+            validate_controller(canister, &sender)?;
+        }
+
+        Ok(FetchCanisterLogsResponse {
+            canister_log_records: canister
+                .system_state
+                .canister_log
+                .records()
+                .iter()
+                .cloned()
+                .collect(),
+        })
+    }
+
     /// Signals a canister to stop.
     ///
     /// If the canister is running, then the canister is marked as "stopping".
@@ -811,12 +1002,20 @@ impl CanisterManager {
     /// ready to be fully stopped, the scheduler will respond to this message.
     ///
     /// If the canister is already stopped, then this function is a no-op.
+    ///
+    /// If `force` is set, then the open call contexts of the canister are
+    /// rejected with `ErrorCode::CanisterCallContextDeleted`, so that the
+    /// canister doesn't wait for them to complete before stopping. This is
+    /// only allowed once the oldest of them has been open for at least
+    /// `STOP_CANISTER_FORCE_DEADLINE`.
     pub(crate) fn stop_canister(
         &self,
         canister_id: CanisterId,
         mut stop_context: StopCanisterContext,
+        force: bool,
         state: &mut ReplicatedState,
     ) -> StopCanisterResult {
+        let time = state.time();
         let mut canister = match state.take_canister_state(&canister_id) {
             None => {
                 return StopCanisterResult::Failure {
@@ -827,12 +1026,18 @@ impl CanisterManager {
             Some(canister) => canister,
         };
 
-        let result = match validate_controller(&canister, stop_context.sender()) {
+        let mut rejects = Vec::new();
+        let result = match validate_controller(&canister, stop_context.sender())
+            .and_then(|()| match force {
+                true => self.reject_blocking_call_contexts(&mut canister, time),
+                false => Ok(Vec::new()),
+            }) {
             Err(err) => StopCanisterResult::Failure {
                 error: err,
                 cycles_to_return: stop_context.take_cycles(),
             },
-            Ok(()) => {
+            Ok(force_rejects) => {
+                rejects = force_rejects;
                 match &mut canister.system_state.status {
                     CanisterStatus::Stopped => StopCanisterResult::AlreadyStopped {
                         cycles_to_return: stop_context.take_cycles(),
@@ -862,9 +1067,78 @@ impl CanisterManager {
             }
         };
         state.put_canister_state(canister);
+        crate::util::process_responses(
+            rejects,
+            state,
+            Arc::clone(&self.ingress_history_writer),
+            self.log.clone(),
+        );
         result
     }
 
+    /// Rejects the open call contexts of a canister that is being stopped
+    /// forcefully and returns the rejects that need to be sent out to their
+    /// callers.
+    ///
+    /// Fails if the oldest open call context is younger than
+    /// `STOP_CANISTER_FORCE_DEADLINE`. Call contexts that were created before
+    /// their creation time was recorded count as old enough.
+    fn reject_blocking_call_contexts(
+        &self,
+        canister: &mut CanisterState,
+        time: Time,
+    ) -> Result<Vec<Response>, CanisterManagerError> {
+        let blocking = match canister.system_state.call_context_manager() {
+            Some(call_context_manager) => blocking_call_contexts(call_context_manager, time),
+            None => return Ok(Vec::new()),
+        };
+        if blocking.is_empty() {
+            return Ok(Vec::new());
+        }
+        let oldest_age = blocking
+            .iter()
+            .map(|call_context| call_context.age_seconds().unwrap_or(u64::MAX))
+            .max()
+            .unwrap_or_default();
+        if oldest_age < STOP_CANISTER_FORCE_DEADLINE.as_secs() {
+            return Err(CanisterManagerError::StopCanisterForceTooEarly {
+                canister_id: canister.canister_id(),
+                oldest_age: Duration::from_secs(oldest_age),
+                deadline: STOP_CANISTER_FORCE_DEADLINE,
+            });
+        }
+        Ok(reject_call_contexts(
+            &self.log,
+            canister,
+            time,
+            CallContextRejectReason::ForcedStop,
+        ))
+    }
+
+    /// Reports whether the canister is stopping and, if so, which call
+    /// contexts keep it from stopping. Only the controllers of the canister
+    /// can do this.
+    pub(crate) fn canister_stopping_status(
+        &self,
+        sender: PrincipalId,
+        canister: &CanisterState,
+        time: Time,
+    ) -> Result<CanisterStoppingStatusResponse, CanisterManagerError> {
+        validate_controller(canister, &sender)?;
+
+        let blocking_call_contexts = match &canister.system_state.status {
+            CanisterStatus::Stopping {
+                call_context_manager,
+                ..
+            } => blocking_call_contexts(call_context_manager, time),
+            CanisterStatus::Running { .. } | CanisterStatus::Stopped => Vec::new(),
+        };
+        Ok(CanisterStoppingStatusResponse {
+            status: canister.status(),
+            blocking_call_contexts,
+        })
+    }
+
     /// Signals a canister to start.
     ///
     /// If the canister is stopped, then the canister is immediately
@@ -909,11 +1183,15 @@ impl CanisterManager {
     }
 
     /// Fetches the current status of the canister.
+    ///
+    /// The status of a stopping canister includes the call contexts that keep
+    /// it from stopping.
     pub(crate) fn get_canister_status(
         &self,
         sender: PrincipalId,
         canister: &mut CanisterState,
         subnet_size: usize,
+        time: Time,
     ) -> Result<CanisterStatusResultV2, CanisterManagerError> {
         // Skip the controller check if the canister itself is requesting its
         // own status, as the canister is considered in the same trust domain.
@@ -929,11 +1207,13 @@ impl CanisterManager {
             .collect::<Vec<PrincipalId>>();
 
         let canister_memory_usage = canister.memory_usage(self.config.own_subnet_type);
+        let memory_breakdown = canister.memory_breakdown();
         let compute_allocation = canister.scheduler_state.compute_allocation;
         let memory_allocation = canister.memory_allocation();
         let freeze_threshold = canister.system_state.freeze_threshold;
+        let executed_instructions = canister.scheduler_state.executed_instructions;
 
-        Ok(CanisterStatusResultV2::new(
+        let status = CanisterStatusResultV2::new(
             canister.status(),
             canister
                 .execution_state
@@ -954,7 +1234,51 @@ impl CanisterManager {
                     subnet_size,
                 )
                 .get(),
+        )
+        .with_memory_metrics(MemoryMetrics::new(
+            memory_breakdown.wasm_memory,
+            memory_breakdown.stable_memory,
+            memory_breakdown.globals,
+            memory_breakdown.wasm_binary,
+            memory_breakdown.message_memory,
         ))
+        .with_instruction_metrics(InstructionMetrics::new(
+            executed_instructions.update.get(),
+            executed_instructions.query.get(),
+            executed_instructions.system_task.get(),
+        ))
+        .with_query_stats(
+            canister
+                .scheduler_state
+                .query_stats
+                .iter()
+                .map(|(method_name, stats)| {
+                    MethodQueryStats::new(
+                        method_name.clone(),
+                        stats.num_calls,
+                        stats.num_instructions.get(),
+                        stats.egress_payload_size.get(),
+                    )
+                })
+                .collect(),
+        )
+        .with_reserved_cycles(canister.system_state.reserved_balance().get());
+        let status = match canister.system_state.wasm_memory_limit {
+            Some(wasm_memory_limit) => status.with_wasm_memory_limit(wasm_memory_limit.get()),
+            None => status,
+        };
+        let status = match canister.system_state.global_timer.to_nanos_since_unix_epoch() {
+            Some(deadline) => status.with_global_timer_deadline(deadline),
+            None => status,
+        };
+        Ok(match &canister.system_state.status {
+            CanisterStatus::Stopping {
+                call_context_manager,
+                ..
+            } => status
+                .with_blocking_call_contexts(blocking_call_contexts(call_context_manager, time)),
+            CanisterStatus::Running { .. } | CanisterStatus::Stopped => status,
+        })
     }
 
     /// Sets a new controller for a canister. Only the current controller of
@@ -971,7 +1295,17 @@ impl CanisterManager {
             .canister_state_mut(&canister_id)
             .ok_or(CanisterManagerError::CanisterNotFound(canister_id))?;
 
-        let settings = CanisterSettings::new(Some(new_controller), None, None, None, None);
+        let settings = CanisterSettings::new(
+            Some(new_controller),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
         self.update_settings(sender, settings, canister, round_limits)
     }
 
@@ -1311,6 +1645,20 @@ pub(crate) enum CanisterManagerError {
     CanisterNotHostedBySubnet {
         message: String,
     },
+    WasmChunkStoreError {
+        canister_id: CanisterId,
+        message: String,
+    },
+    WasmChunkStoreOutOfMemory {
+        canister_id: CanisterId,
+        memory_needed: NumBytes,
+        memory_limit: NumBytes,
+    },
+    StopCanisterForceTooEarly {
+        canister_id: CanisterId,
+        oldest_age: Duration,
+        deadline: Duration,
+    },
 }
 
 impl From<CanisterManagerError> for UserError {
@@ -1467,6 +1815,30 @@ impl From<CanisterManagerError> for UserError {
                     format!("Unsuccessful validation of specified ID: {}", message),
                 )
             }
+            WasmChunkStoreError { canister_id, message } => {
+                Self::new(
+                    ErrorCode::CanisterContractViolation,
+                    format!("Error from the Wasm chunk store of canister {}: {}", canister_id, message),
+                )
+            }
+            WasmChunkStoreOutOfMemory { canister_id, memory_needed, memory_limit } => {
+                Self::new(
+                    ErrorCode::CanisterOutOfMemory,
+                    format!(
+                        "Canister {} cannot store the chunk because it would need {} bytes of memory but its memory limit is {} bytes.",
+                        canister_id, memory_needed, memory_limit,
+                    ),
+                )
+            }
+            StopCanisterForceTooEarly { canister_id, oldest_age, deadline } => {
+                Self::new(
+                    ErrorCode::CanisterNotStopped,
+                    format!(
+                        "Canister {} cannot be stopped forcefully yet: its oldest open call context is {} seconds old, but forcing a stop requires it to be at least {} seconds old.",
+                        canister_id, oldest_age.as_secs(), deadline.as_secs(),
+                    ),
+                )
+            }
         }
     }
 }
@@ -1484,94 +1856,217 @@ impl From<CanisterManagerError> for RejectContext {
     }
 }
 
+/// Assembles the Wasm module of an `install_chunked_code` message from the
+/// Wasm chunk store of the store canister and verifies its hash. The sender
+/// must be a controller of the store canister.
+pub(crate) fn assemble_chunked_wasm_module(
+    sender: &PrincipalId,
+    args: &InstallChunkedCodeArgs,
+    state: &ReplicatedState,
+) -> Result<Vec<u8>, CanisterManagerError> {
+    let store_canister_id = args.get_store_canister();
+    let store_canister = state
+        .canister_state(&store_canister_id)
+        .ok_or(CanisterManagerError::CanisterNotFound(store_canister_id))?;
+    validate_controller(store_canister, sender)?;
+
+    let to_error = |message: String| CanisterManagerError::WasmChunkStoreError {
+        canister_id: store_canister_id,
+        message,
+    };
+    let hashes = args
+        .chunk_hashes_list
+        .iter()
+        .map(|chunk_hash| {
+            WasmChunkHash::try_from(chunk_hash.hash.as_slice()).map_err(|_| {
+                to_error(format!(
+                    "Chunk hash {} is not 32 bytes long",
+                    hex::encode(&chunk_hash.hash)
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let wasm_module = store_canister
+        .system_state
+        .wasm_chunk_store
+        .assemble(&hashes)
+        .map_err(|err| to_error(err.to_string()))?;
+
+    let wasm_module_hash = wasm_chunk_hash(&wasm_module);
+    if wasm_module_hash[..] != args.wasm_module_hash[..] {
+        return Err(to_error(format!(
+            "Hash {} of the assembled Wasm module doesn't match the expected hash {}",
+            hex::encode(wasm_module_hash),
+            hex::encode(&args.wasm_module_hash)
+        )));
+    }
+    Ok(wasm_module)
+}
+
 /// Uninstalls a canister.
 ///
 /// See https://sdk.dfinity.org/docs/interface-spec/index.html#ic-uninstall_code
 ///
-/// Returns a list of rejects that need to be sent out to their callers.
+/// Returns a list of rejects that need to be sent out to their callers, see
+/// `reject_call_contexts()`.
 #[doc(hidden)]
 pub fn uninstall_canister(
     log: &ReplicaLogger,
     canister: &mut CanisterState,
     time: Time,
+    reason: CallContextRejectReason,
 ) -> Vec<Response> {
-    // Drop the canister's execution state.
-    canister.execution_state = None;
+    // Drop the canister's execution state. Stripping the deltas frees the
+    // memory of the pages that are not referenced by other states right away.
+    if let Some(mut execution_state) = canister.execution_state.take() {
+        execution_state.wasm_memory.page_map.strip_all_deltas();
+        execution_state.stable_memory.page_map.strip_all_deltas();
+    }
 
     // Drop its certified data.
     canister.system_state.certified_data = Vec::new();
 
+    // Drop the chunks of its Wasm chunk store.
+    canister.system_state.wasm_chunk_store.clear();
+
+    // Drop the records of its canister log.
+    canister.system_state.canister_log.clear();
+
     // Deactivate global timer.
     canister.system_state.global_timer = CanisterTimer::Inactive;
     // Increment canister version.
     canister.system_state.canister_version += 1;
 
-    let mut rejects = Vec::new();
-    let canister_id = canister.canister_id();
-    if let Some(call_context_manager) = canister.system_state.call_context_manager_mut() {
-        // Mark all call contexts as deleted and prepare reject responses.
-        // Note that callbacks will be unregistered at a later point once they are
-        // received.
-        for call_context in call_context_manager.call_contexts_mut().values_mut() {
-            // Mark the call context as deleted.
-            call_context.mark_deleted();
-
-            if call_context.has_responded() {
-                // Call context has already been responded to. Nothing to do.
-                continue;
-            }
+    reject_call_contexts(log, canister, time, reason)
+}
 
-            // Generate a reject response.
-            match call_context.call_origin() {
-                CallOrigin::Ingress(user_id, message_id) => {
-                    rejects.push(Response::Ingress(IngressResponse {
-                        message_id: message_id.clone(),
-                        status: IngressStatus::Known {
-                            receiver: canister_id.get(),
-                            user_id: *user_id,
-                            time,
-                            state: IngressState::Failed(UserError::new(
-                                ErrorCode::CanisterRejectedMessage,
-                                "Canister has been uninstalled.",
-                            )),
-                        },
-                    }));
-                }
-                CallOrigin::CanisterUpdate(caller_canister_id, callback_id) => {
-                    rejects.push(Response::Canister(CanisterResponse {
-                        originator: *caller_canister_id,
-                        respondent: canister_id,
-                        originator_reply_callback: *callback_id,
-                        refund: call_context.available_cycles(),
-                        response_payload: Payload::Reject(RejectContext {
-                            code: RejectCode::CanisterReject,
-                            message: String::from("Canister has been uninstalled."),
-                        }),
-                    }));
-                }
-                CallOrigin::CanisterQuery(_, _) | CallOrigin::Query(_) => fatal!(
-                    log,
-                    "No callbacks with a query origin should be found when uninstalling"
-                ),
-                CallOrigin::SystemTask => {
-                    // Cannot respond to system tasks. Nothing to do.
-                }
-            }
+/// The reason why the call contexts of a canister are deleted and rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallContextRejectReason {
+    /// A controller uninstalled the code of the canister.
+    Uninstall,
+    /// A controller reinstalled the code of the canister.
+    Reinstall,
+    /// The canister was uninstalled because it ran out of cycles.
+    OutOfCycles,
+    /// A controller stopped the canister forcefully.
+    ForcedStop,
+}
 
-            // Mark the call context as responded to.
-            call_context.mark_responded();
+impl CallContextRejectReason {
+    fn message(&self) -> &'static str {
+        match self {
+            Self::Uninstall => "Canister has been uninstalled.",
+            Self::Reinstall => "Canister has been reinstalled.",
+            Self::OutOfCycles => "Canister has been uninstalled because it ran out of cycles.",
+            Self::ForcedStop => "Canister has been stopped forcefully.",
         }
     }
+}
 
+/// Marks all call contexts of the canister as deleted and rejects the ones
+/// that have not responded yet with `ErrorCode::CanisterCallContextDeleted`.
+/// The rejects refund the cycles that remain in the call contexts.
+///
+/// Responses to the outstanding callbacks of the deleted call contexts are
+/// not executed when they arrive.
+///
+/// Returns a list of rejects that need to be sent out to their callers.
+pub(crate) fn reject_call_contexts(
+    log: &ReplicaLogger,
+    canister: &mut CanisterState,
+    time: Time,
+    reason: CallContextRejectReason,
+) -> Vec<Response> {
+    let canister_id = canister.canister_id();
+    let call_context_manager = match canister.system_state.call_context_manager_mut() {
+        Some(call_context_manager) => call_context_manager,
+        None => return Vec::new(),
+    };
+    let error = UserError::new(ErrorCode::CanisterCallContextDeleted, reason.message());
+    let mut rejects = Vec::new();
+    for call_context in call_context_manager.delete_all_call_contexts() {
+        match call_context.call_origin() {
+            CallOrigin::Ingress(user_id, message_id) => {
+                rejects.push(Response::Ingress(IngressResponse {
+                    message_id: message_id.clone(),
+                    status: IngressStatus::Known {
+                        receiver: canister_id.get(),
+                        user_id: *user_id,
+                        time,
+                        state: IngressState::Failed(error.clone()),
+                    },
+                }));
+            }
+            CallOrigin::CanisterUpdate(caller_canister_id, callback_id, deadline) => {
+                rejects.push(Response::Canister(CanisterResponse {
+                    originator: *caller_canister_id,
+                    respondent: canister_id,
+                    originator_reply_callback: *callback_id,
+                    refund: call_context.available_cycles(),
+                    response_payload: Payload::Reject(error.clone().into()),
+                    deadline: *deadline,
+                }));
+            }
+            CallOrigin::CanisterQuery(_, _) | CallOrigin::Query(_) => fatal!(
+                log,
+                "No callbacks with a query origin should be found when rejecting call contexts"
+            ),
+            CallOrigin::SystemTask => {
+                // Cannot respond to system tasks. Nothing to do.
+            }
+        }
+    }
     rejects
 }
 
+/// Returns the call contexts that keep a canister from stopping, i.e. the
+/// ones that have not been deleted yet, in the order of their creation.
+pub(crate) fn blocking_call_contexts(
+    call_context_manager: &CallContextManager,
+    time: Time,
+) -> Vec<BlockingCallContext> {
+    call_context_manager
+        .call_contexts()
+        .iter()
+        .filter(|(_, call_context)| !call_context.is_deleted())
+        .map(|(call_context_id, call_context)| {
+            let caller = match call_context.call_origin() {
+                CallOrigin::Ingress(user_id, _) | CallOrigin::Query(user_id) => {
+                    Some(user_id.get())
+                }
+                CallOrigin::CanisterUpdate(canister_id, _, _)
+                | CallOrigin::CanisterQuery(canister_id, _) => Some(canister_id.get()),
+                CallOrigin::SystemTask => None,
+            };
+            let age_seconds = call_context.time().map(|created| {
+                Duration::from_nanos(
+                    time.as_nanos_since_unix_epoch()
+                        .saturating_sub(created.as_nanos_since_unix_epoch()),
+                )
+                .as_secs()
+            });
+            let outstanding_callees = call_context_manager
+                .callbacks()
+                .values()
+                .filter(|callback| callback.call_context_id == *call_context_id)
+                .filter_map(|callback| callback.respondent.map(|respondent| respondent.get()))
+                .collect();
+            BlockingCallContext::new(caller, age_seconds, outstanding_callees)
+        })
+        .collect()
+}
+
 struct ValidatedCanisterSettings {
     pub controller: Option<PrincipalId>,
     pub controllers: Option<Vec<PrincipalId>>,
     pub compute_allocation: Option<ComputeAllocation>,
     pub memory_allocation: Option<MemoryAllocation>,
     pub freezing_threshold: Option<NumSeconds>,
+    pub log_visibility: Option<LogVisibility>,
+    pub wasm_memory_threshold: Option<NumBytes>,
+    pub reserved_cycles_limit: Option<Cycles>,
+    pub wasm_memory_limit: Option<NumBytes>,
 }
 
 impl TryFrom<(CanisterSettings, usize)> for ValidatedCanisterSettings {
@@ -1607,6 +2102,10 @@ impl TryFrom<(CanisterSettings, usize)> for ValidatedCanisterSettings {
             compute_allocation: settings.compute_allocation(),
             memory_allocation: settings.memory_allocation(),
             freezing_threshold: settings.freezing_threshold(),
+            log_visibility: settings.log_visibility(),
+            wasm_memory_threshold: settings.wasm_memory_threshold(),
+            reserved_cycles_limit: settings.reserved_cycles_limit(),
+            wasm_memory_limit: settings.wasm_memory_limit(),
         })
     }
 }
@@ -1,8 +1,8 @@
 use crate::{
     as_num_instructions,
     canister_manager::{
-        uninstall_canister, CanisterManager, CanisterManagerError, CanisterMgrConfig,
-        InstallCodeContext, StopCanisterResult,
+        uninstall_canister, CallContextRejectReason, CanisterManager, CanisterManagerError,
+        CanisterMgrConfig, InstallCodeContext, StopCanisterResult,
     },
     canister_settings::CanisterSettings,
     execution::test_utilities::{
@@ -21,7 +21,7 @@ use ic_config::{
     execution_environment::Config, flag_status::FlagStatus, subnet_config::SchedulerConfig,
 };
 use ic_constants::SMALL_APP_SUBNET_MAX_SIZE;
-use ic_cycles_account_manager::CyclesAccountManager;
+use ic_cycles_account_manager::{CyclesAccountManager, ResourceSaturation};
 use ic_error_types::{ErrorCode, UserError};
 use ic_ic00_types::{
     CanisterIdRecord, CanisterInstallMode, CanisterSettingsArgs, CanisterStatusType,
@@ -60,6 +60,7 @@ use ic_types::{
     ingress::{IngressState, IngressStatus, WasmResult},
     messages::{CallbackId, StopCanisterContext},
     nominal_cycles::NominalCycles,
+    time::UNIX_EPOCH,
     CanisterId, CanisterTimer, ComputeAllocation, Cycles, MemoryAllocation, NumBytes,
     NumInstructions, QueryAllocation, SubnetId, UserId,
 };
@@ -100,6 +101,7 @@ lazy_static! {
         compute_allocation: ComputeAllocation::default(),
         subnet_type: SubnetType::Application,
         execution_mode: ExecutionMode::Replicated,
+        subnet_memory_saturation: ResourceSaturation::default(),
     };
 }
 
@@ -1558,7 +1560,7 @@ fn stop_a_running_canister() {
             cycles: Cycles::zero(),
         };
         assert_eq!(
-            canister_manager.stop_canister(canister_id, stop_context.clone(), &mut state),
+            canister_manager.stop_canister(canister_id, stop_context.clone(), false, &mut state),
             StopCanisterResult::RequestAccepted
         );
 
@@ -1603,7 +1605,7 @@ fn stop_a_stopped_canister() {
             message_id: message_test_id(0),
         };
         assert_eq!(
-            canister_manager.stop_canister(canister_id, stop_context, &mut state),
+            canister_manager.stop_canister(canister_id, stop_context, false, &mut state),
             StopCanisterResult::AlreadyStopped {
                 cycles_to_return: Cycles::zero()
             }
@@ -1638,7 +1640,7 @@ fn stop_a_stopped_canister_from_another_canister() {
             cycles: Cycles::from(cycles),
         };
         assert_eq!(
-            canister_manager.stop_canister(canister_id, stop_context, &mut state),
+            canister_manager.stop_canister(canister_id, stop_context, false, &mut state),
             StopCanisterResult::AlreadyStopped {
                 cycles_to_return: Cycles::from(cycles)
             }
@@ -1691,7 +1693,7 @@ fn stop_a_canister_with_incorrect_controller() {
         };
 
         assert_eq!(
-            canister_manager.stop_canister(canister_id, stop_context, &mut state),
+            canister_manager.stop_canister(canister_id, stop_context, false, &mut state),
             StopCanisterResult::Failure {
                 cycles_to_return: Cycles::zero(),
                 error: CanisterManagerError::CanisterInvalidController {
@@ -1716,6 +1718,7 @@ fn stop_a_non_existing_canister() {
                     sender: user_test_id(1),
                     message_id: message_test_id(0),
                 },
+                false,
                 &mut state
             ),
             StopCanisterResult::Failure {
@@ -1899,7 +1902,12 @@ fn get_canister_status_with_incorrect_controller() {
         let other_sender = user_test_id(1).get();
         let canister = state.canister_state_mut(&canister_id).unwrap();
         assert_eq!(
-            canister_manager.get_canister_status(other_sender, canister, SMALL_APP_SUBNET_MAX_SIZE),
+            canister_manager.get_canister_status(
+                other_sender,
+                canister,
+                SMALL_APP_SUBNET_MAX_SIZE,
+                UNIX_EPOCH
+            ),
             Err(CanisterManagerError::CanisterInvalidController {
                 canister_id,
                 controllers_expected: btreeset! {sender},
@@ -1935,7 +1943,7 @@ fn get_canister_status_of_running_canister() {
 
         let canister = state.canister_state_mut(&canister_id).unwrap();
         let status = canister_manager
-            .get_canister_status(sender, canister, SMALL_APP_SUBNET_MAX_SIZE)
+            .get_canister_status(sender, canister, SMALL_APP_SUBNET_MAX_SIZE, UNIX_EPOCH)
             .unwrap()
             .status();
         assert_eq!(status, CanisterStatusType::Running);
@@ -1968,7 +1976,7 @@ fn get_canister_status_of_self() {
 
         let canister = state.canister_state_mut(&canister_id).unwrap();
         let status = canister_manager
-            .get_canister_status(canister_id.get(), canister, SMALL_APP_SUBNET_MAX_SIZE)
+            .get_canister_status(canister_id.get(), canister, SMALL_APP_SUBNET_MAX_SIZE, UNIX_EPOCH)
             .unwrap()
             .status();
         assert_eq!(status, CanisterStatusType::Running);
@@ -1985,7 +1993,7 @@ fn get_canister_status_of_stopped_canister() {
 
         let canister = state.canister_state_mut(&canister_id).unwrap();
         let status = canister_manager
-            .get_canister_status(sender, canister, SMALL_APP_SUBNET_MAX_SIZE)
+            .get_canister_status(sender, canister, SMALL_APP_SUBNET_MAX_SIZE, UNIX_EPOCH)
             .unwrap()
             .status();
         assert_eq!(status, CanisterStatusType::Stopped);
@@ -2002,7 +2010,7 @@ fn get_canister_status_of_stopping_canister() {
 
         let canister = state.canister_state_mut(&canister_id).unwrap();
         let status = canister_manager
-            .get_canister_status(sender, canister, SMALL_APP_SUBNET_MAX_SIZE)
+            .get_canister_status(sender, canister, SMALL_APP_SUBNET_MAX_SIZE, UNIX_EPOCH)
             .unwrap()
             .status();
         assert_eq!(status, CanisterStatusType::Stopping);
@@ -2371,7 +2379,12 @@ fn can_get_canister_balance() {
 
         let canister = state.canister_state_mut(&canister_id).unwrap();
         assert_matches!(
-            canister_manager.get_canister_status( sender, canister, SMALL_APP_SUBNET_MAX_SIZE),
+            canister_manager.get_canister_status(
+                sender,
+                canister,
+                SMALL_APP_SUBNET_MAX_SIZE,
+                UNIX_EPOCH,
+            ),
             Ok(res) if res.cycles() == cycles.get()
         );
     });
@@ -2712,6 +2725,7 @@ fn uninstall_canister_doesnt_respond_to_responded_call_contexts() {
                 .with_call_context(CallContextBuilder::new().with_responded(true).build())
                 .build(),
             mock_time(),
+            CallContextRejectReason::Uninstall,
         ),
         Vec::new()
     );
@@ -2735,6 +2749,7 @@ fn uninstall_canister_responds_to_unresponded_call_contexts() {
                 )
                 .build(),
             mock_time(),
+            CallContextRejectReason::Uninstall,
         )[0],
         Response::Ingress(IngressResponse {
             message_id: message_test_id(456),
@@ -2743,7 +2758,7 @@ fn uninstall_canister_responds_to_unresponded_call_contexts() {
                 user_id: user_test_id(123),
                 time: mock_time(),
                 state: IngressState::Failed(UserError::new(
-                    ErrorCode::CanisterRejectedMessage,
+                    ErrorCode::CanisterCallContextDeleted,
                     "Canister has been uninstalled.",
                 )),
             }
@@ -3384,6 +3399,10 @@ fn lower_memory_allocation_than_usage_fails() {
             None,
             Some(MemoryAllocation::try_from(NumBytes::from(2)).unwrap()),
             None,
+            None,
+            None,
+            None,
+            None,
         );
 
         let canister = state.canister_state_mut(&canister_id).unwrap();
@@ -3412,6 +3431,10 @@ fn test_install_when_updating_memory_allocation_via_canister_settings() {
             None,
             Some(MemoryAllocation::try_from(NumBytes::from(2)).unwrap()),
             None,
+            None,
+            None,
+            None,
+            None,
         );
         let canister_id = canister_manager
             .create_canister(
@@ -3457,6 +3480,10 @@ fn test_install_when_updating_memory_allocation_via_canister_settings() {
             None,
             Some(MemoryAllocation::try_from(NumBytes::from(MEMORY_CAPACITY.get() / 2)).unwrap()),
             None,
+            None,
+            None,
+            None,
+            None,
         );
 
         let canister = state.canister_state_mut(&canister_id).unwrap();
@@ -3502,6 +3529,10 @@ fn test_upgrade_when_updating_memory_allocation_via_canister_settings() {
                 MemoryAllocation::try_from(NumBytes::from(WASM_PAGE_SIZE_IN_BYTES + 100)).unwrap(),
             ),
             None,
+            None,
+            None,
+            None,
+            None,
         );
         let wat = r#"
         (module
@@ -3580,6 +3611,10 @@ fn test_upgrade_when_updating_memory_allocation_via_canister_settings() {
                     .unwrap(),
             ),
             None,
+            None,
+            None,
+            None,
+            None,
         );
 
         let canister = state.canister_state_mut(&canister_id).unwrap();
@@ -3659,7 +3694,7 @@ fn test_install_when_setting_memory_allocation_to_zero() {
         let wasm = ic_test_utilities::universal_canister::UNIVERSAL_CANISTER_WASM.to_vec();
 
         let sender = canister_test_id(100).get();
-        let settings = CanisterSettings::new(None, None, None, None, None);
+        let settings = CanisterSettings::new(None, None, None, None, None, None, None, None, None);
         let canister_id = canister_manager
             .create_canister(
                 sender,
@@ -3681,6 +3716,10 @@ fn test_install_when_setting_memory_allocation_to_zero() {
             None,
             Some(MemoryAllocation::try_from(NumBytes::from(0)).unwrap()),
             None,
+            None,
+            None,
+            None,
+            None,
         );
 
         let canister = state.canister_state_mut(&canister_id).unwrap();
@@ -3732,6 +3771,10 @@ fn test_upgrade_when_setting_memory_allocation_to_zero() {
             None,
             Some(MemoryAllocation::try_from(NumBytes::from(MEMORY_CAPACITY.get() / 2)).unwrap()),
             None,
+            None,
+            None,
+            None,
+            None,
         );
         let canister_id = canister_manager
             .create_canister(
@@ -3772,6 +3815,10 @@ fn test_upgrade_when_setting_memory_allocation_to_zero() {
             None,
             Some(MemoryAllocation::try_from(NumBytes::from(0)).unwrap()),
             None,
+            None,
+            None,
+            None,
+            None,
         );
 
         let canister = state.canister_state_mut(&canister_id).unwrap();
@@ -0,0 +1,131 @@
+use ic_config::execution_environment::ChaosConfig;
+use ic_crypto_sha::Sha256;
+use std::convert::TryInto;
+
+const PARTS_PER_MILLION: u64 = 1_000_000;
+
+/// The faults that the chaos mode injects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChaosFault {
+    /// Messages from different senders are delivered in a different order.
+    Reorder,
+    /// A response is held back to a later round.
+    DelayResponse,
+    /// The execution of a message traps.
+    Trap,
+}
+
+impl ChaosFault {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ChaosFault::Reorder => "reorder",
+            ChaosFault::DelayResponse => "delay_response",
+            ChaosFault::Trap => "trap",
+        }
+    }
+}
+
+/// Decides which faults to inject in chaos mode.
+///
+/// Every decision is a pure function of the seed, the fault, and a key that
+/// identifies the affected message or execution. Replaying the same inputs
+/// with the same seed therefore injects exactly the same faults.
+#[derive(Clone, Debug)]
+pub struct Chaos {
+    config: ChaosConfig,
+}
+
+impl Chaos {
+    pub fn new(config: ChaosConfig) -> Self {
+        Self { config }
+    }
+
+    /// Returns true if the given fault should be injected for the given key.
+    pub fn inject(&self, fault: ChaosFault, key: &[u8]) -> bool {
+        let probability_ppm = match fault {
+            ChaosFault::Reorder => self.config.reorder_probability_ppm,
+            ChaosFault::DelayResponse => self.config.delay_response_probability_ppm,
+            ChaosFault::Trap => self.config.trap_probability_ppm,
+        };
+        probability_ppm > 0
+            && self.draw(fault.as_str(), key) % PARTS_PER_MILLION < probability_ppm as u64
+    }
+
+    /// Shuffles the given items if a `ChaosFault::Reorder` should be injected
+    /// for the given key. The callers must only pass items whose order is not
+    /// guaranteed to be preserved.
+    pub fn maybe_reorder<T>(&self, items: &mut [T], key: &[u8]) {
+        if !self.inject(ChaosFault::Reorder, key) {
+            return;
+        }
+        // Fisher-Yates shuffle with deterministic draws.
+        for i in (1..items.len()).rev() {
+            let mut swap_key = key.to_vec();
+            swap_key.extend_from_slice(&(i as u64).to_le_bytes());
+            let j = self.draw("reorder_swap", &swap_key) % (i as u64 + 1);
+            items.swap(i, j as usize);
+        }
+    }
+
+    fn draw(&self, purpose: &str, key: &[u8]) -> u64 {
+        let mut hasher = Sha256::new();
+        hasher.write(&self.config.seed.to_le_bytes());
+        hasher.write(purpose.as_bytes());
+        hasher.write(key);
+        let digest = hasher.finish();
+        u64::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chaos(seed: u64, probability_ppm: u32) -> Chaos {
+        Chaos::new(ChaosConfig {
+            seed,
+            reorder_probability_ppm: probability_ppm,
+            delay_response_probability_ppm: probability_ppm,
+            trap_probability_ppm: probability_ppm,
+        })
+    }
+
+    fn decisions(chaos: &Chaos, fault: ChaosFault) -> Vec<bool> {
+        (0..100_u64)
+            .map(|key| chaos.inject(fault, &key.to_le_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn probability_bounds_are_respected() {
+        for fault in [ChaosFault::Reorder, ChaosFault::DelayResponse, ChaosFault::Trap] {
+            assert!(decisions(&chaos(1, 0), fault).iter().all(|inject| !inject));
+            assert!(decisions(&chaos(1, 1_000_000), fault)
+                .iter()
+                .all(|inject| *inject));
+        }
+    }
+
+    #[test]
+    fn decisions_are_replayed_with_the_same_seed() {
+        let first = decisions(&chaos(7, 500_000), ChaosFault::Trap);
+        let second = decisions(&chaos(7, 500_000), ChaosFault::Trap);
+        assert_eq!(first, second);
+        assert!(first.iter().any(|inject| *inject));
+        assert!(first.iter().any(|inject| !inject));
+        assert_ne!(first, decisions(&chaos(8, 500_000), ChaosFault::Trap));
+    }
+
+    #[test]
+    fn reorder_is_a_deterministic_permutation() {
+        let chaos = chaos(3, 1_000_000);
+        let mut first: Vec<u32> = (0..20).collect();
+        chaos.maybe_reorder(&mut first, b"key");
+        let mut second: Vec<u32> = (0..20).collect();
+        chaos.maybe_reorder(&mut second, b"key");
+        assert_eq!(first, second);
+        assert_ne!(first, (0..20).collect::<Vec<_>>());
+        first.sort_unstable();
+        assert_eq!(first, (0..20).collect::<Vec<_>>());
+    }
+}
@@ -2,6 +2,7 @@
 // TODO(RUN-60): Move helper functions here.
 
 use ic_base_types::{CanisterId, NumBytes, SubnetId};
+use ic_cycles_account_manager::{CyclesAccountManager, ResourceSaturation};
 use ic_embedders::wasm_executor::{CanisterStateChanges, SliceExecutionOutput};
 use ic_error_types::{ErrorCode, RejectCode, UserError};
 use ic_ic00_types::CanisterStatusType;
@@ -12,12 +13,13 @@ use ic_interfaces::messages::RequestOrIngress;
 use ic_logger::{error, fatal, warn, ReplicaLogger};
 use ic_replicated_state::{
     CallContext, CallContextAction, CallOrigin, CanisterState, ExecutionState, NetworkTopology,
-    SystemState,
+    ReservationError, SystemState,
 };
 use ic_system_api::sandbox_safe_system_state::SystemStateChanges;
 use ic_types::ingress::{IngressState, IngressStatus, WasmResult};
 use ic_types::messages::{CallContextId, CallbackId, MessageId, Payload, RejectContext, Response};
 use ic_types::methods::{Callback, WasmMethod};
+use ic_types::time::CoarseTime;
 use ic_types::{Cycles, MemoryAllocation, NumInstructions, Time, UserId};
 
 use crate::execution_environment::ExecutionResponse;
@@ -53,8 +55,8 @@ pub(crate) fn action_to_response(
             time,
             log,
         ),
-        CallOrigin::CanisterUpdate(caller_canister_id, callback_id) => {
-            action_to_request_response(canister, action, caller_canister_id, callback_id)
+        CallOrigin::CanisterUpdate(caller_canister_id, callback_id, deadline) => {
+            action_to_request_response(canister, action, caller_canister_id, callback_id, deadline)
         }
         CallOrigin::CanisterQuery(_, _) | CallOrigin::Query(_) => fatal!(
             log,
@@ -75,6 +77,7 @@ pub(crate) fn action_to_request_response(
     action: CallContextAction,
     originator: CanisterId,
     reply_callback_id: CallbackId,
+    deadline: CoarseTime,
 ) -> ExecutionResponse {
     let response_payload_and_refund = match action {
         CallContextAction::NotYetResponded | CallContextAction::AlreadyResponded => None,
@@ -115,6 +118,7 @@ pub(crate) fn action_to_request_response(
             originator_reply_callback: reply_callback_id,
             refund,
             response_payload,
+            deadline,
         })
     } else {
         ExecutionResponse::Empty
@@ -224,13 +228,14 @@ pub(crate) fn wasm_result_to_query_response(
         CallOrigin::Ingress(user_id, message_id) => {
             wasm_result_to_ingress_response(result, canister, user_id, message_id, time)
         }
-        CallOrigin::CanisterUpdate(caller_canister_id, callback_id) => {
+        CallOrigin::CanisterUpdate(caller_canister_id, callback_id, deadline) => {
             let response = Response {
                 originator: caller_canister_id,
                 respondent: canister.canister_id(),
                 originator_reply_callback: callback_id,
                 refund: Cycles::zero(),
                 response_payload: Payload::from(result),
+                deadline,
             };
             ExecutionResponse::Request(response)
         }
@@ -381,8 +386,10 @@ pub fn update_round_limits(round_limits: &mut RoundLimits, slice: &SliceExecutio
 }
 
 /// Tries to apply the given canister changes to the given system state and
-/// subnet available memory. In case of an error, the partially applied changes
-/// are not undone.
+/// subnet available memory. Memory allocated on a subnet whose memory usage is
+/// above the threshold reserves cycles from the canister balance. In case of
+/// an error, the partially applied changes are not undone.
+#[allow(clippy::too_many_arguments)]
 fn try_apply_canister_state_changes(
     system_state_changes: SystemStateChanges,
     output: &WasmExecutionOutput,
@@ -391,16 +398,46 @@ fn try_apply_canister_state_changes(
     time: Time,
     network_topology: &NetworkTopology,
     subnet_id: SubnetId,
+    subnet_memory_saturation: &ResourceSaturation,
+    subnet_size: usize,
+    cycles_account_manager: &CyclesAccountManager,
     log: &ReplicaLogger,
 ) -> HypervisorResult<()> {
-    match &system_state.memory_allocation {
-        MemoryAllocation::BestEffort => subnet_available_memory
-            .try_decrement(output.allocated_bytes, output.allocated_message_bytes)
-            .map_err(|_| HypervisorError::OutOfMemory)?,
-        MemoryAllocation::Reserved(_) => (),
-    }
+    let reservation_cycles = match &system_state.memory_allocation {
+        MemoryAllocation::BestEffort => {
+            subnet_available_memory
+                .try_decrement(output.allocated_bytes, output.allocated_message_bytes)
+                .map_err(|err| HypervisorError::OutOfMemory(err.into()))?;
+            cycles_account_manager.storage_reservation_cycles(
+                output.allocated_bytes,
+                subnet_memory_saturation,
+                subnet_size,
+            )
+        }
+        MemoryAllocation::Reserved(_) => Cycles::zero(),
+    };
 
-    system_state_changes.apply_changes(time, system_state, network_topology, subnet_id, log)
+    system_state_changes.apply_changes(time, system_state, network_topology, subnet_id, log)?;
+
+    system_state
+        .reserve_cycles(reservation_cycles)
+        .map_err(|err| match err {
+            ReservationError::InsufficientCycles {
+                requested,
+                available,
+            } => HypervisorError::InsufficientCyclesInMemoryGrow {
+                bytes: output.allocated_bytes,
+                available,
+                requested,
+            },
+            ReservationError::ReservedLimitExceed { requested, limit } => {
+                HypervisorError::ReservedCyclesLimitExceededInMemoryGrow {
+                    bytes: output.allocated_bytes,
+                    requested,
+                    limit,
+                }
+            }
+        })
 }
 
 /// Applies canister state change after Wasm execution if possible.
@@ -412,6 +449,10 @@ fn try_apply_canister_state_changes(
 /// - A mismatch between checks dones by the Wasm executor and checks done when
 ///   applying the changes due to a bug.
 /// - An escape from the Wasm sandbox that corrupts the execution output.
+///
+/// In both cases, it appends the debug prints and trap messages of the
+/// execution to the canister log.
+#[allow(clippy::too_many_arguments)]
 pub fn apply_canister_state_changes(
     canister_state_changes: Option<CanisterStateChanges>,
     execution_state: &mut ExecutionState,
@@ -421,6 +462,9 @@ pub fn apply_canister_state_changes(
     time: Time,
     network_topology: &NetworkTopology,
     subnet_id: SubnetId,
+    subnet_memory_saturation: &ResourceSaturation,
+    subnet_size: usize,
+    cycles_account_manager: &CyclesAccountManager,
     log: &ReplicaLogger,
 ) {
     if let Some(CanisterStateChanges {
@@ -442,6 +486,9 @@ pub fn apply_canister_state_changes(
             time,
             network_topology,
             subnet_id,
+            subnet_memory_saturation,
+            subnet_size,
+            cycles_account_manager,
             log,
         ) {
             Ok(()) => {
@@ -458,9 +505,14 @@ pub fn apply_canister_state_changes(
                             "[EXC-BUG]: Failed to apply state changes due to a bug: {}", err
                         )
                     }
-                    HypervisorError::OutOfMemory => {
+                    HypervisorError::OutOfMemory(_) => {
                         warn!(log, "Failed to apply state changes due to DTS: {}", err)
                     }
+                    HypervisorError::ReservedCyclesLimitExceededInMemoryGrow { .. }
+                    | HypervisorError::InsufficientCyclesInMemoryGrow { .. } => {
+                        // The canister could not pay for the memory it
+                        // allocated. The error is reported to the caller.
+                    }
                     _ => {
                         // TODO(RUN-299): Increment a critical error counter here.
                         error!(
@@ -475,6 +527,14 @@ pub fn apply_canister_state_changes(
             }
         }
     }
+    // The debug prints and trap messages go into the canister log even if the
+    // execution failed.
+    for content in output.canister_log_records.drain(..) {
+        system_state
+            .canister_log
+            .add_record(time.as_nanos_since_unix_epoch(), content);
+    }
+    execution_state.last_instruction_profile = std::mem::take(&mut output.instruction_profile);
 }
 
 pub(crate) fn finish_call_with_error(
@@ -492,6 +552,7 @@ pub(crate) fn finish_call_with_error(
                 originator_reply_callback: request.sender_reply_callback,
                 refund: request.payment,
                 response_payload: Payload::from(Err(user_error)),
+                deadline: request.deadline,
             };
             ExecutionResponse::Request(response)
         }
@@ -26,7 +26,8 @@ use ic_types::{
 
 use crate::{
     canister_manager::{
-        CanisterManagerError, CanisterMgrConfig, DtsInstallCodeResult, InstallCodeResult,
+        reject_call_contexts, CallContextRejectReason, CanisterManagerError, CanisterMgrConfig,
+        DtsInstallCodeResult, InstallCodeResult,
     },
     execution_environment::RoundContext,
     CompilationCostHandling, RoundLimits,
@@ -244,6 +245,18 @@ impl InstallCodeHelper {
 
         round_limits.subnet_available_memory = subnet_available_memory;
 
+        // A reinstall behaves like an uninstall followed by an install, so the
+        // call contexts of the old code are rejected.
+        let call_context_rejects = match original.mode {
+            CanisterInstallMode::Reinstall => reject_call_contexts(
+                round.log,
+                &mut self.canister,
+                round.time,
+                CallContextRejectReason::Reinstall,
+            ),
+            CanisterInstallMode::Install | CanisterInstallMode::Upgrade => Vec::new(),
+        };
+
         round.cycles_account_manager.refund_unused_execution_cycles(
             &mut self.canister.system_state,
             instructions_left,
@@ -274,6 +287,7 @@ impl InstallCodeHelper {
                 heap_delta: self.total_heap_delta,
                 old_wasm_hash,
                 new_wasm_hash,
+                call_context_rejects,
             }),
         }
     }
@@ -486,7 +500,7 @@ impl InstallCodeHelper {
                             "[EXC-BUG]: Failed to apply state changes due to a bug: {}", err
                         )
                     }
-                    HypervisorError::OutOfMemory => {
+                    HypervisorError::OutOfMemory(_) => {
                         warn!(
                             round.log,
                             "Failed to apply state changes due to DTS: {}", err
@@ -516,6 +530,15 @@ impl InstallCodeHelper {
             self.total_heap_delta +=
                 NumBytes::from((output.instance_stats.dirty_pages * PAGE_SIZE) as u64);
         }
+        for content in output.canister_log_records {
+            self.canister
+                .system_state
+                .canister_log
+                .add_record(original.time.as_nanos_since_unix_epoch(), content);
+        }
+        if let Some(execution_state) = self.canister.execution_state.as_mut() {
+            execution_state.last_instruction_profile = output.instruction_profile;
+        }
         Ok(())
     }
 
@@ -5,6 +5,7 @@ use std::sync::Arc;
 
 use ic_base_types::CanisterId;
 use ic_constants::LOG_CANISTER_OPERATION_CYCLES_THRESHOLD;
+use ic_cycles_account_manager::ResourceSaturation;
 use prometheus::IntCounter;
 
 use ic_embedders::wasm_executor::{
@@ -353,6 +354,9 @@ impl ResponseHelper {
             round.time,
             round.network_topology,
             round.hypervisor.subnet_id(),
+            &original.subnet_memory_saturation,
+            original.subnet_size,
+            round.cycles_account_manager,
             round.log,
         );
         match output.wasm_result {
@@ -397,6 +401,9 @@ impl ResponseHelper {
             round.time,
             round.network_topology,
             round.hypervisor.subnet_id(),
+            &original.subnet_memory_saturation,
+            original.subnet_size,
+            round.cycles_account_manager,
             round.log,
         );
 
@@ -534,6 +541,7 @@ struct OriginalContext {
     message_instruction_limit: NumInstructions,
     message: Arc<Response>,
     subnet_size: usize,
+    subnet_memory_saturation: ResourceSaturation,
     freezing_threshold: Cycles,
     canister_id: CanisterId,
 }
@@ -757,6 +765,7 @@ pub fn execute_response(
         message_instruction_limit: execution_parameters.instruction_limits.message(),
         message: Arc::clone(&response),
         subnet_size,
+        subnet_memory_saturation: execution_parameters.subnet_memory_saturation,
         freezing_threshold,
         canister_id: clean_canister.canister_id(),
     };
@@ -777,9 +786,9 @@ pub fn execute_response(
     };
 
     let func_ref = match original.call_origin {
-        CallOrigin::Ingress(_, _) | CallOrigin::CanisterUpdate(_, _) | CallOrigin::SystemTask => {
-            FuncRef::UpdateClosure(closure)
-        }
+        CallOrigin::Ingress(_, _)
+        | CallOrigin::CanisterUpdate(_, _, _)
+        | CallOrigin::SystemTask => FuncRef::UpdateClosure(closure),
         CallOrigin::CanisterQuery(_, _) | CallOrigin::Query(_) => FuncRef::QueryClosure(closure),
     };
 
@@ -789,6 +798,7 @@ pub fn execute_response(
             payload.to_vec(),
             helper.refund_for_sent_cycles(),
             call_context_id,
+            response.deadline,
             call_context.has_responded(),
             execution_parameters.execution_mode.clone(),
         ),
@@ -797,6 +807,7 @@ pub fn execute_response(
             context.clone(),
             helper.refund_for_sent_cycles(),
             call_context_id,
+            response.deadline,
             call_context.has_responded(),
             execution_parameters.execution_mode.clone(),
         ),
@@ -845,9 +856,9 @@ fn execute_response_cleanup(
         .instruction_limits
         .update(instructions_left);
     let func_ref = match original.call_origin {
-        CallOrigin::Ingress(_, _) | CallOrigin::CanisterUpdate(_, _) | CallOrigin::SystemTask => {
-            FuncRef::UpdateClosure(cleanup_closure)
-        }
+        CallOrigin::Ingress(_, _)
+        | CallOrigin::CanisterUpdate(_, _, _)
+        | CallOrigin::SystemTask => FuncRef::UpdateClosure(cleanup_closure),
         CallOrigin::CanisterQuery(_, _) | CallOrigin::Query(_) => {
             FuncRef::QueryClosure(cleanup_closure)
         }
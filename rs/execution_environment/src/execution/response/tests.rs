@@ -15,7 +15,7 @@ use ic_types::{
     CanisterId, Cycles, Time,
 };
 use ic_types::{messages::MAX_INTER_CANISTER_PAYLOAD_IN_BYTES, NumInstructions};
-use ic_universal_canister::{call_args, wasm};
+use ic_universal_canister::{call_args, wasm, UNIVERSAL_CANISTER_WASM};
 
 #[test]
 fn execute_response_with_incorrect_canister_status() {
@@ -1730,6 +1730,43 @@ fn dts_uninstall_with_aborted_response() {
 
     // Execute response with deleted call context.
     let err = check_ingress_status(test.ingress_status(&ingress)).unwrap_err();
-    assert_eq!(err.code(), ErrorCode::CanisterRejectedMessage);
+    assert_eq!(err.code(), ErrorCode::CanisterCallContextDeleted);
     assert_eq!(err.description(), "Canister has been uninstalled.");
 }
+
+#[test]
+fn reinstall_rejects_call_contexts_with_outstanding_calls() {
+    let mut test = ExecutionTestBuilder::new().build();
+    let a_id = test.universal_canister().unwrap();
+    let b_id = test.universal_canister().unwrap();
+
+    let wasm_payload = wasm()
+        .call_simple(
+            b_id.get(),
+            "update",
+            call_args().other_side(wasm().push_bytes(&[42]).append_and_reply()),
+        )
+        .build();
+    let (ingress, _) = test.ingress_raw(a_id, "update", wasm_payload);
+    test.execute_message(a_id);
+
+    test.reinstall_canister(a_id, UNIVERSAL_CANISTER_WASM.to_vec()).unwrap();
+    let err = check_ingress_status(test.ingress_status(&ingress)).unwrap_err();
+    assert_eq!(err.code(), ErrorCode::CanisterCallContextDeleted);
+    assert_eq!(err.description(), "Canister has been reinstalled.");
+
+    // The response to the outstanding call is not executed by the new code.
+    test.induct_messages();
+    test.execute_message(b_id);
+    test.induct_messages();
+    test.execute_message(a_id);
+    let err = check_ingress_status(test.ingress_status(&ingress)).unwrap_err();
+    assert_eq!(err.code(), ErrorCode::CanisterCallContextDeleted);
+    assert!(test
+        .canister_state(a_id)
+        .system_state
+        .call_context_manager()
+        .unwrap()
+        .call_contexts()
+        .is_empty());
+}
@@ -59,7 +59,7 @@ impl SystemTaskResult {
     }
 }
 
-// Validates a canister before executing a heartbeat or a timer.
+// Validates a canister before executing a system task.
 //
 // Returns the canister split in parts if successful,
 // otherwise `SystemTaskResult` which contains the error.
@@ -113,8 +113,8 @@ fn validate_canister(
 ///       Otherwise, `CanisterSystemTaskError::CanisterNotRunning` error is returned.
 ///     - Wasm module is present.
 ///       Otherwise, `CanisterSystemTaskError::CanisterExecutionFailed` error is returned.
-///     - Wasm module exports `canister_heartbeat` or `canister_global_timer`
-///       system method.
+///     - Wasm module exports `canister_heartbeat`, `canister_global_timer`,
+///       or `canister_on_low_wasm_memory` system method.
 ///    
 /// When the system method is not exported, the execution succeeds as a no-op operation.
 /// No changes are applied to the canister state if the canister cannot be validated.
@@ -155,10 +155,12 @@ pub fn execute_system_task(
             );
         }
     }
-    // Only `canister_heartbeat` and `canister_global_timer` are allowed.
+    // Only `canister_heartbeat`, `canister_global_timer`, and
+    // `canister_on_low_wasm_memory` are allowed.
     assert!(
         system_task == SystemMethod::CanisterHeartbeat
             || system_task == SystemMethod::CanisterGlobalTimer
+            || system_task == SystemMethod::CanisterOnLowWasmMemory
     );
     // System task methods run without DTS.
     let instruction_limits = &execution_parameters.instruction_limits;
@@ -253,8 +255,8 @@ pub fn execute_system_task(
     SystemTaskResult::new(canister, instructions_used, heap_delta)
 }
 
-/// Errors when executing `canister_heartbeat` or `canister_global_timer`
-/// system tasks.
+/// Errors when executing `canister_heartbeat`, `canister_global_timer`, or
+/// `canister_on_low_wasm_memory` system tasks.
 #[derive(Debug, Eq, PartialEq)]
 pub enum CanisterSystemTaskError {
     /// The canister isn't running.
@@ -1,11 +1,13 @@
 use crate::execution::system_task::CanisterSystemTaskError;
 use crate::execution::test_utilities::{wat_compilation_cost, ExecutionTestBuilder};
 use assert_matches::assert_matches;
-use ic_ic00_types::CanisterStatusType;
+use ic_ic00_types::{
+    CanisterIdRecord, CanisterStatusResultV2, CanisterStatusType, Method, Payload, IC_00,
+};
 use ic_interfaces::execution_environment::{HypervisorError, TrapCode};
 use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::{page_map::PAGE_SIZE, CanisterStatus};
-use ic_state_machine_tests::{Cycles, StateMachine};
+use ic_state_machine_tests::{CanisterId, Cycles, StateMachine};
 use ic_state_machine_tests::{StateMachineBuilder, WasmResult};
 use ic_test_utilities_metrics::fetch_int_counter_vec;
 use ic_types::methods::SystemMethod;
@@ -641,3 +643,55 @@ fn global_timer_set_returns_zero_in_canister_global_timer_method() {
     let result = env.query(canister_id, "read_value", vec![]).unwrap();
     assert_eq!(result, WasmResult::Reply(0_u64.to_le_bytes().into()));
 }
+
+fn global_timer_deadline(env: &StateMachine, canister_id: CanisterId) -> Option<u64> {
+    let result = env
+        .execute_ingress(
+            IC_00,
+            Method::CanisterStatus,
+            CanisterIdRecord::from(canister_id).encode(),
+        )
+        .unwrap();
+    match result {
+        WasmResult::Reply(bytes) => CanisterStatusResultV2::decode(&bytes)
+            .unwrap()
+            .global_timer_deadline(),
+        WasmResult::Reject(msg) => panic!("Unexpected reject: {}", msg),
+    }
+}
+
+#[test]
+fn canister_status_reports_global_timer_deadline() {
+    let env = StateMachine::new();
+    let canister_id = env
+        .install_canister(UNIVERSAL_CANISTER_WASM.to_vec(), vec![], None)
+        .unwrap();
+    assert_eq!(global_timer_deadline(&env, canister_id), None);
+
+    let deadline = env.time() + Duration::from_secs(5);
+    let deadline_nanos = deadline.duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64;
+    let set_global_timer = wasm()
+        .set_global_timer_method(wasm().inc_global_counter())
+        .api_global_timer_set(deadline_nanos)
+        .reply()
+        .build();
+    env.execute_ingress(canister_id, "update", set_global_timer)
+        .unwrap();
+    assert_eq!(global_timer_deadline(&env, canister_id), Some(deadline_nanos));
+
+    // The timer is still pending before the deadline.
+    env.advance_time(Duration::from_secs(4));
+    env.tick();
+    assert_eq!(global_timer_deadline(&env, canister_id), Some(deadline_nanos));
+
+    // The timer runs once in the first round after the deadline, however far
+    // the time has moved past it.
+    env.advance_time(Duration::from_secs(60));
+    env.tick();
+    assert_eq!(global_timer_deadline(&env, canister_id), None);
+    let get_global_counter = wasm().get_global_counter().reply_int64().build();
+    let result = env
+        .execute_ingress(canister_id, "update", get_global_counter)
+        .unwrap();
+    assert_eq!(result, WasmResult::Reply(1u64.to_le_bytes().into()));
+}
@@ -18,8 +18,8 @@ use ic_embedders::{wasm_utils::compile, WasmtimeEmbedder};
 use ic_error_types::{ErrorCode, RejectCode, UserError};
 use ic_ic00_types::{
     CanisterIdRecord, CanisterInstallMode, CanisterSettingsArgs, CanisterStatusType, EcdsaKeyId,
-    EmptyBlob, InstallCodeArgs, Method, Payload, ProvisionalCreateCanisterWithCyclesArgs,
-    UpdateSettingsArgs,
+    EmptyBlob, InstallChunkedCodeArgs, InstallCodeArgs, Method, Payload,
+    ProvisionalCreateCanisterWithCyclesArgs, UpdateSettingsArgs, UploadChunkArgs,
 };
 use ic_interfaces::{
     execution_environment::{
@@ -448,6 +448,24 @@ impl ExecutionTest {
         message_id
     }
 
+    /// Sends an `install_chunked_code` message to the IC management canister.
+    pub fn install_chunked_code(
+        &mut self,
+        args: InstallChunkedCodeArgs,
+    ) -> Result<WasmResult, UserError> {
+        self.subnet_message(Method::InstallChunkedCode, args.encode())
+    }
+
+    /// Uploads the given chunk to the Wasm chunk store of the given canister.
+    pub fn upload_chunk(
+        &mut self,
+        canister_id: CanisterId,
+        chunk: Vec<u8>,
+    ) -> Result<WasmResult, UserError> {
+        let payload = UploadChunkArgs::new(canister_id, chunk).encode();
+        self.subnet_message(Method::UploadChunk, payload)
+    }
+
     /// Sends an `uninstall_code` message to the IC management canister.
     pub fn uninstall_code(&mut self, canister_id: CanisterId) -> Result<WasmResult, UserError> {
         let payload = CanisterIdRecord::from(canister_id).encode();
@@ -504,6 +522,23 @@ impl ExecutionTest {
         self.subnet_message(Method::UpdateSettings, payload)
     }
 
+    /// Updates the Wasm memory limit of the given canister.
+    pub fn update_wasm_memory_limit(
+        &mut self,
+        canister_id: CanisterId,
+        wasm_memory_limit: NumBytes,
+    ) -> Result<WasmResult, UserError> {
+        let payload = UpdateSettingsArgs {
+            canister_id: canister_id.into(),
+            settings: CanisterSettingsArgs {
+                wasm_memory_limit: Some(candid::Nat::from(wasm_memory_limit.get())),
+                ..Default::default()
+            },
+        }
+        .encode();
+        self.subnet_message(Method::UpdateSettings, payload)
+    }
+
     /// Sets the controller of the canister to the given principal.
     pub fn set_controller(
         &mut self,
@@ -1270,17 +1305,21 @@ pub struct ExecutionTestBuilder {
     initial_canister_cycles: Cycles,
     subnet_total_memory: i64,
     subnet_message_memory: i64,
+    subnet_memory_threshold: NumBytes,
     registry_settings: RegistryExecutionSettings,
     manual_execution: bool,
     rate_limiting_of_instructions: bool,
     deterministic_time_slicing: bool,
     composite_queries: bool,
+    best_effort_responses: bool,
+    reject_message_truncation: bool,
     allocatable_compute_capacity_in_percent: usize,
     subnet_features: String,
     bitcoin_privileged_access: Vec<CanisterId>,
     bitcoin_get_successors_follow_up_responses: BTreeMap<CanisterId, Vec<Vec<u8>>>,
     cost_to_compile_wasm_instruction: u64,
     max_instructions_per_composite_query_call: NumInstructions,
+    max_instructions_per_query: NumInstructions,
 }
 
 impl Default for ExecutionTestBuilder {
@@ -1298,6 +1337,8 @@ impl Default for ExecutionTestBuilder {
         let max_instructions_per_composite_query_call =
             ic_config::execution_environment::Config::default()
                 .max_instructions_per_composite_query_call;
+        let max_instructions_per_query =
+            ic_config::execution_environment::Config::default().max_instructions_per_query;
         Self {
             nns_subnet_id: subnet_test_id(2),
             own_subnet_id: subnet_test_id(1),
@@ -1317,11 +1358,15 @@ impl Default for ExecutionTestBuilder {
             initial_canister_cycles: INITIAL_CANISTER_CYCLES,
             subnet_total_memory,
             subnet_message_memory,
+            subnet_memory_threshold: ic_config::execution_environment::Config::default()
+                .subnet_memory_threshold,
             registry_settings: test_registry_settings(),
             manual_execution: false,
             rate_limiting_of_instructions: false,
             deterministic_time_slicing: false,
             composite_queries: false,
+            best_effort_responses: false,
+            reject_message_truncation: false,
             allocatable_compute_capacity_in_percent: 100,
             subnet_features: String::default(),
             bitcoin_privileged_access: Vec::default(),
@@ -1330,6 +1375,7 @@ impl Default for ExecutionTestBuilder {
                 .cost_to_compile_wasm_instruction
                 .get(),
             max_instructions_per_composite_query_call,
+            max_instructions_per_query,
         }
     }
 }
@@ -1380,6 +1426,13 @@ impl ExecutionTestBuilder {
         }
     }
 
+    pub fn with_max_instructions_per_query(self, max_instructions_per_query: u64) -> Self {
+        Self {
+            max_instructions_per_query: NumInstructions::from(max_instructions_per_query),
+            ..self
+        }
+    }
+
     pub fn with_log(self, log: ReplicaLogger) -> Self {
         Self { log, ..self }
     }
@@ -1454,6 +1507,13 @@ impl ExecutionTestBuilder {
         }
     }
 
+    pub fn with_subnet_memory_threshold(self, subnet_memory_threshold: i64) -> Self {
+        Self {
+            subnet_memory_threshold: NumBytes::from(subnet_memory_threshold as u64),
+            ..self
+        }
+    }
+
     pub fn with_subnet_features(self, subnet_features: &str) -> Self {
         Self {
             subnet_features: String::from(subnet_features),
@@ -1499,6 +1559,20 @@ impl ExecutionTestBuilder {
         }
     }
 
+    pub fn with_best_effort_responses(self) -> Self {
+        Self {
+            best_effort_responses: true,
+            ..self
+        }
+    }
+
+    pub fn with_reject_message_truncation(self) -> Self {
+        Self {
+            reject_message_truncation: true,
+            ..self
+        }
+    }
+
     pub fn with_allocatable_compute_capacity_in_percent(
         self,
         allocatable_compute_capacity_in_percent: usize,
@@ -1640,13 +1714,26 @@ impl ExecutionTestBuilder {
         } else {
             FlagStatus::Disabled
         };
+        let best_effort_responses = if self.best_effort_responses {
+            FlagStatus::Enabled
+        } else {
+            FlagStatus::Disabled
+        };
+        let reject_message_truncation = if self.reject_message_truncation {
+            FlagStatus::Enabled
+        } else {
+            FlagStatus::Disabled
+        };
         let config = Config {
             rate_limiting_of_instructions,
             deterministic_time_slicing,
             composite_queries,
+            best_effort_responses,
+            reject_message_truncation,
             allocatable_compute_capacity_in_percent: self.allocatable_compute_capacity_in_percent,
             subnet_memory_capacity: NumBytes::from(self.subnet_total_memory as u64),
             subnet_message_memory_capacity: NumBytes::from(self.subnet_message_memory as u64),
+            subnet_memory_threshold: self.subnet_memory_threshold,
             bitcoin: BitcoinConfig {
                 privileged_access: self.bitcoin_privileged_access,
                 ..Default::default()
@@ -1654,6 +1741,7 @@ impl ExecutionTestBuilder {
             cost_to_compile_wasm_instruction: self.cost_to_compile_wasm_instruction.into(),
             max_instructions_per_composite_query_call: self
                 .max_instructions_per_composite_query_call,
+            max_instructions_per_query: self.max_instructions_per_query,
             ..Config::default()
         };
         let hypervisor = Hypervisor::new(
@@ -1697,7 +1785,8 @@ impl ExecutionTestBuilder {
             self.subnet_type,
             config,
             &metrics_registry,
-            self.instruction_limit_without_dts,
+            self.instruction_limit_without_dts
+                .min(self.max_instructions_per_query),
             Arc::clone(&cycles_account_manager),
             composite_queries,
         );
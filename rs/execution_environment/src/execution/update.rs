@@ -109,6 +109,7 @@ pub fn execute_update(
         original.message.cycles(),
         *original.message.sender(),
         helper.call_context_id(),
+        original.message.deadline(),
     );
 
     let memory_usage = helper
@@ -344,6 +345,9 @@ impl UpdateHelper {
             round.time,
             round.network_topology,
             round.hypervisor.subnet_id(),
+            &original.execution_parameters.subnet_memory_saturation,
+            original.subnet_size,
+            round.cycles_account_manager,
             round.log,
         );
         let heap_delta = if output.wasm_result.is_ok() {
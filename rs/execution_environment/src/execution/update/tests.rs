@@ -1,11 +1,15 @@
 use std::time::Duration;
 
-use ic_base_types::NumSeconds;
+use ic_base_types::{NumBytes, NumSeconds};
 use ic_config::subnet_config::SchedulerConfig;
 use ic_error_types::ErrorCode;
 use ic_registry_subnet_type::SubnetType;
-use ic_replicated_state::{canister_state::NextExecution, CallOrigin};
+use ic_replicated_state::canister_state::{NextExecution, WASM_PAGE_SIZE_IN_BYTES};
+use ic_replicated_state::{CallOrigin, NumWasmPages};
 use ic_state_machine_tests::{Cycles, WasmResult};
+use ic_test_utilities_metrics::{
+    fetch_histogram_vec_stats, fetch_int_counter_vec, metric_vec, HistogramStats,
+};
 use ic_types::NumInstructions;
 use ic_universal_canister::{call_args, wasm};
 
@@ -581,6 +585,69 @@ fn dts_abort_of_call_works() {
     );
 }
 
+#[test]
+fn dts_update_observes_slice_metrics() {
+    let mut test = ExecutionTestBuilder::new()
+        .with_instruction_limit(1_000_000)
+        .with_slice_instruction_limit(10_000)
+        .with_deterministic_time_slicing()
+        .with_manual_execution()
+        .build();
+    let canister_id = test.universal_canister().unwrap();
+    let work = wasm()
+        .stable64_grow(1)
+        .stable64_fill(0, 0, 10_000)
+        .stable64_fill(0, 0, 10_000)
+        .push_bytes(&[42])
+        .append_and_reply()
+        .build();
+    let (ingress_id, _) = test.ingress_raw(canister_id, "update", work);
+
+    // Abort the execution after the first slice.
+    test.execute_slice(canister_id);
+    assert_eq!(
+        test.canister_state(canister_id).next_execution(),
+        NextExecution::ContinueLong
+    );
+    test.abort_all_paused_executions();
+    assert_eq!(
+        fetch_int_counter_vec(test.metrics_registry(), "execution_dts_aborted_executions_total"),
+        metric_vec(&[(&[("message_type", "update")], 1)])
+    );
+
+    // Execute the message from scratch.
+    let mut slices = 0;
+    while test.canister_state(canister_id).next_execution() != NextExecution::None {
+        test.execute_slice(canister_id);
+        slices += 1;
+    }
+    assert!(slices > 1);
+    let result = check_ingress_status(test.ingress_status(&ingress_id)).unwrap();
+    assert_eq!(result, WasmResult::Reply(vec![42]));
+
+    // Only the execution that finished is counted in the slices per message.
+    assert_eq!(
+        fetch_histogram_vec_stats(test.metrics_registry(), "execution_dts_message_slices"),
+        metric_vec(&[(
+            &[("message_type", "update")],
+            HistogramStats {
+                count: 1,
+                sum: slices as f64,
+            }
+        )])
+    );
+    assert_eq!(
+        fetch_int_counter_vec(test.metrics_registry(), "execution_dts_paused_executions_total"),
+        metric_vec(&[(&[("message_type", "update")], slices)])
+    );
+    let slice_instructions =
+        fetch_histogram_vec_stats(test.metrics_registry(), "execution_dts_slice_instructions");
+    assert_eq!(
+        slice_instructions.values().map(|stats| stats.count).sum::<u64>(),
+        slices + 1
+    );
+}
+
 #[test]
 fn dts_cycles_debit_is_applied_on_aborts() {
     // Test steps:
@@ -687,3 +754,80 @@ fn dts_uninstall_with_aborted_update() {
         )
     );
 }
+
+#[test]
+fn update_reserves_cycles_for_memory_on_saturated_subnet() {
+    let mut test = ExecutionTestBuilder::new()
+        .with_subnet_total_memory(GB as i64)
+        .with_subnet_memory_threshold(0)
+        .build();
+    let canister_id = test.universal_canister().unwrap();
+    assert_eq!(
+        test.canister_state(canister_id).system_state.reserved_balance(),
+        Cycles::zero()
+    );
+    let balance_before = test.canister_state(canister_id).system_state.balance();
+
+    test.ingress(canister_id, "update", wasm().stable64_grow(160).reply().build()).unwrap();
+
+    let system_state = &test.canister_state(canister_id).system_state;
+    let reserved_balance = system_state.reserved_balance();
+    assert!(reserved_balance > Cycles::zero());
+    assert!(balance_before - system_state.balance() >= reserved_balance);
+}
+
+#[test]
+fn update_fails_if_memory_reservation_exceeds_reserved_cycles_limit() {
+    let mut test = ExecutionTestBuilder::new()
+        .with_subnet_total_memory(GB as i64)
+        .with_subnet_memory_threshold(0)
+        .build();
+    let canister_id = test.universal_canister().unwrap();
+    test.canister_state_mut(canister_id)
+        .system_state
+        .set_reserved_balance_limit(Some(Cycles::new(1)));
+
+    let err = test
+        .ingress(canister_id, "update", wasm().stable64_grow(160).reply().build())
+        .unwrap_err();
+
+    assert_eq!(err.code(), ErrorCode::ReservedCyclesLimitExceededInMemoryGrow);
+    assert_eq!(
+        test.canister_state(canister_id).system_state.reserved_balance(),
+        Cycles::zero()
+    );
+}
+
+#[test]
+fn update_traps_if_wasm_memory_exceeds_limit() {
+    let mut test = ExecutionTestBuilder::new().build();
+    let wat = r#"(module
+        (import "ic0" "msg_reply" (func $msg_reply))
+        (func $grow
+            (drop (memory.grow (i32.const 10)))
+            (call $msg_reply)
+        )
+        (export "canister_update grow" (func $grow))
+        (export "canister_query grow_query" (func $grow))
+        (memory 1)
+    )"#;
+    let canister_id = test.canister_from_wat(wat).unwrap();
+    let limit = NumBytes::from(5 * WASM_PAGE_SIZE_IN_BYTES as u64);
+    test.update_wasm_memory_limit(canister_id, limit).unwrap();
+
+    let err = test.ingress(canister_id, "grow", vec![]).unwrap_err();
+    assert_eq!(err.code(), ErrorCode::CanisterWasmMemoryLimitExceeded);
+    let wasm_memory = &test.execution_state(canister_id).wasm_memory;
+    assert_eq!(wasm_memory.size, NumWasmPages::from(1));
+
+    // Queries are exempt from the limit.
+    let result = test.ingress(canister_id, "grow_query", vec![]).unwrap();
+    assert_eq!(result, WasmResult::Reply(vec![]));
+
+    // A limit of zero removes the limit.
+    test.update_wasm_memory_limit(canister_id, NumBytes::from(0)).unwrap();
+    let result = test.ingress(canister_id, "grow", vec![]).unwrap();
+    assert_eq!(result, WasmResult::Reply(vec![]));
+    let wasm_memory = &test.execution_state(canister_id).wasm_memory;
+    assert_eq!(wasm_memory.size, NumWasmPages::from(11));
+}
@@ -4,11 +4,12 @@ use crate::execution::test_utilities::{check_ingress_status, ExecutionTest, Exec
 use ic_error_types::ErrorCode;
 use ic_ic00_types::{EmptyBlob, Payload};
 use ic_logger::replica_logger::LogEntryLogger;
-use ic_replicated_state::{canister_state::NextExecution, CanisterState};
+use ic_replicated_state::canister_state::{NextExecution, WASM_PAGE_SIZE_IN_BYTES};
+use ic_replicated_state::CanisterState;
 use ic_state_machine_tests::{IngressState, WasmResult};
 use ic_test_utilities::types::ids::user_test_id;
 use ic_test_utilities_metrics::fetch_int_counter;
-use ic_types::Cycles;
+use ic_types::{Cycles, NumBytes};
 use maplit::btreeset;
 
 ////////////////////////////////////////////////////////////////////////
@@ -878,3 +879,49 @@ fn dts_uninstall_with_aborted_upgrade() {
         )
     );
 }
+
+////////////////////////////////////////////////////////////////////////
+// Wasm memory limit
+
+/// Returns a WASM binary whose `canister_pre_upgrade` and
+/// `canister_post_upgrade` grow the Wasm memory by the given numbers of pages.
+fn wasm_memory_growing_binary(pre_upgrade_pages: u32, post_upgrade_pages: u32) -> Vec<u8> {
+    let wat = module(format!(
+        r#"(func (export "canister_pre_upgrade")
+                (drop (memory.grow (i32.const {pre_upgrade_pages})))
+            )
+            (func (export "canister_post_upgrade")
+                (drop (memory.grow (i32.const {post_upgrade_pages})))
+            )
+            (memory 1)"#
+    ));
+    wabt::wat2wasm(wat).unwrap()
+}
+
+#[test]
+fn upgrade_fails_if_post_upgrade_exceeds_wasm_memory_limit() {
+    let mut test = execution_test_with_max_rounds(1);
+    let canister_id = test.canister_from_binary(wasm_memory_growing_binary(0, 0)).unwrap();
+    let limit = NumBytes::from(5 * WASM_PAGE_SIZE_IN_BYTES as u64);
+    test.update_wasm_memory_limit(canister_id, limit).unwrap();
+    let canister_state_before = test.canister_state(canister_id).clone();
+
+    let result = test.upgrade_canister(canister_id, wasm_memory_growing_binary(0, 10));
+    assert_eq!(result.unwrap_err().code(), ErrorCode::CanisterWasmMemoryLimitExceeded);
+    assert_canister_state_after_err(&canister_state_before, test.canister_state(canister_id));
+
+    // Growing the Wasm memory within the limit succeeds.
+    test.upgrade_canister(canister_id, wasm_memory_growing_binary(0, 4)).unwrap();
+}
+
+#[test]
+fn upgrade_ok_if_pre_upgrade_exceeds_wasm_memory_limit() {
+    let mut test = execution_test_with_max_rounds(1);
+    let canister_id = test.canister_from_binary(wasm_memory_growing_binary(10, 0)).unwrap();
+    let limit = NumBytes::from(5 * WASM_PAGE_SIZE_IN_BYTES as u64);
+    test.update_wasm_memory_limit(canister_id, limit).unwrap();
+
+    // `canister_pre_upgrade` is exempt from the limit, so that the
+    // controllers can always upgrade a canister that is close to the limit.
+    test.upgrade_canister(canister_id, wasm_memory_growing_binary(0, 0)).unwrap();
+}
@@ -1,9 +1,10 @@
 use crate::{
     canister_manager::{
-        CanisterManager, CanisterManagerError, CanisterMgrConfig, DtsInstallCodeResult,
-        InstallCodeContext, PausedInstallCodeExecution, StopCanisterResult,
+        assemble_chunked_wasm_module, CanisterManager, CanisterManagerError, CanisterMgrConfig,
+        DtsInstallCodeResult, InstallCodeContext, PausedInstallCodeExecution, StopCanisterResult,
     },
     canister_settings::CanisterSettings,
+    chaos::Chaos,
     execution::{
         inspect_message,
         nonreplicated_query::execute_non_replicated_query,
@@ -13,10 +14,11 @@ use crate::{
         update::execute_update,
     },
     execution_environment_metrics::{
-        ExecutionEnvironmentMetrics, SUBMITTED_OUTCOME_LABEL, SUCCESS_STATUS_LABEL,
+        DtsMessageType, ExecutionEnvironmentMetrics, RejectOrigin, SUBMITTED_OUTCOME_LABEL,
+        SUCCESS_STATUS_LABEL,
     },
     hypervisor::Hypervisor,
-    util::candid_error_to_user_error,
+    util::{candid_error_to_user_error, process_responses},
     NonReplicatedQueryKind,
 };
 use candid::Encode;
@@ -25,14 +27,15 @@ use ic_config::execution_environment::Config as ExecutionConfig;
 use ic_config::flag_status::FlagStatus;
 use ic_constants::{LOG_CANISTER_OPERATION_CYCLES_THRESHOLD, SMALL_APP_SUBNET_MAX_SIZE};
 use ic_crypto_tecdsa::derive_tecdsa_public_key;
-use ic_cycles_account_manager::{CyclesAccountManager, IngressInductionCost};
+use ic_cycles_account_manager::{CyclesAccountManager, IngressInductionCost, ResourceSaturation};
 use ic_error_types::{ErrorCode, RejectCode, UserError};
 use ic_ic00_types::{
-    CanisterHttpRequestArgs, CanisterIdRecord, CanisterSettingsArgs,
+    CanisterHttpRequestArgs, CanisterIdRecord, CanisterSettingsArgs, ChunkHash,
     ComputeInitialEcdsaDealingsArgs, CreateCanisterArgs, ECDSAPublicKeyArgs,
-    ECDSAPublicKeyResponse, EcdsaKeyId, EmptyBlob, InstallCodeArgs, Method as Ic00Method,
-    Payload as Ic00Payload, ProvisionalCreateCanisterWithCyclesArgs, ProvisionalTopUpCanisterArgs,
-    SetControllerArgs, SetupInitialDKGArgs, SignWithECDSAArgs, UpdateSettingsArgs, IC_00,
+    ECDSAPublicKeyResponse, EcdsaKeyId, EmptyBlob, InstallChunkedCodeArgs, InstallCodeArgs,
+    Method as Ic00Method, Payload as Ic00Payload, ProvisionalCreateCanisterWithCyclesArgs,
+    ProvisionalTopUpCanisterArgs, SetControllerArgs, SetupInitialDKGArgs, SignWithECDSAArgs,
+    StopCanisterArgs, StoredChunksReply, UpdateSettingsArgs, UploadChunkArgs, IC_00,
 };
 use ic_interfaces::{
     execution_environment::{
@@ -60,11 +63,12 @@ use ic_types::{
     crypto::threshold_sig::ni_dkg::NiDkgTargetId,
     ingress::{IngressState, IngressStatus, WasmResult},
     messages::{
-        extract_effective_canister_id, AnonymousQuery, Payload, RejectContext, Request, Response,
-        SignedIngressContent, StopCanisterContext,
+        extract_effective_canister_id, truncate_reject_message, AnonymousQuery, Payload,
+        RejectContext, Request, Response, SignedIngressContent, StopCanisterContext,
     },
-    CanisterId, CanisterTimer, Cycles, LongExecutionMode, NumBytes, NumInstructions, SubnetId,
-    Time,
+    time::NO_DEADLINE,
+    CanisterId, CanisterTimer, CountBytes, Cycles, LongExecutionMode, NumBytes, NumInstructions,
+    NumSlices, SubnetId, Time,
 };
 use ic_types::{messages::MessageId, methods::SystemMethod, methods::WasmMethod};
 use ic_wasm_types::WasmHash;
@@ -235,6 +239,69 @@ pub trait PausedExecution: std::fmt::Debug + Send {
     fn abort(self: Box<Self>, log: &ReplicaLogger) -> (CanisterInputMessage, Cycles);
 }
 
+/// The progress of a message execution across slices, used for the metrics
+/// of deterministic time slicing.
+#[derive(Clone, Copy, Debug)]
+struct DtsProgress {
+    message_type: DtsMessageType,
+    // The number of slices executed so far.
+    slices: NumSlices,
+}
+
+impl DtsProgress {
+    /// Returns the progress of a new execution of `message` by `canister`
+    /// after its first slice.
+    fn first_slice(canister: &CanisterState, message: &CanisterInputMessage) -> Self {
+        let message_type = match message {
+            CanisterInputMessage::Response(_) => DtsMessageType::Response,
+            CanisterInputMessage::Request(request) => {
+                dts_message_type(canister, &request.method_name)
+            }
+            CanisterInputMessage::Ingress(ingress) => {
+                dts_message_type(canister, &ingress.method_name)
+            }
+        };
+        Self {
+            message_type,
+            slices: NumSlices::from(1),
+        }
+    }
+
+    /// Returns the progress after the next slice.
+    fn next_slice(self) -> Self {
+        Self {
+            message_type: self.message_type,
+            slices: self.slices + NumSlices::from(1),
+        }
+    }
+}
+
+/// Returns the DTS message type of a call to `method_name` of `canister`.
+fn dts_message_type(canister: &CanisterState, method_name: &str) -> DtsMessageType {
+    match resolve_method(canister, method_name) {
+        WasmMethod::Query(_) | WasmMethod::CompositeQuery(_) => DtsMessageType::ReplicatedQuery,
+        WasmMethod::Update(_) | WasmMethod::System(_) => DtsMessageType::Update,
+    }
+}
+
+/// Returns the method that a replicated call to `method_name` executes: the
+/// query or composite query exported under that name, or the update method
+/// otherwise.
+fn resolve_method(canister: &CanisterState, method_name: &str) -> WasmMethod {
+    // Note that Wasm validation guarantees that a name cannot be exported
+    // multiple times as different types. So the order of checks here matters
+    // only for performance, not correctness.
+    let method = WasmMethod::Query(method_name.to_string());
+    if canister.exports_method(&method) {
+        return method;
+    }
+    let method = WasmMethod::CompositeQuery(method_name.to_string());
+    if canister.exports_method(&method) {
+        return method;
+    }
+    WasmMethod::Update(method_name.to_string())
+}
+
 /// Stores all paused executions keyed by their ids.
 #[derive(Default)]
 struct PausedExecutionRegistry {
@@ -244,8 +311,9 @@ struct PausedExecutionRegistry {
     // more than 2^64 paused executions between two checkpoints.
     next_id: u64,
 
-    // Paused executions of ordinary canister messages.
-    paused_execution: HashMap<PausedExecutionId, Box<dyn PausedExecution>>,
+    // Paused executions of ordinary canister messages along with their
+    // progress.
+    paused_execution: HashMap<PausedExecutionId, (Box<dyn PausedExecution>, DtsProgress)>,
 
     // Paused executions of `install_code` subnet messages.
     paused_install_code: HashMap<PausedExecutionId, Box<dyn PausedInstallCodeExecution>>,
@@ -389,15 +457,16 @@ impl ExecutionEnvironment {
                             },
                         );
 
-                        state.push_subnet_output_response(
+                        self.push_subnet_output_response(
+                            &mut state,
                             Response {
                                 originator: request.sender,
                                 respondent: CanisterId::from(self.own_subnet_id),
                                 originator_reply_callback: request.sender_reply_callback,
                                 refund: request.payment,
                                 response_payload: response.response_payload.clone(),
-                            }
-                            .into(),
+                                deadline: request.deadline,
+                            },
                         );
 
                         (state, Some(NumInstructions::from(0)))
@@ -412,7 +481,7 @@ impl ExecutionEnvironment {
         let method = Ic00Method::from_str(msg.method_name());
         let payload = msg.method_payload();
         let result = match method {
-            Ok(Ic00Method::InstallCode) => {
+            Ok(Ic00Method::InstallCode) | Ok(Ic00Method::InstallChunkedCode) => {
                 // Tail call is needed for deterministic time slicing here to
                 // properly handle the case of a paused execution.
                 return self.execute_install_code(
@@ -436,7 +505,8 @@ impl ExecutionEnvironment {
 
                     if !reject_message.is_empty() {
                         use ic_types::messages;
-                        state.push_subnet_output_response(
+                        self.push_subnet_output_response(
+                            &mut state,
                             Response {
                                 originator: request.sender,
                                 respondent: CanisterId::from(self.own_subnet_id),
@@ -448,8 +518,8 @@ impl ExecutionEnvironment {
                                         message: reject_message,
                                     },
                                 ),
-                            }
-                            .into(),
+                                deadline: request.deadline,
+                            },
                         );
                         return (state, Some(NumInstructions::from(0)));
                     }
@@ -641,9 +711,11 @@ impl ExecutionEnvironment {
                 Some((res, msg.take_cycles()))
             }
 
-            Ok(Ic00Method::StopCanister) => match CanisterIdRecord::decode(payload) {
+            Ok(Ic00Method::StopCanister) => match StopCanisterArgs::decode(payload) {
                 Err(err) => Some((Err(candid_error_to_user_error(err)), msg.take_cycles())),
-                Ok(args) => self.stop_canister(args.get_canister_id(), &msg, &mut state),
+                Ok(args) => {
+                    self.stop_canister(args.get_canister_id(), &msg, args.force(), &mut state)
+                }
             },
 
             Ok(Ic00Method::DeleteCanister) => {
@@ -885,6 +957,61 @@ impl ExecutionEnvironment {
                 Some((res, msg.take_cycles()))
             }
 
+            Ok(Ic00Method::UploadChunk) => {
+                let res = match UploadChunkArgs::decode(payload) {
+                    Err(err) => Err(candid_error_to_user_error(err)),
+                    Ok(args) => self.upload_chunk(
+                        *msg.sender(),
+                        args.get_canister_id(),
+                        args.chunk,
+                        &mut state,
+                        round_limits,
+                    ),
+                };
+                Some((res, msg.take_cycles()))
+            }
+
+            Ok(Ic00Method::ClearChunkStore) => {
+                let res = match CanisterIdRecord::decode(payload) {
+                    Err(err) => Err(candid_error_to_user_error(err)),
+                    Ok(args) => self.clear_chunk_store(
+                        *msg.sender(),
+                        args.get_canister_id(),
+                        &mut state,
+                        round_limits,
+                    ),
+                };
+                Some((res, msg.take_cycles()))
+            }
+
+            Ok(Ic00Method::StoredChunks) => {
+                let res = match CanisterIdRecord::decode(payload) {
+                    Err(err) => Err(candid_error_to_user_error(err)),
+                    Ok(args) => self.stored_chunks(*msg.sender(), args.get_canister_id(), &state),
+                };
+                Some((res, msg.take_cycles()))
+            }
+
+            Ok(Ic00Method::FetchCanisterLogs) => {
+                let res = match CanisterIdRecord::decode(payload) {
+                    Err(err) => Err(candid_error_to_user_error(err)),
+                    Ok(args) => {
+                        self.fetch_canister_logs(*msg.sender(), args.get_canister_id(), &state)
+                    }
+                };
+                Some((res, msg.take_cycles()))
+            }
+
+            Ok(Ic00Method::CanisterStoppingStatus) => {
+                let res = match CanisterIdRecord::decode(payload) {
+                    Err(err) => Err(candid_error_to_user_error(err)),
+                    Ok(args) => {
+                        self.canister_stopping_status(*msg.sender(), args.get_canister_id(), &state)
+                    }
+                };
+                Some((res, msg.take_cycles()))
+            }
+
             Ok(Ic00Method::BitcoinGetBalance) => {
                 let cycles = msg.take_cycles();
                 let res = crate::bitcoin::get_balance(msg.method_payload(), &mut state, cycles);
@@ -1033,7 +1160,7 @@ impl ExecutionEnvironment {
 
         let req = match msg {
             CanisterInputMessage::Response(response) => {
-                return self.execute_canister_response(
+                let result = self.execute_canister_response(
                     canister,
                     response,
                     instruction_limits,
@@ -1041,10 +1168,35 @@ impl ExecutionEnvironment {
                     network_topology,
                     round_limits,
                     subnet_size,
-                )
+                );
+                return add_executed_instructions(result, ExecutionKind::Update);
             }
 
-            CanisterInputMessage::Request(request) => RequestOrIngress::Request(request),
+            CanisterInputMessage::Request(request) => {
+                // A best-effort request whose deadline has passed is rejected
+                // without execution, unless its execution was aborted and
+                // has already been paid for.
+                if prepaid_execution_cycles.is_none() && request.has_expired(time) {
+                    let response = Response {
+                        originator: request.sender,
+                        respondent: canister.canister_id(),
+                        originator_reply_callback: request.sender_reply_callback,
+                        refund: request.payment,
+                        response_payload: Payload::Reject(RejectContext::new(
+                            RejectCode::SysUnknown,
+                            "Call deadline has expired.".to_string(),
+                        )),
+                        deadline: request.deadline,
+                    };
+                    return ExecuteMessageResult::Finished {
+                        canister,
+                        response: ExecutionResponse::Request(response),
+                        instructions_used: NumInstructions::from(0),
+                        heap_delta: NumBytes::from(0),
+                    };
+                }
+                RequestOrIngress::Request(request)
+            }
             CanisterInputMessage::Ingress(ingress) => RequestOrIngress::Ingress(ingress),
         };
 
@@ -1057,37 +1209,28 @@ impl ExecutionEnvironment {
             time,
         };
 
-        let method = {
-            // Note that Wasm validation guarantees that a name cannot be
-            // exported multiple times as different types. So the order of
-            // checks here matters only for performance, not correctness.
-            let method = WasmMethod::Query(req.method_name().to_string());
-            if canister.exports_method(&method) {
-                method
-            } else {
-                let method = WasmMethod::CompositeQuery(req.method_name().to_string());
-                if canister.exports_method(&method) {
-                    method
-                } else {
-                    WasmMethod::Update(req.method_name().to_string())
-                }
-            }
-        };
+        let method = resolve_method(&canister, req.method_name());
 
         match &method {
-            WasmMethod::Query(_) | WasmMethod::CompositeQuery(_) => {
+            WasmMethod::Query(method_name) | WasmMethod::CompositeQuery(method_name) => {
+                let method_name = method_name.clone();
                 // A query call is expected to finish quickly, so DTS is not supported for it.
+                let instruction_limit = self
+                    .config
+                    .max_instructions_per_query
+                    .min(max_instructions_per_message_without_dts);
                 let instruction_limits = InstructionLimits::new(
                     FlagStatus::Disabled,
-                    max_instructions_per_message_without_dts,
-                    max_instructions_per_message_without_dts,
+                    instruction_limit,
+                    instruction_limit,
                 );
                 let execution_parameters = self.execution_parameters(
                     &canister,
                     instruction_limits,
                     ExecutionMode::Replicated,
+                    self.subnet_memory_saturation(round_limits),
                 );
-                execute_replicated_query(
+                let result = execute_replicated_query(
                     canister,
                     req,
                     method,
@@ -1096,15 +1239,18 @@ impl ExecutionEnvironment {
                     round,
                     round_limits,
                     subnet_size,
-                )
+                );
+                let result = add_query_stats(result, method_name);
+                add_executed_instructions(result, ExecutionKind::Query)
             }
             WasmMethod::Update(_) => {
                 let execution_parameters = self.execution_parameters(
                     &canister,
                     instruction_limits,
                     ExecutionMode::Replicated,
+                    self.subnet_memory_saturation(round_limits),
                 );
-                execute_update(
+                let result = execute_update(
                     canister,
                     req,
                     method,
@@ -1114,7 +1260,8 @@ impl ExecutionEnvironment {
                     round,
                     round_limits,
                     subnet_size,
-                )
+                );
+                add_executed_instructions(result, ExecutionKind::Update)
             }
             WasmMethod::System(_) => {
                 unreachable!("Unreachable based on the previous statement");
@@ -1138,9 +1285,13 @@ impl ExecutionEnvironment {
         NumInstructions,
         Result<NumBytes, CanisterSystemTaskError>,
     ) {
-        let execution_parameters =
-            self.execution_parameters(&canister, instruction_limits, ExecutionMode::Replicated);
-        let (canister, instructions_used, result) = execute_system_task(
+        let execution_parameters = self.execution_parameters(
+            &canister,
+            instruction_limits,
+            ExecutionMode::Replicated,
+            self.subnet_memory_saturation(round_limits),
+        );
+        let (mut canister, instructions_used, result) = execute_system_task(
             canister,
             system_task.clone(),
             network_topology,
@@ -1155,6 +1306,7 @@ impl ExecutionEnvironment {
             log,
         )
         .into_parts();
+        canister.scheduler_state.executed_instructions.system_task += instructions_used;
         if let Err(err) = &result {
             // We should monitor all errors in the system subnets and only
             // system errors on other subnets.
@@ -1184,11 +1336,28 @@ impl ExecutionEnvironment {
         self.config.max_canister_memory_size
     }
 
+    /// Returns the fault injector if the chaos mode is enabled.
+    pub(crate) fn chaos(&self) -> Option<Chaos> {
+        self.config.chaos_mode.map(Chaos::new)
+    }
+
     /// Returns the subnet memory capacity.
     pub fn subnet_memory_capacity(&self) -> NumBytes {
         self.config.subnet_memory_capacity
     }
 
+    /// Returns the saturation of the subnet memory given the memory that is
+    /// still available in the current round.
+    fn subnet_memory_saturation(&self, round_limits: &RoundLimits) -> ResourceSaturation {
+        let capacity = self.config.subnet_memory_capacity.get();
+        let available = round_limits.subnet_available_memory.get_total_memory().max(0) as u64;
+        ResourceSaturation::new(
+            capacity.saturating_sub(available),
+            self.config.subnet_memory_threshold.get(),
+            capacity,
+        )
+    }
+
     /// Builds execution parameters for the given canister with the given
     /// instruction limit and available subnet memory counter.
     fn execution_parameters(
@@ -1196,6 +1365,7 @@ impl ExecutionEnvironment {
         canister: &CanisterState,
         instruction_limits: InstructionLimits,
         execution_mode: ExecutionMode,
+        subnet_memory_saturation: ResourceSaturation,
     ) -> ExecutionParameters {
         ExecutionParameters {
             instruction_limits,
@@ -1203,6 +1373,7 @@ impl ExecutionEnvironment {
             compute_allocation: canister.scheduler_state.compute_allocation,
             subnet_type: self.own_subnet_type,
             execution_mode,
+            subnet_memory_saturation,
         }
     }
 
@@ -1313,23 +1484,124 @@ impl ExecutionEnvironment {
         state: &mut ReplicatedState,
         subnet_size: usize,
     ) -> Result<Vec<u8>, UserError> {
+        let time = state.time();
         let canister = get_canister_mut(canister_id, state)?;
 
         self.canister_manager
-            .get_canister_status(sender, canister, subnet_size)
+            .get_canister_status(sender, canister, subnet_size, time)
             .map(|status| status.encode())
             .map_err(|err| err.into())
     }
 
+    fn upload_chunk(
+        &self,
+        sender: PrincipalId,
+        canister_id: CanisterId,
+        chunk: Vec<u8>,
+        state: &mut ReplicatedState,
+        round_limits: &mut RoundLimits,
+    ) -> Result<Vec<u8>, UserError> {
+        let canister = get_canister_mut(canister_id, state)?;
+
+        self.canister_manager
+            .upload_chunk(
+                sender,
+                canister,
+                chunk,
+                self.config.max_canister_memory_size,
+                round_limits,
+            )
+            .map(|hash| {
+                ChunkHash {
+                    hash: hash.to_vec(),
+                }
+                .encode()
+            })
+            .map_err(|err| err.into())
+    }
+
+    fn clear_chunk_store(
+        &self,
+        sender: PrincipalId,
+        canister_id: CanisterId,
+        state: &mut ReplicatedState,
+        round_limits: &mut RoundLimits,
+    ) -> Result<Vec<u8>, UserError> {
+        let canister = get_canister_mut(canister_id, state)?;
+
+        self.canister_manager
+            .clear_chunk_store(sender, canister, round_limits)
+            .map(|()| EmptyBlob.encode())
+            .map_err(|err| err.into())
+    }
+
+    fn stored_chunks(
+        &self,
+        sender: PrincipalId,
+        canister_id: CanisterId,
+        state: &ReplicatedState,
+    ) -> Result<Vec<u8>, UserError> {
+        let canister = state
+            .canister_state(&canister_id)
+            .ok_or(CanisterManagerError::CanisterNotFound(canister_id))?;
+
+        self.canister_manager
+            .stored_chunks(sender, canister)
+            .map(|hashes| {
+                let hashes = hashes
+                    .iter()
+                    .map(|hash| ChunkHash {
+                        hash: hash.to_vec(),
+                    })
+                    .collect();
+                StoredChunksReply(hashes).encode()
+            })
+            .map_err(|err| err.into())
+    }
+
+    fn fetch_canister_logs(
+        &self,
+        sender: PrincipalId,
+        canister_id: CanisterId,
+        state: &ReplicatedState,
+    ) -> Result<Vec<u8>, UserError> {
+        let canister = state
+            .canister_state(&canister_id)
+            .ok_or(CanisterManagerError::CanisterNotFound(canister_id))?;
+
+        self.canister_manager
+            .fetch_canister_logs(sender, canister)
+            .map(|response| response.encode())
+            .map_err(|err| err.into())
+    }
+
+    fn canister_stopping_status(
+        &self,
+        sender: PrincipalId,
+        canister_id: CanisterId,
+        state: &ReplicatedState,
+    ) -> Result<Vec<u8>, UserError> {
+        let canister = state
+            .canister_state(&canister_id)
+            .ok_or(CanisterManagerError::CanisterNotFound(canister_id))?;
+
+        self.canister_manager
+            .canister_stopping_status(sender, canister, state.time())
+            .map(|response| response.encode())
+            .map_err(|err| err.into())
+    }
+
     fn stop_canister(
         &self,
         canister_id: CanisterId,
         msg: &RequestOrIngress,
+        force: bool,
         state: &mut ReplicatedState,
     ) -> Option<(Result<Vec<u8>, UserError>, Cycles)> {
         match self.canister_manager.stop_canister(
             canister_id,
             StopCanisterContext::from(msg.clone()),
+            force,
             state,
         ) {
             StopCanisterResult::RequestAccepted => None,
@@ -1373,8 +1645,12 @@ impl ExecutionEnvironment {
         round_limits: &mut RoundLimits,
         subnet_size: usize,
     ) -> ExecuteMessageResult {
-        let execution_parameters =
-            self.execution_parameters(&canister, instruction_limits, ExecutionMode::Replicated);
+        let execution_parameters = self.execution_parameters(
+            &canister,
+            instruction_limits,
+            ExecutionMode::Replicated,
+            self.subnet_memory_saturation(round_limits),
+        );
         let round = RoundContext {
             network_topology: &network_topology,
             hypervisor: &self.hypervisor,
@@ -1467,14 +1743,18 @@ impl ExecutionEnvironment {
             self.config.max_instructions_for_message_acceptance_calls,
             self.config.max_instructions_for_message_acceptance_calls,
         );
-        let execution_parameters =
-            self.execution_parameters(canister_state, instruction_limits, execution_mode);
+        let execution_parameters = self.execution_parameters(
+            canister_state,
+            instruction_limits,
+            execution_mode,
+            ResourceSaturation::default(),
+        );
 
         // Letting the canister grow arbitrarily when executing the
         // query is fine as we do not persist state modifications.
         let subnet_available_memory = subnet_memory_capacity(&self.config);
 
-        inspect_message::execute_inspect_message(
+        let (_, result) = inspect_message::execute_inspect_message(
             state.time(),
             canister_state.clone(),
             ingress,
@@ -1484,8 +1764,9 @@ impl ExecutionEnvironment {
             &self.hypervisor,
             &state.metadata.network_topology,
             &self.log,
-        )
-        .1
+        );
+        self.metrics.observe_inspect_message(&result);
+        result
     }
 
     /// Execute a query call that has no caller provided.
@@ -1506,8 +1787,12 @@ impl ExecutionEnvironment {
             max_instructions_per_query,
             max_instructions_per_query,
         );
-        let execution_parameters =
-            self.execution_parameters(&canister, instruction_limits, ExecutionMode::NonReplicated);
+        let execution_parameters = self.execution_parameters(
+            &canister,
+            instruction_limits,
+            ExecutionMode::NonReplicated,
+            ResourceSaturation::default(),
+        );
         let subnet_available_memory = subnet_memory_capacity(&self.config);
         let mut round_limits = RoundLimits {
             instructions: as_round_instructions(max_instructions_per_query),
@@ -1569,9 +1854,10 @@ impl ExecutionEnvironment {
                     originator_reply_callback: req.sender_reply_callback,
                     refund,
                     response_payload: payload,
+                    deadline: req.deadline,
                 };
 
-                state.push_subnet_output_response(response.into());
+                self.push_subnet_output_response(&mut state, response);
                 state
             }
             RequestOrIngress::Ingress(ingress) => {
@@ -1582,7 +1868,7 @@ impl ExecutionEnvironment {
                         ingress.source, ingress.receiver, ingress.message_id
                     );
                 }
-                let status = match result {
+                let mut status = match result {
                     Ok(payload) => IngressStatus::Known {
                         receiver: ingress.receiver.get(),
                         user_id: ingress.source,
@@ -1596,6 +1882,7 @@ impl ExecutionEnvironment {
                         state: IngressState::Failed(err),
                     },
                 };
+                self.truncate_ingress_reject_message(&mut status);
 
                 self.ingress_history_writer.set_status(
                     &mut state,
@@ -1607,6 +1894,63 @@ impl ExecutionEnvironment {
         }
     }
 
+    // Pushes a response of the subnet to its output queue after truncating its
+    // reject message, if any.
+    fn push_subnet_output_response(&self, state: &mut ReplicatedState, mut response: Response) {
+        if let Payload::Reject(context) = &mut response.response_payload {
+            self.apply_reject_truncation_policy(&mut context.message, RejectOrigin::System);
+        }
+        state.push_subnet_output_response(response.into());
+    }
+
+    // Truncates the reject message of a response produced by a canister
+    // execution, if any. Rejects with any code other than `CanisterReject`
+    // come from the system, e.g. when the execution trapped.
+    fn truncate_reject_message(&self, response: &mut Response) {
+        if let Payload::Reject(context) = &mut response.response_payload {
+            let origin = match context.code {
+                RejectCode::CanisterReject => RejectOrigin::Canister,
+                _ => RejectOrigin::System,
+            };
+            self.apply_reject_truncation_policy(&mut context.message, origin);
+        }
+    }
+
+    // Truncates the reject message of an ingress status, if any. Rejects by
+    // `ic0.msg_reject` complete the ingress message and come from the
+    // canister, while failures come from the system.
+    fn truncate_ingress_reject_message(&self, status: &mut IngressStatus) {
+        match status {
+            IngressStatus::Known {
+                state: IngressState::Completed(WasmResult::Reject(message)),
+                ..
+            } => {
+                self.apply_reject_truncation_policy(message, RejectOrigin::Canister);
+            }
+            IngressStatus::Known {
+                state: IngressState::Failed(err),
+                ..
+            } => {
+                let mut description = err.description().to_string();
+                if self.apply_reject_truncation_policy(&mut description, RejectOrigin::System) {
+                    *err = UserError::new(err.code(), description);
+                }
+            }
+            IngressStatus::Known { .. } | IngressStatus::Unknown => {}
+        }
+    }
+
+    // Truncates the reject message if the truncation is enabled and accounts
+    // for its original length in the metrics. Returns whether the message was
+    // truncated.
+    fn apply_reject_truncation_policy(&self, message: &mut String, origin: RejectOrigin) -> bool {
+        let len = message.len();
+        let truncated = self.config.reject_message_truncation == FlagStatus::Enabled
+            && truncate_reject_message(message, self.config.max_reject_message_len_bytes);
+        self.metrics.observe_reject_message(origin, len, truncated);
+        truncated
+    }
+
     // Rejects pending stop requests with an error indicating the request has been
     // cancelled.
     fn reject_stop_requests(
@@ -1650,8 +1994,9 @@ impl ExecutionEnvironment {
                             code: RejectCode::CanisterReject,
                             message: format!("Canister {}'s stop request cancelled", canister_id),
                         }),
+                        deadline: NO_DEADLINE,
                     };
-                    state.push_subnet_output_response(response.into());
+                    self.push_subnet_output_response(state, response);
                 }
             }
         }
@@ -1855,7 +2200,23 @@ impl ExecutionEnvironment {
             state: &mut ReplicatedState,
         ) -> Result<(InstallCodeContext, CanisterState), UserError> {
             let payload = msg.method_payload();
-            let args = InstallCodeArgs::decode(payload).map_err(candid_error_to_user_error)?;
+            let args = match Ic00Method::from_str(msg.method_name()) {
+                Ok(Ic00Method::InstallChunkedCode) => {
+                    let args = InstallChunkedCodeArgs::decode(payload)
+                        .map_err(candid_error_to_user_error)?;
+                    let wasm_module = assemble_chunked_wasm_module(msg.sender(), &args, state)?;
+                    InstallCodeArgs::new(
+                        args.mode,
+                        args.get_target_canister(),
+                        wasm_module,
+                        args.arg,
+                        None,
+                        None,
+                        None,
+                    )
+                }
+                _ => InstallCodeArgs::decode(payload).map_err(candid_error_to_user_error)?,
+            };
             let install_context = InstallCodeContext::try_from((*msg.sender(), args))?;
             let canister = state
                 .take_canister_state(&install_context.canister_id)
@@ -1906,8 +2267,12 @@ impl ExecutionEnvironment {
             install_context.wasm_module.is_empty().to_string(),
         );
 
-        let execution_parameters =
-            self.execution_parameters(&old_canister, instruction_limits, ExecutionMode::Replicated);
+        let execution_parameters = self.execution_parameters(
+            &old_canister,
+            instruction_limits,
+            ExecutionMode::Replicated,
+            self.subnet_memory_saturation(round_limits),
+        );
 
         let dts_result = self.canister_manager.install_code_dts(
             install_context,
@@ -1949,9 +2314,11 @@ impl ExecutionEnvironment {
                 result,
             } => {
                 let canister_id = canister.canister_id();
+                let mut call_context_rejects = Vec::new();
                 let result = match result {
                     Ok(result) => {
                         state.metadata.heap_delta_estimate += result.heap_delta;
+                        call_context_rejects = result.call_context_rejects;
                         if let Some(new_wasm_hash) = result.new_wasm_hash {
                             state
                                 .metadata
@@ -1979,6 +2346,12 @@ impl ExecutionEnvironment {
                     }
                 };
                 state.put_canister_state(canister);
+                process_responses(
+                    call_context_rejects,
+                    &mut state,
+                    Arc::clone(&self.ingress_history_writer),
+                    self.log.clone(),
+                );
                 let refund = message.take_cycles();
                 let state =
                     self.finish_subnet_message_execution(state, message, result, refund, timer);
@@ -2042,6 +2415,7 @@ impl ExecutionEnvironment {
         match task {
             ExecutionTask::Heartbeat
             | ExecutionTask::GlobalTimer
+            | ExecutionTask::OnLowWasmMemory
             | ExecutionTask::PausedExecution(_)
             | ExecutionTask::AbortedExecution { .. } => {
                 panic!(
@@ -2082,8 +2456,11 @@ impl ExecutionEnvironment {
         }
     }
 
-    /// Returns the paused execution by its id.
-    fn take_paused_execution(&self, id: PausedExecutionId) -> Option<Box<dyn PausedExecution>> {
+    /// Returns the paused execution and its progress by its id.
+    fn take_paused_execution(
+        &self,
+        id: PausedExecutionId,
+    ) -> Option<(Box<dyn PausedExecution>, DtsProgress)> {
         let mut guard = self.paused_execution_registry.lock().unwrap();
         guard.paused_execution.remove(&id)
     }
@@ -2098,11 +2475,15 @@ impl ExecutionEnvironment {
     }
 
     /// Registers the given paused execution and returns its id.
-    fn register_paused_execution(&self, paused: Box<dyn PausedExecution>) -> PausedExecutionId {
+    fn register_paused_execution(
+        &self,
+        paused: Box<dyn PausedExecution>,
+        progress: DtsProgress,
+    ) -> PausedExecutionId {
         let mut guard = self.paused_execution_registry.lock().unwrap();
         let id = PausedExecutionId(guard.next_id);
         guard.next_id += 1;
-        guard.paused_execution.insert(id, paused);
+        guard.paused_execution.insert(id, (paused, progress));
         id
     }
 
@@ -2128,11 +2509,13 @@ impl ExecutionEnvironment {
                     ExecutionTask::AbortedExecution { .. }
                     | ExecutionTask::AbortedInstallCode { .. }
                     | ExecutionTask::Heartbeat
-                    | ExecutionTask::GlobalTimer => task,
+                    | ExecutionTask::GlobalTimer
+                    | ExecutionTask::OnLowWasmMemory => task,
                     ExecutionTask::PausedExecution(id) => {
-                        let paused = self.take_paused_execution(id).unwrap();
+                        let (paused, progress) = self.take_paused_execution(id).unwrap();
                         let (message, prepaid_execution_cycles) = paused.abort(log);
                         self.metrics.executions_aborted.inc();
+                        self.metrics.observe_dts_abort(progress.message_type);
                         ExecutionTask::AbortedExecution {
                             message,
                             prepaid_execution_cycles,
@@ -2169,9 +2552,15 @@ impl ExecutionEnvironment {
     /// If the given result corresponds to a finished execution, then it processes
     /// the response and return the ingress status (if any). Otherwise, it registers
     /// the paused execution and adds it to the task queue.
-    pub fn process_result(
+    ///
+    /// `progress` is the progress of the execution including the slice that
+    /// produced the result and `slice_instructions` are the instructions
+    /// executed in that slice.
+    fn process_result(
         &self,
         result: ExecuteMessageResult,
+        progress: DtsProgress,
+        slice_instructions: NumInstructions,
     ) -> (
         CanisterState,
         Option<NumInstructions>,
@@ -2185,14 +2574,24 @@ impl ExecutionEnvironment {
                 instructions_used,
                 heap_delta,
             } => {
+                self.metrics.observe_dts_slice(
+                    progress.message_type,
+                    slice_instructions,
+                    progress.slices,
+                    false,
+                );
                 let ingress_status = match response {
-                    ExecutionResponse::Ingress(ingress_status) => Some(ingress_status),
-                    ExecutionResponse::Request(response) => {
+                    ExecutionResponse::Ingress((message_id, mut status)) => {
+                        self.truncate_ingress_reject_message(&mut status);
+                        Some((message_id, status))
+                    }
+                    ExecutionResponse::Request(mut response) => {
                         debug_assert_eq!(
                             response.respondent,
                             canister.canister_id(),
                             "Respondent mismatch"
                         );
+                        self.truncate_reject_message(&mut response);
                         canister.push_output_response(response.into());
                         None
                     }
@@ -2210,7 +2609,13 @@ impl ExecutionEnvironment {
                 paused_execution,
                 ingress_status,
             } => {
-                let id = self.register_paused_execution(paused_execution);
+                self.metrics.observe_dts_slice(
+                    progress.message_type,
+                    slice_instructions,
+                    progress.slices,
+                    true,
+                );
+                let id = self.register_paused_execution(paused_execution, progress);
                 canister
                     .system_state
                     .task_queue
@@ -2324,6 +2729,8 @@ fn execute_message(
     subnet_size: usize,
 ) -> ExecuteCanisterResult {
     let msg_info = message.to_string();
+    let progress = DtsProgress::first_slice(&canister, &message);
+    let instructions_before = round_limits.instructions;
     let result = exec_env.execute_canister_message(
         canister,
         instruction_limits,
@@ -2335,7 +2742,9 @@ fn execute_message(
         round_limits,
         subnet_size,
     );
-    let (canister, instructions_used, heap_delta, ingress_status) = exec_env.process_result(result);
+    let slice_instructions = as_num_instructions(instructions_before - round_limits.instructions);
+    let (canister, instructions_used, heap_delta, ingress_status) =
+        exec_env.process_result(result, progress, slice_instructions);
     ExecuteCanisterResult {
         canister,
         instructions_used,
@@ -2345,6 +2754,71 @@ fn execute_message(
     }
 }
 
+/// The kind of execution that the instructions of a message are attributed to,
+/// see `ExecutedInstructions`.
+#[derive(Clone, Copy)]
+enum ExecutionKind {
+    Update,
+    Query,
+}
+
+/// Adds the instructions used by a finished execution to the instructions
+/// executed by the canister. Paused executions are accounted for once they
+/// finish.
+fn add_executed_instructions(
+    mut result: ExecuteMessageResult,
+    kind: ExecutionKind,
+) -> ExecuteMessageResult {
+    if let ExecuteMessageResult::Finished {
+        canister,
+        instructions_used,
+        ..
+    } = &mut result
+    {
+        let executed_instructions = &mut canister.scheduler_state.executed_instructions;
+        match kind {
+            ExecutionKind::Update => executed_instructions.update += *instructions_used,
+            ExecutionKind::Query => executed_instructions.query += *instructions_used,
+        }
+    }
+    result
+}
+
+/// Accounts for a query that executed in replicated mode in the query
+/// statistics of the canister.
+fn add_query_stats(mut result: ExecuteMessageResult, method_name: String) -> ExecuteMessageResult {
+    if let ExecuteMessageResult::Finished {
+        canister,
+        response,
+        instructions_used,
+        ..
+    } = &mut result
+    {
+        canister
+            .scheduler_state
+            .query_stats
+            .entry(method_name)
+            .or_default()
+            .observe(*instructions_used, egress_payload_size(response));
+    }
+    result
+}
+
+/// Returns the size of the reply or reject in the response of an execution.
+fn egress_payload_size(response: &ExecutionResponse) -> NumBytes {
+    match response {
+        ExecutionResponse::Ingress((
+            _,
+            IngressStatus::Known {
+                state: IngressState::Completed(result),
+                ..
+            },
+        )) => NumBytes::from(result.count_bytes() as u64),
+        ExecutionResponse::Request(response) => response.response_payload.size_bytes(),
+        ExecutionResponse::Ingress(_) | ExecutionResponse::Empty => NumBytes::from(0),
+    }
+}
+
 /// Executes either a single task from the task queue of the canister or a
 /// single input message if there is no task.
 pub fn execute_canister(
@@ -2426,8 +2900,36 @@ pub fn execute_canister(
                     description: Some("global timer".to_string()),
                 }
             }
+            ExecutionTask::OnLowWasmMemory => {
+                // The hook is expected to finish quickly, so DTS is not supported for it.
+                let instruction_limits = InstructionLimits::new(
+                    FlagStatus::Disabled,
+                    max_instructions_per_message_without_dts,
+                    max_instructions_per_message_without_dts,
+                );
+                // The hook runs once until the remaining Wasm memory recovers.
+                canister.system_state.on_low_wasm_memory_hook_executed = true;
+                let (canister, instructions_used, result) = exec_env.execute_canister_system_task(
+                    canister,
+                    SystemMethod::CanisterOnLowWasmMemory,
+                    instruction_limits,
+                    network_topology,
+                    time,
+                    round_limits,
+                    subnet_size,
+                    &exec_env.log,
+                );
+                let heap_delta = result.unwrap_or_else(|_| NumBytes::from(0));
+                ExecuteCanisterResult {
+                    canister,
+                    instructions_used: Some(instructions_used),
+                    heap_delta,
+                    ingress_status: None,
+                    description: Some("on low wasm memory".to_string()),
+                }
+            }
             ExecutionTask::PausedExecution(id) => {
-                let paused = exec_env.take_paused_execution(id).unwrap();
+                let (paused, progress) = exec_env.take_paused_execution(id).unwrap();
                 let round_context = RoundContext {
                     network_topology: &network_topology,
                     hypervisor: &exec_env.hypervisor,
@@ -2438,9 +2940,14 @@ pub fn execute_canister(
                     log: &exec_env.log,
                     time,
                 };
+                let instructions_before = round_limits.instructions;
                 let result = paused.resume(canister, round_context, round_limits, subnet_size);
+                let slice_instructions =
+                    as_num_instructions(instructions_before - round_limits.instructions);
+                // Only update calls and responses are executed with DTS.
+                let result = add_executed_instructions(result, ExecutionKind::Update);
                 let (canister, instructions_used, heap_delta, ingress_status) =
-                    exec_env.process_result(result);
+                    exec_env.process_result(result, progress.next_slice(), slice_instructions);
                 ExecuteCanisterResult {
                     canister,
                     instructions_used,
@@ -1,15 +1,18 @@
 use assert_matches::assert_matches;
 use candid::{Decode, Encode};
 
+use crate::canister_manager::STOP_CANISTER_FORCE_DEADLINE;
 use crate::execution::test_utilities::{
     assert_empty_reply, check_ingress_status, get_reply, ExecutionTest, ExecutionTestBuilder,
 };
 use ic_base_types::{NumBytes, NumSeconds};
+use ic_config::execution_environment::MAX_REJECT_MESSAGE_LEN_BYTES;
 use ic_error_types::{ErrorCode, RejectCode, UserError};
 use ic_ic00_types::{
-    self as ic00, CanisterHttpRequestArgs, CanisterIdRecord, CanisterStatusResultV2,
-    CanisterStatusType, EcdsaCurve, EcdsaKeyId, EmptyBlob, HttpMethod, Method,
-    Payload as Ic00Payload, ProvisionalCreateCanisterWithCyclesArgs, ProvisionalTopUpCanisterArgs,
+    self as ic00, BlockingCallContext, CanisterHttpRequestArgs, CanisterIdRecord,
+    CanisterStatusResultV2, CanisterStatusType, CanisterStoppingStatusResponse, EcdsaCurve,
+    EcdsaKeyId, EmptyBlob, HttpMethod, Method, Payload as Ic00Payload,
+    ProvisionalCreateCanisterWithCyclesArgs, ProvisionalTopUpCanisterArgs, StopCanisterArgs,
     TransformContext, TransformFunc, IC_00,
 };
 use ic_registry_routing_table::canister_id_into_u64;
@@ -18,21 +21,24 @@ use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::{
     canister_state::{DEFAULT_QUEUE_CAPACITY, WASM_PAGE_SIZE_IN_BYTES},
     testing::{CanisterQueuesTesting, SystemStateTesting},
-    CanisterStatus, SystemState,
+    wasm_chunk_hash, CanisterStatus, SystemState,
 };
 use ic_test_utilities::{assert_utils::assert_balance_equals, mock_time};
-use ic_test_utilities_metrics::{fetch_histogram_vec_count, metric_vec};
+use ic_test_utilities_metrics::{fetch_histogram_vec_count, fetch_int_counter_vec, metric_vec};
 use ic_types::canister_http::Transform;
 use ic_types::{
     canister_http::CanisterHttpMethod,
     ingress::{IngressState, IngressStatus, WasmResult},
     messages::{
-        CallbackId, Payload, RejectContext, RequestOrResponse, Response, MAX_RESPONSE_COUNT_BYTES,
+        CallbackId, MessageId, Payload, RejectContext, RequestOrResponse, Response, UserQuery,
+        MAX_RESPONSE_COUNT_BYTES, REJECT_MESSAGE_TRUNCATION_SUFFIX,
     },
+    time::NO_DEADLINE,
     CanisterId, Cycles, PrincipalId, RegistryVersion,
 };
 use ic_types_test_utils::ids::{canister_test_id, node_test_id, subnet_test_id, user_test_id};
 use ic_universal_canister::{call_args, wasm};
+use std::sync::Arc;
 
 #[cfg(test)]
 mod compilation;
@@ -446,6 +452,116 @@ fn response_callback_can_reject() {
     }
 }
 
+#[test]
+fn long_canister_reject_message_is_truncated() {
+    // Test scenario:
+    // 1. Canister A calls canister B.
+    // 2. Canister B rejects the call with a message above the length limit.
+    let mut test = ExecutionTestBuilder::new()
+        .with_manual_execution()
+        .with_reject_message_truncation()
+        .build();
+    let a_id = test.universal_canister().unwrap();
+    let b_id = test.universal_canister().unwrap();
+
+    let b = wasm().push_bytes(&[b'x'; 100_000]).reject().build();
+    let a = wasm()
+        .call_simple(b_id.get(), "update", call_args().other_side(b))
+        .build();
+
+    test.ingress_raw(a_id, "update", a);
+    test.execute_message(a_id);
+    test.induct_messages();
+    test.execute_message(b_id);
+
+    let message = test
+        .canister_state_mut(b_id)
+        .system_state
+        .queues_mut()
+        .pop_canister_output(&a_id)
+        .unwrap();
+    match message {
+        RequestOrResponse::Response(msg) => match &msg.response_payload {
+            Payload::Reject(context) => {
+                assert_eq!(RejectCode::CanisterReject, context.code);
+                assert_eq!(MAX_REJECT_MESSAGE_LEN_BYTES, context.message.len());
+                assert!(context.message.ends_with(REJECT_MESSAGE_TRUNCATION_SUFFIX));
+            }
+            Payload::Data(_) => panic!("expected a reject, got {:?}", msg),
+        },
+        RequestOrResponse::Request(_) => panic!("unexpected message popped: {:?}", message),
+    }
+    assert_eq!(
+        metric_vec(&[(&[("origin", "canister")], 1)]),
+        fetch_int_counter_vec(
+            test.metrics_registry(),
+            "execution_truncated_reject_messages_total"
+        )
+    );
+}
+
+#[test]
+fn long_ingress_reject_messages_are_truncated() {
+    let mut test = ExecutionTestBuilder::new()
+        .with_reject_message_truncation()
+        .build();
+    let canister_id = test.universal_canister().unwrap();
+    let long_message = [b'x'; 100_000];
+
+    // A reject by the canister completes the ingress message.
+    let reject = wasm().push_bytes(&long_message).reject().build();
+    match test.ingress(canister_id, "update", reject) {
+        Ok(WasmResult::Reject(message)) => {
+            assert_eq!(MAX_REJECT_MESSAGE_LEN_BYTES, message.len());
+            assert!(message.ends_with(REJECT_MESSAGE_TRUNCATION_SUFFIX));
+        }
+        result => panic!("expected a reject, got {:?}", result),
+    }
+
+    // A trap fails the ingress message with an error of the system.
+    let trap = wasm().trap_with_blob(&long_message).build();
+    let err = test.ingress(canister_id, "update", trap).unwrap_err();
+    assert_eq!(ErrorCode::CanisterCalledTrap, err.code());
+    assert_eq!(MAX_REJECT_MESSAGE_LEN_BYTES, err.description().len());
+    assert!(err.description().ends_with(REJECT_MESSAGE_TRUNCATION_SUFFIX));
+
+    // So does an ingress message to the management canister.
+    let method = String::from_utf8(long_message.to_vec()).unwrap();
+    let err = test.subnet_message(method, vec![]).unwrap_err();
+    assert_eq!(ErrorCode::CanisterMethodNotFound, err.code());
+    assert_eq!(MAX_REJECT_MESSAGE_LEN_BYTES, err.description().len());
+    assert!(err.description().ends_with(REJECT_MESSAGE_TRUNCATION_SUFFIX));
+
+    assert_eq!(
+        metric_vec(&[
+            (&[("origin", "canister")], 1),
+            (&[("origin", "system")], 2)
+        ]),
+        fetch_int_counter_vec(
+            test.metrics_registry(),
+            "execution_truncated_reject_messages_total"
+        )
+    );
+}
+
+#[test]
+fn reject_messages_are_not_truncated_by_default() {
+    let mut test = ExecutionTestBuilder::new().build();
+    let canister_id = test.universal_canister().unwrap();
+    let long_message = [b'x'; 100_000];
+
+    let reject = wasm().push_bytes(&long_message).reject().build();
+    match test.ingress(canister_id, "update", reject) {
+        Ok(WasmResult::Reject(message)) => assert_eq!(long_message.len(), message.len()),
+        result => panic!("expected a reject, got {:?}", result),
+    }
+    assert!(fetch_int_counter_vec(
+        test.metrics_registry(),
+        "execution_truncated_reject_messages_total"
+    )
+    .is_empty());
+}
+
 #[test]
 fn canister_cannot_reply_twice() {
     // Test scenario:
@@ -629,6 +745,109 @@ fn stopping_a_stopping_canister_does_not_update_ingress_history() {
     assert_eq!(ingress_status, IngressStatus::Unknown);
 }
 
+/// Makes canister A call canister B and stops A before B replies, so that the
+/// call context of the ingress message keeps A from stopping.
+fn stopping_canister_with_open_call_context(
+    test: &mut ExecutionTest,
+) -> (CanisterId, CanisterId, MessageId) {
+    let a_id = test.universal_canister().unwrap();
+    let b_id = test.universal_canister().unwrap();
+    let b = wasm().reply().build();
+    let a = wasm()
+        .call_simple(b_id.get(), "update", call_args().other_side(b))
+        .build();
+    let (ingress_id, _) = test.ingress_raw(a_id, "update", a);
+    test.execute_message(a_id);
+    test.stop_canister(a_id);
+    (a_id, b_id, ingress_id)
+}
+
+#[test]
+fn canister_status_reports_call_contexts_blocking_stop() {
+    let mut test = ExecutionTestBuilder::new().with_manual_execution().build();
+    let (a_id, b_id, _) = stopping_canister_with_open_call_context(&mut test);
+    let expected = vec![BlockingCallContext::new(
+        Some(test.user_id().get()),
+        // Executing the call bumped the time by one second.
+        Some(1),
+        vec![b_id.get()],
+    )];
+
+    let result = test.canister_status(a_id);
+    let status = CanisterStatusResultV2::decode(&get_reply(result)).unwrap();
+    assert_eq!(status.status(), CanisterStatusType::Stopping);
+    assert_eq!(status.blocking_call_contexts(), Some(expected.as_slice()));
+
+    let result = test.subnet_message(
+        Method::CanisterStoppingStatus,
+        CanisterIdRecord::from(a_id).encode(),
+    );
+    let response = CanisterStoppingStatusResponse::decode(&get_reply(result)).unwrap();
+    assert_eq!(
+        response,
+        CanisterStoppingStatusResponse {
+            status: CanisterStatusType::Stopping,
+            blocking_call_contexts: expected,
+        }
+    );
+}
+
+#[test]
+fn canister_status_of_running_canister_has_no_blocking_call_contexts() {
+    let mut test = ExecutionTestBuilder::new().with_manual_execution().build();
+    let canister_id = test.universal_canister().unwrap();
+    let result = test.canister_status(canister_id);
+    let status = CanisterStatusResultV2::decode(&get_reply(result)).unwrap();
+    assert_eq!(status.blocking_call_contexts(), None);
+}
+
+#[test]
+fn force_stop_fails_before_deadline() {
+    let mut test = ExecutionTestBuilder::new().with_manual_execution().build();
+    let (a_id, _, ingress_id) = stopping_canister_with_open_call_context(&mut test);
+    let err = test
+        .subnet_message(
+            Method::StopCanister,
+            StopCanisterArgs::new(a_id, Some(true)).encode(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::CanisterNotStopped);
+    assert_eq!(test.ingress_status(&ingress_id), IngressStatus::Unknown);
+    test.process_stopping_canisters();
+    assert_eq!(
+        test.canister_state(a_id).status(),
+        CanisterStatusType::Stopping
+    );
+}
+
+#[test]
+fn force_stop_rejects_blocking_call_contexts_after_deadline() {
+    let mut test = ExecutionTestBuilder::new().with_manual_execution().build();
+    let (a_id, _, ingress_id) = stopping_canister_with_open_call_context(&mut test);
+    test.state_mut().metadata.batch_time += STOP_CANISTER_FORCE_DEADLINE;
+    let stop_id = test.subnet_message_raw(
+        Method::StopCanister,
+        StopCanisterArgs::new(a_id, Some(true)).encode(),
+    );
+    test.execute_subnet_message();
+    assert_eq!(
+        test.ingress_state(&ingress_id),
+        IngressState::Failed(UserError::new(
+            ErrorCode::CanisterCallContextDeleted,
+            "Canister has been stopped forcefully."
+        ))
+    );
+    test.process_stopping_canisters();
+    assert_eq!(
+        test.canister_state(a_id).status(),
+        CanisterStatusType::Stopped
+    );
+    assert_eq!(
+        test.ingress_state(&stop_id),
+        IngressState::Completed(WasmResult::Reply(EmptyBlob.encode()))
+    );
+}
+
 #[test]
 fn stopping_a_canister_with_incorrect_controller_fails() {
     let mut test = ExecutionTestBuilder::new().with_manual_execution().build();
@@ -686,12 +905,150 @@ fn get_running_canister_status_from_another_canister() {
         csr.memory_size(),
         test.execution_state(canister).memory_usage()
     );
+    let memory_metrics = csr.memory_metrics().unwrap();
+    let memory_breakdown = test.canister_state(canister).memory_breakdown();
+    assert_eq!(memory_metrics.wasm_memory_size(), memory_breakdown.wasm_memory);
+    assert_eq!(memory_metrics.wasm_binary_size(), memory_breakdown.wasm_binary);
+    assert_eq!(
+        memory_metrics.wasm_memory_size()
+            + memory_metrics.stable_memory_size()
+            + memory_metrics.global_memory_size()
+            + memory_metrics.wasm_binary_size(),
+        csr.memory_size()
+    );
     assert_eq!(
         Cycles::new(csr.idle_cycles_burned_per_day()),
         test.idle_cycles_burned_per_day(canister)
     );
 }
 
+#[test]
+fn get_canister_status_reports_executed_instructions() {
+    let mut test = ExecutionTestBuilder::new().build();
+    let controller = test.universal_canister().unwrap();
+    let canister = test.universal_canister().unwrap();
+    test.set_controller(canister, controller.get()).unwrap();
+    test.ingress(canister, "update", wasm().reply().build()).unwrap();
+    let executed_instructions = test.canister_state(canister).scheduler_state.executed_instructions;
+    assert!(executed_instructions.update.get() > 0);
+    assert_eq!(executed_instructions.query.get(), 0);
+
+    let canister_status_args = Encode!(&CanisterIdRecord::from(canister)).unwrap();
+    let get_canister_status = wasm()
+        .call_simple(
+            ic00::IC_00,
+            Method::CanisterStatus,
+            call_args().other_side(canister_status_args),
+        )
+        .build();
+    let result = test.ingress(controller, "update", get_canister_status);
+    let reply = get_reply(result);
+    let csr = CanisterStatusResultV2::decode(&reply).unwrap();
+    let instruction_metrics = csr.instruction_metrics().unwrap();
+    assert_eq!(
+        instruction_metrics.update_instructions_executed(),
+        executed_instructions.update.get()
+    );
+    assert_eq!(instruction_metrics.query_instructions_executed(), 0);
+    assert_eq!(instruction_metrics.system_task_instructions_executed(), 0);
+}
+
+#[test]
+fn get_canister_status_reports_query_stats() {
+    let mut test = ExecutionTestBuilder::new().build();
+    let controller = test.universal_canister().unwrap();
+    let canister = test.universal_canister().unwrap();
+    test.set_controller(canister, controller.get()).unwrap();
+    for _ in 0..2 {
+        let result = test.ingress(canister, "query", wasm().reply_data(b"abc").build());
+        assert_eq!(get_reply(result), b"abc".to_vec());
+    }
+    // Queries in non-replicated mode run on a single replica and are not
+    // part of the statistics.
+    let output = test.query(
+        UserQuery {
+            source: user_test_id(2),
+            receiver: canister,
+            method_name: "query".to_string(),
+            method_payload: wasm().reply_data(b"abc").build(),
+            ingress_expiry: 0,
+            nonce: None,
+        },
+        Arc::new(test.state().clone()),
+        vec![],
+    );
+    assert_eq!(output, Ok(WasmResult::Reply(b"abc".to_vec())));
+    let scheduler_state = &test.canister_state(canister).scheduler_state;
+    let query_stats = scheduler_state.query_stats["query"];
+    assert_eq!(query_stats.num_calls, 2);
+    assert_eq!(query_stats.num_instructions, scheduler_state.executed_instructions.query);
+    assert_eq!(query_stats.egress_payload_size, NumBytes::from(6));
+
+    let canister_status_args = Encode!(&CanisterIdRecord::from(canister)).unwrap();
+    let get_canister_status = wasm()
+        .call_simple(
+            ic00::IC_00,
+            Method::CanisterStatus,
+            call_args().other_side(canister_status_args),
+        )
+        .build();
+    let result = test.ingress(controller, "update", get_canister_status);
+    let reply = get_reply(result);
+    let csr = CanisterStatusResultV2::decode(&reply).unwrap();
+    let reported_stats = csr.query_stats().unwrap();
+    assert_eq!(reported_stats.len(), 1);
+    assert_eq!(reported_stats[0].method_name(), "query");
+    assert_eq!(reported_stats[0].num_calls_total(), 2);
+    assert_eq!(
+        reported_stats[0].num_instructions_total(),
+        query_stats.num_instructions.get()
+    );
+    assert_eq!(reported_stats[0].response_payload_bytes_total(), 6);
+}
+
+#[test]
+fn get_canister_status_reports_wasm_memory_limit() {
+    let mut test = ExecutionTestBuilder::new().build();
+    let canister = test.universal_canister().unwrap();
+    let reply = get_reply(test.canister_status(canister));
+    let csr = CanisterStatusResultV2::decode(&reply).unwrap();
+    assert_eq!(csr.wasm_memory_limit(), None);
+
+    test.update_wasm_memory_limit(canister, NumBytes::from(1 << 30)).unwrap();
+    let reply = get_reply(test.canister_status(canister));
+    let csr = CanisterStatusResultV2::decode(&reply).unwrap();
+    assert_eq!(csr.wasm_memory_limit(), Some(1 << 30));
+}
+
+#[test]
+fn query_instruction_limit_does_not_apply_to_updates() {
+    let mut test = ExecutionTestBuilder::new()
+        .with_max_instructions_per_query(1_000)
+        .build();
+    let canister = test.universal_canister().unwrap();
+
+    let result = test.ingress(canister, "update", wasm().reply().build());
+    assert_eq!(result, Ok(WasmResult::Reply(vec![])));
+
+    let err = test
+        .ingress(canister, "query", wasm().reply().build())
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::CanisterInstructionLimitExceeded);
+
+    let query = UserQuery {
+        source: user_test_id(1),
+        receiver: canister,
+        method_name: "query".to_string(),
+        method_payload: wasm().reply().build(),
+        ingress_expiry: 0,
+        nonce: None,
+    };
+    let err = test
+        .query(query, Arc::new(test.state().clone()), vec![])
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::CanisterInstructionLimitExceeded);
+}
+
 #[test]
 fn get_canister_status_from_another_canister_when_memory_low() {
     let mut test = ExecutionTestBuilder::new().build();
@@ -1088,7 +1445,8 @@ fn setup_initial_dkg_sender_not_on_nns() {
                     ic00::Method::SetupInitialDKG,
                     other_canister,
                 )
-            })
+            }),
+            deadline: NO_DEADLINE,
         }
         .into()
     );
@@ -1264,6 +1622,33 @@ fn message_to_canister_with_enough_balance_is_accepted() {
     assert_eq!(Ok(()), result);
 }
 
+#[test]
+fn message_rejected_in_inspect_message_reports_reject_message() {
+    let mut test = ExecutionTestBuilder::new().build();
+    let wat = r#"(module
+        (import "ic0" "msg_reject" (func $msg_reject (param i32 i32)))
+        (func (export "canister_inspect_message")
+            (call $msg_reject (i32.const 0) (i32.const 11))
+        )
+        (func (export "canister_update update"))
+        (memory 1)
+        (data (i32.const 0) "not welcome")
+    )"#;
+    let canister = test.canister_from_wat(wat).unwrap();
+    let err = test
+        .should_accept_ingress_message(canister, "update", vec![])
+        .unwrap_err();
+    assert_eq!(ErrorCode::CanisterRejectedMessage, err.code());
+    assert_eq!(
+        format!("Canister {} rejected the message: not welcome", canister),
+        err.description()
+    );
+    assert_eq!(
+        metric_vec(&[(&[("status", "CanisterRejectedMessage")], 1)]),
+        fetch_int_counter_vec(test.metrics_registry(), "execution_inspect_message_total")
+    );
+}
+
 #[test]
 fn management_message_to_canister_with_enough_balance_is_accepted() {
     let mut test = ExecutionTestBuilder::new().build();
@@ -2125,3 +2510,214 @@ fn bitcoin_get_successors_cannot_be_called_by_non_bitcoin_canisters() {
     let result = test.ingress(uni, "update", call).unwrap();
     assert_eq!(result, WasmResult::Reject("Permission denied.".to_string()));
 }
+
+#[test]
+fn install_chunked_code_installs_module_from_uploaded_chunks() {
+    let mut test = ExecutionTestBuilder::new().build();
+    let canister_id = test.create_canister(Cycles::new(1_000_000_000_000));
+    let wasm = wabt::wat2wasm(CALL_SIMPLE_WAT).unwrap();
+    let (first, second) = wasm.split_at(wasm.len() / 2);
+
+    let mut hashes = vec![];
+    for chunk in [first, second] {
+        let reply = get_reply(test.upload_chunk(canister_id, chunk.to_vec()));
+        let hash = ic00::ChunkHash::decode(&reply).unwrap().hash;
+        assert_eq!(hash, wasm_chunk_hash(chunk).to_vec());
+        hashes.push(hash);
+    }
+    assert_eq!(
+        test.canister_state(canister_id)
+            .memory_breakdown()
+            .wasm_chunk_store,
+        NumBytes::from(wasm.len() as u64)
+    );
+
+    let reply = get_reply(test.subnet_message(
+        Method::StoredChunks,
+        CanisterIdRecord::from(canister_id).encode(),
+    ));
+    let stored = ic00::StoredChunksReply::decode(&reply).unwrap();
+    assert_eq!(stored.0.len(), 2);
+
+    let args = ic00::InstallChunkedCodeArgs::new(
+        ic00::CanisterInstallMode::Install,
+        canister_id,
+        None,
+        hashes,
+        wasm_chunk_hash(&wasm).to_vec(),
+        vec![],
+    );
+    assert_eq!(
+        get_reply(test.install_chunked_code(args)),
+        EmptyBlob.encode()
+    );
+    assert_eq!(
+        test.execution_state(canister_id)
+            .wasm_binary
+            .binary
+            .module_hash(),
+        wasm_chunk_hash(&wasm)
+    );
+}
+
+#[test]
+fn install_chunked_code_fails_on_module_hash_mismatch() {
+    let mut test = ExecutionTestBuilder::new().build();
+    let canister_id = test.create_canister(Cycles::new(1_000_000_000_000));
+    let wasm = wabt::wat2wasm("(module)").unwrap();
+    let reply = get_reply(test.upload_chunk(canister_id, wasm.clone()));
+    let hash = ic00::ChunkHash::decode(&reply).unwrap().hash;
+
+    let args = ic00::InstallChunkedCodeArgs::new(
+        ic00::CanisterInstallMode::Install,
+        canister_id,
+        None,
+        vec![hash],
+        vec![0; 32],
+        vec![],
+    );
+    let err = test.install_chunked_code(args).unwrap_err();
+    assert_eq!(err.code(), ErrorCode::CanisterContractViolation);
+    assert!(test.canister_state(canister_id).execution_state.is_none());
+}
+
+#[test]
+fn upload_chunk_respects_memory_allocation() {
+    let mut test = ExecutionTestBuilder::new().build();
+    let canister_id = test
+        .create_canister_with_allocation(Cycles::new(1_000_000_000_000), None, Some(1000))
+        .unwrap();
+
+    let err = test.upload_chunk(canister_id, vec![1; 1001]).unwrap_err();
+    assert_eq!(err.code(), ErrorCode::CanisterOutOfMemory);
+    assert!(test
+        .canister_state(canister_id)
+        .system_state
+        .wasm_chunk_store
+        .is_empty());
+
+    get_reply(test.upload_chunk(canister_id, vec![1; 1000]));
+}
+
+#[test]
+fn uninstall_code_and_clear_chunk_store_drop_chunks() {
+    let mut test = ExecutionTestBuilder::new()
+        .with_subnet_total_memory(ONE_GIB)
+        .build();
+    let canister_id = test.create_canister(Cycles::new(1_000_000_000_000));
+    let initial_memory = test.subnet_available_memory().get_total_memory();
+
+    get_reply(test.upload_chunk(canister_id, vec![1; 1000]));
+    assert_eq!(
+        test.subnet_available_memory().get_total_memory(),
+        initial_memory - 1000
+    );
+
+    let payload = CanisterIdRecord::from(canister_id).encode();
+    assert_eq!(
+        get_reply(test.subnet_message(Method::ClearChunkStore, payload)),
+        EmptyBlob.encode()
+    );
+    assert_eq!(
+        test.subnet_available_memory().get_total_memory(),
+        initial_memory
+    );
+
+    get_reply(test.upload_chunk(canister_id, vec![2; 1000]));
+    test.uninstall_code(canister_id).unwrap();
+    assert!(test
+        .canister_state(canister_id)
+        .system_state
+        .wasm_chunk_store
+        .is_empty());
+}
+
+const DEBUG_PRINT_WAT: &str = r#"
+    (module
+        (import "ic0" "debug_print" (func $debug_print (param i32 i32)))
+        (import "ic0" "trap" (func $trap (param i32 i32)))
+        (import "ic0" "msg_reply" (func $msg_reply))
+        (func (export "canister_update print")
+            (call $debug_print (i32.const 0) (i32.const 5))
+            (call $msg_reply)
+        )
+        (func (export "canister_update trap")
+            (call $debug_print (i32.const 0) (i32.const 5))
+            (call $trap (i32.const 5) (i32.const 4))
+        )
+        (memory 1)
+        (data (i32.const 0) "helloboom")
+    )"#;
+
+fn fetch_canister_logs(
+    test: &mut ExecutionTest,
+    canister_id: CanisterId,
+) -> Result<Vec<ic00::CanisterLogRecord>, UserError> {
+    let payload = CanisterIdRecord::from(canister_id).encode();
+    test.subnet_message(Method::FetchCanisterLogs, payload)
+        .map(|result| match result {
+            WasmResult::Reply(reply) => {
+                ic00::FetchCanisterLogsResponse::decode(&reply)
+                    .unwrap()
+                    .canister_log_records
+            }
+            WasmResult::Reject(msg) => panic!("unexpected reject: {}", msg),
+        })
+}
+
+#[test]
+fn fetch_canister_logs_returns_debug_prints_and_trap_messages() {
+    let mut test = ExecutionTestBuilder::new().build();
+    let canister_id = test.canister_from_wat(DEBUG_PRINT_WAT).unwrap();
+
+    test.ingress(canister_id, "print", vec![]).unwrap();
+    let err = test.ingress(canister_id, "trap", vec![]).unwrap_err();
+    assert_eq!(err.code(), ErrorCode::CanisterCalledTrap);
+
+    let records = fetch_canister_logs(&mut test, canister_id).unwrap();
+    let contents: Vec<_> = records
+        .iter()
+        .map(|record| (record.idx, record.content.clone()))
+        .collect();
+    assert_eq!(
+        contents,
+        vec![
+            (0, b"hello".to_vec()),
+            (1, b"hello".to_vec()),
+            (2, b"[TRAP]: boom".to_vec()),
+        ]
+    );
+    let time = test.time().as_nanos_since_unix_epoch();
+    assert!(records.iter().all(|record| record.timestamp_nanos == time));
+}
+
+#[test]
+fn fetch_canister_logs_respects_log_visibility() {
+    let mut test = ExecutionTestBuilder::new().build();
+    let canister_id = test.canister_from_wat(DEBUG_PRINT_WAT).unwrap();
+    test.ingress(canister_id, "print", vec![]).unwrap();
+    let controller = test.user_id();
+
+    test.set_user_id(user_test_id(13));
+    let err = fetch_canister_logs(&mut test, canister_id).unwrap_err();
+    assert_eq!(err.code(), ErrorCode::CanisterInvalidController);
+
+    test.set_user_id(controller);
+    let payload = ic00::UpdateSettingsArgs {
+        canister_id: canister_id.into(),
+        settings: ic00::CanisterSettingsArgs {
+            log_visibility: Some(ic00::LogVisibility::Public),
+            ..Default::default()
+        },
+    }
+    .encode();
+    test.subnet_message(Method::UpdateSettings, payload).unwrap();
+    assert_eq!(
+        test.canister_state(canister_id).system_state.log_visibility,
+        ic00::LogVisibility::Public
+    );
+
+    test.set_user_id(user_test_id(13));
+    let records = fetch_canister_logs(&mut test, canister_id).unwrap();
+    assert_eq!(records.len(), 1);
+}
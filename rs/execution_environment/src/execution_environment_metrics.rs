@@ -1,11 +1,13 @@
 use ic_cycles_account_manager::{
     CRITICAL_ERROR_EXECUTION_CYCLES_REFUND, CRITICAL_ERROR_RESPONSE_CYCLES_REFUND,
 };
-use ic_error_types::ErrorCode;
+use crate::metrics::instructions_buckets;
+use ic_error_types::{ErrorCode, UserError};
 use ic_ic00_types as ic00;
 use ic_metrics::buckets::decimal_buckets;
 use ic_metrics::MetricsRegistry;
-use prometheus::{HistogramVec, IntCounter};
+use ic_types::{NumInstructions, NumSlices};
+use prometheus::{HistogramVec, IntCounter, IntCounterVec};
 use std::str::FromStr;
 
 pub const FINISHED_OUTCOME_LABEL: &str = "finished";
@@ -13,6 +15,45 @@ pub const SUBMITTED_OUTCOME_LABEL: &str = "submitted";
 pub const ERROR_OUTCOME_LABEL: &str = "error";
 pub const SUCCESS_STATUS_LABEL: &str = "success";
 
+/// The type of an executed canister message, used to label the metrics of
+/// deterministic time slicing. Replicated queries always finish in a single
+/// slice.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DtsMessageType {
+    ReplicatedQuery,
+    Update,
+    Response,
+}
+
+impl DtsMessageType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DtsMessageType::ReplicatedQuery => "replicated_query",
+            DtsMessageType::Update => "update",
+            DtsMessageType::Response => "response",
+        }
+    }
+}
+
+/// The producer of a reject message, used to label the metrics of reject
+/// messages. Canisters produce rejects with `ic0.msg_reject`; all other
+/// rejects, e.g. of trapped executions or of subnet messages, are produced by
+/// the system.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RejectOrigin {
+    Canister,
+    System,
+}
+
+impl RejectOrigin {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RejectOrigin::Canister => "canister",
+            RejectOrigin::System => "system",
+        }
+    }
+}
+
 /// Metrics used to monitor the performance of the execution environment.
 pub(crate) struct ExecutionEnvironmentMetrics {
     subnet_messages: HistogramVec,
@@ -22,6 +63,20 @@ pub(crate) struct ExecutionEnvironmentMetrics {
     /// Critical error for executions above the maximum allowed size.
     execution_cycles_refund_error: IntCounter,
     pub executions_aborted: IntCounter,
+    /// The executions of `canister_inspect_message` by status.
+    inspect_message_outcomes: IntCounterVec,
+    /// The number of executions paused at the end of a slice by message type.
+    dts_paused_executions: IntCounterVec,
+    /// The number of aborted paused executions by message type.
+    dts_aborted_executions: IntCounterVec,
+    /// The number of slices that messages took to finish by message type.
+    dts_message_slices: HistogramVec,
+    /// The number of instructions executed per slice by message type.
+    dts_slice_instructions: HistogramVec,
+    /// The length of reject messages before truncation by origin.
+    reject_message_len: HistogramVec,
+    /// The number of truncated reject messages by origin.
+    truncated_reject_messages: IntCounterVec,
 }
 
 impl ExecutionEnvironmentMetrics {
@@ -43,6 +98,46 @@ impl ExecutionEnvironmentMetrics {
                 .error_counter(CRITICAL_ERROR_EXECUTION_CYCLES_REFUND),
             executions_aborted: metrics_registry
                 .int_counter("executions_aborted", "Total number of aborted executios"),
+            inspect_message_outcomes: metrics_registry.int_counter_vec(
+                "execution_inspect_message_total",
+                "The number of executions of canister_inspect_message by status.",
+                &["status"],
+            ),
+            dts_paused_executions: metrics_registry.int_counter_vec(
+                "execution_dts_paused_executions_total",
+                "The number of message executions paused at the end of a slice by message type.",
+                &["message_type"],
+            ),
+            dts_aborted_executions: metrics_registry.int_counter_vec(
+                "execution_dts_aborted_executions_total",
+                "The number of paused message executions that were aborted by message type.",
+                &["message_type"],
+            ),
+            dts_message_slices: metrics_registry.histogram_vec(
+                "execution_dts_message_slices",
+                "The number of slices that a message execution took to finish by message type.",
+                // Buckets: 1, 2, 5, ..., 1000, 2000, 5000
+                decimal_buckets(0, 3),
+                &["message_type"],
+            ),
+            dts_slice_instructions: metrics_registry.histogram_vec(
+                "execution_dts_slice_instructions",
+                "The number of instructions executed in a slice by message type.",
+                instructions_buckets(),
+                &["message_type"],
+            ),
+            reject_message_len: metrics_registry.histogram_vec(
+                "execution_reject_message_len_bytes",
+                "The length of reject messages before truncation by origin.",
+                // Buckets: 10B, 20B, 50B, ..., 1MB, 2MB, 5MB
+                decimal_buckets(1, 6),
+                &["origin"],
+            ),
+            truncated_reject_messages: metrics_registry.int_counter_vec(
+                "execution_truncated_reject_messages_total",
+                "The number of reject messages that were truncated by origin.",
+                &["origin"],
+            ),
         }
     }
 
@@ -104,6 +199,65 @@ impl ExecutionEnvironmentMetrics {
             .observe(duration);
     }
 
+    /// Observes the outcome of a `canister_inspect_message` execution. The
+    /// status is `success` if the canister accepted the message and the
+    /// error code otherwise, e.g. `CanisterRejectedMessage`.
+    pub fn observe_inspect_message(&self, result: &Result<(), UserError>) {
+        let status_label = match result {
+            Ok(()) => SUCCESS_STATUS_LABEL.to_string(),
+            Err(err) => format!("{:?}", err.code()),
+        };
+        self.inspect_message_outcomes
+            .with_label_values(&[&status_label])
+            .inc();
+    }
+
+    /// Observes a slice of a message execution that executed `instructions`
+    /// and either paused or finished the execution. `slices` is the number of
+    /// slices that the execution took so far, including this one.
+    pub(crate) fn observe_dts_slice(
+        &self,
+        message_type: DtsMessageType,
+        instructions: NumInstructions,
+        slices: NumSlices,
+        paused: bool,
+    ) {
+        let message_type = message_type.as_str();
+        self.dts_slice_instructions
+            .with_label_values(&[message_type])
+            .observe(instructions.get() as f64);
+        if paused {
+            self.dts_paused_executions
+                .with_label_values(&[message_type])
+                .inc();
+        } else {
+            self.dts_message_slices
+                .with_label_values(&[message_type])
+                .observe(slices.get() as f64);
+        }
+    }
+
+    /// Observes the abort of a paused message execution.
+    pub(crate) fn observe_dts_abort(&self, message_type: DtsMessageType) {
+        self.dts_aborted_executions
+            .with_label_values(&[message_type.as_str()])
+            .inc();
+    }
+
+    /// Observes a reject message of `len` bytes produced by `origin`, before
+    /// it was truncated if `truncated` is set.
+    pub(crate) fn observe_reject_message(&self, origin: RejectOrigin, len: usize, truncated: bool) {
+        let origin = origin.as_str();
+        self.reject_message_len
+            .with_label_values(&[origin])
+            .observe(len as f64);
+        if truncated {
+            self.truncated_reject_messages
+                .with_label_values(&[origin])
+                .inc();
+        }
+    }
+
     pub fn response_cycles_refund_error_counter(&self) -> &IntCounter {
         &self.response_cycles_refund_error
     }
@@ -8,7 +8,7 @@ use ic_logger::{fatal, ReplicaLogger};
 use ic_metrics::{buckets::decimal_buckets, MetricsRegistry, Timer};
 use ic_replicated_state::ReplicatedState;
 use ic_types::{ingress::IngressState, ingress::IngressStatus, messages::MessageId, Height, Time};
-use prometheus::{Histogram, HistogramVec};
+use prometheus::{Histogram, HistogramVec, IntCounter};
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
@@ -83,6 +83,8 @@ pub struct IngressHistoryWriterImpl {
     message_state_transition_completed_wall_clock_duration_seconds: Histogram,
     message_state_transition_failed_ic_duration_seconds: HistogramVec,
     message_state_transition_failed_wall_clock_duration_seconds: HistogramVec,
+    ingress_history_forgotten_statuses: IntCounter,
+    ingress_history_forgotten_bytes: IntCounter,
 }
 
 impl IngressHistoryWriterImpl {
@@ -124,7 +126,15 @@ impl IngressHistoryWriterImpl {
                 // The `user_error_code` label is internal information that provides more
                 // detail about the reason for rejection.
                 &["reject_code", "user_error_code"],
-            )
+            ),
+            ingress_history_forgotten_statuses: metrics_registry.int_counter(
+                "ingress_history_forgotten_statuses_total",
+                "The number of Completed and Failed statuses transitioned to Done to keep the ingress history within its memory capacity",
+            ),
+            ingress_history_forgotten_bytes: metrics_registry.int_counter(
+                "ingress_history_forgotten_bytes_total",
+                "The number of bytes freed by transitioning Completed and Failed statuses to Done to keep the ingress history within its memory capacity",
+            ),
         }
     }
 }
@@ -215,11 +225,13 @@ impl IngressHistoryWriter for IngressHistoryWriterImpl {
             _ => {}
         };
 
-        state.set_ingress_status(
+        let eviction_stats = state.set_ingress_status(
             message_id,
             status,
             self.config.ingress_history_memory_capacity,
         );
+        self.ingress_history_forgotten_statuses.inc_by(eviction_stats.forgotten_statuses as u64);
+        self.ingress_history_forgotten_bytes.inc_by(eviction_stats.freed_bytes.get());
     }
 }
 
@@ -293,6 +305,16 @@ fn dashboard_label_value_from(code: ErrorCode) -> &'static str {
         QueryCallGraphTooDeep => "Query call graph contains too many nested calls",
         QueryCallGraphTotalInstructionLimitExceeded => "Total instructions limit exceeded for query call graph",
         CompositeQueryCalledInReplicatedMode => "Composite query cannot be called in replicated mode",
+        ReservedCyclesLimitExceededInMemoryGrow => {
+            "Canister cannot grow memory because the cycles to reserve exceed its reserved cycles limit"
+        }
+        InsufficientCyclesInMemoryGrow => {
+            "Canister cannot grow memory because it does not have enough cycles to reserve"
+        }
+        CanisterWasmMemoryLimitExceeded => "Canister exceeded its Wasm memory limit",
         CanisterNotHostedBySubnet => "Canister is not hosted by subnet",
+        CanisterCallContextDeleted => {
+            "Canister was uninstalled or reinstalled before it responded to the call"
+        }
     }
 }
@@ -1,19 +1,23 @@
 use ic_canister_sandbox_replica_controller::sandboxed_execution_controller::SandboxedExecutionController;
 use ic_config::flag_status::FlagStatus;
 use ic_config::{embedders::Config as EmbeddersConfig, execution_environment::Config};
+use ic_constants::SMALL_APP_SUBNET_MAX_SIZE;
 use ic_cycles_account_manager::CyclesAccountManager;
 use ic_embedders::wasm_executor::{WasmExecutionResult, WasmExecutor};
 use ic_embedders::wasm_utils::decoding::decoded_wasm_size;
 use ic_embedders::{wasm_executor::WasmExecutorImpl, WasmExecutionInput, WasmtimeEmbedder};
 use ic_embedders::{CompilationCache, CompilationResult};
-use ic_interfaces::execution_environment::{HypervisorResult, WasmExecutionOutput};
+use ic_interfaces::execution_environment::{
+    ExecutionMode, HypervisorError, HypervisorResult, WasmExecutionOutput,
+};
 use ic_logger::{fatal, ReplicaLogger};
 use ic_metrics::buckets::decimal_buckets_with_zero;
 use ic_metrics::{buckets::exponential_buckets, MetricsRegistry};
 use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::NetworkTopology;
 use ic_replicated_state::{
-    page_map::allocated_pages_count, CanisterState, ExecutionState, SchedulerState, SystemState,
+    page_map::{page_allocator_stats, BackingFileBackend},
+    CanisterState, ExecutionState, SchedulerState, SystemState,
 };
 use ic_sys::PAGE_SIZE;
 use ic_system_api::ExecutionParameters;
@@ -22,20 +26,35 @@ use ic_types::{
     ingress::WasmResult, methods::FuncRef, CanisterId, NumBytes, NumInstructions, SubnetId, Time,
 };
 use ic_wasm_types::CanisterModule;
-use prometheus::{Histogram, IntCounterVec, IntGauge};
+use prometheus::{Histogram, IntCounter, IntCounterVec, IntGauge, IntGaugeVec};
 use std::{path::PathBuf, sync::Arc};
 
+use crate::chaos::{Chaos, ChaosFault};
 use crate::execution::common::{apply_canister_state_changes, update_round_limits};
 use crate::execution_environment::{as_round_instructions, CompilationCostHandling, RoundLimits};
 
 #[cfg(test)]
 mod tests;
 
+// Increments the counter to the given total of a statistic that only grows.
+fn inc_to(counter: &IntCounter, total: usize) {
+    counter.inc_by((total as u64).saturating_sub(counter.get()));
+}
+
 #[doc(hidden)] // pub for usage in tests
 pub struct HypervisorMetrics {
     accessed_pages: Histogram,
     dirty_pages: Histogram,
     allocated_pages: IntGauge,
+    freed_pages: IntGauge,
+    page_allocator_file_bytes: IntGauge,
+    page_allocator_mmap_chunks: IntGauge,
+    page_validation_failures: IntCounter,
+    page_checksum_mismatches: IntCounter,
+    deduplicated_pages: IntGauge,
+    page_allocator_backend: IntGaugeVec,
+    page_allocator_directory_fallbacks: IntGauge,
+    quarantined_pages: IntCounter,
     executed_messages: IntCounterVec,
     largest_function_instruction_count: Histogram,
     compile: Histogram,
@@ -60,6 +79,43 @@ impl HypervisorMetrics {
                 "hypervisor_allocated_pages",
                 "Total number of currently allocated pages.",
             ),
+            freed_pages: metrics_registry.int_gauge(
+                "hypervisor_freed_pages",
+                "Total number of dropped pages returned to the OS since the replica started.",
+            ),
+            page_allocator_file_bytes: metrics_registry.int_gauge(
+                "hypervisor_page_allocator_file_bytes",
+                "Total size of the backing files of the page allocators.",
+            ),
+            page_allocator_mmap_chunks: metrics_registry.int_gauge(
+                "hypervisor_page_allocator_mmap_chunks",
+                "Number of memory-mapped chunks of the page allocator backing files.",
+            ),
+            page_validation_failures: metrics_registry.int_counter(
+                "hypervisor_page_validation_failures_total",
+                "Number of pages that failed validation when they were revalidated.",
+            ),
+            page_checksum_mismatches: metrics_registry.int_counter(
+                "hypervisor_page_checksum_mismatches_total",
+                "Number of pages that did not match their checksum when they were revalidated.",
+            ),
+            deduplicated_pages: metrics_registry.int_gauge(
+                "hypervisor_deduplicated_pages",
+                "Number of page allocations that reused an identical page since the replica started.",
+            ),
+            page_allocator_backend: metrics_registry.int_gauge_vec(
+                "hypervisor_page_allocator_backend",
+                "Whether the most recently created page allocator backing file uses the given backend.",
+                &["backend"],
+            ),
+            page_allocator_directory_fallbacks: metrics_registry.int_gauge(
+                "hypervisor_page_allocator_directory_fallbacks",
+                "Number of page allocator backing files created in the default location because the configured directory failed since the replica started.",
+            ),
+            quarantined_pages: metrics_registry.int_counter(
+                "hypervisor_quarantined_pages_total",
+                "Number of pages that failed revalidation after a sandbox process crashed.",
+            ),
             executed_messages: metrics_registry.int_counter_vec(
                 "hypervisor_executed_messages_total",
                 "Number of messages executed, by type and status.",
@@ -85,7 +141,36 @@ impl HypervisorMetrics {
                     .observe(output.instance_stats.accessed_pages as f64);
                 self.dirty_pages
                     .observe(output.instance_stats.dirty_pages as f64);
-                self.allocated_pages.set(allocated_pages_count() as i64);
+                let page_allocator_stats = page_allocator_stats();
+                self.allocated_pages
+                    .set(page_allocator_stats.allocated_pages as i64);
+                self.freed_pages.set(page_allocator_stats.freed_pages as i64);
+                self.page_allocator_file_bytes
+                    .set(page_allocator_stats.backing_file_bytes as i64);
+                self.page_allocator_mmap_chunks
+                    .set(page_allocator_stats.mmap_chunks as i64);
+                inc_to(
+                    &self.page_validation_failures,
+                    page_allocator_stats.validation_failures,
+                );
+                inc_to(
+                    &self.page_checksum_mismatches,
+                    page_allocator_stats.checksum_mismatches,
+                );
+                self.deduplicated_pages
+                    .set(page_allocator_stats.deduplicated_pages as i64);
+                for backend in BackingFileBackend::ALL {
+                    let in_use = page_allocator_stats.backing_file_backend == Some(backend);
+                    self.page_allocator_backend
+                        .with_label_values(&[backend.as_str()])
+                        .set(in_use as i64);
+                }
+                self.page_allocator_directory_fallbacks
+                    .set(page_allocator_stats.backing_file_directory_fallbacks as i64);
+                inc_to(
+                    &self.quarantined_pages,
+                    page_allocator_stats.quarantined_pages,
+                );
 
                 match &output.wasm_result {
                     Ok(Some(WasmResult::Reply(_))) => "success",
@@ -124,6 +209,7 @@ pub struct Hypervisor {
     deterministic_time_slicing: FlagStatus,
     cost_to_compile_wasm_instruction: NumInstructions,
     dirty_page_overhead: NumInstructions,
+    chaos: Option<Chaos>,
 }
 
 impl Hypervisor {
@@ -230,6 +316,9 @@ impl Hypervisor {
         embedder_config.feature_flags.rate_limiting_of_debug_prints =
             config.rate_limiting_of_debug_prints;
         embedder_config.cost_to_compile_wasm_instruction = config.cost_to_compile_wasm_instruction;
+        embedder_config.feature_flags.instruction_profiling = config.instruction_profiling;
+        embedder_config.feature_flags.best_effort_responses = config.best_effort_responses;
+        embedder_config.page_allocator_config = config.page_allocator_config.clone();
 
         let wasm_executor: Arc<dyn WasmExecutor> = match config.canister_sandboxing_flag {
             FlagStatus::Enabled => {
@@ -262,6 +351,7 @@ impl Hypervisor {
             deterministic_time_slicing: config.deterministic_time_slicing,
             cost_to_compile_wasm_instruction: config.cost_to_compile_wasm_instruction,
             dirty_page_overhead,
+            chaos: config.chaos_mode.map(Chaos::new),
         }
     }
 
@@ -288,6 +378,7 @@ impl Hypervisor {
             deterministic_time_slicing,
             cost_to_compile_wasm_instruction,
             dirty_page_overhead,
+            chaos: None,
         }
     }
 
@@ -316,6 +407,7 @@ impl Hypervisor {
             execution_parameters.instruction_limits.message(),
             execution_parameters.instruction_limits.slice()
         );
+        let subnet_memory_saturation = execution_parameters.subnet_memory_saturation;
         let execution_result = self.execute_dts(
             api_type,
             &execution_state,
@@ -344,6 +436,11 @@ impl Hypervisor {
             time,
             network_topology,
             self.own_subnet_id,
+            &subnet_memory_saturation,
+            network_topology
+                .get_subnet_size(&self.own_subnet_id)
+                .unwrap_or(SMALL_APP_SUBNET_MAX_SIZE),
+            &self.cycles_account_manager,
             &self.log,
         );
         (output, execution_state, system_state)
@@ -373,6 +470,10 @@ impl Hypervisor {
             ),
         }
         let api_type_str = api_type.as_str();
+        let chaos_trap_key = self
+            .chaos
+            .as_ref()
+            .and_then(|_| chaos_trap_key(&api_type, system_state.canister_id));
         let static_system_state = SandboxSafeSystemState::new(
             system_state,
             *self.cycles_account_manager,
@@ -396,12 +497,62 @@ impl Hypervisor {
             self.metrics
                 .observe_compilation_metrics(&compilation_result);
         }
+        let execution_result = match (execution_result, chaos_trap_key) {
+            (WasmExecutionResult::Finished(slice, mut output, _), Some(key))
+                if self.chaos_injects_trap(&key) =>
+            {
+                // The injected trap discards all state changes of the execution
+                // just like a real trap would.
+                output.wasm_result = Err(HypervisorError::CalledTrap(
+                    "Trap injected by the chaos mode.".to_string(),
+                ));
+                WasmExecutionResult::Finished(slice, output, None)
+            }
+            (execution_result, _) => execution_result,
+        };
         self.metrics.observe(api_type_str, &execution_result);
         execution_result
     }
 
+    fn chaos_injects_trap(&self, key: &[u8]) -> bool {
+        self.chaos
+            .as_ref()
+            .map_or(false, |chaos| chaos.inject(ChaosFault::Trap, key))
+    }
+
     #[doc(hidden)]
     pub fn clear_compilation_cache_for_testing(&self) {
         self.compilation_cache.clear_for_testing()
     }
 }
+
+/// Returns the key that identifies the execution in the chaos mode decision
+/// whether to inject a trap. Only replicated update and callback executions
+/// are eligible.
+fn chaos_trap_key(api_type: &ApiType, canister_id: CanisterId) -> Option<Vec<u8>> {
+    let (time, call_context_id) = match api_type {
+        ApiType::Update {
+            time,
+            call_context_id,
+            ..
+        }
+        | ApiType::ReplyCallback {
+            time,
+            call_context_id,
+            execution_mode: ExecutionMode::Replicated,
+            ..
+        }
+        | ApiType::RejectCallback {
+            time,
+            call_context_id,
+            execution_mode: ExecutionMode::Replicated,
+            ..
+        } => (time, call_context_id),
+        _ => return None,
+    };
+    let mut key = canister_id.get_ref().as_slice().to_vec();
+    key.extend_from_slice(api_type.as_str().as_bytes());
+    key.extend_from_slice(&call_context_id.get().to_le_bytes());
+    key.extend_from_slice(&time.as_nanos_since_unix_epoch().to_le_bytes());
+    Some(key)
+}
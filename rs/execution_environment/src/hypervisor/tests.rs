@@ -22,7 +22,9 @@ use ic_test_utilities::assert_utils::assert_balance_equals;
 use ic_test_utilities_metrics::fetch_int_counter;
 use ic_test_utilities_metrics::{fetch_histogram_stats, HistogramStats};
 use ic_types::ingress::{IngressState, IngressStatus};
+use ic_types::messages::RequestOrResponse;
 use ic_types::methods::SystemMethod;
+use ic_types::time::NO_DEADLINE;
 use ic_types::{
     ingress::WasmResult, messages::MAX_INTER_CANISTER_PAYLOAD_IN_BYTES, methods::WasmMethod,
     CanisterId, Cycles, NumBytes, NumInstructions,
@@ -1559,6 +1561,58 @@ fn ic0_call_perform_has_no_effect_on_trap() {
     assert_eq!(0, test.xnet_messages().len());
 }
 
+#[test]
+fn ic0_call_with_best_effort_response_requires_feature_flag() {
+    let wat = r#"
+        (module
+            (import "ic0" "call_new"
+                (func $ic0_call_new
+                    (param i32 i32)
+                    (param $method_name_src i32)    (param $method_name_len i32)
+                    (param $reply_fun i32)          (param $reply_env i32)
+                    (param $reject_fun i32)         (param $reject_env i32)
+                )
+            )
+            (import "ic0" "call_with_best_effort_response"
+                (func $ic0_call_with_best_effort_response (param $timeout_seconds i32))
+            )
+            (import "ic0" "call_perform" (func $ic0_call_perform (result i32)))
+            (func (export "canister_update test")
+                (call $ic0_call_new
+                    (i32.const 100) (i32.const 10)  ;; callee canister id = 777
+                    (i32.const 0) (i32.const 18)    ;; refers to "some_remote_method" on the heap
+                    (i32.const 11) (i32.const 22)   ;; fictive on_reply closure
+                    (i32.const 33) (i32.const 44)   ;; fictive on_reject closure
+                )
+                (call $ic0_call_with_best_effort_response (i32.const 10))
+                (drop (call $ic0_call_perform))
+            )
+            (memory 1)
+            (data (i32.const 0) "some_remote_method XYZ")
+            (data (i32.const 100) "\09\03\00\00\00\00\00\00\ff\01")
+        )"#;
+
+    // Best-effort calls trap while the feature is disabled.
+    let mut test = ExecutionTestBuilder::new().build();
+    let canister_id = test.canister_from_wat(wat).unwrap();
+    let err = test.ingress(canister_id, "test", vec![]).unwrap_err();
+    assert_eq!(ErrorCode::CanisterContractViolation, err.code());
+    assert_eq!(0, test.xnet_messages().len());
+
+    let mut test = ExecutionTestBuilder::new()
+        .with_best_effort_responses()
+        .build();
+    let canister_id = test.canister_from_wat(wat).unwrap();
+    test.ingress_raw(canister_id, "test", vec![]);
+    assert_eq!(1, test.xnet_messages().len());
+    match &test.xnet_messages()[0] {
+        RequestOrResponse::Request(request) => assert_ne!(NO_DEADLINE, request.deadline),
+        RequestOrResponse::Response(response) => {
+            panic!("Expected a request, but got a response: {:?}", response)
+        }
+    }
+}
+
 #[test]
 fn ic0_call_cycles_add_deducts_cycles() {
     let mut test = ExecutionTestBuilder::new()
@@ -4506,7 +4560,7 @@ fn system_state_apply_change_fails() {
         WasmResult::Reply(_) => unreachable!("Expected the canister to reject the message"),
         WasmResult::Reject(err) => {
             assert!(
-                err.contains("exceeded its allowed memory allocation"),
+                err.contains("the subnet is out of memory"),
                 "{}",
                 err
             );
@@ -2,6 +2,7 @@ mod anonymous_query_handler;
 mod bitcoin;
 mod canister_manager;
 mod canister_settings;
+mod chaos;
 pub mod execution;
 mod execution_environment;
 mod execution_environment_metrics;
@@ -19,11 +20,14 @@ pub use execution_environment::{
     as_num_instructions, as_round_instructions, execute_canister, CompilationCostHandling,
     ExecuteMessageResult, ExecutionEnvironment, ExecutionResponse, RoundInstructions, RoundLimits,
 };
+pub use chaos::{Chaos, ChaosFault};
 pub use history::{IngressHistoryReaderImpl, IngressHistoryWriterImpl};
 pub use hypervisor::{Hypervisor, HypervisorMetrics};
 use ic_base_types::PrincipalId;
 use ic_btc_canister::BitcoinCanister;
-use ic_config::{execution_environment::Config, subnet_config::SchedulerConfig};
+use ic_config::{
+    execution_environment::Config, flag_status::FlagStatus, subnet_config::SchedulerConfig,
+};
 use ic_cycles_account_manager::CyclesAccountManager;
 use ic_interfaces::execution_environment::AnonymousQueryService;
 use ic_interfaces::execution_environment::{
@@ -37,7 +41,7 @@ use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::{CallOrigin, NetworkTopology, ReplicatedState};
 use ic_types::{messages::CallContextId, SubnetId};
 use ingress_filter::IngressFilter;
-use query_handler::HttpQueryHandler;
+use query_handler::{HttpQueryHandler, QueryCache};
 pub use query_handler::InternalHttpQueryHandler;
 pub use scheduler::RoundSchedule;
 use scheduler::SchedulerImpl;
@@ -131,13 +135,16 @@ impl ExecutionServices {
             config.clone(),
             Arc::clone(&cycles_account_manager),
         ));
+        let max_instructions_per_query = config
+            .max_instructions_per_query
+            .min(scheduler_config.max_instructions_per_message_without_dts);
         let sync_query_handler = Arc::new(InternalHttpQueryHandler::new(
             logger.clone(),
             hypervisor,
             own_subnet_type,
             config.clone(),
             metrics_registry,
-            scheduler_config.max_instructions_per_message_without_dts,
+            max_instructions_per_query,
             Arc::clone(&cycles_account_manager),
             config.composite_queries,
         ));
@@ -152,6 +159,13 @@ impl ExecutionServices {
         let concurrency_buffer = GlobalConcurrencyLimitLayer::new(
             config.query_execution_threads * MAX_INFLIGHT_QUERIES_PER_THREAD,
         );
+        let query_cache = match config.query_caching {
+            FlagStatus::Enabled => Some(Arc::new(QueryCache::new(
+                metrics_registry,
+                config.query_cache_capacity,
+            ))),
+            FlagStatus::Disabled => None,
+        };
         // Creating the async services require that a tokio runtime context is available.
 
         let async_query_handler = HttpQueryHandler::new_service(
@@ -159,6 +173,7 @@ impl ExecutionServices {
             Arc::clone(&sync_query_handler) as Arc<_>,
             Arc::clone(&threadpool),
             Arc::clone(&state_reader),
+            query_cache,
         );
         let ingress_filter = IngressFilter::new_service(
             concurrency_buffer.clone(),
@@ -171,7 +186,7 @@ impl ExecutionServices {
             threadpool,
             Arc::clone(&state_reader),
             Arc::clone(&exec_env),
-            scheduler_config.max_instructions_per_message_without_dts,
+            max_instructions_per_query,
         );
 
         let bitcoin_canister = Arc::new(BitcoinCanister::new(metrics_registry, logger.clone()));
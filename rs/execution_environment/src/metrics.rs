@@ -232,7 +232,7 @@ pub fn duration_histogram<S: Into<String>>(
 }
 
 /// Returns buckets appropriate for instructions.
-fn instructions_buckets() -> Vec<f64> {
+pub(crate) fn instructions_buckets() -> Vec<f64> {
     let mut buckets: Vec<NumInstructions> = decimal_buckets_with_zero(4, 11)
         .into_iter()
         .map(|x| NumInstructions::from(x as u64))
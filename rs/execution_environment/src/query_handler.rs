@@ -1,10 +1,13 @@
 //! This module implements the `QueryHandler` trait which is used to execute
 //! query methods via query calls.
 
+mod query_cache;
 mod query_context;
 #[cfg(test)]
 mod tests;
 
+pub(crate) use query_cache::QueryCache;
+
 use crate::execution_environment::subnet_memory_capacity;
 use crate::{
     hypervisor::Hypervisor,
@@ -27,7 +30,7 @@ use ic_types::{
         Blob, Certificate, CertificateDelegation, HttpQueryResponse, HttpQueryResponseReply,
         UserQuery,
     },
-    CanisterId, NumInstructions,
+    CanisterId, Height, NumInstructions,
 };
 use serde::Serialize;
 use std::{
@@ -52,7 +55,7 @@ fn get_latest_certified_state_and_data_certificate(
     state_reader: Arc<dyn StateReader<State = ReplicatedState>>,
     certificate_delegation: Option<CertificateDelegation>,
     canister_id: CanisterId,
-) -> Option<(Arc<ReplicatedState>, Vec<u8>)> {
+) -> Option<(Arc<ReplicatedState>, Vec<u8>, Height)> {
     // The path to fetch the data certificate for the canister.
     let path = SubTree(flatmap! {
         label("canister") => SubTree(
@@ -77,6 +80,7 @@ fn get_latest_certified_state_and_data_certificate(
                     signature: Blob(cert.signed.signature.signature.get().0),
                     delegation: certificate_delegation,
                 }),
+                cert.height,
             )
         })
 }
@@ -102,6 +106,8 @@ pub(crate) struct HttpQueryHandler {
     internal: Arc<dyn QueryHandler<State = ReplicatedState>>,
     state_reader: Arc<dyn StateReader<State = ReplicatedState>>,
     threadpool: Arc<Mutex<threadpool::ThreadPool>>,
+    // The cache of query results, if query caching is enabled.
+    query_cache: Option<Arc<QueryCache>>,
 }
 
 impl InternalHttpQueryHandler {
@@ -173,11 +179,13 @@ impl HttpQueryHandler {
         internal: Arc<dyn QueryHandler<State = ReplicatedState>>,
         threadpool: Arc<Mutex<threadpool::ThreadPool>>,
         state_reader: Arc<dyn StateReader<State = ReplicatedState>>,
+        query_cache: Option<Arc<QueryCache>>,
     ) -> QueryExecutionService {
         let base_service = BoxCloneService::new(Self {
             internal,
             state_reader,
             threadpool,
+            query_cache,
         });
         ServiceBuilder::new()
             .layer(concurrency_buffer)
@@ -214,6 +222,7 @@ impl Service<(UserQuery, Option<CertificateDelegation>)> for HttpQueryHandler {
     ) -> Self::Future {
         let internal = Arc::clone(&self.internal);
         let state_reader = Arc::clone(&self.state_reader);
+        let query_cache = self.query_cache.clone();
         let (tx, rx) = oneshot::channel();
         let threadpool = self.threadpool.lock().unwrap().clone();
         threadpool.execute(move || {
@@ -226,7 +235,12 @@ impl Service<(UserQuery, Option<CertificateDelegation>)> for HttpQueryHandler {
                     certificate_delegation,
                     query.receiver,
                 ) {
-                    Some((state, cert)) => internal.query(query, state, cert),
+                    Some((state, cert, height)) => match query_cache {
+                        Some(query_cache) => query_cache.get_or_execute(&query, height, || {
+                            internal.query(query.clone(), state, cert)
+                        }),
+                        None => internal.query(query, state, cert),
+                    },
                     None => Err(UserError::new(
                         ErrorCode::CertifiedStateUnavailable,
                         "Certified state is not available yet. Please try again...",
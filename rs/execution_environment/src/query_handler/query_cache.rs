@@ -0,0 +1,196 @@
+//! This module implements a cache of the results of user queries.
+//!
+//! A user query executes against the latest certified state and its result
+//! depends only on that state, the caller, the receiver, the method, and the
+//! argument. Identical queries against the same state therefore produce the
+//! same result, so the cache returns the stored result instead of executing
+//! the query again.
+//!
+//! The entries are keyed by the height of the certified state. Once a query
+//! arrives for a newer state, the canisters may have changed, so the cache
+//! drops all entries of the older states. Only replies and rejects of
+//! canisters are cached, errors are not.
+
+use ic_crypto_sha::Sha256;
+use ic_error_types::UserError;
+use ic_metrics::MetricsRegistry;
+use ic_types::{ingress::WasmResult, messages::UserQuery, CanisterId, Height, NumBytes, UserId};
+use lru::LruCache;
+use prometheus::{IntCounter, IntGauge};
+use std::{mem::size_of, sync::Mutex};
+
+#[cfg(test)]
+mod tests;
+
+pub(crate) struct QueryCacheMetrics {
+    pub hits: IntCounter,
+    pub misses: IntCounter,
+    pub invalidated_entries: IntCounter,
+    pub evicted_entries: IntCounter,
+    pub count_bytes: IntGauge,
+}
+
+impl QueryCacheMetrics {
+    fn new(metrics_registry: &MetricsRegistry) -> Self {
+        Self {
+            hits: metrics_registry.int_counter(
+                "execution_query_cache_hits",
+                "The number of user queries answered from the query cache",
+            ),
+            misses: metrics_registry.int_counter(
+                "execution_query_cache_misses",
+                "The number of user queries not found in the query cache",
+            ),
+            invalidated_entries: metrics_registry.int_counter(
+                "execution_query_cache_invalidated_entries",
+                "The number of query cache entries dropped because of a newer state",
+            ),
+            evicted_entries: metrics_registry.int_counter(
+                "execution_query_cache_evicted_entries",
+                "The number of query cache entries evicted to stay within the capacity",
+            ),
+            count_bytes: metrics_registry.int_gauge(
+                "execution_query_cache_count_bytes",
+                "The total size of the query cache entries in bytes",
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct EntryKey {
+    /// The height of the certified state that the query executed against.
+    height: Height,
+    source: UserId,
+    receiver: CanisterId,
+    method_name: String,
+    method_payload_hash: [u8; 32],
+}
+
+impl EntryKey {
+    fn new(query: &UserQuery, height: Height) -> Self {
+        Self {
+            height,
+            source: query.source,
+            receiver: query.receiver,
+            method_name: query.method_name.clone(),
+            method_payload_hash: Sha256::hash(&query.method_payload),
+        }
+    }
+}
+
+/// Returns the memory used by the entry, excluding the overhead of the LRU
+/// cache.
+fn entry_size(key: &EntryKey, result: &WasmResult) -> NumBytes {
+    let result_size = match result {
+        WasmResult::Reply(payload) => payload.len(),
+        WasmResult::Reject(message) => message.len(),
+    };
+    let key_size = size_of::<EntryKey>() + key.method_name.len();
+    NumBytes::from((key_size + size_of::<WasmResult>() + result_size) as u64)
+}
+
+struct Entries {
+    /// The height of the newest state that the cache has seen. All entries
+    /// belong to this state.
+    height: Height,
+    results: LruCache<EntryKey, WasmResult>,
+    /// The total size of the entries, see `entry_size`.
+    size: NumBytes,
+}
+
+/// A cache of the results of user queries with a memory budget. If the
+/// entries exceed the budget, then the least recently used ones are evicted.
+pub(crate) struct QueryCache {
+    entries: Mutex<Entries>,
+    capacity: NumBytes,
+    pub(crate) metrics: QueryCacheMetrics,
+}
+
+impl QueryCache {
+    pub(crate) fn new(metrics_registry: &MetricsRegistry, capacity: NumBytes) -> Self {
+        Self {
+            entries: Mutex::new(Entries {
+                height: Height::from(0),
+                results: LruCache::unbounded(),
+                size: NumBytes::from(0),
+            }),
+            capacity,
+            metrics: QueryCacheMetrics::new(metrics_registry),
+        }
+    }
+
+    /// Returns the cached result of the query against the certified state at
+    /// the given height. If there is none, then it executes the query with
+    /// `execute` and caches the result.
+    pub(crate) fn get_or_execute<F>(
+        &self,
+        query: &UserQuery,
+        height: Height,
+        execute: F,
+    ) -> Result<WasmResult, UserError>
+    where
+        F: FnOnce() -> Result<WasmResult, UserError>,
+    {
+        let key = EntryKey::new(query, height);
+        if let Some(result) = self.get(&key) {
+            self.metrics.hits.inc();
+            return Ok(result);
+        }
+        self.metrics.misses.inc();
+        // The lock is not held while the query executes, so identical queries
+        // that arrive concurrently may all execute.
+        let result = execute();
+        if let Ok(wasm_result) = &result {
+            self.insert(key, wasm_result.clone());
+        }
+        result
+    }
+
+    fn get(&self, key: &EntryKey) -> Option<WasmResult> {
+        let mut entries = self.entries.lock().unwrap();
+        self.invalidate_older_states(&mut entries, key.height);
+        entries.results.get(key).cloned()
+    }
+
+    fn insert(&self, key: EntryKey, result: WasmResult) {
+        let size = entry_size(&key, &result);
+        if size > self.capacity {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        self.invalidate_older_states(&mut entries, key.height);
+        if key.height < entries.height {
+            // The query executed against a state that has been superseded
+            // in the meantime.
+            return;
+        }
+        if let Some(old_result) = entries.results.put(key.clone(), result) {
+            entries.size -= entry_size(&key, &old_result);
+        }
+        entries.size += size;
+        while entries.size > self.capacity {
+            let (evicted_key, evicted_result) = entries
+                .results
+                .pop_lru()
+                .expect("the size of an empty query cache must be zero");
+            entries.size -= entry_size(&evicted_key, &evicted_result);
+            self.metrics.evicted_entries.inc();
+        }
+        self.metrics.count_bytes.set(entries.size.get() as i64);
+    }
+
+    /// Drops all entries if the given height is newer than the state of the
+    /// entries.
+    fn invalidate_older_states(&self, entries: &mut Entries, height: Height) {
+        if height > entries.height {
+            self.metrics
+                .invalidated_entries
+                .inc_by(entries.results.len() as u64);
+            entries.results.clear();
+            entries.size = NumBytes::from(0);
+            entries.height = height;
+            self.metrics.count_bytes.set(0);
+        }
+    }
+}
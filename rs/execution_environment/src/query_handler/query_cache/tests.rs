@@ -0,0 +1,114 @@
+use super::*;
+use ic_error_types::ErrorCode;
+use ic_test_utilities::types::ids::{canister_test_id, user_test_id};
+
+fn query(source: u64, payload: &[u8]) -> UserQuery {
+    UserQuery {
+        source: user_test_id(source),
+        receiver: canister_test_id(1),
+        method_name: "query".to_string(),
+        method_payload: payload.to_vec(),
+        ingress_expiry: 0,
+        nonce: None,
+    }
+}
+
+fn reply(data: &[u8]) -> Result<WasmResult, UserError> {
+    Ok(WasmResult::Reply(data.to_vec()))
+}
+
+fn query_cache(capacity: u64) -> QueryCache {
+    QueryCache::new(&MetricsRegistry::new(), NumBytes::from(capacity))
+}
+
+#[test]
+fn query_cache_returns_cached_result() {
+    let cache = query_cache(1_000_000);
+    let height = Height::from(1);
+
+    let result = cache.get_or_execute(&query(1, b"arg"), height, || reply(b"first"));
+    assert_eq!(result, reply(b"first"));
+    let result = cache.get_or_execute(&query(1, b"arg"), height, || reply(b"second"));
+    assert_eq!(result, reply(b"first"));
+
+    assert_eq!(cache.metrics.misses.get(), 1);
+    assert_eq!(cache.metrics.hits.get(), 1);
+}
+
+#[test]
+fn query_cache_distinguishes_callers_and_arguments() {
+    let cache = query_cache(1_000_000);
+    let height = Height::from(1);
+
+    cache.get_or_execute(&query(1, b"arg"), height, || reply(b"first"));
+    let result = cache.get_or_execute(&query(2, b"arg"), height, || reply(b"caller"));
+    assert_eq!(result, reply(b"caller"));
+    let result = cache.get_or_execute(&query(1, b"other"), height, || reply(b"argument"));
+    assert_eq!(result, reply(b"argument"));
+
+    assert_eq!(cache.metrics.misses.get(), 3);
+    assert_eq!(cache.metrics.hits.get(), 0);
+}
+
+#[test]
+fn query_cache_invalidates_entries_of_older_states() {
+    let cache = query_cache(1_000_000);
+
+    cache.get_or_execute(&query(1, b"arg"), Height::from(1), || reply(b"first"));
+    let result = cache.get_or_execute(&query(1, b"arg"), Height::from(2), || reply(b"second"));
+    assert_eq!(result, reply(b"second"));
+    assert_eq!(cache.metrics.invalidated_entries.get(), 1);
+
+    // A query against the older state executes, but its result is not cached.
+    let result = cache.get_or_execute(&query(1, b"arg"), Height::from(1), || reply(b"old"));
+    assert_eq!(result, reply(b"old"));
+    let result = cache.get_or_execute(&query(1, b"arg"), Height::from(2), || reply(b"third"));
+    assert_eq!(result, reply(b"second"));
+}
+
+#[test]
+fn query_cache_does_not_cache_errors() {
+    let cache = query_cache(1_000_000);
+    let height = Height::from(1);
+    let error = || Err(UserError::new(ErrorCode::CanisterTrapped, "trapped"));
+
+    assert_eq!(cache.get_or_execute(&query(1, b"arg"), height, error), error());
+    let result = cache.get_or_execute(&query(1, b"arg"), height, || reply(b"reply"));
+    assert_eq!(result, reply(b"reply"));
+    assert_eq!(cache.metrics.hits.get(), 0);
+}
+
+#[test]
+fn query_cache_evicts_least_recently_used_entries() {
+    let entry_size = entry_size(
+        &EntryKey::new(&query(1, b""), Height::from(1)),
+        &WasmResult::Reply(vec![0; 100]),
+    );
+    let cache = query_cache(2 * entry_size.get());
+    let height = Height::from(1);
+    let payload = vec![0; 100];
+
+    cache.get_or_execute(&query(1, b""), height, || reply(&payload));
+    cache.get_or_execute(&query(2, b""), height, || reply(&payload));
+    // Use the first entry, so that the second one is the least recently used.
+    cache.get_or_execute(&query(1, b""), height, || reply(&payload));
+    cache.get_or_execute(&query(3, b""), height, || reply(&payload));
+    assert_eq!(cache.metrics.evicted_entries.get(), 1);
+    assert_eq!(cache.metrics.count_bytes.get() as u64, 2 * entry_size.get());
+
+    let result = cache.get_or_execute(&query(1, b""), height, || reply(b"new"));
+    assert_eq!(result, reply(&payload));
+    let result = cache.get_or_execute(&query(2, b""), height, || reply(b"new"));
+    assert_eq!(result, reply(b"new"));
+}
+
+#[test]
+fn query_cache_skips_entries_larger_than_capacity() {
+    let cache = query_cache(100);
+    let height = Height::from(1);
+
+    cache.get_or_execute(&query(1, b""), height, || reply(&[0; 200]));
+    let result = cache.get_or_execute(&query(1, b""), height, || reply(b"new"));
+    assert_eq!(result, reply(b"new"));
+    assert_eq!(cache.metrics.count_bytes.get(), 0);
+}
@@ -46,7 +46,7 @@ use crate::{
 use ic_base_types::NumBytes;
 use ic_config::flag_status::FlagStatus;
 use ic_constants::SMALL_APP_SUBNET_MAX_SIZE;
-use ic_cycles_account_manager::CyclesAccountManager;
+use ic_cycles_account_manager::{CyclesAccountManager, ResourceSaturation};
 use ic_error_types::{ErrorCode, RejectCode, UserError};
 use ic_interfaces::execution_environment::{ExecutionMode, HypervisorError, SubnetAvailableMemory};
 use ic_logger::{debug, error, fatal, warn, ReplicaLogger};
@@ -61,6 +61,7 @@ use ic_types::{
         CallbackId, Payload, RejectContext, Request, RequestOrResponse, Response, UserQuery,
     },
     methods::WasmMethod,
+    time::NO_DEADLINE,
     CanisterId, Cycles, NumInstructions, NumMessages, Time,
 };
 use ic_types::{
@@ -104,6 +105,7 @@ fn generate_response(request: Arc<Request>, payload: Payload) -> Response {
         originator_reply_callback: request.sender_reply_callback,
         response_payload: payload,
         refund: Cycles::zero(),
+        deadline: request.deadline,
     }
 }
 
@@ -218,24 +220,7 @@ impl<'a> QueryContext<'a> {
         let canister_id = query.receiver;
         debug!(self.log, "Executing query for {}", canister_id);
         let old_canister = self.state.get_active_canister(&canister_id)?;
-
-        let subnet_size = self
-            .network_topology
-            .get_subnet_size(&cycles_account_manager.get_subnet_id())
-            .unwrap_or(SMALL_APP_SUBNET_MAX_SIZE);
-        if cycles_account_manager.freeze_threshold_cycles(
-            old_canister.system_state.freeze_threshold,
-            old_canister.system_state.memory_allocation,
-            old_canister.memory_usage(self.own_subnet_type),
-            old_canister.scheduler_state.compute_allocation,
-            subnet_size,
-        ) > old_canister.system_state.balance()
-        {
-            return Err(UserError::new(
-                ErrorCode::CanisterOutOfCycles,
-                format!("Canister {} is unable to process query calls because it's frozen. Please top up the canister with cycles and try again.", canister_id))
-            );
-        }
+        self.validate_not_frozen(&old_canister, &cycles_account_manager)?;
 
         let call_origin = CallOrigin::Query(query.source);
         let (method, query_kind, retry_as_stateful) = {
@@ -335,7 +320,12 @@ impl<'a> QueryContext<'a> {
                     )),
                     EnqueueRequestsResult::MessagesEnqueued => {
                         self.call_stack.insert(canister.canister_id(), canister);
-                        self.run_loop(canister_id, metrics, measurement_scope)
+                        self.run_loop(
+                            canister_id,
+                            metrics,
+                            &cycles_account_manager,
+                            measurement_scope,
+                        )
                     }
                     _ => Err(map_enqueue_error_to_user(r).unwrap()),
                 }
@@ -343,12 +333,41 @@ impl<'a> QueryContext<'a> {
         }
     }
 
+    /// Returns an error if the canister is below its freezing threshold.
+    /// Frozen canisters cannot process queries, neither from users nor from
+    /// other canisters in the call graph.
+    fn validate_not_frozen(
+        &self,
+        canister: &CanisterState,
+        cycles_account_manager: &CyclesAccountManager,
+    ) -> Result<(), UserError> {
+        let subnet_size = self
+            .network_topology
+            .get_subnet_size(&cycles_account_manager.get_subnet_id())
+            .unwrap_or(SMALL_APP_SUBNET_MAX_SIZE);
+        if cycles_account_manager.freeze_threshold_cycles(
+            canister.system_state.freeze_threshold,
+            canister.system_state.memory_allocation,
+            canister.memory_usage(self.own_subnet_type),
+            canister.scheduler_state.compute_allocation,
+            subnet_size,
+        ) > canister.system_state.balance()
+        {
+            return Err(UserError::new(
+                ErrorCode::CanisterOutOfCycles,
+                format!("Canister {} is unable to process query calls because it's frozen. Please top up the canister with cycles and try again.", canister.canister_id()))
+            );
+        }
+        Ok(())
+    }
+
     // Keep processing the call graph till a result is achieved or no more
     // outstanding calls are left.
     fn run_loop<'b>(
         &mut self,
         starting_canister_id: CanisterId,
         metrics: &'b QueryHandlerMetrics,
+        cycles_account_manager: &CyclesAccountManager,
         measurement_scope: &MeasurementScope<'b>,
     ) -> Result<WasmResult, UserError> {
         let measurement_scope =
@@ -366,7 +385,9 @@ impl<'a> QueryContext<'a> {
 
             if let Some(request) = self.outstanding_requests.pop() {
                 debug!(self.log, "Executing request for {}", request.receiver);
-                if let Some(err) = self.handle_request(request, &measurement_scope) {
+                if let Some(err) =
+                    self.handle_request(request, cycles_account_manager, &measurement_scope)
+                {
                     return Err(err);
                 }
                 continue;
@@ -427,7 +448,7 @@ impl<'a> QueryContext<'a> {
                         // Messages of these types are not produced by this
                         // module so must have existed on the canister's output
                         // queue from before.
-                        CallOrigin::CanisterUpdate(_, _)
+                        CallOrigin::CanisterUpdate(_, _, _)
                         | CallOrigin::SystemTask
                         | CallOrigin::Ingress(_, _) => continue,
 
@@ -562,7 +583,7 @@ impl<'a> QueryContext<'a> {
         };
         let func_ref = match call_origin {
             CallOrigin::Ingress(_, _)
-            | CallOrigin::CanisterUpdate(_, _)
+            | CallOrigin::CanisterUpdate(_, _, _)
             | CallOrigin::SystemTask => unreachable!("Unreachable in the QueryContext."),
             CallOrigin::CanisterQuery(_, _) | CallOrigin::Query(_) => {
                 FuncRef::QueryClosure(closure)
@@ -585,6 +606,7 @@ impl<'a> QueryContext<'a> {
                 payload.to_vec(),
                 incoming_cycles,
                 call_context_id,
+                NO_DEADLINE,
                 call_responded,
                 execution_parameters.execution_mode.clone(),
             ),
@@ -593,6 +615,7 @@ impl<'a> QueryContext<'a> {
                 context,
                 incoming_cycles,
                 call_context_id,
+                NO_DEADLINE,
                 call_responded,
                 execution_parameters.execution_mode.clone(),
             ),
@@ -682,7 +705,7 @@ impl<'a> QueryContext<'a> {
     ) -> (NumInstructions, Result<Option<WasmResult>, HypervisorError>) {
         let func_ref = match call_origin {
             CallOrigin::Ingress(_, _)
-            | CallOrigin::CanisterUpdate(_, _)
+            | CallOrigin::CanisterUpdate(_, _, _)
             | CallOrigin::SystemTask => unreachable!("Unreachable in the QueryContext."),
             CallOrigin::CanisterQuery(_, _) | CallOrigin::Query(_) => {
                 FuncRef::QueryClosure(cleanup_closure)
@@ -729,6 +752,7 @@ impl<'a> QueryContext<'a> {
     fn handle_request(
         &mut self,
         request: Arc<Request>,
+        cycles_account_manager: &CyclesAccountManager,
         measurement_scope: &MeasurementScope,
     ) -> Option<UserError> {
         // we are always prioritising responses over requests so when we execute
@@ -747,7 +771,13 @@ impl<'a> QueryContext<'a> {
             error!(self.log, "[EXC-BUG] The canister that we want to execute a request on should not already be loaded.");
         }
 
-        let canister = match self.state.get_active_canister(&request.receiver) {
+        let canister = match self
+            .state
+            .get_active_canister(&request.receiver)
+            .and_then(|canister| {
+                self.validate_not_frozen(&canister, cycles_account_manager)?;
+                Ok(canister)
+            }) {
             Ok(canister) => canister,
             Err(err) => {
                 let payload = Payload::Reject(RejectContext::from(err));
@@ -928,6 +958,7 @@ impl<'a> QueryContext<'a> {
                 originator_reply_callback: callback_id,
                 response_payload: payload,
                 refund: Cycles::zero(),
+                deadline: NO_DEADLINE,
             };
             self.outstanding_response = Some(response);
         };
@@ -1056,7 +1087,7 @@ impl<'a> QueryContext<'a> {
         match call_origin {
             CallOrigin::Query(_) => self.handle_response_with_query_origin(canister, action),
 
-            CallOrigin::CanisterUpdate(_, _)
+            CallOrigin::CanisterUpdate(_, _, _)
             | CallOrigin::Ingress(_, _)
             | CallOrigin::SystemTask => fatal!(
                 self.log,
@@ -1081,6 +1112,7 @@ impl<'a> QueryContext<'a> {
             compute_allocation: canister.scheduler_state.compute_allocation,
             subnet_type: self.own_subnet_type,
             execution_mode: ExecutionMode::NonReplicated,
+            subnet_memory_saturation: ResourceSaturation::default(),
         }
     }
 }
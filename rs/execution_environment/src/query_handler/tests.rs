@@ -726,6 +726,51 @@ fn queries_to_frozen_canisters_are_rejected() {
     assert!(result.is_ok());
 }
 
+#[test]
+fn composite_query_calls_to_frozen_canisters_are_rejected() {
+    let mut test = ExecutionTestBuilder::new().with_composite_queries().build();
+    let freezing_threshold = NumSeconds::from(3_000_000_000);
+
+    // Canister A calls canister B, which is below its freezing threshold, see
+    // `queries_to_frozen_canisters_are_rejected`.
+    let canister_a = test.universal_canister_with_cycles(CYCLES_BALANCE).unwrap();
+    let low_cycles = Cycles::new(80_000_590_000);
+    let canister_b = test.universal_canister_with_cycles(low_cycles).unwrap();
+    test.update_freezing_threshold(canister_b, freezing_threshold)
+        .unwrap();
+
+    let result = test.query(
+        UserQuery {
+            source: user_test_id(0),
+            receiver: canister_a,
+            method_name: "composite_query".to_string(),
+            method_payload: wasm()
+                .composite_query(
+                    canister_b,
+                    call_args()
+                        .other_side(wasm().reply_data(b"ignore".as_ref()))
+                        .on_reject(wasm().reject_message().reject()),
+                )
+                .build(),
+            ingress_expiry: 0,
+            nonce: None,
+        },
+        Arc::new(test.state().clone()),
+        vec![],
+    );
+    match result {
+        Ok(WasmResult::Reject(message)) => assert!(
+            message.contains(&format!(
+                "Canister {} is unable to process query calls because it's frozen",
+                canister_b
+            )),
+            "unexpected reject message: {}",
+            message
+        ),
+        other => panic!("Expected the call to be rejected, got {:?}", other),
+    }
+}
+
 const COMPOSITE_QUERY_WAT: &str = r#"
         (module
             (import "ic0" "msg_reply" (func $msg_reply))
@@ -1,5 +1,6 @@
 use crate::{
-    canister_manager::uninstall_canister,
+    canister_manager::{uninstall_canister, CallContextRejectReason},
+    chaos::{Chaos, ChaosFault},
     execution_environment::{
         as_num_instructions, as_round_instructions, execute_canister, ExecuteCanisterResult,
         ExecutionEnvironment, RoundInstructions, RoundLimits,
@@ -9,7 +10,7 @@ use crate::{
 };
 use ic_btc_canister::BitcoinCanister;
 use ic_config::flag_status::FlagStatus;
-use ic_config::subnet_config::SchedulerConfig;
+use ic_config::subnet_config::{SchedulerConfig, SystemTaskPriority};
 use ic_crypto_prng::{Csprng, RandomnessPurpose::ExecutionThread};
 use ic_cycles_account_manager::CyclesAccountManager;
 use ic_error_types::{ErrorCode, UserError};
@@ -21,17 +22,18 @@ use ic_interfaces::{
 };
 use ic_logger::{debug, error, fatal, info, new_logger, warn, ReplicaLogger};
 use ic_metrics::MetricsRegistry;
+use ic_nns_constants::{GOVERNANCE_CANISTER_ID, LEDGER_CANISTER_ID, REGISTRY_CANISTER_ID};
 use ic_replicated_state::{
-    bitcoin_state::BitcoinState, canister_state::NextExecution, CanisterState, ExecutionTask,
-    InputQueueType, NetworkTopology, ReplicatedState,
+    bitcoin_state::BitcoinState, canister_state::NextExecution, CanisterState,
+    ExecutedInstructions, ExecutionTask, InputQueueType, NetworkTopology, ReplicatedState,
 };
 use ic_system_api::InstructionLimits;
 use ic_types::{
     crypto::canister_threshold_sig::MasterEcdsaPublicKey,
     ingress::{IngressState, IngressStatus},
-    messages::{Ingress, MessageId},
-    CanisterId, ComputeAllocation, Cycles, ExecutionRound, LongExecutionMode, MemoryAllocation,
-    NumBytes, NumInstructions, NumSlices, Randomness, SubnetId, Time,
+    messages::{Ingress, MessageId, RequestOrResponse},
+    CanisterId, CanisterTimer, ComputeAllocation, Cycles, ExecutionRound, LongExecutionMode,
+    MemoryAllocation, NumBytes, NumInstructions, NumSlices, Randomness, SubnetId, Time,
 };
 use ic_types::{nominal_cycles::NominalCycles, NumMessages};
 use num_rational::Ratio;
@@ -57,6 +59,11 @@ const MAX_BITCOIN_REQUESTS_PER_ROUND: usize = 5;
 /// rate around 1.0, this will result in logging about once every 10 minutes.
 const SPAMMY_LOG_INTERVAL_ROUNDS: u64 = 10 * 60;
 
+/// The NNS canisters that are scheduled in the priority lane if
+/// `SchedulerConfig::system_canister_priority_lane` is enabled.
+const PRIORITY_LANE_CANISTER_IDS: [CanisterId; 3] =
+    [REGISTRY_CANISTER_ID, GOVERNANCE_CANISTER_ID, LEDGER_CANISTER_ID];
+
 #[cfg(test)]
 pub(crate) mod test_utilities;
 #[cfg(test)]
@@ -100,12 +107,46 @@ impl SchedulerImpl {
             (
                 Reverse(rs.long_execution_mode),
                 Reverse(rs.has_aborted_or_paused_execution),
+                Reverse(rs.in_priority_lane),
+                Reverse(rs.has_overdue_global_timer),
                 Reverse(rs.accumulated_priority),
                 rs.canister_id,
             )
         });
     }
 
+    /// Returns true if the canister is scheduled ahead of the other canisters
+    /// with new executions regardless of its accumulated priority.
+    ///
+    /// As the canisters of the priority lane are the first ones to be assigned
+    /// to the new execution cores, a message to one of them is executed in the
+    /// first inner round after it was inducted, unless there are more priority
+    /// lane canisters with messages than new execution cores.
+    fn in_priority_lane(&self, canister_id: &CanisterId) -> bool {
+        self.config.system_canister_priority_lane == FlagStatus::Enabled
+            && PRIORITY_LANE_CANISTER_IDS.contains(canister_id)
+    }
+
+    /// Returns true if the global timer of the canister has been due for at
+    /// least `SchedulerConfig::max_global_timer_delay`.
+    ///
+    /// Such canisters are scheduled ahead of the other canisters with new
+    /// executions, except for the priority lane, so that rounds in which a
+    /// saturated subnet does not get to the canister don't keep delaying its
+    /// timer. The timer then runs at most one round after the maximum delay,
+    /// unless more canisters have overdue timers than there are new execution
+    /// cores.
+    fn has_overdue_global_timer(&self, canister: &CanisterState, now: Time) -> bool {
+        match canister.system_state.global_timer {
+            CanisterTimer::Inactive => false,
+            CanisterTimer::Active(deadline) => {
+                deadline <= now
+                    && now - deadline >= self.config.max_global_timer_delay
+                    && canister.exports_global_timer_method()
+            }
+        }
+    }
+
     /// Orders the canisters and updates their accumulated priorities according to
     /// the strategy described in RUN-58.
     ///
@@ -115,6 +156,7 @@ impl SchedulerImpl {
         &self,
         scheduler_cores: usize,
         current_round: ExecutionRound,
+        now: Time,
         canister_states: &mut BTreeMap<CanisterId, CanisterState>,
     ) -> RoundSchedule {
         let number_of_canisters = canister_states.len();
@@ -168,6 +210,8 @@ impl SchedulerImpl {
                 compute_allocation,
                 long_execution_mode: canister.scheduler_state.long_execution_mode,
                 has_aborted_or_paused_execution,
+                in_priority_lane: self.in_priority_lane(&canister_id),
+                has_overdue_global_timer: self.has_overdue_global_timer(canister, now),
             });
 
             total_compute_allocation_percent += compute_allocation.as_percent() as i64;
@@ -461,7 +505,8 @@ impl SchedulerImpl {
 
         let mut total_heap_delta = NumBytes::from(0);
 
-        // Add `Heartbeat` and `GlobalTimer` tasks to be executed before input messages.
+        // Add `Heartbeat`, `GlobalTimer`, and `OnLowWasmMemory` tasks to be
+        // executed before input messages.
         {
             let _timer = self
                 .metrics
@@ -471,25 +516,48 @@ impl SchedulerImpl {
             for canister in state.canisters_iter_mut() {
                 let global_timer_has_reached_deadline =
                     canister.system_state.global_timer.has_reached_deadline(now);
+                let is_low_wasm_memory = canister.is_low_wasm_memory();
+                if !is_low_wasm_memory {
+                    // The remaining Wasm memory has recovered, so the hook
+                    // may run again the next time it drops below the threshold.
+                    canister.system_state.on_low_wasm_memory_hook_executed = false;
+                }
                 match canister.next_execution() {
                     NextExecution::ContinueLong | NextExecution::ContinueInstallCode => {
                         // Do not add a heartbeat task if a long execution
                         // is pending.
                     }
                     NextExecution::None | NextExecution::StartNew => {
-                        if canister.exports_heartbeat_method() {
-                            canister
-                                .system_state
-                                .task_queue
-                                .push_front(ExecutionTask::Heartbeat);
+                        // Canisters that don't export the methods get no
+                        // tasks, so they don't take part in the round.
+                        let heartbeat = (
+                            canister.exports_heartbeat_method(),
+                            ExecutionTask::Heartbeat,
+                        );
+                        let global_timer = (
+                            global_timer_has_reached_deadline
+                                && canister.exports_global_timer_method(),
+                            ExecutionTask::GlobalTimer,
+                        );
+                        // The tasks are pushed to the front of the queue, so
+                        // the task pushed last runs first.
+                        let tasks = match self.config.system_task_priority {
+                            SystemTaskPriority::GlobalTimerFirst => [heartbeat, global_timer],
+                            SystemTaskPriority::HeartbeatFirst => [global_timer, heartbeat],
+                        };
+                        for (is_due, task) in tasks {
+                            if is_due {
+                                canister.system_state.task_queue.push_front(task);
+                            }
                         }
-                        if global_timer_has_reached_deadline
-                            && canister.exports_global_timer_method()
+                        if is_low_wasm_memory
+                            && !canister.system_state.on_low_wasm_memory_hook_executed
+                            && canister.exports_on_low_wasm_memory_method()
                         {
                             canister
                                 .system_state
                                 .task_queue
-                                .push_front(ExecutionTask::GlobalTimer);
+                                .push_front(ExecutionTask::OnLowWasmMemory);
                         }
                     }
                 }
@@ -594,11 +662,14 @@ impl SchedulerImpl {
                 .metrics
                 .round_inner_heartbeat_overhead_duration
                 .start_timer();
-            // Remove all remaining `Heartbeat` and `GlobalTimer` tasks
-            // because they will be added again in the next round.
+            // Remove all remaining `Heartbeat`, `GlobalTimer`, and
+            // `OnLowWasmMemory` tasks because they will be added again in the
+            // next round.
             for canister in state.canisters_iter_mut() {
                 canister.system_state.task_queue.retain(|task| match task {
-                    ExecutionTask::Heartbeat | ExecutionTask::GlobalTimer => false,
+                    ExecutionTask::Heartbeat
+                    | ExecutionTask::GlobalTimer
+                    | ExecutionTask::OnLowWasmMemory => false,
                     ExecutionTask::PausedExecution(..)
                     | ExecutionTask::PausedInstallCode(..)
                     | ExecutionTask::AbortedExecution { .. }
@@ -636,6 +707,28 @@ impl SchedulerImpl {
             if *allocation.numer() > 0 && Ratio::from_integer(canister_age) > allocation.recip() {
                 self.metrics.canister_compute_allocation_violation.inc();
             }
+            // A canister achieves its compute allocation if it has a full
+            // execution in at least that percentage of the rounds in which it
+            // has work. The number of canisters with a compute allocation is
+            // bounded by the compute capacity, which bounds the cardinality.
+            if *allocation.numer() > 0 {
+                let canister_id = canister_id.to_string();
+                let labels = [canister_id.as_str()];
+                self.metrics
+                    .canister_compute_allocation_percent
+                    .with_label_values(&labels)
+                    .set(*allocation.numer() as i64);
+                self.metrics
+                    .canister_compute_allocation_executable_rounds
+                    .with_label_values(&labels)
+                    .inc();
+                if canister_age == 0 {
+                    self.metrics
+                        .canister_compute_allocation_full_execution_rounds
+                        .with_label_values(&labels)
+                        .inc();
+                }
+            }
         }
 
         for (message_id, status) in ingress_execution_results {
@@ -833,21 +926,21 @@ impl SchedulerImpl {
         self.metrics
             .canister_balance
             .observe(canister.system_state.balance().get() as f64);
-        if let Some(es) = &canister.execution_state {
+        let memory_breakdown = canister.memory_breakdown();
+        if canister.execution_state.is_some() {
             self.metrics
                 .canister_binary_size
-                .observe(es.wasm_binary.binary.len() as f64);
-            self.metrics.canister_wasm_memory_usage.observe(
-                ic_replicated_state::num_bytes_try_from(es.wasm_memory.size)
-                    .unwrap()
-                    .get() as f64,
-            );
-            self.metrics.canister_stable_memory_usage.observe(
-                ic_replicated_state::num_bytes_try_from(es.stable_memory.size)
-                    .unwrap()
-                    .get() as f64,
-            );
+                .observe(memory_breakdown.wasm_binary.get() as f64);
+            self.metrics
+                .canister_wasm_memory_usage
+                .observe(memory_breakdown.wasm_memory.get() as f64);
+            self.metrics
+                .canister_stable_memory_usage
+                .observe(memory_breakdown.stable_memory.get() as f64);
         }
+        self.metrics
+            .canister_message_memory_usage
+            .observe(memory_breakdown.message_memory.get() as f64);
         self.metrics
             .canister_memory_allocation
             .observe(match canister.memory_allocation() {
@@ -899,7 +992,12 @@ impl SchedulerImpl {
                     )
                     .is_err()
                 {
-                    all_rejects.push(uninstall_canister(&self.log, canister, state_time));
+                    all_rejects.push(uninstall_canister(
+                        &self.log,
+                        canister,
+                        state_time,
+                        CallContextRejectReason::OutOfCycles,
+                    ));
                     canister.scheduler_state.compute_allocation = ComputeAllocation::zero();
                     canister.system_state.memory_allocation = MemoryAllocation::BestEffort;
                     // Burn the remaining balance of the canister.
@@ -953,12 +1051,21 @@ impl SchedulerImpl {
         // Get a list of canisters in the map before we iterate over the map.
         // This is because we cannot hold an immutable reference to the map
         // while trying to simultaneously mutate it.
-        let canisters_with_outputs: Vec<CanisterId> = canisters
+        let mut canisters_with_outputs: Vec<CanisterId> = canisters
             .iter()
             .filter(|(_, canister)| canister.has_output())
             .map(|(canister_id, _)| *canister_id)
             .collect();
 
+        // In chaos mode, messages from different canisters may be inducted in
+        // any order and responses may be held back to a later round. The order
+        // of messages between any pair of canisters is preserved.
+        let chaos = self.exec_env.chaos();
+        let round_key = state.time().as_nanos_since_unix_epoch().to_le_bytes();
+        if let Some(chaos) = &chaos {
+            chaos.maybe_reorder(&mut canisters_with_outputs, &round_key);
+        }
+
         let mut inducted_messages_to_self = 0;
         let mut inducted_messages_to_others = 0;
         for source_canister_id in canisters_with_outputs {
@@ -996,6 +1103,7 @@ impl SchedulerImpl {
             source_canister
                 .system_state
                 .output_queues_for_each(|canister_id, msg| match canisters.get_mut(canister_id) {
+                    Some(_) if chaos_delays_response(chaos.as_ref(), msg, &round_key) => Err(()),
                     Some(dest_canister) => dest_canister
                         .push_input(
                             (*msg).clone(),
@@ -1074,6 +1182,7 @@ impl SchedulerImpl {
 
     /// Aborts paused execution above `max_paused_executions` based on scheduler priority.
     fn abort_paused_executions_above_limit(&self, state: &mut ReplicatedState) {
+        let now = state.time();
         let mut paused_round_states = state
             .canisters_iter()
             .filter_map(|canister| {
@@ -1084,6 +1193,8 @@ impl SchedulerImpl {
                         compute_allocation: Default::default(), // not used
                         long_execution_mode: canister.scheduler_state.long_execution_mode,
                         has_aborted_or_paused_execution: true,
+                        in_priority_lane: self.in_priority_lane(&canister.canister_id()),
+                        has_overdue_global_timer: self.has_overdue_global_timer(canister, now),
                     })
                 } else {
                     None
@@ -1137,7 +1248,7 @@ impl SchedulerImpl {
             .iter()
             .filter(|(_, canister)| !canister.system_state.task_queue.is_empty());
 
-        // 1. Heartbeat and GlobalTimer tasks exist only during the round
+        // 1. Heartbeat, GlobalTimer, and OnLowWasmMemory tasks exist only during the round
         //    and must not exist after the round.
         // 2. Paused executions can exist only in ordinary rounds (not checkpoint rounds).
         // 3. If deterministic time slicing is disabled, then there are no paused tasks.
@@ -1159,6 +1270,12 @@ impl SchedulerImpl {
                             id
                         );
                     }
+                    ExecutionTask::OnLowWasmMemory => {
+                        panic!(
+                            "Unexpected on low wasm memory task after a round in canister {:?}",
+                            id
+                        );
+                    }
                     ExecutionTask::PausedExecution(_) | ExecutionTask::PausedInstallCode(_) => {
                         assert_eq!(
                             self.deterministic_time_slicing,
@@ -1390,10 +1507,12 @@ impl Scheduler for SchedulerImpl {
         {
             let _timer = self.metrics.round_scheduling_duration.start_timer();
             round_schedule = {
+                let now = state.time();
                 let mut canisters = state.take_canister_states();
                 let round_schedule = self.apply_scheduling_strategy(
                     self.config.scheduler_cores,
                     current_round,
+                    now,
                     &mut canisters,
                 );
 
@@ -1769,6 +1888,7 @@ fn observe_replicated_state_metrics(
     let mut num_aborted_install = 0;
 
     let mut consumed_cycles_total = NominalCycles::new(0);
+    let mut executed_instructions_total = ExecutedInstructions::default();
 
     let mut ingress_queue_message_count = 0;
     let mut ingress_queue_size_bytes = 0;
@@ -1800,12 +1920,19 @@ fn observe_replicated_state_metrics(
             Some(&ExecutionTask::AbortedInstallCode { .. }) => {
                 num_aborted_install += 1;
             }
-            Some(&ExecutionTask::Heartbeat) | Some(&ExecutionTask::GlobalTimer) | None => {}
+            Some(&ExecutionTask::Heartbeat)
+            | Some(&ExecutionTask::GlobalTimer)
+            | Some(&ExecutionTask::OnLowWasmMemory)
+            | None => {}
         }
         consumed_cycles_total += canister
             .system_state
             .canister_metrics
             .consumed_cycles_since_replica_started;
+        let executed_instructions = &canister.scheduler_state.executed_instructions;
+        executed_instructions_total.update += executed_instructions.update;
+        executed_instructions_total.query += executed_instructions.query;
+        executed_instructions_total.system_task += executed_instructions.system_task;
         let queues = canister.system_state.queues();
         ingress_queue_message_count += queues.ingress_queue_message_count();
         ingress_queue_size_bytes += queues.ingress_queue_size_bytes();
@@ -1863,6 +1990,7 @@ fn observe_replicated_state_metrics(
         .subnet_metrics
         .consumed_cycles_by_deleted_canisters;
     metrics.observe_consumed_cycles(consumed_cycles_total);
+    metrics.observe_executed_instructions(executed_instructions_total);
 
     let observe_reading = |status: CanisterStatusType, num: i64| {
         metrics
@@ -1936,7 +2064,9 @@ fn can_execute_msg(
         };
 
         // Only one install code message allowed at a time.
-        if let Some(Ic00Method::InstallCode) = maybe_instal_code_method {
+        if let Some(Ic00Method::InstallCode | Ic00Method::InstallChunkedCode) =
+            maybe_instal_code_method
+        {
             return false;
         }
     }
@@ -1992,8 +2122,13 @@ fn get_instructions_limits_for_subnet_message(
             | BitcoinGetCurrentFeePercentiles
             | BitcoinGetSuccessors
             | ProvisionalCreateCanisterWithCycles
-            | ProvisionalTopUpCanister => default_limits,
-            InstallCode => InstructionLimits::new(
+            | ProvisionalTopUpCanister
+            | UploadChunk
+            | ClearChunkStore
+            | StoredChunks
+            | FetchCanisterLogs
+            | CanisterStoppingStatus => default_limits,
+            InstallCode | InstallChunkedCode => InstructionLimits::new(
                 dts,
                 config.max_instructions_per_install_code,
                 config.max_instructions_per_install_code_slice,
@@ -2033,10 +2168,34 @@ fn is_bitcoin_request(msg: &CanisterInputMessage) -> bool {
                 | UpdateSettings
                 | ProvisionalCreateCanisterWithCycles
                 | ProvisionalTopUpCanister
-                | InstallCode => false,
+                | InstallCode
+                | InstallChunkedCode
+                | UploadChunk
+                | ClearChunkStore
+                | StoredChunks
+                | FetchCanisterLogs
+                | CanisterStoppingStatus => false,
             },
             Err(_) => false,
         },
         CanisterInputMessage::Response(_) => false,
     }
 }
+
+/// Returns true if the chaos mode holds back the given response to a later
+/// round. Requests are never delayed.
+fn chaos_delays_response(
+    chaos: Option<&Chaos>,
+    msg: &RequestOrResponse,
+    round_key: &[u8],
+) -> bool {
+    match (chaos, msg) {
+        (Some(chaos), RequestOrResponse::Response(response)) => {
+            let mut key = response.originator.get_ref().as_slice().to_vec();
+            key.extend_from_slice(&response.originator_reply_callback.get().to_le_bytes());
+            key.extend_from_slice(round_key);
+            chaos.inject(ChaosFault::DelayResponse, &key)
+        }
+        _ => false,
+    }
+}
@@ -19,6 +19,12 @@ pub(super) struct CanisterRoundState {
     /// True when there is an aborted or paused long update execution.
     /// Note: this doesn't include paused or aborted install codes.
     pub(super) has_aborted_or_paused_execution: bool,
+    /// True when the canister is scheduled in the priority lane for NNS
+    /// system canisters.
+    pub(super) in_priority_lane: bool,
+    /// True when the global timer of the canister has been due for at least
+    /// `SchedulerConfig::max_global_timer_delay`.
+    pub(super) has_overdue_global_timer: bool,
 }
 
 /// Represents three ordered active Canister ID groups to schedule.
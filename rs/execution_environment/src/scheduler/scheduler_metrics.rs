@@ -4,6 +4,7 @@ use ic_metrics::{
     buckets::{decimal_buckets, decimal_buckets_with_zero, linear_buckets},
     MetricsRegistry,
 };
+use ic_replicated_state::ExecutedInstructions;
 use ic_types::nominal_cycles::NominalCycles;
 use prometheus::{Gauge, Histogram, IntCounter, IntCounterVec, IntGauge, IntGaugeVec};
 
@@ -19,10 +20,14 @@ pub(crate) const SUBNET_MEMORY_USAGE_INVARIANT_BROKEN: &str =
 pub(super) struct SchedulerMetrics {
     pub(super) canister_age: Histogram,
     pub(super) canister_compute_allocation_violation: IntCounter,
+    pub(super) canister_compute_allocation_percent: IntGaugeVec,
+    pub(super) canister_compute_allocation_executable_rounds: IntCounterVec,
+    pub(super) canister_compute_allocation_full_execution_rounds: IntCounterVec,
     pub(super) canister_balance: Histogram,
     pub(super) canister_binary_size: Histogram,
     pub(super) canister_wasm_memory_usage: Histogram,
     pub(super) canister_stable_memory_usage: Histogram,
+    pub(super) canister_message_memory_usage: Histogram,
     pub(super) canister_memory_allocation: Histogram,
     pub(super) canister_compute_allocation: Histogram,
     pub(super) compute_utilization_per_core: Histogram,
@@ -81,9 +86,11 @@ pub(super) struct SchedulerMetrics {
     pub(super) canister_paused_install_code: Histogram,
     pub(super) canister_aborted_install_code: Histogram,
     pub(super) inducted_messages: IntCounterVec,
+    pub(super) canister_instructions_executed: IntGaugeVec,
 }
 
 const LABEL_MESSAGE_KIND: &str = "kind";
+const LABEL_CANISTER_ID: &str = "canister_id";
 pub(super) const MESSAGE_KIND_INGRESS: &str = "ingress";
 pub(super) const MESSAGE_KIND_CANISTER: &str = "canister";
 
@@ -104,6 +111,26 @@ impl SchedulerMetrics {
                 "scheduler_compute_allocation_violations",
                 "Total number of canister allocation violations.",
             ),
+            canister_compute_allocation_percent: metrics_registry.int_gauge_vec(
+                "scheduler_compute_allocation_percent",
+                "Compute allocation in percent of the executable canisters with a \
+                compute allocation, by canister.",
+                &[LABEL_CANISTER_ID],
+            ),
+            canister_compute_allocation_executable_rounds: metrics_registry.int_counter_vec(
+                "scheduler_compute_allocation_executable_rounds_total",
+                "Number of rounds in which a canister with a compute allocation had work \
+                to execute, by canister.",
+                &[LABEL_CANISTER_ID],
+            ),
+            canister_compute_allocation_full_execution_rounds: metrics_registry.int_counter_vec(
+                "scheduler_compute_allocation_full_execution_rounds_total",
+                "Number of rounds in which a canister with a compute allocation had work \
+                to execute and a full execution, by canister. The canister achieves its \
+                allocation if these are at least scheduler_compute_allocation_percent \
+                percent of its executable rounds.",
+                &[LABEL_CANISTER_ID],
+            ),
             canister_balance: cycles_histogram(
                 "canister_balance_cycles",
                 "Canisters balance distribution in Cycles.",
@@ -124,6 +151,11 @@ impl SchedulerMetrics {
                 "Canisters stable memory usage distribution in bytes.",
                 metrics_registry,
             ),
+            canister_message_memory_usage: memory_histogram(
+                "canister_message_memory_usage_bytes",
+                "Canisters message memory usage distribution in bytes.",
+                metrics_registry,
+            ),
             canister_memory_allocation: memory_histogram(
                 "canister_memory_allocation_bytes",
                 "Canisters memory allocation distribution in bytes.",
@@ -553,6 +585,13 @@ impl SchedulerMetrics {
                 "Number of messages inducted, by destination.",
                 &["destination"],
             ),
+            // Not monotonically increasing, because the instructions of
+            // deleted canisters are not counted anymore.
+            canister_instructions_executed: metrics_registry.int_gauge_vec(
+                "replicated_state_canister_instructions_executed",
+                "Total number of instructions executed by the canisters since their creation, by kind of execution.",
+                &["kind"],
+            ),
         }
     }
 
@@ -561,6 +600,18 @@ impl SchedulerMetrics {
             .set(consumed_cycles.get() as f64);
     }
 
+    pub(super) fn observe_executed_instructions(&self, executed: ExecutedInstructions) {
+        for (kind, instructions) in [
+            ("update", executed.update),
+            ("query", executed.query),
+            ("system_task", executed.system_task),
+        ] {
+            self.canister_instructions_executed
+                .with_label_values(&[kind])
+                .set(instructions.get() as i64);
+        }
+    }
+
     pub(super) fn observe_input_messages(&self, kind: &str, message_count: usize) {
         self.input_queue_messages
             .with_label_values(&[kind])
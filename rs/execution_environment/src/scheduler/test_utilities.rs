@@ -23,16 +23,20 @@ use ic_error_types::UserError;
 use ic_ic00_types::{CanisterInstallMode, InstallCodeArgs, Method, Payload};
 use ic_interfaces::execution_environment::{
     ExecutionRoundType, HypervisorError, HypervisorResult, IngressHistoryWriter, InstanceStats,
-    RegistryExecutionSettings, Scheduler, WasmExecutionOutput,
+    InstructionProfile, RegistryExecutionSettings, Scheduler, WasmExecutionOutput,
 };
 use ic_logger::{replica_logger::no_op_logger, ReplicaLogger};
 use ic_metrics::MetricsRegistry;
 use ic_registry_routing_table::{CanisterIdRange, RoutingTable};
 use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::{
-    canister_state::execution_state::{self, WasmMetadata},
+    canister_state::{
+        execution_state::{self, WasmMetadata},
+        WASM_PAGE_SIZE_IN_BYTES,
+    },
     testing::{CanisterQueuesTesting, ReplicatedStateTesting},
-    CanisterState, ExecutionState, ExportedFunctions, InputQueueType, ReplicatedState,
+    CanisterState, ExecutionState, ExportedFunctions, InputQueueType, NumWasmPages,
+    ReplicatedState,
 };
 use ic_system_api::{
     sandbox_safe_system_state::{SandboxSafeSystemState, SystemStateChanges},
@@ -63,7 +67,7 @@ use crate::{
 use super::SchedulerImpl;
 use crate::metrics::MeasurementScope;
 use ic_crypto_prng::{Csprng, RandomnessPurpose::ExecutionThread};
-use ic_types::time::UNIX_EPOCH;
+use ic_types::time::{NO_DEADLINE, UNIX_EPOCH};
 use std::collections::BTreeSet;
 
 /// A helper for the scheduler tests. It comes with its own Wasm executor that
@@ -161,6 +165,13 @@ impl SchedulerTest {
         wasm_executor.schedule.clone()
     }
 
+    /// Returns the system tasks executed by the canisters in the execution
+    /// order.
+    pub fn executed_system_tasks(&self) -> Vec<(CanisterId, SystemMethod)> {
+        let wasm_executor = self.wasm_executor.core.lock().unwrap();
+        wasm_executor.executed_system_tasks.clone()
+    }
+
     pub fn create_canister(&mut self) -> CanisterId {
         self.create_canister_with(
             self.initial_canister_cycles,
@@ -404,6 +415,20 @@ impl SchedulerTest {
         wasm_executor.push_system_task(canister_id, system_task);
     }
 
+    pub fn expect_on_low_wasm_memory(&mut self, canister_id: CanisterId, system_task: TestMessage) {
+        assert!(
+            self.canister_state(canister_id)
+                .execution_state
+                .as_ref()
+                .unwrap()
+                .exports_method(&WasmMethod::System(SystemMethod::CanisterOnLowWasmMemory)),
+            "The canister should be created with \
+             `create_canister_with(.., Some(SystemMethod::CanisterOnLowWasmMemory))`"
+        );
+        let mut wasm_executor = self.wasm_executor.core.lock().unwrap();
+        wasm_executor.push_system_task(canister_id, system_task);
+    }
+
     pub fn execute_round(&mut self, round_type: ExecutionRoundType) {
         let state = self.state.take().unwrap();
         let state = self.scheduler.execute_round(
@@ -493,6 +518,30 @@ impl SchedulerTest {
         canister_state.system_state.global_timer = CanisterTimer::Active(time);
     }
 
+    /// Adds the given system method to the exports of the canister, e.g., to
+    /// export both the heartbeat and the global timer.
+    pub(crate) fn export_system_method(&mut self, canister: CanisterId, method: SystemMethod) {
+        let execution_state = self.canister_state_mut(canister).execution_state.as_mut().unwrap();
+        let mut exports = execution_state.exports.as_ref().clone();
+        exports.insert(WasmMethod::System(method));
+        execution_state.exports = ExportedFunctions::new(exports);
+    }
+
+    pub(crate) fn set_canister_wasm_memory_threshold(
+        &mut self,
+        canister: CanisterId,
+        threshold: NumBytes,
+    ) {
+        let canister_state = self.canister_state_mut(canister);
+        canister_state.system_state.wasm_memory_threshold = threshold;
+    }
+
+    pub(crate) fn set_canister_wasm_memory_size(&mut self, canister: CanisterId, size: NumBytes) {
+        let canister_state = self.canister_state_mut(canister);
+        canister_state.execution_state.as_mut().unwrap().wasm_memory.size =
+            NumWasmPages::from(size.get() as usize / WASM_PAGE_SIZE_IN_BYTES);
+    }
+
     pub(crate) fn set_time(&mut self, time: Time) {
         self.state_mut().metadata.batch_time = time;
     }
@@ -901,6 +950,7 @@ struct TestWasmExecutorCore {
     messages: HashMap<u32, TestMessage>,
     system_tasks: HashMap<CanisterId, VecDeque<TestMessage>>,
     schedule: Vec<(ExecutionRound, CanisterId, NumInstructions)>,
+    executed_system_tasks: Vec<(CanisterId, SystemMethod)>,
     next_message_id: u32,
     round: ExecutionRound,
     subnet_size: usize,
@@ -913,6 +963,7 @@ impl TestWasmExecutorCore {
             messages: HashMap::new(),
             system_tasks: HashMap::new(),
             schedule: vec![],
+            executed_system_tasks: vec![],
             next_message_id: 0,
             round: ExecutionRound::new(0),
             cycles_account_manager,
@@ -958,6 +1009,8 @@ impl TestWasmExecutorCore {
                     accessed_pages: 0,
                     dirty_pages: 0,
                 },
+                canister_log_records: vec![],
+                instruction_profile: InstructionProfile::default(),
             };
             self.schedule
                 .push((self.round, canister_id, instructions_to_execute));
@@ -996,6 +1049,8 @@ impl TestWasmExecutorCore {
             allocated_message_bytes: NumBytes::from(0),
             num_instructions_left: instructions_left,
             instance_stats,
+            canister_log_records: vec![],
+            instruction_profile: InstructionProfile::default(),
         };
         self.schedule
             .push((self.round, canister_id, instructions_to_execute));
@@ -1092,6 +1147,7 @@ impl TestWasmExecutorCore {
                 on_reply: closure.clone(),
                 on_reject: closure,
                 on_cleanup: None,
+                deadline: NO_DEADLINE,
             })
             .map_err(|err| err.to_string())?;
         let request = Request {
@@ -1101,6 +1157,7 @@ impl TestWasmExecutorCore {
             payment: Cycles::zero(),
             method_name: "update".into(),
             method_payload: encode_message_id_as_payload(call_message_id),
+            deadline: NO_DEADLINE,
         };
         if let Err(req) = system_state.push_output_request(
             canister_current_memory_usage,
@@ -1147,8 +1204,11 @@ impl TestWasmExecutorCore {
                 (message_id, message, Some(*call_context_id))
             }
             ApiType::SystemTask {
-                call_context_id, ..
+                system_task,
+                call_context_id,
+                ..
             } => {
+                self.executed_system_tasks.push((canister_id, system_task.clone()));
                 let message_id = self.next_message_id();
                 let message = self
                     .system_tasks
@@ -6,7 +6,7 @@ use super::{
 use crate::scheduler::test_utilities::{on_response, other_side};
 use candid::Encode;
 use ic_btc_types::NetworkInRequest;
-use ic_config::subnet_config::{CyclesAccountManagerConfig, SchedulerConfig};
+use ic_config::subnet_config::{CyclesAccountManagerConfig, SchedulerConfig, SystemTaskPriority};
 use ic_ic00_types::{BitcoinGetBalanceArgs, CanisterIdRecord, EmptyBlob, Method, Payload as _};
 use ic_interfaces::execution_environment::SubnetAvailableMemory;
 use ic_logger::replica_logger::no_op_logger;
@@ -27,7 +27,7 @@ use ic_test_utilities::{
 use ic_test_utilities_metrics::{fetch_int_gauge, fetch_int_gauge_vec, metric_vec};
 use ic_types::messages::{Payload, MAX_RESPONSE_COUNT_BYTES};
 use ic_types::methods::SystemMethod;
-use ic_types::{time::UNIX_EPOCH, ComputeAllocation, Cycles, NumBytes};
+use ic_types::{time::UNIX_EPOCH, ComputeAllocation, Cycles, NumBytes, MAX_WASM_MEMORY_IN_BYTES};
 use proptest::prelude::*;
 use std::collections::HashMap;
 use std::{cmp::min, ops::Range};
@@ -1275,6 +1275,107 @@ fn global_timer_is_not_scheduled_if_global_timer_method_is_not_exported() {
     assert_eq!(metrics.round_inner.messages.get_sample_sum(), 1.0);
 }
 
+fn executed_system_tasks_with_priority(priority: SystemTaskPriority) -> Vec<SystemMethod> {
+    let mut test = SchedulerTestBuilder::new()
+        .with_scheduler_config(SchedulerConfig {
+            system_task_priority: priority,
+            ..SchedulerConfig::application_subnet()
+        })
+        .build();
+    let canister = test.create_canister_with(
+        Cycles::new(1_000_000_000_000),
+        ComputeAllocation::zero(),
+        MemoryAllocation::BestEffort,
+        Some(SystemMethod::CanisterHeartbeat),
+        None,
+    );
+    test.export_system_method(canister, SystemMethod::CanisterGlobalTimer);
+    test.set_canister_global_timer(canister, Time::from_nanos_since_unix_epoch(1));
+    test.set_time(Time::from_nanos_since_unix_epoch(1));
+
+    test.expect_heartbeat(canister, instructions(1));
+    test.expect_global_timer(canister, instructions(1));
+    test.execute_round(ExecutionRoundType::OrdinaryRound);
+    test.executed_system_tasks()
+        .into_iter()
+        .map(|(canister_id, method)| {
+            assert_eq!(canister_id, canister);
+            method
+        })
+        .collect()
+}
+
+#[test]
+fn global_timer_runs_before_heartbeat_by_default() {
+    let priority = SchedulerConfig::application_subnet().system_task_priority;
+    assert_eq!(priority, SystemTaskPriority::GlobalTimerFirst);
+    assert_eq!(
+        executed_system_tasks_with_priority(priority),
+        vec![SystemMethod::CanisterGlobalTimer, SystemMethod::CanisterHeartbeat]
+    );
+}
+
+#[test]
+fn heartbeat_runs_before_global_timer_if_configured() {
+    assert_eq!(
+        executed_system_tasks_with_priority(SystemTaskPriority::HeartbeatFirst),
+        vec![SystemMethod::CanisterHeartbeat, SystemMethod::CanisterGlobalTimer]
+    );
+}
+
+#[test]
+fn execute_on_low_wasm_memory_hook_once_until_memory_recovers() {
+    let mut test = SchedulerTestBuilder::new().build();
+    let canister = test.create_canister_with(
+        Cycles::new(1_000_000_000_000),
+        ComputeAllocation::zero(),
+        MemoryAllocation::BestEffort,
+        Some(SystemMethod::CanisterOnLowWasmMemory),
+        None,
+    );
+    let low_wasm_memory_size = NumBytes::from(MAX_WASM_MEMORY_IN_BYTES - (1 << 16));
+    test.set_canister_wasm_memory_threshold(canister, NumBytes::from(1 << 20));
+    test.set_canister_wasm_memory_size(canister, low_wasm_memory_size);
+
+    test.expect_on_low_wasm_memory(canister, instructions(1));
+    test.execute_round(ExecutionRoundType::OrdinaryRound);
+    assert!(test.canister_state(canister).system_state.on_low_wasm_memory_hook_executed);
+
+    // The hook does not run again while the remaining Wasm memory is low.
+    test.send_ingress(canister, ingress(1));
+    test.execute_round(ExecutionRoundType::OrdinaryRound);
+    let metrics = &test.scheduler().metrics;
+    assert_eq!(metrics.round_inner.messages.get_sample_sum(), 2.0);
+
+    // The hook runs again after the remaining Wasm memory recovers.
+    test.set_canister_wasm_memory_size(canister, NumBytes::from(0));
+    test.execute_round(ExecutionRoundType::OrdinaryRound);
+    assert!(!test.canister_state(canister).system_state.on_low_wasm_memory_hook_executed);
+    test.set_canister_wasm_memory_size(canister, low_wasm_memory_size);
+    test.expect_on_low_wasm_memory(canister, instructions(1));
+    test.execute_round(ExecutionRoundType::OrdinaryRound);
+    let metrics = &test.scheduler().metrics;
+    assert_eq!(metrics.round_inner.messages.get_sample_sum(), 3.0);
+}
+
+#[test]
+fn on_low_wasm_memory_hook_is_not_scheduled_above_threshold() {
+    let mut test = SchedulerTestBuilder::new().build();
+    let canister = test.create_canister_with(
+        Cycles::new(1_000_000_000_000),
+        ComputeAllocation::zero(),
+        MemoryAllocation::BestEffort,
+        Some(SystemMethod::CanisterOnLowWasmMemory),
+        None,
+    );
+    test.set_canister_wasm_memory_threshold(canister, NumBytes::from(1 << 20));
+
+    test.send_ingress(canister, ingress(1));
+    test.execute_round(ExecutionRoundType::OrdinaryRound);
+    let metrics = &test.scheduler().metrics;
+    assert_eq!(metrics.round_inner.messages.get_sample_sum(), 1.0);
+}
+
 #[test]
 fn execute_heartbeat_before_messages() {
     // This test sets up a canister on a system subnet with a heartbeat method and
@@ -1693,12 +1794,249 @@ fn can_record_metrics_for_a_round() {
     assert_eq!(metrics.round_finalization_ingress.get_sample_count(), 1);
     assert_eq!(metrics.round_finalization_charge.get_sample_count(), 1);
     assert_eq!(metrics.canister_compute_allocation_violation.get(), 1);
+    let mut allocations = vec![];
+    let mut full_execution_rounds = 0;
+    for canister_id in test.state().canister_states.keys() {
+        let canister_id = canister_id.to_string();
+        let labels = [canister_id.as_str()];
+        allocations.push(
+            metrics
+                .canister_compute_allocation_percent
+                .with_label_values(&labels)
+                .get(),
+        );
+        assert_eq!(
+            metrics
+                .canister_compute_allocation_executable_rounds
+                .with_label_values(&labels)
+                .get(),
+            1
+        );
+        full_execution_rounds += metrics
+            .canister_compute_allocation_full_execution_rounds
+            .with_label_values(&labels)
+            .get();
+    }
+    allocations.sort_unstable();
+    assert_eq!(allocations, vec![9, 45, 45]);
+    assert_eq!(full_execution_rounds, 2);
     assert_eq!(
         metrics.canister_messages_where_cycles_were_charged.get(),
         10
     );
 }
 
+#[test]
+fn canister_with_compute_allocation_achieves_it_under_load() {
+    let mut test = SchedulerTestBuilder::new()
+        .with_scheduler_config(SchedulerConfig {
+            scheduler_cores: 2,
+            max_instructions_per_round: NumInstructions::from(10),
+            max_instructions_per_message: NumInstructions::from(10),
+            max_instructions_per_message_without_dts: NumInstructions::new(10),
+            max_instructions_per_slice: NumInstructions::from(10),
+            instruction_overhead_per_message: NumInstructions::from(0),
+            instruction_overhead_per_canister_for_finalization: NumInstructions::from(0),
+            ..SchedulerConfig::application_subnet()
+        })
+        .build();
+
+    // One canister with a compute allocation of 50% competes with four
+    // best-effort canisters for a single allocatable core.
+    let mut allocated_canister = None;
+    for i in 0..5 {
+        let compute_allocation = if i == 0 { 50 } else { 0 };
+        let canister = test.create_canister_with(
+            Cycles::new(1_000_000_000_000_000),
+            ComputeAllocation::try_from(compute_allocation).unwrap(),
+            MemoryAllocation::BestEffort,
+            None,
+            None,
+        );
+        for _ in 0..100 {
+            test.send_ingress(canister, ingress(10));
+        }
+        allocated_canister.get_or_insert(canister);
+    }
+    let allocated_canister = allocated_canister.unwrap();
+
+    // In round 0 all canisters look as if they had a full execution.
+    test.advance_to_round(ExecutionRound::from(1));
+    let rounds = 20;
+    let mut full_execution_rounds = 0;
+    for _ in 0..rounds {
+        test.execute_round(ExecutionRoundType::OrdinaryRound);
+        let scheduler_state = &test.canister_state(allocated_canister).scheduler_state;
+        if scheduler_state.last_full_execution_round == test.last_round() {
+            full_execution_rounds += 1;
+        }
+    }
+    assert!(full_execution_rounds * 2 >= rounds);
+
+    let metrics = &test.scheduler().metrics;
+    let canister_id = allocated_canister.to_string();
+    let labels = [canister_id.as_str()];
+    assert_eq!(
+        metrics
+            .canister_compute_allocation_percent
+            .with_label_values(&labels)
+            .get(),
+        50
+    );
+    assert_eq!(
+        metrics
+            .canister_compute_allocation_executable_rounds
+            .with_label_values(&labels)
+            .get(),
+        rounds
+    );
+    assert_eq!(
+        metrics
+            .canister_compute_allocation_full_execution_rounds
+            .with_label_values(&labels)
+            .get(),
+        full_execution_rounds
+    );
+}
+
+/// Returns the number of rounds in which a message that was sent to the NNS
+/// governance canister at the start of the round got executed, while four
+/// other canisters saturate both scheduler cores.
+fn governance_rounds_with_execution_under_load(
+    system_canister_priority_lane: FlagStatus,
+    rounds: usize,
+) -> usize {
+    let mut test = SchedulerTestBuilder::new()
+        .with_scheduler_config(SchedulerConfig {
+            scheduler_cores: 2,
+            max_instructions_per_round: NumInstructions::from(10),
+            max_instructions_per_message: NumInstructions::from(10),
+            max_instructions_per_message_without_dts: NumInstructions::new(10),
+            max_instructions_per_slice: NumInstructions::from(10),
+            instruction_overhead_per_message: NumInstructions::from(0),
+            instruction_overhead_per_canister_for_finalization: NumInstructions::from(0),
+            system_canister_priority_lane,
+            ..SchedulerConfig::application_subnet()
+        })
+        .build();
+
+    // The first canisters get the ids of the NNS registry, governance, and
+    // ledger canisters. Only the governance canister receives messages.
+    let mut canisters = vec![];
+    for _ in 0..7 {
+        canisters.push(test.create_canister_with(
+            Cycles::new(1_000_000_000_000_000),
+            ComputeAllocation::zero(),
+            MemoryAllocation::BestEffort,
+            None,
+            None,
+        ));
+    }
+    let governance = canisters[1];
+    assert_eq!(governance, GOVERNANCE_CANISTER_ID);
+    for canister in &canisters[3..] {
+        for _ in 0..100 {
+            test.send_ingress(*canister, ingress(10));
+        }
+    }
+
+    // In round 0 all canisters look as if they had a full execution.
+    test.advance_to_round(ExecutionRound::from(1));
+    let mut rounds_with_execution = 0;
+    for _ in 0..rounds {
+        test.send_ingress(governance, ingress(10));
+        test.execute_round(ExecutionRoundType::OrdinaryRound);
+        let scheduler_state = &test.canister_state(governance).scheduler_state;
+        if scheduler_state.last_full_execution_round == test.last_round() {
+            rounds_with_execution += 1;
+        }
+    }
+    rounds_with_execution
+}
+
+#[test]
+fn system_canister_priority_lane_bounds_latency_under_load() {
+    assert_eq!(
+        SchedulerConfig::system_subnet().system_canister_priority_lane,
+        FlagStatus::Enabled
+    );
+    // Every message to the governance canister is executed in the round in
+    // which it arrives.
+    assert_eq!(
+        governance_rounds_with_execution_under_load(FlagStatus::Enabled, 20),
+        20
+    );
+}
+
+#[test]
+fn system_canisters_share_cores_fairly_without_priority_lane() {
+    assert_eq!(
+        SchedulerConfig::application_subnet().system_canister_priority_lane,
+        FlagStatus::Disabled
+    );
+    assert!(governance_rounds_with_execution_under_load(FlagStatus::Disabled, 20) < 20);
+}
+
+/// Returns the number of the round, counting from one, in which the global
+/// timer of a canister ran that became due in the first round, while twenty
+/// other canisters saturate both scheduler cores. A round takes one second.
+fn rounds_until_global_timer_runs_under_load(max_global_timer_delay: Duration) -> usize {
+    let mut test = SchedulerTestBuilder::new()
+        .with_scheduler_config(SchedulerConfig {
+            scheduler_cores: 2,
+            max_instructions_per_round: NumInstructions::from(10),
+            max_instructions_per_message: NumInstructions::from(10),
+            max_instructions_per_message_without_dts: NumInstructions::new(10),
+            max_instructions_per_slice: NumInstructions::from(10),
+            instruction_overhead_per_message: NumInstructions::from(0),
+            instruction_overhead_per_canister_for_finalization: NumInstructions::from(0),
+            max_global_timer_delay,
+            ..SchedulerConfig::application_subnet()
+        })
+        .build();
+
+    for _ in 0..20 {
+        let canister = test.create_canister();
+        for _ in 0..20 {
+            test.send_ingress(canister, ingress(10));
+        }
+    }
+    // The canister with the timer is created last, so it loses the ties of
+    // the accumulated priorities.
+    let canister = test.create_canister_with(
+        Cycles::new(1_000_000_000_000_000),
+        ComputeAllocation::zero(),
+        MemoryAllocation::BestEffort,
+        Some(SystemMethod::CanisterGlobalTimer),
+        None,
+    );
+    let start = UNIX_EPOCH + Duration::from_secs(1);
+    test.set_canister_global_timer(canister, start);
+    test.expect_global_timer(canister, instructions(1));
+
+    // In round 0 all canisters look as if they had a full execution.
+    test.advance_to_round(ExecutionRound::from(1));
+    for round in 0..20 {
+        test.set_time(start + Duration::from_secs(round as u64));
+        test.execute_round(ExecutionRoundType::OrdinaryRound);
+        if !test.executed_system_tasks().is_empty() {
+            return round + 1;
+        }
+    }
+    panic!("The global timer did not run within 20 rounds");
+}
+
+#[test]
+fn overdue_global_timer_runs_within_max_delay_under_load() {
+    // The timer becomes overdue in the third round.
+    assert_eq!(rounds_until_global_timer_runs_under_load(Duration::from_secs(2)), 3);
+}
+
+#[test]
+fn global_timer_waits_for_its_turn_under_load_without_max_delay() {
+    assert!(rounds_until_global_timer_runs_under_load(Duration::from_secs(1_000)) > 3);
+}
+
 #[test]
 fn heap_delta_rate_limiting_metrics_recorded() {
     let scheduler_config = SchedulerConfig {
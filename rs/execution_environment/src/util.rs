@@ -8,6 +8,7 @@ use ic_replicated_state::{CanisterStatus, ReplicatedState};
 use ic_types::{
     ingress::{IngressState, IngressStatus, WasmResult},
     messages::{Payload, StopCanisterContext},
+    time::NO_DEADLINE,
     CanisterId,
 };
 use std::{mem, sync::Arc};
@@ -98,6 +99,7 @@ pub fn process_stopping_canisters(
                             originator_reply_callback: reply_callback,
                             refund: cycles,
                             response_payload: Payload::Data(EmptyBlob.encode()),
+                            deadline: NO_DEADLINE,
                         };
                         state.push_subnet_output_response(response.into());
                     }
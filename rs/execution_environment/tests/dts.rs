@@ -106,8 +106,18 @@ fn dts_env(
     message_instruction_limit: NumInstructions,
     slice_instruction_limit: NumInstructions,
 ) -> StateMachine {
+    StateMachine::new_with_config(dts_config(
+        message_instruction_limit,
+        slice_instruction_limit,
+    ))
+}
+
+fn dts_config(
+    message_instruction_limit: NumInstructions,
+    slice_instruction_limit: NumInstructions,
+) -> StateMachineConfig {
     let subnet_config = SubnetConfigs::default().own_subnet_config(SubnetType::Application);
-    StateMachine::new_with_config(StateMachineConfig::new(
+    StateMachineConfig::new(
         SubnetConfig {
             scheduler_config: SchedulerConfig {
                 max_instructions_per_install_code: message_instruction_limit,
@@ -125,7 +135,7 @@ fn dts_env(
             deterministic_time_slicing: FlagStatus::Enabled,
             ..Default::default()
         },
-    ))
+    )
 }
 
 fn dts_install_code_env(
@@ -230,6 +240,10 @@ fn setup_dts_install_code(
             compute_allocation: Some(1u32.into()),
             memory_allocation: None,
             freezing_threshold: Some(freezing_threshold_in_seconds.into()),
+            log_visibility: None,
+            wasm_memory_threshold: None,
+            reserved_cycles_limit: None,
+            wasm_memory_limit: None,
         }),
     );
 
@@ -434,6 +448,10 @@ fn dts_pending_upgrade_with_heartbeat() {
         compute_allocation: None,
         memory_allocation: None,
         freezing_threshold: None,
+        log_visibility: None,
+        wasm_memory_threshold: None,
+        reserved_cycles_limit: None,
+        wasm_memory_limit: None,
     });
 
     let canister = env
@@ -531,6 +549,10 @@ fn dts_scheduling_of_install_code() {
         compute_allocation: None,
         memory_allocation: None,
         freezing_threshold: None,
+        log_visibility: None,
+        wasm_memory_threshold: None,
+        reserved_cycles_limit: None,
+        wasm_memory_limit: None,
     });
 
     let n = 10;
@@ -693,6 +715,10 @@ fn dts_pending_install_code_does_not_block_subnet_messages_of_other_canisters()
         compute_allocation: None,
         memory_allocation: None,
         freezing_threshold: None,
+        log_visibility: None,
+        wasm_memory_threshold: None,
+        reserved_cycles_limit: None,
+        wasm_memory_limit: None,
     });
 
     let mut canister = vec![];
@@ -812,6 +838,10 @@ fn dts_pending_execution_blocks_subnet_messages_to_the_same_canister() {
         compute_allocation: None,
         memory_allocation: None,
         freezing_threshold: None,
+        log_visibility: None,
+        wasm_memory_threshold: None,
+        reserved_cycles_limit: None,
+        wasm_memory_limit: None,
     });
 
     let canister = env
@@ -903,6 +933,10 @@ fn dts_pending_install_code_blocks_update_messages_to_the_same_canister() {
         compute_allocation: None,
         memory_allocation: None,
         freezing_threshold: None,
+        log_visibility: None,
+        wasm_memory_threshold: None,
+        reserved_cycles_limit: None,
+        wasm_memory_limit: None,
     });
 
     let canister = env.create_canister_with_cycles(INITIAL_CYCLES_BALANCE, settings);
@@ -989,6 +1023,10 @@ fn dts_long_running_install_and_update() {
             compute_allocation: None,
             memory_allocation: None,
             freezing_threshold: None,
+            log_visibility: None,
+            wasm_memory_threshold: None,
+            reserved_cycles_limit: None,
+            wasm_memory_limit: None,
         });
 
         let id = env
@@ -1177,6 +1215,68 @@ fn dts_long_running_calls() {
     }
 }
 
+#[test]
+fn dts_call_in_flight_survives_replica_upgrade() {
+    if should_skip_test_due_to_disabled_dts() {
+        // Skip this test if DTS is not supported.
+        return;
+    }
+
+    let message_instruction_limit = NumInstructions::from(1_000_000_000);
+    let slice_instruction_limit = NumInstructions::from(10_000);
+    let env = dts_env(message_instruction_limit, slice_instruction_limit);
+
+    let binary = UNIVERSAL_CANISTER_WASM.to_vec();
+
+    let user_id = PrincipalId::new_anonymous();
+
+    let a_id = env
+        .install_canister_with_cycles(binary.clone(), vec![], None, INITIAL_CYCLES_BALANCE)
+        .unwrap();
+
+    let b_id = env
+        .install_canister_with_cycles(binary, vec![], None, INITIAL_CYCLES_BALANCE)
+        .unwrap();
+
+    let b = wasm()
+        .stable64_grow(1)
+        .stable64_fill(0, 0, 10_000)
+        .stable64_fill(0, 0, 10_000)
+        .push_bytes(&[42])
+        .append_and_reply()
+        .build();
+
+    let a = wasm()
+        .stable64_grow(1)
+        .stable64_fill(0, 0, 10_000)
+        .stable64_fill(0, 0, 10_000)
+        .call_simple(
+            b_id,
+            "update",
+            call_args()
+                .other_side(b)
+                .on_reply(wasm().message_payload().append_and_reply()),
+        )
+        .build();
+
+    let update = env.send_ingress(user_id, a_id, "update", a);
+
+    env.tick();
+
+    assert_eq!(
+        ingress_state(env.ingress_status(&update)),
+        Some(IngressState::Processing)
+    );
+
+    let env = env.upgrade_replica(Some(dts_config(
+        message_instruction_limit,
+        slice_instruction_limit,
+    )));
+
+    let result = env.await_ingress(update, 100).unwrap();
+    assert_eq!(result, WasmResult::Reply(vec![42]));
+}
+
 #[test]
 fn dts_unrelated_subnet_messages_make_progress() {
     if should_skip_test_due_to_disabled_dts() {
@@ -1199,6 +1299,10 @@ fn dts_unrelated_subnet_messages_make_progress() {
         compute_allocation: None,
         memory_allocation: None,
         freezing_threshold: None,
+        log_visibility: None,
+        wasm_memory_threshold: None,
+        reserved_cycles_limit: None,
+        wasm_memory_limit: None,
     });
 
     let canister = env
@@ -1607,6 +1711,10 @@ fn dts_canister_uninstalled_due_to_resource_charges_with_aborted_updrade() {
         compute_allocation: Some(1u32.into()),
         memory_allocation: None,
         freezing_threshold: None,
+        log_visibility: None,
+        wasm_memory_threshold: None,
+        reserved_cycles_limit: None,
+        wasm_memory_limit: None,
     });
 
     let canister = env
@@ -1674,6 +1782,10 @@ fn dts_canister_uninstalled_due_resource_charges_with_aborted_update() {
             compute_allocation: Some(1u32.into()),
             memory_allocation: None,
             freezing_threshold: None,
+            log_visibility: None,
+            wasm_memory_threshold: None,
+            reserved_cycles_limit: None,
+            wasm_memory_limit: None,
         });
 
         let id = env
@@ -1,14 +1,18 @@
 use ic_config::{
-    execution_environment::Config as HypervisorConfig,
+    execution_environment::{ChaosConfig, Config as HypervisorConfig},
     subnet_config::{CyclesAccountManagerConfig, SubnetConfigs},
 };
+use ic_ic00_types::{
+    self as ic00, ECDSAPublicKeyArgs, ECDSAPublicKeyResponse, EcdsaCurve, Method, Payload,
+    SignWithECDSAArgs, SignWithECDSAReply,
+};
 use ic_registry_subnet_type::SubnetType;
 use ic_state_machine_tests::{
-    CanisterSettingsArgs, ErrorCode, PrincipalId, StateMachine, StateMachineConfig, SubnetId,
-    UserError,
+    CanisterSettingsArgs, EcdsaKeyId, ErrorCode, PrincipalId, StateMachine, StateMachineBuilder,
+    StateMachineConfig, SubnetId, UserError,
 };
 use ic_types::{ingress::WasmResult, Cycles, NumBytes};
-use ic_universal_canister::{wasm, UNIVERSAL_CANISTER_WASM};
+use ic_universal_canister::{call_args, wasm, UNIVERSAL_CANISTER_WASM};
 use std::{convert::TryInto, time::Duration};
 
 const INITIAL_CYCLES_BALANCE: Cycles = Cycles::new(100_000_000_000_000);
@@ -321,6 +325,10 @@ fn test_canister_out_of_cycles() {
             compute_allocation: Some(candid::Nat::from(1)),
             memory_allocation: None,
             freezing_threshold: None,
+            log_visibility: None,
+            wasm_memory_threshold: None,
+            reserved_cycles_limit: None,
+            wasm_memory_limit: None,
         }),
     );
 
@@ -384,6 +392,10 @@ fn canister_has_zero_balance_when_uninstalled_due_to_low_cycles() {
                 compute_allocation: Some(candid::Nat::from(1)),
                 memory_allocation: None,
                 freezing_threshold: None,
+                log_visibility: None,
+                wasm_memory_threshold: None,
+                reserved_cycles_limit: None,
+                wasm_memory_limit: None,
             }),
             INITIAL_CYCLES_BALANCE,
         )
@@ -679,6 +691,10 @@ fn exceeding_memory_capacity_fails_when_memory_allocation_changes() {
                 compute_allocation: None,
                 memory_allocation: None,
                 freezing_threshold: None,
+                log_visibility: None,
+                wasm_memory_threshold: None,
+                reserved_cycles_limit: None,
+                wasm_memory_limit: None,
             }),
             INITIAL_CYCLES_BALANCE,
         )
@@ -694,6 +710,10 @@ fn exceeding_memory_capacity_fails_when_memory_allocation_changes() {
                 compute_allocation: None,
                 memory_allocation: Some(candid::Nat::from(20u64 * 1024 * 1024 + 1)),
                 freezing_threshold: None,
+                log_visibility: None,
+                wasm_memory_threshold: None,
+                reserved_cycles_limit: None,
+                wasm_memory_limit: None,
             },
         )
         .unwrap_err();
@@ -708,6 +728,10 @@ fn exceeding_memory_capacity_fails_when_memory_allocation_changes() {
             compute_allocation: None,
             memory_allocation: Some(candid::Nat::from(20u64 * 1024 * 1024)),
             freezing_threshold: None,
+            log_visibility: None,
+            wasm_memory_threshold: None,
+            reserved_cycles_limit: None,
+            wasm_memory_limit: None,
         },
     )
     .unwrap();
@@ -756,6 +780,10 @@ fn exceeding_memory_capacity_fails_during_message_execution() {
                 compute_allocation: None,
                 memory_allocation: None,
                 freezing_threshold: None,
+                log_visibility: None,
+                wasm_memory_threshold: None,
+                reserved_cycles_limit: None,
+                wasm_memory_limit: None,
             }),
             INITIAL_CYCLES_BALANCE,
         )
@@ -821,6 +849,10 @@ fn max_canister_memory_respected_even_when_no_memory_allocation_is_set() {
                 compute_allocation: None,
                 memory_allocation: None,
                 freezing_threshold: None,
+                log_visibility: None,
+                wasm_memory_threshold: None,
+                reserved_cycles_limit: None,
+                wasm_memory_limit: None,
             }),
             INITIAL_CYCLES_BALANCE,
         )
@@ -844,3 +876,200 @@ fn max_canister_memory_respected_even_when_no_memory_allocation_is_set() {
     );
     assert_replied(res, 0);
 }
+
+const PROFILED_CANISTER: &str = r#"
+(module
+    (import "ic0" "msg_reply" (func $msg_reply))
+    (func $work (param $n i32)
+        (loop $loop
+            (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+            (br_if $loop (local.get $n))))
+    (func $update
+        (call $work (i32.const 1000))
+        (call $msg_reply))
+    (export "canister_update update" (func $update)))
+"#;
+
+#[test]
+fn instruction_profile_counts_instructions_per_function() {
+    let env = StateMachineBuilder::new()
+        .with_instruction_profiling(true)
+        .build();
+    let wasm = wabt::wat2wasm(PROFILED_CANISTER).expect("invalid WAT");
+    let canister_id = env.install_canister(wasm, vec![], None).unwrap();
+
+    env.execute_ingress(canister_id, "update", vec![]).unwrap();
+
+    let profile = env.instruction_profile(canister_id).unwrap();
+    // The non-exported `$work` function is reported by its index.
+    let work = profile.get("func_1").unwrap();
+    let update = profile.get("canister_update update").unwrap();
+    assert!(work.get() >= 1000);
+    assert!(update.get() > 0);
+    assert!(work > update);
+    assert_eq!(
+        profile.to_folded_stacks(),
+        format!("func_1 {}\ncanister_update_update {}\n", work, update)
+    );
+}
+
+#[test]
+fn instruction_profile_is_empty_without_profiling() {
+    let env = StateMachine::new();
+    let wasm = wabt::wat2wasm(PROFILED_CANISTER).expect("invalid WAT");
+    let canister_id = env.install_canister(wasm, vec![], None).unwrap();
+
+    env.execute_ingress(canister_id, "update", vec![]).unwrap();
+
+    assert!(env.instruction_profile(canister_id).unwrap().is_empty());
+}
+
+fn chaos_updates_outcomes(chaos_mode: ChaosConfig, updates: usize) -> Vec<bool> {
+    let env = StateMachineBuilder::new().with_chaos_mode(chaos_mode).build();
+    let canister_id = env
+        .install_canister(UNIVERSAL_CANISTER_WASM.into(), vec![], None)
+        .unwrap();
+    (0..updates)
+        .map(|_| env.execute_ingress(canister_id, "update", wasm().reply().build()).is_ok())
+        .collect()
+}
+
+#[test]
+fn chaos_mode_injects_traps_into_updates() {
+    let env = StateMachineBuilder::new()
+        .with_chaos_mode(ChaosConfig {
+            trap_probability_ppm: 1_000_000,
+            ..ChaosConfig::default()
+        })
+        .build();
+    let canister_id = env
+        .install_canister(UNIVERSAL_CANISTER_WASM.into(), vec![], None)
+        .unwrap();
+
+    let err = env
+        .execute_ingress(canister_id, "update", wasm().reply().build())
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::CanisterCalledTrap);
+
+    // Queries are never affected.
+    env.query(canister_id, "query", wasm().reply().build()).unwrap();
+}
+
+#[test]
+fn chaos_mode_replays_the_same_faults_with_the_same_seed() {
+    let chaos_mode = ChaosConfig {
+        seed: 42,
+        trap_probability_ppm: 500_000,
+        ..ChaosConfig::default()
+    };
+    let first = chaos_updates_outcomes(chaos_mode, 30);
+    let second = chaos_updates_outcomes(chaos_mode, 30);
+    assert_eq!(first, second);
+    assert!(first.iter().any(|ok| *ok));
+    assert!(first.iter().any(|ok| !ok));
+}
+
+#[test]
+fn chaos_mode_delayed_responses_are_delivered() {
+    let env = StateMachineBuilder::new()
+        .with_chaos_mode(ChaosConfig {
+            delay_response_probability_ppm: 1_000_000,
+            reorder_probability_ppm: 1_000_000,
+            ..ChaosConfig::default()
+        })
+        .build();
+    let caller = env
+        .install_canister(UNIVERSAL_CANISTER_WASM.into(), vec![], None)
+        .unwrap();
+    let callee = env
+        .install_canister(UNIVERSAL_CANISTER_WASM.into(), vec![], None)
+        .unwrap();
+
+    let result = env
+        .execute_ingress(
+            caller,
+            "update",
+            wasm()
+                .inter_update(callee, call_args().other_side(wasm().reply_data(b"pong")))
+                .build(),
+        )
+        .unwrap();
+    assert_eq!(result, WasmResult::Reply(b"pong".to_vec()));
+}
+
+#[test]
+fn state_machine_signs_with_ecdsa_keys_derived_for_the_caller() {
+    let key_id = EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: "test_key".to_string(),
+    };
+    let derivation_path = vec![b"path".to_vec()];
+    let message_hash = [7; 32];
+    let env = StateMachineBuilder::new()
+        .with_ecdsa_key(key_id.clone())
+        .build();
+    let canister_id = env
+        .install_canister_with_cycles(
+            UNIVERSAL_CANISTER_WASM.into(),
+            vec![],
+            None,
+            INITIAL_CYCLES_BALANCE,
+        )
+        .unwrap();
+
+    // The helper derives the same key as the management canister.
+    let public_key_args = ECDSAPublicKeyArgs {
+        canister_id: None,
+        derivation_path: derivation_path.clone(),
+        key_id: key_id.clone(),
+    };
+    let result = env
+        .execute_ingress(
+            canister_id,
+            "update",
+            wasm()
+                .call_simple(
+                    ic00::IC_00,
+                    Method::ECDSAPublicKey,
+                    call_args().other_side(public_key_args.encode()),
+                )
+                .build(),
+        )
+        .unwrap();
+    let response = match result {
+        WasmResult::Reply(bytes) => ECDSAPublicKeyResponse::decode(&bytes).unwrap(),
+        WasmResult::Reject(msg) => panic!("Unexpected reject: {}", msg),
+    };
+    let public_key = env.ecdsa_public_key(&key_id, canister_id, derivation_path.clone());
+    assert_eq!(response.public_key, public_key.public_key);
+    assert_eq!(response.chain_code, public_key.chain_key);
+
+    // The pending signing request is answered with a signature of that key.
+    let sign_args = SignWithECDSAArgs {
+        message_hash,
+        derivation_path,
+        key_id,
+    };
+    let msg_id = env.send_ingress(
+        PrincipalId::new_anonymous(),
+        canister_id,
+        "update",
+        wasm()
+            .call_with_cycles(
+                ic00::IC_00,
+                Method::SignWithECDSA,
+                call_args().other_side(sign_args.encode()),
+                Cycles::new(10_000_000_000).into_parts(),
+            )
+            .build(),
+    );
+    env.tick();
+    assert_eq!(env.sign_with_ecdsa_contexts().len(), 1);
+    env.sign_pending_ecdsa_requests();
+    let signature = match env.await_ingress(msg_id, 10).unwrap() {
+        WasmResult::Reply(bytes) => SignWithECDSAReply::decode(&bytes).unwrap().signature,
+        WasmResult::Reject(msg) => panic!("Unexpected reject: {}", msg),
+    };
+    assert!(env.verify_ecdsa_signature(&public_key.public_key, &message_hash, &signature));
+    assert!(!env.verify_ecdsa_signature(&public_key.public_key, &[8; 32], &signature));
+}
@@ -194,6 +194,10 @@ fn simulate_one_gib_per_second_cost(
             compute_allocation: Some(candid::Nat::from(compute_allocation.as_percent())),
             memory_allocation: Some(candid::Nat::from(one_gib)),
             freezing_threshold: None,
+            log_visibility: None,
+            wasm_memory_threshold: None,
+            reserved_cycles_limit: None,
+            wasm_memory_limit: None,
         }),
     );
 
@@ -638,6 +642,7 @@ fn get_cycles_account_manager_config(subnet_type: SubnetType) -> CyclesAccountMa
             ingress_byte_reception_fee: Cycles::new(0),
             gib_storage_per_second_fee: Cycles::new(0),
             duration_between_allocation_charges: Duration::from_secs(10),
+            max_storage_reservation_period: Duration::from_secs(0),
             /// The ECDSA signature fee is the fee charged when creating a
             /// signature on this subnet. The request likely came from a
             /// different subnet which is not a system subnet. There is an
@@ -664,6 +669,7 @@ fn get_cycles_account_manager_config(subnet_type: SubnetType) -> CyclesAccountMa
             // 4 SDR per GiB per year => 4e12 Cycles per year
             gib_storage_per_second_fee: Cycles::new(127_000),
             duration_between_allocation_charges: Duration::from_secs(10),
+            max_storage_reservation_period: Duration::from_secs(300_000_000),
             ecdsa_signature_fee: ECDSA_SIGNATURE_FEE,
             http_request_baseline_fee: Cycles::new(400_000_000),
             http_request_per_byte_fee: Cycles::new(100_000),
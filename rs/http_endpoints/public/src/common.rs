@@ -117,7 +117,11 @@ pub(crate) fn make_response(user_error: UserError) -> Response<Body> {
         C::QueryCallGraphTooDeep => StatusCode::INTERNAL_SERVER_ERROR,
         C::QueryCallGraphTotalInstructionLimitExceeded => StatusCode::INTERNAL_SERVER_ERROR,
         C::CompositeQueryCalledInReplicatedMode => StatusCode::INTERNAL_SERVER_ERROR,
+        C::ReservedCyclesLimitExceededInMemoryGrow => StatusCode::INTERNAL_SERVER_ERROR,
+        C::InsufficientCyclesInMemoryGrow => StatusCode::INTERNAL_SERVER_ERROR,
+        C::CanisterWasmMemoryLimitExceeded => StatusCode::INTERNAL_SERVER_ERROR,
         C::CanisterNotHostedBySubnet => StatusCode::NOT_FOUND,
+        C::CanisterCallContextDeleted => StatusCode::SERVICE_UNAVAILABLE,
     };
     make_plaintext_response(status, user_error.description().to_string())
 }
@@ -1,7 +1,7 @@
 //! The execution environment public interface.
 mod errors;
 
-pub use errors::{CanisterOutOfCyclesError, HypervisorError, TrapCode};
+pub use errors::{CanisterOutOfCyclesError, HypervisorError, MemoryReservationError, TrapCode};
 use ic_base_types::NumBytes;
 use ic_error_types::UserError;
 use ic_ic00_types::EcdsaKeyId;
@@ -39,6 +39,44 @@ pub struct InstanceStats {
     pub dirty_pages: usize,
 }
 
+/// The number of instructions executed by each function of a canister during
+/// a message execution. It is only collected if instruction profiling is
+/// enabled in the embedders config, otherwise it is empty.
+///
+/// The counts are deterministic: they are derived from the same static costs
+/// that are charged for the execution.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct InstructionProfile {
+    /// The names of the executed functions together with the number of
+    /// instructions executed in the function itself, excluding the functions
+    /// it called.
+    pub functions: Vec<(String, NumInstructions)>,
+}
+
+impl InstructionProfile {
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    /// Returns the number of instructions executed in the given function.
+    pub fn get(&self, function: &str) -> Option<NumInstructions> {
+        self.functions
+            .iter()
+            .find(|(name, _)| name == function)
+            .map(|(_, instructions)| *instructions)
+    }
+
+    /// Returns the profile in the folded stacks format: one line per function
+    /// with its name and instruction count. The output can be rendered by
+    /// flamegraph tools, e.g. `inferno-flamegraph`.
+    pub fn to_folded_stacks(&self) -> String {
+        self.functions
+            .iter()
+            .map(|(name, instructions)| format!("{} {}\n", name.replace(' ', "_"), instructions))
+            .collect()
+    }
+}
+
 /// Errors that can be returned when fetching the available memory on a subnet.
 #[derive(Debug)]
 pub enum SubnetAvailableMemoryError {
@@ -50,6 +88,33 @@ pub enum SubnetAvailableMemoryError {
     },
 }
 
+impl From<SubnetAvailableMemoryError> for MemoryReservationError {
+    fn from(err: SubnetAvailableMemoryError) -> Self {
+        match err {
+            SubnetAvailableMemoryError::InsufficientMemory {
+                requested_total,
+                message_requested,
+                available_total,
+                available_messages,
+            } => {
+                let total_is_available = i64::try_from(requested_total.get())
+                    .map_or(false, |requested| requested <= available_total);
+                if total_is_available {
+                    Self::SubnetMessageMemoryCapacityExceeded {
+                        requested: message_requested,
+                        available: available_messages,
+                    }
+                } else {
+                    Self::SubnetMemoryCapacityExceeded {
+                        requested: requested_total,
+                        available: available_total,
+                    }
+                }
+            }
+        }
+    }
+}
+
 /// Performance counter type.
 #[derive(Debug)]
 pub enum PerformanceCounterType {
@@ -433,6 +498,13 @@ pub trait SystemApi {
         heap: &mut [u8],
     ) -> HypervisorResult<()>;
 
+    /// Returns the deadline of the message that is being executed in
+    /// nanoseconds since 1970-01-01, or 0 if the message is an ingress message,
+    /// a guaranteed response call, or a response to one.
+    ///
+    /// Traps if there is no incoming message, e.g., in a system task.
+    fn ic0_msg_deadline(&self) -> HypervisorResult<u64>;
+
     /// Returns the size of the blob corresponding to the id of the canister.
     fn ic0_canister_self_size(&self) -> HypervisorResult<usize>;
 
@@ -459,11 +531,17 @@ pub trait SystemApi {
         heap: &mut [u8],
     ) -> HypervisorResult<()>;
 
-    /// Outputs the specified bytes on the heap as a string on STDOUT.
-    fn ic0_debug_print(&self, src: u32, size: u32, heap: &[u8]) -> HypervisorResult<()>;
+    /// Outputs the specified bytes on the heap as a string on STDOUT and
+    /// appends them to the canister log.
+    fn ic0_debug_print(&mut self, src: u32, size: u32, heap: &[u8]) -> HypervisorResult<()>;
+
+    /// Appends the specified bytes on the heap to the canister log without
+    /// printing them, for subnets that rate limit debug prints.
+    fn save_debug_print(&mut self, src: u32, size: u32, heap: &[u8]);
 
-    /// Traps, with a possibly helpful message
-    fn ic0_trap(&self, src: u32, size: u32, heap: &[u8]) -> HypervisorResult<()>;
+    /// Traps, with a possibly helpful message that is appended to the
+    /// canister log.
+    fn ic0_trap(&mut self, src: u32, size: u32, heap: &[u8]) -> HypervisorResult<()>;
 
     /// Creates a pending inter-canister message that will be scheduled if the
     /// current message execution completes successfully.
@@ -518,6 +596,14 @@ pub trait SystemApi {
     /// See https://sdk.dfinity.org/docs/interface-spec/index.html#system-api-call
     fn ic0_call_on_cleanup(&mut self, fun: u32, env: u32) -> HypervisorResult<()>;
 
+    /// Turns the call under construction into a best-effort call that times
+    /// out after `timeout_seconds`, capped to 300 seconds. The caller receives
+    /// a `SYS_UNKNOWN` reject if no response arrives before the deadline.
+    ///
+    /// Traps if it is called more than once between `ic0.call_new` and
+    /// `ic0.call_perform`.
+    fn ic0_call_with_best_effort_response(&mut self, timeout_seconds: u32) -> HypervisorResult<()>;
+
     /// (deprecated) Please use `ic0_call_cycles_add128` instead, as this API
     /// can only add a 64-bit value.
     ///
@@ -554,6 +640,33 @@ pub trait SystemApi {
     /// `ic0.call_*` calls trap.
     fn ic0_call_perform(&mut self) -> HypervisorResult<i32>;
 
+    /// Copies the amount of cycles that a call with the given sizes of the
+    /// method name and the payload costs to the canister memory at `dst`.
+    /// The amount is a 128-bit value and includes the prepayment for the
+    /// response, but not the cycles attached to the call.
+    fn ic0_cost_call(
+        &self,
+        method_name_size: u64,
+        payload_size: u64,
+        dst: u32,
+        heap: &mut [u8],
+    ) -> HypervisorResult<()>;
+
+    /// Copies the amount of cycles that creating a canister costs to the
+    /// canister memory at `dst`. The amount is a 128-bit value.
+    fn ic0_cost_create_canister(&self, dst: u32, heap: &mut [u8]) -> HypervisorResult<()>;
+
+    /// Copies the amount of cycles that an HTTP outcall with the given request
+    /// size and maximum response size costs to the canister memory at `dst`.
+    /// The amount is a 128-bit value.
+    fn ic0_cost_http_request(
+        &self,
+        request_size: u64,
+        max_res_bytes: u64,
+        dst: u32,
+        heap: &mut [u8],
+    ) -> HypervisorResult<()>;
+
     /// Returns the current size of the stable memory in WebAssembly pages.
     fn ic0_stable_size(&self) -> HypervisorResult<u32>;
 
@@ -904,6 +1017,12 @@ pub struct WasmExecutionOutput {
     pub allocated_bytes: NumBytes,
     pub allocated_message_bytes: NumBytes,
     pub instance_stats: InstanceStats,
+    /// The debug prints and trap messages of the execution that go into the
+    /// canister log.
+    pub canister_log_records: Vec<Vec<u8>>,
+    /// The instructions executed by each function if instruction profiling is
+    /// enabled.
+    pub instruction_profile: InstructionProfile,
 }
 
 impl fmt::Display for WasmExecutionOutput {
@@ -1,6 +1,6 @@
 use ic_base_types::{CanisterIdError, PrincipalIdBlobParseError};
 use ic_error_types::UserError;
-use ic_types::{methods::WasmMethod, CanisterId, Cycles, NumInstructions};
+use ic_types::{methods::WasmMethod, CanisterId, Cycles, NumBytes, NumInstructions};
 use ic_wasm_types::{WasmEngineError, WasmInstrumentationError, WasmValidationError};
 use serde::{Deserialize, Serialize};
 
@@ -64,6 +64,59 @@ impl std::fmt::Display for CanisterOutOfCyclesError {
     }
 }
 
+/// Error when memory cannot be reserved for a canister, e.g. when it grows
+/// its Wasm or stable memory or pushes an output message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemoryReservationError {
+    /// The canister would exceed its memory allocation or, if it doesn't
+    /// have one, the memory limit of best-effort canisters.
+    CanisterMemoryLimitExceeded {
+        requested: NumBytes,
+        usage: NumBytes,
+        limit: NumBytes,
+    },
+    /// The subnet doesn't have enough memory available.
+    SubnetMemoryCapacityExceeded { requested: NumBytes, available: i64 },
+    /// The subnet doesn't have enough memory available for messages.
+    SubnetMessageMemoryCapacityExceeded { requested: NumBytes, available: i64 },
+}
+
+impl std::error::Error for MemoryReservationError {}
+
+impl std::fmt::Display for MemoryReservationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CanisterMemoryLimitExceeded {
+                requested,
+                usage,
+                limit,
+            } => write!(
+                f,
+                "requested {} bytes in addition to the {} bytes in use, but the limit is {} bytes",
+                requested, usage, limit
+            ),
+            Self::SubnetMemoryCapacityExceeded {
+                requested,
+                available,
+            } => write!(
+                f,
+                "requested {} bytes, but the subnet has only {} bytes available",
+                requested,
+                available.max(&0)
+            ),
+            Self::SubnetMessageMemoryCapacityExceeded {
+                requested,
+                available,
+            } => write!(
+                f,
+                "requested {} bytes for messages, but the subnet has only {} bytes available for messages",
+                requested,
+                available.max(&0)
+            ),
+        }
+    }
+}
+
 /// Errors returned by the Hypervisor.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum HypervisorError {
@@ -93,8 +146,8 @@ pub enum HypervisorError {
     /// contain a Wasm module.
     WasmModuleNotFound,
     /// An attempt was made to grow the canister's memory above its memory
-    /// allocation.
-    OutOfMemory,
+    /// allocation or above the memory available on the subnet.
+    OutOfMemory(MemoryReservationError),
     /// An attempt to perform an operation that isn't allowed when the canister
     /// is stopped.
     CanisterStopped,
@@ -108,8 +161,10 @@ pub enum HypervisorError {
     InvalidPrincipalId(PrincipalIdBlobParseError),
     /// The canister ID specified by the canister is invalid.
     InvalidCanisterId(CanisterIdError),
-    /// The canister rejected the message.
-    MessageRejected,
+    /// The canister did not accept the message in `canister_inspect_message`.
+    /// It carries the message of the canister if it rejected explicitly with
+    /// `ic0.msg_reject`.
+    MessageRejected { reject_message: Option<String> },
     /// An attempt was made to add more cycles to an outgoing call than
     /// available in the canister's balance.
     InsufficientCyclesBalance(CanisterOutOfCyclesError),
@@ -133,6 +188,23 @@ pub enum HypervisorError {
     },
     /// A canister has written too much new data in a single message.
     MemoryAccessLimitExceeded(String),
+    /// The cycles that a memory allocation had to reserve would exceed the
+    /// reserved cycles limit of the canister.
+    ReservedCyclesLimitExceededInMemoryGrow {
+        bytes: NumBytes,
+        requested: Cycles,
+        limit: Cycles,
+    },
+    /// The canister does not have enough cycles to reserve for a memory
+    /// allocation.
+    InsufficientCyclesInMemoryGrow {
+        bytes: NumBytes,
+        available: Cycles,
+        requested: Cycles,
+    },
+    /// The canister grew its Wasm memory beyond the `wasm_memory_limit` set by
+    /// its controllers.
+    WasmMemoryLimitExceeded { bytes: NumBytes, limit: NumBytes },
 }
 
 impl From<WasmInstrumentationError> for HypervisorError {
@@ -164,9 +236,14 @@ impl HypervisorError {
         use ic_error_types::ErrorCode as E;
 
         match self {
-            Self::MessageRejected => UserError::new(
+            Self::MessageRejected { reject_message } => UserError::new(
                 E::CanisterRejectedMessage,
-                format!("Canister {} rejected the message", canister_id),
+                match reject_message {
+                    Some(message) => {
+                        format!("Canister {} rejected the message: {}", canister_id, message)
+                    }
+                    None => format!("Canister {} rejected the message", canister_id),
+                },
             ),
             Self::FunctionNotFound(table_idx, func_idx) => UserError::new(
                 E::CanisterFunctionNotFound,
@@ -233,13 +310,25 @@ impl HypervisorError {
                 E::CanisterCalledTrap,
                 format!("Canister {} trapped explicitly: {}", canister_id, msg),
             ),
-            Self::OutOfMemory => UserError::new(
-                E::CanisterOutOfMemory,
-                format!(
-                    "Canister {} exceeded its allowed memory allocation",
-                    canister_id
+            Self::OutOfMemory(err) => match err {
+                MemoryReservationError::CanisterMemoryLimitExceeded { .. } => UserError::new(
+                    E::CanisterOutOfMemory,
+                    format!(
+                        "Canister {} exceeded its allowed memory allocation: {}",
+                        canister_id, err
+                    ),
                 ),
-            ),
+                MemoryReservationError::SubnetMemoryCapacityExceeded { .. }
+                | MemoryReservationError::SubnetMessageMemoryCapacityExceeded { .. } => {
+                    UserError::new(
+                        E::CanisterOutOfMemory,
+                        format!(
+                            "Canister {} cannot grow its memory because the subnet is out of memory: {}",
+                            canister_id, err
+                        ),
+                    )
+                }
+            },
             Self::WasmReservedPages => UserError::new(
                 E::CanisterOutOfMemory,
                 format!(
@@ -308,6 +397,34 @@ impl HypervisorError {
                 format!("Canister exceeded memory access limits: {}", s)
 
             ),
+            Self::ReservedCyclesLimitExceededInMemoryGrow { bytes, requested, limit } => {
+                UserError::new(
+                    E::ReservedCyclesLimitExceededInMemoryGrow,
+                    format!(
+                        "Canister {} cannot grow memory by {} bytes due to its reserved cycles limit. \
+                        The current limit ({}) would be exceeded by {}.",
+                        canister_id, bytes, limit, requested - limit
+                    ),
+                )
+            }
+            Self::InsufficientCyclesInMemoryGrow { bytes, available, requested } => {
+                UserError::new(
+                    E::InsufficientCyclesInMemoryGrow,
+                    format!(
+                        "Canister {} cannot grow memory by {} bytes due to insufficient cycles. \
+                        At least {} additional cycles are required.",
+                        canister_id, bytes, requested - available
+                    ),
+                )
+            }
+            Self::WasmMemoryLimitExceeded { bytes, limit } => UserError::new(
+                E::CanisterWasmMemoryLimitExceeded,
+                format!(
+                    "Canister {} exceeded its current Wasm memory limit of {} bytes. \
+                    The Wasm memory would grow to {} bytes.",
+                    canister_id, limit, bytes
+                ),
+            ),
         }
     }
 
@@ -324,12 +441,12 @@ impl HypervisorError {
             HypervisorError::Trapped(_) => "Trapped",
             HypervisorError::CalledTrap(_) => "CalledTrap",
             HypervisorError::WasmModuleNotFound => "WasmModuleNotFound",
-            HypervisorError::OutOfMemory => "OutOfMemory",
+            HypervisorError::OutOfMemory(_) => "OutOfMemory",
             HypervisorError::CanisterStopped => "CanisterStopped",
             HypervisorError::InsufficientCyclesInCall { .. } => "InsufficientCyclesInCall",
             HypervisorError::InvalidPrincipalId(_) => "InvalidPrincipalId",
             HypervisorError::InvalidCanisterId(_) => "InvalidCanisterId",
-            HypervisorError::MessageRejected => "MessageRejected",
+            HypervisorError::MessageRejected { .. } => "MessageRejected",
             HypervisorError::InsufficientCyclesBalance { .. } => "InsufficientCyclesBalance",
             HypervisorError::Cleanup { .. } => "Cleanup",
             HypervisorError::WasmEngineError(_) => "WasmEngineError",
@@ -337,6 +454,13 @@ impl HypervisorError {
             HypervisorError::Aborted => "Aborted",
             HypervisorError::SliceOverrun { .. } => "SliceOverrun",
             HypervisorError::MemoryAccessLimitExceeded(_) => "MemoryAccessLimitExceeded",
+            HypervisorError::ReservedCyclesLimitExceededInMemoryGrow { .. } => {
+                "ReservedCyclesLimitExceededInMemoryGrow"
+            }
+            HypervisorError::InsufficientCyclesInMemoryGrow { .. } => {
+                "InsufficientCyclesInMemoryGrow"
+            }
+            HypervisorError::WasmMemoryLimitExceeded { .. } => "WasmMemoryLimitExceeded",
         }
     }
 
@@ -360,15 +484,18 @@ impl HypervisorError {
             | HypervisorError::Trapped(_)
             | HypervisorError::CalledTrap(_)
             | HypervisorError::WasmModuleNotFound
-            | HypervisorError::OutOfMemory
+            | HypervisorError::OutOfMemory(_)
             | HypervisorError::CanisterStopped
             | HypervisorError::InsufficientCyclesInCall { .. }
             | HypervisorError::InvalidPrincipalId(_)
             | HypervisorError::InvalidCanisterId(_)
-            | HypervisorError::MessageRejected
+            | HypervisorError::MessageRejected { .. }
             | HypervisorError::InsufficientCyclesBalance(_)
             | HypervisorError::WasmReservedPages
-            | HypervisorError::MemoryAccessLimitExceeded(_) => false,
+            | HypervisorError::MemoryAccessLimitExceeded(_)
+            | HypervisorError::ReservedCyclesLimitExceededInMemoryGrow { .. }
+            | HypervisorError::InsufficientCyclesInMemoryGrow { .. }
+            | HypervisorError::WasmMemoryLimitExceeded { .. } => false,
         }
     }
 }
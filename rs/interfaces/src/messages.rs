@@ -1,6 +1,7 @@
 //! Messages used in various components.
 use ic_types::{
     messages::{Ingress, Request, Response, StopCanisterContext},
+    time::{CoarseTime, NO_DEADLINE},
     CanisterId, Cycles, PrincipalId,
 };
 use std::{convert::TryFrom, sync::Arc};
@@ -77,6 +78,15 @@ impl RequestOrIngress {
         }
     }
 
+    /// Returns the deadline of this message, `NO_DEADLINE` if it is not a
+    /// best-effort request.
+    pub fn deadline(&self) -> CoarseTime {
+        match self {
+            RequestOrIngress::Request(request) => request.deadline,
+            RequestOrIngress::Ingress(_) => NO_DEADLINE,
+        }
+    }
+
     /// Extracts the cycles received with this message.
     pub fn take_cycles(&mut self) -> Cycles {
         match self {
@@ -20,6 +20,10 @@ const MAX_PAGES_TO_MAP: usize = 128;
 // to `MAX_PAGES_TO_MAP`, this value also affects memory usage. Setting it
 // too high may increase memory usage.
 const MAX_PAGES_TO_COPY: usize = 64;
+// The number of pages following the mapped pages that are read ahead when
+// the signal handler detects sequential access. Reading ahead doesn't map the
+// pages, it only populates their backing memory asynchronously.
+const MAX_PAGES_TO_PREFETCH: usize = 256;
 
 // The new signal handler requires `AccessKind` which currently available only
 // on Linux without WSL.
@@ -533,6 +537,7 @@ pub fn sigsegv_fault_handler_new(
                 min_prefetch_range,
                 max_prefetch_range,
             );
+            prefetch_if_sequential(page_map, &accessed_bitmap, faulting_page, &prefetch_range);
             accessed_bitmap.mark_range(&prefetch_range);
         }
         (AccessKind::Read, DirtyPageTracking::Track) => {
@@ -550,6 +555,7 @@ pub fn sigsegv_fault_handler_new(
                 min_prefetch_range,
                 max_prefetch_range,
             );
+            prefetch_if_sequential(page_map, &accessed_bitmap, faulting_page, &prefetch_range);
             accessed_bitmap.mark_range(&prefetch_range);
         }
         (AccessKind::Write, DirtyPageTracking::Track) => {
@@ -601,6 +607,30 @@ pub fn sigsegv_fault_handler_new(
     true
 }
 
+// If the page preceding the `faulting_page` has already been accessed, then
+// the canister is likely scanning its memory sequentially. In that case the
+// backing memory of the pages following the newly `mapped_range` is read ahead
+// asynchronously, so that the next signal handler calls don't stall on it.
+fn prefetch_if_sequential(
+    page_map: &PageMap,
+    accessed_bitmap: &PageBitmap,
+    faulting_page: PageIndex,
+    mapped_range: &Range<PageIndex>,
+) {
+    let is_sequential = faulting_page.get() > 0
+        && accessed_bitmap.is_marked(PageIndex::new(faulting_page.get() - 1));
+    if !is_sequential {
+        return;
+    }
+    let readahead_range = range_intersection(
+        &range_from_count(mapped_range.end, MAX_PAGES_TO_PREFETCH),
+        &accessed_bitmap.page_range(),
+    );
+    if !readahead_range.is_empty() {
+        page_map.prefetch_pages(readahead_range);
+    }
+}
+
 // Sets up page mapping for the given `faulting_page` and its subsequent pages
 // using the backing memory from the given `page_map`. The range of mapped pages
 // depends on the type of the backing memory. If it is cheap to map then the
@@ -57,6 +57,7 @@ pub(crate) const METRIC_TIME_IN_BACKLOG: &str = "mr_time_in_backlog";
 pub(crate) const METRIC_TIME_IN_STREAM: &str = "mr_time_in_stream";
 
 const LABEL_STATUS: &str = "status";
+const LABEL_CALLEE: &str = "callee";
 pub(crate) const LABEL_REMOTE: &str = "remote";
 
 const STATUS_IGNORED: &str = "ignored";
@@ -69,6 +70,8 @@ const PHASE_COMMIT: &str = "commit";
 const METRIC_PROCESS_BATCH_DURATION: &str = "mr_process_batch_duration_seconds";
 const METRIC_PROCESS_BATCH_PHASE_DURATION: &str = "mr_process_batch_phase_duration_seconds";
 const METRIC_TIMED_OUT_REQUESTS_TOTAL: &str = "mr_timed_out_requests_total";
+const METRIC_TIMED_OUT_CALLBACKS_TOTAL: &str = "mr_timed_out_callbacks_total";
+const METRIC_TIMED_OUT_STUCK_CALLBACKS_TOTAL: &str = "mr_timed_out_stuck_callbacks_total";
 
 const CRITICAL_ERROR_MISSING_SUBNET_SIZE: &str = "cycles_account_manager_missing_subnet_size_error";
 const CRITICAL_ERROR_NO_CANISTER_ALLOCATION_RANGE: &str = "mr_empty_canister_allocation_range";
@@ -249,6 +252,12 @@ pub(crate) struct MessageRoutingMetrics {
     critical_error_no_canister_allocation_range: IntCounter,
     /// Number of timed out requests.
     pub timed_out_requests_total: IntCounter,
+    /// Number of timed out best-effort callbacks.
+    pub timed_out_callbacks_total: IntCounter,
+    /// Number of timed out guaranteed response callbacks of call contexts
+    /// that exceeded the maximum lifetime, by callee. Only callees that did
+    /// not respond within the maximum lifetime show up, so there are few.
+    pub timed_out_stuck_callbacks_total: IntCounterVec,
 }
 
 impl MessageRoutingMetrics {
@@ -296,6 +305,16 @@ impl MessageRoutingMetrics {
                 METRIC_TIMED_OUT_REQUESTS_TOTAL,
                 "Count of timed out requests.",
             ),
+            timed_out_callbacks_total: metrics_registry.int_counter(
+                METRIC_TIMED_OUT_CALLBACKS_TOTAL,
+                "Count of timed out best-effort callbacks.",
+            ),
+            timed_out_stuck_callbacks_total: metrics_registry.int_counter_vec(
+                METRIC_TIMED_OUT_STUCK_CALLBACKS_TOTAL,
+                "Count of timed out callbacks of call contexts that exceeded the maximum lifetime, \
+                by callee.",
+                &[LABEL_CALLEE],
+            ),
         }
     }
 
@@ -928,6 +947,7 @@ impl MessageRoutingImpl {
             scheduler,
             demux,
             stream_builder,
+            &hypervisor_config,
             log.clone(),
             Arc::clone(&metrics),
         ));
@@ -31,6 +31,7 @@ use ic_test_utilities_metrics::{
 };
 use ic_types::{
     messages::{CallbackId, Payload, Request, MAX_RESPONSE_COUNT_BYTES},
+    time::NO_DEADLINE,
     xnet::{testing::StreamSliceTesting, StreamIndex, StreamIndexedQueue},
     CanisterId, Cycles,
 };
@@ -1276,6 +1277,7 @@ fn generate_reject_response_queue_full() {
                 RejectCode::SysTransient,
                 err.to_string(),
             )),
+            deadline: NO_DEADLINE,
         }
         .into(),
     );
@@ -1316,6 +1318,7 @@ fn generate_reject_response_canister_not_found() {
                 RejectCode::DestinationInvalid,
                 err.to_string(),
             )),
+            deadline: NO_DEADLINE,
         }
         .into(),
     );
@@ -1,5 +1,7 @@
 use crate::message_routing::MessageRoutingMetrics;
 use crate::routing::{demux::Demux, stream_builder::StreamBuilder};
+use ic_config::execution_environment::Config as HypervisorConfig;
+use ic_config::flag_status::FlagStatus;
 use ic_ic00_types::CanisterStatusType;
 use ic_interfaces::execution_environment::{
     ExecutionRoundType, RegistryExecutionSettings, Scheduler,
@@ -10,6 +12,7 @@ use ic_registry_subnet_features::SubnetFeatures;
 use ic_replicated_state::{NetworkTopology, ReplicatedState};
 use ic_types::{batch::Batch, ExecutionRound};
 use std::sync::Arc;
+use std::time::Duration;
 
 #[cfg(test)]
 mod tests;
@@ -19,6 +22,7 @@ const PHASE_EXECUTION: &str = "execution";
 const PHASE_MESSAGE_ROUTING: &str = "message_routing";
 const PHASE_REMOVE_CANISTERS: &str = "remove_canisters_not_in_rt";
 const PHASE_TIME_OUT_REQUESTS: &str = "time_out_requests";
+const PHASE_TIME_OUT_CALLBACKS: &str = "time_out_callbacks";
 
 pub(crate) trait StateMachine: Send {
     fn execute_round(
@@ -34,6 +38,9 @@ pub(crate) struct StateMachineImpl {
     scheduler: Box<dyn Scheduler<State = ReplicatedState>>,
     demux: Box<dyn Demux>,
     stream_builder: Box<dyn StreamBuilder>,
+    /// The maximum lifetime of an open call context, or `None` if the calls
+    /// of call contexts that exceed it are not timed out.
+    max_call_context_lifetime: Option<Duration>,
     log: ReplicaLogger,
     metrics: Arc<MessageRoutingMetrics>,
 }
@@ -43,13 +50,19 @@ impl StateMachineImpl {
         scheduler: Box<dyn Scheduler<State = ReplicatedState>>,
         demux: Box<dyn Demux>,
         stream_builder: Box<dyn StreamBuilder>,
+        hypervisor_config: &HypervisorConfig,
         log: ReplicaLogger,
         metrics: Arc<MessageRoutingMetrics>,
     ) -> Self {
+        let max_call_context_lifetime = match hypervisor_config.stuck_call_timeouts {
+            FlagStatus::Enabled => Some(hypervisor_config.max_call_context_lifetime),
+            FlagStatus::Disabled => None,
+        };
         Self {
             scheduler,
             demux,
             stream_builder,
+            max_call_context_lifetime,
             log,
             metrics,
         }
@@ -142,6 +155,30 @@ impl StateMachine for StateMachineImpl {
             .inc_by(timed_out_requests);
         self.observe_phase_duration(PHASE_TIME_OUT_REQUESTS, &phase_timer);
 
+        // Time out best-effort callbacks.
+        let phase_timer = Timer::start();
+        let timed_out_callbacks = state.time_out_callbacks(batch.time);
+        self.metrics
+            .timed_out_callbacks_total
+            .inc_by(timed_out_callbacks);
+        // Time out the calls of call contexts that exceeded the maximum lifetime.
+        if let Some(max_lifetime) = self.max_call_context_lifetime {
+            for (caller, callee) in state.time_out_stuck_callbacks(batch.time, max_lifetime) {
+                self.metrics
+                    .timed_out_stuck_callbacks_total
+                    .with_label_values(&[&callee.to_string()])
+                    .inc();
+                warn!(
+                    self.log,
+                    "Timed out a call from {} to {}: the callee did not respond within {:?}",
+                    caller,
+                    callee,
+                    max_lifetime
+                );
+            }
+        }
+        self.observe_phase_duration(PHASE_TIME_OUT_CALLBACKS, &phase_timer);
+
         // Preprocess messages and add messages to the induction pool through the Demux.
         let phase_timer = Timer::start();
         let mut state_with_messages = self.demux.process_payload(state, batch.payload);
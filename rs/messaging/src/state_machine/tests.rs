@@ -148,6 +148,7 @@ fn state_machine_populates_network_topology() {
             fixture.scheduler,
             fixture.demux,
             fixture.stream_builder,
+            &HypervisorConfig::default(),
             log,
             fixture.metrics,
         ));
@@ -180,6 +181,7 @@ fn test_delivered_batch(provided_batch: Batch) {
             fixture.scheduler,
             fixture.demux,
             fixture.stream_builder,
+            &HypervisorConfig::default(),
             log,
             fixture.metrics,
         ));
@@ -124,6 +124,9 @@ fn test_rosetta1_92() {
                 num_blocks_to_archive: blocks_per_archive_call,
                 cycles_for_archive_creation: Some(0),
                 max_transactions_per_response: None,
+                max_archive_nodes: None,
+                cycles_for_archive_top_up: None,
+                cycles_for_archive_top_ups_pool: None,
             })
             .send_whitelist(ALL_NNS_CANISTER_IDS.iter().map(|&x| *x).collect())
             .build()
@@ -70,6 +70,9 @@ impl NnsInitPayloadsBuilder {
                     controller_id: ROOT_CANISTER_ID.into(),
                     cycles_for_archive_creation: Some(0),
                     max_transactions_per_response: None,
+                    max_archive_nodes: None,
+                    cycles_for_archive_top_up: None,
+                    cycles_for_archive_top_ups_pool: None,
                 })
                 .max_message_size_bytes(128 * 1024)
                 // 24 hour transaction window
@@ -29,6 +29,10 @@ pub mod call_context {
         pub canister_id: ::core::option::Option<super::super::super::super::types::v1::CanisterId>,
         #[prost(uint64, tag = "2")]
         pub callback_id: u64,
+        /// The deadline of a best-effort call, in seconds since Unix epoch. Zero
+        /// for guaranteed response calls.
+        #[prost(uint32, tag = "3")]
+        pub deadline_seconds: u32,
     }
     /// System task is either a Heartbeat or a GlobalTimer.
     #[allow(clippy::derive_partial_eq_without_eq)]
@@ -87,6 +91,10 @@ pub struct Callback {
     #[prost(message, optional, tag = "9")]
     pub prepayment_for_response_transmission:
         ::core::option::Option<super::super::queues::v1::Cycles>,
+    /// The deadline of a best-effort call, in seconds since Unix epoch. Zero for
+    /// guaranteed response calls.
+    #[prost(uint32, tag = "10")]
+    pub deadline_seconds: u32,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -107,6 +115,10 @@ pub struct CallContextManager {
     pub call_contexts: ::prost::alloc::vec::Vec<CallContextEntry>,
     #[prost(message, repeated, tag = "4")]
     pub callbacks: ::prost::alloc::vec::Vec<CallbackEntry>,
+    /// The best-effort callbacks that have already been answered with a
+    /// `SYS_UNKNOWN` reject because their deadline expired.
+    #[prost(uint64, repeated, tag = "5")]
+    pub expired_callback_ids: ::prost::alloc::vec::Vec<u64>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -157,6 +169,7 @@ pub mod wasm_method {
         CanisterHeartbeat = 6,
         Empty = 7,
         CanisterGlobalTimer = 8,
+        CanisterOnLowWasmMemory = 9,
     }
     impl SystemMethod {
         /// String value of the enum field names used in the ProtoBuf definition.
@@ -174,6 +187,9 @@ pub mod wasm_method {
                 SystemMethod::CanisterHeartbeat => "SYSTEM_METHOD_CANISTER_HEARTBEAT",
                 SystemMethod::Empty => "SYSTEM_METHOD_EMPTY",
                 SystemMethod::CanisterGlobalTimer => "SYSTEM_METHOD_CANISTER_GLOBAL_TIMER",
+                SystemMethod::CanisterOnLowWasmMemory => {
+                    "SYSTEM_METHOD_CANISTER_ON_LOW_WASM_MEMORY"
+                }
             }
         }
     }
@@ -340,6 +356,30 @@ pub mod execution_task {
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CanisterLogRecord {
+    #[prost(uint64, tag = "1")]
+    pub idx: u64,
+    #[prost(uint64, tag = "2")]
+    pub timestamp_nanos: u64,
+    #[prost(bytes = "vec", tag = "3")]
+    pub content: ::prost::alloc::vec::Vec<u8>,
+}
+/// The statistics of the queries that executed a method of a canister in
+/// replicated mode.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct QueryStats {
+    #[prost(string, tag = "1")]
+    pub method_name: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "2")]
+    pub num_calls: u64,
+    #[prost(uint64, tag = "3")]
+    pub num_instructions: u64,
+    #[prost(uint64, tag = "4")]
+    pub egress_payload_size: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct CanisterStateBits {
     #[prost(uint64, tag = "2")]
     pub last_full_execution_round: u64,
@@ -400,6 +440,45 @@ pub struct CanisterStateBits {
     /// Canister version.
     #[prost(uint64, tag = "34")]
     pub canister_version: u64,
+    /// Who may fetch the canister log.
+    #[prost(enumeration = "LogVisibility", tag = "35")]
+    pub log_visibility: i32,
+    /// The records of the canister log, oldest first.
+    #[prost(message, repeated, tag = "36")]
+    pub canister_log_records: ::prost::alloc::vec::Vec<CanisterLogRecord>,
+    /// The index of the next record of the canister log.
+    #[prost(uint64, tag = "37")]
+    pub next_canister_log_record_idx: u64,
+    /// The remaining Wasm memory below which `canister_on_low_wasm_memory` runs.
+    #[prost(uint64, tag = "38")]
+    pub wasm_memory_threshold: u64,
+    /// Whether `canister_on_low_wasm_memory` already ran since the remaining
+    /// Wasm memory dropped below `wasm_memory_threshold`.
+    #[prost(bool, tag = "39")]
+    pub on_low_wasm_memory_hook_executed: bool,
+    /// The instructions executed by update calls and their responses.
+    #[prost(uint64, tag = "40")]
+    pub update_instructions_executed: u64,
+    /// The instructions executed by queries in replicated mode.
+    #[prost(uint64, tag = "41")]
+    pub query_instructions_executed: u64,
+    /// The instructions executed by system tasks.
+    #[prost(uint64, tag = "42")]
+    pub system_task_instructions_executed: u64,
+    /// Cycles reserved for future storage payments when the canister allocated
+    /// memory on a subnet with high memory usage.
+    #[prost(message, optional, tag = "43")]
+    pub reserved_balance: ::core::option::Option<super::super::queues::v1::Cycles>,
+    /// The upper bound on `reserved_balance` set by the controllers, if any.
+    #[prost(message, optional, tag = "44")]
+    pub reserved_balance_limit: ::core::option::Option<super::super::queues::v1::Cycles>,
+    /// The statistics of the queries that the canister executed in replicated
+    /// mode, by method.
+    #[prost(message, repeated, tag = "45")]
+    pub query_stats: ::prost::alloc::vec::Vec<QueryStats>,
+    /// The upper limit of the Wasm memory set by the controllers, if any.
+    #[prost(uint64, optional, tag = "46")]
+    pub wasm_memory_limit: ::core::option::Option<u64>,
     #[prost(oneof = "canister_state_bits::CanisterStatus", tags = "11, 12, 13")]
     pub canister_status: ::core::option::Option<canister_state_bits::CanisterStatus>,
 }
@@ -416,6 +495,15 @@ pub mod canister_state_bits {
         Stopped(super::CanisterStatusStopped),
     }
 }
+/// The Wasm chunk store of a canister. It is persisted separately from
+/// `CanisterStateBits` because the chunks can be large.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WasmChunkStore {
+    /// The contents of the chunks in increasing order of their hashes.
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub chunks: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+}
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
 #[repr(i32)]
@@ -437,3 +525,25 @@ impl CustomSectionType {
         }
     }
 }
+/// Who may fetch the logs of a canister.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum LogVisibility {
+    Unspecified = 0,
+    Controllers = 1,
+    Public = 2,
+}
+impl LogVisibility {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            LogVisibility::Unspecified => "LOG_VISIBILITY_UNSPECIFIED",
+            LogVisibility::Controllers => "LOG_VISIBILITY_CONTROLLERS",
+            LogVisibility::Public => "LOG_VISIBILITY_PUBLIC",
+        }
+    }
+}
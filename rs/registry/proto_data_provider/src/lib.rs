@@ -46,6 +46,24 @@ impl ProtoRegistryDataProvider {
     where
         T: RegistryValue,
     {
+        let value = value.map(|v| {
+            let mut buf: Vec<u8> = vec![];
+            v.encode(&mut buf)
+                .expect("can't fail, encoding is infallible");
+            buf
+        });
+        self.add_encoded(key, version, value)
+    }
+
+    /// Same as [Self::add], but for a value that is already encoded, e.g. a
+    /// value read from a registry local store. `None` means that the key is
+    /// deleted at `version`.
+    pub fn add_encoded(
+        &self,
+        key: &str,
+        version: RegistryVersion,
+        value: Option<Vec<u8>>,
+    ) -> Result<(), ProtoRegistryDataProviderError> {
         assert!(version.get() > 0);
         let mut records = self.records.write().unwrap();
 
@@ -59,12 +77,7 @@ impl ProtoRegistryDataProvider {
                 let record = ProtoRegistryRecord {
                     key: key.to_string(),
                     version: version.get(),
-                    value: value.map(|v| {
-                        let mut buf: Vec<u8> = vec![];
-                        v.encode(&mut buf)
-                            .expect("can't fail, encoding is infallible");
-                        buf
-                    }),
+                    value,
                 };
                 records.insert(idx, record);
                 Ok(())
@@ -222,6 +222,9 @@ fn main() -> io::Result<()> {
     );
 
     let subnet_config = SubnetConfigs::default().own_subnet_config(subnet_type);
+    ic_replicated_state::page_map::configure_page_allocators(
+        &config.hypervisor.page_allocator_config,
+    );
 
     // Read the root subnet id from registry
     let root_subnet_id = registry
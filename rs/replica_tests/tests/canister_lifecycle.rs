@@ -4,7 +4,8 @@ use ic_config::Config;
 use ic_error_types::{ErrorCode, RejectCode};
 use ic_ic00_types::{
     self as ic00, CanisterIdRecord, CanisterInstallMode, CanisterStatusResultV2,
-    CanisterStatusType, EmptyBlob, InstallCodeArgs, Method, Payload, SetControllerArgs, IC_00,
+    CanisterStatusType, EmptyBlob, InstallCodeArgs, InstructionMetrics, MemoryMetrics, Method,
+    Payload, SetControllerArgs, IC_00,
 };
 use ic_registry_provisional_whitelist::ProvisionalWhitelist;
 use ic_replica_tests as utils;
@@ -681,6 +682,15 @@ fn can_get_canister_information() {
                 2592000,
                 0u128,
             )
+            .with_memory_metrics(MemoryMetrics::new(
+                NumBytes::from(0),
+                NumBytes::from(0),
+                NumBytes::from(0),
+                NumBytes::from(0),
+                NumBytes::from(0),
+            ))
+            .with_instruction_metrics(InstructionMetrics::new(0, 0, 0))
+            .with_reserved_cycles(0)
         );
 
         // Install code to canister_b.
@@ -4,7 +4,8 @@ use std::time::Duration;
 
 use criterion::{black_box, BenchmarkId, Criterion};
 use criterion_time::ProcessTime;
-use ic_replicated_state::page_map::PageAllocator;
+use ic_config::embedders::PageAllocatorConfig;
+use ic_replicated_state::page_map::{configure_page_allocators, PageAllocator, PageMap};
 use ic_replicated_state::PageIndex;
 use ic_sys::{PageBytes, PAGE_SIZE};
 
@@ -16,9 +17,21 @@ const NUM_THREADS: u32 = 4;
 // simulate the number of rounds between checkpoints.
 const NUM_ALLOCATIONS: usize = 100;
 
-fn bench_allocator(c: &mut Criterion<ProcessTime>) {
+fn set_transparent_huge_pages(transparent_huge_pages: bool) {
+    configure_page_allocators(&PageAllocatorConfig {
+        transparent_huge_pages,
+        ..Default::default()
+    });
+}
+
+fn bench_allocator(c: &mut Criterion<ProcessTime>, transparent_huge_pages: bool) {
+    set_transparent_huge_pages(transparent_huge_pages);
     let page = &[1u8; PAGE_SIZE];
-    let mut group = c.benchmark_group("Allocate");
+    let mut group = c.benchmark_group(if transparent_huge_pages {
+        "AllocateWithHugePages"
+    } else {
+        "Allocate"
+    });
     for n in [1usize, 10, 100, 1_000].iter().cloned() {
         let pages: Vec<(PageIndex, &PageBytes)> = (0..n)
             .into_iter()
@@ -45,12 +58,85 @@ fn bench_allocator(c: &mut Criterion<ProcessTime>) {
     group.finish();
 }
 
+// The number of pages per batch in `bench_batch_allocation`.
+const BATCH_SIZE: usize = 10_000;
+
+// Allocates a large batch of pages from multiple threads that share one page
+// allocator, either with one `allocate()` call for the whole batch or with one
+// call per page. The former reserves all pages in one critical section and
+// copies their contents without holding the lock of the page allocator.
+fn bench_batch_allocation(c: &mut Criterion<ProcessTime>) {
+    set_transparent_huge_pages(false);
+    let page = &[1u8; PAGE_SIZE];
+    let pages: Vec<(PageIndex, &PageBytes)> = (0..BATCH_SIZE)
+        .map(|i| (PageIndex::new(i as u64), page))
+        .collect();
+    let mut group = c.benchmark_group("AllocateBatch");
+    let mut thread_pool = Cell::new(scoped_threadpool::Pool::new(NUM_THREADS));
+    for per_page in [false, true] {
+        let name = if per_page { "PageByPage" } else { "Batch" };
+        group.bench_function(BenchmarkId::new(name, BATCH_SIZE), |b| {
+            b.iter(|| {
+                let allocator = PageAllocator::default();
+                thread_pool.get_mut().scoped(|scope| {
+                    for _ in 0..NUM_THREADS {
+                        scope.execute(|| {
+                            if per_page {
+                                for page in pages.iter() {
+                                    black_box(allocator.allocate(std::slice::from_ref(page)));
+                                }
+                            } else {
+                                black_box(allocator.allocate(&pages[..]));
+                            }
+                        });
+                    }
+                });
+            })
+        });
+    }
+    group.finish();
+}
+
+// The number of pages in the page map read by `bench_page_map_reads`, to
+// simulate a memory-heavy canister (1GiB).
+const NUM_HEAP_PAGES: usize = 256 * 1024;
+
+// Reads one word of every page of a large page map, which is dominated by TLB
+// misses unless the pages are backed by huge pages.
+fn bench_page_map_reads(c: &mut Criterion<ProcessTime>, transparent_huge_pages: bool) {
+    set_transparent_huge_pages(transparent_huge_pages);
+    let page = &[1u8; PAGE_SIZE];
+    let pages: Vec<(PageIndex, &PageBytes)> = (0..NUM_HEAP_PAGES)
+        .map(|i| (PageIndex::new(i as u64), page))
+        .collect();
+    let mut page_map = PageMap::new();
+    page_map.update(&pages);
+    let name = if transparent_huge_pages {
+        "ReadPageMapWithHugePages"
+    } else {
+        "ReadPageMap"
+    };
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            let mut sum = 0u64;
+            for i in (0..NUM_HEAP_PAGES).rev() {
+                sum += page_map.get_page(PageIndex::new(i as u64))[i % PAGE_SIZE] as u64;
+            }
+            black_box(sum)
+        })
+    });
+}
+
 fn main() {
     let mut c = Criterion::default()
         .with_measurement(ProcessTime::UserTime)
         .sample_size(10)
         .measurement_time(Duration::from_secs(40))
         .configure_from_args();
-    bench_allocator(&mut c);
+    bench_allocator(&mut c, false);
+    bench_allocator(&mut c, true);
+    bench_batch_allocation(&mut c);
+    bench_page_map_reads(&mut c, false);
+    bench_page_map_reads(&mut c, true);
     c.final_summary();
 }
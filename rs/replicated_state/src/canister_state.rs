@@ -1,5 +1,6 @@
 pub mod execution_state;
 pub(crate) mod queues;
+pub mod snapshot;
 pub mod system_state;
 #[cfg(test)]
 mod tests;
@@ -19,10 +20,10 @@ use ic_types::{
     AccumulatedPriority, CanisterId, ComputeAllocation, ExecutionRound, MemoryAllocation, NumBytes,
     PrincipalId, Time,
 };
-use ic_types::{LongExecutionMode, NumInstructions};
+use ic_types::{LongExecutionMode, NumInstructions, MAX_WASM_MEMORY_IN_BYTES};
 use phantom_newtype::AmountOf;
-pub use queues::{CanisterQueues, DEFAULT_QUEUE_CAPACITY};
-use std::collections::BTreeSet;
+pub use queues::{CanisterQueues, QueuedMessageInfo, QueuedMessageKind, DEFAULT_QUEUE_CAPACITY};
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::From;
 use std::sync::Arc;
 use std::time::Duration;
@@ -70,6 +71,15 @@ pub struct SchedulerState {
     /// needed to calculate how much time should be considered when charging
     /// occurs.
     pub time_of_last_allocation_charge: Time,
+
+    /// The instructions that the canister executed since its creation, by
+    /// kind of execution.
+    pub executed_instructions: ExecutedInstructions,
+
+    /// The statistics of the queries that the canister executed in replicated
+    /// mode since its creation, by method name. See [QueryStats] for why
+    /// non-replicated queries are not included.
+    pub query_stats: BTreeMap<String, QueryStats>,
 }
 
 impl Default for SchedulerState {
@@ -83,6 +93,8 @@ impl Default for SchedulerState {
             heap_delta_debit: 0.into(),
             install_code_debit: 0.into(),
             time_of_last_allocation_charge: UNIX_EPOCH,
+            executed_instructions: ExecutedInstructions::default(),
+            query_stats: BTreeMap::new(),
         }
     }
 }
@@ -96,6 +108,75 @@ impl SchedulerState {
     }
 }
 
+/// The memory used by a canister broken down by component.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryBreakdown {
+    /// The Wasm heap.
+    pub wasm_memory: NumBytes,
+    pub stable_memory: NumBytes,
+    /// The exported globals.
+    pub globals: NumBytes,
+    pub wasm_binary: NumBytes,
+    /// The chunks uploaded to the Wasm chunk store.
+    pub wasm_chunk_store: NumBytes,
+    /// The canister messages in the queues and the reservations for responses.
+    pub message_memory: NumBytes,
+}
+
+impl MemoryBreakdown {
+    /// Returns the execution memory: heap, stable, globals and Wasm binary.
+    pub fn execution_memory(&self) -> NumBytes {
+        self.wasm_memory + self.stable_memory + self.globals + self.wasm_binary
+    }
+}
+
+/// The instructions executed by a canister broken down by kind of execution.
+/// Executions are accounted for once they finish, so the instructions of an
+/// aborted execution are only counted when it finishes after the restart.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExecutedInstructions {
+    /// Update calls and the responses to the calls that they made.
+    pub update: NumInstructions,
+    /// Queries executed in replicated mode, e.g. called by other canisters.
+    pub query: NumInstructions,
+    /// Heartbeats, global timers, and other system tasks.
+    pub system_task: NumInstructions,
+}
+
+impl ExecutedInstructions {
+    pub fn total(&self) -> NumInstructions {
+        self.update + self.query + self.system_task
+    }
+}
+
+/// The statistics of the queries that executed a single method of a canister.
+///
+/// Only queries that execute in replicated mode are accounted for: calls from
+/// other canisters and ingress messages to query methods. They execute on
+/// every replica of the subnet, so the statistics are part of the replicated
+/// state. Queries sent to the query endpoint of a replica run in
+/// non-replicated mode on that replica only and are NOT included, because
+/// replicas cannot agree on them without consensus. The query handler of
+/// every replica exports them as replica-local metrics instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueryStats {
+    /// The number of executed queries.
+    pub num_calls: u64,
+    /// The instructions executed by the queries.
+    pub num_instructions: NumInstructions,
+    /// The total size of the replies and rejects of the queries.
+    pub egress_payload_size: NumBytes,
+}
+
+impl QueryStats {
+    /// Accounts for a single query execution.
+    pub fn observe(&mut self, instructions: NumInstructions, egress_payload_size: NumBytes) {
+        self.num_calls += 1;
+        self.num_instructions += instructions;
+        self.egress_payload_size += egress_payload_size;
+    }
+}
+
 /// The full state of a single canister.
 #[derive(Clone, Debug, PartialEq)]
 pub struct CanisterState {
@@ -231,6 +312,7 @@ impl CanisterState {
             (None, true) => NextExecution::StartNew,
             (Some(ExecutionTask::Heartbeat), _) => NextExecution::StartNew,
             (Some(ExecutionTask::GlobalTimer), _) => NextExecution::StartNew,
+            (Some(ExecutionTask::OnLowWasmMemory), _) => NextExecution::StartNew,
             (Some(ExecutionTask::AbortedExecution { .. }), _)
             | (Some(ExecutionTask::PausedExecution(..)), _) => NextExecution::ContinueLong,
             (Some(ExecutionTask::AbortedInstallCode { .. }), _)
@@ -250,6 +332,7 @@ impl CanisterState {
             None
             | Some(ExecutionTask::Heartbeat)
             | Some(ExecutionTask::GlobalTimer)
+            | Some(ExecutionTask::OnLowWasmMemory)
             | Some(ExecutionTask::PausedExecution(..))
             | Some(ExecutionTask::PausedInstallCode(..))
             | Some(ExecutionTask::AbortedInstallCode { .. }) => false,
@@ -263,6 +346,7 @@ impl CanisterState {
             None
             | Some(ExecutionTask::Heartbeat)
             | Some(ExecutionTask::GlobalTimer)
+            | Some(ExecutionTask::OnLowWasmMemory)
             | Some(ExecutionTask::PausedInstallCode(..))
             | Some(ExecutionTask::AbortedExecution { .. })
             | Some(ExecutionTask::AbortedInstallCode { .. }) => false,
@@ -276,6 +360,7 @@ impl CanisterState {
             None
             | Some(ExecutionTask::Heartbeat)
             | Some(ExecutionTask::GlobalTimer)
+            | Some(ExecutionTask::OnLowWasmMemory)
             | Some(ExecutionTask::PausedExecution(..))
             | Some(ExecutionTask::AbortedExecution { .. })
             | Some(ExecutionTask::AbortedInstallCode { .. }) => false,
@@ -289,6 +374,7 @@ impl CanisterState {
             None
             | Some(ExecutionTask::Heartbeat)
             | Some(ExecutionTask::GlobalTimer)
+            | Some(ExecutionTask::OnLowWasmMemory)
             | Some(ExecutionTask::PausedExecution(..))
             | Some(ExecutionTask::PausedInstallCode(..))
             | Some(ExecutionTask::AbortedExecution { .. }) => false,
@@ -424,9 +510,9 @@ impl CanisterState {
 
     /// The amount of memory currently being used by the canister.
     ///
-    /// This only includes execution memory (heap, stable, globals, Wasm) for
-    /// system subnets; and execution memory plus system state memory (canister
-    /// messages) for application subnets.
+    /// This only includes execution memory (heap, stable, globals, Wasm) and
+    /// the Wasm chunk store for system subnets; and additionally system state
+    /// memory (canister messages) for application subnets.
     pub fn memory_usage(&self, own_subnet_type: SubnetType) -> NumBytes {
         let mut result = self.raw_memory_usage();
         if own_subnet_type != SubnetType::System {
@@ -435,13 +521,36 @@ impl CanisterState {
         result
     }
 
+    /// Returns the memory currently used by the canister broken down by
+    /// component. The message memory is included regardless of the subnet
+    /// type, see `memory_usage()` for what is accounted on which subnet.
+    pub fn memory_breakdown(&self) -> MemoryBreakdown {
+        let mut breakdown = MemoryBreakdown {
+            wasm_chunk_store: self.system_state.wasm_chunk_store.memory_usage(),
+            message_memory: self.message_memory_usage(),
+            ..Default::default()
+        };
+        if let Some(es) = &self.execution_state {
+            breakdown.wasm_memory = num_bytes_try_from(es.wasm_memory.size)
+                .expect("could not convert from wasm memory number of pages to bytes");
+            breakdown.stable_memory = num_bytes_try_from(es.stable_memory.size)
+                .expect("could not convert from stable memory number of pages to bytes");
+            // We use 8 bytes per global.
+            breakdown.globals = NumBytes::from(8 * es.exported_globals.len() as u64);
+            breakdown.wasm_binary = NumBytes::from(es.wasm_binary.binary.len() as u64);
+        }
+        breakdown
+    }
+
     /// Returns the amount of raw memory currently used by the canister in bytes.
     ///
-    /// This only includes execution memory (heap, stable, globals, Wasm).
+    /// This only includes execution memory (heap, stable, globals, Wasm) and
+    /// the Wasm chunk store.
     pub(crate) fn raw_memory_usage(&self) -> NumBytes {
         self.execution_state
             .as_ref()
             .map_or(NumBytes::from(0), |es| es.memory_usage())
+            + self.system_state.wasm_chunk_store.memory_usage()
     }
 
     /// Returns the amount of system state memory used by the canister in bytes
@@ -493,6 +602,27 @@ impl CanisterState {
         self.exports_method(&WasmMethod::System(SystemMethod::CanisterGlobalTimer))
     }
 
+    /// Returns true if the canister exports the `canister_on_low_wasm_memory`
+    /// system method.
+    pub fn exports_on_low_wasm_memory_method(&self) -> bool {
+        self.exports_method(&WasmMethod::System(SystemMethod::CanisterOnLowWasmMemory))
+    }
+
+    /// Returns true if the canister has a non-zero `wasm_memory_threshold` and
+    /// its remaining Wasm memory, i.e. `MAX_WASM_MEMORY_IN_BYTES` minus the
+    /// current Wasm memory size, is below the threshold.
+    pub fn is_low_wasm_memory(&self) -> bool {
+        let threshold = self.system_state.wasm_memory_threshold;
+        match &self.execution_state {
+            Some(execution_state) if threshold.get() > 0 => {
+                let wasm_memory_size = num_bytes_try_from(execution_state.wasm_memory.size)
+                    .expect("could not convert from wasm memory number of pages to bytes");
+                MAX_WASM_MEMORY_IN_BYTES.saturating_sub(wasm_memory_size.get()) < threshold.get()
+            }
+            _ => false,
+        }
+    }
+
     /// Returns true if the canister exports the given Wasm method.
     pub fn exports_method(&self, method: &WasmMethod) -> bool {
         match &self.execution_state {
@@ -1,5 +1,6 @@
 use super::SessionNonce;
 use crate::{canister_state::WASM_PAGE_SIZE_IN_BYTES, num_bytes_try_from, NumWasmPages, PageMap};
+use ic_interfaces::execution_environment::InstructionProfile;
 use ic_protobuf::{
     proxy::{try_from_option_field, ProxyDecodeError},
     state::canister_state_bits::v1 as pb,
@@ -374,6 +375,11 @@ pub struct ExecutionState {
     /// Round number at which canister executed
     /// update type operation.
     pub last_executed_round: ExecutionRound,
+
+    /// The instructions executed by each function during the last message
+    /// execution. Only collected if instruction profiling is enabled. It is
+    /// not persisted in checkpoints.
+    pub last_instruction_profile: InstructionProfile,
 }
 
 // We have to implement it by hand as embedder_cache can not be compared for
@@ -417,6 +423,7 @@ impl ExecutionState {
             exported_globals,
             metadata: wasm_metadata,
             last_executed_round: ExecutionRound::from(0),
+            last_instruction_profile: InstructionProfile::default(),
         }
     }
 
@@ -14,11 +14,13 @@ use ic_protobuf::{
 };
 use ic_types::{
     messages::{
-        Ingress, Payload, RejectContext, Request, RequestOrResponse, Response,
+        CallbackId, Ingress, Payload, RejectContext, Request, RequestOrResponse, Response,
         MAX_RESPONSE_COUNT_BYTES,
     },
+    methods::Callback,
+    time::NO_DEADLINE,
     xnet::{QueueId, SessionId},
-    CanisterId, CountBytes, Cycles, Time,
+    CanisterId, CountBytes, Cycles, NumBytes, PrincipalId, Time,
 };
 use queue::{IngressQueue, InputQueue, OutputQueue};
 use std::{
@@ -32,7 +34,8 @@ use std::{
 pub const DEFAULT_QUEUE_CAPACITY: usize = 500;
 
 /// The default lifetime of a request in OutputQueue from which the deadline
-/// is computed as time + REQUEST_LIFETIME.
+/// is computed as time + REQUEST_LIFETIME. A best-effort request times out
+/// no later than its own deadline.
 pub const REQUEST_LIFETIME: Duration = Duration::from_secs(300);
 
 /// Encapsulates information about `CanisterQueues`,
@@ -107,6 +110,59 @@ pub struct CanisterQueues {
     next_input_queue: NextInputQueue,
 }
 
+/// The kind of a message enqueued in `CanisterQueues`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueuedMessageKind {
+    Ingress,
+    Request,
+    Response,
+}
+
+/// Information about a message enqueued in `CanisterQueues`, as returned by
+/// e.g. `CanisterQueues::input_queues_messages()`. It only describes the
+/// message and doesn't hold (a copy of) its payload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueuedMessageInfo {
+    pub kind: QueuedMessageKind,
+    /// The user or canister that sent the message.
+    pub sender: PrincipalId,
+    pub receiver: CanisterId,
+    pub payload_size_bytes: NumBytes,
+    /// The expiry time of an ingress message or the deadline of an output
+    /// request; `None` for all other messages.
+    pub deadline: Option<Time>,
+}
+
+impl QueuedMessageInfo {
+    fn new(msg: &RequestOrResponse, deadline: Option<Time>) -> Self {
+        let kind = match msg {
+            RequestOrResponse::Request(_) => QueuedMessageKind::Request,
+            RequestOrResponse::Response(_) => QueuedMessageKind::Response,
+        };
+        Self {
+            kind,
+            sender: msg.sender().get(),
+            receiver: msg.receiver(),
+            payload_size_bytes: msg.payload_size_bytes(),
+            deadline,
+        }
+    }
+}
+
+impl From<&Ingress> for QueuedMessageInfo {
+    fn from(msg: &Ingress) -> Self {
+        Self {
+            kind: QueuedMessageKind::Ingress,
+            sender: msg.source.get(),
+            receiver: msg.receiver,
+            payload_size_bytes: NumBytes::from(
+                (msg.method_name.len() + msg.method_payload.len()) as u64,
+            ),
+            deadline: Some(msg.expiry_time),
+        }
+    }
+}
+
 /// Circular iterator that consumes output queue messages: loops over output
 /// queues, popping one message at a time from each in a round robin fashion.
 /// All messages that have not been explicitly popped will remain in the state.
@@ -572,8 +628,12 @@ impl CanisterQueues {
         let oq_stats_delta =
             OutputQueuesStats::stats_delta(&RequestOrResponse::Request(msg.clone()));
 
+        let mut deadline = time + REQUEST_LIFETIME;
+        if msg.is_best_effort() {
+            deadline = deadline.min(msg.deadline.as_time());
+        }
         output_queue
-            .push_request(msg, time + REQUEST_LIFETIME)
+            .push_request(msg, deadline)
             .expect("cannot fail due to checks above");
 
         self.input_queues_stats.reserved_slots += 1;
@@ -615,6 +675,7 @@ impl CanisterQueues {
             originator_reply_callback: request.sender_reply_callback,
             refund: request.payment,
             response_payload: Payload::Reject(reject_context),
+            deadline: request.deadline,
         }));
         self.push_input(response, InputQueueType::LocalSubnet)
             .map_err(|(e, _msg)| e)
@@ -686,6 +747,16 @@ impl CanisterQueues {
         self.push_input(msg, InputQueueType::LocalSubnet)
             .map_err(|_| ())?;
 
+        self.pop_message_to_self(own_canister_id);
+
+        Ok(())
+    }
+
+    /// Pops the message at the front of the canister's output queue to itself
+    /// without inducting it, e.g. a late response to a best-effort call.
+    ///
+    /// Panics if the output queue to self is empty.
+    pub(super) fn pop_message_to_self(&mut self, own_canister_id: CanisterId) {
         let msg = self
             .canister_queues
             .get_mut(&own_canister_id)
@@ -697,8 +768,6 @@ impl CanisterQueues {
         self.output_queues_stats -= oq_stats_delta;
         self.memory_usage_stats -= MemoryUsageStats::stats_delta(QueueOp::Pop, &msg);
         debug_assert!(self.stats_ok());
-
-        Ok(())
     }
 
     /// Returns the number of enqueued ingress messages.
@@ -711,6 +780,37 @@ impl CanisterQueues {
         self.ingress_queue.count_bytes()
     }
 
+    /// Returns information about the enqueued ingress messages, in the order in
+    /// which they will be consumed.
+    pub fn ingress_queue_messages(&self) -> impl Iterator<Item = QueuedMessageInfo> + '_ {
+        self.ingress_queue
+            .iter()
+            .map(|msg| QueuedMessageInfo::from(msg.as_ref()))
+    }
+
+    /// Returns information about the messages enqueued in input queues. The
+    /// queues are visited in the order of the sender canister IDs and the
+    /// messages of each queue in the order in which they will be consumed.
+    pub fn input_queues_messages(&self) -> impl Iterator<Item = QueuedMessageInfo> + '_ {
+        self.canister_queues.values().flat_map(|(input_queue, _)| {
+            input_queue
+                .iter()
+                .map(|msg| QueuedMessageInfo::new(msg, None))
+        })
+    }
+
+    /// Returns information about the messages enqueued in output queues,
+    /// including the deadlines of requests. The queues are visited in the
+    /// order of the receiver canister IDs and the messages of each queue in the
+    /// order in which they will be routed.
+    pub fn output_queues_messages(&self) -> impl Iterator<Item = QueuedMessageInfo> + '_ {
+        self.canister_queues.values().flat_map(|(_, output_queue)| {
+            output_queue
+                .iter_with_deadlines()
+                .map(|(msg, deadline)| QueuedMessageInfo::new(msg, deadline))
+        })
+    }
+
     /// Returns the number of canister messages enqueued in input queues.
     pub fn input_queues_message_count(&self) -> usize {
         self.input_queues_stats.message_count
@@ -1040,20 +1140,107 @@ impl CanisterQueues {
 
         timed_out_requests_count
     }
+
+    /// Enqueues a `SYS_UNKNOWN` reject response for the expired callback with
+    /// the given ID into the slot reserved for its response. Best-effort
+    /// callbacks expire with their deadline, guaranteed response callbacks
+    /// with the lifetime of their call context.
+    ///
+    /// Nothing is enqueued if the request is still in the output queue (it
+    /// times out on its own) or if a response to it is already enqueued.
+    ///
+    /// Returns true if a reject response was enqueued.
+    pub(crate) fn time_out_callback(
+        &mut self,
+        callback_id: CallbackId,
+        callback: &Callback,
+        own_canister_id: &CanisterId,
+        local_canisters: &BTreeMap<CanisterId, CanisterState>,
+    ) -> bool {
+        let respondent = match callback.respondent {
+            Some(respondent) => respondent,
+            None => return false,
+        };
+        let (input_queue, output_queue) = match self.canister_queues.get_mut(&respondent) {
+            Some(queues) => queues,
+            None => return false,
+        };
+        let request_enqueued = output_queue.iter_with_deadlines().any(|(msg, _)| match msg {
+            RequestOrResponse::Request(request) => request.sender_reply_callback == callback_id,
+            RequestOrResponse::Response(_) => false,
+        });
+        let response_enqueued = input_queue.iter().any(|msg| match msg {
+            RequestOrResponse::Response(response) => {
+                response.originator_reply_callback == callback_id
+            }
+            RequestOrResponse::Request(_) => false,
+        });
+        if request_enqueued || response_enqueued {
+            return false;
+        }
+
+        // The callee may have accepted the attached cycles, so none are
+        // refunded.
+        let reject_message = if callback.deadline == NO_DEADLINE {
+            "Callee did not respond within the maximum call context lifetime."
+        } else {
+            "Call deadline has expired."
+        };
+        let response = RequestOrResponse::Response(Arc::new(Response {
+            originator: *own_canister_id,
+            respondent,
+            originator_reply_callback: callback_id,
+            refund: Cycles::zero(),
+            response_payload: Payload::Reject(RejectContext::new(
+                RejectCode::SysUnknown,
+                reject_message.to_string(),
+            )),
+            deadline: callback.deadline,
+        }));
+        let iq_stats_delta = InputQueuesStats::stats_delta(QueueOp::Push, &response);
+        let mu_stats_delta = MemoryUsageStats::stats_delta(QueueOp::Push, &response);
+        input_queue
+            .push(response)
+            .expect("a callback must have a reserved slot");
+        self.input_queues_stats += iq_stats_delta;
+        self.memory_usage_stats += mu_stats_delta;
+
+        // If this was a previously empty input queue, add it to input queue schedule.
+        if input_queue.num_messages() == 1 {
+            if respondent == *own_canister_id || local_canisters.contains_key(&respondent) {
+                self.local_subnet_input_schedule.push_back(respondent);
+            } else {
+                self.remote_subnet_input_schedule.push_back(respondent);
+            }
+        }
+
+        debug_assert!(self.stats_ok());
+        debug_assert!(self.schedules_ok(own_canister_id, local_canisters));
+
+        true
+    }
 }
 
 /// Generates a timeout reject response from a request, refunding its payment.
+/// The outcome of a timed out best-effort request is unknown to the caller,
+/// so the reject code is `SYS_UNKNOWN` for those.
 fn generate_timeout_response(request: &Arc<Request>) -> RequestOrResponse {
+    let reject_code = if request.is_best_effort() {
+        RejectCode::SysUnknown
+    } else {
+        RejectCode::SysTransient
+    };
     RequestOrResponse::Response(Arc::new(Response {
         originator: request.sender,
         respondent: request.receiver,
         originator_reply_callback: request.sender_reply_callback,
         refund: request.payment,
         response_payload: Payload::Reject(RejectContext::new_with_message_length_limit(
-            RejectCode::SysTransient,
+            reject_code,
             "Request timed out.".to_string(),
             MR_SYNTHETIC_REJECT_MESSAGE_MAX_LEN,
         )),
+        deadline: request.deadline,
     }))
 }
 
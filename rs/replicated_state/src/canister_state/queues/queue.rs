@@ -238,6 +238,12 @@ impl InputQueue {
         self.queue.queue.len()
     }
 
+    /// Returns an iterator over the messages in the queue, in the order in
+    /// which they will be popped.
+    pub(super) fn iter(&self) -> impl Iterator<Item = &RequestOrResponse> {
+        self.queue.queue.iter()
+    }
+
     /// Returns the number of reserved slots in the queue.
     pub(super) fn reserved_slots(&self) -> usize {
         self.queue.reserved_slots()
@@ -516,6 +522,32 @@ impl OutputQueue {
         self.num_messages
     }
 
+    /// Returns an iterator over the messages in the queue, in the order in
+    /// which they will be popped, together with their deadlines. Only requests
+    /// have deadlines, the deadline of a response is always `None`.
+    pub(super) fn iter_with_deadlines(
+        &self,
+    ) -> impl Iterator<Item = (&RequestOrResponse, Option<Time>)> {
+        let mut deadline_range_ends = self.deadline_range_ends.iter().peekable();
+        self.queue
+            .queue
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, item)| {
+                let index = self.begin + i;
+                // Skip the deadline ranges that end before this message.
+                while deadline_range_ends.next_if(|(_, end)| *end <= index).is_some() {}
+                let msg = item.as_ref()?;
+                let deadline = match msg {
+                    RequestOrResponse::Request(_) => {
+                        deadline_range_ends.peek().map(|(deadline, _)| *deadline)
+                    }
+                    RequestOrResponse::Response(_) => None,
+                };
+                Some((msg, deadline))
+            })
+    }
+
     /// Returns the number of reserved slots in the queue.
     pub(super) fn reserved_slots(&self) -> usize {
         self.queue.reserved_slots()
@@ -707,6 +739,12 @@ impl IngressQueue {
         self.queue.len()
     }
 
+    /// Returns an iterator over the messages in the queue, in the order in
+    /// which they will be popped.
+    pub(super) fn iter(&self) -> impl Iterator<Item = &Arc<Ingress>> {
+        self.queue.iter()
+    }
+
     pub(super) fn is_empty(&self) -> bool {
         self.size() == 0
     }
@@ -14,7 +14,11 @@ use ic_test_utilities::{
         messages::{IngressBuilder, RequestBuilder, ResponseBuilder},
     },
 };
-use ic_types::{messages::CallbackId, time::current_time_and_expiry_time};
+use ic_types::{
+    messages::{CallContextId, CallbackId},
+    methods::WasmClosure,
+    time::{current_time_and_expiry_time, CoarseTime, NO_DEADLINE},
+};
 use proptest::prelude::*;
 use std::convert::TryInto;
 
@@ -609,6 +613,107 @@ fn test_output_into_iter() {
     assert_eq!(0, queues.output_message_count());
 }
 
+/// Tests that the introspection methods describe all enqueued messages
+/// without consuming them.
+#[test]
+fn test_queued_messages_info() {
+    let this = canister_test_id(13);
+    let other_1 = canister_test_id(1);
+    let other_2 = canister_test_id(2);
+    let mut queues = CanisterQueues::default();
+
+    let expiry_time = current_time_and_expiry_time().1;
+    queues.push_ingress(Ingress {
+        source: user_test_id(77),
+        receiver: this,
+        effective_canister_id: None,
+        method_name: String::from("test"),
+        method_payload: vec![1, 2, 3],
+        message_id: message_test_id(555),
+        expiry_time,
+    });
+    let request = RequestBuilder::default()
+        .sender(other_1)
+        .receiver(this)
+        .method_payload(vec![1; 10])
+        .build();
+    queues
+        .push_input(request.clone().into(), InputQueueType::RemoteSubnet)
+        .unwrap();
+    let response = ResponseBuilder::default()
+        .respondent(this)
+        .originator(other_1)
+        .build();
+    queues.push_output_response(response.clone().into());
+    for (i, time) in [mock_time(), mock_time() + Duration::from_secs(10)]
+        .into_iter()
+        .enumerate()
+    {
+        queues
+            .push_output_request(
+                RequestBuilder::default()
+                    .sender(this)
+                    .receiver(other_2)
+                    .method_payload(vec![i as u8])
+                    .build()
+                    .into(),
+                time,
+            )
+            .unwrap();
+    }
+
+    assert_eq!(
+        vec![QueuedMessageInfo {
+            kind: QueuedMessageKind::Ingress,
+            sender: user_test_id(77).get(),
+            receiver: this,
+            payload_size_bytes: NumBytes::from(7),
+            deadline: Some(expiry_time),
+        }],
+        queues.ingress_queue_messages().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        vec![QueuedMessageInfo {
+            kind: QueuedMessageKind::Request,
+            sender: other_1.get(),
+            receiver: this,
+            payload_size_bytes: request.payload_size_bytes(),
+            deadline: None,
+        }],
+        queues.input_queues_messages().collect::<Vec<_>>()
+    );
+    let output_messages: Vec<_> = queues.output_queues_messages().collect();
+    assert_eq!(3, output_messages.len());
+    assert_eq!(
+        QueuedMessageInfo {
+            kind: QueuedMessageKind::Response,
+            sender: this.get(),
+            receiver: other_1,
+            payload_size_bytes: response.payload_size_bytes(),
+            deadline: None,
+        },
+        output_messages[0]
+    );
+    assert_eq!(
+        vec![
+            Some(mock_time() + REQUEST_LIFETIME),
+            Some(mock_time() + Duration::from_secs(10) + REQUEST_LIFETIME)
+        ],
+        output_messages[1..]
+            .iter()
+            .map(|info| info.deadline)
+            .collect::<Vec<_>>()
+    );
+    assert!(output_messages[1..]
+        .iter()
+        .all(|info| info.kind == QueuedMessageKind::Request && info.receiver == other_2));
+
+    // Nothing was consumed.
+    assert_eq!(1, queues.ingress_queue_message_count());
+    assert_eq!(1, queues.input_queues_message_count());
+    assert_eq!(3, queues.output_queues_message_count());
+}
+
 /// Tests that an encode-decode roundtrip yields a result equal to the
 /// original (and the queue size metrics of an organically constructed
 /// `CanisterQueues` match those of a deserialized one).
@@ -1626,6 +1731,7 @@ fn time_out_requests_pushes_correct_reject_responses() {
                     payment: Cycles::from(cycles as u64),
                     method_name: "No-Op".to_string(),
                     method_payload: vec![],
+                    deadline: NO_DEADLINE,
                 }),
                 deadline,
             )
@@ -1681,7 +1787,8 @@ fn time_out_requests_pushes_correct_reject_responses() {
                     RejectCode::SysTransient,
                     "Request timed out.".to_string(),
                     MR_SYNTHETIC_REJECT_MESSAGE_MAX_LEN
-                ))
+                )),
+                deadline: NO_DEADLINE,
             }),
             *reject_response,
         );
@@ -1715,3 +1822,82 @@ fn time_out_requests_pushes_correct_reject_responses() {
         VecDeque::from(vec![remote_canister_id]),
     );
 }
+
+#[test]
+fn time_out_callback_pushes_sys_unknown_reject() {
+    let mut canister_queues = CanisterQueues::default();
+    let own_canister_id = canister_test_id(67);
+    let remote_canister_id = canister_test_id(97);
+    let local_canisters = BTreeMap::new();
+
+    let callback_id = CallbackId::from(1);
+    let deadline = CoarseTime::from_secs_since_unix_epoch(10);
+    let callback = Callback::new(
+        CallContextId::from(1),
+        Some(own_canister_id),
+        Some(remote_canister_id),
+        Cycles::new(7),
+        None,
+        None,
+        WasmClosure::new(0, 1),
+        WasmClosure::new(2, 3),
+        None,
+        deadline,
+    );
+    let request = RequestBuilder::new()
+        .sender(own_canister_id)
+        .receiver(remote_canister_id)
+        .sender_reply_callback(callback_id)
+        .payment(Cycles::new(7))
+        .deadline(deadline)
+        .build();
+    canister_queues
+        .push_output_request(Arc::new(request), mock_time())
+        .unwrap();
+
+    // The request is still in the output queue, where it times out instead.
+    assert!(!canister_queues.time_out_callback(
+        callback_id,
+        &callback,
+        &own_canister_id,
+        &local_canisters
+    ));
+
+    assert!(canister_queues.output_into_iter(own_canister_id).next().is_some());
+    assert!(canister_queues.time_out_callback(
+        callback_id,
+        &callback,
+        &own_canister_id,
+        &local_canisters
+    ));
+    assert_eq!(
+        canister_queues.remote_subnet_input_schedule,
+        VecDeque::from(vec![remote_canister_id])
+    );
+
+    // A reject response is already enqueued.
+    assert!(!canister_queues.time_out_callback(
+        callback_id,
+        &callback,
+        &own_canister_id,
+        &local_canisters
+    ));
+
+    match canister_queues.pop_input() {
+        Some(CanisterInputMessage::Response(response)) => assert_eq!(
+            Response {
+                originator: own_canister_id,
+                respondent: remote_canister_id,
+                originator_reply_callback: callback_id,
+                refund: Cycles::zero(),
+                response_payload: Payload::Reject(RejectContext::new(
+                    RejectCode::SysUnknown,
+                    "Call deadline has expired.".to_string(),
+                )),
+                deadline,
+            },
+            *response
+        ),
+        msg => panic!("Expected a reject response, got {:?}", msg),
+    }
+}
@@ -0,0 +1,119 @@
+use super::execution_state::{ExecutionState, Memory};
+use super::system_state::WasmChunkStore;
+use super::CanisterState;
+use ic_types::{CanisterId, NumBytes, Time};
+
+/// A snapshot of the state of a canister that can be restored later.
+///
+/// The memories of the canister are captured as clones of their `PageMap`s
+/// that share all pages with the canister, so taking a snapshot doesn't copy
+/// the contents of the memories. Subsequent writes of the canister go to new
+/// pages and don't affect the snapshot. The Wasm binary and the chunks of the
+/// Wasm chunk store are shared as well.
+#[derive(Clone, Debug)]
+pub struct CanisterSnapshot {
+    canister_id: CanisterId,
+    taken_at_timestamp: Time,
+    canister_version: u64,
+    certified_data: Vec<u8>,
+    execution_state: Option<ExecutionState>,
+    wasm_chunk_store: WasmChunkStore,
+}
+
+impl CanisterSnapshot {
+    /// Takes a snapshot of the given canister at the given time.
+    pub fn new(canister: &CanisterState, taken_at_timestamp: Time) -> Self {
+        Self {
+            canister_id: canister.canister_id(),
+            taken_at_timestamp,
+            canister_version: canister.system_state.canister_version,
+            certified_data: canister.system_state.certified_data.clone(),
+            execution_state: canister.execution_state.as_ref().map(copy_on_write),
+            wasm_chunk_store: canister.system_state.wasm_chunk_store.clone(),
+        }
+    }
+
+    pub fn canister_id(&self) -> CanisterId {
+        self.canister_id
+    }
+
+    pub fn taken_at_timestamp(&self) -> Time {
+        self.taken_at_timestamp
+    }
+
+    /// The version of the canister when the snapshot was taken.
+    pub fn canister_version(&self) -> u64 {
+        self.canister_version
+    }
+
+    pub fn certified_data(&self) -> &[u8] {
+        &self.certified_data
+    }
+
+    /// The execution state of the canister when the snapshot was taken, if the
+    /// canister had a Wasm module.
+    pub fn execution_state(&self) -> Option<&ExecutionState> {
+        self.execution_state.as_ref()
+    }
+
+    /// The Wasm chunk store of the canister when the snapshot was taken.
+    pub fn wasm_chunk_store(&self) -> &WasmChunkStore {
+        &self.wasm_chunk_store
+    }
+
+    /// Returns the amount of memory that the snapshot accounts for: the
+    /// execution memory (heap, stable, globals, Wasm), the Wasm chunk store,
+    /// and the certified data.
+    ///
+    /// Note that this is the logical size of the snapshot. Most of its pages
+    /// are physically shared with the canister.
+    pub fn memory_usage(&self) -> NumBytes {
+        let execution_memory_usage = self
+            .execution_state
+            .as_ref()
+            .map_or(NumBytes::from(0), |es| es.memory_usage());
+        execution_memory_usage
+            + self.wasm_chunk_store.memory_usage()
+            + NumBytes::from(self.certified_data.len() as u64)
+    }
+}
+
+impl CanisterState {
+    /// Takes a copy-on-write snapshot of this canister at the given time.
+    /// See `CanisterSnapshot` for details.
+    pub fn take_snapshot(&self, time: Time) -> CanisterSnapshot {
+        CanisterSnapshot::new(self, time)
+    }
+
+    /// Replaces the execution state, the Wasm chunk store, and the certified
+    /// data of this canister with the ones captured by the given snapshot. The snapshot remains
+    /// valid and can be restored again.
+    ///
+    /// The canister version is not changed, bumping it is up to the caller.
+    pub fn restore_snapshot(&mut self, snapshot: &CanisterSnapshot) -> Result<(), String> {
+        if snapshot.canister_id != self.canister_id() {
+            return Err(format!(
+                "Snapshot of canister {} cannot be restored to canister {}",
+                snapshot.canister_id,
+                self.canister_id()
+            ));
+        }
+        self.execution_state = snapshot.execution_state.as_ref().map(copy_on_write);
+        self.system_state.wasm_chunk_store = snapshot.wasm_chunk_store.clone();
+        self.system_state.certified_data = snapshot.certified_data.clone();
+        Ok(())
+    }
+}
+
+// Returns a copy of the given execution state that shares the pages of its
+// memories and its Wasm binary. The memories of the copy are not synced with
+// any sandbox process, so that the copy is independent of the original.
+fn copy_on_write(execution_state: &ExecutionState) -> ExecutionState {
+    let copy_memory = |memory: &Memory| Memory::new(memory.page_map.clone(), memory.size);
+    ExecutionState {
+        session_nonce: None,
+        wasm_memory: copy_memory(&execution_state.wasm_memory),
+        stable_memory: copy_memory(&execution_state.stable_memory),
+        ..execution_state.clone()
+    }
+}
@@ -1,11 +1,18 @@
 mod call_context_manager;
+mod canister_log;
+mod wasm_chunk_store;
 
 use super::queues::can_push;
 pub use super::queues::memory_required_to_push_request;
 pub use crate::canister_state::queues::CanisterOutputQueuesIterator;
 use crate::{CanisterQueues, CanisterState, InputQueueType, StateError};
 pub use call_context_manager::{CallContext, CallContextAction, CallContextManager, CallOrigin};
+pub use canister_log::{CanisterLog, MAX_CANISTER_LOG_SIZE};
+pub use wasm_chunk_store::{
+    wasm_chunk_hash, WasmChunkHash, WasmChunkStore, WasmChunkStoreError, MAX_CHUNKS, MAX_CHUNK_SIZE,
+};
 use ic_base_types::NumSeconds;
+use ic_ic00_types::LogVisibility;
 use ic_interfaces::messages::{CanisterInputMessage, RequestOrIngress};
 use ic_logger::{error, ReplicaLogger};
 use ic_protobuf::{
@@ -14,7 +21,10 @@ use ic_protobuf::{
 };
 use ic_registry_subnet_type::SubnetType;
 use ic_types::{
-    messages::{Ingress, RejectContext, Request, RequestOrResponse, Response, StopCanisterContext},
+    messages::{
+        CallbackId, Ingress, RejectContext, Request, RequestOrResponse, Response,
+        StopCanisterContext,
+    },
     nominal_cycles::NominalCycles,
     CanisterId, CanisterTimer, Cycles, MemoryAllocation, NumBytes, PrincipalId, Time,
 };
@@ -24,6 +34,7 @@ use serde::{Deserialize, Serialize};
 use std::{
     collections::BTreeMap,
     convert::{TryFrom, TryInto},
+    time::Duration,
 };
 use std::{collections::BTreeSet, sync::Arc};
 use std::{collections::VecDeque, str::FromStr};
@@ -103,6 +114,15 @@ pub struct SystemState {
     /// it will apply `cycles_debit` to `cycles_balance`.
     cycles_debit: Cycles,
 
+    /// Cycles moved out of `cycles_balance` when the canister allocated memory
+    /// on a saturated subnet. They pay for the storage of the canister before
+    /// `cycles_balance` does and can't be used for anything else.
+    reserved_balance: Cycles,
+
+    /// The upper limit of `reserved_balance` set by the controllers. Memory
+    /// allocations that would exceed it fail. `None` means no limit.
+    reserved_balance_limit: Option<Cycles>,
+
     /// Tasks to execute before processing input messages.
     /// Currently the task queue is empty outside of execution rounds.
     pub task_queue: VecDeque<ExecutionTask>,
@@ -112,6 +132,40 @@ pub struct SystemState {
 
     /// Canister version.
     pub canister_version: u64,
+
+    /// Chunks of Wasm modules uploaded with `upload_chunk`. The chunks count
+    /// towards the memory usage of the canister.
+    pub wasm_chunk_store: WasmChunkStore,
+
+    /// Who may fetch the canister log with `fetch_canister_logs`.
+    pub log_visibility: LogVisibility,
+
+    /// The debug prints and trap messages of the executions of the canister.
+    pub canister_log: CanisterLog,
+
+    /// If the remaining Wasm memory of the canister drops below this
+    /// threshold, then the `canister_on_low_wasm_memory` system task runs.
+    /// Zero disables the hook.
+    pub wasm_memory_threshold: NumBytes,
+
+    /// Whether `canister_on_low_wasm_memory` already ran since the remaining
+    /// Wasm memory dropped below `wasm_memory_threshold`. The hook runs again
+    /// only after the remaining Wasm memory recovers.
+    pub on_low_wasm_memory_hook_executed: bool,
+
+    /// The upper limit of the Wasm memory of the canister set by the
+    /// controllers. Executions that grow the Wasm memory beyond it trap,
+    /// except for queries and system tasks. `None` means no limit.
+    pub wasm_memory_limit: Option<NumBytes>,
+}
+
+/// Errors of `SystemState::reserve_cycles()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReservationError {
+    /// The balance is smaller than the cycles to reserve.
+    InsufficientCycles { requested: Cycles, available: Cycles },
+    /// The reserved balance would exceed the limit set by the controllers.
+    ReservedLimitExceed { requested: Cycles, limit: Cycles },
 }
 
 /// A wrapper around the different canister statuses.
@@ -211,6 +265,10 @@ pub enum ExecutionTask {
     /// The task exists only within an execution round, it never gets serialized.
     GlobalTimer,
 
+    /// Canister on low Wasm memory task.
+    /// The task exists only within an execution round, it never gets serialized.
+    OnLowWasmMemory,
+
     // A paused execution task exists only within an epoch (between
     // checkpoints). It is never serialized, and it turns into `AbortedExecution`
     // before the checkpoint or when there are too many long-running executions.
@@ -249,6 +307,7 @@ impl From<&ExecutionTask> for pb::ExecutionTask {
         match item {
             ExecutionTask::Heartbeat
             | ExecutionTask::GlobalTimer
+            | ExecutionTask::OnLowWasmMemory
             | ExecutionTask::PausedExecution(_)
             | ExecutionTask::PausedInstallCode(_) => {
                 panic!("Attempt to serialize ephemeral task: {:?}.", item);
@@ -408,6 +467,8 @@ impl SystemState {
             queues: CanisterQueues::default(),
             cycles_balance: initial_cycles,
             cycles_debit: Cycles::zero(),
+            reserved_balance: Cycles::zero(),
+            reserved_balance_limit: None,
             memory_allocation: MemoryAllocation::BestEffort,
             freeze_threshold,
             status,
@@ -416,6 +477,12 @@ impl SystemState {
             task_queue: Default::default(),
             global_timer: CanisterTimer::Inactive,
             canister_version: 0,
+            wasm_chunk_store: WasmChunkStore::default(),
+            log_visibility: LogVisibility::default(),
+            canister_log: CanisterLog::default(),
+            wasm_memory_threshold: NumBytes::from(0),
+            on_low_wasm_memory_hook_executed: false,
+            wasm_memory_limit: None,
         }
     }
 
@@ -447,9 +514,17 @@ impl SystemState {
         canister_metrics: CanisterMetrics,
         cycles_balance: Cycles,
         cycles_debit: Cycles,
+        reserved_balance: Cycles,
+        reserved_balance_limit: Option<Cycles>,
         task_queue: VecDeque<ExecutionTask>,
         global_timer: CanisterTimer,
         canister_version: u64,
+        wasm_chunk_store: WasmChunkStore,
+        log_visibility: LogVisibility,
+        canister_log: CanisterLog,
+        wasm_memory_threshold: NumBytes,
+        on_low_wasm_memory_hook_executed: bool,
+        wasm_memory_limit: Option<NumBytes>,
     ) -> Self {
         Self {
             controllers,
@@ -462,9 +537,17 @@ impl SystemState {
             canister_metrics,
             cycles_balance,
             cycles_debit,
+            reserved_balance,
+            reserved_balance_limit,
             task_queue,
             global_timer,
             canister_version,
+            wasm_chunk_store,
+            log_visibility,
+            canister_log,
+            wasm_memory_threshold,
+            on_low_wasm_memory_hook_executed,
+            wasm_memory_limit,
         }
     }
 
@@ -482,6 +565,56 @@ impl SystemState {
         self.cycles_balance
     }
 
+    /// Returns the cycles reserved for future storage payments.
+    pub fn reserved_balance(&self) -> Cycles {
+        self.reserved_balance
+    }
+
+    /// Returns the upper limit of the reserved balance, if any.
+    pub fn reserved_balance_limit(&self) -> Option<Cycles> {
+        self.reserved_balance_limit
+    }
+
+    /// Sets the upper limit of the reserved balance. `None` removes the limit.
+    pub fn set_reserved_balance_limit(&mut self, limit: Option<Cycles>) {
+        self.reserved_balance_limit = limit;
+    }
+
+    /// Moves `amount` cycles from the balance to the reserved balance.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error without changing the balances if the reserved balance
+    /// would exceed its limit or if the balance is smaller than `amount`.
+    pub fn reserve_cycles(&mut self, amount: Cycles) -> Result<(), ReservationError> {
+        if amount.get() == 0 {
+            return Ok(());
+        }
+        if let Some(limit) = self.reserved_balance_limit {
+            let requested = self.reserved_balance + amount;
+            if requested > limit {
+                return Err(ReservationError::ReservedLimitExceed { requested, limit });
+            }
+        }
+        if amount > self.cycles_balance {
+            return Err(ReservationError::InsufficientCycles {
+                requested: amount,
+                available: self.cycles_balance,
+            });
+        }
+        self.cycles_balance -= amount;
+        self.reserved_balance += amount;
+        Ok(())
+    }
+
+    /// Removes up to `amount` cycles from the reserved balance to pay for
+    /// storage. Returns the removed cycles.
+    pub fn take_reserved_cycles(&mut self, amount: Cycles) -> Cycles {
+        let taken = amount.min(self.reserved_balance);
+        self.reserved_balance -= taken;
+        taken
+    }
+
     /// Returns the balance after applying the pending debit.
     /// Returns 0 if the balance is smaller than the pending debit.
     pub fn debited_balance(&self) -> Cycles {
@@ -697,7 +830,7 @@ impl SystemState {
             msg.receiver()
         );
 
-        match (&msg, &self.status) {
+        match (&msg, &mut self.status) {
             // Requests and responses are both rejected when stopped.
             (_, CanisterStatus::Stopped { .. }) => {
                 Err((StateError::CanisterStopped(self.canister_id()), msg))
@@ -723,6 +856,12 @@ impl SystemState {
                 },
             ) => {
                 if let RequestOrResponse::Response(response) = &msg {
+                    if call_context_manager.is_late_response(response) {
+                        // The call has already been answered with a
+                        // `SYS_UNKNOWN` reject, drop the response.
+                        call_context_manager.drop_late_response(response);
+                        return Ok(());
+                    }
                     call_context_manager
                         .validate_response(response)
                         .map_err(|err| (err, msg.clone()))?;
@@ -843,28 +982,47 @@ impl SystemState {
         own_subnet_type: SubnetType,
     ) {
         // Bail out if the canister is not running.
-        match self.status {
-            CanisterStatus::Running { .. } => (),
+        let call_context_manager = match &mut self.status {
+            CanisterStatus::Running {
+                call_context_manager,
+            } => call_context_manager,
             CanisterStatus::Stopped | CanisterStatus::Stopping { .. } => return,
-        }
+        };
 
         let mut available_memory = canister_available_memory.min(*subnet_available_memory);
         let mut memory_usage = self.queues.memory_usage() as i64;
 
         while let Some(msg) = self.queues.peek_output(&self.canister_id) {
-            // Ensure that enough memory is available for inducting `msg`.
-            if own_subnet_type != SubnetType::System && can_push(msg, available_memory).is_err() {
-                // Bail out if not enough memory available for message.
-                return;
-            }
+            let late_response = match msg {
+                RequestOrResponse::Response(response)
+                    if call_context_manager.is_late_response(response) =>
+                {
+                    Some(Arc::clone(response))
+                }
+                _ => None,
+            };
+
+            if let Some(response) = late_response {
+                // Drop late responses instead of inducting them.
+                call_context_manager.drop_late_response(&response);
+                self.queues.pop_message_to_self(self.canister_id);
+            } else {
+                // Ensure that enough memory is available for inducting `msg`.
+                if own_subnet_type != SubnetType::System
+                    && can_push(msg, available_memory).is_err()
+                {
+                    // Bail out if not enough memory available for message.
+                    return;
+                }
 
-            // Attempt inducting `msg`. May fail if the input queue is full.
-            if self
-                .queues
-                .induct_message_to_self(self.canister_id)
-                .is_err()
-            {
-                return;
+                // Attempt inducting `msg`. May fail if the input queue is full.
+                if self
+                    .queues
+                    .induct_message_to_self(self.canister_id)
+                    .is_err()
+                {
+                    return;
+                }
             }
 
             // Adjust both `available_memory` and `subnet_available_memory` by
@@ -902,6 +1060,104 @@ impl SystemState {
         self.queues
             .time_out_requests(current_time, own_canister_id, local_canisters)
     }
+
+    /// Queries whether the deadline of any best-effort callback that has not
+    /// been timed out yet has expired.
+    pub fn has_expired_callbacks(&self, current_time: Time) -> bool {
+        self.call_context_manager()
+            .map_or(false, |ccm| ccm.has_expired_callbacks(current_time))
+    }
+
+    /// Times out the best-effort callbacks with expired deadlines, enqueuing a
+    /// `SYS_UNKNOWN` reject response for each one whose request has left the
+    /// output queue and that has no response enqueued yet. Returns the number
+    /// of callbacks that were timed out.
+    ///
+    /// See `CanisterQueues::time_out_callback` for further details.
+    pub fn time_out_callbacks(
+        &mut self,
+        current_time: Time,
+        own_canister_id: &CanisterId,
+        local_canisters: &BTreeMap<CanisterId, CanisterState>,
+    ) -> u64 {
+        self.time_out_callbacks_impl(
+            |call_context_manager| call_context_manager.take_expired_callbacks(current_time),
+            own_canister_id,
+            local_canisters,
+        )
+        .len() as u64
+    }
+
+    /// Queries whether any guaranteed response callback that has not been
+    /// timed out yet belongs to a call context that is at least
+    /// `max_lifetime` old.
+    pub fn has_stuck_callbacks(&self, current_time: Time, max_lifetime: Duration) -> bool {
+        self.call_context_manager().map_or(false, |ccm| {
+            !ccm.stuck_callbacks(current_time, max_lifetime).is_empty()
+        })
+    }
+
+    /// Times out the guaranteed response callbacks of call contexts that are
+    /// at least `max_lifetime` old, i.e. whose callees did not respond in
+    /// time, enqueuing a `SYS_UNKNOWN` reject response for each one whose
+    /// request has left the output queue and that has no response enqueued
+    /// yet. Late responses to these callbacks are dropped. Returns the callees
+    /// of the callbacks that were timed out.
+    pub fn time_out_stuck_callbacks(
+        &mut self,
+        current_time: Time,
+        max_lifetime: Duration,
+        own_canister_id: &CanisterId,
+        local_canisters: &BTreeMap<CanisterId, CanisterState>,
+    ) -> Vec<CanisterId> {
+        self.time_out_callbacks_impl(
+            |call_context_manager| call_context_manager.stuck_callbacks(current_time, max_lifetime),
+            own_canister_id,
+            local_canisters,
+        )
+    }
+
+    /// Times out the callbacks returned by `take_callbacks`, see
+    /// `CanisterQueues::time_out_callback`. Returns the callees of the
+    /// callbacks that were timed out.
+    fn time_out_callbacks_impl<F>(
+        &mut self,
+        take_callbacks: F,
+        own_canister_id: &CanisterId,
+        local_canisters: &BTreeMap<CanisterId, CanisterState>,
+    ) -> Vec<CanisterId>
+    where
+        F: FnOnce(&mut CallContextManager) -> Vec<CallbackId>,
+    {
+        let call_context_manager = match &mut self.status {
+            CanisterStatus::Running {
+                call_context_manager,
+            }
+            | CanisterStatus::Stopping {
+                call_context_manager,
+                ..
+            } => call_context_manager,
+            CanisterStatus::Stopped => return vec![],
+        };
+
+        let mut callees = Vec::new();
+        for callback_id in take_callbacks(call_context_manager) {
+            let callback = match call_context_manager.callback(&callback_id) {
+                Some(callback) => callback,
+                None => continue,
+            };
+            if self.queues.time_out_callback(
+                callback_id,
+                callback,
+                own_canister_id,
+                local_canisters,
+            ) {
+                callees.extend(callback.respondent);
+                call_context_manager.mark_callback_expired(callback_id);
+            }
+        }
+        callees
+    }
 }
 
 /// Implements memory limits verification for pushing a canister-to-canister
@@ -7,6 +7,7 @@ use ic_protobuf::proxy::{try_from_option_field, ProxyDecodeError};
 use ic_protobuf::state::canister_state_bits::v1 as pb;
 use ic_protobuf::types::v1 as pb_types;
 use ic_types::messages::Response;
+use ic_types::time::{CoarseTime, NO_DEADLINE};
 use ic_types::Time;
 use ic_types::{
     ingress::WasmResult,
@@ -15,7 +16,7 @@ use ic_types::{
     user_id_into_protobuf, user_id_try_from_protobuf, CanisterId, Cycles, Funds, UserId,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::{From, TryFrom, TryInto};
 use std::time::Duration;
 
@@ -210,12 +211,28 @@ pub struct CallContextManager {
     // maps call context to its responded status
     call_contexts: BTreeMap<CallContextId, CallContext>,
     callbacks: BTreeMap<CallbackId, Callback>,
+    /// The best-effort callbacks ordered by deadline, until they are
+    /// considered for expiration. Derived from `callbacks` and
+    /// `expired_callbacks`, so it is not persisted.
+    unexpired_callbacks: BTreeSet<(CoarseTime, CallbackId)>,
+    /// The guaranteed response callbacks that have not been answered with a
+    /// `SYS_UNKNOWN` reject, ordered by call context and thus by the age of
+    /// the call context. Derived from `callbacks` and `expired_callbacks`, so
+    /// it is not persisted.
+    unexpired_guaranteed_response_callbacks: BTreeSet<(CallContextId, CallbackId)>,
+    /// The callbacks that have been answered with a `SYS_UNKNOWN` reject,
+    /// either because their deadline expired (best-effort calls) or because
+    /// their call context exceeded the maximum lifetime (guaranteed response
+    /// calls). Late responses to these callbacks are dropped.
+    expired_callbacks: BTreeSet<CallbackId>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CallOrigin {
     Ingress(UserId, MessageId),
-    CanisterUpdate(CanisterId, CallbackId),
+    /// A call from a canister, with the deadline of the call (`NO_DEADLINE`
+    /// for guaranteed response calls).
+    CanisterUpdate(CanisterId, CallbackId, CoarseTime),
     Query(UserId),
     CanisterQuery(CanisterId, CallbackId),
     /// System task is either a Heartbeat or a GlobalTimer.
@@ -229,10 +246,11 @@ impl From<&CallOrigin> for pb::call_context::CallOrigin {
                 user_id: Some(user_id_into_protobuf(*user_id)),
                 message_id: message_id.as_bytes().to_vec(),
             }),
-            CallOrigin::CanisterUpdate(canister_id, callback_id) => {
+            CallOrigin::CanisterUpdate(canister_id, callback_id, deadline) => {
                 Self::CanisterUpdate(pb::call_context::CanisterUpdateOrQuery {
                     canister_id: Some(pb_types::CanisterId::from(*canister_id)),
                     callback_id: callback_id.get(),
+                    deadline_seconds: deadline.as_secs_since_unix_epoch(),
                 })
             }
             CallOrigin::Query(user_id) => Self::Query(user_id_into_protobuf(*user_id)),
@@ -240,6 +258,7 @@ impl From<&CallOrigin> for pb::call_context::CallOrigin {
                 Self::CanisterQuery(pb::call_context::CanisterUpdateOrQuery {
                     canister_id: Some(pb_types::CanisterId::from(*canister_id)),
                     callback_id: callback_id.get(),
+                    deadline_seconds: NO_DEADLINE.as_secs_since_unix_epoch(),
                 })
             }
             CallOrigin::SystemTask => Self::SystemTask(pb::call_context::SystemTask {}),
@@ -265,10 +284,12 @@ impl TryFrom<pb::call_context::CallOrigin> for CallOrigin {
                 pb::call_context::CanisterUpdateOrQuery {
                     canister_id,
                     callback_id,
+                    deadline_seconds,
                 },
             ) => Self::CanisterUpdate(
                 try_from_option_field(canister_id, "CallOrigin::CanisterUpdate::canister_id")?,
                 callback_id.into(),
+                CoarseTime::from_secs_since_unix_epoch(deadline_seconds),
             ),
             pb::call_context::CallOrigin::Query(user_id) => {
                 Self::Query(user_id_try_from_protobuf(user_id)?)
@@ -277,6 +298,7 @@ impl TryFrom<pb::call_context::CallOrigin> for CallOrigin {
                 pb::call_context::CanisterUpdateOrQuery {
                     canister_id,
                     callback_id,
+                    ..
                 },
             ) => Self::CanisterQuery(
                 try_from_option_field(canister_id, "CallOrigin::CanisterQuery::canister_id")?,
@@ -322,6 +344,25 @@ impl CallContextManager {
         &mut self.call_contexts
     }
 
+    /// Marks all call contexts as deleted, e.g., when the canister is
+    /// uninstalled, and returns the call contexts that had not responded yet,
+    /// with their available cycles. The caller must reject them.
+    ///
+    /// The callbacks stay registered until their responses arrive. The
+    /// responses then find a deleted call context and are not executed.
+    pub fn delete_all_call_contexts(&mut self) -> Vec<CallContext> {
+        let mut unresponded = Vec::new();
+        for call_context in self.call_contexts.values_mut() {
+            call_context.mark_deleted();
+            if !call_context.has_responded() {
+                unresponded.push(call_context.clone());
+                call_context.mark_responded();
+            }
+        }
+        debug_assert!(self.check_invariants());
+        unresponded
+    }
+
     /// Returns a reference to the call context with `call_context_id`.
     pub fn call_context(&self, call_context_id: CallContextId) -> Option<&CallContext> {
         self.call_contexts.get(&call_context_id)
@@ -488,6 +529,12 @@ impl CallContextManager {
     pub fn register_callback(&mut self, callback: Callback) -> CallbackId {
         self.next_callback_id += 1;
         let callback_id = CallbackId::from(self.next_callback_id);
+        if callback.deadline != NO_DEADLINE {
+            self.unexpired_callbacks.insert((callback.deadline, callback_id));
+        } else {
+            self.unexpired_guaranteed_response_callbacks
+                .insert((callback.call_context_id, callback_id));
+        }
         self.callbacks.insert(callback_id, callback);
         callback_id
     }
@@ -500,7 +547,103 @@ impl CallContextManager {
     /// If we get a response for one of the outstanding calls, we unregister
     /// the callback and return it.
     pub fn unregister_callback(&mut self, callback_id: CallbackId) -> Option<Callback> {
-        self.callbacks.remove(&callback_id)
+        let callback = self.callbacks.remove(&callback_id)?;
+        self.unexpired_callbacks.remove(&(callback.deadline, callback_id));
+        self.unexpired_guaranteed_response_callbacks
+            .remove(&(callback.call_context_id, callback_id));
+        // The response to a guaranteed response call is still going to arrive,
+        // so the callback stays expired until the late response is dropped.
+        if callback.deadline != NO_DEADLINE {
+            self.expired_callbacks.remove(&callback_id);
+        }
+        Some(callback)
+    }
+
+    /// Returns true if the deadline of at least one best-effort callback that
+    /// has not been considered for expiration yet is not later than
+    /// `current_time`.
+    pub(crate) fn has_expired_callbacks(&self, current_time: Time) -> bool {
+        match self.unexpired_callbacks.iter().next() {
+            Some((deadline, _)) => deadline.as_time() <= current_time,
+            None => false,
+        }
+    }
+
+    /// Removes and returns the best-effort callbacks whose deadline is not
+    /// later than `current_time` from the callbacks to be considered for
+    /// expiration.
+    pub(crate) fn take_expired_callbacks(&mut self, current_time: Time) -> Vec<CallbackId> {
+        let mut expired_callbacks = Vec::new();
+        while let Some(&(deadline, callback_id)) = self.unexpired_callbacks.iter().next() {
+            if deadline.as_time() > current_time {
+                break;
+            }
+            self.unexpired_callbacks.remove(&(deadline, callback_id));
+            expired_callbacks.push(callback_id);
+        }
+        expired_callbacks
+    }
+
+    /// Returns the guaranteed response callbacks that have not been answered
+    /// with a `SYS_UNKNOWN` reject yet and whose call context was created at
+    /// least `max_lifetime` before `current_time`.
+    pub(crate) fn stuck_callbacks(
+        &self,
+        current_time: Time,
+        max_lifetime: Duration,
+    ) -> Vec<CallbackId> {
+        // Call contexts are created in order of increasing `CallContextId` and
+        // time, see `call_contexts_older_than()`, so the scan stops at the
+        // first call context that is not old enough.
+        let mut stuck_callbacks = Vec::new();
+        for (call_context_id, callback_id) in &self.unexpired_guaranteed_response_callbacks {
+            match self.call_contexts.get(call_context_id).and_then(|cc| cc.time()) {
+                Some(context_time) if context_time + max_lifetime <= current_time => {
+                    stuck_callbacks.push(*callback_id)
+                }
+                Some(_) => break,
+                // Callbacks without a call context or with a call context
+                // without a creation time never time out.
+                None => continue,
+            }
+        }
+        stuck_callbacks
+    }
+
+    /// Records that the given callback has been answered with a `SYS_UNKNOWN`
+    /// reject.
+    pub(crate) fn mark_callback_expired(&mut self, callback_id: CallbackId) {
+        if let Some(callback) = self.callbacks.get(&callback_id) {
+            self.unexpired_guaranteed_response_callbacks
+                .remove(&(callback.call_context_id, callback_id));
+        }
+        self.expired_callbacks.insert(callback_id);
+    }
+
+    /// Returns true if the given response is a late response, i.e. its
+    /// callback has already been answered with a `SYS_UNKNOWN` reject or, for
+    /// a best-effort call, is unknown. Such responses are dropped on
+    /// induction.
+    pub(crate) fn is_late_response(&self, response: &Response) -> bool {
+        let callback_id = response.originator_reply_callback;
+        self.expired_callbacks.contains(&callback_id)
+            || (response.is_best_effort() && !self.callbacks.contains_key(&callback_id))
+    }
+
+    /// Drops the given late response, see `is_late_response()`. Once the
+    /// late response to an unregistered guaranteed response callback has
+    /// arrived, the callback is forgotten.
+    pub(crate) fn drop_late_response(&mut self, response: &Response) {
+        let callback_id = response.originator_reply_callback;
+        if !response.is_best_effort() && !self.callbacks.contains_key(&callback_id) {
+            self.expired_callbacks.remove(&callback_id);
+        }
+    }
+
+    /// Returns true if the given callback has been answered with a
+    /// `SYS_UNKNOWN` reject.
+    pub fn is_callback_expired(&self, callback_id: CallbackId) -> bool {
+        self.expired_callbacks.contains(&callback_id)
     }
 
     /// Returns the call origin, which is either the message id of the ingress
@@ -538,6 +681,36 @@ impl CallContextManager {
                 .all(|(_, callback)| self.call_contexts.contains_key(&callback.call_context_id))
     }
 
+    /// Call context manager invariant check that panics if any invariant does
+    /// not hold. Intended to be called from within a `debug_assert!()` in
+    /// production code.
+    ///
+    /// # Panics
+    ///
+    /// If an invariant is violated.
+    fn check_invariants(&self) -> bool {
+        if let Err(err) = self.test_invariants() {
+            panic!("{}", err);
+        }
+        true
+    }
+
+    /// Call context manager invariant check that produces an error if any
+    /// invariant does not hold.
+    fn test_invariants(&self) -> Result<(), String> {
+        for (call_context_id, call_context) in self.call_contexts.iter() {
+            // A deleted call context cannot respond anymore, so it must have
+            // been rejected when it was deleted.
+            if call_context.is_deleted() && !call_context.has_responded() {
+                return Err(format!(
+                    "Call context {} is deleted but has not responded",
+                    call_context_id
+                ));
+            }
+        }
+        Ok(())
+    }
+
     /// Expose the `next_callback_id` field so that the canister sandbox can
     /// predict what the new ids will be.
     pub fn next_callback_id(&self) -> u64 {
@@ -576,7 +749,11 @@ impl From<&RequestOrIngress> for CallOrigin {
     fn from(msg: &RequestOrIngress) -> Self {
         match msg {
             RequestOrIngress::Request(request) => {
-                CallOrigin::CanisterUpdate(request.sender, request.sender_reply_callback)
+                CallOrigin::CanisterUpdate(
+                    request.sender,
+                    request.sender_reply_callback,
+                    request.deadline,
+                )
             }
             RequestOrIngress::Ingress(ingress) => {
                 CallOrigin::Ingress(ingress.source, ingress.message_id.clone())
@@ -606,6 +783,7 @@ impl From<&CallContextManager> for pb::CallContextManager {
                     callback: Some(callback.into()),
                 })
                 .collect(),
+            expired_callback_ids: item.expired_callbacks.iter().map(|id| id.get()).collect(),
         }
     }
 }
@@ -636,11 +814,34 @@ impl TryFrom<pb::CallContextManager> for CallContextManager {
             );
         }
 
+        let expired_callbacks: BTreeSet<CallbackId> = value
+            .expired_callback_ids
+            .into_iter()
+            .map(CallbackId::from)
+            .collect();
+        let unexpired_callbacks = callbacks
+            .iter()
+            .filter(|(id, callback)| {
+                callback.deadline != NO_DEADLINE && !expired_callbacks.contains(id)
+            })
+            .map(|(id, callback)| (callback.deadline, *id))
+            .collect();
+        let unexpired_guaranteed_response_callbacks = callbacks
+            .iter()
+            .filter(|(id, callback)| {
+                callback.deadline == NO_DEADLINE && !expired_callbacks.contains(id)
+            })
+            .map(|(id, callback)| (callback.call_context_id, *id))
+            .collect();
+
         Ok(Self {
             next_call_context_id: value.next_call_context_id,
             next_callback_id: value.next_callback_id,
             call_contexts,
             callbacks,
+            unexpired_callbacks,
+            unexpired_guaranteed_response_callbacks,
+            expired_callbacks,
         })
     }
 }
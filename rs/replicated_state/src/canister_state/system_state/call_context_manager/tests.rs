@@ -1,6 +1,6 @@
 use super::*;
 use ic_test_utilities::types::ids::canister_test_id;
-use ic_types::methods::WasmClosure;
+use ic_types::{messages::Payload, methods::WasmClosure};
 
 #[test]
 fn call_context_origin() {
@@ -8,13 +8,13 @@ fn call_context_origin() {
     let id = canister_test_id(42);
     let cb_id = CallbackId::from(1);
     let cc_id = ccm.new_call_context(
-        CallOrigin::CanisterUpdate(id, cb_id),
+        CallOrigin::CanisterUpdate(id, cb_id, NO_DEADLINE),
         Cycles::new(10),
         Time::from_nanos_since_unix_epoch(0),
     );
     assert_eq!(
         ccm.call_contexts().get(&cc_id).unwrap().call_origin,
-        CallOrigin::CanisterUpdate(id, cb_id)
+        CallOrigin::CanisterUpdate(id, cb_id, NO_DEADLINE)
     );
 }
 
@@ -26,18 +26,18 @@ fn call_context_handling() {
 
     // On two incoming calls
     let call_context_id1 = call_context_manager.new_call_context(
-        CallOrigin::CanisterUpdate(canister_test_id(123), CallbackId::from(1)),
+        CallOrigin::CanisterUpdate(canister_test_id(123), CallbackId::from(1), NO_DEADLINE),
         Cycles::zero(),
         Time::from_nanos_since_unix_epoch(0),
     );
     let call_context_id2 = call_context_manager.new_call_context(
-        CallOrigin::CanisterUpdate(canister_test_id(123), CallbackId::from(2)),
+        CallOrigin::CanisterUpdate(canister_test_id(123), CallbackId::from(2), NO_DEADLINE),
         Cycles::zero(),
         Time::from_nanos_since_unix_epoch(0),
     );
 
     let call_context_id3 = call_context_manager.new_call_context(
-        CallOrigin::CanisterUpdate(canister_test_id(123), CallbackId::from(3)),
+        CallOrigin::CanisterUpdate(canister_test_id(123), CallbackId::from(3), NO_DEADLINE),
         Cycles::zero(),
         Time::from_nanos_since_unix_epoch(0),
     );
@@ -78,6 +78,7 @@ fn call_context_handling() {
         WasmClosure::new(0, 1),
         WasmClosure::new(2, 3),
         None,
+        NO_DEADLINE,
     ));
     let callback_id2 = call_context_manager.register_callback(Callback::new(
         call_context_id1,
@@ -89,6 +90,7 @@ fn call_context_handling() {
         WasmClosure::new(4, 5),
         WasmClosure::new(6, 7),
         None,
+        NO_DEADLINE,
     ));
 
     // There are 2 ougoing calls
@@ -105,6 +107,7 @@ fn call_context_handling() {
         WasmClosure::new(8, 9),
         WasmClosure::new(10, 11),
         None,
+        NO_DEADLINE,
     ));
     // There is 1 outgoing call
     assert_eq!(call_context_manager.outstanding_calls(call_context_id2), 1);
@@ -254,7 +257,7 @@ fn withdraw_cycles_fails_when_not_enough_available_cycles() {
     let id = canister_test_id(42);
     let cb_id = CallbackId::from(1);
     let cc_id = ccm.new_call_context(
-        CallOrigin::CanisterUpdate(id, cb_id),
+        CallOrigin::CanisterUpdate(id, cb_id, NO_DEADLINE),
         Cycles::new(30),
         Time::from_nanos_since_unix_epoch(0),
     );
@@ -276,7 +279,7 @@ fn withdraw_cycles_succeeds_when_enough_available_cycles() {
     let id = canister_test_id(42);
     let cb_id = CallbackId::from(1);
     let cc_id = ccm.new_call_context(
-        CallOrigin::CanisterUpdate(id, cb_id),
+        CallOrigin::CanisterUpdate(id, cb_id, NO_DEADLINE),
         Cycles::new(30),
         Time::from_nanos_since_unix_epoch(0),
     );
@@ -288,3 +291,172 @@ fn withdraw_cycles_succeeds_when_enough_available_cycles() {
         Ok(())
     );
 }
+
+#[test]
+fn expired_callbacks_are_taken_in_deadline_order() {
+    let mut ccm = CallContextManager::default();
+    let cc_id = ccm.new_call_context(
+        CallOrigin::CanisterUpdate(canister_test_id(42), CallbackId::from(1), NO_DEADLINE),
+        Cycles::zero(),
+        Time::from_nanos_since_unix_epoch(0),
+    );
+    let mut register_callback = |deadline| {
+        ccm.register_callback(Callback::new(
+            cc_id,
+            None,
+            None,
+            Cycles::zero(),
+            None,
+            None,
+            WasmClosure::new(0, 1),
+            WasmClosure::new(2, 3),
+            None,
+            deadline,
+        ))
+    };
+    let callback_id1 = register_callback(CoarseTime::from_secs_since_unix_epoch(20));
+    let callback_id2 = register_callback(CoarseTime::from_secs_since_unix_epoch(10));
+    let callback_id3 = register_callback(NO_DEADLINE);
+    let callback_id4 = register_callback(CoarseTime::from_secs_since_unix_epoch(30));
+
+    let time = |secs| CoarseTime::from_secs_since_unix_epoch(secs).as_time();
+    assert!(!ccm.has_expired_callbacks(time(9)));
+    assert!(ccm.has_expired_callbacks(time(20)));
+    assert_eq!(ccm.take_expired_callbacks(time(20)), vec![callback_id2, callback_id1]);
+    assert!(!ccm.has_expired_callbacks(time(20)));
+    ccm.mark_callback_expired(callback_id1);
+    assert!(ccm.is_callback_expired(callback_id1));
+    assert!(!ccm.is_callback_expired(callback_id3));
+
+    // Only the expired callbacks are persisted, the unexpired ones are
+    // rebuilt from the callbacks.
+    let pb_ccm = pb::CallContextManager::from(&ccm);
+    let round_trip = CallContextManager::try_from(pb_ccm).unwrap();
+    assert_eq!(ccm, round_trip);
+
+    // Unregistering a callback forgets that it expired.
+    ccm.unregister_callback(callback_id1);
+    assert!(!ccm.is_callback_expired(callback_id1));
+    ccm.unregister_callback(callback_id4);
+    assert!(!ccm.has_expired_callbacks(time(u32::MAX)));
+}
+
+#[test]
+fn late_best_effort_responses_are_detected() {
+    let mut ccm = CallContextManager::default();
+    let cc_id = ccm.new_call_context(
+        CallOrigin::CanisterUpdate(canister_test_id(42), CallbackId::from(1), NO_DEADLINE),
+        Cycles::zero(),
+        Time::from_nanos_since_unix_epoch(0),
+    );
+    let deadline = CoarseTime::from_secs_since_unix_epoch(10);
+    let callback_id = ccm.register_callback(Callback::new(
+        cc_id,
+        None,
+        None,
+        Cycles::zero(),
+        None,
+        None,
+        WasmClosure::new(0, 1),
+        WasmClosure::new(2, 3),
+        None,
+        deadline,
+    ));
+    let response = |callback_id, deadline| Response {
+        originator: canister_test_id(1),
+        respondent: canister_test_id(2),
+        originator_reply_callback: callback_id,
+        refund: Cycles::zero(),
+        response_payload: Payload::Data(vec![]),
+        deadline,
+    };
+
+    assert!(!ccm.is_late_response(&response(callback_id, deadline)));
+    // Responses to unknown guaranteed response callbacks are not late.
+    assert!(!ccm.is_late_response(&response(CallbackId::from(13), NO_DEADLINE)));
+    assert!(ccm.is_late_response(&response(CallbackId::from(13), deadline)));
+
+    ccm.mark_callback_expired(callback_id);
+    assert!(ccm.is_late_response(&response(callback_id, deadline)));
+}
+
+#[test]
+fn stuck_guaranteed_response_callbacks_are_detected() {
+    let mut ccm = CallContextManager::default();
+    let new_call_context = |ccm: &mut CallContextManager, secs| {
+        ccm.new_call_context(
+            CallOrigin::CanisterUpdate(canister_test_id(42), CallbackId::from(1), NO_DEADLINE),
+            Cycles::zero(),
+            Time::from_nanos_since_unix_epoch(secs * 1_000_000_000),
+        )
+    };
+    let register_callback = |ccm: &mut CallContextManager, cc_id, deadline| {
+        ccm.register_callback(Callback::new(
+            cc_id,
+            None,
+            None,
+            Cycles::zero(),
+            None,
+            None,
+            WasmClosure::new(0, 1),
+            WasmClosure::new(2, 3),
+            None,
+            deadline,
+        ))
+    };
+    let old_cc_id = new_call_context(&mut ccm, 0);
+    let new_cc_id = new_call_context(&mut ccm, 100);
+    let stuck_callback_id = register_callback(&mut ccm, old_cc_id, NO_DEADLINE);
+    register_callback(&mut ccm, old_cc_id, CoarseTime::from_secs_since_unix_epoch(1_000));
+    register_callback(&mut ccm, new_cc_id, NO_DEADLINE);
+
+    let max_lifetime = Duration::from_secs(50);
+    let time = Time::from_nanos_since_unix_epoch(60 * 1_000_000_000);
+    assert_eq!(ccm.stuck_callbacks(time, max_lifetime), vec![stuck_callback_id]);
+
+    // The index of the guaranteed response callbacks is rebuilt on decoding.
+    let decoded = CallContextManager::try_from(pb::CallContextManager::from(&ccm)).unwrap();
+    assert_eq!(ccm, decoded);
+
+    let late_response = Response {
+        originator: canister_test_id(1),
+        respondent: canister_test_id(2),
+        originator_reply_callback: stuck_callback_id,
+        refund: Cycles::zero(),
+        response_payload: Payload::Data(vec![]),
+        deadline: NO_DEADLINE,
+    };
+    ccm.mark_callback_expired(stuck_callback_id);
+    assert!(ccm.stuck_callbacks(time, max_lifetime).is_empty());
+    assert!(ccm.is_late_response(&late_response));
+
+    // The callback stays expired after the reject was executed, until the
+    // late response is dropped.
+    ccm.unregister_callback(stuck_callback_id);
+    assert!(ccm.is_late_response(&late_response));
+    ccm.drop_late_response(&late_response);
+    assert!(!ccm.is_late_response(&late_response));
+}
+
+#[test]
+fn delete_all_call_contexts_returns_unresponded_call_contexts() {
+    let mut ccm = CallContextManager::default();
+    let origin = CallOrigin::CanisterUpdate(canister_test_id(42), CallbackId::from(1), NO_DEADLINE);
+    let time = Time::from_nanos_since_unix_epoch(0);
+    let responded_id = ccm.new_call_context(origin.clone(), Cycles::new(10), time);
+    ccm.call_context_mut(responded_id).unwrap().mark_responded();
+    let unresponded_id = ccm.new_call_context(origin.clone(), Cycles::new(20), time);
+
+    let unresponded = ccm.delete_all_call_contexts();
+    assert_eq!(unresponded.len(), 1);
+    assert_eq!(unresponded[0].call_origin(), &origin);
+    assert_eq!(unresponded[0].available_cycles(), Cycles::new(20));
+    for id in [responded_id, unresponded_id] {
+        let call_context = ccm.call_context(id).unwrap();
+        assert!(call_context.is_deleted());
+        assert!(call_context.has_responded());
+        assert_eq!(call_context.available_cycles(), Cycles::zero());
+    }
+    // The call contexts are rejected only once.
+    assert!(ccm.delete_all_call_contexts().is_empty());
+}
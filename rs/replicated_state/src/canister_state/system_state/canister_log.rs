@@ -0,0 +1,72 @@
+#[cfg(test)]
+mod tests;
+
+use ic_ic00_types::CanisterLogRecord;
+use std::collections::VecDeque;
+
+/// The maximum total size of the contents of the records in a canister log.
+pub const MAX_CANISTER_LOG_SIZE: usize = 4 * 1024;
+
+/// A bounded log of the debug prints and trap messages of a canister. Every
+/// execution that completes appends its records. If the contents of the
+/// records exceed `MAX_CANISTER_LOG_SIZE` bytes, then the oldest records are
+/// dropped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CanisterLog {
+    /// The index of the next record.
+    next_idx: u64,
+    /// The records, oldest first.
+    records: VecDeque<CanisterLogRecord>,
+    /// The total size of the contents of the records.
+    size: usize,
+}
+
+impl CanisterLog {
+    /// Creates a log with the given records, e.g., loaded from a checkpoint.
+    pub fn new(next_idx: u64, records: Vec<CanisterLogRecord>) -> Self {
+        let size = records.iter().map(|record| record.content.len()).sum();
+        Self {
+            next_idx,
+            records: records.into(),
+            size,
+        }
+    }
+
+    /// Returns the index of the next record.
+    pub fn next_idx(&self) -> u64 {
+        self.next_idx
+    }
+
+    /// Returns the records, oldest first.
+    pub fn records(&self) -> &VecDeque<CanisterLogRecord> {
+        &self.records
+    }
+
+    /// Appends a record with the given content, which is truncated to
+    /// `MAX_CANISTER_LOG_SIZE` bytes, and drops the oldest records that don't
+    /// fit into the log anymore.
+    pub fn add_record(&mut self, timestamp_nanos: u64, mut content: Vec<u8>) {
+        content.truncate(MAX_CANISTER_LOG_SIZE);
+        self.size += content.len();
+        self.records.push_back(CanisterLogRecord {
+            idx: self.next_idx,
+            timestamp_nanos,
+            content,
+        });
+        self.next_idx += 1;
+        while self.size > MAX_CANISTER_LOG_SIZE {
+            let record = self
+                .records
+                .pop_front()
+                .expect("the size of an empty canister log must be zero");
+            self.size -= record.content.len();
+        }
+    }
+
+    /// Drops all records. The indices of new records continue after the
+    /// dropped ones.
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.size = 0;
+    }
+}
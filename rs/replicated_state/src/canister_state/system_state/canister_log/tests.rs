@@ -0,0 +1,56 @@
+use super::*;
+
+fn contents(log: &CanisterLog) -> Vec<(u64, Vec<u8>)> {
+    log.records()
+        .iter()
+        .map(|record| (record.idx, record.content.clone()))
+        .collect()
+}
+
+#[test]
+fn canister_log_assigns_consecutive_indices() {
+    let mut log = CanisterLog::default();
+    log.add_record(10, b"first".to_vec());
+    log.add_record(20, b"second".to_vec());
+
+    assert_eq!(
+        contents(&log),
+        vec![(0, b"first".to_vec()), (1, b"second".to_vec())]
+    );
+    assert_eq!(log.records()[1].timestamp_nanos, 20);
+    assert_eq!(log.next_idx(), 2);
+}
+
+#[test]
+fn canister_log_drops_oldest_records() {
+    let mut log = CanisterLog::default();
+    let half = vec![0; MAX_CANISTER_LOG_SIZE / 2];
+    log.add_record(0, half.clone());
+    log.add_record(0, half.clone());
+    log.add_record(0, b"new".to_vec());
+
+    assert_eq!(contents(&log), vec![(1, half), (2, b"new".to_vec())]);
+}
+
+#[test]
+fn canister_log_truncates_large_records() {
+    let mut log = CanisterLog::default();
+    log.add_record(0, b"old".to_vec());
+    log.add_record(0, vec![1; MAX_CANISTER_LOG_SIZE + 1]);
+
+    assert_eq!(contents(&log), vec![(1, vec![1; MAX_CANISTER_LOG_SIZE])]);
+}
+
+#[test]
+fn canister_log_keeps_indices_after_clear() {
+    let mut log = CanisterLog::default();
+    log.add_record(0, b"first".to_vec());
+    log.clear();
+    log.add_record(0, b"second".to_vec());
+
+    assert_eq!(contents(&log), vec![(1, b"second".to_vec())]);
+    assert_eq!(
+        CanisterLog::new(log.next_idx(), log.records().iter().cloned().collect()),
+        log
+    );
+}
@@ -0,0 +1,174 @@
+#[cfg(test)]
+mod tests;
+
+use ic_crypto_sha::Sha256;
+use ic_protobuf::{proxy::ProxyDecodeError, state::canister_state_bits::v1 as pb};
+use ic_types::NumBytes;
+use std::{collections::BTreeMap, convert::TryFrom, fmt, sync::Arc};
+
+/// The maximum size of a chunk in bytes.
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The maximum number of chunks in the chunk store of a canister.
+pub const MAX_CHUNKS: usize = 100;
+
+/// The SHA-256 hash of a chunk. Chunks are identified by their hashes.
+pub type WasmChunkHash = [u8; 32];
+
+/// Returns the hash of the given chunk.
+pub fn wasm_chunk_hash(chunk: &[u8]) -> WasmChunkHash {
+    Sha256::hash(chunk)
+}
+
+/// Errors returned by the operations on a `WasmChunkStore`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WasmChunkStoreError {
+    ChunkTooLarge { size: usize, max_size: usize },
+    StoreFull { max_chunks: usize },
+    ChunkNotFound(WasmChunkHash),
+}
+
+impl fmt::Display for WasmChunkStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ChunkTooLarge { size, max_size } => write!(
+                f,
+                "Chunk of {} bytes exceeds the maximum chunk size of {} bytes",
+                size, max_size
+            ),
+            Self::StoreFull { max_chunks } => write!(
+                f,
+                "Wasm chunk store is full, it cannot hold more than {} chunks",
+                max_chunks
+            ),
+            Self::ChunkNotFound(hash) => {
+                write!(f, "Chunk with hash ")?;
+                for byte in hash.iter() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, " is not in the Wasm chunk store")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WasmChunkStoreError {}
+
+/// A content-addressed store of chunks of Wasm modules. It allows installing
+/// Wasm modules that don't fit into a single message: the chunks are uploaded
+/// with `upload_chunk` and then concatenated by `install_chunked_code`.
+///
+/// The contents of the chunks count towards the memory usage of the canister.
+/// Uploading a chunk that is already in the store doesn't change the store.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WasmChunkStore {
+    // The chunks are shared, so that cloning the canister state is cheap.
+    chunks: BTreeMap<WasmChunkHash, Arc<Vec<u8>>>,
+    // The total size of the chunks.
+    size: NumBytes,
+}
+
+impl WasmChunkStore {
+    /// Returns the memory used by the chunks.
+    pub fn memory_usage(&self) -> NumBytes {
+        self.size
+    }
+
+    /// Returns the number of chunks in the store.
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Returns the hashes of the chunks in increasing order.
+    pub fn hashes(&self) -> impl Iterator<Item = &WasmChunkHash> {
+        self.chunks.keys()
+    }
+
+    /// Returns the contents of the chunk with the given hash.
+    pub fn get(&self, hash: &WasmChunkHash) -> Option<&[u8]> {
+        self.chunks.get(hash).map(|chunk| chunk.as_slice())
+    }
+
+    /// Returns the memory that inserting the given chunk would add to the
+    /// store, which is zero if the chunk is already in the store, or an error
+    /// if the chunk cannot be inserted.
+    pub fn can_insert(&self, chunk: &[u8]) -> Result<NumBytes, WasmChunkStoreError> {
+        if chunk.len() > MAX_CHUNK_SIZE {
+            return Err(WasmChunkStoreError::ChunkTooLarge {
+                size: chunk.len(),
+                max_size: MAX_CHUNK_SIZE,
+            });
+        }
+        if self.chunks.contains_key(&wasm_chunk_hash(chunk)) {
+            return Ok(NumBytes::from(0));
+        }
+        if self.chunks.len() >= MAX_CHUNKS {
+            return Err(WasmChunkStoreError::StoreFull {
+                max_chunks: MAX_CHUNKS,
+            });
+        }
+        Ok(NumBytes::from(chunk.len() as u64))
+    }
+
+    /// Inserts the given chunk and returns its hash.
+    pub fn insert(&mut self, chunk: Vec<u8>) -> Result<WasmChunkHash, WasmChunkStoreError> {
+        let new_memory = self.can_insert(&chunk)?;
+        let hash = wasm_chunk_hash(&chunk);
+        if new_memory.get() > 0 {
+            self.chunks.insert(hash, Arc::new(chunk));
+            self.size += new_memory;
+        }
+        Ok(hash)
+    }
+
+    /// Removes all chunks.
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.size = NumBytes::from(0);
+    }
+
+    /// Returns the concatenation of the chunks with the given hashes in the
+    /// given order. A chunk may occur multiple times.
+    pub fn assemble<'a, I>(&self, hashes: I) -> Result<Vec<u8>, WasmChunkStoreError>
+    where
+        I: IntoIterator<Item = &'a WasmChunkHash>,
+    {
+        let mut result = vec![];
+        for hash in hashes {
+            let chunk = self
+                .get(hash)
+                .ok_or(WasmChunkStoreError::ChunkNotFound(*hash))?;
+            result.extend_from_slice(chunk);
+        }
+        Ok(result)
+    }
+}
+
+impl From<&WasmChunkStore> for pb::WasmChunkStore {
+    fn from(item: &WasmChunkStore) -> Self {
+        Self {
+            chunks: item.chunks.values().map(|chunk| chunk.to_vec()).collect(),
+        }
+    }
+}
+
+impl TryFrom<pb::WasmChunkStore> for WasmChunkStore {
+    type Error = ProxyDecodeError;
+
+    fn try_from(value: pb::WasmChunkStore) -> Result<Self, Self::Error> {
+        let mut store = WasmChunkStore::default();
+        for chunk in value.chunks {
+            store
+                .insert(chunk)
+                .map_err(|err| ProxyDecodeError::ValueOutOfRange {
+                    typ: "WasmChunkStore",
+                    err: err.to_string(),
+                })?;
+        }
+        Ok(store)
+    }
+}
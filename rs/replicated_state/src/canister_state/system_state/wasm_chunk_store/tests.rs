@@ -0,0 +1,93 @@
+use super::*;
+
+#[test]
+fn insert_returns_hash_and_accounts_memory() {
+    let mut store = WasmChunkStore::default();
+    let chunk = vec![1, 2, 3];
+    let hash = store.insert(chunk.clone()).unwrap();
+
+    assert_eq!(hash, wasm_chunk_hash(&chunk));
+    assert_eq!(store.get(&hash), Some(chunk.as_slice()));
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.memory_usage(), NumBytes::from(3));
+}
+
+#[test]
+fn inserting_same_chunk_twice_is_noop() {
+    let mut store = WasmChunkStore::default();
+    let first = store.insert(vec![7; 100]).unwrap();
+    assert_eq!(store.can_insert(&[7; 100]), Ok(NumBytes::from(0)));
+    let second = store.insert(vec![7; 100]).unwrap();
+
+    assert_eq!(first, second);
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.memory_usage(), NumBytes::from(100));
+}
+
+#[test]
+fn chunk_too_large_is_rejected() {
+    let mut store = WasmChunkStore::default();
+    assert_eq!(
+        store.insert(vec![0; MAX_CHUNK_SIZE + 1]),
+        Err(WasmChunkStoreError::ChunkTooLarge {
+            size: MAX_CHUNK_SIZE + 1,
+            max_size: MAX_CHUNK_SIZE,
+        })
+    );
+    assert!(store.is_empty());
+    store.insert(vec![0; MAX_CHUNK_SIZE]).unwrap();
+}
+
+#[test]
+fn full_store_rejects_new_chunks_only() {
+    let mut store = WasmChunkStore::default();
+    for i in 0..MAX_CHUNKS {
+        store.insert((i as u64).to_le_bytes().to_vec()).unwrap();
+    }
+    assert_eq!(
+        store.insert(vec![42; 10]),
+        Err(WasmChunkStoreError::StoreFull {
+            max_chunks: MAX_CHUNKS
+        })
+    );
+    // Chunks that are already in the store can still be uploaded.
+    store.insert(0_u64.to_le_bytes().to_vec()).unwrap();
+
+    store.clear();
+    assert!(store.is_empty());
+    assert_eq!(store.memory_usage(), NumBytes::from(0));
+}
+
+#[test]
+fn assemble_concatenates_chunks_in_order() {
+    let mut store = WasmChunkStore::default();
+    let a = store.insert(vec![1, 2]).unwrap();
+    let b = store.insert(vec![3]).unwrap();
+
+    assert_eq!(store.assemble(&[b, a, b]), Ok(vec![3, 1, 2, 3]));
+    assert_eq!(store.assemble(&[]), Ok(vec![]));
+
+    let missing = wasm_chunk_hash(&[4]);
+    assert_eq!(
+        store.assemble(&[a, missing]),
+        Err(WasmChunkStoreError::ChunkNotFound(missing))
+    );
+}
+
+#[test]
+fn proto_round_trip() {
+    let mut store = WasmChunkStore::default();
+    store.insert(vec![1, 2, 3]).unwrap();
+    store.insert(vec![4; 1000]).unwrap();
+
+    let proto = pb::WasmChunkStore::from(&store);
+    assert_eq!(WasmChunkStore::try_from(proto).unwrap(), store);
+}
+
+#[test]
+fn proto_with_oversized_chunk_is_rejected() {
+    let proto = pb::WasmChunkStore {
+        chunks: vec![vec![0; MAX_CHUNK_SIZE + 1]],
+    };
+    assert!(WasmChunkStore::try_from(proto).is_err());
+}
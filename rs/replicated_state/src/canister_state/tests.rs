@@ -12,6 +12,7 @@ use ic_types::messages::CallContextId;
 use ic_types::{
     messages::CallbackId,
     methods::{Callback, WasmClosure},
+    time::NO_DEADLINE,
     Time,
 };
 use ic_types::{messages::MAX_RESPONSE_COUNT_BYTES, CountBytes, Cycles};
@@ -64,7 +65,7 @@ fn canister_state_push_input_response_no_reservation() {
             .call_context_manager_mut()
             .unwrap()
             .new_call_context(
-                CallOrigin::CanisterUpdate(CANISTER_ID, CallbackId::from(1)),
+                CallOrigin::CanisterUpdate(CANISTER_ID, CallbackId::from(1), NO_DEADLINE),
                 Cycles::zero(),
                 Time::from_nanos_since_unix_epoch(0),
             );
@@ -82,6 +83,7 @@ fn canister_state_push_input_response_no_reservation() {
                 WasmClosure::new(0, 2),
                 WasmClosure::new(0, 2),
                 None,
+                NO_DEADLINE,
             ));
 
         let response: RequestOrResponse = ResponseBuilder::default()
@@ -125,7 +127,7 @@ fn canister_state_push_input_response_success() {
             .call_context_manager_mut()
             .unwrap()
             .new_call_context(
-                CallOrigin::CanisterUpdate(CANISTER_ID, CallbackId::from(1)),
+                CallOrigin::CanisterUpdate(CANISTER_ID, CallbackId::from(1), NO_DEADLINE),
                 Cycles::zero(),
                 Time::from_nanos_since_unix_epoch(0),
             );
@@ -143,6 +145,7 @@ fn canister_state_push_input_response_success() {
                 WasmClosure::new(0, 2),
                 WasmClosure::new(0, 2),
                 None,
+                NO_DEADLINE,
             ));
 
         canister_state
@@ -456,7 +459,7 @@ fn canister_state_push_input_response_memory_limit_test_impl(
             .call_context_manager_mut()
             .unwrap()
             .new_call_context(
-                CallOrigin::CanisterUpdate(CANISTER_ID, CallbackId::from(1)),
+                CallOrigin::CanisterUpdate(CANISTER_ID, CallbackId::from(1), NO_DEADLINE),
                 Cycles::zero(),
                 Time::from_nanos_since_unix_epoch(0),
             );
@@ -474,6 +477,7 @@ fn canister_state_push_input_response_memory_limit_test_impl(
                 WasmClosure::new(0, 2),
                 WasmClosure::new(0, 2),
                 None,
+                NO_DEADLINE,
             ));
 
         let response: RequestOrResponse = ResponseBuilder::default()
@@ -589,6 +593,7 @@ fn canister_state_callback_round_trip() {
         WasmClosure::new(0, 2),
         WasmClosure::new(0, 2),
         None,
+        NO_DEADLINE,
     );
 
     let pb_callback = pb::Callback::from(&callback);
@@ -597,3 +602,137 @@ fn canister_state_callback_round_trip() {
 
     assert_eq!(callback, round_trip);
 }
+
+#[test]
+fn canister_snapshot_is_not_affected_by_later_writes() {
+    use crate::{page_map::PAGE_SIZE, PageIndex, PageMap};
+
+    canister_state_test(|mut canister_state| {
+        let mut wasm_memory =
+            execution_state::Memory::new(PageMap::default(), NumWasmPages::new(1));
+        wasm_memory
+            .page_map
+            .update(&[(PageIndex::new(0), &[1u8; PAGE_SIZE])]);
+        canister_state.execution_state = Some(ExecutionState::new(
+            Default::default(),
+            execution_state::WasmBinary::new(CanisterModule::new(vec![1, 2, 3])),
+            ExportedFunctions::new(Default::default()),
+            wasm_memory,
+            Default::default(),
+            vec![Global::I64(14)],
+            WasmMetadata::default(),
+        ));
+        canister_state.system_state.certified_data = vec![4, 5, 6];
+        let chunk_hash = canister_state
+            .system_state
+            .wasm_chunk_store
+            .insert(vec![8; 100])
+            .unwrap();
+
+        let snapshot = canister_state.take_snapshot(mock_time());
+        assert_eq!(snapshot.canister_id(), CANISTER_ID);
+        assert_eq!(
+            snapshot.memory_usage(),
+            canister_state.raw_memory_usage() + NumBytes::new(3)
+        );
+
+        let execution_state = canister_state.execution_state.as_mut().unwrap();
+        execution_state
+            .wasm_memory
+            .page_map
+            .update(&[(PageIndex::new(0), &[2u8; PAGE_SIZE])]);
+        execution_state.exported_globals = vec![Global::I64(15)];
+        canister_state.system_state.certified_data = vec![7];
+        canister_state.system_state.wasm_chunk_store.clear();
+
+        assert_eq!(
+            snapshot.wasm_chunk_store().get(&chunk_hash),
+            Some(&[8; 100][..])
+        );
+        let snapshot_state = snapshot.execution_state().unwrap();
+        assert_eq!(
+            snapshot_state.wasm_memory.page_map.get_page(PageIndex::new(0)),
+            &[1u8; PAGE_SIZE]
+        );
+        assert_eq!(snapshot_state.exported_globals, vec![Global::I64(14)]);
+
+        canister_state.restore_snapshot(&snapshot).unwrap();
+        let execution_state = canister_state.execution_state.as_ref().unwrap();
+        assert_eq!(
+            execution_state.wasm_memory.page_map.get_page(PageIndex::new(0)),
+            &[1u8; PAGE_SIZE]
+        );
+        assert_eq!(execution_state.exported_globals, vec![Global::I64(14)]);
+        assert_eq!(canister_state.system_state.certified_data, vec![4, 5, 6]);
+        assert_eq!(
+            canister_state.system_state.wasm_chunk_store.get(&chunk_hash),
+            Some(&[8; 100][..])
+        );
+    })
+}
+
+#[test]
+fn memory_breakdown_adds_up_to_memory_usage() {
+    canister_state_test(|mut canister_state| {
+        assert_eq!(canister_state.memory_breakdown(), MemoryBreakdown::default());
+
+        canister_state.execution_state = Some(ExecutionState::new(
+            Default::default(),
+            execution_state::WasmBinary::new(CanisterModule::new(vec![1, 2, 3])),
+            ExportedFunctions::new(Default::default()),
+            execution_state::Memory::new(crate::PageMap::default(), NumWasmPages::new(2)),
+            execution_state::Memory::new(crate::PageMap::default(), NumWasmPages::new(1)),
+            vec![Global::I64(14), Global::I32(15)],
+            WasmMetadata::default(),
+        ));
+        canister_state
+            .push_input(
+                RequestBuilder::default()
+                    .receiver(CANISTER_ID)
+                    .build()
+                    .into(),
+                MAX_CANISTER_MEMORY_SIZE,
+                &mut SUBNET_AVAILABLE_MEMORY.clone(),
+                SubnetType::Application,
+                InputQueueType::RemoteSubnet,
+            )
+            .unwrap();
+
+        let breakdown = canister_state.memory_breakdown();
+        assert_eq!(breakdown.wasm_memory, NumBytes::new(2 * 64 * 1024));
+        assert_eq!(breakdown.stable_memory, NumBytes::new(64 * 1024));
+        assert_eq!(breakdown.globals, NumBytes::new(16));
+        assert_eq!(breakdown.wasm_binary, NumBytes::new(3));
+        assert!(breakdown.message_memory.get() > 0);
+        assert_eq!(
+            breakdown.execution_memory(),
+            canister_state.memory_usage(SubnetType::System)
+        );
+        assert_eq!(
+            breakdown.execution_memory() + breakdown.message_memory,
+            canister_state.memory_usage(SubnetType::Application)
+        );
+    })
+}
+
+#[test]
+fn wasm_chunk_store_counts_towards_memory_usage() {
+    canister_state_test(|mut canister_state| {
+        canister_state
+            .system_state
+            .wasm_chunk_store
+            .insert(vec![1; 1000])
+            .unwrap();
+
+        let breakdown = canister_state.memory_breakdown();
+        assert_eq!(breakdown.wasm_chunk_store, NumBytes::new(1000));
+        assert_eq!(
+            breakdown.execution_memory() + breakdown.wasm_chunk_store,
+            canister_state.memory_usage(SubnetType::System)
+        );
+        assert_eq!(
+            breakdown.execution_memory() + breakdown.wasm_chunk_store + breakdown.message_memory,
+            canister_state.memory_usage(SubnetType::Application)
+        );
+    })
+}
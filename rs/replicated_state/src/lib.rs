@@ -1,6 +1,7 @@
 mod bitcoin;
 pub mod bitcoin_state;
 pub mod canister_state;
+pub mod memory_reservation;
 pub mod metadata_state;
 pub mod page_map;
 pub mod replicated_state;
@@ -14,13 +15,21 @@ pub use bitcoin_state::{BitcoinState, BitcoinStateError};
 pub use canister_state::{
     execution_state::Memory,
     num_bytes_try_from,
+    snapshot::CanisterSnapshot,
     system_state::{
-        memory_required_to_push_request, CallContext, CallContextAction, CallContextManager,
-        CallOrigin, CanisterMetrics, CanisterStatus, ExecutionTask, SystemState,
+        memory_required_to_push_request, wasm_chunk_hash, CallContext, CallContextAction,
+        CallContextManager, CallOrigin, CanisterLog, CanisterMetrics, CanisterStatus, ExecutionTask,
+        ReservationError, SystemState, WasmChunkHash, WasmChunkStore, WasmChunkStoreError,
+        MAX_CANISTER_LOG_SIZE,
     },
-    CanisterQueues, CanisterState, EmbedderCache, ExecutionState, ExportedFunctions, Global,
-    NumWasmPages, SchedulerState,
+    CanisterQueues, CanisterState, EmbedderCache, ExecutedInstructions, ExecutionState,
+    ExportedFunctions, Global, MemoryBreakdown, NumWasmPages, QueryStats, QueuedMessageInfo,
+    QueuedMessageKind, SchedulerState,
 };
-pub use metadata_state::{NetworkTopology, NodeTopology, Stream, SubnetTopology, SystemMetadata};
-pub use page_map::{PageIndex, PageMap};
+pub use memory_reservation::MemoryReservation;
+pub use metadata_state::{
+    IngressHistoryEvictionStats, NetworkTopology, NodeTopology, Stream, SubnetTopology,
+    SystemMetadata,
+};
+pub use page_map::{PageIndex, PageMap, PageMapView, PageSize};
 pub use replicated_state::{InputQueueType, NextInputQueue, ReplicatedState, StateError};
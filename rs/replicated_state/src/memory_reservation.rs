@@ -0,0 +1,149 @@
+#[cfg(test)]
+mod tests;
+
+use crate::{num_bytes_try_from, NumWasmPages};
+use ic_interfaces::execution_environment::{MemoryReservationError, SubnetAvailableMemory};
+use ic_types::NumBytes;
+
+/// Tracks the memory that an execution reserves on behalf of a canister, e.g.
+/// when the canister grows its Wasm or stable memory or pushes an output
+/// message.
+///
+/// A reservation succeeds only if the canister stays within its memory limit,
+/// which is its memory allocation if it has one, and the subnet has enough
+/// available memory. The reserved memory is deducted from the subnet
+/// available memory that the reservation was created with, so that all
+/// reservations of an execution are checked against the same capacity.
+#[derive(Clone, Debug)]
+pub struct MemoryReservation {
+    /// Upper limit on how much memory the canister could use.
+    limit: NumBytes,
+
+    /// The current amount of memory that the canister is using, including the
+    /// memory reserved so far.
+    current_usage: NumBytes,
+
+    /// The memory that the subnet has available. The reserved memory is
+    /// already deducted from it.
+    subnet_available_memory: SubnetAvailableMemory,
+
+    /// The total memory reserved so far. It is always greater than or equal
+    /// to `reserved_message_memory`.
+    reserved_memory: NumBytes,
+
+    /// The message memory reserved so far.
+    reserved_message_memory: NumBytes,
+}
+
+impl MemoryReservation {
+    pub fn new(
+        limit: NumBytes,
+        current_usage: NumBytes,
+        subnet_available_memory: SubnetAvailableMemory,
+    ) -> Self {
+        Self {
+            limit,
+            current_usage,
+            subnet_available_memory,
+            reserved_memory: NumBytes::from(0),
+            reserved_message_memory: NumBytes::from(0),
+        }
+    }
+
+    /// Returns the memory limit of the canister.
+    pub fn limit(&self) -> NumBytes {
+        self.limit
+    }
+
+    /// Returns the memory usage of the canister including the reserved memory.
+    pub fn current_usage(&self) -> NumBytes {
+        self.current_usage
+    }
+
+    /// Returns the memory that the subnet has available after the reservations.
+    pub fn subnet_available_memory(&self) -> SubnetAvailableMemory {
+        self.subnet_available_memory
+    }
+
+    /// Returns the total memory reserved so far.
+    pub fn reserved_memory(&self) -> NumBytes {
+        self.reserved_memory
+    }
+
+    /// Returns the message memory reserved so far.
+    pub fn reserved_message_memory(&self) -> NumBytes {
+        self.reserved_message_memory
+    }
+
+    /// Tries to reserve `total_bytes` of memory of which `message_bytes` are
+    /// used for messages, so `total_bytes` must not be smaller than
+    /// `message_bytes`.
+    ///
+    /// Returns an error and leaves `self` unchanged if the canister memory
+    /// limit, the subnet memory capacity, or the subnet message memory
+    /// capacity would be exceeded.
+    pub fn reserve(
+        &mut self,
+        total_bytes: NumBytes,
+        message_bytes: NumBytes,
+    ) -> Result<(), MemoryReservationError> {
+        debug_assert!(total_bytes >= message_bytes);
+        let new_usage = self
+            .current_usage
+            .get()
+            .checked_add(total_bytes.get())
+            .filter(|new_usage| *new_usage <= self.limit.get())
+            .ok_or(MemoryReservationError::CanisterMemoryLimitExceeded {
+                requested: total_bytes,
+                usage: self.current_usage,
+                limit: self.limit,
+            })?;
+        self.subnet_available_memory
+            .try_decrement(total_bytes, message_bytes)?;
+        self.current_usage = NumBytes::from(new_usage);
+        self.reserved_memory += total_bytes;
+        self.reserved_message_memory += message_bytes;
+        Ok(())
+    }
+
+    /// Tries to reserve the memory of the given number of Wasm pages, e.g.
+    /// before growing the Wasm or stable memory. See `reserve()`.
+    pub fn reserve_wasm_pages(
+        &mut self,
+        pages: NumWasmPages,
+    ) -> Result<(), MemoryReservationError> {
+        let bytes = num_bytes_try_from(pages).map_err(|_| {
+            MemoryReservationError::CanisterMemoryLimitExceeded {
+                requested: NumBytes::from(u64::MAX),
+                usage: self.current_usage,
+                limit: self.limit,
+            }
+        })?;
+        self.reserve(bytes, NumBytes::from(0))
+    }
+
+    /// Releases memory that was reserved with `reserve()`, e.g. if growing
+    /// the memory failed after the reservation.
+    pub fn release(&mut self, total_bytes: NumBytes, message_bytes: NumBytes) {
+        debug_assert!(total_bytes >= message_bytes);
+        debug_assert!(self.reserved_memory >= total_bytes);
+        debug_assert!(self.reserved_message_memory >= message_bytes);
+        self.subnet_available_memory
+            .increment(total_bytes, message_bytes);
+        self.current_usage -= total_bytes;
+        self.reserved_memory -= total_bytes;
+        self.reserved_message_memory -= message_bytes;
+    }
+
+    /// Releases memory that was reserved with `reserve_wasm_pages()`.
+    pub fn release_wasm_pages(&mut self, pages: NumWasmPages) {
+        // The conversion succeeded when the pages were reserved.
+        let bytes = num_bytes_try_from(pages).expect("could not convert wasm pages to bytes");
+        self.release(bytes, NumBytes::from(0))
+    }
+
+    /// Releases all reserved memory, e.g. if the execution failed.
+    pub fn release_all(&mut self) {
+        self.release(self.reserved_memory, self.reserved_message_memory)
+    }
+}
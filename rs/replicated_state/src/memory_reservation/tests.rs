@@ -0,0 +1,97 @@
+use super::*;
+
+const MB: u64 = 1024 * 1024;
+
+fn reservation(limit: u64, usage: u64, total: i64, messages: i64) -> MemoryReservation {
+    MemoryReservation::new(
+        NumBytes::from(limit),
+        NumBytes::from(usage),
+        SubnetAvailableMemory::new(total, messages),
+    )
+}
+
+#[test]
+fn reserve_and_release_update_usage_and_subnet_memory() {
+    let mut reservation = reservation(10 * MB, MB, 20 * MB as i64, 5 * MB as i64);
+    reservation
+        .reserve(NumBytes::from(3 * MB), NumBytes::from(MB))
+        .unwrap();
+    assert_eq!(reservation.current_usage(), NumBytes::from(4 * MB));
+    assert_eq!(reservation.reserved_memory(), NumBytes::from(3 * MB));
+    assert_eq!(reservation.reserved_message_memory(), NumBytes::from(MB));
+    let available = reservation.subnet_available_memory();
+    assert_eq!(available.get_total_memory(), 17 * MB as i64);
+    assert_eq!(available.get_message_memory(), 4 * MB as i64);
+
+    reservation.release_all();
+    assert_eq!(reservation.current_usage(), NumBytes::from(MB));
+    assert_eq!(reservation.reserved_memory(), NumBytes::from(0));
+    let available = reservation.subnet_available_memory();
+    assert_eq!(available.get_total_memory(), 20 * MB as i64);
+    assert_eq!(available.get_message_memory(), 5 * MB as i64);
+}
+
+#[test]
+fn reserve_fails_above_canister_limit() {
+    let mut reservation = reservation(2 * MB, MB, 20 * MB as i64, 20 * MB as i64);
+    assert_eq!(
+        reservation.reserve(NumBytes::from(2 * MB), NumBytes::from(0)),
+        Err(MemoryReservationError::CanisterMemoryLimitExceeded {
+            requested: NumBytes::from(2 * MB),
+            usage: NumBytes::from(MB),
+            limit: NumBytes::from(2 * MB),
+        })
+    );
+    assert_eq!(reservation.current_usage(), NumBytes::from(MB));
+    assert_eq!(
+        reservation.subnet_available_memory().get_total_memory(),
+        20 * MB as i64
+    );
+}
+
+#[test]
+fn reserve_fails_above_subnet_capacity() {
+    let mut reservation = reservation(10 * MB, 0, MB as i64, MB as i64);
+    assert_eq!(
+        reservation.reserve(NumBytes::from(2 * MB), NumBytes::from(0)),
+        Err(MemoryReservationError::SubnetMemoryCapacityExceeded {
+            requested: NumBytes::from(2 * MB),
+            available: MB as i64,
+        })
+    );
+    assert_eq!(
+        reservation.reserve(NumBytes::from(MB), NumBytes::from(MB)),
+        Ok(())
+    );
+    assert_eq!(
+        reservation.reserve(NumBytes::from(0), NumBytes::from(0)),
+        Ok(())
+    );
+}
+
+#[test]
+fn reserve_fails_above_subnet_message_capacity() {
+    let mut reservation = reservation(10 * MB, 0, 10 * MB as i64, MB as i64);
+    assert_eq!(
+        reservation.reserve(NumBytes::from(2 * MB), NumBytes::from(2 * MB)),
+        Err(MemoryReservationError::SubnetMessageMemoryCapacityExceeded {
+            requested: NumBytes::from(2 * MB),
+            available: MB as i64,
+        })
+    );
+    assert_eq!(reservation.reserved_memory(), NumBytes::from(0));
+}
+
+#[test]
+fn reserve_wasm_pages_reserves_64_kib_per_page() {
+    let mut reservation = reservation(10 * MB, 0, 10 * MB as i64, 0);
+    reservation
+        .reserve_wasm_pages(NumWasmPages::from(16))
+        .unwrap();
+    assert_eq!(reservation.reserved_memory(), NumBytes::from(MB));
+    reservation.release_wasm_pages(NumWasmPages::from(16));
+    assert_eq!(reservation.reserved_memory(), NumBytes::from(0));
+    assert!(reservation
+        .reserve_wasm_pages(NumWasmPages::from(usize::MAX))
+        .is_err());
+}
@@ -1212,6 +1212,17 @@ impl<'a> StreamHandle<'a> {
     }
 }
 
+/// Statistics about the `Completed` and `Failed` statuses that were forgotten,
+/// i.e. transitioned to `Done`, to keep the ingress history within its memory
+/// capacity.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IngressHistoryEvictionStats {
+    /// The number of statuses that were transitioned to `Done`.
+    pub forgotten_statuses: usize,
+    /// The decrease of the memory usage of the ingress history.
+    pub freed_bytes: NumBytes,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 /// State associated with the history of statuses of ingress messages as they
 /// traversed through the system.
@@ -1312,13 +1323,18 @@ impl IngressHistoryState {
     /// already present this entry will be overwritten. If `status` is a terminal
     /// status (`completed`, `failed`, or `done`) the entry will also be enrolled
     /// to be pruned at `time + MAX_INGRESS_TTL`.
+    ///
+    /// If the memory usage exceeds `ingress_memory_capacity` afterwards, the
+    /// oldest `Completed` and `Failed` statuses are transitioned to `Done`
+    /// until it doesn't. Returns the statistics of these transitions, which
+    /// only depend on the ingress history and the arguments.
     pub fn insert(
         &mut self,
         message_id: MessageId,
         status: IngressStatus,
         time: Time,
         ingress_memory_capacity: NumBytes,
-    ) {
+    ) -> IngressHistoryEvictionStats {
         // Store the associated expiry time for the given message id only for a
         // "terminal" ingress status. This way we are not risking deleting any status
         // for a message that is still not in a terminal status.
@@ -1347,14 +1363,17 @@ impl IngressHistoryState {
             self.memory_usage -= old.payload_bytes();
         }
 
-        if self.memory_usage > ingress_memory_capacity.get() as usize {
-            self.forget_terminal_statuses(ingress_memory_capacity);
-        }
+        let eviction_stats = if self.memory_usage > ingress_memory_capacity.get() as usize {
+            self.forget_terminal_statuses(ingress_memory_capacity)
+        } else {
+            IngressHistoryEvictionStats::default()
+        };
 
         debug_assert_eq!(
             Self::compute_memory_usage(&self.statuses),
             self.memory_usage
         );
+        eviction_stats
     }
 
     /// Returns an iterator over response statuses, sorted lexicographically by
@@ -1411,15 +1430,18 @@ impl IngressHistoryState {
     /// the referenced `Completed` and/or `Failed` statuses to `Done` (i.e.,
     /// forgets the replies). It will stop at the pruning time where the memory
     /// usage is below `target_size` for the first time. To handle repeated calls
-    /// efficiently it remembers the pruning time it stopped at.
+    /// efficiently it remembers the pruning time it stopped at. Returns the
+    /// statistics of the forgotten statuses.
     ///
     /// Note that this function must remain private and should only be
     /// called from within `insert` to ensure that `next_terminal_time`
     /// is consistently updated and we don't miss any completed statuses.
-    fn forget_terminal_statuses(&mut self, target_size: NumBytes) {
+    fn forget_terminal_statuses(&mut self, target_size: NumBytes) -> IngressHistoryEvictionStats {
+        let mut stats = IngressHistoryEvictionStats::default();
+
         // Before certification version 8 no done statuses are produced
         if CURRENT_CERTIFICATION_VERSION < CertificationVersion::V8 {
-            return;
+            return stats;
         }
 
         // In debug builds we store the length of the statuses map here so that
@@ -1454,13 +1476,18 @@ impl IngressHistoryState {
                             time,
                             state: IngressState::Done,
                         });
-                        self.memory_usage += done_status.payload_bytes();
+                        let done_size = done_status.payload_bytes();
+                        self.memory_usage += done_size;
 
                         // We can safely unwrap here because we know there must be an
                         // ingress status with the given `id` in `statuses` in this
                         // branch.
                         let old_status = statuses.insert(id.clone(), done_status).unwrap();
                         self.memory_usage -= old_status.payload_bytes();
+
+                        stats.forgotten_statuses += 1;
+                        stats.freed_bytes +=
+                            NumBytes::from((old_status.payload_bytes() - done_size) as u64);
                     }
                     _ => continue,
                 }
@@ -1473,6 +1500,7 @@ impl IngressHistoryState {
             Self::compute_memory_usage(&self.statuses),
             self.memory_usage
         );
+        stats
     }
 
     /// Returns the memory usage of the statuses in the ingress history. See the
@@ -866,6 +866,41 @@ fn ingress_history_respects_limits() {
     run_test(10, 0);
 }
 
+#[test]
+fn ingress_history_insert_reports_eviction_stats() {
+    let mut ingress_history = IngressHistoryState::default();
+
+    // Inserting within the memory capacity doesn't forget any status.
+    for i in 1..=4 {
+        let stats = ingress_history.insert(
+            message_test_id(i),
+            test_status_terminal(i),
+            Time::from_nanos_since_unix_epoch(i),
+            NumBytes::from(u64::MAX),
+        );
+        assert_eq!(stats, IngressHistoryEvictionStats::default());
+    }
+
+    // Inserting with a memory capacity of zero forgets all terminal statuses.
+    let memory_usage_before =
+        ingress_history.memory_usage().get() + test_status_terminal(5).payload_bytes() as u64;
+    let stats = ingress_history.insert(
+        message_test_id(5),
+        test_status_terminal(5),
+        Time::from_nanos_since_unix_epoch(5),
+        NumBytes::from(0),
+    );
+    if CURRENT_CERTIFICATION_VERSION >= CertificationVersion::V8 {
+        assert_eq!(stats.forgotten_statuses, 5);
+        assert_eq!(
+            memory_usage_before - stats.freed_bytes.get(),
+            ingress_history.memory_usage().get()
+        );
+    } else {
+        assert_eq!(stats, IngressHistoryEvictionStats::default());
+    }
+}
+
 #[test]
 fn ingress_history_insert_before_next_complete_time_resets_it() {
     if CURRENT_CERTIFICATION_VERSION < CertificationVersion::V8 {
@@ -1,16 +1,23 @@
 mod checkpoint;
 pub mod int_map;
+mod overlay;
 mod page_allocator;
+mod page_size;
+mod view;
 
 use checkpoint::Checkpoint;
 pub use checkpoint::{CheckpointSerialization, MappingSerialization};
 use ic_sys::PageBytes;
 pub use ic_sys::{PageIndex, PAGE_SIZE};
 use ic_utils::{deterministic_operations::deterministic_copy_from_slice, fs::write_all_vectored};
+pub use overlay::OverlayFile;
 pub use page_allocator::{
-    allocated_pages_count, PageAllocator, PageAllocatorSerialization, PageDeltaSerialization,
-    PageSerialization,
+    allocated_pages_count, configure_page_allocators, page_allocator_stats, BackingFileBackend,
+    IoOperation, PageAllocator, PageAllocatorIoError, PageAllocatorSerialization,
+    PageAllocatorStats, PageDeltaSerialization, PageSerialization,
 };
+pub use page_size::PageSize;
+pub use view::PageMapView;
 
 // NOTE: We use a persistent map to make snapshotting of a PageMap a cheap
 // operation. This allows us to simplify canister state management: we can
@@ -43,7 +50,9 @@ impl<'a> WriteBuffer<'a> {
     fn apply_to_file(&mut self, file: &mut File, path: &Path) -> Result<(), PersistenceError> {
         use std::io::{Seek, SeekFrom};
 
-        let offset = self.start_index.get() * PAGE_SIZE as u64;
+        let offset = PageSize::HOST
+            .page_start(self.start_index.get())
+            .expect("Page index out of range");
         file.seek(SeekFrom::Start(offset as u64)).map_err(|err| {
             PersistenceError::FileSystemError {
                 path: path.display().to_string(),
@@ -68,6 +77,67 @@ impl<'a> WriteBuffer<'a> {
     }
 }
 
+/// Makes the given pages of the file read as zeroes. Punches a hole into the
+/// file, so that the file system releases the blocks backing the pages, and
+/// extends the file if it is shorter than the range. Falls back to writing
+/// zeroes if the file system doesn't support holes.
+fn write_zero_pages(
+    file: &mut File,
+    pages: Range<u64>,
+    path: &Path,
+) -> Result<(), PersistenceError> {
+    let fs_error = |context: &str, err: std::io::Error| PersistenceError::FileSystemError {
+        path: path.display().to_string(),
+        context: context.to_string(),
+        internal_error: err.to_string(),
+    };
+    let start = pages.start * PAGE_SIZE as u64;
+    let end = pages.end * PAGE_SIZE as u64;
+    let len = file
+        .metadata()
+        .map_err(|err| fs_error("Failed to get the file length", err))?
+        .len();
+    if len < end {
+        // Extending the file leaves a hole after its old end.
+        file.set_len(end)
+            .map_err(|err| fs_error("Failed to extend the file", err))?;
+    }
+    if start < len {
+        match punch_hole(file, start, len.min(end) - start) {
+            Ok(()) => (),
+            Err(nix::errno::Errno::EOPNOTSUPP) => {
+                let zeroes = [0; PAGE_SIZE];
+                let end_index = len.min(end) / PAGE_SIZE as u64;
+                WriteBuffer {
+                    content: vec![&zeroes[..]; (end_index - pages.start) as usize],
+                    start_index: PageIndex::new(pages.start),
+                }
+                .apply_to_file(file, path)?;
+            }
+            Err(err) => return Err(fs_error("Failed to punch a hole", err.into())),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn punch_hole(file: &File, offset: u64, len: u64) -> nix::Result<()> {
+    use nix::fcntl::{fallocate, FallocateFlags};
+    use std::os::unix::io::AsRawFd;
+
+    fallocate(
+        file.as_raw_fd(),
+        FallocateFlags::FALLOC_FL_PUNCH_HOLE | FallocateFlags::FALLOC_FL_KEEP_SIZE,
+        offset as off_t,
+        len as off_t,
+    )
+}
+
+#[cfg(not(target_os = "linux"))]
+fn punch_hole(_file: &File, _offset: u64, _len: u64) -> nix::Result<()> {
+    Err(nix::errno::Errno::EOPNOTSUPP)
+}
+
 /// `PageDelta` represents a changeset of the module heap.
 #[derive(Clone, Default, Debug)]
 struct PageDelta(IntMap<Page>);
@@ -199,6 +269,8 @@ pub enum PersistenceError {
     },
     /// (Slice) size is not equal to page size.
     BadPageSize { expected: usize, actual: usize },
+    /// Overlay file is malformed or doesn't match its checksums.
+    InvalidOverlayFile { path: String, reason: String },
 }
 
 impl PersistenceError {
@@ -244,6 +316,9 @@ impl std::fmt::Display for PersistenceError {
                 "Bad slice size: expected {}, actual {}",
                 expected, actual
             ),
+            PersistenceError::InvalidOverlayFile { path, reason } => {
+                write!(f, "Invalid overlay file {}: {}", path, reason)
+            }
         }
     }
 }
@@ -398,6 +473,57 @@ impl PageMap {
         pages.iter().map(|(index, _)| *index).collect()
     }
 
+    /// Zeroes the `len` bytes starting at `offset`, so that a canister can
+    /// give back memory that it no longer uses. Returns the indices of the
+    /// modified pages, like `update()`.
+    ///
+    /// All the whole pages in the range share a single zeroed page, so the
+    /// pages that they replace are released once no other version of this
+    /// page map refers to them. Pages that are already zero are skipped, so
+    /// discarding memory that was never written doesn't create a delta. When
+    /// the delta is persisted, the zeroed pages become holes in the file, so
+    /// they don't take up disk space in the checkpoint either.
+    pub fn discard_range(&mut self, offset: u64, len: u64) -> Vec<PageIndex> {
+        let end = offset
+            .saturating_add(len)
+            .min((self.num_host_pages() * PAGE_SIZE) as u64);
+        let mut whole_pages = vec![];
+        let mut partial_pages: Vec<(PageIndex, PageBytes)> = vec![];
+        let mut offset = offset;
+        while offset < end {
+            let page_index = PageIndex::new(PageSize::HOST.page_index(offset));
+            let offset_into_page = PageSize::HOST.offset_in_page(offset) as usize;
+            let page_len = ((end - offset) as usize).min(PAGE_SIZE - offset_into_page);
+            let range = offset_into_page..offset_into_page + page_len;
+            let contents = self.get_page(page_index);
+            if contents[range.clone()].iter().any(|byte| *byte != 0) {
+                if page_len == PAGE_SIZE {
+                    whole_pages.push(page_index);
+                } else {
+                    let mut contents = *contents;
+                    contents[range].fill(0);
+                    partial_pages.push((page_index, contents));
+                }
+            }
+            offset += page_len as u64;
+        }
+
+        let mut page_delta = self.page_allocator.allocate(
+            &partial_pages
+                .iter()
+                .map(|(index, contents)| (*index, contents))
+                .collect::<Vec<_>>(),
+        );
+        if let Some(first) = whole_pages.first() {
+            let zeroed_page = self.page_allocator.allocate(&[(*first, &[0; PAGE_SIZE])]);
+            let (_, zeroed_page) = &zeroed_page[0];
+            page_delta.extend(whole_pages.iter().map(|index| (*index, zeroed_page.clone())));
+        }
+        let indices = page_delta.iter().map(|(index, _)| *index).collect();
+        self.apply(page_delta);
+        indices
+    }
+
     /// Persists the heap delta contained in this page map to the specified
     /// destination.
     pub fn persist_delta(&self, dst: &Path) -> Result<(), PersistenceError> {
@@ -410,6 +536,22 @@ impl PageMap {
         self.persist_to_file(&self.round_delta, dst)
     }
 
+    /// Writes the heap delta contained in this page map into a new overlay
+    /// file at the specified destination. The pages are written directly from
+    /// the page allocator without intermediate copies.
+    pub fn persist_delta_to_overlay(&self, dst: &Path) -> Result<(), PersistenceError> {
+        overlay::write_overlay_file(
+            self.page_delta.iter().map(|(index, page)| (index, page.contents())),
+            dst,
+        )
+    }
+
+    /// Updates this page map with the pages of the given overlay file.
+    pub fn apply_overlay(&mut self, overlay: &OverlayFile) {
+        let pages: Vec<_> = overlay.iter().collect();
+        self.update(&pages);
+    }
+
     /// Returns the iterator over host pages managed by this `PageMap`.
     pub fn host_pages_iter(&self) -> impl Iterator<Item = (PageIndex, &PageBytes)> + '_ {
         (0..self.num_host_pages()).map(move |i| {
@@ -426,6 +568,67 @@ impl PageMap {
         }
     }
 
+    /// Copies the bytes starting at the given offset into `dst`.
+    ///
+    /// A run of contiguous pages that are not in the page delta is copied
+    /// from the checkpoint with a single copy instead of page by page.
+    pub fn read_range(&self, mut offset: usize, mut dst: &mut [u8]) {
+        while !dst.is_empty() {
+            let page_index = PageIndex::new(PageSize::HOST.page_index(offset as u64));
+            let len = match self.page_delta.get_page(page_index) {
+                Some(page) => {
+                    let offset_into_page = PageSize::HOST.offset_in_page(offset as u64) as usize;
+                    let len = dst.len().min(PAGE_SIZE - offset_into_page);
+                    dst[..len].copy_from_slice(&page[offset_into_page..offset_into_page + len]);
+                    len
+                }
+                None => {
+                    // All pages before the next page in the delta come from
+                    // the checkpoint.
+                    let (_, next_delta_page) = self.page_delta.bounds(page_index);
+                    let len = match next_delta_page {
+                        Some(end) => dst.len().min(end.get() as usize * PAGE_SIZE - offset),
+                        None => dst.len(),
+                    };
+                    self.checkpoint.read(offset, &mut dst[..len]);
+                    len
+                }
+            };
+            offset += len;
+            dst = &mut dst[len..];
+        }
+    }
+
+    /// Hints that the pages in the given range are about to be accessed, e.g.
+    /// because the canister scans its memory sequentially. The backing memory
+    /// of the pages is read ahead asynchronously, so that the upcoming
+    /// accesses don't stall on page faults. The contents of the pages are not
+    /// affected.
+    pub fn prefetch_pages(&self, page_range: Range<PageIndex>) {
+        let mut delta_pages = vec![];
+        let mut page_index = page_range.start;
+        while page_index < page_range.end {
+            match self.page_delta.get_page_ref(page_index) {
+                Some(page) => {
+                    delta_pages.push(page);
+                    page_index = PageIndex::new(page_index.get() + 1);
+                }
+                None => {
+                    // All pages before the next page in the delta come from
+                    // the checkpoint.
+                    let (_, next_delta_page) = self.page_delta.bounds(page_index);
+                    let end = match next_delta_page {
+                        Some(end) => end.min(page_range.end),
+                        None => page_range.end,
+                    };
+                    self.checkpoint.prefetch(page_index..end);
+                    page_index = end;
+                }
+            }
+        }
+        page_allocator::prefetch_pages(delta_pages);
+    }
+
     /// Returns the largest contiguous range of pages that contains the given
     /// page such that all pages share the same backing store.
     pub fn get_memory_region(&self, page_index: PageIndex) -> MemoryRegion {
@@ -464,6 +667,32 @@ impl PageMap {
             .get_memory_region(start, Range { start, end })
     }
 
+    /// Returns a read-only memory-mapped view of the current contents of this
+    /// page map, see `PageMapView`. The pages of the checkpoint are mapped
+    /// from the checkpoint file and only the pages of the page delta are
+    /// copied, so the view is cheap to create for large memories with small
+    /// deltas. The view can be shared by concurrent readers, e.g. queries.
+    pub fn read_only_view(&self) -> Result<PageMapView, PersistenceError> {
+        PageMapView::new(self)
+    }
+
+    /// Checks the pages of the page delta against their validation
+    /// information and returns the indices of the pages that fail the check.
+    /// The failing pages are quarantined, so that accessing them panics
+    /// instead of returning corrupted contents.
+    ///
+    /// The pages are shared with sandbox processes through the backing files
+    /// of the page allocator. This is meant to be called after a sandbox
+    /// process dies unexpectedly, see
+    /// `PageAllocatorConfig::revalidate_pages_after_sandbox_crash`.
+    pub fn revalidate_pages(&self) -> Vec<PageIndex> {
+        self.page_delta
+            .iter()
+            .filter(|(_, page)| !page.revalidate())
+            .map(|(page_index, _)| page_index)
+            .collect()
+    }
+
     /// Removes the page delta from this page map.
     pub fn strip_all_deltas(&mut self) {
         // Ensure that all pages are dropped before we drop the page allocator.
@@ -474,6 +703,9 @@ impl PageMap {
             std::mem::take(&mut self.page_delta);
             std::mem::take(&mut self.round_delta);
         }
+        // The page allocator may be shared with other page maps that keep it
+        // alive, so free the memory of the dropped pages right away.
+        self.page_allocator.reclaim_dropped_pages();
         std::mem::take(&mut self.page_allocator);
     }
 
@@ -488,6 +720,66 @@ impl PageMap {
         self.page_delta.iter().map(|(index, _)| index).collect()
     }
 
+    /// Returns the indices of the pages whose contents differ between this
+    /// page map and `other`, in ascending order.
+    ///
+    /// If both page maps are backed by the same checkpoint, e.g. because one
+    /// is a later version of the other, only the pages in their page deltas
+    /// are compared. Otherwise all the pages in the modified prefixes are
+    /// compared.
+    pub fn changed_pages_since(&self, other: &PageMap) -> Vec<PageIndex> {
+        if !self.checkpoint.same_mapping(&other.checkpoint) {
+            let num_pages = self.num_host_pages().max(other.num_host_pages());
+            return (0..num_pages as u64)
+                .map(PageIndex::new)
+                .filter(|index| self.get_page(*index) != other.get_page(*index))
+                .collect();
+        }
+
+        let mut changed: Vec<PageIndex> = self
+            .page_delta
+            .iter()
+            .filter(|(index, page)| match other.page_delta.get_page_ref(*index) {
+                Some(other_page) => {
+                    !page.ptr_eq(other_page) && page.contents() != other_page.contents()
+                }
+                None => page.contents() != self.checkpoint.get_page(*index),
+            })
+            .map(|(index, _)| index)
+            .collect();
+        changed.extend(
+            other
+                .page_delta
+                .iter()
+                .filter(|(index, page)| {
+                    self.page_delta.get_page_ref(*index).is_none()
+                        && page.contents() != other.checkpoint.get_page(*index)
+                })
+                .map(|(index, _)| index),
+        );
+        changed.sort_unstable();
+        changed
+    }
+
+    /// Returns the pages modified after the checkpoint at the given height in
+    /// ascending order of their indices, or `None` if the page map is backed
+    /// by a newer checkpoint, which doesn't record when its pages changed.
+    ///
+    /// The page map tracks modifications only since its own checkpoint, so if
+    /// that checkpoint is older than `height`, the result also includes pages
+    /// modified between the two heights. This is sufficient for computing an
+    /// incremental manifest, which only needs to rehash the chunks of the
+    /// returned pages.
+    pub fn delta_since(
+        &self,
+        height: Height,
+    ) -> Option<impl Iterator<Item = (PageIndex, &PageBytes)> + '_> {
+        if self.base_height.map_or(false, |base_height| base_height > height) {
+            return None;
+        }
+        Some(self.page_delta.iter().map(|(index, page)| (index, page.contents())))
+    }
+
     /// Whether there are any page deltas
     pub fn page_delta_is_empty(&self) -> bool {
         self.page_delta.is_empty()
@@ -520,6 +812,14 @@ impl PageMap {
         )
     }
 
+    /// Returns the number of pages of the given size that cover all host
+    /// pages of the page map, e.g. the number of Wasm pages that a memory
+    /// backed by the page map needs at least.
+    pub fn num_pages(&self, page_size: PageSize) -> u64 {
+        let num_bytes = self.num_host_pages() as u64 * PAGE_SIZE as u64;
+        page_size.pages_for(num_bytes)
+    }
+
     /// Switches the checkpoint file of the current page map to the one provided
     /// by the given page map. Page deltas of both page maps must be empty.
     pub fn switch_to_checkpoint(&mut self, checkpointed_page_map: &PageMap) {
@@ -561,6 +861,10 @@ impl PageMap {
 
     /// Applies the given delta to the specified file.
     /// Precondition: `file` is seekable and writeable.
+    ///
+    /// Zero pages in the delta, e.g. the pages released by `discard_range()`,
+    /// are not written. The file gets holes at their place instead, so that
+    /// they don't take up space on disk.
     fn apply_delta_to_file(
         &self,
         file: &mut File,
@@ -568,10 +872,30 @@ impl PageMap {
         path: &Path,
     ) -> Result<(), PersistenceError> {
         let mut opt_buffer: Option<WriteBuffer> = None;
+        // The consecutive zero pages of the delta that are not applied yet.
+        let mut opt_zero_pages: Option<Range<u64>> = None;
         let maximum_gap =
             page_delta.write_amplification_to_gap(MAXIMUM_GAP, MAXIMUM_WRITE_AMPLIFICATION);
 
         for (index, page) in page_delta.iter() {
+            if page.contents().iter().all(|byte| *byte == 0) {
+                if let Some(mut buffer) = opt_buffer.take() {
+                    buffer.apply_to_file(file, path)?;
+                }
+                if matches!(&opt_zero_pages, Some(pages) if pages.end == index.get()) {
+                    opt_zero_pages.as_mut().unwrap().end += 1;
+                } else {
+                    if let Some(pages) = opt_zero_pages.take() {
+                        write_zero_pages(file, pages, path)?;
+                    }
+                    opt_zero_pages = Some(index.get()..index.get() + 1);
+                }
+                continue;
+            }
+            if let Some(pages) = opt_zero_pages.take() {
+                write_zero_pages(file, pages, path)?;
+            }
+
             if let Some(buffer) = &mut opt_buffer {
                 let next_index = buffer.start_index.get() + buffer.content.len() as u64;
                 if index.get() <= next_index + maximum_gap {
@@ -600,6 +924,9 @@ impl PageMap {
         if let Some(buffer) = &mut opt_buffer {
             buffer.apply_to_file(file, path)?;
         }
+        if let Some(pages) = opt_zero_pages {
+            write_zero_pages(file, pages, path)?;
+        }
 
         Ok(())
     }
@@ -640,12 +967,16 @@ impl Buffer {
     /// Reads the contents of this buffer at the specified offset into the
     /// specified destination buffer.
     pub fn read(&self, mut dst: &mut [u8], mut offset: usize) {
-        let page_size = PAGE_SIZE;
+        if self.dirty_pages.is_empty() {
+            self.page_map.read_range(offset, dst);
+            return;
+        }
+        let page_size = PageSize::HOST;
 
         while !dst.is_empty() {
-            let page = PageIndex::new((offset / page_size) as u64);
-            let offset_into_page = offset % page_size;
-            let page_len = dst.len().min(page_size - offset_into_page);
+            let page = PageIndex::new(page_size.page_index(offset as u64));
+            let offset_into_page = page_size.offset_in_page(offset as u64) as usize;
+            let page_len = dst.len().min(page_size.bytes() as usize - offset_into_page);
 
             let page_contents = match self.dirty_pages.get(&page) {
                 Some(bytes) => bytes,
@@ -665,12 +996,12 @@ impl Buffer {
     /// Overwrites the contents of this buffer at the specified offset with the
     /// contents of the source buffer.
     pub fn write(&mut self, mut src: &[u8], mut offset: usize) {
-        let page_size = PAGE_SIZE;
+        let page_size = PageSize::HOST;
 
         while !src.is_empty() {
-            let page = PageIndex::new((offset / page_size) as u64);
-            let offset_into_page = offset % page_size;
-            let page_len = src.len().min(page_size - offset_into_page);
+            let page = PageIndex::new(page_size.page_index(offset as u64));
+            let offset_into_page = page_size.offset_in_page(offset as u64) as usize;
+            let page_len = src.len().min(page_size.bytes() as usize - offset_into_page);
 
             let dirty_page = self
                 .dirty_pages
@@ -686,6 +1017,35 @@ impl Buffer {
         }
     }
 
+    /// Zeroes `len` bytes of this buffer starting at `offset`. Unlike writing
+    /// zeroes, this doesn't create dirty pages for pages that are already
+    /// zero, e.g. because they were never written.
+    pub fn discard(&mut self, mut offset: usize, len: usize) {
+        let page_size = PageSize::HOST;
+        let end = offset.saturating_add(len);
+
+        while offset < end {
+            let page = PageIndex::new(page_size.page_index(offset as u64));
+            let offset_into_page = page_size.offset_in_page(offset as u64) as usize;
+            let page_len = (end - offset).min(page_size.bytes() as usize - offset_into_page);
+            let range = offset_into_page..offset_into_page + page_len;
+
+            match self.dirty_pages.get_mut(&page) {
+                Some(dirty_page) => dirty_page[range].fill(0),
+                None => {
+                    let contents = self.page_map.get_page(page);
+                    if contents[range.clone()].iter().any(|byte| *byte != 0) {
+                        let mut dirty_page = *contents;
+                        dirty_page[range].fill(0);
+                        self.dirty_pages.insert(page, dirty_page);
+                    }
+                }
+            }
+
+            offset += page_len;
+        }
+    }
+
     /// Determines the number of dirty pages that would be created by a write at
     /// the given offset with the given size. This does not guarantee that the
     /// write will succeed.
@@ -696,11 +1056,11 @@ impl Buffer {
         if size == 0 {
             return NumPages::from(0);
         }
-        let first_page = offset / (PAGE_SIZE as u64);
-        let last_page = offset
+        let first_page = PageSize::HOST.page_index(offset);
+        let last_byte = offset
             .saturating_add(size - 1)
-            .min(MAX_STABLE_MEMORY_IN_BYTES)
-            / (PAGE_SIZE as u64);
+            .min(MAX_STABLE_MEMORY_IN_BYTES);
+        let last_page = PageSize::HOST.page_index(last_byte);
         let dirty_page_count = (first_page..=last_page)
             .filter(|p| !self.dirty_pages.contains_key(&PageIndex::new(*p)))
             .count();
@@ -2,6 +2,8 @@ use crate::page_map::{FileDescriptor, MemoryRegion, PageIndex, PersistenceError}
 use ic_sys::{mmap::ScopedMmap, PAGE_SIZE};
 use ic_sys::{page_bytes_from_ptr, PageBytes};
 use lazy_static::lazy_static;
+use libc::c_void;
+use nix::sys::mman::{madvise, MmapAdvise};
 use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
 use std::ops::Range;
@@ -147,6 +149,24 @@ impl Mapping {
     pub fn num_pages(&self) -> usize {
         self.mmap.len() / PAGE_SIZE
     }
+
+    fn prefetch(&self, page_range: Range<PageIndex>) {
+        let num_pages = self.num_pages() as u64;
+        let start = page_range.start.get().min(num_pages) as usize;
+        let end = page_range.end.get().min(num_pages) as usize;
+        if start < end {
+            // SAFETY: The range is within the mapping. The advice doesn't
+            // change the contents of the mapping. Failures are ignored
+            // because the advice is only a hint.
+            let _ = unsafe {
+                madvise(
+                    self.mmap.addr().add(start * PAGE_SIZE) as *mut c_void,
+                    (end - start) * PAGE_SIZE,
+                    MmapAdvise::MADV_WILLNEED,
+                )
+            };
+        }
+    }
 }
 
 impl Checkpoint {
@@ -163,6 +183,16 @@ impl Checkpoint {
         })
     }
 
+    /// Returns true if both checkpoints are backed by the same mapping, in
+    /// which case they serve the same pages.
+    pub fn same_mapping(&self, other: &Checkpoint) -> bool {
+        match (&self.mapping, &other.mapping) {
+            (None, None) => true,
+            (Some(lhs), Some(rhs)) => Arc::ptr_eq(lhs, rhs),
+            _ => false,
+        }
+    }
+
     /// Returns a serialization-friendly representation of `Checkpoint`.
     pub fn serialize(&self) -> CheckpointSerialization {
         CheckpointSerialization {
@@ -205,6 +235,28 @@ impl Checkpoint {
         }
     }
 
+    /// Copies the bytes starting at the given offset into `dst` with a single
+    /// copy. The bytes beyond the end of the checkpoint file are zeros.
+    pub fn read(&self, offset: usize, dst: &mut [u8]) {
+        let bytes = match self.mapping {
+            Some(ref mapping) => mapping.mmap.as_slice(),
+            None => &[],
+        };
+        let start = offset.min(bytes.len());
+        let end = offset.saturating_add(dst.len()).min(bytes.len());
+        let (from_file, zeros) = dst.split_at_mut(end - start);
+        from_file.copy_from_slice(&bytes[start..end]);
+        zeros.fill(0);
+    }
+
+    /// Starts reading the pages in the given range from the checkpoint file
+    /// into the page cache without waiting for the reads to complete.
+    pub fn prefetch(&self, page_range: Range<PageIndex>) {
+        if let Some(ref mapping) = self.mapping {
+            mapping.prefetch(page_range)
+        }
+    }
+
     /// Returns the max number of (possibly) non-zero pages in this
     /// checkpoint.
     pub fn num_pages(&self) -> usize {
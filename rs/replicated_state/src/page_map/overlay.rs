@@ -0,0 +1,205 @@
+//! Overlay files hold a set of pages that override the pages of a checkpoint.
+//!
+//! An overlay file consists of three segments:
+//! - the data segment with the contents of the pages in increasing order of
+//!   their page indices. It comes first, so that all pages are aligned to the
+//!   page size within the file.
+//! - the index segment with one entry per page: the page index and the
+//!   checksum of the page contents, both as 64-bit little-endian integers.
+//! - the footer: the number of pages and the checksum of the index segment as
+//!   64-bit little-endian integers followed by the magic bytes.
+//!
+//! The pages are written to the data segment directly from the memory of the
+//! page allocator without copying them to intermediate buffers.
+
+use crate::page_map::{PageIndex, PersistenceError};
+use ic_sys::{mmap::ScopedMmap, page_bytes_from_ptr, PageBytes, PAGE_SIZE};
+use ic_utils::fs::write_all_vectored;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+
+const OVERLAY_MAGIC: &[u8; 8] = b"ICOVRL01";
+
+const INDEX_ENTRY_SIZE: usize = 16;
+
+const FOOTER_SIZE: usize = 24;
+
+/// A read-only memory-mapped overlay file. All checksums were verified when
+/// the file was opened.
+pub struct OverlayFile {
+    mmap: ScopedMmap,
+    // The page indices in increasing order. The contents of the `i`-th page
+    // are at the offset `i * PAGE_SIZE` in the file.
+    page_indices: Vec<PageIndex>,
+}
+
+impl OverlayFile {
+    /// Opens the overlay file at the given path and verifies its structure
+    /// and the checksums of the index and all pages.
+    pub fn open(path: &Path) -> Result<Self, PersistenceError> {
+        let file = OpenOptions::new().read(true).open(path).map_err(|err| {
+            PersistenceError::FileSystemError {
+                path: path.display().to_string(),
+                context: "Failed to open overlay file".to_string(),
+                internal_error: err.to_string(),
+            }
+        })?;
+        let len = file
+            .metadata()
+            .map_err(|err| PersistenceError::FileSystemError {
+                path: path.display().to_string(),
+                context: "Failed to retrieve file metadata".to_string(),
+                internal_error: err.to_string(),
+            })?
+            .len() as usize;
+        let invalid = |reason: String| PersistenceError::InvalidOverlayFile {
+            path: path.display().to_string(),
+            reason,
+        };
+        if len < FOOTER_SIZE {
+            return Err(invalid(format!(
+                "File size {} is smaller than the footer size {}",
+                len, FOOTER_SIZE
+            )));
+        }
+        let mmap = ScopedMmap::from_readonly_file(&file, len).map_err(|err| {
+            PersistenceError::MmapError {
+                path: path.display().to_string(),
+                len,
+                internal_error: err.to_string(),
+            }
+        })?;
+        let bytes = mmap.as_slice();
+
+        let footer = &bytes[len - FOOTER_SIZE..];
+        if &footer[16..] != OVERLAY_MAGIC {
+            return Err(invalid("Bad magic bytes".to_string()));
+        }
+        let num_pages = read_u64(&footer[0..8]) as usize;
+        let index_checksum = read_u64(&footer[8..16]);
+        let expected_len = num_pages
+            .checked_mul(PAGE_SIZE + INDEX_ENTRY_SIZE)
+            .and_then(|segments_len| segments_len.checked_add(FOOTER_SIZE));
+        if expected_len != Some(len) {
+            return Err(invalid(format!(
+                "File size {} doesn't match the number of pages {}",
+                len, num_pages
+            )));
+        }
+
+        let data_len = num_pages * PAGE_SIZE;
+        let index = &bytes[data_len..len - FOOTER_SIZE];
+        if checksum(index) != index_checksum {
+            return Err(invalid("Index segment doesn't match its checksum".to_string()));
+        }
+
+        let mut page_indices = Vec::with_capacity(num_pages);
+        for (i, entry) in index.chunks_exact(INDEX_ENTRY_SIZE).enumerate() {
+            let page_index = PageIndex::new(read_u64(&entry[0..8]));
+            if page_indices.last().map_or(false, |last| *last >= page_index) {
+                return Err(invalid(format!(
+                    "Page index {} is not greater than the previous one",
+                    page_index
+                )));
+            }
+            let contents = &bytes[i * PAGE_SIZE..(i + 1) * PAGE_SIZE];
+            if checksum(contents) != read_u64(&entry[8..16]) {
+                return Err(invalid(format!(
+                    "Page {} doesn't match its checksum",
+                    page_index
+                )));
+            }
+            page_indices.push(page_index);
+        }
+
+        Ok(Self { mmap, page_indices })
+    }
+
+    /// Returns the number of pages in the overlay file.
+    pub fn num_pages(&self) -> usize {
+        self.page_indices.len()
+    }
+
+    /// Returns the contents of the page with the given index if the overlay
+    /// file contains it.
+    pub fn get_page(&self, page_index: PageIndex) -> Option<&PageBytes> {
+        self.page_indices
+            .binary_search(&page_index)
+            .ok()
+            .map(|i| self.page_at(i))
+    }
+
+    /// Returns the iterator over the pages of the overlay file in increasing
+    /// order of their indices.
+    pub fn iter(&self) -> impl Iterator<Item = (PageIndex, &PageBytes)> + '_ {
+        self.page_indices
+            .iter()
+            .enumerate()
+            .map(move |(i, page_index)| (*page_index, self.page_at(i)))
+    }
+
+    fn page_at(&self, i: usize) -> &PageBytes {
+        assert!(i < self.page_indices.len());
+        // SAFETY: The data segment of the memory-mapped file contains
+        // `page_indices.len()` pages, so the page starting at `i * PAGE_SIZE`
+        // is mapped and remains valid for the lifetime of `self`. The mapping
+        // is read-only.
+        unsafe { page_bytes_from_ptr(self, self.mmap.addr().add(i * PAGE_SIZE)) }
+    }
+}
+
+/// Writes the given pages into a new overlay file at the given path,
+/// replacing the file if it exists.
+/// Precondition: the pages are sorted by their indices and the indices are
+/// unique.
+pub(crate) fn write_overlay_file<'a, I>(pages: I, dst: &Path) -> Result<(), PersistenceError>
+where
+    I: IntoIterator<Item = (PageIndex, &'a PageBytes)>,
+{
+    let mut data: Vec<&[u8]> = vec![];
+    let mut index = vec![];
+    for (page_index, contents) in pages {
+        data.push(&contents[..]);
+        index.extend_from_slice(&page_index.get().to_le_bytes());
+        index.extend_from_slice(&checksum(contents).to_le_bytes());
+    }
+    let mut footer = Vec::with_capacity(FOOTER_SIZE);
+    footer.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    footer.extend_from_slice(&checksum(&index).to_le_bytes());
+    footer.extend_from_slice(OVERLAY_MAGIC);
+
+    let mut file = create_file(dst)?;
+    data.push(&index);
+    data.push(&footer);
+    write_all_vectored(&mut file, &data).map_err(|err| PersistenceError::FileSystemError {
+        path: dst.display().to_string(),
+        context: format!("Failed to write overlay file with {} pages", data.len() - 2),
+        internal_error: err.to_string(),
+    })
+}
+
+fn create_file(path: &Path) -> Result<File, PersistenceError> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .map_err(|err| PersistenceError::FileSystemError {
+            path: path.display().to_string(),
+            context: "Failed to create overlay file".to_string(),
+            internal_error: err.to_string(),
+        })
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap())
+}
+
+// The 64-bit FNV-1a hash of the given bytes.
+fn checksum(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(PRIME)
+    })
+}
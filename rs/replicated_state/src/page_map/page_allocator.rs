@@ -11,11 +11,22 @@ mod page_allocator_registry;
 
 pub mod mmap;
 
+mod versioned;
+
 use mmap::{PageAllocatorId, PageAllocatorInner, PageInner};
+pub use mmap::{configure_page_allocators, BackingFileBackend, IoOperation, PageAllocatorIoError};
 
 use super::{FileDescriptor, FileOffset};
 
-static ALLOCATED_PAGES: PageCounter = PageCounter::new();
+static ALLOCATED_PAGES: Counter = Counter::new();
+static FREED_PAGES: Counter = Counter::new();
+static BACKING_FILE_BYTES: Counter = Counter::new();
+static MMAP_CHUNKS: Counter = Counter::new();
+static PAGE_VALIDATION_FAILURES: Counter = Counter::new();
+static PAGE_CHECKSUM_MISMATCHES: Counter = Counter::new();
+static DEDUPLICATED_PAGES: Counter = Counter::new();
+static BACKING_FILE_DIRECTORY_FALLBACKS: Counter = Counter::new();
+static QUARANTINED_PAGES: Counter = Counter::new();
 
 /// A clonable wrapper around a 4KiB memory page implementation.
 /// It is mostly immutable after creation with the only exception of `Buffer`
@@ -39,6 +50,28 @@ impl Page {
     pub(super) fn contents(&self) -> &PageBytes {
         self.0.contents()
     }
+
+    /// Returns true if both pages are the same allocation, in which case they
+    /// have the same contents.
+    pub(super) fn ptr_eq(&self, other: &Page) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Checks the contents of the page against its validation information
+    /// and quarantines the page if they don't match. Returns true if the page
+    /// is valid.
+    pub(super) fn revalidate(&self) -> bool {
+        self.0.revalidate()
+    }
+}
+
+/// Advises the kernel to read ahead the backing memory of the given pages
+/// without blocking the caller.
+pub(super) fn prefetch_pages<'a, I>(pages: I)
+where
+    I: IntoIterator<Item = &'a Page>,
+{
+    mmap::prefetch_pages(pages.into_iter().map(|page| page.0.as_ref()))
 }
 
 /// We have to implement `Clone` manually because `#[derive(Clone)]` is confused
@@ -83,6 +116,21 @@ impl PageAllocator {
         PageAllocatorInner::allocate(&self.0, pages)
     }
 
+    /// Same as `allocate()`, but returns an error instead of panicking if an
+    /// operation on the backing files fails.
+    pub fn try_allocate(
+        &self,
+        pages: &[(PageIndex, &PageBytes)],
+    ) -> Result<Vec<(PageIndex, Page)>, PageAllocatorIoError> {
+        PageAllocatorInner::try_allocate(&self.0, pages)
+    }
+
+    /// Frees the memory of the pages dropped so far right away instead of
+    /// waiting until enough dropped pages accumulate to amortize the cost.
+    pub fn reclaim_dropped_pages(&self) {
+        self.0.reclaim_dropped_pages()
+    }
+
     /// Returns a serialization-friendly representation of the page allocator.
     pub fn serialize(&self) -> PageAllocatorSerialization {
         self.0.serialize()
@@ -103,6 +151,18 @@ impl PageAllocator {
         self.0.serialize_page_delta(page_delta)
     }
 
+    /// Same as `serialize_page_delta()`, but returns an error instead of
+    /// panicking if an operation on the backing files fails.
+    pub fn try_serialize_page_delta<'a, I>(
+        &'a self,
+        page_delta: I,
+    ) -> Result<PageDeltaSerialization, PageAllocatorIoError>
+    where
+        I: IntoIterator<Item = (PageIndex, &'a Page)>,
+    {
+        self.0.try_serialize_page_delta(page_delta)
+    }
+
     /// Creates a page-delta from the given serialization-friendly
     /// representation.
     pub fn deserialize_page_delta(
@@ -111,11 +171,22 @@ impl PageAllocator {
     ) -> Vec<(PageIndex, Page)> {
         PageAllocatorInner::deserialize_page_delta(&self.0, page_delta)
     }
+
+    /// Same as `deserialize_page_delta()`, but returns an error instead of
+    /// panicking if the backing files cannot be memory-mapped or are shorter
+    /// than the page delta requires.
+    pub fn try_deserialize_page_delta(
+        &self,
+        page_delta: PageDeltaSerialization,
+    ) -> Result<Vec<(PageIndex, Page)>, PageAllocatorIoError> {
+        PageAllocatorInner::try_deserialize_page_delta(&self.0, page_delta)
+    }
 }
 
-struct PageCounter(AtomicUsize);
+/// A process-wide counter shared by all page allocators.
+struct Counter(AtomicUsize);
 
-impl PageCounter {
+impl Counter {
     const fn new() -> Self {
         Self(AtomicUsize::new(0))
     }
@@ -138,15 +209,71 @@ pub fn allocated_pages_count() -> usize {
     ALLOCATED_PAGES.get()
 }
 
+/// A snapshot of the statistics of all the page allocators in this process.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PageAllocatorStats {
+    /// The number of tracked pages allocated at the moment.
+    pub allocated_pages: usize,
+    /// The number of dropped pages whose memory was returned to the OS since
+    /// the start of the process.
+    pub freed_pages: usize,
+    /// The total size of the backing files owned by the page allocators.
+    pub backing_file_bytes: usize,
+    /// The number of memory-mapped chunks of the backing files.
+    pub mmap_chunks: usize,
+    /// The number of revalidated pages whose contents didn't match their
+    /// validation information since the start of the process. A failure on
+    /// access aborts the process, so it isn't counted.
+    pub validation_failures: usize,
+    /// The number of revalidated pages whose contents didn't match their
+    /// checksum since the start of the process. A mismatch on
+    /// deserialization aborts the process, so it isn't counted.
+    pub checksum_mismatches: usize,
+    /// The number of allocations that reused an existing page with the same
+    /// contents since the start of the process.
+    pub deduplicated_pages: usize,
+    /// The backend of the most recently created backing file, if any.
+    pub backing_file_backend: Option<BackingFileBackend>,
+    /// The number of backing files that couldn't be created in the configured
+    /// backing file directory and were created in the default location
+    /// instead since the start of the process.
+    pub backing_file_directory_fallbacks: usize,
+    /// The number of pages that failed revalidation after a sandbox process
+    /// died unexpectedly and were quarantined since the start of the process.
+    pub quarantined_pages: usize,
+}
+
+/// Returns the current statistics of the page allocators.
+pub fn page_allocator_stats() -> PageAllocatorStats {
+    PageAllocatorStats {
+        allocated_pages: ALLOCATED_PAGES.get(),
+        freed_pages: FREED_PAGES.get(),
+        backing_file_bytes: BACKING_FILE_BYTES.get(),
+        mmap_chunks: MMAP_CHUNKS.get(),
+        validation_failures: PAGE_VALIDATION_FAILURES.get(),
+        checksum_mismatches: PAGE_CHECKSUM_MISMATCHES.get(),
+        deduplicated_pages: DEDUPLICATED_PAGES.get(),
+        backing_file_backend: mmap::last_backing_file_backend(),
+        backing_file_directory_fallbacks: BACKING_FILE_DIRECTORY_FALLBACKS.get(),
+        quarantined_pages: QUARANTINED_PAGES.get(),
+    }
+}
+
 /// Serialization-friendly representation of `PageAllocator`.
 ///
 /// It contains sufficient information to reconstruct the page allocator
 /// in another process ensuring that there are no two page allocators
 /// with the same id.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+///
+/// It is encoded in a versioned format, see the `versioned` module.
+#[derive(Clone, Debug)]
 pub struct PageAllocatorSerialization {
     pub id: PageAllocatorId,
+    /// The first backing file.
     pub fd: FileDescriptor,
+    /// The backing files created after the first one reached the maximum
+    /// backing file size, in the order of creation.
+    pub additional_fds: Vec<FileDescriptor>,
 }
 
 /// Serialization-friendly representation of an indexed page.
@@ -172,6 +299,11 @@ pub struct PageSerialization {
 ///
 /// This validation is also useful for checking that the page transfer between
 /// the sandbox and the replica processes works properly.
+///
+/// If page checksums are enabled, then the validation also includes a
+/// checksum of the whole page, which is verified when the page is
+/// deserialized. It catches corruption of the backing file that the check of
+/// a single word would miss.
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
 pub struct PageValidation {
     // The index of a non-zero two-byte word in the page.
@@ -180,6 +312,8 @@ pub struct PageValidation {
     // The value of a non-zero two-byte word specified by the index above.
     // It is zero if no such word exists.
     pub non_zero_word_value: u16,
+    // The checksum of the page contents, if page checksums are enabled.
+    pub checksum: Option<u64>,
 }
 
 /// Serialization-friendly representation of an mmap-based page.
@@ -193,19 +327,27 @@ pub struct MmapPageSerialization {
 /// Serialization-friendly representation of `PageDelta`.
 ///
 /// It contains sufficient information to reconstruct the page-delta
-/// in another process. Note that he pages are backed by the file owned by the page allocator.
-/// Each page is represented by its offset in the file. The length of the file is
-/// sent along to simplify deserialization. It is guaranteed that the file
-/// offsets of all pages are smaller than the length of the file.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// in another process. Note that he pages are backed by the files owned by the page allocator.
+/// Each page is represented by its offset in the files. The index and the length of the
+/// last file are sent along to simplify deserialization. It is guaranteed that all pages
+/// are in the files up to the last one and that their offsets in the last file are
+/// smaller than its length.
+///
+/// It is encoded in a versioned format, see the `versioned` module.
+#[derive(Clone, Debug)]
 pub struct PageDeltaSerialization {
+    file_index: usize,
     file_len: FileOffset,
     pages: Vec<MmapPageSerialization>,
 }
 
 impl PageDeltaSerialization {
     pub fn is_empty(&self) -> bool {
-        let Self { file_len, pages } = self;
+        let Self {
+            file_index: _,
+            file_len,
+            pages,
+        } = self;
         *file_len == 0 && pages.is_empty()
     }
 }
@@ -3,20 +3,213 @@ use crate::page_map::{FileDescriptor, FileOffset};
 use super::page_allocator_registry::PageAllocatorRegistry;
 use super::{
     MmapPageSerialization, Page, PageAllocatorSerialization, PageDeltaSerialization,
-    PageValidation, ALLOCATED_PAGES,
+    PageValidation, ALLOCATED_PAGES, BACKING_FILE_BYTES, BACKING_FILE_DIRECTORY_FALLBACKS,
+    DEDUPLICATED_PAGES, FREED_PAGES, MMAP_CHUNKS, PAGE_CHECKSUM_MISMATCHES,
+    PAGE_VALIDATION_FAILURES, QUARANTINED_PAGES,
 };
-use cvt::{cvt, cvt_r};
+use cvt::cvt_r;
+use ic_config::embedders::PageAllocatorConfig;
 use ic_sys::{page_bytes_from_ptr, PageBytes, PageIndex, PAGE_SIZE};
+use io::{operation_failed, BackingFileIo, SystemIo};
 use libc::{c_void, close};
-use nix::sys::mman::{madvise, mmap, munmap, MapFlags, MmapAdvise, ProtFlags};
+use nix::sys::mman::{madvise, munmap, MmapAdvise};
 use serde::{Deserialize, Serialize};
-use std::os::raw::c_int;
+use std::collections::HashMap;
 use std::os::unix::io::RawFd;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+mod io;
+
+pub(super) use io::last_backing_file_backend;
+pub use io::{BackingFileBackend, IoOperation, PageAllocatorIoError};
 
 const MIN_PAGES_TO_FREE: usize = 10000;
 
+// The size of a transparent huge page on x86-64 and aarch64.
+const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+// Whether page allocators created from now on back their memory with
+// transparent huge pages. See `set_transparent_huge_pages()`.
+static TRANSPARENT_HUGE_PAGES: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables backing the memory of page allocators created after
+/// this call with transparent huge pages. Existing page allocators are not
+/// affected.
+///
+/// When enabled, the backing file grows in multiples of 2MiB and every chunk
+/// is advised with `MADV_HUGEPAGE`, so that the kernel can map it with huge
+/// pages if `/sys/kernel/mm/transparent_hugepage/shmem_enabled` allows it.
+/// This has no effect on platforms other than Linux.
+fn set_transparent_huge_pages(enabled: bool) {
+    TRANSPARENT_HUGE_PAGES.store(enabled, Ordering::Relaxed);
+}
+
+// Whether the validation of pages written by page allocators created from now
+// on includes a checksum of the page contents. See `set_page_checksums()`.
+static PAGE_CHECKSUMS: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables recording a checksum of the contents of every page
+/// written by page allocators created after this call. The checksum of a
+/// page is verified when the page is deserialized, e.g. in the sandbox
+/// process, to catch corruption of the backing file before the page makes it
+/// into the state.
+fn set_page_checksums(enabled: bool) {
+    PAGE_CHECKSUMS.store(enabled, Ordering::Relaxed);
+}
+
+// Whether page allocators created from now on reuse existing pages with the
+// same contents. See `set_page_deduplication()`.
+static PAGE_DEDUPLICATION: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables deduplication of pages in page allocators created
+/// after this call. Existing page allocators are not affected.
+///
+/// When enabled, allocating a page with the same contents as a live page of
+/// any deduplicating page allocator in this process returns the existing
+/// page instead of a new one. Pages are immutable, so a modification always
+/// allocates a new page. When a page delta with a page of another page
+/// allocator is serialized, the page is copied once into the backing file of
+/// the serializing page allocator, because the other process can only access
+/// the backing files of that page allocator.
+fn set_page_deduplication(enabled: bool) {
+    PAGE_DEDUPLICATION.store(enabled, Ordering::Relaxed);
+}
+
+lazy_static::lazy_static! {
+    // The directory for the backing files of page allocators created from now
+    // on. See `set_backing_file_directory()`.
+    static ref BACKING_FILE_DIRECTORY: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// Sets the directory in which the backing files of page allocators created
+/// after this call are placed, e.g. a tmpfs mount or a dedicated disk
+/// partition. `None` restores the default: an in-memory file on Linux and a
+/// temporary file elsewhere.
+///
+/// If a backing file cannot be created in the directory, the page allocator
+/// falls back to the default and counts the fallback in the statistics.
+fn set_backing_file_directory(directory: Option<PathBuf>) {
+    *BACKING_FILE_DIRECTORY.lock().unwrap() = directory;
+}
+
+lazy_static::lazy_static! {
+    static ref DEDUPLICATION_CACHE: Mutex<DeduplicationCache> = Mutex::new(DeduplicationCache::new());
+}
+
+/// A process-wide content-addressed index of the pages allocated by the
+/// deduplicating page allocators. It holds weak references, so it doesn't
+/// keep pages alive.
+struct DeduplicationCache {
+    // The pages by the hash of their contents.
+    table: HashMap<u64, Vec<Weak<PageInner>>>,
+
+    // The table will be compacted once its length reaches this threshold.
+    // Compaction means filtering out empty weak references.
+    compaction_threshold: usize,
+}
+
+impl DeduplicationCache {
+    fn new() -> Self {
+        Self {
+            table: HashMap::default(),
+            compaction_threshold: 1024,
+        }
+    }
+
+    // Returns a live page with the given contents if there is one.
+    // The caller must not hold the lock of any page allocator, because
+    // dropping a page that was looked up may need to lock its page allocator.
+    fn lookup(hash: u64, contents: &PageBytes) -> Option<Arc<PageInner>> {
+        let mut mismatches = vec![];
+        let found = {
+            let mut cache = DEDUPLICATION_CACHE.lock().unwrap();
+            let pages = cache.table.get(&hash)?;
+            let mut found = None;
+            for page in pages.iter().filter_map(Weak::upgrade) {
+                if !page.is_quarantined() && page.contents() == contents {
+                    found = Some(page);
+                    break;
+                }
+                mismatches.push(page);
+            }
+            if found.is_none() {
+                if let Some(pages) = cache.table.get_mut(&hash) {
+                    pages.retain(|page| page.strong_count() > 0);
+                }
+            }
+            found
+        };
+        // The pages are dropped here, after the cache is unlocked.
+        drop(mismatches);
+        found
+    }
+
+    fn insert(hash: u64, page: &Arc<PageInner>) {
+        let mut cache = DEDUPLICATION_CACHE.lock().unwrap();
+        cache.table.entry(hash).or_default().push(Arc::downgrade(page));
+        if cache.table.len() >= cache.compaction_threshold {
+            // Perform amortized compaction of the table.
+            cache.table.retain(|_hash, pages| {
+                pages.retain(|page| page.strong_count() > 0);
+                !pages.is_empty()
+            });
+            cache.compaction_threshold = (cache.table.len() * 2).max(1024);
+        }
+    }
+}
+
+// A page allocator may have multiple backing files. The pages of the `i`-th
+// backing file have file offsets starting at `i * BACKING_FILE_STRIDE`, so
+// that a single file offset identifies a page across all backing files.
+// This bounds the size of each backing file to 1TiB.
+const BACKING_FILE_STRIDE: FileOffset = 1 << 40;
+
+// The size after which page allocators created from now on continue in a new
+// backing file. See `set_max_backing_file_size()`.
+static MAX_BACKING_FILE_SIZE: AtomicUsize = AtomicUsize::new(BACKING_FILE_STRIDE as usize);
+
+/// Sets the maximum size of a backing file of page allocators created after
+/// this call. Existing page allocators are not affected.
+///
+/// When growing the backing file would exceed the maximum size, the page
+/// allocator creates a new backing file and continues allocating in it.
+/// The size is rounded down to a multiple of the page size and is clamped to
+/// the range from one page to 1TiB.
+///
+/// Only the process that creates a page allocator starts new backing files.
+/// A page allocator deserialized in another process keeps growing the last
+/// backing file it knows about, so its files may exceed the maximum size.
+fn set_max_backing_file_size(bytes: usize) {
+    let bytes = bytes.clamp(PAGE_SIZE, BACKING_FILE_STRIDE as usize);
+    MAX_BACKING_FILE_SIZE.store(bytes / PAGE_SIZE * PAGE_SIZE, Ordering::Relaxed);
+}
+
+/// Applies the given configuration to the page allocators created or
+/// deserialized in this process from now on.
+///
+/// The replica and every sandbox process call this at startup with the same
+/// configuration, because a page allocator is shared between the processes
+/// and each of them grows its backing files independently.
+pub fn configure_page_allocators(config: &PageAllocatorConfig) {
+    set_transparent_huge_pages(config.transparent_huge_pages);
+    set_page_checksums(config.page_checksums);
+    set_max_backing_file_size(config.max_backing_file_size.get() as usize);
+    set_page_deduplication(config.page_deduplication);
+    set_backing_file_directory(config.backing_file_directory.clone());
+}
+
+// Returns the file offset of the given offset within the given backing file.
+fn page_offset(file_index: usize, offset_in_file: FileOffset) -> FileOffset {
+    file_index as FileOffset * BACKING_FILE_STRIDE + offset_in_file
+}
+
+// Returns the index of the backing file that contains the given file offset.
+fn backing_file_index(offset: FileOffset) -> usize {
+    (offset / BACKING_FILE_STRIDE) as usize
+}
+
 // The start address of a page.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 struct PagePtr(*mut u8);
@@ -35,7 +228,7 @@ unsafe impl Send for PagePtr {}
 /// access the page contents requires a reference to the page allocator.
 ///
 /// It is exported publicly for benchmarking.
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct PageInner {
     ptr: PagePtr,
     offset: FileOffset,
@@ -44,6 +237,8 @@ pub struct PageInner {
     // does not own the backing file.
     page_allocator: Option<Arc<PageAllocatorInner>>,
     validation: PageValidation,
+    // Whether the page failed revalidation, see `revalidate()`.
+    quarantined: AtomicBool,
 }
 
 impl Drop for PageInner {
@@ -59,12 +254,14 @@ impl PageInner {
         // SAFETY: The provided reference to the page allocator is a witness that the
         // underlying memory is still valid.
         unsafe {
-            assert!(self.is_valid());
+            self.assert_valid();
             page_bytes_from_ptr(self, self.ptr.0)
         }
     }
 
-    fn copy_from_slice(&mut self, offset: usize, slice: &[u8]) {
+    // Copies the slice into the page and updates the validation information,
+    // which includes a checksum of the contents if `checksum` is true.
+    fn copy_from_slice(&mut self, offset: usize, slice: &[u8], checksum: bool) {
         assert!(offset + slice.len() <= PAGE_SIZE);
         // SAFETY: The provided reference to the page allocator is a witness that the
         // underlying memory is still valid. The mutable reference to self shows that
@@ -73,16 +270,82 @@ impl PageInner {
             if self.validation.non_zero_word_value != 0 {
                 // The branch optimizes for the common case when the page is
                 // initialized immediately after allocation.
-                assert!(self.is_valid());
+                self.assert_valid();
             }
             std::ptr::copy_nonoverlapping(slice.as_ptr(), self.ptr.0.add(offset), slice.len());
-            // Update the validation information if it wasn't initialized yet or
-            // became invalid.
-            if self.validation.non_zero_word_value == 0 || !self.is_valid() {
-                self.validation = self.compute_validation();
+            // Update the validation information if it wasn't initialized yet,
+            // became invalid, or includes a checksum of the old contents.
+            if self.validation.non_zero_word_value == 0
+                || !self.is_valid()
+                || self.validation.checksum.is_some()
+            {
+                self.validation = self.compute_validation(checksum);
+            }
+        };
+    }
+    /// Checks the contents of the page against its validation information,
+    /// including the checksum if there is one, and returns true if they
+    /// match. Unlike the checks on access, this doesn't panic.
+    ///
+    /// A page that fails the check is quarantined: it is never reused by
+    /// deduplication and accessing its contents panics.
+    pub fn revalidate(&self) -> bool {
+        if self.is_quarantined() {
+            return false;
+        }
+        // SAFETY: The page allocator is a witness that the underlying memory
+        // is still valid.
+        let valid = unsafe {
+            if !self.is_valid() {
+                PAGE_VALIDATION_FAILURES.inc_by(1);
+                false
+            } else if !self.matches_checksum() {
+                PAGE_CHECKSUM_MISMATCHES.inc_by(1);
+                false
+            } else {
+                true
             }
         };
+        if !valid && !self.quarantined.swap(true, Ordering::Relaxed) {
+            QUARANTINED_PAGES.inc_by(1);
+        }
+        valid
+    }
+
+    /// Returns true if the page failed revalidation.
+    pub fn is_quarantined(&self) -> bool {
+        self.quarantined.load(Ordering::Relaxed)
     }
+
+    // Panics if the page is quarantined or not valid.
+    #[inline]
+    unsafe fn assert_valid(&self) {
+        if self.is_quarantined() {
+            panic!("Page at file offset {} is quarantined", self.offset);
+        }
+        if !self.is_valid() {
+            panic!("Page at file offset {} failed validation", self.offset);
+        }
+    }
+
+    // Panics if the contents of the page don't match its checksum.
+    unsafe fn verify_checksum(&self) {
+        if !self.matches_checksum() {
+            panic!(
+                "Page at file offset {} does not match its checksum",
+                self.offset
+            );
+        }
+    }
+
+    // Returns true if the contents of the page match its checksum. Pages
+    // without a checksum always match.
+    unsafe fn matches_checksum(&self) -> bool {
+        self.validation.checksum.map_or(true, |checksum| {
+            page_checksum(page_bytes_from_ptr(self, self.ptr.0)) == checksum
+        })
+    }
+
     // See the comments of `PageValidation`.
     #[inline]
     unsafe fn is_valid(&self) -> bool {
@@ -92,7 +355,12 @@ impl PageInner {
     }
 
     // See the comments of `PageValidation`.
-    unsafe fn compute_validation(&self) -> PageValidation {
+    unsafe fn compute_validation(&self, checksum: bool) -> PageValidation {
+        let checksum = if checksum {
+            Some(page_checksum(page_bytes_from_ptr(self, self.ptr.0)))
+        } else {
+            None
+        };
         // Search for the first non-zero 8-byte word.
         let mut ptr = self.ptr.0 as *const u64;
         let end = self.ptr.0.add(PAGE_SIZE) as *const u64;
@@ -101,7 +369,10 @@ impl PageInner {
         }
         if ptr == end {
             // The page contains only zeros.
-            return PageValidation::default();
+            return PageValidation {
+                checksum,
+                ..PageValidation::default()
+            };
         }
         // We found the non-zero 8-byte word. Now find the non-zero two-byte
         // word within it. The `while` loop below is guaranteed to stop after
@@ -113,10 +384,22 @@ impl PageInner {
         PageValidation {
             non_zero_word_index: ptr.offset_from(self.ptr.0 as *const u16) as u16,
             non_zero_word_value: *ptr,
+            checksum,
         }
     }
 }
 
+// A fast non-cryptographic 64-bit checksum of the given page contents. It
+// also serves as the hash of the contents for deduplication.
+fn page_checksum(contents: &PageBytes) -> u64 {
+    const SEED: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    contents.chunks_exact(8).fold(SEED, |checksum, word| {
+        let word = u64::from_le_bytes(word.try_into().unwrap());
+        (checksum ^ word).wrapping_mul(PRIME).rotate_left(29)
+    })
+}
+
 /// A page allocator that uses a memory-mapped file as a backing store of pages.
 ///
 /// On Linux the page allocator uses `memfd_create` to create the file in memory.
@@ -148,34 +431,113 @@ impl PageAllocatorInner {
         page_allocator: &Arc<Self>,
         pages: &[(PageIndex, &PageBytes)],
     ) -> Vec<(PageIndex, Page)> {
-        let mut guard = page_allocator.0.lock().unwrap();
-        let core = guard.get_or_insert_with(MmapBasedPageAllocatorCore::new);
-        // It would also be correct to increment the counters after all the
-        // allocations, but doing it before gives better performance because
-        // the core allocator can memory-map larger chunks.
-        ALLOCATED_PAGES.inc_by(pages.len());
-        core.allocated_pages += pages.len();
-        pages
+        Self::try_allocate(page_allocator, pages).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // See the comments of the corresponding method in `PageAllocator`.
+    pub fn try_allocate(
+        page_allocator: &Arc<Self>,
+        pages: &[(PageIndex, &PageBytes)],
+    ) -> Result<Vec<(PageIndex, Page)>, PageAllocatorIoError> {
+        let deduplicate = {
+            let mut guard = page_allocator.0.lock().unwrap();
+            get_or_create_core(&mut guard)?.deduplicate
+        };
+        if !deduplicate {
+            return Self::allocate_new_pages(page_allocator, pages);
+        }
+
+        // Look up the existing pages without holding the lock of the page
+        // allocator. See `DeduplicationCache::lookup()`.
+        let hashes: Vec<u64> = pages
+            .iter()
+            .map(|(_, contents)| page_checksum(contents))
+            .collect();
+        let mut result: Vec<Option<Page>> = pages
             .iter()
-            .map(|(page_index, contents)| {
-                let mut page = core.allocate_page(page_allocator);
+            .zip(hashes.iter())
+            .map(|((_, contents), hash)| DeduplicationCache::lookup(*hash, contents).map(Page))
+            .collect();
+        DEDUPLICATED_PAGES.inc_by(result.iter().filter(|page| page.is_some()).count());
+
+        let missing: Vec<usize> = (0..pages.len()).filter(|i| result[*i].is_none()).collect();
+        let missing_pages: Vec<_> = missing.iter().map(|i| pages[*i]).collect();
+        let new_pages = Self::allocate_new_pages(page_allocator, &missing_pages)?;
+        for (i, (_, page)) in missing.into_iter().zip(new_pages.into_iter()) {
+            DeduplicationCache::insert(hashes[i], &page.0);
+            result[i] = Some(page);
+        }
+        Ok(pages
+            .iter()
+            .zip(result.into_iter())
+            .map(|((page_index, _), page)| (*page_index, page.unwrap()))
+            .collect())
+    }
+
+    // Allocates a new page for each of the given pages.
+    //
+    // The pages of the whole batch are reserved in one critical section. The
+    // contents of the pages are copied and validated after unlocking the page
+    // allocator, so that concurrent allocations and page drops don't wait for
+    // the copying.
+    fn allocate_new_pages(
+        page_allocator: &Arc<Self>,
+        pages: &[(PageIndex, &PageBytes)],
+    ) -> Result<Vec<(PageIndex, Page)>, PageAllocatorIoError> {
+        let (regions, owner, checksums) = {
+            let mut guard = page_allocator.0.lock().unwrap();
+            let core = get_or_create_core(&mut guard)?;
+            // It would also be correct to increment the counters after all the
+            // allocations, but doing it before gives better performance because
+            // the core allocator can memory-map larger chunks.
+            ALLOCATED_PAGES.inc_by(pages.len());
+            core.allocated_pages += pages.len();
+            // Only the owner of the backing files tracks the dropped pages.
+            let owner = match core.backing_file_owner {
+                BackingFileOwner::CurrentAllocator => Some(page_allocator),
+                BackingFileOwner::AnotherAllocator => None,
+            };
+            match core.reserve_pages(pages.len()) {
+                Ok(regions) => (regions, owner, core.page_checksums),
+                Err(err) => {
+                    ALLOCATED_PAGES.dec_by(pages.len());
+                    core.allocated_pages -= pages.len();
+                    return Err(err);
+                }
+            }
+        };
+        let mut result = Vec::with_capacity(pages.len());
+        let mut pages = pages.iter();
+        for mut region in regions {
+            while !region.is_empty() {
+                let (page_index, contents) = pages.next().unwrap();
+                // SAFETY: the region was reserved above in a memory-mapped
+                // `Chunk` and its pages are owned only by this allocation. The
+                // chunk stays mapped while `page_allocator` is alive.
+                let mut page = unsafe { region.allocate_page(owner) };
                 // Lint suggestion leads to non-compiling a bug. Rustc 1.65
                 #[allow(clippy::explicit_auto_deref)]
-                page.copy_from_slice(0, *contents);
-                (*page_index, Page(Arc::new(page)))
-            })
-            .collect()
+                page.copy_from_slice(0, *contents, checksums);
+                result.push((*page_index, Page(Arc::new(page))));
+            }
+        }
+        debug_assert!(pages.next().is_none());
+        Ok(result)
     }
 
     // See the comments of the corresponding method in `PageAllocator`.
     pub fn serialize(&self) -> PageAllocatorSerialization {
         let mut guard = self.0.lock().unwrap();
-        let core = guard.get_or_insert_with(MmapBasedPageAllocatorCore::new);
+        let core = get_or_create_core(&mut guard).unwrap_or_else(|err| panic!("{}", err));
         PageAllocatorSerialization {
             id: core.id,
             fd: FileDescriptor {
-                fd: core.file_descriptor,
+                fd: core.files[0].fd,
             },
+            additional_fds: core.files[1..]
+                .iter()
+                .map(|file| FileDescriptor { fd: file.fd })
+                .collect(),
         }
     }
 
@@ -184,11 +546,27 @@ impl PageAllocatorInner {
     // reference to that page allocator.
     // Otherwise, the function creates a new page allocator and registers it in the
     // global `PageAllocatorRegistry`.
+    // The backing files that were added to the page allocator after it was
+    // deserialized are adopted by the existing page allocator.
     pub fn deserialize(serialized_page_allocator: PageAllocatorSerialization) -> Arc<Self> {
-        let PageAllocatorSerialization { id, fd } = serialized_page_allocator;
-        PageAllocatorRegistry::lookup_or_insert_with(&id, || {
-            Arc::new(Self::open(id, fd, BackingFileOwner::AnotherAllocator))
-        })
+        let PageAllocatorSerialization {
+            id,
+            fd,
+            additional_fds,
+        } = serialized_page_allocator;
+        let mut fds = Some(std::iter::once(fd).chain(additional_fds).collect::<Vec<_>>());
+        let page_allocator = PageAllocatorRegistry::lookup_or_insert_with(&id, || {
+            Arc::new(Self::open(
+                id,
+                fds.take().unwrap(),
+                BackingFileOwner::AnotherAllocator,
+            ))
+        });
+        if let Some(fds) = fds {
+            let mut guard = page_allocator.0.lock().unwrap();
+            guard.as_mut().unwrap().adopt_backing_files(fds);
+        }
+        page_allocator
     }
 
     // See the comments of the corresponding method in `PageAllocator`.
@@ -196,20 +574,47 @@ impl PageAllocatorInner {
     where
         I: IntoIterator<Item = (PageIndex, &'a Page)>,
     {
-        let pages: Vec<_> = page_delta
+        self.try_serialize_page_delta(page_delta).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // See the comments of the corresponding method in `PageAllocator`.
+    pub fn try_serialize_page_delta<'a, I>(
+        &'a self,
+        page_delta: I,
+    ) -> Result<PageDeltaSerialization, PageAllocatorIoError>
+    where
+        I: IntoIterator<Item = (PageIndex, &'a Page)>,
+    {
+        let mut guard = self.0.lock().unwrap();
+        let core = get_or_create_core(&mut guard)?;
+        let pages = page_delta
             .into_iter()
-            .map(|(page_index, page)| MmapPageSerialization {
-                page_index,
-                file_offset: page.0.offset,
-                validation: page.0.validation,
+            .map(|(page_index, page)| {
+                let is_foreign = page
+                    .0
+                    .page_allocator
+                    .as_ref()
+                    .map_or(false, |owner| !std::ptr::eq(Arc::as_ptr(owner), self));
+                // A deduplicated page of another page allocator is serialized
+                // as its copy in the backing file of this page allocator.
+                let page = if is_foreign {
+                    core.materialize_page(&page.0)?
+                } else {
+                    &page.0
+                };
+                Ok(MmapPageSerialization {
+                    page_index,
+                    file_offset: page.offset,
+                    validation: page.validation,
+                })
             })
-            .collect();
-        let mut guard = self.0.lock().unwrap();
-        let core = guard.get_or_insert_with(MmapBasedPageAllocatorCore::new);
-        PageDeltaSerialization {
-            file_len: core.file_len,
+            .collect::<Result<Vec<_>, _>>()?;
+        let last_file = core.files.len() - 1;
+        Ok(PageDeltaSerialization {
+            file_index: last_file,
+            file_len: core.files[last_file].len,
             pages,
-        }
+        })
     }
 
     // See the comments of the corresponding method in `PageAllocator`.
@@ -217,39 +622,71 @@ impl PageAllocatorInner {
         page_allocator: &Arc<PageAllocatorInner>,
         page_delta: PageDeltaSerialization,
     ) -> Vec<(PageIndex, Page)> {
+        Self::try_deserialize_page_delta(page_allocator, page_delta)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // See the comments of the corresponding method in `PageAllocator`.
+    pub fn try_deserialize_page_delta(
+        page_allocator: &Arc<PageAllocatorInner>,
+        page_delta: PageDeltaSerialization,
+    ) -> Result<Vec<(PageIndex, Page)>, PageAllocatorIoError> {
         let mut guard = page_allocator.0.lock().unwrap();
         let core = guard.as_mut().unwrap();
-        core.grow_for_deserialization(page_delta.file_len);
+        core.grow_for_deserialization(page_delta.file_index, page_delta.file_len)?;
         core.deserialized_pages += page_delta.pages.len();
         // Deserialized pages are considered as allocated for the purposes of the metric.
         ALLOCATED_PAGES.inc_by(page_delta.pages.len());
-        // File offsets of all pages are smaller than `file_len`, which means
-        // that the precondition of `deserialize_page()` is fulfilled after
-        // the call to `grow_for_deserialization(file_len)`.
-        page_delta
+        // File offsets of all pages are in the backing files up to `file_index`
+        // and are smaller than `file_len` in the last one, which means that the
+        // precondition of `deserialize_page()` is fulfilled after the call to
+        // `grow_for_deserialization(file_index, file_len)`.
+        Ok(page_delta
             .pages
             .into_iter()
             .map(|ser| {
                 let page = core.deserialize_page(&ser, page_allocator);
                 (ser.page_index, Page(Arc::new(page)))
             })
-            .collect()
+            .collect())
+    }
+}
+
+// Returns the core of the page allocator, creating it on the first use.
+fn get_or_create_core(
+    core: &mut Option<MmapBasedPageAllocatorCore>,
+) -> Result<&mut MmapBasedPageAllocatorCore, PageAllocatorIoError> {
+    if core.is_none() {
+        *core = Some(MmapBasedPageAllocatorCore::new()?);
     }
+    Ok(core.as_mut().unwrap())
 }
 
 impl PageAllocatorInner {
+    // See the comments of the corresponding method in `PageAllocator`.
+    pub fn reclaim_dropped_pages(&self) {
+        let dropped_pages = {
+            let mut guard = self.0.lock().unwrap();
+            match guard.as_mut() {
+                Some(core) => std::mem::take(&mut core.dropped_pages),
+                None => return,
+            }
+        };
+        free_pages(dropped_pages);
+    }
+
     fn new() -> Self {
         Self(Mutex::new(None))
     }
 
     fn open(
         id: PageAllocatorId,
-        file_descriptor: FileDescriptor,
+        file_descriptors: Vec<FileDescriptor>,
         backing_file_owner: BackingFileOwner,
     ) -> Self {
         Self(Mutex::new(Some(MmapBasedPageAllocatorCore::open(
             id,
-            file_descriptor,
+            file_descriptors,
             backing_file_owner,
         ))))
     }
@@ -315,6 +752,30 @@ impl AllocationArea {
         self.start == self.end
     }
 
+    fn num_pages(&self) -> usize {
+        (self.end as usize - self.start as usize) / PAGE_SIZE
+    }
+
+    // Splits off the region of at most the given number of pages from the
+    // beginning of the allocation area. The region is contiguous in memory
+    // and in the backing file.
+    fn split_off_front(&mut self, num_pages: usize) -> AllocationArea {
+        let size = num_pages.min(self.num_pages()) * PAGE_SIZE;
+        let region = AllocationArea {
+            start: self.start,
+            end: self.start.wrapping_add(size),
+            offset: self.offset,
+        };
+        self.start = region.end;
+        self.offset += size as FileOffset;
+        region
+    }
+
+    // Returns the start addresses of the pages in the allocation area.
+    fn page_ptrs(&self) -> impl Iterator<Item = PagePtr> + '_ {
+        (0..self.num_pages()).map(move |i| PagePtr(self.start.wrapping_add(i * PAGE_SIZE)))
+    }
+
     // SAFETY: The caller must ensure that `self.start` and `self.end`
     // are backed a valid mutable memory.
     unsafe fn allocate_page(
@@ -331,6 +792,7 @@ impl AllocationArea {
             offset,
             page_allocator: page_allocator.map(Arc::clone),
             validation: PageValidation::default(),
+            quarantined: AtomicBool::new(false),
         }
     }
 }
@@ -344,6 +806,14 @@ impl AllocationArea {
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PageAllocatorId(usize);
 
+impl PageAllocatorId {
+    // Returns the id with the given value in the baseline serialization
+    // format, see the `versioned` module.
+    pub(super) fn from_serialized(id: u64) -> Self {
+        Self(id as usize)
+    }
+}
+
 impl Default for PageAllocatorId {
     fn default() -> Self {
         // Each active canister creates a few page allocators per a checkpoint
@@ -363,6 +833,15 @@ enum BackingFileOwner {
     AnotherAllocator,
 }
 
+/// A backing file of the page allocator.
+#[derive(Debug)]
+struct BackingFile {
+    // The descriptor of the file.
+    fd: RawFd,
+    // The length of the file.
+    len: FileOffset,
+}
+
 /// The actual allocator implementation. It starts with an empty file, an
 /// emty set of memory-mapped `Chunk`s, and an empty allocation area.
 /// Allocation has two paths: slow and fast.
@@ -374,8 +853,16 @@ enum BackingFileOwner {
 /// to the number of already allocated pages to ensure that the number of
 /// expensive `mmap` operations remains low - O(log(allocated_pages)).
 ///
+/// If growing the file would exceed the maximum file size, then the slow path
+/// creates a new backing file and grows that one instead. File offsets of
+/// pages and chunks are offsets within all backing files as described in
+/// `BACKING_FILE_STRIDE`.
+///
 /// The fast path simply increments the `start` pointer in the allocation area.
-/// It is expected that almost all pages take the fast path.
+/// It is expected that almost all pages take the fast path. A batch of pages
+/// is reserved at once by splitting off a region of the allocation area, so
+/// the cost of the critical section doesn't depend on the size of the batch
+/// unless the slow path is taken.
 #[derive(Debug)]
 struct MmapBasedPageAllocatorCore {
     // The unique id of the page allocator.
@@ -386,16 +873,31 @@ struct MmapBasedPageAllocatorCore {
     allocated_pages: usize,
     // The number of deserialized pages.
     deserialized_pages: usize,
-    // The descriptor of the backing file.
-    file_descriptor: RawFd,
-    // The length of the backing file.
-    file_len: FileOffset,
+    // The backing files. There is always at least one and new pages are
+    // allocated in the last one.
+    files: Vec<BackingFile>,
+    // The size of a backing file after which a new backing file is created.
+    max_file_size: FileOffset,
     // The memory-mapped chunks. We need to remember them so that we can unmap them on drop.
     chunks: Vec<Chunk>,
     // Pages that are not longer used.
     dropped_pages: Vec<PagePtr>,
     // The owner of the backing file.
     backing_file_owner: BackingFileOwner,
+    // Whether the chunks are advised to be backed by transparent huge pages.
+    transparent_huge_pages: bool,
+    // Whether allocation reuses existing pages with the same contents.
+    deduplicate: bool,
+    // Whether the validation information of allocated pages includes a
+    // checksum of their contents.
+    page_checksums: bool,
+    // The copies of the deduplicated pages of other page allocators that were
+    // serialized by this page allocator, by the address of the original page.
+    // The weak reference ensures that the address is not reused while the
+    // entry exists.
+    materialized_pages: HashMap<usize, (Weak<PageInner>, PageInner)>,
+    // The operations on the backing files.
+    io: Arc<dyn BackingFileIo>,
 }
 
 impl Drop for MmapBasedPageAllocatorCore {
@@ -406,67 +908,158 @@ impl Drop for MmapBasedPageAllocatorCore {
             unsafe { munmap(ptr, chunk.size) }.unwrap_or_else(|err| {
                 panic!(
                     "MmapPageAllocator failed to munmap {} bytes at address {:?} for memory file #{}: {}",
-                    chunk.size, chunk.ptr, self.file_descriptor, err
+                    chunk.size, chunk.ptr, self.files[backing_file_index(chunk.offset)].fd, err
+                )
+            });
+        }
+        for file in self.files.iter() {
+            // SAFETY: the file descriptor is valid. We need `cvt_r` to handle `EINTR`.
+            cvt_r(|| unsafe { close(file.fd) }).unwrap_or_else(|err| {
+                panic!(
+                    "MmapPageAllocator failed to close the memory file #{}: {}",
+                    file.fd, err
                 )
             });
         }
-        // SAFETY: the file descriptor is valid. We need `cvt_r` to handle `EINTR`.
-        cvt_r(|| unsafe { close(self.file_descriptor) }).unwrap_or_else(|err| {
-            panic!(
-                "MmapPageAllocator failed to close the memory file #{}: {}",
-                self.file_descriptor, err
-            )
-        });
         ALLOCATED_PAGES.dec_by(self.allocated_pages);
         // Deserialized pages are considered as allocated for the purposes of the metric.
         ALLOCATED_PAGES.dec_by(self.deserialized_pages);
+        MMAP_CHUNKS.dec_by(self.chunks.len());
+        if self.backing_file_owner == BackingFileOwner::CurrentAllocator {
+            BACKING_FILE_BYTES.dec_by(self.files.iter().map(|file| file.len as usize).sum());
+        }
     }
 }
 
 impl MmapBasedPageAllocatorCore {
-    fn new() -> Self {
-        let fd = create_backing_file();
-        Self::open(
+    fn new() -> Result<Self, PageAllocatorIoError> {
+        let fd = SystemIo.create_file().map_err(operation_failed(IoOperation::CreateFile, None))?;
+        Ok(Self::open(
             PageAllocatorId::default(),
-            FileDescriptor { fd },
+            vec![FileDescriptor { fd }],
             BackingFileOwner::CurrentAllocator,
-        )
+        ))
     }
 
     fn open(
         id: PageAllocatorId,
-        file_descriptor: FileDescriptor,
+        file_descriptors: Vec<FileDescriptor>,
         backing_file_owner: BackingFileOwner,
     ) -> Self {
-        // SAFETY: The file descriptor is valid.
-        let file_len = unsafe { get_file_length(file_descriptor.fd) };
+        assert!(
+            !file_descriptors.is_empty(),
+            "MmapPageAllocator requires at least one backing file"
+        );
+        let io: Arc<dyn BackingFileIo> = Arc::new(SystemIo);
+        let files = file_descriptors
+            .into_iter()
+            .map(|file_descriptor| BackingFile {
+                fd: file_descriptor.fd,
+                len: file_length(io.as_ref(), file_descriptor.fd)
+                    .unwrap_or_else(|err| panic!("{}", err)),
+            })
+            .collect();
+        // Only the owner of the backing files can share its pages with other
+        // page allocators.
+        let deduplicate = PAGE_DEDUPLICATION.load(Ordering::Relaxed)
+            && backing_file_owner == BackingFileOwner::CurrentAllocator;
         Self {
             id,
             allocation_area: Default::default(),
             allocated_pages: 0,
             deserialized_pages: 0,
-            file_descriptor: file_descriptor.fd,
-            file_len,
+            files,
+            max_file_size: MAX_BACKING_FILE_SIZE.load(Ordering::Relaxed) as FileOffset,
             chunks: vec![],
             dropped_pages: vec![],
             backing_file_owner,
+            transparent_huge_pages: TRANSPARENT_HUGE_PAGES.load(Ordering::Relaxed),
+            deduplicate,
+            page_checksums: PAGE_CHECKSUMS.load(Ordering::Relaxed),
+            materialized_pages: HashMap::new(),
+            io,
         }
     }
 
-    fn allocate_page(&mut self, page_allocator: &Arc<PageAllocatorInner>) -> PageInner {
-        if self.allocation_area.is_empty() {
-            // Slow path of allocation.
-            self.allocation_area = self.new_allocation_area();
-            assert!(!self.allocation_area.is_empty());
+    // Returns the copy of the given page of another page allocator in the
+    // backing file of this page allocator, copying the page if needed.
+    // The copies of the pages that were dropped in the meantime are freed.
+    fn materialize_page(
+        &mut self,
+        page: &Arc<PageInner>,
+    ) -> Result<&PageInner, PageAllocatorIoError> {
+        let key = Arc::as_ptr(page) as usize;
+        if !self.materialized_pages.contains_key(&key) {
+            let mut dropped = vec![];
+            self.materialized_pages.retain(|_, (original, copy)| {
+                let live = original.strong_count() > 0;
+                if !live {
+                    dropped.push(copy.ptr);
+                }
+                live
+            });
+            self.dropped_pages.extend(dropped);
+
+            if self.allocation_area.is_empty() {
+                self.allocation_area = self.new_allocation_area()?;
+            }
+            ALLOCATED_PAGES.inc_by(1);
+            self.allocated_pages += 1;
+            // SAFETY: the allocation area is backed by the most recently
+            // allocated `Chunk` and is not empty. The copy is not tracked by
+            // the page allocator and is freed when its entry is removed.
+            let mut copy = unsafe { self.allocation_area.allocate_page(None) };
+            copy.copy_from_slice(0, page.contents(), self.page_checksums);
+            self.materialized_pages.insert(key, (Arc::downgrade(page), copy));
         }
-        let page_allocator = match self.backing_file_owner {
-            BackingFileOwner::CurrentAllocator => Some(page_allocator),
-            BackingFileOwner::AnotherAllocator => None,
-        };
-        // Fast path of allocation.
-        // SAFETY: the allocation area is backed by the most recently
-        // allocated `Chunk`. We also know that it is not empty.
-        unsafe { self.allocation_area.allocate_page(page_allocator) }
+        Ok(&self.materialized_pages[&key].1)
+    }
+
+    // Takes ownership of the given backing files that follow the already known
+    // ones, i.e. the files that were created by the owner of the page allocator
+    // after this page allocator was deserialized.
+    fn adopt_backing_files(&mut self, file_descriptors: Vec<FileDescriptor>) {
+        for file_descriptor in file_descriptors.into_iter().skip(self.files.len()) {
+            self.files.push(BackingFile {
+                fd: file_descriptor.fd,
+                len: file_length(self.io.as_ref(), file_descriptor.fd)
+                    .unwrap_or_else(|err| panic!("{}", err)),
+            });
+        }
+    }
+
+    // Reserves the given number of pages for a batch allocation and returns
+    // them as contiguous regions, usually one or two. The pages are created
+    // from the regions by the caller.
+    // On failure the regions reserved so far are freed as dropped pages.
+    fn reserve_pages(
+        &mut self,
+        num_pages: usize,
+    ) -> Result<Vec<AllocationArea>, PageAllocatorIoError> {
+        let mut regions = vec![];
+        let mut remaining = num_pages;
+        while remaining > 0 {
+            if self.allocation_area.is_empty() {
+                // Slow path of allocation.
+                match self.new_allocation_area() {
+                    Ok(allocation_area) => self.allocation_area = allocation_area,
+                    Err(err) => {
+                        if self.backing_file_owner == BackingFileOwner::CurrentAllocator {
+                            for region in regions.iter() {
+                                self.dropped_pages.extend(region.page_ptrs());
+                            }
+                        }
+                        return Err(err);
+                    }
+                }
+                assert!(!self.allocation_area.is_empty());
+            }
+            // Fast path of allocation.
+            let region = self.allocation_area.split_off_front(remaining);
+            remaining -= region.num_pages();
+            regions.push(region);
+        }
+        Ok(regions)
     }
 
     // Returns the number of pages that should be memory-mapped in the slow path of
@@ -475,118 +1068,159 @@ impl MmapBasedPageAllocatorCore {
         const MIN_CHUNK_SIZE_IN_PAGES: usize = 4;
         // Grow the chunk size proportionally to the already allocated pages.
         // The proportion is 1 to 1.
-        self.allocated_pages.max(MIN_CHUNK_SIZE_IN_PAGES)
+        let pages = self.allocated_pages.max(MIN_CHUNK_SIZE_IN_PAGES);
+        let pages = if self.transparent_huge_pages {
+            // Keep the chunks and their file offsets aligned to huge pages,
+            // otherwise the kernel cannot map them with huge pages.
+            const HUGE_PAGE_SIZE_IN_PAGES: usize = HUGE_PAGE_SIZE / PAGE_SIZE;
+            (pages + HUGE_PAGE_SIZE_IN_PAGES - 1) / HUGE_PAGE_SIZE_IN_PAGES
+                * HUGE_PAGE_SIZE_IN_PAGES
+        } else {
+            pages
+        };
+        // A chunk never spans multiple backing files.
+        pages.min(self.max_file_size as usize / PAGE_SIZE)
     }
 
     // The implementation of the slow path of allocation.
-    fn new_allocation_area(&mut self) -> AllocationArea {
+    // On failure the backing files remain consistent with the page allocator,
+    // so that a later allocation can succeed.
+    fn new_allocation_area(&mut self) -> Result<AllocationArea, PageAllocatorIoError> {
         let mmap_pages = self.get_amortized_chunk_size_in_pages();
         let mmap_size = mmap_pages * PAGE_SIZE;
-        let mmap_file_offset = self.file_len;
 
-        // SAFETY: The file descriptor is valid.
-        let file_len = unsafe { get_file_length(self.file_descriptor) };
+        let last_file = self.files.last().unwrap();
+        if self.backing_file_owner == BackingFileOwner::CurrentAllocator
+            && last_file.len > 0
+            && last_file.len + mmap_size as FileOffset > self.max_file_size
+        {
+            // Continue in a new backing file instead of growing the last one
+            // beyond the maximum size.
+            let fd = self
+                .io
+                .create_file()
+                .map_err(operation_failed(IoOperation::CreateFile, None))?;
+            self.files.push(BackingFile { fd, len: 0 });
+        }
+        let file_index = self.files.len() - 1;
+        let file = &mut self.files[file_index];
+        let mmap_file_offset = file.len;
+
+        let file_len = file_length(self.io.as_ref(), file.fd)?;
 
         // Allocation is the only operation that modifies the file size.
         // Ensure that the file size did not change since the last allocation.
-        assert_eq!(file_len, self.file_len);
+        if file_len != file.len {
+            return Err(PageAllocatorIoError::UnexpectedFileLength {
+                fd: file.fd,
+                expected: file.len,
+                actual: file_len,
+            });
+        }
 
-        self.file_len += mmap_size as i64;
-        // SAFETY: The file descriptor is valid.  We need `cvt_r` to handle `EINTR`.
-        cvt_r(|| unsafe { truncate_file(self.file_descriptor, self.file_len) }).unwrap_or_else(
-            |err| {
-                panic!(
-                    "MmapPageAllocator failed to grow the memory file #{} to {} bytes: {}",
-                    self.file_descriptor, self.file_len, err
-                )
-            },
+        let new_file_len = file.len + mmap_size as FileOffset;
+        assert!(
+            new_file_len <= BACKING_FILE_STRIDE,
+            "MmapPageAllocator cannot grow the memory file #{} beyond {} bytes",
+            file.fd,
+            BACKING_FILE_STRIDE
         );
+        self.io
+            .set_file_length(file.fd, new_file_len)
+            .map_err(operation_failed(IoOperation::GrowFile, Some(file.fd)))?;
+        // If memory-mapping fails below, the new range of the file stays
+        // unused and the next allocation continues after it.
+        file.len = new_file_len;
+        if self.backing_file_owner == BackingFileOwner::CurrentAllocator {
+            BACKING_FILE_BYTES.inc_by(mmap_size);
+        }
 
-        // SAFETY: The parameters are valid.
-        let mmap_ptr = unsafe {
-            mmap(
-                std::ptr::null_mut(),
-                mmap_size,
-                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
-                MapFlags::MAP_SHARED,
-                self.file_descriptor,
-                mmap_file_offset,
-            )
-        }
-        .unwrap_or_else(|err| {
-            panic!(
-                "MmapPageAllocator failed to mmap {} bytes to memory file #{} \
-                 at offset {} while allocating a new memory block: {}",
-                mmap_size, self.file_descriptor, mmap_file_offset, err,
-            )
-        }) as *mut u8;
+        // SAFETY: The file descriptor is valid.
+        let mmap_ptr = unsafe { self.io.mmap(mmap_size, file.fd, mmap_file_offset) }
+            .map_err(operation_failed(IoOperation::Mmap, Some(file.fd)))?;
+        if self.transparent_huge_pages {
+            // SAFETY: The range was just memory-mapped.
+            unsafe { madvise_hugepage(mmap_ptr, mmap_size) };
+        }
+        let offset = page_offset(file_index, mmap_file_offset);
         self.chunks.push(Chunk {
             ptr: mmap_ptr,
             size: mmap_size,
-            offset: mmap_file_offset,
+            offset,
         });
+        MMAP_CHUNKS.inc_by(1);
 
         let start = mmap_ptr;
         // SAFETY: We memory-mapped exactly `mmap_size` bytes, so `end` points one byte
         // after the last byte of the chunk.
         let end = unsafe { mmap_ptr.add(mmap_size) };
-        AllocationArea {
-            start,
-            end,
-            offset: mmap_file_offset,
-        }
+        Ok(AllocationArea { start, end, offset })
     }
 
-    // Ensures that that last chunk of the file up to the given length is
-    // memory-mapped to allow deserialization of pages.
-    fn grow_for_deserialization(&mut self, file_len: FileOffset) {
-        if file_len == self.file_len {
-            return;
+    // Ensures that that last chunk of the backing file with the given index up
+    // to the given length is memory-mapped to allow deserialization of pages.
+    fn grow_for_deserialization(
+        &mut self,
+        file_index: usize,
+        file_len: FileOffset,
+    ) -> Result<(), PageAllocatorIoError> {
+        assert!(
+            file_index < self.files.len(),
+            "MmapPageAllocator doesn't know the memory file with index {}: it has {} files",
+            file_index,
+            self.files.len()
+        );
+        let file = &mut self.files[file_index];
+        if file_len == file.len {
+            return Ok(());
         }
-        if file_len < self.file_len {
+        let actual_file_len = file_length(self.io.as_ref(), file.fd)?;
+        if file_len < file.len {
             // This may happen if another thread already called `grow_for_deserialization`
             // while this thread was waiting for the lock. In that case the actual file
             // length is the same or is larger than the saved file length.
-            let actual_file_len = unsafe { get_file_length(self.file_descriptor) };
-            assert!(
-                actual_file_len >= self.file_len,
-                "The page allocator file was truncated: actual file_len = {}, new file_len = {}, old file_len = {}",
-                actual_file_len,
-                file_len,
-                self.file_len
-            );
-            return;
+            if actual_file_len < file.len {
+                return Err(PageAllocatorIoError::UnexpectedFileLength {
+                    fd: file.fd,
+                    expected: file.len,
+                    actual: actual_file_len,
+                });
+            }
+            return Ok(());
         }
-        let mmap_size = (file_len - self.file_len) as usize;
-        let mmap_file_offset = self.file_len;
-        self.file_len = file_len;
+        // Accessing a memory-mapped range beyond the end of the file raises
+        // `SIGBUS`, so the file must be at least as long as the serialized length.
+        if actual_file_len < file_len {
+            return Err(PageAllocatorIoError::UnexpectedFileLength {
+                fd: file.fd,
+                expected: file_len,
+                actual: actual_file_len,
+            });
+        }
+        let mmap_size = (file_len - file.len) as usize;
+        let mmap_file_offset = file.len;
 
         // The mapping is read/write because freeing of pages uses `madvise()` with
         // `MADV_REMOVE`, which requires writable mapping.
-        // SAFETY: The parameters are valid.
-        let mmap_ptr = unsafe {
-            mmap(
-                std::ptr::null_mut(),
-                mmap_size,
-                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
-                MapFlags::MAP_SHARED,
-                self.file_descriptor,
-                mmap_file_offset,
-            )
-        }
-        .unwrap_or_else(|err| {
-            panic!(
-                "MmapPageAllocator failed to mmap {} bytes to memory file #{} \
-                         at offset {} for deserialization: {}",
-                mmap_size, self.file_descriptor, mmap_file_offset, err,
-            )
-        }) as *mut u8;
+        // SAFETY: The file descriptor is valid.
+        let mmap_ptr = unsafe { self.io.mmap(mmap_size, file.fd, mmap_file_offset) }
+            .map_err(operation_failed(IoOperation::Mmap, Some(file.fd)))?;
+        file.len = file_len;
+        if self.transparent_huge_pages {
+            // SAFETY: The range was just memory-mapped.
+            unsafe { madvise_hugepage(mmap_ptr, mmap_size) };
+        }
 
         self.chunks.push(Chunk {
             ptr: mmap_ptr,
             size: mmap_size,
-            offset: mmap_file_offset,
+            offset: page_offset(file_index, mmap_file_offset),
         });
+        MMAP_CHUNKS.inc_by(1);
+        if self.backing_file_owner == BackingFileOwner::CurrentAllocator {
+            BACKING_FILE_BYTES.inc_by(mmap_size);
+        }
+        Ok(())
     }
 
     // Returns a page that starts at the given file offset.
@@ -620,22 +1254,35 @@ impl MmapBasedPageAllocatorCore {
                 // `chunk.ptr + chunk.size` is valid. The page is fully contained in that
                 // address range.
                 let page_start = unsafe { chunk.ptr.add((file_offset - chunk.offset) as usize) };
-                return PageInner {
+                let page = PageInner {
                     ptr: PagePtr(page_start),
                     offset: file_offset,
                     page_allocator: page_allocator.map(Arc::clone),
                     validation: serialized_page.validation,
+                    quarantined: AtomicBool::new(false),
                 };
+                // SAFETY: The page is memory-mapped as shown above.
+                unsafe { page.verify_checksum() };
+                return page;
             }
         }
         // Unreachable based on the precondition.
         unreachable!(
-            "Couldn't deserialize a page at offset {}. Current file length {}.",
-            file_offset, self.file_len
+            "Couldn't deserialize a page at offset {}. Current length of backing file {}: {}.",
+            file_offset,
+            backing_file_index(file_offset),
+            self.files
+                .get(backing_file_index(file_offset))
+                .map_or(0, |file| file.len)
         );
     }
 }
 
+// Returns the length of the given backing file.
+fn file_length(io: &dyn BackingFileIo, fd: RawFd) -> Result<FileOffset, PageAllocatorIoError> {
+    io.file_length(fd).map_err(operation_failed(IoOperation::GetFileLength, Some(fd)))
+}
+
 // Free the memory of given range and punch a hole in the backing file.
 // Preconditions:
 // - the range is mapped as shared and writable.
@@ -651,7 +1298,17 @@ unsafe fn madvise_remove(start_ptr: *mut u8, end_ptr: *mut u8) {
     #[cfg(not(target_os = "linux"))]
     let advise = MmapAdvise::MADV_DONTNEED;
     // SAFETY: the range is mapped as shared and writable by precondition.
-    madvise(ptr, size as usize, advise).unwrap_or_else(|err| {
+    let result = match madvise(ptr, size as usize, advise) {
+        // The file system of the backing file may not support punching holes,
+        // e.g. for the temporary file used on WSL. In that case at least drop
+        // the pages from the page tables of this process so that the kernel
+        // can reclaim them.
+        Err(nix::errno::Errno::EOPNOTSUPP) if advise != MmapAdvise::MADV_DONTNEED => {
+            madvise(ptr, size as usize, MmapAdvise::MADV_DONTNEED)
+        }
+        result => result,
+    };
+    result.unwrap_or_else(|err| {
         panic!(
             "Failed to madvise a page range {:?}..{:?}:
         {}",
@@ -660,119 +1317,96 @@ unsafe fn madvise_remove(start_ptr: *mut u8, end_ptr: *mut u8) {
     });
 }
 
+// Advises the kernel to read ahead the given range. Failures are ignored
+// because the advice is only a hint.
+// Precondition: the range is memory-mapped and not empty.
+unsafe fn madvise_willneed(start_ptr: *mut u8, end_ptr: *mut u8) {
+    let size = end_ptr.offset_from(start_ptr);
+    assert!(size > 0);
+    let _ = madvise(
+        start_ptr as *mut c_void,
+        size as usize,
+        MmapAdvise::MADV_WILLNEED,
+    );
+}
+
+// Advises the kernel to back the given range with transparent huge pages.
+// Failures are ignored because the advice is only an optimization, e.g. the
+// kernel may be built without support for transparent huge pages.
+// Precondition: the range is memory-mapped.
+#[cfg(target_os = "linux")]
+unsafe fn madvise_hugepage(ptr: *mut u8, size: usize) {
+    let _ = madvise(ptr as *mut c_void, size, MmapAdvise::MADV_HUGEPAGE);
+}
+
+#[cfg(not(target_os = "linux"))]
+unsafe fn madvise_hugepage(_ptr: *mut u8, _size: usize) {}
+
 // Frees the memory used by the given pages.
 // Precondition:
 // - each page is mapped as shared and writable.
-fn free_pages(mut pages: Vec<PagePtr>) {
+fn free_pages(pages: Vec<PagePtr>) {
+    if pages.is_empty() {
+        return;
+    }
+
+    FREED_PAGES.inc_by(pages.len());
+
+    for_each_contiguous_range(pages, |start_ptr, end_ptr| {
+        // SAFETY: the range consists of pages that mapped as shared and writable.
+        unsafe { madvise_remove(start_ptr, end_ptr) }
+    });
+}
+
+/// Advises the kernel that the given pages are about to be accessed, so that
+/// their backing memory is read ahead without blocking the caller.
+pub fn prefetch_pages<'a, I>(pages: I)
+where
+    I: IntoIterator<Item = &'a PageInner>,
+{
+    let pages: Vec<PagePtr> = pages.into_iter().map(|page| page.ptr).collect();
+    for_each_contiguous_range(pages, |start_ptr, end_ptr| {
+        // SAFETY: the range consists of pages that are alive and thus mapped.
+        unsafe { madvise_willneed(start_ptr, end_ptr) }
+    });
+}
+
+// Calls `f` with the start and end of each maximal range of contiguous pages
+// among the given pages.
+fn for_each_contiguous_range<F>(mut pages: Vec<PagePtr>, mut f: F)
+where
+    F: FnMut(*mut u8, *mut u8),
+{
     if pages.is_empty() {
         return;
     }
 
-    // Sort the pages to find contiguous page ranges.
+    // Sort the pages to find contiguous page ranges. Deduplicated pages may
+    // occur multiple times.
     pages.sort_unstable();
+    pages.dedup();
 
     // The start and end of the current contiguous page range.
     let mut start_ptr = pages[0].0;
     // SAFETY: the page is valid.
     let mut end_ptr = unsafe { start_ptr.add(PAGE_SIZE) };
 
-    for page_ptr in pages.into_iter() {
+    for page_ptr in pages.into_iter().skip(1) {
         if page_ptr.0 == end_ptr {
             // Extend the current page range.
             // SAFETY: the page is valid.
             end_ptr = unsafe { end_ptr.add(PAGE_SIZE) };
         } else {
-            // Free the current page range and a start a new one.
-            // SAFETY: the range consists of pages that mapped as shared and writable.
-            unsafe { madvise_remove(start_ptr, end_ptr) }
+            // Finish the current page range and a start a new one.
+            f(start_ptr, end_ptr);
             start_ptr = page_ptr.0;
             // SAFETY: the page is valid.
             end_ptr = unsafe { start_ptr.add(PAGE_SIZE) };
         }
     }
 
-    // Free the last page range.
-    // SAFETY: the range consists of pages that mapped as shared and writable.
-    unsafe { madvise_remove(start_ptr, end_ptr) }
-}
-
-// A platform-specific function that creates the backing file of the page allocator.
-// On Linux it uses `memfd_create` to create an in-memory file.
-// On MacOS and WSL it uses an ordinary temporary file.
-#[cfg(target_os = "linux")]
-fn create_backing_file() -> RawFd {
-    if *ic_sys::IS_WSL {
-        return create_backing_file_portable();
-    }
-
-    match nix::sys::memfd::memfd_create(
-        &std::ffi::CString::default(),
-        nix::sys::memfd::MemFdCreateFlag::empty(),
-    ) {
-        Ok(fd) => fd,
-        Err(err) => {
-            panic!(
-                "MmapPageAllocatorCore failed to create the backing file {}",
-                err
-            )
-        }
-    }
-}
-
-#[cfg(not(target_os = "linux"))]
-fn create_backing_file() -> RawFd {
-    create_backing_file_portable()
-}
-
-fn create_backing_file_portable() -> RawFd {
-    use std::os::unix::io::IntoRawFd;
-    match tempfile::tempfile() {
-        Ok(file) => file.into_raw_fd(),
-        Err(err) => {
-            panic!(
-                "MmapPageAllocatorCore failed to create the backing file {}",
-                err
-            )
-        }
-    }
-}
-
-// A platform-specific function to truncate a file.
-// On Linux it uses `ftruncate64()`.
-// On MacOS it uses `ftruncate()` that accepts 64-bit offset.
-#[cfg(target_os = "linux")]
-unsafe fn truncate_file(fd: RawFd, offset: FileOffset) -> c_int {
-    libc::ftruncate64(fd, offset)
-}
-#[cfg(not(target_os = "linux"))]
-unsafe fn truncate_file(fd: RawFd, offset: FileOffset) -> c_int {
-    libc::ftruncate(fd, offset)
-}
-
-// A platform-specific function to get the length of a file.
-// On Linux it uses `fstat64()`.
-// On MacOS it uses `fstat()` that returns 64-bit `st_size`.
-#[cfg(target_os = "linux")]
-unsafe fn get_file_length(fd: RawFd) -> FileOffset {
-    let mut stat = std::mem::MaybeUninit::<libc::stat64>::uninit();
-    cvt(libc::fstat64(fd, stat.as_mut_ptr())).unwrap_or_else(|err| {
-        panic!(
-            "MmapPageAllocator failed get the length of the file #{}: {}",
-            fd, err
-        )
-    });
-    stat.assume_init().st_size
-}
-#[cfg(not(target_os = "linux"))]
-unsafe fn get_file_length(fd: RawFd) -> FileOffset {
-    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
-    cvt(libc::fstat(fd, stat.as_mut_ptr())).unwrap_or_else(|err| {
-        panic!(
-            "MmapPageAllocator failed get the length of the file #{}: {}",
-            fd, err
-        )
-    });
-    stat.assume_init().st_size
+    // Finish the last page range.
+    f(start_ptr, end_ptr);
 }
 
 #[cfg(test)]
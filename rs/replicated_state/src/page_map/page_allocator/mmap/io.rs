@@ -0,0 +1,353 @@
+//! The operations of the page allocator on its backing files. They go through
+//! the `BackingFileIo` trait, so that tests can inject I/O failures and check
+//! that they are propagated as `PageAllocatorIoError`s.
+
+use super::{BACKING_FILE_DIRECTORY, BACKING_FILE_DIRECTORY_FALLBACKS};
+use crate::page_map::FileOffset;
+use cvt::{cvt, cvt_r};
+use libc::c_void;
+use nix::errno::Errno;
+use nix::sys::mman::{mmap, MapFlags, ProtFlags};
+use std::fmt;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// An operation of the page allocator on a backing file.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum IoOperation {
+    CreateFile,
+    GrowFile,
+    GetFileLength,
+    Mmap,
+}
+
+impl fmt::Display for IoOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IoOperation::CreateFile => write!(f, "create the memory file"),
+            IoOperation::GrowFile => write!(f, "grow the memory file"),
+            IoOperation::GetFileLength => write!(f, "get the length of the memory file"),
+            IoOperation::Mmap => write!(f, "mmap the memory file"),
+        }
+    }
+}
+
+/// An error of the page allocator while operating on its backing files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PageAllocatorIoError {
+    /// The operation on the given backing file failed with the given error.
+    /// The file descriptor is `None` if the file doesn't exist yet.
+    OperationFailed {
+        operation: IoOperation,
+        fd: Option<RawFd>,
+        errno: Errno,
+    },
+    /// The backing file has a different length than the page allocator
+    /// expects, e.g. it is shorter than the pages sent by another process.
+    UnexpectedFileLength {
+        fd: RawFd,
+        expected: FileOffset,
+        actual: FileOffset,
+    },
+}
+
+impl fmt::Display for PageAllocatorIoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PageAllocatorIoError::OperationFailed {
+                operation,
+                fd: Some(fd),
+                errno,
+            } => write!(
+                f,
+                "MmapPageAllocator failed to {} #{}: {}",
+                operation, fd, errno
+            ),
+            PageAllocatorIoError::OperationFailed {
+                operation,
+                fd: None,
+                errno,
+            } => write!(f, "MmapPageAllocator failed to {}: {}", operation, errno),
+            PageAllocatorIoError::UnexpectedFileLength {
+                fd,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "MmapPageAllocator expected the memory file #{} to have {} bytes, \
+                 but it has {} bytes",
+                fd, expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PageAllocatorIoError {}
+
+// Returns a function that converts the error of the given operation on the
+// given backing file to `PageAllocatorIoError`.
+pub(super) fn operation_failed(
+    operation: IoOperation,
+    fd: Option<RawFd>,
+) -> impl FnOnce(Errno) -> PageAllocatorIoError {
+    move |errno| PageAllocatorIoError::OperationFailed {
+        operation,
+        fd,
+        errno,
+    }
+}
+
+/// The kind of storage of the backing files of the page allocators.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackingFileBackend {
+    /// An in-memory file created with `memfd_create`.
+    Memfd,
+    /// An ordinary temporary file in the default temporary directory, e.g. on
+    /// MacOS and WSL.
+    TempFile,
+    /// A temporary file in the directory set with
+    /// `set_backing_file_directory()`, e.g. on tmpfs or a dedicated disk.
+    Directory,
+}
+
+impl BackingFileBackend {
+    pub const ALL: [BackingFileBackend; 3] = [
+        BackingFileBackend::Memfd,
+        BackingFileBackend::TempFile,
+        BackingFileBackend::Directory,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackingFileBackend::Memfd => "memfd",
+            BackingFileBackend::TempFile => "temp_file",
+            BackingFileBackend::Directory => "directory",
+        }
+    }
+}
+
+// The backend of the most recently created backing file, encoded as its
+// position in `BackingFileBackend::ALL` plus one, or zero if no backing file
+// was created yet.
+static LAST_BACKING_FILE_BACKEND: AtomicU8 = AtomicU8::new(0);
+
+/// Returns the backend of the most recently created backing file, if any.
+pub(super) fn last_backing_file_backend() -> Option<BackingFileBackend> {
+    match LAST_BACKING_FILE_BACKEND.load(Ordering::Relaxed) {
+        0 => None,
+        i => Some(BackingFileBackend::ALL[i as usize - 1]),
+    }
+}
+
+fn record_backing_file_backend(backend: BackingFileBackend) {
+    let i = BackingFileBackend::ALL.iter().position(|b| *b == backend).unwrap();
+    LAST_BACKING_FILE_BACKEND.store(i as u8 + 1, Ordering::Relaxed);
+}
+
+/// The operations of the page allocator on its backing files.
+pub(super) trait BackingFileIo: fmt::Debug + Send + Sync {
+    /// Creates a new empty backing file.
+    fn create_file(&self) -> Result<RawFd, Errno>;
+
+    /// Sets the length of the given backing file.
+    fn set_file_length(&self, fd: RawFd, len: FileOffset) -> Result<(), Errno>;
+
+    /// Returns the length of the given backing file.
+    fn file_length(&self, fd: RawFd) -> Result<FileOffset, Errno>;
+
+    /// Maps the given range of the given backing file as shared and writable.
+    ///
+    /// # Safety
+    /// The file descriptor must be valid.
+    unsafe fn mmap(&self, size: usize, fd: RawFd, offset: FileOffset) -> Result<*mut u8, Errno>;
+}
+
+/// The implementation of `BackingFileIo` using the system calls.
+#[derive(Debug, Default)]
+pub(super) struct SystemIo;
+
+impl BackingFileIo for SystemIo {
+    fn create_file(&self) -> Result<RawFd, Errno> {
+        let (fd, backend) = create_backing_file()?;
+        record_backing_file_backend(backend);
+        Ok(fd)
+    }
+
+    fn set_file_length(&self, fd: RawFd, len: FileOffset) -> Result<(), Errno> {
+        // SAFETY: Truncating a file has no memory safety requirements. We
+        // need `cvt_r` to handle `EINTR`.
+        cvt_r(|| unsafe { truncate_file(fd, len) })
+            .map(|_| ())
+            .map_err(errno_from_io_error)
+    }
+
+    fn file_length(&self, fd: RawFd) -> Result<FileOffset, Errno> {
+        // SAFETY: `fstat` writes to the given buffer only.
+        unsafe { get_file_length(fd) }
+    }
+
+    unsafe fn mmap(&self, size: usize, fd: RawFd, offset: FileOffset) -> Result<*mut u8, Errno> {
+        mmap(
+            std::ptr::null_mut(),
+            size,
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+            MapFlags::MAP_SHARED,
+            fd,
+            offset,
+        )
+        .map(|ptr: *mut c_void| ptr as *mut u8)
+    }
+}
+
+fn errno_from_io_error(err: std::io::Error) -> Errno {
+    err.raw_os_error().map_or(Errno::EIO, Errno::from_i32)
+}
+
+// Creates the backing file of the page allocator in the configured directory
+// if there is one. Falls back to the default location if that fails, e.g.
+// because the directory doesn't exist or is not writable.
+fn create_backing_file() -> Result<(RawFd, BackingFileBackend), Errno> {
+    let directory = BACKING_FILE_DIRECTORY.lock().unwrap().clone();
+    if let Some(directory) = directory {
+        match tempfile::tempfile_in(directory) {
+            Ok(file) => return Ok((file.into_raw_fd(), BackingFileBackend::Directory)),
+            Err(_) => BACKING_FILE_DIRECTORY_FALLBACKS.inc_by(1),
+        }
+    }
+    create_default_backing_file()
+}
+
+// A platform-specific function that creates the backing file of the page allocator.
+// On Linux it uses `memfd_create` to create an in-memory file.
+// On MacOS and WSL it uses an ordinary temporary file.
+#[cfg(target_os = "linux")]
+fn create_default_backing_file() -> Result<(RawFd, BackingFileBackend), Errno> {
+    if *ic_sys::IS_WSL {
+        return create_backing_file_portable();
+    }
+
+    match nix::sys::memfd::memfd_create(
+        &std::ffi::CString::default(),
+        nix::sys::memfd::MemFdCreateFlag::empty(),
+    ) {
+        Ok(fd) => Ok((fd, BackingFileBackend::Memfd)),
+        Err(err) => {
+            // Fall back to an ordinary temporary file if `memfd_create` is
+            // not supported.
+            if err == Errno::ENOSYS {
+                create_backing_file_portable()
+            } else {
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn create_default_backing_file() -> Result<(RawFd, BackingFileBackend), Errno> {
+    create_backing_file_portable()
+}
+
+fn create_backing_file_portable() -> Result<(RawFd, BackingFileBackend), Errno> {
+    tempfile::tempfile()
+        .map(|file| (file.into_raw_fd(), BackingFileBackend::TempFile))
+        .map_err(errno_from_io_error)
+}
+
+// A platform-specific function to truncate a file.
+// On Linux it uses `ftruncate64()`.
+// On MacOS it uses `ftruncate()` that accepts 64-bit offset.
+#[cfg(target_os = "linux")]
+unsafe fn truncate_file(fd: RawFd, offset: FileOffset) -> libc::c_int {
+    libc::ftruncate64(fd, offset)
+}
+#[cfg(not(target_os = "linux"))]
+unsafe fn truncate_file(fd: RawFd, offset: FileOffset) -> libc::c_int {
+    libc::ftruncate(fd, offset)
+}
+
+// A platform-specific function to get the length of a file.
+// On Linux it uses `fstat64()`.
+// On MacOS it uses `fstat()` that returns 64-bit `st_size`.
+#[cfg(target_os = "linux")]
+unsafe fn get_file_length(fd: RawFd) -> Result<FileOffset, Errno> {
+    let mut stat = std::mem::MaybeUninit::<libc::stat64>::uninit();
+    cvt(libc::fstat64(fd, stat.as_mut_ptr())).map_err(errno_from_io_error)?;
+    Ok(stat.assume_init().st_size)
+}
+#[cfg(not(target_os = "linux"))]
+unsafe fn get_file_length(fd: RawFd) -> Result<FileOffset, Errno> {
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    cvt(libc::fstat(fd, stat.as_mut_ptr())).map_err(errno_from_io_error)?;
+    Ok(stat.assume_init().st_size)
+}
+
+/// A fault injected into an operation of `FaultInjectingIo`.
+#[cfg(test)]
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Fault {
+    /// The operation fails with the given error.
+    Error(Errno),
+    /// The file length is reported shorter by the given number of bytes, as
+    /// if a read of the file came up short. Only applies to `GetFileLength`.
+    ShortLength(FileOffset),
+}
+
+/// An implementation of `BackingFileIo` for tests that performs the system
+/// calls unless a fault is injected into the operation.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(super) struct FaultInjectingIo {
+    faults: std::sync::Mutex<std::collections::HashMap<IoOperation, Fault>>,
+}
+
+#[cfg(test)]
+impl FaultInjectingIo {
+    /// Makes all subsequent calls of the given operation fail with the given
+    /// fault until the faults are cleared.
+    pub(super) fn inject(&self, operation: IoOperation, fault: Fault) {
+        self.faults.lock().unwrap().insert(operation, fault);
+    }
+
+    pub(super) fn clear(&self) {
+        self.faults.lock().unwrap().clear();
+    }
+
+    fn fault(&self, operation: IoOperation) -> Option<Fault> {
+        self.faults.lock().unwrap().get(&operation).copied()
+    }
+
+    fn check(&self, operation: IoOperation) -> Result<(), Errno> {
+        match self.fault(operation) {
+            Some(Fault::Error(errno)) => Err(errno),
+            Some(Fault::ShortLength(_)) | None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl BackingFileIo for FaultInjectingIo {
+    fn create_file(&self) -> Result<RawFd, Errno> {
+        self.check(IoOperation::CreateFile)?;
+        SystemIo.create_file()
+    }
+
+    fn set_file_length(&self, fd: RawFd, len: FileOffset) -> Result<(), Errno> {
+        self.check(IoOperation::GrowFile)?;
+        SystemIo.set_file_length(fd, len)
+    }
+
+    fn file_length(&self, fd: RawFd) -> Result<FileOffset, Errno> {
+        self.check(IoOperation::GetFileLength)?;
+        let len = SystemIo.file_length(fd)?;
+        match self.fault(IoOperation::GetFileLength) {
+            Some(Fault::ShortLength(missing)) => Ok((len - missing).max(0)),
+            _ => Ok(len),
+        }
+    }
+
+    unsafe fn mmap(&self, size: usize, fd: RawFd, offset: FileOffset) -> Result<*mut u8, Errno> {
+        self.check(IoOperation::Mmap)?;
+        SystemIo.mmap(size, fd, offset)
+    }
+}
@@ -1,7 +1,30 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use crate::page_map::page_allocator::PageAllocatorInner;
+use super::io::{Fault, FaultInjectingIo};
+use super::{
+    set_backing_file_directory, IoOperation, MmapBasedPageAllocatorCore, PageAllocatorIoError,
+    BACKING_FILE_STRIDE, HUGE_PAGE_SIZE, MIN_PAGES_TO_FREE,
+};
+use crate::page_map::page_allocator::{
+    page_allocator_stats, PageAllocatorInner, PageAllocatorSerialization,
+};
+use crate::page_map::{FileDescriptor, FileOffset};
 use ic_sys::{PageIndex, PAGE_SIZE};
+use nix::errno::Errno;
+use nix::unistd::dup;
+
+fn duplicate_file_descriptors(
+    page_allocator: PageAllocatorSerialization,
+) -> PageAllocatorSerialization {
+    let dup_fd = |fd: &FileDescriptor| FileDescriptor {
+        fd: dup(fd.fd).unwrap(),
+    };
+    PageAllocatorSerialization {
+        id: page_allocator.id,
+        fd: dup_fd(&page_allocator.fd),
+        additional_fds: page_allocator.additional_fds.iter().map(dup_fd).collect(),
+    }
+}
 
 #[test]
 fn test_page_validation_zero_page() {
@@ -57,3 +80,391 @@ fn test_page_validation_non_zero_middle_byte() {
     );
     assert_eq!(pages[0].1 .0.validation.non_zero_word_value, 42 * 256);
 }
+
+#[test]
+fn test_huge_page_backed_allocator_grows_in_huge_pages() {
+    let mut core = MmapBasedPageAllocatorCore::new().unwrap();
+    core.transparent_huge_pages = true;
+    let page_allocator = Arc::new(PageAllocatorInner(Mutex::new(Some(core))));
+    let contents = [42u8; PAGE_SIZE];
+    let pages = PageAllocatorInner::allocate(&page_allocator, &[(PageIndex::new(0), &contents)]);
+    assert_eq!(pages[0].1 .0.contents(), &contents);
+    let file_len = page_allocator.0.lock().unwrap().as_ref().unwrap().files[0].len;
+    assert_eq!(file_len, HUGE_PAGE_SIZE as i64);
+}
+
+// Returns a page allocator that records the checksums of its pages.
+fn checksumming_page_allocator() -> Arc<PageAllocatorInner> {
+    let mut core = MmapBasedPageAllocatorCore::new().unwrap();
+    core.page_checksums = true;
+    Arc::new(PageAllocatorInner(Mutex::new(Some(core))))
+}
+
+#[test]
+fn test_page_checksum_survives_serialization() {
+    let page_allocator = checksumming_page_allocator();
+    let contents = [42u8; PAGE_SIZE];
+    let pages = PageAllocatorInner::allocate(&page_allocator, &[(PageIndex::new(0), &contents)]);
+    let delta = page_allocator.serialize_page_delta(pages.iter().map(|(i, p)| (*i, p)));
+    assert!(delta.pages[0].validation.checksum.is_some());
+    let deserialized = PageAllocatorInner::deserialize_page_delta(&page_allocator, delta);
+    assert_eq!(deserialized[0].1 .0.contents(), &contents);
+}
+
+#[test]
+#[should_panic(expected = "does not match its checksum")]
+fn test_page_checksum_mismatch_is_detected() {
+    let page_allocator = checksumming_page_allocator();
+    let contents = [42u8; PAGE_SIZE];
+    let mut pages =
+        PageAllocatorInner::allocate(&page_allocator, &[(PageIndex::new(0), &contents)]);
+    let page = Arc::get_mut(&mut pages[0].1 .0).unwrap();
+    page.validation.checksum = page.validation.checksum.map(|checksum| checksum ^ 1);
+    unsafe { page.verify_checksum() };
+}
+
+#[test]
+fn test_revalidation_quarantines_corrupted_pages() {
+    let page_allocator = Arc::new(PageAllocatorInner::default());
+    let contents = [42u8; PAGE_SIZE];
+    let pages = PageAllocatorInner::allocate(
+        &page_allocator,
+        &[(PageIndex::new(0), &contents), (PageIndex::new(1), &contents)],
+    );
+    assert!(pages[0].1 .0.revalidate());
+    assert!(pages[1].1 .0.revalidate());
+    let stats = page_allocator_stats();
+    // Simulate a sandbox process that overwrote the page in the backing file.
+    unsafe { std::ptr::write_bytes(pages[1].1 .0.ptr.0, 0, PAGE_SIZE) };
+    assert!(pages[0].1 .0.revalidate());
+    assert!(!pages[1].1 .0.revalidate());
+    assert!(!pages[0].1 .0.is_quarantined());
+    assert!(pages[1].1 .0.is_quarantined());
+    assert!(page_allocator_stats().quarantined_pages > stats.quarantined_pages);
+    assert!(page_allocator_stats().validation_failures > stats.validation_failures);
+}
+
+#[test]
+fn test_revalidation_counts_checksum_mismatches() {
+    let page_allocator = checksumming_page_allocator();
+    let contents = [42u8; PAGE_SIZE];
+    let pages = PageAllocatorInner::allocate(&page_allocator, &[(PageIndex::new(0), &contents)]);
+    let checksum_mismatches = page_allocator_stats().checksum_mismatches;
+    // Corrupt a byte other than the validated word, so that only the checksum
+    // catches it.
+    unsafe { *pages[0].1 .0.ptr.0.add(PAGE_SIZE - 1) = 0 };
+    assert!(!pages[0].1 .0.revalidate());
+    assert!(pages[0].1 .0.is_quarantined());
+    assert!(page_allocator_stats().checksum_mismatches > checksum_mismatches);
+}
+
+#[test]
+#[should_panic(expected = "is quarantined")]
+fn test_quarantined_page_cannot_be_accessed() {
+    let page_allocator = Arc::new(PageAllocatorInner::default());
+    let contents = [42u8; PAGE_SIZE];
+    let pages = PageAllocatorInner::allocate(&page_allocator, &[(PageIndex::new(0), &contents)]);
+    let page = &pages[0].1 .0;
+    unsafe { std::ptr::write_bytes(page.ptr.0, 0, PAGE_SIZE) };
+    assert!(!page.revalidate());
+    page.contents();
+}
+
+#[test]
+fn test_allocator_continues_in_new_backing_file_at_max_size() {
+    let mut core = MmapBasedPageAllocatorCore::new().unwrap();
+    core.max_file_size = 8 * PAGE_SIZE as FileOffset;
+    let page_allocator = Arc::new(PageAllocatorInner(Mutex::new(Some(core))));
+    let contents: Vec<[u8; PAGE_SIZE]> = (0..20).map(|i| [i as u8 + 1; PAGE_SIZE]).collect();
+    let mut pages = vec![];
+    for (i, contents) in contents.iter().enumerate() {
+        pages.extend(PageAllocatorInner::allocate(
+            &page_allocator,
+            &[(PageIndex::new(i as u64), contents)],
+        ));
+    }
+    for ((_, page), contents) in pages.iter().zip(contents.iter()) {
+        assert_eq!(page.0.contents(), contents);
+    }
+    let files = {
+        let guard = page_allocator.0.lock().unwrap();
+        let core = guard.as_ref().unwrap();
+        for file in core.files.iter() {
+            assert!(file.len <= core.max_file_size);
+        }
+        core.files.len()
+    };
+    assert!(files > 1);
+    assert_eq!(page_allocator.serialize().additional_fds.len(), files - 1);
+}
+
+#[test]
+fn test_batch_allocation_spans_allocation_areas() {
+    let mut core = MmapBasedPageAllocatorCore::new().unwrap();
+    core.transparent_huge_pages = false;
+    core.deduplicate = false;
+    let page_allocator = Arc::new(PageAllocatorInner(Mutex::new(Some(core))));
+    let contents: Vec<[u8; PAGE_SIZE]> = (0..13).map(|i| [i as u8 + 1; PAGE_SIZE]).collect();
+    let pages: Vec<_> = contents
+        .iter()
+        .enumerate()
+        .map(|(i, contents)| (PageIndex::new(i as u64), contents))
+        .collect();
+    // The first batch leaves one page of the first chunk, so the second batch
+    // takes that page and continues in a new chunk.
+    let mut allocated = PageAllocatorInner::allocate(&page_allocator, &pages[..3]);
+    allocated.extend(PageAllocatorInner::allocate(&page_allocator, &pages[3..]));
+    assert_eq!(page_allocator.0.lock().unwrap().as_ref().unwrap().chunks.len(), 2);
+    for (i, ((page_index, page), contents)) in allocated.iter().zip(contents.iter()).enumerate() {
+        assert_eq!(*page_index, PageIndex::new(i as u64));
+        assert_eq!(page.0.offset, (i * PAGE_SIZE) as FileOffset);
+        assert_eq!(page.0.contents(), contents);
+    }
+}
+
+#[test]
+fn test_page_delta_in_additional_backing_file_survives_serialization() {
+    let mut core = MmapBasedPageAllocatorCore::new().unwrap();
+    core.max_file_size = 4 * PAGE_SIZE as FileOffset;
+    let replica = Arc::new(PageAllocatorInner(Mutex::new(Some(core))));
+    let contents = [1u8; PAGE_SIZE];
+    // The first allocation fills the first backing file, so the second one
+    // starts a new backing file.
+    let first: Vec<_> = (0..4).map(|i| (PageIndex::new(i), &contents)).collect();
+    let _first = PageAllocatorInner::allocate(&replica, &first);
+    let _second = PageAllocatorInner::allocate(&replica, &[(PageIndex::new(4), &contents)]);
+
+    let serialized = duplicate_file_descriptors(replica.serialize());
+    assert_eq!(serialized.additional_fds.len(), 1);
+    let sandbox = PageAllocatorInner::deserialize(serialized);
+
+    let contents = [2u8; PAGE_SIZE];
+    let pages = PageAllocatorInner::allocate(&sandbox, &[(PageIndex::new(5), &contents)]);
+    assert!(pages[0].1 .0.offset >= BACKING_FILE_STRIDE);
+    let delta = sandbox.serialize_page_delta(pages.iter().map(|(i, p)| (*i, p)));
+    let deserialized = PageAllocatorInner::deserialize_page_delta(&replica, delta);
+    assert_eq!(deserialized[0].0, PageIndex::new(5));
+    assert_eq!(deserialized[0].1 .0.contents(), &contents);
+}
+
+// Returns how many of the given pages are resident in memory. Unlike the
+// resident set size of the process, this is not affected by other tests.
+#[cfg(target_os = "linux")]
+fn resident_pages(page_ptrs: &[*mut u8]) -> usize {
+    page_ptrs
+        .iter()
+        .filter(|ptr| {
+            let mut residency = [0u8; 1];
+            // SAFETY: The pointer is page-aligned and points into a mapping of the
+            // page allocator, which stays mapped while the page allocator is alive.
+            let result = unsafe {
+                libc::mincore(
+                    **ptr as *mut libc::c_void,
+                    PAGE_SIZE,
+                    residency.as_mut_ptr(),
+                )
+            };
+            assert_eq!(result, 0, "mincore failed: {}", Errno::last());
+            residency[0] & 1 != 0
+        })
+        .count()
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_reclaiming_dropped_pages_reduces_resident_memory() {
+    // Stay below the threshold for freeing dropped pages automatically.
+    const PAGES: usize = MIN_PAGES_TO_FREE - 1;
+    let page_allocator = Arc::new(PageAllocatorInner::default());
+    let contents = [13u8; PAGE_SIZE];
+    let pages: Vec<_> = (0..PAGES)
+        .map(|i| (PageIndex::new(i as u64), &contents))
+        .collect();
+    let pages = PageAllocatorInner::allocate(&page_allocator, &pages);
+    let page_ptrs: Vec<_> = pages.iter().map(|(_, page)| page.0.ptr.0).collect();
+    assert_eq!(resident_pages(&page_ptrs), PAGES);
+    drop(pages);
+    // Dropping alone does not free the pages below the threshold.
+    assert_eq!(resident_pages(&page_ptrs), PAGES);
+    page_allocator.reclaim_dropped_pages();
+    assert_eq!(resident_pages(&page_ptrs), 0);
+}
+
+fn deduplicating_page_allocator() -> Arc<PageAllocatorInner> {
+    let mut core = MmapBasedPageAllocatorCore::new().unwrap();
+    core.deduplicate = true;
+    Arc::new(PageAllocatorInner(Mutex::new(Some(core))))
+}
+
+#[test]
+fn test_deduplicating_allocators_share_identical_pages() {
+    let page_allocator_1 = deduplicating_page_allocator();
+    let page_allocator_2 = deduplicating_page_allocator();
+    let contents = [71u8; PAGE_SIZE];
+    let other_contents = [72u8; PAGE_SIZE];
+    let pages_1 =
+        PageAllocatorInner::allocate(&page_allocator_1, &[(PageIndex::new(0), &contents)]);
+    let pages_2 = PageAllocatorInner::allocate(
+        &page_allocator_2,
+        &[
+            (PageIndex::new(5), &contents),
+            (PageIndex::new(6), &other_contents),
+        ],
+    );
+    assert_eq!(pages_2[0].0, PageIndex::new(5));
+    assert_eq!(pages_2[1].0, PageIndex::new(6));
+    assert!(pages_1[0].1.ptr_eq(&pages_2[0].1));
+    assert!(!pages_1[0].1.ptr_eq(&pages_2[1].1));
+    assert_eq!(pages_2[1].1 .0.contents(), &other_contents);
+}
+
+#[test]
+fn test_deduplicated_page_of_another_allocator_survives_serialization() {
+    let page_allocator_1 = deduplicating_page_allocator();
+    let page_allocator_2 = deduplicating_page_allocator();
+    let contents = [73u8; PAGE_SIZE];
+    let _pages_1 =
+        PageAllocatorInner::allocate(&page_allocator_1, &[(PageIndex::new(0), &contents)]);
+    let pages_2 =
+        PageAllocatorInner::allocate(&page_allocator_2, &[(PageIndex::new(1), &contents)]);
+
+    let sandbox =
+        PageAllocatorInner::deserialize(duplicate_file_descriptors(page_allocator_2.serialize()));
+    let delta = page_allocator_2.serialize_page_delta(pages_2.iter().map(|(i, p)| (*i, p)));
+    // The copy is made only once.
+    let delta_again = page_allocator_2.serialize_page_delta(pages_2.iter().map(|(i, p)| (*i, p)));
+    assert_eq!(delta.pages[0].file_offset, delta_again.pages[0].file_offset);
+
+    let deserialized = PageAllocatorInner::deserialize_page_delta(&sandbox, delta);
+    assert_eq!(deserialized[0].0, PageIndex::new(1));
+    assert_eq!(deserialized[0].1 .0.contents(), &contents);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_backing_files_are_created_in_configured_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    set_backing_file_directory(Some(dir.path().to_path_buf()));
+    let page_allocator = PageAllocatorInner::default();
+    let fd = page_allocator.serialize().fd.fd;
+    let path = std::fs::read_link(format!("/proc/self/fd/{}", fd)).unwrap();
+
+    // A directory that doesn't exist falls back to the default location.
+    let fallbacks = page_allocator_stats().backing_file_directory_fallbacks;
+    set_backing_file_directory(Some(dir.path().join("missing")));
+    let fallback_page_allocator = PageAllocatorInner::default();
+    fallback_page_allocator.serialize();
+    set_backing_file_directory(None);
+
+    assert!(
+        path.starts_with(dir.path().canonicalize().unwrap()),
+        "The backing file {} is not in the configured directory",
+        path.display()
+    );
+    assert!(page_allocator_stats().backing_file_directory_fallbacks > fallbacks);
+}
+
+// Returns a page allocator and the I/O layer of its backing files that allows
+// injecting faults.
+fn fault_injecting_page_allocator() -> (Arc<PageAllocatorInner>, Arc<FaultInjectingIo>) {
+    let io = Arc::new(FaultInjectingIo::default());
+    let mut core = MmapBasedPageAllocatorCore::new().unwrap();
+    core.io = Arc::clone(&io) as _;
+    (Arc::new(PageAllocatorInner(Mutex::new(Some(core)))), io)
+}
+
+// Returns a page allocator in the role of the sandbox process for the given
+// page allocator and the I/O layer of its backing files that allows injecting
+// faults.
+fn fault_injecting_sandbox(
+    replica: &PageAllocatorInner,
+) -> (Arc<PageAllocatorInner>, Arc<FaultInjectingIo>) {
+    let io = Arc::new(FaultInjectingIo::default());
+    let sandbox = PageAllocatorInner::deserialize(duplicate_file_descriptors(replica.serialize()));
+    sandbox.0.lock().unwrap().as_mut().unwrap().io = Arc::clone(&io) as _;
+    (sandbox, io)
+}
+
+#[test]
+fn test_allocation_failure_is_reported_and_recoverable() {
+    let (page_allocator, io) = fault_injecting_page_allocator();
+    let contents = [5u8; PAGE_SIZE];
+    let pages: Vec<_> = (0..4).map(|i| (PageIndex::new(i), &contents)).collect();
+
+    io.inject(IoOperation::GrowFile, Fault::Error(Errno::ENOSPC));
+    let err = PageAllocatorInner::try_allocate(&page_allocator, &pages).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            PageAllocatorIoError::OperationFailed {
+                operation: IoOperation::GrowFile,
+                errno: Errno::ENOSPC,
+                ..
+            }
+        ),
+        "Unexpected error: {}",
+        err
+    );
+    {
+        let guard = page_allocator.0.lock().unwrap();
+        let core = guard.as_ref().unwrap();
+        assert_eq!(core.allocated_pages, 0);
+        assert_eq!(core.files[0].len, 0);
+    }
+
+    io.clear();
+    let pages = PageAllocatorInner::try_allocate(&page_allocator, &pages).unwrap();
+    for (_, page) in pages.iter() {
+        assert_eq!(page.0.contents(), &contents);
+    }
+}
+
+#[test]
+fn test_mmap_failure_during_deserialization_is_reported_and_recoverable() {
+    let replica = Arc::new(PageAllocatorInner::default());
+    let (sandbox, io) = fault_injecting_sandbox(&replica);
+    let contents = [6u8; PAGE_SIZE];
+    let pages = PageAllocatorInner::allocate(&replica, &[(PageIndex::new(0), &contents)]);
+    let delta = replica.serialize_page_delta(pages.iter().map(|(i, p)| (*i, p)));
+
+    io.inject(IoOperation::Mmap, Fault::Error(Errno::EIO));
+    let err = PageAllocatorInner::try_deserialize_page_delta(&sandbox, delta.clone()).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            PageAllocatorIoError::OperationFailed {
+                operation: IoOperation::Mmap,
+                errno: Errno::EIO,
+                ..
+            }
+        ),
+        "Unexpected error: {}",
+        err
+    );
+
+    io.clear();
+    let deserialized = PageAllocatorInner::try_deserialize_page_delta(&sandbox, delta).unwrap();
+    assert_eq!(deserialized[0].1 .0.contents(), &contents);
+}
+
+#[test]
+fn test_deserializing_page_delta_beyond_end_of_file_fails() {
+    let replica = Arc::new(PageAllocatorInner::default());
+    let (sandbox, io) = fault_injecting_sandbox(&replica);
+    let contents = [7u8; PAGE_SIZE];
+    let pages = PageAllocatorInner::allocate(&replica, &[(PageIndex::new(0), &contents)]);
+    let delta = replica.serialize_page_delta(pages.iter().map(|(i, p)| (*i, p)));
+
+    io.inject(IoOperation::GetFileLength, Fault::ShortLength(PAGE_SIZE as FileOffset));
+    let file_len = delta.file_len;
+    let err = PageAllocatorInner::try_deserialize_page_delta(&sandbox, delta).unwrap_err();
+    match err {
+        PageAllocatorIoError::UnexpectedFileLength {
+            expected, actual, ..
+        } => {
+            assert_eq!(expected, file_len);
+            assert_eq!(actual, file_len - PAGE_SIZE as FileOffset);
+        }
+        err => panic!("Unexpected error: {}", err),
+    }
+}
@@ -1,8 +1,11 @@
-use crate::page_map::FileDescriptor;
+use crate::page_map::{FileDescriptor, FileOffset};
 
-use super::{PageAllocator, PageAllocatorSerialization, PageSerialization};
+use super::mmap::PageAllocatorId;
+use super::versioned::{MmapPageSerializationV1, PageValidationV1};
+use super::{PageAllocator, PageAllocatorSerialization, PageDeltaSerialization, PageSerialization};
 use ic_sys::{PageIndex, PAGE_SIZE};
 use nix::unistd::dup;
+use serde::Serialize;
 
 fn duplicate_file_descriptors(
     page_allocator: PageAllocatorSerialization,
@@ -12,6 +15,13 @@ fn duplicate_file_descriptors(
         fd: FileDescriptor {
             fd: dup(page_allocator.fd.fd).unwrap(),
         },
+        additional_fds: page_allocator
+            .additional_fds
+            .iter()
+            .map(|fd| FileDescriptor {
+                fd: dup(fd.fd).unwrap(),
+            })
+            .collect(),
     }
 }
 
@@ -74,3 +84,95 @@ fn test_page_deserialize_twice() {
         deserialized2.serialize().fd.fd
     );
 }
+
+// The page allocator serialization of the previous release.
+#[derive(Serialize)]
+struct BaselinePageAllocatorSerialization {
+    id: PageAllocatorId,
+    fd: FileDescriptor,
+}
+
+// The page delta serialization of the previous release.
+#[derive(Serialize)]
+struct BaselinePageDeltaSerialization {
+    file_len: FileOffset,
+    pages: Vec<MmapPageSerializationV1>,
+}
+
+// Encodes the value followed by a sentinel and decodes them with bincode, the
+// encoding of the messages between the replica and the sandbox processes.
+// The sentinel checks that decoding consumes exactly the encoded value.
+fn encode_and_decode<T, U>(value: &T) -> U
+where
+    T: serde::Serialize,
+    U: serde::de::DeserializeOwned,
+{
+    const SENTINEL: u32 = 0xdead_beef;
+    let bytes = bincode::serialize(&(value, SENTINEL)).unwrap();
+    let (decoded, sentinel): (U, u32) = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(sentinel, SENTINEL);
+    decoded
+}
+
+#[test]
+fn test_page_delta_serialization_round_trip() {
+    let page_allocator: PageAllocator = PageAllocator::default();
+    let contents = [7u8; PAGE_SIZE];
+    let pages = page_allocator.allocate(&[(PageIndex::new(3), &contents)]);
+
+    let delta = page_allocator.serialize_page_delta(pages.iter().map(|(i, p)| (*i, p)));
+    let delta: PageDeltaSerialization = encode_and_decode(&delta);
+    let deserialized = page_allocator.deserialize_page_delta(delta);
+    assert_eq!(deserialized[0].0, PageIndex::new(3));
+    assert_eq!(deserialized[0].1.contents(), &contents);
+}
+
+#[test]
+fn test_baseline_page_delta_serialization_is_decoded() {
+    let page_allocator: PageAllocator = PageAllocator::default();
+    let contents = [7u8; PAGE_SIZE];
+    let pages = page_allocator.allocate(&[(PageIndex::new(3), &contents)]);
+
+    let delta = page_allocator.serialize_page_delta(pages.iter().map(|(i, p)| (*i, p)));
+    let baseline = BaselinePageDeltaSerialization {
+        file_len: delta.file_len,
+        pages: delta
+            .pages
+            .iter()
+            .map(|page| MmapPageSerializationV1 {
+                page_index: page.page_index,
+                file_offset: page.file_offset,
+                validation: PageValidationV1 {
+                    non_zero_word_index: page.validation.non_zero_word_index,
+                    non_zero_word_value: page.validation.non_zero_word_value,
+                },
+            })
+            .collect(),
+    };
+    let delta: PageDeltaSerialization = encode_and_decode(&baseline);
+    assert_eq!(delta.file_index, 0);
+    assert!(delta.pages[0].validation.checksum.is_none());
+    let deserialized = page_allocator.deserialize_page_delta(delta);
+    assert_eq!(deserialized[0].0, PageIndex::new(3));
+    assert_eq!(deserialized[0].1.contents(), &contents);
+}
+
+#[test]
+fn test_baseline_page_allocator_serialization_is_decoded() {
+    let page_allocator: PageAllocator = PageAllocator::default();
+    page_allocator.allocate(&[(PageIndex::new(1), &[1u8; PAGE_SIZE])]);
+    let serialized = page_allocator.serialize();
+
+    let baseline = BaselinePageAllocatorSerialization {
+        id: serialized.id,
+        fd: serialized.fd.clone(),
+    };
+    let decoded: PageAllocatorSerialization = encode_and_decode(&baseline);
+    assert_eq!(decoded.id, serialized.id);
+    assert_eq!(decoded.fd.fd, serialized.fd.fd);
+    assert!(decoded.additional_fds.is_empty());
+
+    let decoded: PageAllocatorSerialization = encode_and_decode(&serialized);
+    assert_eq!(decoded.id, serialized.id);
+    assert_eq!(decoded.fd.fd, serialized.fd.fd);
+}
@@ -0,0 +1,178 @@
+//! Versioned wire format of the page allocator and page delta serializations.
+//!
+//! The serializations are shipped between the replica and the sandbox
+//! processes with bincode, which is not self-describing: a value is decoded
+//! as whatever the decoder expects at its position in the message. That's
+//! why the baseline format, i.e. the plain struct of the previous release, is
+//! kept as it is, and only the current format carries a version marker.
+//!
+//! The current format is a tuple whose first element is a version marker in
+//! place of the first field of the baseline struct. The marker is a value
+//! that this field never takes in the baseline format, so the decoder reads
+//! the first element and decodes the remaining elements of the detected
+//! format. This way a process decodes the serializations of a process of the
+//! previous release during a rolling upgrade.
+//!
+//! A process of the previous release cannot decode the current format, so
+//! the replica and the sandbox processes it spawns must run the same or two
+//! consecutive releases with the newer one on the decoding side.
+//!
+//! To change the format:
+//! 1. Add a new marker and encode the new format with it.
+//! 2. Decode the previous format in addition to the new one.
+//! 3. Remove the format before the previous one.
+
+use super::{
+    FileOffset, MmapPageSerialization, PageAllocatorId, PageAllocatorSerialization,
+    PageDeltaSerialization, PageIndex, PageValidation,
+};
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeTuple, Serializer};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// The first field of the baseline page allocator serialization is the id of
+// the page allocator, a counter starting at zero that never gets this large.
+const PAGE_ALLOCATOR_V2: u64 = u64::MAX;
+
+// The first field of the baseline page delta serialization is the length of
+// the last backing file, which is never negative.
+const PAGE_DELTA_V2: FileOffset = -1;
+
+// The number of elements of the current format of the page allocator
+// serialization: the marker, the id, the first file, and the other files.
+const PAGE_ALLOCATOR_V2_LEN: usize = 4;
+
+// The number of elements of the current format of the page delta
+// serialization: the marker, the file index, the file length, and the pages.
+const PAGE_DELTA_V2_LEN: usize = 4;
+
+/// An mmap-based page in the baseline page delta serialization.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(super) struct MmapPageSerializationV1 {
+    pub(super) page_index: PageIndex,
+    pub(super) file_offset: FileOffset,
+    pub(super) validation: PageValidationV1,
+}
+
+/// The validation information of a page without a checksum in the baseline
+/// page delta serialization.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub(super) struct PageValidationV1 {
+    pub(super) non_zero_word_index: u16,
+    pub(super) non_zero_word_value: u16,
+}
+
+impl From<MmapPageSerializationV1> for MmapPageSerialization {
+    fn from(page: MmapPageSerializationV1) -> Self {
+        Self {
+            page_index: page.page_index,
+            file_offset: page.file_offset,
+            validation: PageValidation {
+                non_zero_word_index: page.validation.non_zero_word_index,
+                non_zero_word_value: page.validation.non_zero_word_value,
+                checksum: None,
+            },
+        }
+    }
+}
+
+// Returns the next element of the serialization or an error if there is none.
+fn next_element<'de, A, T>(seq: &mut A) -> Result<T, A::Error>
+where
+    A: SeqAccess<'de>,
+    T: Deserialize<'de>,
+{
+    seq.next_element()?
+        .ok_or_else(|| de::Error::custom("the serialization is truncated"))
+}
+
+impl Serialize for PageAllocatorSerialization {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(PAGE_ALLOCATOR_V2_LEN)?;
+        tuple.serialize_element(&PAGE_ALLOCATOR_V2)?;
+        tuple.serialize_element(&self.id)?;
+        tuple.serialize_element(&self.fd)?;
+        tuple.serialize_element(&self.additional_fds)?;
+        tuple.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for PageAllocatorSerialization {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PageAllocatorVisitor;
+
+        impl<'de> Visitor<'de> for PageAllocatorVisitor {
+            type Value = PageAllocatorSerialization;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a page allocator serialization")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let first: u64 = next_element(&mut seq)?;
+                if first == PAGE_ALLOCATOR_V2 {
+                    return Ok(PageAllocatorSerialization {
+                        id: next_element(&mut seq)?,
+                        fd: next_element(&mut seq)?,
+                        additional_fds: next_element(&mut seq)?,
+                    });
+                }
+                // The baseline format with a single backing file.
+                Ok(PageAllocatorSerialization {
+                    id: PageAllocatorId::from_serialized(first),
+                    fd: next_element(&mut seq)?,
+                    additional_fds: vec![],
+                })
+            }
+        }
+
+        deserializer.deserialize_tuple(PAGE_ALLOCATOR_V2_LEN, PageAllocatorVisitor)
+    }
+}
+
+impl Serialize for PageDeltaSerialization {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(PAGE_DELTA_V2_LEN)?;
+        tuple.serialize_element(&PAGE_DELTA_V2)?;
+        tuple.serialize_element(&self.file_index)?;
+        tuple.serialize_element(&self.file_len)?;
+        tuple.serialize_element(&self.pages)?;
+        tuple.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for PageDeltaSerialization {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PageDeltaVisitor;
+
+        impl<'de> Visitor<'de> for PageDeltaVisitor {
+            type Value = PageDeltaSerialization;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a page delta serialization")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let first: FileOffset = next_element(&mut seq)?;
+                if first == PAGE_DELTA_V2 {
+                    return Ok(PageDeltaSerialization {
+                        file_index: next_element(&mut seq)?,
+                        file_len: next_element(&mut seq)?,
+                        pages: next_element(&mut seq)?,
+                    });
+                }
+                // The baseline format with a single backing file and pages
+                // without checksums.
+                let pages: Vec<MmapPageSerializationV1> = next_element(&mut seq)?;
+                Ok(PageDeltaSerialization {
+                    file_index: 0,
+                    file_len: first,
+                    pages: pages.into_iter().map(MmapPageSerialization::from).collect(),
+                })
+            }
+        }
+
+        deserializer.deserialize_tuple(PAGE_DELTA_V2_LEN, PageDeltaVisitor)
+    }
+}
@@ -0,0 +1,87 @@
+//! Conversions between byte offsets and indices of pages of a given size.
+//!
+//! A `PageMap` stores its contents in host pages of `PAGE_SIZE` bytes, but the
+//! memories it hosts are made of 64 KiB Wasm pages, and future hosts may use
+//! pages larger than 4 KiB. All index arithmetic goes through `PageSize`
+//! instead of dividing by `PAGE_SIZE` directly. It is done in `u64`, so that
+//! it doesn't overflow for offsets in Wasm64 memories beyond 4 GiB.
+
+#[cfg(test)]
+mod tests;
+
+use crate::canister_state::WASM_PAGE_SIZE_IN_BYTES;
+use ic_sys::{PageIndex, PAGE_SIZE};
+use std::ops::Range;
+
+/// The size of a page in bytes. It is a power of two and a multiple of the
+/// host page size, so every page consists of a whole number of host pages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PageSize(u64);
+
+impl PageSize {
+    /// The size of the host pages that a `PageMap` stores.
+    pub const HOST: PageSize = PageSize(PAGE_SIZE as u64);
+
+    /// The size of Wasm pages.
+    pub const WASM: PageSize = PageSize(WASM_PAGE_SIZE_IN_BYTES as u64);
+
+    /// Returns the page size of the given number of bytes, or `None` if it is
+    /// not a power of two or not a multiple of the host page size.
+    pub const fn new(bytes: u64) -> Option<Self> {
+        if bytes.is_power_of_two() && bytes % PAGE_SIZE as u64 == 0 {
+            Some(Self(bytes))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of bytes in a page.
+    pub const fn bytes(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the number of host pages in a page.
+    pub const fn host_pages_per_page(self) -> u64 {
+        self.0 / PAGE_SIZE as u64
+    }
+
+    /// Returns the index of the page that contains the given byte offset.
+    pub const fn page_index(self, offset: u64) -> u64 {
+        offset / self.0
+    }
+
+    /// Returns the offset of the given byte offset within its page.
+    pub const fn offset_in_page(self, offset: u64) -> u64 {
+        offset % self.0
+    }
+
+    /// Returns the byte offset of the start of the page with the given index,
+    /// or `None` if it doesn't fit into `u64`.
+    pub const fn page_start(self, page_index: u64) -> Option<u64> {
+        page_index.checked_mul(self.0)
+    }
+
+    /// Returns the number of pages needed to hold the given number of bytes.
+    pub const fn pages_for(self, len: u64) -> u64 {
+        len / self.0 + (len % self.0 != 0) as u64
+    }
+
+    /// Returns the indices of the pages touched by an access of `len` bytes
+    /// at the given offset. The range is empty if `len` is zero.
+    pub fn pages_touched(self, offset: u64, len: u64) -> Range<u64> {
+        let first_page = self.page_index(offset);
+        if len == 0 {
+            return first_page..first_page;
+        }
+        let last_page = self.page_index(offset.saturating_add(len - 1));
+        first_page..last_page + 1
+    }
+
+    /// Returns the range of host pages that make up the page with the given
+    /// index.
+    pub fn host_page_range(self, page_index: u64) -> Range<PageIndex> {
+        let host_pages = self.host_pages_per_page();
+        let start = page_index.saturating_mul(host_pages);
+        PageIndex::new(start)..PageIndex::new(start.saturating_add(host_pages))
+    }
+}
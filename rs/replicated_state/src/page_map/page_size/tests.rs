@@ -0,0 +1,91 @@
+use super::*;
+use proptest::prelude::*;
+
+fn arb_page_size() -> impl Strategy<Value = PageSize> {
+    (0..8_u32).prop_map(|shift| PageSize::new((PAGE_SIZE as u64) << shift).unwrap())
+}
+
+#[test]
+fn new_rejects_invalid_page_sizes() {
+    assert_eq!(PageSize::new(PAGE_SIZE as u64), Some(PageSize::HOST));
+    assert_eq!(
+        PageSize::new(WASM_PAGE_SIZE_IN_BYTES as u64),
+        Some(PageSize::WASM)
+    );
+    assert_eq!(PageSize::new(0), None);
+    assert_eq!(PageSize::new(PAGE_SIZE as u64 / 2), None);
+    assert_eq!(PageSize::new(3 * PAGE_SIZE as u64), None);
+}
+
+#[test]
+fn wasm_pages_consist_of_host_pages() {
+    assert_eq!(
+        PageSize::WASM.host_pages_per_page() * PAGE_SIZE as u64,
+        WASM_PAGE_SIZE_IN_BYTES as u64
+    );
+    assert_eq!(PageSize::HOST.host_pages_per_page(), 1);
+}
+
+#[test]
+fn offsets_beyond_4_gib_do_not_overflow() {
+    let offset = 6 << 30;
+    let page_index = PageSize::WASM.page_index(offset);
+    assert_eq!(page_index, 6 << 14);
+    assert_eq!(PageSize::WASM.page_start(page_index), Some(offset));
+    assert_eq!(PageSize::WASM.page_start(u64::MAX), None);
+    assert_eq!(
+        PageSize::HOST.pages_touched(u64::MAX - 1, 10),
+        PageSize::HOST.page_index(u64::MAX)..PageSize::HOST.page_index(u64::MAX) + 1
+    );
+}
+
+proptest! {
+    #[test]
+    fn page_index_and_offset_round_trip(page_size in arb_page_size(), offset in any::<u64>()) {
+        let page_index = page_size.page_index(offset);
+        let page_start = page_size.page_start(page_index).unwrap();
+        prop_assert_eq!(page_start + page_size.offset_in_page(offset), offset);
+        prop_assert!(page_size.offset_in_page(offset) < page_size.bytes());
+    }
+
+    #[test]
+    fn host_arithmetic_matches_page_size(offset in 0..u32::MAX as usize) {
+        prop_assert_eq!(PageSize::HOST.page_index(offset as u64), (offset / PAGE_SIZE) as u64);
+        prop_assert_eq!(
+            PageSize::HOST.offset_in_page(offset as u64),
+            (offset % PAGE_SIZE) as u64
+        );
+    }
+
+    #[test]
+    fn pages_for_covers_length(page_size in arb_page_size(), len in 0..u64::MAX / 2) {
+        let pages = page_size.pages_for(len);
+        prop_assert!(pages * page_size.bytes() >= len);
+        prop_assert!(pages == 0 || (pages - 1) * page_size.bytes() < len);
+    }
+
+    #[test]
+    fn pages_touched_contains_first_and_last_byte(
+        page_size in arb_page_size(),
+        offset in 0..u64::MAX / 2,
+        len in 1..1_u64 << 40,
+    ) {
+        let pages = page_size.pages_touched(offset, len);
+        prop_assert_eq!(pages.start, page_size.page_index(offset));
+        prop_assert_eq!(pages.end - 1, page_size.page_index(offset + len - 1));
+        prop_assert!(page_size.pages_touched(offset, 0).is_empty());
+    }
+
+    #[test]
+    fn host_page_ranges_tile_pages(page_size in arb_page_size(), page_index in 0..1_u64 << 40) {
+        let range = page_size.host_page_range(page_index);
+        let next = page_size.host_page_range(page_index + 1);
+        prop_assert_eq!(range.end, next.start);
+        prop_assert_eq!(
+            range.end.get() - range.start.get(),
+            page_size.host_pages_per_page()
+        );
+        let page_start = page_size.page_start(page_index).unwrap();
+        prop_assert_eq!(range.start.get(), PageSize::HOST.page_index(page_start));
+    }
+}
@@ -1,7 +1,8 @@
 use super::{
     checkpoint::{Checkpoint, MappingSerialization},
     page_allocator::PageAllocatorSerialization,
-    Buffer, FileDescriptor, PageAllocator, PageDelta, PageIndex, PageMap, PageMapSerialization,
+    Buffer, FileDescriptor, OverlayFile, PageAllocator, PageDelta, PageIndex, PageMap,
+    PageMapSerialization, PageSize, PersistenceError,
 };
 use ic_sys::PAGE_SIZE;
 use ic_types::{Height, MAX_STABLE_MEMORY_IN_BYTES};
@@ -38,6 +39,14 @@ fn duplicate_file_descriptors(
         fd: FileDescriptor {
             fd: dup(serialized_page_map.page_allocator.fd.fd).unwrap(),
         },
+        additional_fds: serialized_page_map
+            .page_allocator
+            .additional_fds
+            .iter()
+            .map(|fd| FileDescriptor {
+                fd: dup(fd.fd).unwrap(),
+            })
+            .collect(),
     };
     serialized_page_map
 }
@@ -97,6 +106,19 @@ fn new_delta_wins_on_update() {
     assert_eq!(page_map.get_page(PageIndex::new(1)), &page_2);
 }
 
+#[test]
+fn num_pages_rounds_up_to_whole_pages() {
+    let mut page_map = PageMap::new();
+    assert_eq!(page_map.num_pages(PageSize::WASM), 0);
+
+    let page = [1u8; PAGE_SIZE];
+    let last_host_page = PageSize::WASM.host_page_range(2).start;
+    page_map.update(&[(last_host_page, &page)]);
+
+    assert_eq!(page_map.num_pages(PageSize::HOST), last_host_page.get() + 1);
+    assert_eq!(page_map.num_pages(PageSize::WASM), 3);
+}
+
 #[test]
 fn persisted_map_is_equivalent_to_the_original() {
     let tmp = tempfile::Builder::new()
@@ -146,6 +168,155 @@ fn persisted_map_is_equivalent_to_the_original() {
     assert_eq!(persisted_map, original_map);
 }
 
+#[test]
+fn changed_pages_since_reports_modified_pages() {
+    let ones = [1u8; PAGE_SIZE];
+    let twos = [2u8; PAGE_SIZE];
+    let zeros = [0u8; PAGE_SIZE];
+
+    let mut base = PageMap::new();
+    base.update(&[(PageIndex::new(1), &ones), (PageIndex::new(5), &ones)]);
+    assert!(base.changed_pages_since(&base).is_empty());
+    assert_eq!(
+        base.changed_pages_since(&PageMap::new()),
+        vec![PageIndex::new(1), PageIndex::new(5)]
+    );
+
+    let mut next = base.clone();
+    next.update(&[
+        (PageIndex::new(1), &twos),
+        // Rewriting a page with the same contents is not a change.
+        (PageIndex::new(5), &ones),
+        (PageIndex::new(7), &twos),
+        // Neither is writing zeros to a page that was never written.
+        (PageIndex::new(9), &zeros),
+    ]);
+    let expected = vec![PageIndex::new(1), PageIndex::new(7)];
+    assert_eq!(next.changed_pages_since(&base), expected);
+    assert_eq!(base.changed_pages_since(&next), expected);
+}
+
+#[test]
+fn changed_pages_since_compares_page_maps_with_different_checkpoints() {
+    let tmp = tempfile::Builder::new()
+        .prefix("checkpoints")
+        .tempdir()
+        .unwrap();
+    let heap_file = tmp.path().join("heap");
+
+    let ones = [1u8; PAGE_SIZE];
+    let twos = [2u8; PAGE_SIZE];
+
+    let mut base = PageMap::new();
+    base.update(&[(PageIndex::new(0), &ones), (PageIndex::new(2), &ones)]);
+    base.persist_delta(&heap_file).unwrap();
+
+    let mut checkpointed = PageMap::open(&heap_file, Height::new(0)).unwrap();
+    assert!(checkpointed.changed_pages_since(&base).is_empty());
+
+    checkpointed.update(&[(PageIndex::new(2), &twos), (PageIndex::new(4), &twos)]);
+    assert_eq!(
+        checkpointed.changed_pages_since(&base),
+        vec![PageIndex::new(2), PageIndex::new(4)]
+    );
+}
+
+#[test]
+fn delta_since_returns_pages_modified_after_the_checkpoint() {
+    let tmp = tempfile::Builder::new()
+        .prefix("checkpoints")
+        .tempdir()
+        .unwrap();
+    let heap_file = tmp.path().join("heap");
+
+    let ones = [1u8; PAGE_SIZE];
+    let twos = [2u8; PAGE_SIZE];
+
+    let mut base = PageMap::new();
+    base.update(&[(PageIndex::new(0), &ones), (PageIndex::new(2), &ones)]);
+    base.persist_delta(&heap_file).unwrap();
+
+    let mut page_map = PageMap::open(&heap_file, Height::new(10)).unwrap();
+    assert_eq!(page_map.delta_since(Height::new(10)).unwrap().count(), 0);
+
+    page_map.update(&[(PageIndex::new(4), &twos), (PageIndex::new(2), &twos)]);
+    for height in [Height::new(10), Height::new(11)] {
+        let delta: Vec<_> = page_map.delta_since(height).unwrap().collect();
+        assert_eq!(delta, vec![(PageIndex::new(2), &twos), (PageIndex::new(4), &twos)]);
+    }
+    // The checkpoint doesn't record which of its pages changed after height 9.
+    assert!(page_map.delta_since(Height::new(9)).is_none());
+}
+
+#[test]
+fn read_only_view_matches_the_page_map() {
+    let tmp = tempfile::Builder::new()
+        .prefix("checkpoints")
+        .tempdir()
+        .unwrap();
+    let heap_file = tmp.path().join("heap");
+
+    let ones = [1u8; PAGE_SIZE];
+    let twos = [2u8; PAGE_SIZE];
+    let threes = [3u8; PAGE_SIZE];
+
+    let mut base = PageMap::new();
+    base.update(&[(PageIndex::new(1), &ones), (PageIndex::new(2), &ones)]);
+    base.persist_delta(&heap_file).unwrap();
+
+    let mut page_map = PageMap::open(&heap_file, Height::new(0)).unwrap();
+    page_map.update(&[(PageIndex::new(2), &twos), (PageIndex::new(5), &twos)]);
+
+    let view = page_map.read_only_view().unwrap();
+    assert_eq!(view.num_host_pages(), page_map.num_host_pages());
+    for i in 0..page_map.num_host_pages() + 2 {
+        let page_index = PageIndex::new(i as u64);
+        assert_eq!(view.get_page(page_index), page_map.get_page(page_index));
+    }
+
+    let mut expected = vec![0; 3 * PAGE_SIZE];
+    let mut actual = vec![0; 3 * PAGE_SIZE];
+    for offset in [0, PAGE_SIZE / 2, 2 * PAGE_SIZE + 1, 5 * PAGE_SIZE] {
+        page_map.read_range(offset, &mut expected);
+        view.read_range(offset, &mut actual);
+        assert_eq!(actual, expected);
+    }
+
+    // Subsequent updates of the page map don't affect the view.
+    page_map.update(&[(PageIndex::new(1), &threes), (PageIndex::new(7), &threes)]);
+    assert_eq!(view.get_page(PageIndex::new(1)), &ones);
+    assert_eq!(view.get_page(PageIndex::new(7)), &[0; PAGE_SIZE]);
+}
+
+#[test]
+fn read_only_view_of_an_empty_page_map_is_empty() {
+    let view = PageMap::new().read_only_view().unwrap();
+    assert_eq!(view.num_host_pages(), 0);
+    assert!(view.as_slice().is_empty());
+    assert_eq!(view.get_page(PageIndex::new(0)), &[0; PAGE_SIZE]);
+    let mut buf = vec![1; 10];
+    view.read_range(100, &mut buf);
+    assert_eq!(buf, vec![0; 10]);
+}
+
+#[test]
+fn read_only_view_can_be_shared_by_threads() {
+    let page = [9u8; PAGE_SIZE];
+    let mut page_map = PageMap::new();
+    page_map.update(&[(PageIndex::new(3), &page)]);
+    let view = std::sync::Arc::new(page_map.read_only_view().unwrap());
+
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let view = std::sync::Arc::clone(&view);
+            std::thread::spawn(move || *view.get_page(PageIndex::new(3)))
+        })
+        .collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), page);
+    }
+}
+
 #[test]
 fn can_persist_and_load_an_empty_page_map() {
     let tmp = tempfile::Builder::new()
@@ -190,6 +361,186 @@ fn returns_an_error_if_file_size_is_not_a_multiple_of_page_size() {
     }
 }
 
+#[test]
+fn prefetching_pages_preserves_contents() {
+    let tmp = tempfile::Builder::new()
+        .prefix("checkpoints")
+        .tempdir()
+        .unwrap();
+    let heap_file = tmp.path().join("heap");
+
+    let base_pages: Vec<[u8; PAGE_SIZE]> = (0..8).map(|i| [i as u8 + 1; PAGE_SIZE]).collect();
+    let mut base_map = PageMap::default();
+    base_map.update(
+        &base_pages
+            .iter()
+            .enumerate()
+            .map(|(i, page)| (PageIndex::new(i as u64), page))
+            .collect::<Vec<_>>(),
+    );
+    base_map.persist_delta(&heap_file).unwrap();
+
+    let mut page_map = PageMap::open(&heap_file, Height::new(0)).unwrap();
+    let page_2 = [22u8; PAGE_SIZE];
+    let page_3 = [33u8; PAGE_SIZE];
+    let page_10 = [10u8; PAGE_SIZE];
+    page_map.update(&[
+        (PageIndex::new(2), &page_2),
+        (PageIndex::new(3), &page_3),
+        (PageIndex::new(10), &page_10),
+    ]);
+    let expected: Vec<Vec<u8>> = (0..12)
+        .map(|i| page_map.get_page(PageIndex::new(i)).to_vec())
+        .collect();
+
+    // Ranges that start and end in the checkpoint, in the delta, beyond both
+    // of them, and an empty range.
+    for (start, end) in [(0, 12), (1, 3), (3, 9), (9, 11), (20, 30), (5, 5)] {
+        page_map.prefetch_pages(PageIndex::new(start)..PageIndex::new(end));
+    }
+
+    for (i, contents) in expected.iter().enumerate() {
+        assert_eq!(page_map.get_page(PageIndex::new(i as u64)), &contents[..]);
+    }
+}
+
+#[test]
+fn read_range_matches_page_by_page_reads() {
+    let tmp = tempfile::Builder::new()
+        .prefix("checkpoints")
+        .tempdir()
+        .unwrap();
+    let heap_file = tmp.path().join("heap");
+
+    let base_pages: Vec<[u8; PAGE_SIZE]> = (0..10).map(|i| [i as u8 + 1; PAGE_SIZE]).collect();
+    let mut base_map = PageMap::default();
+    base_map.update(
+        &base_pages
+            .iter()
+            .enumerate()
+            .map(|(i, page)| (PageIndex::new(i as u64), page))
+            .collect::<Vec<_>>(),
+    );
+    base_map.persist_delta(&heap_file).unwrap();
+
+    let mut page_map = PageMap::open(&heap_file, Height::new(0)).unwrap();
+    let page_3 = [33u8; PAGE_SIZE];
+    let page_4 = [44u8; PAGE_SIZE];
+    let page_12 = [12u8; PAGE_SIZE];
+    page_map.update(&[
+        (PageIndex::new(3), &page_3),
+        (PageIndex::new(4), &page_4),
+        (PageIndex::new(12), &page_12),
+    ]);
+
+    let expected: Vec<u8> = (0..15)
+        .flat_map(|i| page_map.get_page(PageIndex::new(i)).to_vec())
+        .collect();
+    for (offset, len) in [
+        (0, 15 * PAGE_SIZE),
+        (5, 2 * PAGE_SIZE),
+        (3 * PAGE_SIZE - 1, PAGE_SIZE + 2),
+        (4 * PAGE_SIZE + 100, 6 * PAGE_SIZE),
+        (9 * PAGE_SIZE + 7, 5 * PAGE_SIZE),
+        (14 * PAGE_SIZE, PAGE_SIZE),
+        (100, 0),
+    ] {
+        let mut dst = vec![0xff; len];
+        page_map.read_range(offset, &mut dst);
+        assert_eq!(dst, &expected[offset..offset + len]);
+    }
+}
+
+#[test]
+fn overlay_file_contains_the_page_delta() {
+    let tmp = tempfile::Builder::new()
+        .prefix("checkpoints")
+        .tempdir()
+        .unwrap();
+    let overlay_file = tmp.path().join("overlay");
+
+    let page_1 = [1u8; PAGE_SIZE];
+    let page_3 = [3u8; PAGE_SIZE];
+    let page_100 = [100u8; PAGE_SIZE];
+    let mut original_map = PageMap::default();
+    original_map.update(&[
+        (PageIndex::new(100), &page_100),
+        (PageIndex::new(1), &page_1),
+        (PageIndex::new(3), &page_3),
+    ]);
+    original_map.persist_delta_to_overlay(&overlay_file).unwrap();
+
+    let overlay = OverlayFile::open(&overlay_file).unwrap();
+    assert_eq!(overlay.num_pages(), 3);
+    assert_eq!(overlay.get_page(PageIndex::new(3)), Some(&page_3));
+    assert_eq!(overlay.get_page(PageIndex::new(2)), None);
+    assert_eq!(
+        overlay.iter().map(|(index, _)| index).collect::<Vec<_>>(),
+        vec![PageIndex::new(1), PageIndex::new(3), PageIndex::new(100)]
+    );
+
+    let mut loaded_map = PageMap::default();
+    loaded_map.apply_overlay(&overlay);
+    assert_equal_page_maps(&original_map, &loaded_map);
+}
+
+#[test]
+fn can_persist_and_load_an_empty_overlay_file() {
+    let tmp = tempfile::Builder::new()
+        .prefix("checkpoints")
+        .tempdir()
+        .unwrap();
+    let overlay_file = tmp.path().join("overlay");
+
+    PageMap::default()
+        .persist_delta_to_overlay(&overlay_file)
+        .unwrap();
+    let overlay = OverlayFile::open(&overlay_file).unwrap();
+    assert_eq!(overlay.num_pages(), 0);
+}
+
+#[test]
+fn corrupted_overlay_files_are_rejected() {
+    let tmp = tempfile::Builder::new()
+        .prefix("checkpoints")
+        .tempdir()
+        .unwrap();
+    let overlay_file = tmp.path().join("overlay");
+
+    let page_1 = [1u8; PAGE_SIZE];
+    let page_2 = [2u8; PAGE_SIZE];
+    let mut page_map = PageMap::default();
+    page_map.update(&[(PageIndex::new(1), &page_1), (PageIndex::new(2), &page_2)]);
+    page_map.persist_delta_to_overlay(&overlay_file).unwrap();
+    let original = std::fs::read(&overlay_file).unwrap();
+
+    let assert_rejected = |contents: &[u8]| {
+        std::fs::write(&overlay_file, contents).unwrap();
+        match OverlayFile::open(&overlay_file) {
+            Err(PersistenceError::InvalidOverlayFile { .. }) => (),
+            Err(err) => panic!("Expected invalid overlay file error, got {:?}", err),
+            Ok(_) => panic!("Expected invalid overlay file error, got a valid overlay file"),
+        }
+    };
+
+    // A flipped bit in the data, index and footer segments.
+    for offset in [PAGE_SIZE + 7, 2 * PAGE_SIZE + 3, original.len() - 1] {
+        let mut corrupted = original.clone();
+        corrupted[offset] ^= 1;
+        assert_rejected(&corrupted);
+    }
+    // A truncated file.
+    assert_rejected(&original[..original.len() - 1]);
+    assert_rejected(&original[..10]);
+    // A file with an extra page.
+    let mut extended = vec![0; PAGE_SIZE];
+    extended.extend_from_slice(&original);
+    assert_rejected(&extended);
+
+    std::fs::write(&overlay_file, &original).unwrap();
+    assert!(OverlayFile::open(&overlay_file).is_ok());
+}
+
 #[test]
 fn can_use_buffer_to_modify_page_map() {
     let page_1 = [1u8; PAGE_SIZE];
@@ -225,6 +576,112 @@ fn can_use_buffer_to_modify_page_map() {
     }
 }
 
+#[test]
+fn discard_range_zeroes_the_range() {
+    let pages: Vec<[u8; PAGE_SIZE]> = (0..6).map(|i| [i as u8 + 1; PAGE_SIZE]).collect();
+    let mut page_map = PageMap::default();
+    page_map.update(
+        &pages
+            .iter()
+            .enumerate()
+            .map(|(i, page)| (PageIndex::new(i as u64), page))
+            .collect::<Vec<_>>(),
+    );
+    let mut expected: Vec<u8> = pages.iter().flatten().cloned().collect();
+
+    let (offset, len) = (PAGE_SIZE + 100, 3 * PAGE_SIZE);
+    let dirty_pages = page_map.discard_range(offset as u64, len as u64);
+    expected[offset..offset + len].fill(0);
+
+    let mut dirty_pages: Vec<_> = dirty_pages.into_iter().map(|i| i.get()).collect();
+    dirty_pages.sort_unstable();
+    assert_eq!(dirty_pages, vec![1, 2, 3, 4]);
+    let mut contents = vec![0xff; expected.len()];
+    page_map.read_range(0, &mut contents);
+    assert_eq!(contents, expected);
+
+    // The whole pages share a single zeroed page.
+    let page_2 = page_map.page_delta.get_page_ref(PageIndex::new(2)).unwrap();
+    let page_3 = page_map.page_delta.get_page_ref(PageIndex::new(3)).unwrap();
+    assert!(page_2.ptr_eq(page_3));
+    assert_eq!(page_map.num_host_pages(), 6);
+}
+
+#[test]
+fn discard_range_skips_zero_pages() {
+    let page_1 = [1u8; PAGE_SIZE];
+    let mut page_map = PageMap::default();
+    page_map.update(&[(PageIndex::new(1), &page_1)]);
+
+    // Page 0 was never written and everything after page 1 is beyond the
+    // end of the page map.
+    let dirty_pages = page_map.discard_range(0, 10 * PAGE_SIZE as u64);
+    assert_eq!(dirty_pages, vec![PageIndex::new(1)]);
+    assert_eq!(page_map.get_page(PageIndex::new(1)), &[0; PAGE_SIZE]);
+    assert!(page_map.discard_range(0, 10 * PAGE_SIZE as u64).is_empty());
+}
+
+#[test]
+fn persisting_discarded_pages_punches_holes() {
+    let tmp = tempfile::Builder::new()
+        .prefix("checkpoints")
+        .tempdir()
+        .unwrap();
+    let heap_file = tmp.path().join("heap");
+
+    let page = [1u8; PAGE_SIZE];
+    let mut base_map = PageMap::default();
+    base_map.update(
+        &(0..8)
+            .map(|i| (PageIndex::new(i), &page))
+            .collect::<Vec<_>>(),
+    );
+    base_map.persist_delta(&heap_file).unwrap();
+
+    // Discard two pages in the middle and the pages at the end of the file.
+    let mut page_map = PageMap::open(&heap_file, Height::new(0)).unwrap();
+    page_map.discard_range(2 * PAGE_SIZE as u64, 2 * PAGE_SIZE as u64);
+    page_map.discard_range(6 * PAGE_SIZE as u64, 2 * PAGE_SIZE as u64);
+    page_map.persist_delta(&heap_file).unwrap();
+
+    assert_eq!(
+        std::fs::metadata(&heap_file).unwrap().len(),
+        8 * PAGE_SIZE as u64
+    );
+    let persisted_map = PageMap::open(&heap_file, Height::new(0)).unwrap();
+    assert_eq!(persisted_map, page_map);
+    assert_eq!(persisted_map.get_page(PageIndex::new(3)), &[0; PAGE_SIZE]);
+    assert_eq!(persisted_map.get_page(PageIndex::new(4)), &page);
+
+    // Zero pages beyond the end of the file extend it.
+    let mut page_map = persisted_map;
+    page_map.update(&[(PageIndex::new(9), &[0; PAGE_SIZE])]);
+    page_map.persist_delta(&heap_file).unwrap();
+    assert_eq!(
+        std::fs::metadata(&heap_file).unwrap().len(),
+        10 * PAGE_SIZE as u64
+    );
+}
+
+#[test]
+fn buffer_discard_zeroes_the_range() {
+    let page_1 = [1u8; PAGE_SIZE];
+    let mut page_map = PageMap::default();
+    page_map.update(&[(PageIndex::new(1), &page_1)]);
+    let mut buf = Buffer::new(page_map);
+    buf.write(&[2u8; 10], 3 * PAGE_SIZE);
+
+    buf.discard(PAGE_SIZE - 10, 3 * PAGE_SIZE);
+
+    let mut dirty_pages: Vec<_> = buf.dirty_pages().map(|(i, _)| i.get()).collect();
+    dirty_pages.sort_unstable();
+    // Pages 0 and 2 are already zero.
+    assert_eq!(dirty_pages, vec![1, 3]);
+    let mut contents = vec![0xff; 4 * PAGE_SIZE];
+    buf.read(&mut contents, 0);
+    assert_eq!(contents, vec![0; 4 * PAGE_SIZE]);
+}
+
 #[test]
 fn serialize_empty_page_map() {
     let original_page_map = PageMap::default();
@@ -0,0 +1,175 @@
+//! Read-only memory-mapped views of page maps.
+//!
+//! A view maps the pages of a page map into a single contiguous region of
+//! memory. The pages of the checkpoint are mapped directly from the checkpoint
+//! file, so they share the page cache with all other mappings of the file.
+//! Only the pages of the page delta are copied into the view when it is
+//! created. After that the region is read-only and can be accessed by many
+//! threads concurrently, e.g. by query executions of the same canister.
+
+use crate::page_map::{MemoryRegion, PageIndex, PageMap, PersistenceError};
+use ic_sys::{page_bytes_from_ptr, PageBytes, PAGE_SIZE};
+use lazy_static::lazy_static;
+use libc::c_void;
+use nix::sys::mman::{mmap, mprotect, munmap, MapFlags, ProtFlags};
+
+lazy_static! {
+    static ref ZEROED_PAGE: Box<PageBytes> = Box::new([0; PAGE_SIZE]);
+}
+
+/// A read-only view of the pages of a `PageMap` at the time the view was
+/// created. Subsequent changes of the page map don't affect the view.
+pub struct PageMapView {
+    // The start of the mapping. It is null if the view is empty.
+    addr: *mut c_void,
+    // The number of pages in the mapping.
+    num_pages: usize,
+}
+
+// SAFETY: The mapping is read-only after the view is created and is owned by
+// the view, so it can be accessed and unmapped from any thread.
+unsafe impl Send for PageMapView {}
+unsafe impl Sync for PageMapView {}
+
+impl PageMapView {
+    pub(super) fn new(page_map: &PageMap) -> Result<Self, PersistenceError> {
+        let num_pages = page_map.num_host_pages();
+        if num_pages == 0 {
+            // It's illegal to mmap an empty region.
+            return Ok(Self {
+                addr: std::ptr::null_mut(),
+                num_pages,
+            });
+        }
+        let len = num_pages * PAGE_SIZE;
+        let mmap_error = |internal_error: nix::Error| PersistenceError::MmapError {
+            path: "<page map view>".to_string(),
+            len,
+            internal_error: internal_error.to_string(),
+        };
+
+        // Reserve the whole region as anonymous memory, which serves the zero
+        // pages and holds the copies of the pages of the page delta.
+        //
+        // SAFETY: A new private mapping doesn't alias any existing memory.
+        let addr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        }
+        .map_err(mmap_error)?;
+        // Owning the mapping right away unmaps it if the steps below fail.
+        let view = Self { addr, num_pages };
+
+        let mut page_index = 0;
+        while page_index < num_pages {
+            match page_map.get_memory_region(PageIndex::new(page_index as u64)) {
+                MemoryRegion::Zeros(range) => {
+                    page_index = (range.end.get() as usize).min(num_pages);
+                }
+                MemoryRegion::BackedByFile(range, fd) => {
+                    let end = (range.end.get() as usize).min(num_pages);
+                    // SAFETY: The target range is within the region owned by
+                    // the view, which is not accessed by anyone else yet. The
+                    // file descriptor is kept alive by the page map.
+                    unsafe {
+                        mmap(
+                            view.page_ptr(page_index) as *mut c_void,
+                            (end - page_index) * PAGE_SIZE,
+                            ProtFlags::PROT_READ,
+                            MapFlags::MAP_PRIVATE | MapFlags::MAP_FIXED,
+                            fd.fd,
+                            (page_index * PAGE_SIZE) as libc::off_t,
+                        )
+                    }
+                    .map_err(mmap_error)?;
+                    page_index = end;
+                }
+                MemoryRegion::BackedByPage(page) => {
+                    // SAFETY: The page is within the region owned by the view,
+                    // which is not accessed by anyone else yet.
+                    unsafe {
+                        std::ptr::copy_nonoverlapping(
+                            page.as_ptr(),
+                            view.page_ptr(page_index),
+                            PAGE_SIZE,
+                        )
+                    };
+                    page_index += 1;
+                }
+            }
+        }
+
+        // SAFETY: The region is owned by the view.
+        unsafe { mprotect(view.addr, len, ProtFlags::PROT_READ) }.map_err(mmap_error)?;
+        Ok(view)
+    }
+
+    /// Returns the number of pages in the view. All pages after them are
+    /// zero pages.
+    pub fn num_host_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    /// Returns the contents of the view as a contiguous slice.
+    pub fn as_slice(&self) -> &[u8] {
+        if self.num_pages == 0 {
+            return &[];
+        }
+        // SAFETY: The region is mapped and read-only for the lifetime of
+        // `self`.
+        unsafe { std::slice::from_raw_parts(self.addr as *const u8, self.num_pages * PAGE_SIZE) }
+    }
+
+    /// Returns the page with the specified `page_index`.
+    pub fn get_page(&self, page_index: PageIndex) -> &PageBytes {
+        let page_index = page_index.get() as usize;
+        if page_index < self.num_pages {
+            // SAFETY: The page is within the region, which is mapped and
+            // read-only for the lifetime of `self`.
+            unsafe { page_bytes_from_ptr(self, self.page_ptr(page_index)) }
+        } else {
+            &ZEROED_PAGE
+        }
+    }
+
+    /// Copies the bytes starting at the given offset into `dst`. The bytes
+    /// after the last page of the view are zeros.
+    pub fn read_range(&self, offset: usize, dst: &mut [u8]) {
+        let contents = self.as_slice();
+        let start = offset.min(contents.len());
+        let end = offset.saturating_add(dst.len()).min(contents.len());
+        let (mapped, zeros) = dst.split_at_mut(end - start);
+        mapped.copy_from_slice(&contents[start..end]);
+        zeros.fill(0);
+    }
+
+    fn page_ptr(&self, page_index: usize) -> *mut u8 {
+        // SAFETY: Callers only pass page indices within the region.
+        unsafe { (self.addr as *mut u8).add(page_index * PAGE_SIZE) }
+    }
+}
+
+impl Drop for PageMapView {
+    fn drop(&mut self) {
+        if self.num_pages > 0 {
+            // SAFETY: The region is owned by the view and no references to it
+            // outlive the view.
+            unsafe { munmap(self.addr, self.num_pages * PAGE_SIZE) }
+                .expect("Failed to unmap a page map view");
+        }
+    }
+}
+
+impl std::fmt::Debug for PageMapView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PageMapView")
+            .field("num_pages", &self.num_pages)
+            .finish()
+    }
+}
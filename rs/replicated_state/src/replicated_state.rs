@@ -1,6 +1,8 @@
 use super::{
     canister_state::CanisterState,
-    metadata_state::{IngressHistoryState, Stream, Streams, SystemMetadata},
+    metadata_state::{
+        IngressHistoryEvictionStats, IngressHistoryState, Stream, Streams, SystemMetadata,
+    },
 };
 use crate::{
     bitcoin_state::{BitcoinState, BitcoinStateError},
@@ -30,6 +32,7 @@ use rand_chacha::ChaChaRng;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, VecDeque};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Maximum message length of a synthetic reject response produced by message
 /// routing.
@@ -469,19 +472,20 @@ impl ReplicatedState {
     /// ingress history will be below or equal to `ingress_memory_capacity`
     /// by transitioning `Completed` and `Failed` statuses to `Done` from
     /// oldest to newest in case inserting `status` pushes the memory
-    /// consumption over the bound.
+    /// consumption over the bound. Returns the statistics of these
+    /// transitions.
     pub fn set_ingress_status(
         &mut self,
         message_id: MessageId,
         status: IngressStatus,
         ingress_memory_capacity: NumBytes,
-    ) {
+    ) -> IngressHistoryEvictionStats {
         self.metadata.ingress_history.insert(
             message_id,
             status,
             self.time(),
             ingress_memory_capacity,
-        );
+        )
     }
 
     /// Prunes ingress history statuses with a pruning time older than
@@ -821,6 +825,92 @@ impl ReplicatedState {
 
         timed_out_requests_count
     }
+
+    /// Times out the best-effort callbacks with expired deadlines of all
+    /// canisters, enqueuing `SYS_UNKNOWN` reject responses for them. Returns
+    /// the number of callbacks that were timed out.
+    ///
+    /// Canisters with a paused or aborted execution are skipped, because the
+    /// response being executed may be the one to an expired callback. Their
+    /// callbacks are timed out once the execution completes.
+    ///
+    /// See `SystemState::time_out_callbacks` for further details.
+    #[allow(clippy::needless_collect)]
+    pub fn time_out_callbacks(&mut self, current_time: Time) -> u64 {
+        // Same as in `time_out_requests()`, only remove and replace the
+        // canisters with expired callbacks.
+        let canister_ids_with_expired_callbacks = self
+            .canister_states
+            .iter()
+            .filter(|(_, canister_state)| {
+                canister_state
+                    .system_state
+                    .has_expired_callbacks(current_time)
+                    && !canister_state.has_paused_execution()
+                    && !canister_state.has_aborted_execution()
+            })
+            .map(|(canister_id, _)| *canister_id)
+            .collect::<Vec<_>>();
+
+        let mut timed_out_callbacks_count = 0;
+        for canister_id in canister_ids_with_expired_callbacks {
+            let mut canister = self.canister_states.remove(&canister_id).unwrap();
+            timed_out_callbacks_count += canister.system_state.time_out_callbacks(
+                current_time,
+                &canister_id,
+                &self.canister_states,
+            );
+            self.canister_states.insert(canister_id, canister);
+        }
+
+        timed_out_callbacks_count
+    }
+
+    /// Times out the guaranteed response callbacks of all canisters whose
+    /// call contexts are at least `max_lifetime` old, enqueuing `SYS_UNKNOWN`
+    /// reject responses for them. This way, a callee that never responds
+    /// cannot keep the call contexts of its callers open forever, which would
+    /// prevent the callers from stopping and upgrading. Returns the caller
+    /// and the callee of every callback that was timed out.
+    ///
+    /// Canisters with a paused or aborted execution are skipped, same as in
+    /// `time_out_callbacks()`.
+    ///
+    /// See `SystemState::time_out_stuck_callbacks` for further details.
+    #[allow(clippy::needless_collect)]
+    pub fn time_out_stuck_callbacks(
+        &mut self,
+        current_time: Time,
+        max_lifetime: Duration,
+    ) -> Vec<(CanisterId, CanisterId)> {
+        let canister_ids_with_stuck_callbacks = self
+            .canister_states
+            .iter()
+            .filter(|(_, canister_state)| {
+                canister_state
+                    .system_state
+                    .has_stuck_callbacks(current_time, max_lifetime)
+                    && !canister_state.has_paused_execution()
+                    && !canister_state.has_aborted_execution()
+            })
+            .map(|(canister_id, _)| *canister_id)
+            .collect::<Vec<_>>();
+
+        let mut timed_out_calls = Vec::new();
+        for canister_id in canister_ids_with_stuck_callbacks {
+            let mut canister = self.canister_states.remove(&canister_id).unwrap();
+            let callees = canister.system_state.time_out_stuck_callbacks(
+                current_time,
+                max_lifetime,
+                &canister_id,
+                &self.canister_states,
+            );
+            timed_out_calls.extend(callees.into_iter().map(|callee| (canister_id, callee)));
+            self.canister_states.insert(canister_id, canister);
+        }
+
+        timed_out_calls
+    }
 }
 
 /// A trait exposing `ReplicatedState` functionality for the exclusive use of
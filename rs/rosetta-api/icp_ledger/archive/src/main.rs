@@ -3,12 +3,17 @@ use dfn_candid::candid_one;
 use dfn_core::api::{print, stable_memory_size_in_pages};
 use dfn_core::{over_init, stable, BytesS};
 use dfn_protobuf::protobuf;
+use ic_icrc1::icrc3;
+use ic_ledger_canister_core::archive::{
+    verify_block_chain, VerifyBlocksArgs, VerifyBlocksError, VerifyBlocksResult,
+    MAX_BLOCKS_TO_VERIFY,
+};
 use ic_ledger_canister_core::range_utils;
 use ic_ledger_core::block::{BlockIndex, BlockType, EncodedBlock};
 use ic_metrics_encoder::MetricsEncoder;
 use icp_ledger::{
     Block, BlockRange, BlockRes, CandidBlock, GetBlocksArgs, GetBlocksError, GetBlocksResult,
-    IterBlocksArgs, MAX_BLOCKS_PER_REQUEST,
+    IterBlocksArgs, MAX_BLOCKS_PER_REQUEST, MAX_BLOCKS_RESPONSE_SIZE_BYTES,
 };
 use serde::{Deserialize, Serialize};
 use std::sync::RwLock;
@@ -148,6 +153,16 @@ fn remaining_capacity_() {
     dfn_core::over(dfn_candid::candid, |()| remaining_capacity());
 }
 
+#[candid_method(query, rename = "cycles_balance")]
+fn cycles_balance() -> u64 {
+    dfn_core::api::canister_cycle_balance()
+}
+
+#[export_name = "canister_query cycles_balance"]
+fn cycles_balance_() {
+    dfn_core::over(dfn_candid::candid, |()| cycles_balance());
+}
+
 #[export_name = "canister_update append_blocks"]
 fn append_blocks_() {
     dfn_core::over(dfn_candid::candid_one, append_blocks);
@@ -197,24 +212,23 @@ fn get_blocks(GetBlocksArgs { start, length }: GetBlocksArgs) -> GetBlocksResult
     }
 
     let requested_range = range_utils::make_range(start, length);
-    let effective_range = match range_utils::intersect(
-        &block_range,
-        &range_utils::take(&requested_range, MAX_BLOCKS_PER_REQUEST),
-    ) {
+    let effective_range = match range_utils::intersect(&block_range, &requested_range) {
         Ok(range) => range,
         Err(range_utils::NoIntersection) => return Ok(BlockRange { blocks: vec![] }),
     };
 
-    let mut candid_blocks: Vec<CandidBlock> =
-        Vec::with_capacity(range_utils::range_len(&effective_range) as usize);
-
-    for i in effective_range {
-        let encoded_block = &blocks[(i - block_range.start) as usize];
-        let candid_block = CandidBlock::from(
-            Block::decode(encoded_block.clone()).expect("failed to decode a block"),
-        );
-        candid_blocks.push(candid_block);
-    }
+    // Return as many blocks as fit into the response. The caller can fetch
+    // the remaining blocks with subsequent calls.
+    let candid_blocks: Vec<CandidBlock> = icp_ledger::take_blocks_within_size(
+        blocks[range_utils::offset(&effective_range, block_range.start)]
+            .iter()
+            .map(|encoded_block| {
+                CandidBlock::from(
+                    Block::decode(encoded_block.clone()).expect("failed to decode a block"),
+                )
+            }),
+        MAX_BLOCKS_RESPONSE_SIZE_BYTES,
+    );
 
     Ok(BlockRange {
         blocks: candid_blocks,
@@ -228,6 +242,114 @@ fn get_blocks_candid_() {
     dfn_core::over(candid_one, get_blocks);
 }
 
+#[candid_method(query, rename = "icrc3_get_blocks")]
+fn icrc3_get_blocks(args: Vec<icrc3::GetBlocksArgs>) -> icrc3::GetBlocksResult {
+    let archive_state = ARCHIVE_STATE.read().unwrap();
+    let blocks = &archive_state.blocks;
+    let block_range = range_utils::make_range(archive_state.block_height_offset, blocks.len());
+
+    let mut result = vec![];
+    for arg in args {
+        let (start, length) = arg.as_start_and_length().unwrap_or_else(|msg| {
+            dfn_core::api::trap_with(&msg);
+            unreachable!()
+        });
+        let requested_range = range_utils::make_range(start, length);
+        let effective_range = match range_utils::intersect(&block_range, &requested_range) {
+            Ok(range) => range_utils::take(&range, MAX_BLOCKS_PER_REQUEST - result.len()),
+            Err(range_utils::NoIntersection) => continue,
+        };
+        result.extend(icp_ledger::icrc3_blocks(
+            &blocks[range_utils::offset(&effective_range, block_range.start)],
+            effective_range.start,
+        ));
+    }
+
+    icrc3::GetBlocksResult {
+        log_length: candid::Nat::from(block_range.end),
+        blocks: result,
+        archived_blocks: vec![],
+    }
+}
+
+#[export_name = "canister_query icrc3_get_blocks"]
+fn icrc3_get_blocks_candid_() {
+    dfn_core::over(candid_one, icrc3_get_blocks);
+}
+
+/// Returns the encoded blocks in the given range, as many as fit into a
+/// response. The ledger copies the blocks of an archive node through this
+/// endpoint when it re-archives them.
+#[candid_method(query, rename = "get_encoded_blocks")]
+fn get_encoded_blocks(GetBlocksArgs { start, length }: GetBlocksArgs) -> Vec<EncodedBlock> {
+    let archive_state = ARCHIVE_STATE.read().unwrap();
+    let blocks = &archive_state.blocks;
+    let block_range = range_utils::make_range(archive_state.block_height_offset, blocks.len());
+    let requested_range = range_utils::make_range(start, length.min(MAX_BLOCKS_PER_REQUEST));
+    let effective_range = match range_utils::intersect(&block_range, &requested_range) {
+        Ok(range) => range,
+        Err(range_utils::NoIntersection) => return vec![],
+    };
+    icp_ledger::take_blocks_within_size(
+        blocks[range_utils::offset(&effective_range, block_range.start)]
+            .iter()
+            .cloned(),
+        MAX_BLOCKS_RESPONSE_SIZE_BYTES,
+    )
+}
+
+#[export_name = "canister_query get_encoded_blocks"]
+fn get_encoded_blocks_candid_() {
+    dfn_core::over(candid_one, get_encoded_blocks);
+}
+
+/// Checks that the blocks in the given range form a hash chain that ends in
+/// a block with the given hash, see `verify_block_chain`. Only the ledger
+/// and the controller of the archive can call it: the check is expensive.
+/// Callers verify long ranges in chunks of at most `MAX_BLOCKS_TO_VERIFY`
+/// blocks.
+#[candid_method(update, rename = "verify_blocks")]
+fn verify_blocks(args: VerifyBlocksArgs) -> VerifyBlocksResult {
+    let archive_state = ARCHIVE_STATE.read().unwrap();
+    let caller = dfn_core::api::caller();
+    if caller != archive_state.ledger_canister_id.get() && caller != dfn_core::api::controller() {
+        dfn_core::api::trap_with(
+            "Only the ledger and the controller of the archive can verify blocks",
+        );
+    }
+    if args.length > MAX_BLOCKS_TO_VERIFY {
+        return Err(VerifyBlocksError {
+            block_index: args.start,
+            message: format!(
+                "cannot verify more than {} blocks in one call, got {}",
+                MAX_BLOCKS_TO_VERIFY, args.length
+            ),
+        });
+    }
+    let block_range =
+        range_utils::make_range(archive_state.block_height_offset, archive_state.blocks.len());
+    let requested_range = range_utils::make_range(args.start, args.length as usize);
+    if !range_utils::is_subrange(&requested_range, &block_range) {
+        return Err(VerifyBlocksError {
+            block_index: args.start,
+            message: format!(
+                "the archive stores blocks {:?}, not all of {:?}",
+                block_range, requested_range
+            ),
+        });
+    }
+    verify_block_chain::<Block>(
+        &archive_state.blocks[range_utils::offset(&requested_range, block_range.start)],
+        args.start,
+        args.last_hash,
+    )
+}
+
+#[export_name = "canister_update verify_blocks"]
+fn verify_blocks_candid_() {
+    dfn_core::over(candid_one, verify_blocks);
+}
+
 #[export_name = "canister_post_upgrade"]
 fn post_upgrade() {
     over_init(|_: BytesS| {
@@ -119,6 +119,8 @@ pub struct TipOfChainResponse {
     pub certification: ::core::option::Option<Certification>,
     #[prost(message, optional, tag = "2")]
     pub chain_length: ::core::option::Option<BlockIndex>,
+    #[prost(message, optional, tag = "15")]
+    pub deprecation_warning: ::core::option::Option<DeprecationWarning>,
 }
 /// How many Tokens are there not in the minting account
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -149,6 +151,8 @@ pub struct EncodedBlock {
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct BlockResponse {
+    #[prost(message, optional, tag = "15")]
+    pub deprecation_warning: ::core::option::Option<DeprecationWarning>,
     #[prost(oneof = "block_response::BlockContent", tags = "1, 2")]
     pub block_content: ::core::option::Option<block_response::BlockContent>,
 }
@@ -186,6 +190,8 @@ pub struct EncodedBlocks {
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct GetBlocksResponse {
+    #[prost(message, optional, tag = "15")]
+    pub deprecation_warning: ::core::option::Option<DeprecationWarning>,
     #[prost(oneof = "get_blocks_response::GetBlocksContent", tags = "1, 2")]
     pub get_blocks_content: ::core::option::Option<get_blocks_response::GetBlocksContent>,
 }
@@ -211,6 +217,8 @@ pub struct IterBlocksRequest {
 pub struct IterBlocksResponse {
     #[prost(message, repeated, tag = "1")]
     pub blocks: ::prost::alloc::vec::Vec<EncodedBlock>,
+    #[prost(message, optional, tag = "15")]
+    pub deprecation_warning: ::core::option::Option<DeprecationWarning>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ArchiveIndexEntry {
@@ -225,6 +233,20 @@ pub struct ArchiveIndexEntry {
 pub struct ArchiveIndexResponse {
     #[prost(message, repeated, tag = "1")]
     pub entries: ::prost::alloc::vec::Vec<ArchiveIndexEntry>,
+    #[prost(message, optional, tag = "15")]
+    pub deprecation_warning: ::core::option::Option<DeprecationWarning>,
+}
+/// Attached by the ledger to the responses of the deprecated protobuf
+/// endpoints. Clients should move to the `replacement` endpoint before the
+/// protobuf endpoint is removed.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeprecationWarning {
+    #[prost(string, tag = "1")]
+    pub endpoint: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub replacement: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub message: ::prost::alloc::string::String,
 }
 // ** ARCHIVE CANISTER ENDPOINTS **
 
@@ -394,4 +416,6 @@ pub struct TransferFeeRequest {}
 pub struct TransferFeeResponse {
     #[prost(message, optional, tag = "1")]
     pub transfer_fee: ::core::option::Option<Tokens>,
+    #[prost(message, optional, tag = "15")]
+    pub deprecation_warning: ::core::option::Option<DeprecationWarning>,
 }
@@ -0,0 +1,89 @@
+//! The ICRC-1 accounts behind the account identifiers that the ledger has
+//! seen.
+//!
+//! An account identifier is a hash of the principal and the subaccount, so the
+//! ledger cannot recover the ICRC-1 account from it. Whenever a caller
+//! addresses its own account as (principal, subaccount), the ledger remembers
+//! the account, so that clients of the legacy API can look up the ICRC-1
+//! address of the accounts they know by account identifier.
+
+use crate::stable_balances::{AccountKey, ACCOUNT_KEY_SIZE};
+use crate::stable_memory::{get_memory, Memory, ICRC1_ACCOUNTS_MEMORY_ID};
+use ic_base_types::PrincipalId;
+use ic_icrc1::Account;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use icp_ledger::AccountIdentifier;
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+/// The length of the principal, the principal, and the subaccount if set.
+const MAX_STORED_ACCOUNT_SIZE: u32 = 1 + PrincipalId::MAX_LENGTH_IN_BYTES as u32 + 32;
+
+thread_local! {
+    static ICRC1_ACCOUNTS: RefCell<StableBTreeMap<Memory, AccountKey, StoredAccount>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(ICRC1_ACCOUNTS_MEMORY_ID),
+            ACCOUNT_KEY_SIZE,
+            MAX_STORED_ACCOUNT_SIZE,
+        ));
+}
+
+/// The value of the accounts map: the length of the principal, the principal,
+/// and the subaccount if it is set.
+struct StoredAccount(Account);
+
+impl Storable for StoredAccount {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let principal = self.0.owner.as_slice();
+        let mut bytes = Vec::with_capacity(MAX_STORED_ACCOUNT_SIZE as usize);
+        bytes.push(principal.len() as u8);
+        bytes.extend_from_slice(principal);
+        if let Some(subaccount) = &self.0.subaccount {
+            bytes.extend_from_slice(&subaccount[..]);
+        }
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let principal_len = bytes[0] as usize;
+        let (principal, subaccount) = bytes[1..].split_at(principal_len);
+        Self(Account {
+            owner: PrincipalId::try_from(principal).expect("invalid stored principal"),
+            subaccount: if subaccount.is_empty() {
+                None
+            } else {
+                Some(subaccount.try_into().expect("invalid stored subaccount"))
+            },
+        })
+    }
+}
+
+/// Remembers the ICRC-1 account behind its account identifier, unless it is
+/// already known.
+pub fn record(account: &Account) {
+    let key = AccountKey(AccountIdentifier::from(account.clone()));
+    ICRC1_ACCOUNTS.with(|accounts| {
+        let mut accounts = accounts.borrow_mut();
+        if accounts.get(&key).is_none() {
+            accounts
+                .insert(key, StoredAccount(account.clone()))
+                .expect("failed to insert an account into the stable map");
+        }
+    });
+}
+
+/// Returns the ICRC-1 account behind the account identifier if the ledger has
+/// seen it.
+pub fn lookup(account_identifier: &AccountIdentifier) -> Option<Account> {
+    ICRC1_ACCOUNTS.with(|accounts| {
+        accounts
+            .borrow()
+            .get(&AccountKey(*account_identifier))
+            .map(|account| account.0)
+    })
+}
+
+/// Returns the number of accounts that the ledger has recorded.
+pub fn len() -> u64 {
+    ICRC1_ACCOUNTS.with(|accounts| accounts.borrow().len())
+}
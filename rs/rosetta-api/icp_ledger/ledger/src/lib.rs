@@ -1,6 +1,7 @@
 use dfn_core::api::now;
 use ic_base_types::{CanisterId, PrincipalId};
-use ic_icrc1::Account;
+use ic_crypto_tree_hash::MixedHashTree;
+use ic_icrc1::{icrc3, Account};
 use ic_ledger_canister_core::archive::ArchiveCanisterWasm;
 use ic_ledger_canister_core::blockchain::Blockchain;
 use ic_ledger_canister_core::ledger::{self as core_ledger, LedgerData, TransactionInfo};
@@ -11,18 +12,26 @@ use ic_ledger_core::{
 };
 use ic_ledger_core::{block::BlockIndex, tokens::Tokens};
 use icp_ledger::{
-    AccountIdentifier, Block, LedgerBalances, Memo, Operation, PaymentError, Transaction,
-    TransferError, TransferFee, DEFAULT_TRANSFER_FEE,
+    AccountIdentifier, Block, CertifiedTip, LedgerCanisterUpgradePayload, Memo, MigrationError,
+    NotifyError, Operation, PaymentError, ProtobufEndpoint, Transaction, TransferError,
+    TransferFee, DEFAULT_TRANSFER_FEE,
 };
 use intmap::IntMap;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use stable_balances::StableBalances;
 use std::borrow::Cow;
-use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::sync::RwLock;
 use std::time::Duration;
 
 mod dfn_runtime;
+pub mod icrc1_accounts;
+pub mod stable_balances;
+pub mod protobuf_usage;
+pub mod stable_memory;
+pub mod transfer_stats;
 
 #[cfg(test)]
 mod tests;
@@ -61,7 +70,7 @@ fn unknown_token() -> String {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Ledger {
-    pub balances: LedgerBalances,
+    pub balances: Balances<AccountIdentifier, StableBalances>,
     pub blockchain: Blockchain<dfn_runtime::DfnRuntime, IcpLedgerArchiveWasm>,
     // A cap on the maximum number of accounts
     pub maximum_number_of_accounts: usize,
@@ -99,14 +108,23 @@ pub struct Ledger {
     /// Token name
     #[serde(default = "unknown_token")]
     pub token_name: String,
+    /// Whether the deprecated notifications are disabled for good, see
+    /// `Ledger::disable_notifications`.
+    #[serde(default)]
+    notifications_disabled: bool,
+    /// The deprecated protobuf endpoints that reject calls.
+    #[serde(default)]
+    disabled_protobuf_endpoints: BTreeSet<ProtobufEndpoint>,
 }
 
 impl LedgerData for Ledger {
     type AccountId = AccountIdentifier;
+    type Tokens = Tokens;
     type Runtime = dfn_runtime::DfnRuntime;
     type ArchiveWasm = IcpLedgerArchiveWasm;
     type Transaction = Transaction;
     type Block = Block;
+    type BalancesStore = StableBalances;
 
     fn transaction_window(&self) -> Duration {
         self.transaction_window
@@ -136,11 +154,11 @@ impl LedgerData for Ledger {
         &self.token_symbol
     }
 
-    fn balances(&self) -> &Balances<Self::AccountId, HashMap<Self::AccountId, Tokens>> {
+    fn balances(&self) -> &Balances<Self::AccountId, Self::BalancesStore> {
         &self.balances
     }
 
-    fn balances_mut(&mut self) -> &mut Balances<Self::AccountId, HashMap<Self::AccountId, Tokens>> {
+    fn balances_mut(&mut self) -> &mut Balances<Self::AccountId, Self::BalancesStore> {
         &mut self.balances
     }
 
@@ -176,7 +194,7 @@ impl LedgerData for Ledger {
 impl Default for Ledger {
     fn default() -> Self {
         Self {
-            balances: LedgerBalances::default(),
+            balances: Balances::default(),
             blockchain: Blockchain::default(),
             maximum_number_of_accounts: 28_000_000,
             accounts_overflow_trim_quantity: 100_000,
@@ -191,6 +209,8 @@ impl Default for Ledger {
             transfer_fee: DEFAULT_TRANSFER_FEE,
             token_symbol: unknown_token(),
             token_name: unknown_token(),
+            notifications_disabled: false,
+            disabled_protobuf_endpoints: BTreeSet::new(),
         }
     }
 }
@@ -312,6 +332,9 @@ impl Ledger {
         new_state: bool,
         now: TimeStamp,
     ) -> Result<(), String> {
+        if self.notifications_disabled {
+            return Err(NotifyError::NotificationsDisabled.to_string());
+        }
         if block_timestamp + self.transaction_window <= now {
             return Err(format!(
                 "You cannot send a notification for a transaction that is more than {} seconds old",
@@ -356,7 +379,79 @@ impl Ledger {
     /// Check if it's allowed to notify this canister
     /// Currently we reuse whitelist for that
     pub fn can_be_notified(&self, canister_id: &CanisterId) -> bool {
-        LEDGER.read().unwrap().send_whitelist.contains(canister_id)
+        self.send_whitelist.contains(canister_id)
+    }
+
+    /// Checks that `caller` may notify `to_canister` about a payment for which
+    /// it is willing to pay `max_fee`.
+    pub fn check_notification(
+        &self,
+        caller: &PrincipalId,
+        to_canister: &CanisterId,
+        max_fee: Tokens,
+    ) -> Result<(), NotifyError> {
+        if self.notifications_disabled {
+            return Err(NotifyError::NotificationsDisabled);
+        }
+        if !self.can_send(caller) {
+            return Err(NotifyError::CallerNotAllowed { caller: *caller });
+        }
+        if !self.can_be_notified(to_canister) {
+            return Err(NotifyError::CanisterNotWhitelisted {
+                canister_id: *to_canister,
+            });
+        }
+        if max_fee != self.transfer_fee {
+            return Err(NotifyError::BadFee {
+                expected_fee: self.transfer_fee,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn notifications_disabled(&self) -> bool {
+        self.notifications_disabled
+    }
+
+    /// Disables the deprecated notifications for good and frees the state
+    /// that only notifications use: the send whitelist and the record of
+    /// notified blocks.
+    pub fn disable_notifications(&mut self) {
+        self.notifications_disabled = true;
+        self.send_whitelist = HashSet::new();
+        self.blocks_notified = IntMap::new();
+    }
+
+    /// Returns an error if calls to the deprecated protobuf `endpoint` are
+    /// disabled.
+    pub fn check_protobuf_endpoint(
+        &self,
+        endpoint: ProtobufEndpoint,
+    ) -> Result<(), MigrationError> {
+        if self.disabled_protobuf_endpoints.contains(&endpoint) {
+            return Err(MigrationError::EndpointDisabled { endpoint });
+        }
+        Ok(())
+    }
+
+    pub fn protobuf_endpoint_disabled(&self, endpoint: ProtobufEndpoint) -> bool {
+        self.disabled_protobuf_endpoints.contains(&endpoint)
+    }
+
+    /// Applies the upgrade arguments. Returns an error and leaves the ledger
+    /// unchanged if the arguments are invalid.
+    pub fn upgrade(&mut self, args: LedgerCanisterUpgradePayload) -> Result<(), String> {
+        match args.disable_notifications {
+            Some(true) => self.disable_notifications(),
+            Some(false) if self.notifications_disabled => {
+                return Err("notifications cannot be enabled again".to_string())
+            }
+            Some(false) | None => {}
+        }
+        if let Some(endpoints) = args.disabled_protobuf_endpoints {
+            self.disabled_protobuf_endpoints = endpoints.into_iter().collect();
+        }
+        Ok(())
     }
 
     pub fn transactions_by_hash_len(&self) -> usize {
@@ -367,11 +462,74 @@ impl Ledger {
         self.transactions_by_height.len()
     }
 
+    /// Returns the average number of blocks per second that the transactions
+    /// in the deduplication window created, or zero if there are fewer than
+    /// two such transactions.
+    pub fn recent_blocks_per_second(&self) -> f64 {
+        let (first, last) = match (
+            self.transactions_by_height.front(),
+            self.transactions_by_height.back(),
+        ) {
+            (Some(first), Some(last)) => (first.block_timestamp, last.block_timestamp),
+            _ => return 0.0,
+        };
+        let nanos = last
+            .as_nanos_since_unix_epoch()
+            .saturating_sub(first.as_nanos_since_unix_epoch());
+        if nanos == 0 {
+            return 0.0;
+        }
+        (self.transactions_by_height.len() - 1) as f64 / Duration::from_nanos(nanos).as_secs_f64()
+    }
+
     pub fn transfer_fee(&self) -> TransferFee {
         TransferFee {
             transfer_fee: self.transfer_fee,
         }
     }
+
+    /// Returns the certified state of the ledger, or None if the ledger has
+    /// no blocks.
+    pub fn certified_tip(&self) -> Option<CertifiedTip> {
+        let last_block_hash = self.blockchain.last_hash?;
+        let archives = self
+            .blockchain
+            .archive
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|archive| archive.index())
+            .unwrap_or_default();
+        Some(CertifiedTip {
+            last_block_index: self.blockchain.chain_length() - 1,
+            last_block_hash,
+            archives,
+        })
+    }
+
+    fn construct_hash_tree(&self) -> MixedHashTree {
+        self.certified_tip()
+            .map_or(MixedHashTree::Empty, |tip| tip.hash_tree())
+    }
+
+    /// Returns the root hash of the certified ledger state.
+    /// The canister code must call set_certified_data with the value this function returns after
+    /// each modification of the blocks or of the archive ranges.
+    pub fn root_hash(&self) -> [u8; 32] {
+        self.construct_hash_tree().digest().0
+    }
+
+    /// Returns the certificate of the ledger state, given the data
+    /// certificate of the canister.
+    pub fn tip_certificate(&self, certificate: Vec<u8>) -> icrc3::DataCertificate {
+        let mut hash_tree = vec![];
+        ciborium::ser::into_writer(&self.construct_hash_tree(), &mut hash_tree)
+            .expect("bug: failed to encode the hash tree");
+        icrc3::DataCertificate {
+            certificate: ByteBuf::from(certificate),
+            hash_tree: ByteBuf::from(hash_tree),
+        }
+    }
 }
 
 pub fn add_payment(
@@ -4,30 +4,40 @@ use dfn_core::{
     api::{caller, data_certificate, print, set_certified_data, trap_with},
     over, over_async, over_init, printer, setup, stable, BytesS,
 };
-use dfn_protobuf::protobuf;
+use dfn_protobuf::{protobuf, ToProto};
 use ic_base_types::CanisterId;
-use ic_icrc1::{endpoints::Value, Account};
+use ic_icrc1::{endpoints::Value, icrc3, Account};
 use ic_ledger_canister_core::{
-    archive::{Archive, ArchiveOptions},
-    ledger::{archive_blocks, block_locations, find_block_in_archive, LedgerAccess},
+    archive::{
+        get_encoded_block, rearchive_node, top_up_nodes, verify_node, Archive, ArchiveOptions,
+        ArchiveTopUpEvent, ArchivingGuard, ArchivingGuardError,
+    },
+    ledger::{archive_blocks, block_locations, find_block_in_archive, LedgerAccess, LedgerData},
     range_utils,
 };
 use ic_ledger_core::{
-    block::{BlockIndex, BlockType, EncodedBlock},
+    balances::InspectableBalancesStore,
+    block::{BlockIndex, BlockType, EncodedBlock, HashOf},
     timestamp::TimeStamp,
     tokens::{Tokens, DECIMAL_PLACES},
 };
 use icp_ledger::{
-    protobuf, tokens_into_proto, AccountBalanceArgs, AccountIdentifier, ArchiveInfo,
-    ArchivedBlocksRange, Archives, BinaryAccountBalanceArgs, Block, BlockArg, BlockRes,
-    CandidBlock, Decimals, GetBlocksArgs, IterBlocksArgs, LedgerCanisterInitPayload, Memo, Name,
-    Operation, PaymentError, QueryArchiveFn, QueryBlocksResponse, SendArgs, Subaccount, Symbol,
-    TipOfChainRes, TotalSupplyArgs, TransferArgs, TransferError, TransferFee, TransferFeeArgs,
-    MAX_BLOCKS_PER_REQUEST,
+    protobuf, tokens_into_proto, AccountBalanceArgs, AccountIdBlob, AccountIdentifier, ArchiveInfo,
+    ArchivedBlocksRange, Archives, BinaryAccountBalanceArgs, Block, BlockArg, BlockRes, CandidBlock,
+    Decimals, GetBlocksArgs, IterBlocksArgs, LedgerCanisterInitPayload,
+    LedgerCanisterUpgradePayload, Memo, Name, Operation, PaymentError, ProtobufEndpoint,
+    ProtobufEndpointUsage, QueryArchiveFn, QueryBlocksResponse, RearchiveNodeArgs, SendArgs,
+    Subaccount, Symbol, TipOfChainRes, TotalSupplyArgs, TransferArgs, TransferError, TransferFee,
+    TransferFeeArgs, TransferHeavyHitters, MAX_BLOCKS_PER_REQUEST, MAX_BLOCKS_RESPONSE_SIZE_BYTES,
+};
+use ledger_canister::{
+    icrc1_accounts, protobuf_usage, stable_memory,
+    transfer_stats::{self, CallOutcome},
+    Ledger, LEDGER, MAX_MESSAGE_SIZE_BYTES,
 };
-use ledger_canister::{Ledger, LEDGER, MAX_MESSAGE_SIZE_BYTES};
 use std::{
-    collections::{HashMap, HashSet},
+    cell::Cell,
+    collections::{BTreeMap, HashMap, HashSet},
     sync::{Arc, RwLock},
     time::Duration,
 };
@@ -93,20 +103,18 @@ fn init(
             ));
         }
     }
-    set_certified_data(
-        &LEDGER
-            .read()
-            .unwrap()
-            .blockchain
-            .last_hash
-            .map(|h| h.into_bytes())
-            .unwrap_or([0u8; 32]),
-    );
-
     if let Some(archive_options) = archive_options {
         LEDGER.write().unwrap().blockchain.archive =
             Arc::new(RwLock::new(Some(Archive::new(archive_options))))
     }
+
+    certify_ledger();
+}
+
+/// Sets the certified data to the root hash of the ledger state, see
+/// `icp_ledger::certified_tip`.
+fn certify_ledger() {
+    set_certified_data(&LEDGER.read().unwrap().root_hash());
 }
 
 #[cfg(feature = "notify-method")]
@@ -116,7 +124,7 @@ fn add_payment(
     created_at_time: Option<TimeStamp>,
 ) -> (BlockIndex, ic_ledger_core::block::HashOf<EncodedBlock>) {
     let (height, hash) = ledger_canister::add_payment(memo, operation, created_at_time);
-    set_certified_data(&hash.into_bytes());
+    certify_ledger();
     (height, hash)
 }
 
@@ -151,6 +159,13 @@ async fn send(
         panic!("Sending from {} is not allowed", caller_principal_id);
     }
 
+    // The caller addresses its account as (principal, subaccount), so the
+    // ledger can remember the ICRC-1 account behind the account identifier.
+    icrc1_accounts::record(&Account {
+        owner: caller_principal_id,
+        subaccount: from_subaccount.map(|s| s.0),
+    });
+
     let from = AccountIdentifier::new(caller_principal_id, from_subaccount);
     let minting_acc = LEDGER
         .read()
@@ -175,9 +190,15 @@ async fn send(
     } else {
         let transfer_fee = LEDGER.read().unwrap().transfer_fee;
         if fee != transfer_fee {
-            return Err(TransferError::BadFee {
+            let error = TransferError::BadFee {
                 expected_fee: transfer_fee,
-            });
+            };
+            transfer_stats::record(
+                dfn_core::api::now().into(),
+                caller_principal_id,
+                CallOutcome::from_error(&error),
+            );
+            return Err(error);
         }
         Operation::Transfer {
             from,
@@ -186,22 +207,35 @@ async fn send(
             fee,
         }
     };
-    let (height, hash) = match LEDGER
+    let outcome = CallOutcome::from_operation(&transfer);
+    let result = LEDGER
         .write()
         .unwrap()
-        .add_payment(memo, transfer, created_at_time)
-    {
-        Ok((height, hash)) => (height, hash),
-        Err(PaymentError::TransferError(transfer_error)) => return Err(transfer_error),
+        .add_payment(memo, transfer, created_at_time);
+    let height = match result {
+        Ok((height, _)) => {
+            transfer_stats::record(dfn_core::api::now().into(), caller_principal_id, outcome);
+            height
+        }
+        Err(PaymentError::TransferError(transfer_error)) => {
+            transfer_stats::record(
+                dfn_core::api::now().into(),
+                caller_principal_id,
+                CallOutcome::from_error(&transfer_error),
+            );
+            return Err(transfer_error);
+        }
         Err(PaymentError::Reject(msg)) => panic!("{}", msg),
     };
-    set_certified_data(&hash.into_bytes());
+    certify_ledger();
 
     // Don't put anything that could ever trap after this call or people using this
     // endpoint. If something did panic the payment would appear to fail, but would
     // actually succeed on chain.
     let max_msg_size = *MAX_MESSAGE_SIZE_BYTES.read().unwrap();
     archive_blocks::<Access>(max_msg_size).await;
+    // Archiving changes the certified archive ranges.
+    certify_ledger();
     Ok(height)
 }
 
@@ -236,27 +270,16 @@ pub async fn notify(
 
     let caller_principal_id = caller();
 
-    if !LEDGER.read().unwrap().can_send(&caller_principal_id) {
-        panic!("Notifying from {} is not allowed", caller_principal_id);
-    }
-
-    if !LEDGER.read().unwrap().can_be_notified(&to_canister) {
-        panic!(
-            "Notifying non-whitelisted canister is not allowed: {}",
-            to_canister
-        );
-    }
+    LEDGER
+        .read()
+        .unwrap()
+        .check_notification(&caller_principal_id, &to_canister, max_fee)
+        .map_err(|e| e.to_string())?;
 
     let expected_from = AccountIdentifier::new(caller_principal_id, from_subaccount);
 
     let expected_to = AccountIdentifier::new(to_canister.get(), to_subaccount);
 
-    let transfer_fee = LEDGER.read().unwrap().transfer_fee;
-
-    if max_fee != transfer_fee {
-        panic!("Transfer fee should be {}", transfer_fee);
-    }
-
     let raw_block: EncodedBlock =
         match block(block_height).unwrap_or_else(|| panic!("Block {} not found", block_height)) {
             Ok(raw_block) => raw_block,
@@ -381,7 +404,8 @@ pub async fn notify(
 }
 
 /// This gives you the index of the last block added to the chain
-/// together with certification
+/// together with certification. The certified data is the root hash of the
+/// tree that `icrc3_get_tip_certificate` returns.
 fn tip_of_chain() -> TipOfChainRes {
     let last_block_idx = &LEDGER
         .read()
@@ -434,6 +458,125 @@ fn icrc1_balance_of(acc: Account) -> Nat {
     )
 }
 
+/// Returns the ICRC-1 account behind the account identifier, if the ledger
+/// has seen the owner of the account use it.
+#[candid_method(query, rename = "account_identifier_to_icrc1_account")]
+fn account_identifier_to_icrc1_account(account: AccountIdBlob) -> Option<Account> {
+    let account = AccountIdentifier::from_address(account).unwrap_or_else(|e| {
+        trap_with(&format!("Invalid account identifier: {}", e));
+        unreachable!()
+    });
+    icrc1_accounts::lookup(&account)
+}
+
+/// Returns the principals that sent the most transfers in the previous and in
+/// the current time window. Only the controller of the ledger can call it.
+#[candid_method(query, rename = "get_transfer_heavy_hitters")]
+fn get_transfer_heavy_hitters() -> Vec<TransferHeavyHitters> {
+    if caller() != dfn_core::api::controller() {
+        trap_with("Only the controller of the ledger can read the transfer heavy hitters");
+        unreachable!()
+    }
+    transfer_stats::with_stats(|stats| stats.heavy_hitters(dfn_core::api::now().into()))
+}
+
+/// Returns the callers of the deprecated protobuf endpoints since the last
+/// upgrade. Only the controller of the ledger can call it.
+#[candid_method(query, rename = "get_protobuf_endpoint_usage")]
+fn get_protobuf_endpoint_usage() -> Vec<ProtobufEndpointUsage> {
+    if caller() != dfn_core::api::controller() {
+        trap_with("Only the controller of the ledger can read the protobuf endpoint usage");
+        unreachable!()
+    }
+    let ledger = LEDGER.read().unwrap();
+    protobuf_usage::with_usage(|usage| {
+        usage.usage(|endpoint| ledger.protobuf_endpoint_disabled(endpoint))
+    })
+}
+
+type Runtime = <Ledger as LedgerData>::Runtime;
+type ArchiveWasm = <Ledger as LedgerData>::ArchiveWasm;
+
+/// Returns the hash that the last block of an archive node must have: the
+/// parent hash of the block after the node's blocks, which is stored either
+/// in the ledger or in the next archive node.
+async fn expected_last_hash_of_node(node: CanisterId) -> Result<HashOf<EncodedBlock>, String> {
+    let (next_index, local_block, next_node, last_hash) = {
+        let ledger = LEDGER.read().unwrap();
+        let archive_guard = ledger.blockchain.archive.read().unwrap();
+        let archive = archive_guard
+            .as_ref()
+            .ok_or_else(|| "the ledger has no archive".to_string())?;
+        let (_, to) = archive
+            .node_block_range(&node)
+            .ok_or_else(|| format!("{} is not an archive node with blocks", node))?;
+        (
+            to + 1,
+            ledger.blockchain.get(to + 1).cloned(),
+            archive.node_of_block(to + 1),
+            ledger.blockchain.last_hash,
+        )
+    };
+    let next_block = match (local_block, next_node) {
+        (Some(block), _) => block,
+        (None, Some(next_node)) => {
+            get_encoded_block::<Runtime>(next_node, next_index).await?
+        }
+        // The node stores the last block of the chain.
+        (None, None) => return last_hash.ok_or_else(|| "the chain is empty".to_string()),
+    };
+    Block::decode(next_block)?
+        .parent_hash
+        .ok_or_else(|| format!("block {} has no parent hash", next_index))
+}
+
+/// Prevents archiving while the controller repairs the archive nodes.
+fn lock_archive() -> Result<ArchivingGuard<Runtime, ArchiveWasm>, String> {
+    let archive = LEDGER.read().unwrap().blockchain.archive.clone();
+    ArchivingGuard::new(archive).map_err(|e| match e {
+        ArchivingGuardError::NoArchive => "the ledger has no archive".to_string(),
+        ArchivingGuardError::AlreadyArchiving => {
+            "the ledger is archiving blocks, try again later".to_string()
+        }
+    })
+}
+
+/// Verifies that the blocks of an archive node form a hash chain that ends
+/// in the parent hash of the block after them. Verify the nodes from the
+/// newest to the oldest: the check trusts the block after the node. Only the
+/// controller of the ledger can call it.
+#[candid_method(update, rename = "verify_archive_node")]
+async fn verify_archive_node(archive: CanisterId) -> Result<(), String> {
+    if caller() != dfn_core::api::controller() {
+        trap_with("Only the controller of the ledger can verify archive nodes");
+    }
+    let _archiving_guard = lock_archive()?;
+    let archive_arc = LEDGER.read().unwrap().blockchain.archive.clone();
+    let last_hash = expected_last_hash_of_node(archive).await?;
+    verify_node(&archive_arc, archive, last_hash).await?;
+    Ok(())
+}
+
+/// Moves the blocks of an archive node to a new archive node and returns the
+/// new node, e.g., to decommission the node or to replace a corrupted node
+/// with a good copy of its blocks, see `RearchiveNodeArgs`. The ledger
+/// verifies the copy like `verify_archive_node` before it serves the blocks
+/// from the new node. Only the controller of the ledger can call it.
+#[candid_method(update, rename = "rearchive_node")]
+async fn rearchive_node_(args: RearchiveNodeArgs) -> Result<CanisterId, String> {
+    if caller() != dfn_core::api::controller() {
+        trap_with("Only the controller of the ledger can re-archive blocks");
+    }
+    let _archiving_guard = lock_archive()?;
+    let archive_arc = LEDGER.read().unwrap().blockchain.archive.clone();
+    let last_hash = expected_last_hash_of_node(args.archive).await?;
+    let source = args.source.unwrap_or(args.archive);
+    let node = rearchive_node(&archive_arc, args.archive, source, last_hash).await?;
+    // The new node serves the blocks in the certified archive ranges.
+    certify_ledger();
+    Ok(node)
+}
+
 #[candid_method(query, rename = "icrc1_minting_account")]
 fn icrc1_minting_account() -> Option<Account> {
     LEDGER.read().unwrap().minting_account_icrc1.clone()
@@ -532,20 +675,36 @@ fn main() {
 
 #[export_name = "canister_post_upgrade"]
 fn post_upgrade() {
-    over_init(|_: BytesS| {
+    over_init(|BytesS(arg_bytes)| {
         let mut ledger = LEDGER.write().unwrap();
-        *ledger = ciborium::de::from_reader(stable::StableReader::new())
-            .expect("Decoding stable memory failed");
+        // Ledger versions without the stable balance store wrote the whole
+        // state to the stable memory. The balances of such a state are moved
+        // to the stable store in the heartbeat.
+        *ledger = if stable_memory::is_legacy_layout() {
+            ciborium::de::from_reader(stable::StableReader::new())
+                .expect("Decoding stable memory failed")
+        } else {
+            stable_memory::load_upgrade_state().expect("Decoding stable memory failed")
+        };
 
         ledger.maximum_number_of_accounts = 28_000_000;
 
-        set_certified_data(
-            &ledger
-                .blockchain
-                .last_hash
-                .map(|h| h.into_bytes())
-                .unwrap_or([0u8; 32]),
-        );
+        // Upgrades without arguments keep the ledger configuration.
+        if !arg_bytes.is_empty() {
+            let args: Option<LedgerCanisterUpgradePayload> = candid::decode_one(&arg_bytes)
+                .unwrap_or_else(|e| {
+                    trap_with(&format!("failed to decode upgrade arguments: {}", e));
+                    unreachable!()
+                });
+            if let Some(args) = args {
+                ledger.upgrade(args).unwrap_or_else(|e| {
+                    trap_with(&format!("invalid upgrade arguments: {}", e));
+                    unreachable!()
+                });
+            }
+        }
+
+        set_certified_data(&ledger.root_hash());
     })
 }
 
@@ -559,10 +718,68 @@ fn pre_upgrade() {
         .read()
         // This should never happen, but it's better to be safe than sorry
         .unwrap_or_else(|poisoned| poisoned.into_inner());
-    ciborium::ser::into_writer(&*ledger, stable::StableWriter::new())
+    stable_memory::save_upgrade_state(&*ledger)
         .expect("failed to write ledger state to stable memory");
 }
 
+/// The maximum number of accounts that a heartbeat moves from the heap to the
+/// stable balance store. Moving all accounts at once could exceed the
+/// instruction limit.
+const MAX_ACCOUNTS_TO_MIGRATE_PER_HEARTBEAT: usize = 10_000;
+
+/// How often the heartbeat checks the cycle balances of the archive nodes.
+const ARCHIVE_TOP_UP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+thread_local! {
+    // The time of the last check of the cycle balances of the archive nodes
+    // in nanoseconds since the Unix epoch. The first heartbeat after an
+    // upgrade checks the nodes again.
+    static LAST_ARCHIVE_TOP_UP: Cell<u64> = Cell::new(0);
+}
+
+#[export_name = "canister_heartbeat"]
+fn canister_heartbeat() {
+    // Skip the heartbeat if the ledger is locked, the next one will continue.
+    if let Ok(mut ledger) = LEDGER.try_write() {
+        if ledger.balances.store.num_accounts_to_migrate() > 0 {
+            let migrated = ledger
+                .balances
+                .store
+                .migrate(MAX_ACCOUNTS_TO_MIGRATE_PER_HEARTBEAT);
+            print(format!(
+                "[ledger] moved {} accounts to the stable balance store, {} left",
+                migrated,
+                ledger.balances.store.num_accounts_to_migrate()
+            ));
+        }
+    }
+    top_up_archive_nodes();
+}
+
+/// Tops up the archive nodes that are low on cycles every
+/// `ARCHIVE_TOP_UP_INTERVAL`. The check is skipped while the ledger archives
+/// blocks, the next heartbeat will retry.
+fn top_up_archive_nodes() {
+    let now = TimeStamp::from(dfn_core::api::now()).as_nanos_since_unix_epoch();
+    let last = LAST_ARCHIVE_TOP_UP.with(|last| last.get());
+    if now < last.saturating_add(ARCHIVE_TOP_UP_INTERVAL.as_nanos() as u64) {
+        return;
+    }
+    let archive = match LEDGER.try_read() {
+        Ok(ledger) => ledger.blockchain.archive.clone(),
+        Err(_) => return,
+    };
+    let archiving_guard = match ArchivingGuard::new(archive.clone()) {
+        Ok(guard) => guard,
+        Err(_) => return,
+    };
+    LAST_ARCHIVE_TOP_UP.with(|last| last.set(now));
+    dfn_core::api::futures::spawn(async move {
+        top_up_nodes(&archive, now).await;
+        drop(archiving_guard);
+    });
+}
+
 struct Access;
 
 impl LedgerAccess for Access {
@@ -579,6 +796,13 @@ impl LedgerAccess for Access {
     }
 }
 
+/// Traps if calls to the deprecated protobuf `endpoint` are disabled.
+fn check_protobuf_endpoint(endpoint: ProtobufEndpoint) {
+    if let Err(err) = LEDGER.read().unwrap().check_protobuf_endpoint(endpoint) {
+        trap_with(&err.to_string());
+    }
+}
+
 /// Canister endpoints
 #[export_name = "canister_update send_pb"]
 fn send_() {
@@ -592,6 +816,8 @@ fn send_() {
              to,
              created_at_time,
          }| async move {
+            check_protobuf_endpoint(ProtobufEndpoint::SendPb);
+            protobuf_usage::record(ProtobufEndpoint::SendPb, caller());
             send(memo, amount, fee, from_subaccount, to, created_at_time)
                 .await
                 .unwrap_or_else(|e| {
@@ -633,6 +859,13 @@ fn notify_() {
              to_canister,
              to_subaccount,
          })| async move {
+            // Rejecting instead of trapping keeps the record of the call.
+            protobuf_usage::record(ProtobufEndpoint::NotifyPb, caller());
+            LEDGER
+                .read()
+                .unwrap()
+                .check_protobuf_endpoint(ProtobufEndpoint::NotifyPb)
+                .map_err(|err| err.to_string())?;
             notify(
                 block_height,
                 max_fee,
@@ -698,17 +931,28 @@ fn notify_dfx_() {
 
 #[export_name = "canister_query block_pb"]
 fn block_() {
-    over(protobuf, |BlockArg(height)| BlockRes(block(height)));
+    over(protobuf, |BlockArg(height)| {
+        check_protobuf_endpoint(ProtobufEndpoint::BlockPb);
+        let mut res = BlockRes(block(height)).into_proto();
+        res.deprecation_warning = Some(ProtobufEndpoint::BlockPb.deprecation_warning());
+        res
+    });
 }
 
 #[export_name = "canister_query tip_of_chain_pb"]
 fn tip_of_chain_() {
-    over(protobuf, |protobuf::TipOfChainRequest {}| tip_of_chain());
+    over(protobuf, |protobuf::TipOfChainRequest {}| {
+        check_protobuf_endpoint(ProtobufEndpoint::TipOfChainPb);
+        let mut res = tip_of_chain().into_proto();
+        res.deprecation_warning = Some(ProtobufEndpoint::TipOfChainPb.deprecation_warning());
+        res
+    });
 }
 
 #[export_name = "canister_query get_archive_index_pb"]
 fn get_archive_index_() {
     over(protobuf, |()| {
+        check_protobuf_endpoint(ProtobufEndpoint::GetArchiveIndexPb);
         let state = LEDGER.read().unwrap();
         let entries = match &state
             .blockchain
@@ -730,13 +974,17 @@ fn get_archive_index_() {
                 )
                 .collect(),
         };
-        protobuf::ArchiveIndexResponse { entries }
+        protobuf::ArchiveIndexResponse {
+            entries,
+            deprecation_warning: Some(ProtobufEndpoint::GetArchiveIndexPb.deprecation_warning()),
+        }
     });
 }
 
 #[export_name = "canister_query account_balance_pb"]
 fn account_balance_() {
     over(protobuf, |AccountBalanceArgs { account }| {
+        check_protobuf_endpoint(ProtobufEndpoint::AccountBalancePb);
         tokens_into_proto(account_balance(account))
     })
 }
@@ -783,7 +1031,12 @@ fn icrc1_fee_candid() {
 
 #[export_name = "canister_query transfer_fee_pb"]
 fn transfer_fee_() {
-    over(protobuf, transfer_fee)
+    over(protobuf, |args: TransferFeeArgs| {
+        check_protobuf_endpoint(ProtobufEndpoint::TransferFeePb);
+        let mut res = transfer_fee(args).into_proto();
+        res.deprecation_warning = Some(ProtobufEndpoint::TransferFeePb.deprecation_warning());
+        res
+    })
 }
 
 #[export_name = "canister_query symbol"]
@@ -814,6 +1067,7 @@ fn icrc1_decimals_candid() {
 #[export_name = "canister_query total_supply_pb"]
 fn total_supply_() {
     over(protobuf, |_: TotalSupplyArgs| {
+        check_protobuf_endpoint(ProtobufEndpoint::TotalSupplyPb);
         tokens_into_proto(total_supply())
     })
 }
@@ -831,8 +1085,11 @@ fn icrc1_total_supply_candid() {
 #[export_name = "canister_query iter_blocks_pb"]
 fn iter_blocks_() {
     over(protobuf, |IterBlocksArgs { start, length }| {
+        check_protobuf_endpoint(ProtobufEndpoint::IterBlocksPb);
         let blocks = &LEDGER.read().unwrap().blockchain.blocks;
-        icp_ledger::iter_blocks(blocks, start, length)
+        let mut res = icp_ledger::iter_blocks(blocks, start, length).into_proto();
+        res.deprecation_warning = Some(ProtobufEndpoint::IterBlocksPb.deprecation_warning());
+        res
     });
 }
 
@@ -841,9 +1098,13 @@ fn iter_blocks_() {
 #[export_name = "canister_query get_blocks_pb"]
 fn get_blocks_() {
     over(protobuf, |GetBlocksArgs { start, length }| {
+        check_protobuf_endpoint(ProtobufEndpoint::GetBlocksPb);
         let blockchain = &LEDGER.read().unwrap().blockchain;
         let start_offset = blockchain.num_archived_blocks();
-        icp_ledger::get_blocks(&blockchain.blocks, start_offset, start, length)
+        let mut res =
+            icp_ledger::get_blocks(&blockchain.blocks, start_offset, start, length).into_proto();
+        res.deprecation_warning = Some(ProtobufEndpoint::GetBlocksPb.deprecation_warning());
+        res
     });
 }
 
@@ -852,18 +1113,20 @@ fn query_blocks(GetBlocksArgs { start, length }: GetBlocksArgs) -> QueryBlocksRe
     let ledger = LEDGER.read().unwrap();
     let locations = block_locations(&*ledger, start, length);
 
-    let local_blocks = range_utils::take(&locations.local_blocks, MAX_BLOCKS_PER_REQUEST);
-
-    let blocks: Vec<CandidBlock> = ledger
-        .blockchain
-        .block_slice(local_blocks.clone())
-        .iter()
-        .map(|enc_block| -> CandidBlock {
-            Block::decode(enc_block.clone())
-                .expect("bug: failed to decode encoded block")
-                .into()
-        })
-        .collect();
+    // Return as many blocks as fit into the response. The caller can fetch
+    // the remaining blocks with subsequent calls.
+    let blocks: Vec<CandidBlock> = icp_ledger::take_blocks_within_size(
+        ledger
+            .blockchain
+            .block_slice(locations.local_blocks.clone())
+            .iter()
+            .map(|enc_block| -> CandidBlock {
+                Block::decode(enc_block.clone())
+                    .expect("bug: failed to decode encoded block")
+                    .into()
+            }),
+        MAX_BLOCKS_RESPONSE_SIZE_BYTES,
+    );
 
     let archived_blocks = locations
         .archived_blocks
@@ -884,7 +1147,7 @@ fn query_blocks(GetBlocksArgs { start, length }: GetBlocksArgs) -> QueryBlocksRe
         chain_length,
         certificate: dfn_core::api::data_certificate().map(serde_bytes::ByteBuf::from),
         blocks,
-        first_block_index: local_blocks.start as BlockIndex,
+        first_block_index: locations.local_blocks.start as BlockIndex,
         archived_blocks,
     }
 }
@@ -894,6 +1157,31 @@ fn query_blocks_() {
     over(candid_one, query_blocks)
 }
 
+#[export_name = "canister_query account_identifier_to_icrc1_account"]
+fn account_identifier_to_icrc1_account_candid() {
+    over(candid_one, account_identifier_to_icrc1_account)
+}
+
+#[export_name = "canister_query get_transfer_heavy_hitters"]
+fn get_transfer_heavy_hitters_candid() {
+    over(candid_one, |()| get_transfer_heavy_hitters())
+}
+
+#[export_name = "canister_query get_protobuf_endpoint_usage"]
+fn get_protobuf_endpoint_usage_candid() {
+    over(candid_one, |()| get_protobuf_endpoint_usage())
+}
+
+#[export_name = "canister_update verify_archive_node"]
+fn verify_archive_node_candid() {
+    over_async(candid_one, verify_archive_node)
+}
+
+#[export_name = "canister_update rearchive_node"]
+fn rearchive_node_candid() {
+    over_async(candid_one, rearchive_node_)
+}
+
 #[export_name = "canister_query icrc1_minting_account"]
 fn icrc1_minting_account_candid() {
     over(candid_one, |()| icrc1_minting_account())
@@ -912,10 +1200,10 @@ fn archives() -> Archives {
         .as_ref()
         .iter()
         .flat_map(|archive| {
-            archive
-                .nodes()
-                .iter()
-                .map(|cid| ArchiveInfo { canister_id: *cid })
+            archive.nodes().iter().map(|cid| ArchiveInfo {
+                canister_id: *cid,
+                remaining_capacity: archive.remaining_capacity(cid).map(|c| c as u64),
+            })
         })
         .collect();
     Archives { archives }
@@ -937,6 +1225,135 @@ fn archives_candid() {
     over(candid_one, |()| archives());
 }
 
+/// Returns the most recent events of the cycle top ups of the archive nodes,
+/// oldest first.
+#[candid_method(query, rename = "get_archive_top_up_events")]
+fn get_archive_top_up_events() -> Vec<ArchiveTopUpEvent> {
+    let ledger_guard = LEDGER.try_read().expect("Failed to get ledger read lock");
+    let archive_guard = ledger_guard.blockchain.archive.read().unwrap();
+    archive_guard
+        .iter()
+        .flat_map(|archive| archive.top_up_events().cloned())
+        .collect()
+}
+
+#[export_name = "canister_query get_archive_top_up_events"]
+fn get_archive_top_up_events_candid() {
+    over(candid_one, |()| get_archive_top_up_events());
+}
+
+#[candid_method(query, rename = "icrc3_get_blocks")]
+fn icrc3_get_blocks(args: Vec<icrc3::GetBlocksArgs>) -> icrc3::GetBlocksResult {
+    let ledger = LEDGER.read().unwrap();
+    let mut blocks = vec![];
+    let mut archived_blocks: BTreeMap<CanisterId, Vec<icrc3::GetBlocksArgs>> = BTreeMap::new();
+
+    for arg in args {
+        let (start, length) = arg.as_start_and_length().unwrap_or_else(|msg| {
+            trap_with(&msg);
+            unreachable!()
+        });
+        let locations = block_locations(&*ledger, start, length);
+
+        let local_blocks = range_utils::take(
+            &locations.local_blocks,
+            MAX_BLOCKS_PER_REQUEST.saturating_sub(blocks.len()),
+        );
+        blocks.extend(icp_ledger::icrc3_blocks(
+            ledger.blockchain.block_slice(local_blocks.clone()),
+            local_blocks.start,
+        ));
+
+        for (canister_id, slice) in locations.archived_blocks {
+            archived_blocks
+                .entry(canister_id)
+                .or_default()
+                .push(icrc3::GetBlocksArgs::new(
+                    slice.start,
+                    range_utils::range_len(&slice),
+                ));
+        }
+    }
+
+    let archived_blocks = archived_blocks
+        .into_iter()
+        .map(|(canister_id, args)| icrc3::ArchivedBlocks {
+            args,
+            callback: icrc3::GetBlocksFn {
+                canister_id,
+                method: "icrc3_get_blocks".to_string(),
+            },
+        })
+        .collect();
+
+    icrc3::GetBlocksResult {
+        log_length: Nat::from(ledger.blockchain.chain_length()),
+        blocks,
+        archived_blocks,
+    }
+}
+
+#[export_name = "canister_query icrc3_get_blocks"]
+fn icrc3_get_blocks_candid() {
+    over(candid_one, icrc3_get_blocks)
+}
+
+#[candid_method(query, rename = "icrc3_get_archives")]
+fn icrc3_get_archives(args: icrc3::GetArchivesArgs) -> Vec<icrc3::ArchiveInfo> {
+    let ledger = LEDGER.read().unwrap();
+    let archive_guard = ledger.blockchain.archive.read().unwrap();
+    let index = archive_guard
+        .as_ref()
+        .map(|archive| archive.index())
+        .unwrap_or_default();
+    // The archives are ordered by their block ranges, so the archives after
+    // `from` store the blocks after the blocks of `from`.
+    let skip = match args.from {
+        Some(from) => index
+            .iter()
+            .position(|(_, canister_id)| canister_id.get() == from)
+            .map_or(index.len(), |position| position + 1),
+        None => 0,
+    };
+    index
+        .into_iter()
+        .skip(skip)
+        .map(|((start, end), canister_id)| icrc3::ArchiveInfo {
+            canister_id: canister_id.get(),
+            start: Nat::from(start),
+            end: Nat::from(end),
+        })
+        .collect()
+}
+
+#[export_name = "canister_query icrc3_get_archives"]
+fn icrc3_get_archives_candid() {
+    over(candid_one, icrc3_get_archives)
+}
+
+#[candid_method(query, rename = "icrc3_supported_block_types")]
+fn icrc3_supported_block_types() -> Vec<icrc3::SupportedBlockType> {
+    icp_ledger::icrc3_supported_block_types()
+}
+
+#[export_name = "canister_query icrc3_supported_block_types"]
+fn icrc3_supported_block_types_candid() {
+    over(candid_one, |()| icrc3_supported_block_types())
+}
+
+/// Returns the certificate of the ledger state with the hash tree of the tip
+/// of the chain and of the archive ranges, see `icp_ledger::certified_tip`.
+#[candid_method(query, rename = "icrc3_get_tip_certificate")]
+fn icrc3_get_tip_certificate() -> Option<icrc3::DataCertificate> {
+    let certificate = data_certificate()?;
+    Some(LEDGER.read().unwrap().tip_certificate(certificate))
+}
+
+#[export_name = "canister_query icrc3_get_tip_certificate"]
+fn icrc3_get_tip_certificate_candid() {
+    over(candid_one, |()| icrc3_get_tip_certificate())
+}
+
 #[export_name = "canister_query icrc1_metadata"]
 fn icrc1_metadata_candid() {
     over(candid_one, |()| icrc1_metadata())
@@ -987,6 +1404,88 @@ fn encode_metrics(w: &mut ic_metrics_encoder::MetricsEncoder<Vec<u8>>) -> std::i
         ledger.blockchain.num_archived_blocks as f64,
         "Total number of blocks sent to the archive.",
     )?;
+    w.encode_counter(
+        "ledger_chain_length",
+        ledger.blockchain.chain_length() as f64,
+        "Total number of blocks in the ledger, including the archived blocks.",
+    )?;
+    w.encode_gauge(
+        "ledger_blocks_per_second",
+        ledger.recent_blocks_per_second(),
+        "Average number of blocks per second in the transaction deduplication window.",
+    )?;
+    w.encode_gauge(
+        "ledger_cycle_balance",
+        dfn_core::api::canister_cycle_balance() as f64,
+        "Cycle balance of the ledger canister.",
+    )?;
+    if let Some(archive) = ledger.blockchain.archive.read().unwrap().as_ref() {
+        w.encode_gauge(
+            "ledger_archive_nodes",
+            archive.nodes().len() as f64,
+            "Total number of archive nodes.",
+        )?;
+        if let Some(capacity) = archive
+            .nodes()
+            .last()
+            .and_then(|node| archive.remaining_capacity(node))
+        {
+            w.encode_gauge(
+                "ledger_last_archive_node_remaining_capacity_bytes",
+                capacity as f64,
+                "Remaining capacity of the last archive node as of the last check.",
+            )?;
+        }
+        let mut capacities = w.gauge_vec(
+            "ledger_archive_node_remaining_capacity_bytes",
+            "Remaining capacity of the archive nodes as of the last check, by archive node.",
+        )?;
+        for node in archive.nodes() {
+            if let Some(capacity) = archive.remaining_capacity(node) {
+                let canister_id = node.to_string();
+                capacities =
+                    capacities.value(&[("canister_id", canister_id.as_str())], capacity as f64)?;
+            }
+        }
+        w.encode_counter(
+            "ledger_archive_top_ups",
+            archive.num_top_ups() as f64,
+            "Total number of times the ledger topped up an archive node with cycles.",
+        )?;
+        w.encode_counter(
+            "ledger_archive_top_up_cycles",
+            archive.cycles_sent_in_top_ups() as f64,
+            "Total number of cycles the ledger sent to archive nodes in top ups.",
+        )?;
+        w.encode_counter(
+            "ledger_archive_failed_top_ups",
+            archive.num_failed_top_ups() as f64,
+            "Total number of failed cycle balance checks and top ups of archive nodes.",
+        )?;
+        if let Some(pool) = archive.remaining_top_up_pool() {
+            w.encode_gauge(
+                "ledger_archive_top_up_pool_cycles",
+                pool as f64,
+                "Number of cycles left in the pool for archive top ups.",
+            )?;
+        }
+        let mut balances = w.gauge_vec(
+            "ledger_archive_node_cycle_balance",
+            "Cycle balance of the archive nodes as of the last check, by archive node.",
+        )?;
+        for node in archive.nodes() {
+            if let Some(balance) = archive.cycles_balance(node) {
+                let canister_id = node.to_string();
+                balances =
+                    balances.value(&[("canister_id", canister_id.as_str())], balance as f64)?;
+            }
+        }
+    }
+    w.encode_gauge(
+        "ledger_total_supply_e8s",
+        ledger.balances.total_supply().get_e8s() as f64,
+        "Total number of e8s in circulation.",
+    )?;
     w.encode_gauge(
         "ledger_balances_token_pool",
         ledger.balances.token_pool.get_tokens() as f64,
@@ -997,6 +1496,78 @@ fn encode_metrics(w: &mut ic_metrics_encoder::MetricsEncoder<Vec<u8>>) -> std::i
         ledger.balances.store.len() as f64,
         "Total number of accounts in the balance store.",
     )?;
+    w.encode_gauge(
+        "ledger_balance_store_entries_to_migrate",
+        ledger.balances.store.num_accounts_to_migrate() as f64,
+        "Number of accounts that still need to be moved to the stable balance store.",
+    )?;
+    w.encode_gauge(
+        "ledger_icrc1_accounts",
+        icrc1_accounts::len() as f64,
+        "Number of account identifiers whose ICRC-1 account the ledger knows.",
+    )?;
+    transfer_stats::with_stats(|stats| -> std::io::Result<()> {
+        let now = dfn_core::api::now().into();
+        let mut calls = w.counter_vec(
+            "ledger_transfer_calls",
+            "Total number of transfer calls since the last upgrade, by outcome.",
+        )?;
+        for outcome in CallOutcome::ALL {
+            calls = calls.value(
+                &[("outcome", outcome.as_str())],
+                stats.totals().get(outcome) as f64,
+            )?;
+        }
+        let (current, previous) = stats.window_counts(now);
+        let mut window_calls = w.gauge_vec(
+            "ledger_transfer_calls_in_window",
+            "Number of transfer calls in the current and in the previous time window, by outcome.",
+        )?;
+        for (window, counts) in [("current", &current), ("previous", &previous)] {
+            for outcome in CallOutcome::ALL {
+                window_calls = window_calls.value(
+                    &[("window", window), ("outcome", outcome.as_str())],
+                    counts.get(outcome) as f64,
+                )?;
+            }
+        }
+        w.encode_gauge(
+            "ledger_transfer_stats_window_seconds",
+            transfer_stats::WINDOW.as_secs() as f64,
+            "Length of the time windows of the transfer call statistics.",
+        )?;
+        w.encode_gauge(
+            "ledger_transfer_max_calls_per_principal",
+            stats.max_calls_per_principal(now) as f64,
+            "Upper bound of the number of transfer calls of the most active principal in the \
+             current time window.",
+        )
+    })?;
+    protobuf_usage::with_usage(|usage| -> std::io::Result<()> {
+        let mut calls = w.counter_vec(
+            "ledger_protobuf_endpoint_calls",
+            "Total number of calls to the deprecated protobuf update endpoints since the last \
+             upgrade, by endpoint.",
+        )?;
+        for endpoint in [ProtobufEndpoint::SendPb, ProtobufEndpoint::NotifyPb] {
+            calls = calls.value(
+                &[("endpoint", endpoint.method_name())],
+                usage.calls(endpoint) as f64,
+            )?;
+        }
+        let mut callers = w.gauge_vec(
+            "ledger_protobuf_endpoint_callers",
+            "Number of distinct callers of the deprecated protobuf update endpoints since the \
+             last upgrade, by endpoint.",
+        )?;
+        for endpoint in [ProtobufEndpoint::SendPb, ProtobufEndpoint::NotifyPb] {
+            callers = callers.value(
+                &[("endpoint", endpoint.method_name())],
+                usage.num_callers(endpoint) as f64,
+            )?;
+        }
+        Ok(())
+    })?;
     w.encode_gauge(
         "ledger_most_recent_block_time_seconds",
         ledger.blockchain.last_timestamp.as_nanos_since_unix_epoch() as f64 / 1_000_000_000.0,
@@ -0,0 +1,93 @@
+//! Usage statistics of the deprecated protobuf endpoints that tell which
+//! clients still have to migrate before the endpoints can be removed.
+//!
+//! The ledger counts the calls per endpoint and per caller. It tracks up to
+//! `MAX_TRACKED_CALLERS` callers per endpoint and only counts the calls of
+//! further callers in total. The statistics live on the heap and start from
+//! scratch after an upgrade. Only calls to update endpoints are counted: the
+//! IC discards the changes that queries make to the state, so query endpoints
+//! can only announce their deprecation in their responses.
+
+use ic_base_types::PrincipalId;
+use icp_ledger::{ProtobufEndpoint, ProtobufEndpointCaller, ProtobufEndpointUsage};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+/// The number of callers that the ledger tracks per endpoint.
+pub const MAX_TRACKED_CALLERS: usize = 1_000;
+
+thread_local! {
+    static PROTOBUF_USAGE: RefCell<ProtobufUsage> = RefCell::new(ProtobufUsage::default());
+}
+
+/// Records a call of `caller` to `endpoint`.
+pub fn record(endpoint: ProtobufEndpoint, caller: PrincipalId) {
+    PROTOBUF_USAGE.with(|usage| usage.borrow_mut().record(endpoint, caller))
+}
+
+pub fn with_usage<R>(f: impl FnOnce(&ProtobufUsage) -> R) -> R {
+    PROTOBUF_USAGE.with(|usage| f(&usage.borrow()))
+}
+
+#[derive(Clone, Debug, Default)]
+struct EndpointUsage {
+    calls: u64,
+    callers: BTreeMap<PrincipalId, u64>,
+    untracked_calls: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct ProtobufUsage {
+    endpoints: BTreeMap<ProtobufEndpoint, EndpointUsage>,
+}
+
+impl ProtobufUsage {
+    pub fn record(&mut self, endpoint: ProtobufEndpoint, caller: PrincipalId) {
+        let usage = self.endpoints.entry(endpoint).or_default();
+        usage.calls += 1;
+        if let Some(calls) = usage.callers.get_mut(&caller) {
+            *calls += 1;
+        } else if usage.callers.len() < MAX_TRACKED_CALLERS {
+            usage.callers.insert(caller, 1);
+        } else {
+            usage.untracked_calls += 1;
+        }
+    }
+
+    /// Returns the number of calls to `endpoint`.
+    pub fn calls(&self, endpoint: ProtobufEndpoint) -> u64 {
+        self.endpoints.get(&endpoint).map_or(0, |usage| usage.calls)
+    }
+
+    /// Returns the number of distinct callers of `endpoint`, not counting
+    /// untracked callers.
+    pub fn num_callers(&self, endpoint: ProtobufEndpoint) -> usize {
+        self.endpoints
+            .get(&endpoint)
+            .map_or(0, |usage| usage.callers.len())
+    }
+
+    /// Returns the usage of all protobuf endpoints. `disabled` tells whether
+    /// an endpoint rejects calls.
+    pub fn usage(&self, disabled: impl Fn(ProtobufEndpoint) -> bool) -> Vec<ProtobufEndpointUsage> {
+        ProtobufEndpoint::ALL
+            .iter()
+            .map(|endpoint| {
+                let usage = self.endpoints.get(endpoint).cloned().unwrap_or_default();
+                let mut callers: Vec<_> = usage
+                    .callers
+                    .into_iter()
+                    .map(|(principal, calls)| ProtobufEndpointCaller { principal, calls })
+                    .collect();
+                callers.sort_by(|a, b| b.calls.cmp(&a.calls));
+                ProtobufEndpointUsage {
+                    endpoint: *endpoint,
+                    calls: usage.calls,
+                    callers,
+                    untracked_calls: usage.untracked_calls,
+                    disabled: disabled(*endpoint),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,140 @@
+//! A balances store that keeps the account balances in a stable B-tree map.
+
+use crate::stable_memory::{get_memory, Memory, BALANCES_MEMORY_ID};
+use ic_ledger_core::balances::{BalancesStore, InspectableBalancesStore};
+use ic_ledger_core::tokens::Tokens;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use icp_ledger::AccountIdentifier;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+pub(crate) const ACCOUNT_KEY_SIZE: u32 = 28;
+const TOKENS_SIZE: u32 = 8;
+
+thread_local! {
+    static BALANCES: RefCell<StableBTreeMap<Memory, AccountKey, StoredTokens>> = RefCell::new(
+        StableBTreeMap::init(get_memory(BALANCES_MEMORY_ID), ACCOUNT_KEY_SIZE, TOKENS_SIZE)
+    );
+}
+
+/// The key of the stable maps indexed by account: the 28-byte hash of an
+/// account.
+pub(crate) struct AccountKey(pub(crate) AccountIdentifier);
+
+impl Storable for AccountKey {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(&self.0.hash[..])
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(AccountIdentifier::from_slice(&bytes).expect("invalid account key"))
+    }
+}
+
+/// The value of the stable balances map: the number of e8s, little-endian.
+struct StoredTokens(Tokens);
+
+impl Storable for StoredTokens {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(self.0.get_e8s().to_le_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let e8s = bytes.try_into().expect("invalid balance encoding");
+        Self(Tokens::from_e8s(u64::from_le_bytes(e8s)))
+    }
+}
+
+/// The balances of the ledger accounts.
+///
+/// The balances live in a stable map that survives upgrades without being
+/// serialized. Ledger versions before the stable map kept all balances in a
+/// heap map that was serialized on every upgrade. After an upgrade from such a
+/// version, the balances are deserialized into `heap` and moved to the stable
+/// map in batches by `migrate`. An account is never in both maps.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(transparent)]
+pub struct StableBalances {
+    heap: HashMap<AccountIdentifier, Tokens>,
+}
+
+impl StableBalances {
+    /// Returns the number of accounts that still need to be moved to the
+    /// stable map.
+    pub fn num_accounts_to_migrate(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Moves up to `max_accounts` balances from the heap to the stable map and
+    /// returns the number of accounts moved.
+    pub fn migrate(&mut self, max_accounts: usize) -> usize {
+        let accounts: Vec<AccountIdentifier> =
+            self.heap.keys().take(max_accounts).copied().collect();
+        BALANCES.with(|balances| {
+            let mut balances = balances.borrow_mut();
+            for account in accounts.iter() {
+                let tokens = self.heap.remove(account).unwrap();
+                balances
+                    .insert(AccountKey(*account), StoredTokens(tokens))
+                    .expect("failed to insert a balance into the stable map");
+            }
+        });
+        if self.heap.is_empty() {
+            self.heap.shrink_to_fit();
+        }
+        accounts.len()
+    }
+}
+
+impl BalancesStore<AccountIdentifier> for StableBalances {
+    type Tokens = Tokens;
+
+    fn get_balance(&self, k: &AccountIdentifier) -> Option<Tokens> {
+        self.heap.get(k).copied().or_else(|| {
+            BALANCES.with(|balances| {
+                balances
+                    .borrow()
+                    .get(&AccountKey(*k))
+                    .map(|tokens| tokens.0)
+            })
+        })
+    }
+
+    fn update<F, E>(&mut self, k: AccountIdentifier, mut f: F) -> Result<Tokens, E>
+    where
+        F: FnMut(Option<&Tokens>) -> Result<Tokens, E>,
+    {
+        let new_v = f(self.get_balance(&k).as_ref())?;
+        self.heap.remove(&k);
+        BALANCES.with(|balances| {
+            let mut balances = balances.borrow_mut();
+            if new_v != Tokens::ZERO {
+                balances
+                    .insert(AccountKey(k), StoredTokens(new_v))
+                    .expect("failed to insert a balance into the stable map");
+            } else {
+                balances.remove(&AccountKey(k));
+            }
+        });
+        Ok(new_v)
+    }
+}
+
+impl InspectableBalancesStore<AccountIdentifier> for StableBalances {
+    fn len(&self) -> usize {
+        self.heap.len() + BALANCES.with(|balances| balances.borrow().len()) as usize
+    }
+
+    fn for_each_balance(&self, mut f: impl FnMut(&AccountIdentifier, Tokens)) {
+        for (account, tokens) in self.heap.iter() {
+            f(account, *tokens)
+        }
+        BALANCES.with(|balances| {
+            for (account, tokens) in balances.borrow().iter() {
+                f(&account.0, tokens.0)
+            }
+        });
+    }
+}
@@ -0,0 +1,148 @@
+//! The layout of the ledger stable memory.
+//!
+//! The stable memory is split into virtual memories by a `MemoryManager`. The
+//! account balances live in a stable map in `BALANCES_MEMORY_ID` at all times,
+//! so they don't need to be serialized on upgrade. The rest of the ledger
+//! state is serialized into `UPGRADES_MEMORY_ID` in `pre_upgrade`, prefixed
+//! with its length. The ICRC-1 accounts behind account identifiers live in a
+//! stable map in `ICRC1_ACCOUNTS_MEMORY_ID`.
+//!
+//! Ledger versions that kept all balances on the heap wrote the serialized
+//! state directly to the stable memory, see `is_legacy_layout`.
+
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Memory as _};
+use serde::{de::DeserializeOwned, Serialize};
+use std::cell::RefCell;
+use std::io;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const UPGRADES_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub(crate) const BALANCES_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub(crate) const ICRC1_ACCOUNTS_MEMORY_ID: MemoryId = MemoryId::new(2);
+
+const WASM_PAGE_SIZE: u64 = 65536;
+
+/// The number of bytes of the length prefix of the upgrade state.
+const LENGTH_BYTES: u64 = 8;
+
+/// The bytes at the beginning of a stable memory managed by a `MemoryManager`.
+const MEMORY_MANAGER_MAGIC: &[u8; 3] = b"MGR";
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub(crate) fn get_memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+/// Returns true if the stable memory holds the state written by a ledger
+/// version that didn't use a `MemoryManager`, i.e., the length of the state in
+/// the first 4 bytes followed by the state itself.
+///
+/// NB. This function must be called before anything accesses the memory
+/// manager: initializing the manager overwrites the beginning of the legacy
+/// state.
+pub fn is_legacy_layout() -> bool {
+    let memory = DefaultMemoryImpl::default();
+    if memory.size() == 0 {
+        return false;
+    }
+    let mut magic = [0u8; 3];
+    memory.read(0, &mut magic);
+    magic != *MEMORY_MANAGER_MAGIC
+}
+
+/// Serializes the state into the upgrades memory.
+pub fn save_upgrade_state<T: Serialize>(state: &T) -> Result<(), String> {
+    let mut writer = UpgradeWriter {
+        memory: get_memory(UPGRADES_MEMORY_ID),
+        offset: LENGTH_BYTES,
+    };
+    ciborium::ser::into_writer(state, &mut writer)
+        .map_err(|e| format!("failed to encode the ledger state: {:?}", e))?;
+    writer.write_length().map_err(|e| e.to_string())
+}
+
+/// Deserializes the state saved by `save_upgrade_state`.
+pub fn load_upgrade_state<T: DeserializeOwned>() -> Result<T, String> {
+    let memory = get_memory(UPGRADES_MEMORY_ID);
+    if memory.size() == 0 {
+        return Err("the upgrades memory is empty".to_string());
+    }
+    let mut length = [0u8; LENGTH_BYTES as usize];
+    memory.read(0, &mut length);
+    let reader = UpgradeReader {
+        memory,
+        offset: LENGTH_BYTES,
+        bytes_left: u64::from_le_bytes(length),
+    };
+    ciborium::de::from_reader(reader)
+        .map_err(|e| format!("failed to decode the ledger state: {:?}", e))
+}
+
+/// A writer to the upgrades memory that grows the memory as it writes.
+struct UpgradeWriter {
+    memory: Memory,
+    /// The offset of the next write.
+    offset: u64,
+}
+
+impl UpgradeWriter {
+    fn ensure_capacity(&self, bytes: u64) -> io::Result<()> {
+        let required_pages = (bytes + WASM_PAGE_SIZE - 1) / WASM_PAGE_SIZE;
+        let current_pages = self.memory.size();
+        if required_pages > current_pages
+            && self.memory.grow(required_pages - current_pages) < 0
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("failed to grow the upgrades memory to {} pages", required_pages),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes the number of bytes written so far to the beginning of the memory.
+    fn write_length(&self) -> io::Result<()> {
+        self.ensure_capacity(LENGTH_BYTES)?;
+        let length = self.offset - LENGTH_BYTES;
+        self.memory.write(0, &length.to_le_bytes());
+        Ok(())
+    }
+}
+
+impl io::Write for UpgradeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.offset + buf.len() as u64;
+        self.ensure_capacity(end)?;
+        self.memory.write(self.offset, buf);
+        self.offset = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A reader of the upgrades memory that stops at the end of the saved state.
+struct UpgradeReader {
+    memory: Memory,
+    /// The offset of the next read.
+    offset: u64,
+    bytes_left: u64,
+}
+
+impl io::Read for UpgradeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let to_read = (buf.len() as u64).min(self.bytes_left) as usize;
+        self.memory.read(self.offset, &mut buf[..to_read]);
+        self.offset += to_read as u64;
+        self.bytes_left -= to_read as u64;
+        Ok(to_read)
+    }
+}
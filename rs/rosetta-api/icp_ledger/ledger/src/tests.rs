@@ -1,24 +1,26 @@
-use crate::Ledger;
+use crate::protobuf_usage::{ProtobufUsage, MAX_TRACKED_CALLERS};
+use crate::transfer_stats::{CallOutcome, TransferStats, MAX_HEAVY_HITTERS, WINDOW};
+use crate::{stable_balances::StableBalances, Ledger};
 use ic_base_types::{CanisterId, PrincipalId};
 use ic_ledger_canister_core::{archive::Archive, ledger as core_ledger, ledger::LedgerTransaction};
 use ic_ledger_core::{
+    balances::{Balances, BalancesStore, InspectableBalancesStore},
     block::{BlockIndex, BlockType},
     timestamp::TimeStamp,
     tokens::Tokens,
 };
 use icp_ledger::{
-    apply_operation, ArchiveOptions, Block, LedgerBalances, Memo, Operation, PaymentError,
-    Transaction, TransferError, DEFAULT_TRANSFER_FEE,
+    apply_operation, AccountIdentifier, ArchiveOptions, Block, LedgerBalances,
+    LedgerCanisterUpgradePayload, Memo, MigrationError, NotifyError, Operation, PaymentError,
+    ProtobufEndpoint, Transaction, TransferError, DEFAULT_TRANSFER_FEE,
 };
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime};
 
 #[test]
 fn balances_overflow() {
-    let balances = LedgerBalances::new();
     let mut state = Ledger {
-        balances,
         maximum_number_of_accounts: 8,
         accounts_overflow_trim_quantity: 2,
         minting_account_id: Some(PrincipalId::new_user_test_id(137).into()),
@@ -275,7 +277,63 @@ fn serialize() {
         state.blockchain.blocks.len(),
         state_decoded.blockchain.blocks.len()
     );
-    assert_eq!(state.balances.store, state_decoded.balances.store);
+    assert_eq!(
+        balances_snapshot(&state.balances.store),
+        balances_snapshot(&state_decoded.balances.store)
+    );
+}
+
+fn balances_snapshot(store: &StableBalances) -> BTreeMap<AccountIdentifier, Tokens> {
+    let mut balances = BTreeMap::new();
+    store.for_each_balance(|account, tokens| {
+        balances.insert(*account, tokens);
+    });
+    balances
+}
+
+#[test]
+fn stable_balances_migration() {
+    let account = |i: u64| AccountIdentifier::from(PrincipalId::new_user_test_id(i));
+
+    // The balances of a ledger that kept them on the heap.
+    let mut heap_balances = LedgerBalances::new();
+    for i in 0..10 {
+        heap_balances.mint(&account(i), Tokens::from_e8s(i + 1)).unwrap();
+    }
+    let heap_bytes = serde_cbor::to_vec(&heap_balances).unwrap();
+    let mut balances: Balances<AccountIdentifier, StableBalances> =
+        serde_cbor::from_slice(&heap_bytes).unwrap();
+    assert_eq!(balances.store.num_accounts_to_migrate(), 10);
+    assert_eq!(balances.store.len(), 10);
+    assert_eq!(balances.token_pool, heap_balances.token_pool);
+
+    // Updating an account moves it to the stable map.
+    balances.credit(&account(0), Tokens::from_e8s(5));
+    assert_eq!(balances.store.num_accounts_to_migrate(), 9);
+    assert_eq!(balances.store.len(), 10);
+    assert_eq!(balances.account_balance(&account(0)), Tokens::from_e8s(6));
+
+    assert_eq!(balances.store.migrate(4), 4);
+    assert_eq!(balances.store.num_accounts_to_migrate(), 5);
+    assert_eq!(balances.store.migrate(100), 5);
+    assert_eq!(balances.store.num_accounts_to_migrate(), 0);
+    assert_eq!(balances.store.migrate(100), 0);
+
+    assert_eq!(balances.store.len(), 10);
+    for i in 1..10 {
+        assert_eq!(balances.store.get_balance(&account(i)), Some(Tokens::from_e8s(i + 1)));
+    }
+
+    // Accounts with a zero balance are removed from the stable map.
+    balances.burn(&account(1), Tokens::from_e8s(2)).unwrap();
+    assert_eq!(balances.store.get_balance(&account(1)), None);
+    assert_eq!(balances.store.len(), 9);
+
+    // The migrated balances are not serialized on upgrade.
+    assert_eq!(
+        serde_cbor::to_vec(&balances.store).unwrap(),
+        serde_cbor::to_vec(&HashMap::<AccountIdentifier, Tokens>::new()).unwrap()
+    );
 }
 
 /// Check that 'created_at_time' is not too far in the past or
@@ -369,6 +427,9 @@ fn duplicate_txns() {
         controller_id: CanisterId::from_u64(876).into(),
         cycles_for_archive_creation: Some(0),
         max_transactions_per_response: None,
+        max_archive_nodes: None,
+        cycles_for_archive_top_up: None,
+        cycles_for_archive_top_ups_pool: None,
     }))));
 
     let user1 = PrincipalId::new_user_test_id(1).into();
@@ -650,6 +711,191 @@ fn test_purge() {
     assert_eq!(res6, None);
 }
 
+#[test]
+fn disable_notifications() {
+    let caller = PrincipalId::new_user_test_id(1);
+    let whitelisted = CanisterId::from_u64(100);
+    let mut ledger = Ledger {
+        send_whitelist: HashSet::from([whitelisted]),
+        ..Default::default()
+    };
+    let genesis = SystemTime::UNIX_EPOCH.into();
+
+    assert_eq!(
+        ledger.check_notification(&caller, &whitelisted, DEFAULT_TRANSFER_FEE),
+        Ok(())
+    );
+    assert_eq!(
+        ledger.check_notification(&caller, &CanisterId::from_u64(101), DEFAULT_TRANSFER_FEE),
+        Err(NotifyError::CanisterNotWhitelisted {
+            canister_id: CanisterId::from_u64(101)
+        })
+    );
+    assert_eq!(
+        ledger.check_notification(&caller, &whitelisted, Tokens::ZERO),
+        Err(NotifyError::BadFee {
+            expected_fee: DEFAULT_TRANSFER_FEE
+        })
+    );
+    ledger
+        .change_notification_state(1, genesis, true, genesis)
+        .unwrap();
+
+    ledger
+        .upgrade(LedgerCanisterUpgradePayload {
+            disable_notifications: Some(true),
+            disabled_protobuf_endpoints: None,
+        })
+        .unwrap();
+
+    assert!(ledger.notifications_disabled());
+    assert!(ledger.send_whitelist.is_empty());
+    assert_eq!(ledger.blocks_notified.get(1), None);
+    assert_eq!(
+        ledger.check_notification(&caller, &whitelisted, DEFAULT_TRANSFER_FEE),
+        Err(NotifyError::NotificationsDisabled)
+    );
+    assert_eq!(
+        ledger.change_notification_state(2, genesis, true, genesis),
+        Err(NotifyError::NotificationsDisabled.to_string())
+    );
+
+    // Upgrades that keep notifications as they are succeed, enabling them fails.
+    assert_eq!(
+        ledger.upgrade(LedgerCanisterUpgradePayload::default()),
+        Ok(())
+    );
+    assert!(ledger
+        .upgrade(LedgerCanisterUpgradePayload {
+            disable_notifications: Some(false),
+            disabled_protobuf_endpoints: None,
+        })
+        .is_err());
+    assert!(ledger.notifications_disabled());
+}
+
+#[test]
+fn transfer_stats_windows() {
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let start = TimeStamp::from(SystemTime::UNIX_EPOCH + 1000 * WINDOW);
+    let mut stats = TransferStats::default();
+
+    stats.record(start, p1, CallOutcome::Transfer);
+    stats.record(start, p1, CallOutcome::Duplicate);
+    stats.record(start + WINDOW / 2, p2, CallOutcome::Mint);
+
+    let (current, previous) = stats.window_counts(start + WINDOW / 2);
+    assert_eq!(current.get(CallOutcome::Transfer), 1);
+    assert_eq!(current.get(CallOutcome::Duplicate), 1);
+    assert_eq!(current.get(CallOutcome::Mint), 1);
+    assert_eq!(current.total(), 3);
+    assert_eq!(previous.total(), 0);
+    assert_eq!(stats.max_calls_per_principal(start), 2);
+
+    // The current window becomes the previous one.
+    stats.record(start + WINDOW, p2, CallOutcome::Burn);
+    let (current, previous) = stats.window_counts(start + WINDOW);
+    assert_eq!(current.total(), 1);
+    assert_eq!(current.get(CallOutcome::Burn), 1);
+    assert_eq!(previous.total(), 3);
+
+    let heavy_hitters = stats.heavy_hitters(start + WINDOW);
+    assert_eq!(heavy_hitters.len(), 2);
+    assert_eq!(heavy_hitters[0].total_calls, 3);
+    assert_eq!(heavy_hitters[0].heavy_hitters[0].principal, p1);
+    assert_eq!(heavy_hitters[0].heavy_hitters[0].calls, 2);
+    assert_eq!(heavy_hitters[1].heavy_hitters[0].principal, p2);
+
+    // Windows without calls are empty.
+    let (current, previous) = stats.window_counts(start + 3 * WINDOW);
+    assert_eq!(current.total(), 0);
+    assert_eq!(previous.total(), 0);
+    assert!(stats.heavy_hitters(start + 3 * WINDOW).is_empty());
+    assert_eq!(stats.totals().total(), 4);
+}
+
+#[test]
+fn transfer_stats_track_heavy_hitters() {
+    let heavy = PrincipalId::new_user_test_id(1);
+    let now = TimeStamp::from(SystemTime::UNIX_EPOCH + WINDOW);
+    let mut stats = TransferStats::default();
+
+    // Many principals call once, one principal calls often.
+    for i in 0..10 * MAX_HEAVY_HITTERS as u64 {
+        stats.record(now, PrincipalId::new_user_test_id(100 + i), CallOutcome::Transfer);
+        if i % 5 == 0 {
+            stats.record(now, heavy, CallOutcome::Transfer);
+        }
+    }
+
+    let heavy_hitters = stats.heavy_hitters(now).pop().unwrap();
+    assert!(heavy_hitters.heavy_hitters.len() <= MAX_HEAVY_HITTERS);
+    let top = &heavy_hitters.heavy_hitters[0];
+    assert_eq!(top.principal, heavy);
+    assert!(top.calls >= 2 * MAX_HEAVY_HITTERS as u64);
+    assert!(top.calls - top.max_overcount <= 2 * MAX_HEAVY_HITTERS as u64);
+}
+
+#[test]
+fn protobuf_usage_bounds_tracked_callers() {
+    let frequent = PrincipalId::new_user_test_id(1);
+    let mut usage = ProtobufUsage::default();
+
+    usage.record(ProtobufEndpoint::SendPb, frequent);
+    for i in 0..2 * MAX_TRACKED_CALLERS as u64 {
+        usage.record(ProtobufEndpoint::SendPb, PrincipalId::new_user_test_id(100 + i));
+    }
+    usage.record(ProtobufEndpoint::SendPb, frequent);
+
+    assert_eq!(usage.calls(ProtobufEndpoint::SendPb), 2 * MAX_TRACKED_CALLERS as u64 + 2);
+    assert_eq!(usage.num_callers(ProtobufEndpoint::SendPb), MAX_TRACKED_CALLERS);
+    assert_eq!(usage.calls(ProtobufEndpoint::NotifyPb), 0);
+
+    let send_usage = usage
+        .usage(|endpoint| endpoint == ProtobufEndpoint::SendPb)
+        .into_iter()
+        .find(|u| u.endpoint == ProtobufEndpoint::SendPb)
+        .unwrap();
+    assert!(send_usage.disabled);
+    assert_eq!(send_usage.callers[0].principal, frequent);
+    assert_eq!(send_usage.callers[0].calls, 2);
+    assert_eq!(send_usage.untracked_calls, MAX_TRACKED_CALLERS as u64 + 1);
+}
+
+#[test]
+fn disable_protobuf_endpoints() {
+    let mut ledger = Ledger::default();
+    assert_eq!(ledger.check_protobuf_endpoint(ProtobufEndpoint::SendPb), Ok(()));
+
+    ledger
+        .upgrade(LedgerCanisterUpgradePayload {
+            disable_notifications: None,
+            disabled_protobuf_endpoints: Some(vec![ProtobufEndpoint::SendPb]),
+        })
+        .unwrap();
+    assert_eq!(
+        ledger.check_protobuf_endpoint(ProtobufEndpoint::SendPb),
+        Err(MigrationError::EndpointDisabled {
+            endpoint: ProtobufEndpoint::SendPb
+        })
+    );
+    assert_eq!(ledger.check_protobuf_endpoint(ProtobufEndpoint::BlockPb), Ok(()));
+
+    // Upgrades without the setting keep it, an empty list enables all endpoints.
+    ledger
+        .upgrade(LedgerCanisterUpgradePayload::default())
+        .unwrap();
+    assert!(ledger.protobuf_endpoint_disabled(ProtobufEndpoint::SendPb));
+    ledger
+        .upgrade(LedgerCanisterUpgradePayload {
+            disable_notifications: None,
+            disabled_protobuf_endpoints: Some(vec![]),
+        })
+        .unwrap();
+    assert!(!ledger.protobuf_endpoint_disabled(ProtobufEndpoint::SendPb));
+}
+
 fn apply_at(ledger: &mut Ledger, op: &Operation, ts: TimeStamp) -> BlockIndex {
     let memo = Memo::default();
     ledger
@@ -781,3 +1027,26 @@ fn test_transaction_hash_consistency() {
         "Transaction hash must be stable."
     );
 }
+
+#[test]
+fn recent_blocks_per_second() {
+    let mut state = Ledger::default();
+    assert_eq!(state.recent_blocks_per_second(), 0.0);
+
+    let start = TimeStamp::from_nanos_since_unix_epoch(1_000_000_000);
+    for i in 0..11 {
+        state
+            .add_payment_with_timestamp(
+                Memo(i),
+                Operation::Mint {
+                    to: PrincipalId::new_user_test_id(i).into(),
+                    amount: Tokens::from_e8s(1),
+                },
+                None,
+                start + Duration::from_millis(500 * i),
+            )
+            .unwrap();
+    }
+    // 10 blocks in 5 seconds.
+    assert_eq!(state.recent_blocks_per_second(), 2.0);
+}
@@ -0,0 +1,258 @@
+//! Statistics of the transfer calls that help to analyze incidents without an
+//! external indexer.
+//!
+//! The ledger counts the calls by outcome, in total and in fixed time windows,
+//! and tracks the principals that send the most transfers in each window with
+//! a Space-Saving sketch, which takes a bounded amount of memory no matter how
+//! many principals call the ledger. The statistics live on the heap and start
+//! from scratch after an upgrade. Calls that trap are not counted: the trap
+//! reverts the changes to the statistics as well.
+
+use ic_base_types::PrincipalId;
+use ic_ledger_core::timestamp::TimeStamp;
+use icp_ledger::{Operation, TransferError, TransferHeavyHitter, TransferHeavyHitters};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The length of the time windows.
+pub const WINDOW: Duration = Duration::from_secs(60 * 60);
+const WINDOW_NANOS: u64 = WINDOW.as_nanos() as u64;
+
+/// The number of principals that the sketch tracks per window. The sketch
+/// tracks every principal that sends more than 1/`MAX_HEAVY_HITTERS` of the
+/// calls in a window.
+pub const MAX_HEAVY_HITTERS: usize = 100;
+
+thread_local! {
+    static TRANSFER_STATS: RefCell<TransferStats> = RefCell::new(TransferStats::default());
+}
+
+/// Records a transfer call of `caller` at time `now`.
+pub fn record(now: TimeStamp, caller: PrincipalId, outcome: CallOutcome) {
+    TRANSFER_STATS.with(|stats| stats.borrow_mut().record(now, caller, outcome))
+}
+
+pub fn with_stats<R>(f: impl FnOnce(&TransferStats) -> R) -> R {
+    TRANSFER_STATS.with(|stats| f(&stats.borrow()))
+}
+
+const NUM_OUTCOMES: usize = 5;
+
+/// The outcome of a transfer call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallOutcome {
+    Transfer,
+    Mint,
+    Burn,
+    /// The call was rejected because it duplicates an earlier transaction.
+    Duplicate,
+    /// The call was rejected for any other reason.
+    Rejected,
+}
+
+impl CallOutcome {
+    pub const ALL: [CallOutcome; NUM_OUTCOMES] = [
+        CallOutcome::Transfer,
+        CallOutcome::Mint,
+        CallOutcome::Burn,
+        CallOutcome::Duplicate,
+        CallOutcome::Rejected,
+    ];
+
+    pub fn from_operation(operation: &Operation) -> Self {
+        match operation {
+            Operation::Transfer { .. } => CallOutcome::Transfer,
+            Operation::Mint { .. } => CallOutcome::Mint,
+            Operation::Burn { .. } => CallOutcome::Burn,
+        }
+    }
+
+    pub fn from_error(error: &TransferError) -> Self {
+        match error {
+            TransferError::TxDuplicate { .. } => CallOutcome::Duplicate,
+            _ => CallOutcome::Rejected,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CallOutcome::Transfer => "transfer",
+            CallOutcome::Mint => "mint",
+            CallOutcome::Burn => "burn",
+            CallOutcome::Duplicate => "duplicate",
+            CallOutcome::Rejected => "rejected",
+        }
+    }
+}
+
+/// The number of calls by outcome.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallCounts([u64; NUM_OUTCOMES]);
+
+impl CallCounts {
+    pub fn get(&self, outcome: CallOutcome) -> u64 {
+        self.0[outcome as usize]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.0.iter().sum()
+    }
+
+    fn add(&mut self, outcome: CallOutcome) {
+        self.0[outcome as usize] += 1;
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Counter {
+    count: u64,
+    overcount: u64,
+}
+
+/// A Space-Saving sketch of the principals with the most calls.
+///
+/// The sketch keeps up to `MAX_HEAVY_HITTERS` counters. If a principal
+/// without a counter calls while all counters are taken, it takes over the
+/// counter with the lowest count and inherits that count as the overcount.
+#[derive(Clone, Debug, Default)]
+struct HeavyHitters {
+    counters: BTreeMap<PrincipalId, Counter>,
+}
+
+impl HeavyHitters {
+    fn add(&mut self, principal: PrincipalId) {
+        if let Some(counter) = self.counters.get_mut(&principal) {
+            counter.count += 1;
+            return;
+        }
+        let overcount = if self.counters.len() < MAX_HEAVY_HITTERS {
+            0
+        } else {
+            let (evicted, min) = self
+                .counters
+                .iter()
+                .min_by_key(|(_, counter)| counter.count)
+                .map(|(principal, counter)| (*principal, counter.count))
+                .unwrap();
+            self.counters.remove(&evicted);
+            min
+        };
+        self.counters.insert(
+            principal,
+            Counter {
+                count: overcount + 1,
+                overcount,
+            },
+        );
+    }
+
+    fn max_count(&self) -> u64 {
+        self.counters.values().map(|c| c.count).max().unwrap_or(0)
+    }
+
+    /// Returns the tracked principals, the most calls first.
+    fn sorted(&self) -> Vec<TransferHeavyHitter> {
+        let mut heavy_hitters: Vec<_> = self
+            .counters
+            .iter()
+            .map(|(principal, counter)| TransferHeavyHitter {
+                principal: *principal,
+                calls: counter.count,
+                max_overcount: counter.overcount,
+            })
+            .collect();
+        heavy_hitters.sort_by(|a, b| b.calls.cmp(&a.calls));
+        heavy_hitters
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct WindowStats {
+    /// The start of the window in nanoseconds since the Unix epoch.
+    start: u64,
+    counts: CallCounts,
+    heavy_hitters: HeavyHitters,
+}
+
+#[derive(Debug, Default)]
+pub struct TransferStats {
+    totals: CallCounts,
+    current: WindowStats,
+    previous: Option<WindowStats>,
+}
+
+fn window_start(now: TimeStamp) -> u64 {
+    let nanos = now.as_nanos_since_unix_epoch();
+    nanos - nanos % WINDOW_NANOS
+}
+
+impl TransferStats {
+    pub fn record(&mut self, now: TimeStamp, caller: PrincipalId, outcome: CallOutcome) {
+        let start = window_start(now);
+        if start > self.current.start {
+            let current = std::mem::replace(
+                &mut self.current,
+                WindowStats {
+                    start,
+                    ..WindowStats::default()
+                },
+            );
+            self.previous = Some(current).filter(|w| w.start + WINDOW_NANOS == start);
+        }
+        self.totals.add(outcome);
+        self.current.counts.add(outcome);
+        self.current.heavy_hitters.add(caller);
+    }
+
+    /// Returns the number of calls since the last upgrade.
+    pub fn totals(&self) -> &CallCounts {
+        &self.totals
+    }
+
+    /// Returns the statistics of the window that contains `now` and of the
+    /// window before it.
+    fn windows(&self, now: TimeStamp) -> (Option<&WindowStats>, Option<&WindowStats>) {
+        let start = window_start(now);
+        let find = |start: u64| {
+            std::iter::once(&self.current)
+                .chain(self.previous.as_ref())
+                .find(|w| w.start == start)
+        };
+        (find(start), start.checked_sub(WINDOW_NANOS).and_then(find))
+    }
+
+    /// Returns the number of calls in the window that contains `now` and in
+    /// the window before it.
+    pub fn window_counts(&self, now: TimeStamp) -> (CallCounts, CallCounts) {
+        let (current, previous) = self.windows(now);
+        let counts = |w: Option<&WindowStats>| w.map(|w| w.counts.clone()).unwrap_or_default();
+        (counts(current), counts(previous))
+    }
+
+    /// Returns an upper bound of the number of calls of the most active
+    /// principal in the window that contains `now`.
+    pub fn max_calls_per_principal(&self, now: TimeStamp) -> u64 {
+        self.windows(now)
+            .0
+            .map(|w| w.heavy_hitters.max_count())
+            .unwrap_or(0)
+    }
+
+    /// Returns the principals with the most calls in the window before the
+    /// one that contains `now` and in the window that contains `now`, in this
+    /// order.
+    pub fn heavy_hitters(&self, now: TimeStamp) -> Vec<TransferHeavyHitters> {
+        let (current, previous) = self.windows(now);
+        [previous, current]
+            .into_iter()
+            .flatten()
+            .map(|w| TransferHeavyHitters {
+                window_start: w.start,
+                window_length_nanos: WINDOW_NANOS,
+                total_calls: w.counts.total(),
+                heavy_hitters: w.heavy_hitters.sorted(),
+            })
+            .collect()
+    }
+}
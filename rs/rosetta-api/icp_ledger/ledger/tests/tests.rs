@@ -1,6 +1,26 @@
+use candid::{Decode, Encode};
+use ic_base_types::PrincipalId;
+use ic_crypto_tree_hash::MixedHashTree;
+use ic_icrc1::{
+    icrc3::{self, DataCertificate},
+    Account,
+};
+use ic_ledger_canister_core::archive::{VerifyBlocksArgs, VerifyBlocksResult, MAX_BLOCKS_TO_VERIFY};
+use ic_ledger_core::block::{EncodedBlock, HashOf};
 use ic_ledger_core::Tokens;
+use ic_state_machine_tests::{CanisterId, Cycles, StateMachine, WasmResult};
 use icp_ledger::LedgerCanisterInitPayload as InitArgs;
-use std::collections::HashSet;
+use dfn_protobuf::ProtoBuf;
+use icp_ledger::{
+    protobuf, AccountIdentifier, ArchiveOptions, ArchiveTopUpEvent, ArchiveTopUpEventKind,
+    Archives, BlockIndex, CertifiedTip, GetBlocksArgs, LedgerCanisterUpgradePayload, Memo,
+    MigrationError, NotifyCanisterArgs, NotifyError, ProtobufEndpoint, ProtobufEndpointCaller,
+    ProtobufEndpointUsage, RearchiveNodeArgs, SendArgs, Subaccount, TransferArgs, TransferError,
+    DEFAULT_TRANSFER_FEE,
+};
+use on_wire::{FromWire, IntoWire};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
 fn ledger_wasm() -> Vec<u8> {
     ic_test_utilities_load_wasm::load_wasm(
@@ -10,6 +30,14 @@ fn ledger_wasm() -> Vec<u8> {
     )
 }
 
+fn ledger_wasm_notify_method() -> Vec<u8> {
+    ic_test_utilities_load_wasm::load_wasm(
+        std::env::var("CARGO_MANIFEST_DIR").unwrap(),
+        "ledger-canister",
+        &["notify-method"],
+    )
+}
+
 fn encode_init_args(args: ic_icrc1_ledger_sm_tests::InitArgs) -> InitArgs {
     let initial_values = args
         .initial_balances
@@ -30,6 +58,198 @@ fn encode_init_args(args: ic_icrc1_ledger_sm_tests::InitArgs) -> InitArgs {
     }
 }
 
+fn account_identifier_to_icrc1_account(
+    env: &StateMachine,
+    ledger: CanisterId,
+    account: AccountIdentifier,
+) -> Option<Account> {
+    Decode!(
+        &env.query(
+            ledger,
+            "account_identifier_to_icrc1_account",
+            Encode!(&account.to_address()).unwrap()
+        )
+        .expect("failed to look up the account")
+        .bytes(),
+        Option<Account>
+    )
+    .expect("failed to decode the account lookup response")
+}
+
+fn transfer(
+    env: &StateMachine,
+    ledger: CanisterId,
+    from: PrincipalId,
+    to: AccountIdentifier,
+    amount: u64,
+) -> Result<BlockIndex, TransferError> {
+    Decode!(
+        &env.execute_ingress_as(
+            from,
+            ledger,
+            "transfer",
+            Encode!(&TransferArgs {
+                memo: Memo(0),
+                amount: Tokens::from_e8s(amount),
+                fee: DEFAULT_TRANSFER_FEE,
+                from_subaccount: None,
+                to: to.to_address(),
+                created_at_time: None,
+            })
+            .unwrap()
+        )
+        .expect("failed to transfer funds")
+        .bytes(),
+        Result<BlockIndex, TransferError>
+    )
+    .expect("failed to decode transfer response")
+}
+
+/// Calls `notify_dfx` and returns the reject message.
+fn notify_rejection(
+    env: &StateMachine,
+    ledger: CanisterId,
+    from: PrincipalId,
+    block_height: BlockIndex,
+    to_canister: CanisterId,
+) -> String {
+    let result = env
+        .execute_ingress_as(
+            from,
+            ledger,
+            "notify_dfx",
+            Encode!(&NotifyCanisterArgs {
+                block_height,
+                max_fee: DEFAULT_TRANSFER_FEE,
+                from_subaccount: None,
+                to_canister,
+                to_subaccount: None,
+            })
+            .unwrap(),
+        )
+        .expect("failed to call notify_dfx");
+    match result {
+        WasmResult::Reject(message) => message,
+        WasmResult::Reply(_) => panic!("expected notify_dfx to reject"),
+    }
+}
+
+fn archives(env: &StateMachine, ledger: CanisterId) -> Vec<CanisterId> {
+    Decode!(
+        &env.query(ledger, "archives", Encode!().unwrap())
+            .expect("failed to list the archives")
+            .bytes(),
+        Archives
+    )
+    .expect("failed to decode the archives")
+    .archives
+    .into_iter()
+    .map(|archive| archive.canister_id)
+    .collect()
+}
+
+fn get_encoded_blocks(
+    env: &StateMachine,
+    archive: CanisterId,
+    start: BlockIndex,
+    length: usize,
+) -> Vec<EncodedBlock> {
+    Decode!(
+        &env.query(
+            archive,
+            "get_encoded_blocks",
+            Encode!(&GetBlocksArgs { start, length }).unwrap()
+        )
+        .expect("failed to get the encoded blocks")
+        .bytes(),
+        Vec<EncodedBlock>
+    )
+    .expect("failed to decode the encoded blocks")
+}
+
+fn tip_certificate(env: &StateMachine, ledger: CanisterId) -> CertifiedTip {
+    let cert = Decode!(
+        &env.query(ledger, "icrc3_get_tip_certificate", Encode!().unwrap())
+            .expect("failed to query the tip certificate")
+            .bytes(),
+        Option<DataCertificate>
+    )
+    .expect("failed to decode icrc3_get_tip_certificate response")
+    .expect("the ledger returned no certificate");
+    assert!(!cert.certificate.is_empty());
+    let hash_tree: MixedHashTree =
+        ciborium::de::from_reader(&cert.hash_tree[..]).expect("failed to decode the hash tree");
+    let tip = CertifiedTip::from_hash_tree(hash_tree.clone()).expect("invalid hash tree");
+    assert_eq!(tip.hash_tree().digest(), hash_tree.digest());
+    tip
+}
+
+fn upgrade_args(disable_notifications: Option<bool>) -> Vec<u8> {
+    Encode!(&Some(LedgerCanisterUpgradePayload {
+        disable_notifications,
+        disabled_protobuf_endpoints: None,
+    }))
+    .unwrap()
+}
+
+fn send_pb(
+    env: &StateMachine,
+    ledger: CanisterId,
+    from: PrincipalId,
+    to: AccountIdentifier,
+    amount: u64,
+) -> Result<BlockIndex, String> {
+    let args = SendArgs {
+        memo: Memo(0),
+        amount: Tokens::from_e8s(amount),
+        fee: DEFAULT_TRANSFER_FEE,
+        from_subaccount: None,
+        to,
+        created_at_time: None,
+    };
+    let result = env
+        .execute_ingress_as(from, ledger, "send_pb", ProtoBuf(args).into_bytes().unwrap())
+        .map_err(|err| err.description().to_string())?;
+    match result {
+        WasmResult::Reply(bytes) => Ok(ProtoBuf::<BlockIndex>::from_bytes(bytes).unwrap().0),
+        WasmResult::Reject(message) => Err(message),
+    }
+}
+
+fn tip_of_chain_pb(
+    env: &StateMachine,
+    ledger: CanisterId,
+) -> Result<protobuf::TipOfChainResponse, String> {
+    let result = env
+        .query(
+            ledger,
+            "tip_of_chain_pb",
+            ProtoBuf(protobuf::TipOfChainRequest {}).into_bytes().unwrap(),
+        )
+        .map_err(|err| err.description().to_string())?;
+    match result {
+        WasmResult::Reply(bytes) => Ok(ProtoBuf::from_bytes(bytes).unwrap().0),
+        WasmResult::Reject(message) => Err(message),
+    }
+}
+
+fn protobuf_endpoint_usage(
+    env: &StateMachine,
+    ledger: CanisterId,
+    endpoint: ProtobufEndpoint,
+) -> ProtobufEndpointUsage {
+    Decode!(
+        &env.query(ledger, "get_protobuf_endpoint_usage", Encode!().unwrap())
+            .expect("failed to query the protobuf endpoint usage")
+            .bytes(),
+        Vec<ProtobufEndpointUsage>
+    )
+    .expect("failed to decode the protobuf endpoint usage")
+    .into_iter()
+    .find(|usage| usage.endpoint == endpoint)
+    .expect("missing protobuf endpoint usage")
+}
+
 #[test]
 fn test_balance_of() {
     ic_icrc1_ledger_sm_tests::test_balance_of(ledger_wasm(), encode_init_args)
@@ -49,3 +269,561 @@ fn test_total_supply() {
 fn test_minting_account() {
     ic_icrc1_ledger_sm_tests::test_minting_account(ledger_wasm(), encode_init_args)
 }
+
+#[test]
+fn test_icrc3_get_blocks() {
+    ic_icrc1_ledger_sm_tests::test_icrc3_get_blocks(
+        ledger_wasm(),
+        encode_init_args,
+        icp_ledger::validate_icrc3_block,
+    )
+}
+
+#[test]
+fn test_icrc3_get_blocks_ranges() {
+    ic_icrc1_ledger_sm_tests::test_icrc3_get_blocks_ranges(ledger_wasm(), encode_init_args)
+}
+
+#[test]
+fn test_icrc3_supported_block_types() {
+    ic_icrc1_ledger_sm_tests::test_icrc3_supported_block_types(
+        ledger_wasm(),
+        encode_init_args,
+        icp_ledger::validate_icrc3_block,
+    )
+}
+
+#[test]
+fn test_icrc3_blocks_follow_the_advertised_schemas() {
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let (env, canister_id) = ic_icrc1_ledger_sm_tests::setup(
+        ledger_wasm(),
+        encode_init_args,
+        vec![(Account::from(p1), 10_000_000)],
+    );
+    transfer(&env, canister_id, p1, p2.into(), 1_000_000).expect("transfer failed");
+
+    let supported_block_types: Vec<String> =
+        ic_icrc1_ledger_sm_tests::icrc3_supported_block_types(&env, canister_id)
+            .into_iter()
+            .map(|block_type| block_type.block_type)
+            .collect();
+    let blocks = ic_icrc1_ledger_sm_tests::icrc3_get_blocks(
+        &env,
+        canister_id,
+        vec![icrc3::GetBlocksArgs::new(0, 10)],
+    )
+    .blocks;
+    let block_types: Vec<String> = blocks
+        .iter()
+        .zip(0..)
+        .map(|(block, index)| icp_ledger::validate_icrc3_block(index, &block.block).unwrap())
+        .collect();
+    assert_eq!(block_types, ["icp_mint", "icp_xfer"]);
+    for block_type in block_types {
+        assert!(supported_block_types.contains(&block_type));
+    }
+}
+
+#[test]
+fn test_icrc3_get_archives_without_archives() {
+    ic_icrc1_ledger_sm_tests::test_icrc3_get_archives_without_archives(
+        ledger_wasm(),
+        encode_init_args,
+    )
+}
+
+#[test]
+fn test_account_identifier_to_icrc1_account() {
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let from = Account {
+        owner: p1,
+        subaccount: Some([1; 32]),
+    };
+    let (env, canister_id) = ic_icrc1_ledger_sm_tests::setup(
+        ledger_wasm(),
+        encode_init_args,
+        vec![(from.clone(), 10_000_000)],
+    );
+    let from_id = AccountIdentifier::from(from.clone());
+    let to_id = AccountIdentifier::new(p2, None);
+
+    // The ledger only knows the accounts that their owners sent tokens from.
+    assert_eq!(
+        None,
+        account_identifier_to_icrc1_account(&env, canister_id, from_id)
+    );
+
+    Decode!(
+        &env.execute_ingress_as(
+            p1,
+            canister_id,
+            "transfer",
+            Encode!(&TransferArgs {
+                memo: Memo(0),
+                amount: Tokens::from_e8s(1_000_000),
+                fee: DEFAULT_TRANSFER_FEE,
+                from_subaccount: Some(Subaccount([1; 32])),
+                to: to_id.to_address(),
+                created_at_time: None,
+            })
+            .unwrap()
+        )
+        .expect("failed to transfer funds")
+        .bytes(),
+        Result<BlockIndex, TransferError>
+    )
+    .expect("failed to decode transfer response")
+    .expect("transfer failed");
+
+    assert_eq!(
+        Some(from),
+        account_identifier_to_icrc1_account(&env, canister_id, from_id)
+    );
+    assert_eq!(
+        None,
+        account_identifier_to_icrc1_account(&env, canister_id, to_id)
+    );
+}
+
+#[test]
+fn test_disable_notifications_on_upgrade() {
+    let env = StateMachine::new();
+    let p1 = PrincipalId::new_user_test_id(1);
+    let minter = PrincipalId::new_user_test_id(1000);
+    let whitelisted = CanisterId::from_u64(100);
+    let not_whitelisted = CanisterId::from_u64(101);
+
+    let init_args = InitArgs::builder()
+        .minting_account(AccountIdentifier::new(minter, None))
+        .initial_values(HashMap::from([(
+            AccountIdentifier::new(p1, None),
+            Tokens::from_e8s(10_000_000),
+        )]))
+        .send_whitelist(HashSet::from([whitelisted]))
+        .build()
+        .unwrap();
+    let ledger = env
+        .install_canister(ledger_wasm_notify_method(), Encode!(&init_args).unwrap(), None)
+        .expect("failed to install the ledger");
+
+    let block_height = transfer(
+        &env,
+        ledger,
+        p1,
+        AccountIdentifier::new(whitelisted.get(), None),
+        1_000_000,
+    )
+    .expect("transfer failed");
+
+    assert_eq!(
+        notify_rejection(&env, ledger, p1, block_height, not_whitelisted),
+        NotifyError::CanisterNotWhitelisted {
+            canister_id: not_whitelisted
+        }
+        .to_string()
+    );
+
+    // Upgrades without arguments or without the flag keep notifications.
+    env.upgrade_canister(ledger, ledger_wasm_notify_method(), vec![])
+        .expect("failed to upgrade the ledger without arguments");
+    env.upgrade_canister(ledger, ledger_wasm_notify_method(), upgrade_args(None))
+        .expect("failed to upgrade the ledger");
+    assert_eq!(
+        notify_rejection(&env, ledger, p1, block_height, not_whitelisted),
+        NotifyError::CanisterNotWhitelisted {
+            canister_id: not_whitelisted
+        }
+        .to_string()
+    );
+
+    env.upgrade_canister(ledger, ledger_wasm_notify_method(), upgrade_args(Some(true)))
+        .expect("failed to disable notifications");
+
+    assert_eq!(
+        notify_rejection(&env, ledger, p1, block_height, whitelisted),
+        NotifyError::NotificationsDisabled.to_string()
+    );
+    transfer(
+        &env,
+        ledger,
+        p1,
+        AccountIdentifier::new(whitelisted.get(), None),
+        1_000_000,
+    )
+    .expect("transfers must keep working after disabling notifications");
+
+    // The flag survives upgrades and cannot be reverted.
+    env.upgrade_canister(ledger, ledger_wasm_notify_method(), vec![])
+        .expect("failed to upgrade the ledger without arguments");
+    assert_eq!(
+        notify_rejection(&env, ledger, p1, block_height, whitelisted),
+        NotifyError::NotificationsDisabled.to_string()
+    );
+    env.upgrade_canister(ledger, ledger_wasm_notify_method(), upgrade_args(Some(false)))
+        .expect_err("notifications must not be enabled again");
+    assert_eq!(
+        notify_rejection(&env, ledger, p1, block_height, whitelisted),
+        NotifyError::NotificationsDisabled.to_string()
+    );
+}
+
+#[test]
+fn test_protobuf_endpoint_deprecation() {
+    let env = StateMachine::new();
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let minter = PrincipalId::new_user_test_id(1000);
+    let to = AccountIdentifier::new(PrincipalId::new_user_test_id(3), None);
+
+    let init_args = InitArgs::builder()
+        .minting_account(AccountIdentifier::new(minter, None))
+        .initial_values(HashMap::from([
+            (AccountIdentifier::new(p1, None), Tokens::from_e8s(10_000_000)),
+            (AccountIdentifier::new(p2, None), Tokens::from_e8s(10_000_000)),
+        ]))
+        .build()
+        .unwrap();
+    let ledger = env
+        .install_canister(ledger_wasm(), Encode!(&init_args).unwrap(), None)
+        .expect("failed to install the ledger");
+
+    // Query responses carry a deprecation warning.
+    let tip = tip_of_chain_pb(&env, ledger).expect("tip_of_chain_pb failed");
+    assert_eq!(
+        tip.deprecation_warning,
+        Some(ProtobufEndpoint::TipOfChainPb.deprecation_warning())
+    );
+
+    // Update calls are counted by caller.
+    send_pb(&env, ledger, p1, to, 1_000).expect("send_pb failed");
+    send_pb(&env, ledger, p1, to, 2_000).expect("send_pb failed");
+    send_pb(&env, ledger, p2, to, 3_000).expect("send_pb failed");
+    assert_eq!(
+        protobuf_endpoint_usage(&env, ledger, ProtobufEndpoint::SendPb),
+        ProtobufEndpointUsage {
+            endpoint: ProtobufEndpoint::SendPb,
+            calls: 3,
+            callers: vec![
+                ProtobufEndpointCaller {
+                    principal: p1,
+                    calls: 2
+                },
+                ProtobufEndpointCaller {
+                    principal: p2,
+                    calls: 1
+                },
+            ],
+            untracked_calls: 0,
+            disabled: false,
+        }
+    );
+
+    let disable = |endpoints: Vec<ProtobufEndpoint>| {
+        Encode!(&Some(LedgerCanisterUpgradePayload {
+            disable_notifications: None,
+            disabled_protobuf_endpoints: Some(endpoints),
+        }))
+        .unwrap()
+    };
+    env.upgrade_canister(
+        ledger,
+        ledger_wasm(),
+        disable(vec![ProtobufEndpoint::SendPb, ProtobufEndpoint::TipOfChainPb]),
+    )
+    .expect("failed to disable the protobuf endpoints");
+
+    let err = send_pb(&env, ledger, p1, to, 1_000).expect_err("send_pb must be disabled");
+    assert!(
+        err.contains(
+            &MigrationError::EndpointDisabled {
+                endpoint: ProtobufEndpoint::SendPb
+            }
+            .to_string()
+        ),
+        "unexpected error: {}",
+        err
+    );
+    let err = tip_of_chain_pb(&env, ledger).expect_err("tip_of_chain_pb must be disabled");
+    assert!(
+        err.contains(
+            &MigrationError::EndpointDisabled {
+                endpoint: ProtobufEndpoint::TipOfChainPb
+            }
+            .to_string()
+        ),
+        "unexpected error: {}",
+        err
+    );
+    transfer(&env, ledger, p1, to, 1_000).expect("the Candid endpoints must keep working");
+
+    let usage = protobuf_endpoint_usage(&env, ledger, ProtobufEndpoint::SendPb);
+    assert!(usage.disabled);
+    assert_eq!(usage.calls, 0);
+
+    // The setting survives upgrades without arguments and can be reverted.
+    env.upgrade_canister(ledger, ledger_wasm(), vec![])
+        .expect("failed to upgrade the ledger without arguments");
+    assert!(send_pb(&env, ledger, p1, to, 1_000).is_err());
+    env.upgrade_canister(ledger, ledger_wasm(), disable(vec![]))
+        .expect("failed to enable the protobuf endpoints");
+    send_pb(&env, ledger, p1, to, 1_000).expect("send_pb must be enabled again");
+    tip_of_chain_pb(&env, ledger).expect("tip_of_chain_pb must be enabled again");
+}
+
+#[test]
+fn test_verify_and_rearchive_node() {
+    let env = StateMachine::new();
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let init_args = InitArgs::builder()
+        .minting_account(AccountIdentifier::new(PrincipalId::new_user_test_id(1000), None))
+        .initial_values(HashMap::from([(
+            AccountIdentifier::new(p1, None),
+            Tokens::from_e8s(10_000_000),
+        )]))
+        .archive_options(ArchiveOptions {
+            trigger_threshold: 10,
+            num_blocks_to_archive: 5,
+            node_max_memory_size_bytes: None,
+            max_message_size_bytes: None,
+            controller_id: PrincipalId::new_user_test_id(100),
+            cycles_for_archive_creation: None,
+            max_transactions_per_response: None,
+            max_archive_nodes: None,
+            cycles_for_archive_top_up: None,
+            cycles_for_archive_top_ups_pool: None,
+        })
+        .build()
+        .unwrap();
+    let ledger = env
+        .install_canister(ledger_wasm(), Encode!(&init_args).unwrap(), None)
+        .expect("failed to install the ledger");
+    for _ in 0..10 {
+        transfer(&env, ledger, p1, AccountIdentifier::new(p2, None), 10_000)
+            .expect("transfer failed");
+    }
+    let old_archive = match archives(&env, ledger)[..] {
+        [archive] => archive,
+        ref other => panic!("expected one archive, got {:?}", other),
+    };
+    let archived_blocks = get_encoded_blocks(&env, old_archive, 0, 100);
+    assert!(!archived_blocks.is_empty());
+
+    // Only the controller of the ledger can verify and re-archive nodes.
+    let verify = |caller: PrincipalId, archive: CanisterId| {
+        env.execute_ingress_as(caller, ledger, "verify_archive_node", Encode!(&archive).unwrap())
+            .map(|res| Decode!(&res.bytes(), Result<(), String>).unwrap())
+    };
+    verify(p1, old_archive).expect_err("only the controller can verify archive nodes");
+    let controller = PrincipalId::new_anonymous();
+    assert_eq!(verify(controller, old_archive).unwrap(), Ok(()));
+
+    // Archive nodes verify a bounded number of blocks per call.
+    let args = VerifyBlocksArgs {
+        start: 0,
+        length: MAX_BLOCKS_TO_VERIFY + 1,
+        last_hash: HashOf::new([0; 32]),
+    };
+    let result = Decode!(
+        &env.execute_ingress_as(
+            PrincipalId::new_user_test_id(100),
+            old_archive,
+            "verify_blocks",
+            Encode!(&args).unwrap()
+        )
+        .expect("failed to call verify_blocks")
+        .bytes(),
+        VerifyBlocksResult
+    )
+    .unwrap();
+    assert!(
+        matches!(&result, Err(e) if e.message.starts_with("cannot verify more than")),
+        "unexpected result: {:?}",
+        result
+    );
+
+    let args = RearchiveNodeArgs {
+        archive: old_archive,
+        source: None,
+    };
+    env.execute_ingress_as(p1, ledger, "rearchive_node", Encode!(&args).unwrap())
+        .expect_err("only the controller can re-archive nodes");
+    let rearchive = |args: &RearchiveNodeArgs| {
+        Decode!(
+            &env.execute_ingress(ledger, "rearchive_node", Encode!(args).unwrap())
+                .expect("failed to re-archive the node")
+                .bytes(),
+            Result<CanisterId, String>
+        )
+        .unwrap()
+    };
+
+    // The ledger deletes the new node if it cannot copy the blocks, here
+    // because the source serves no blocks. The state machine assigns
+    // canister ids in sequence: the ledger, the archive, and the new node.
+    assert_eq!(old_archive, CanisterId::from_u64(1));
+    let bad_source = RearchiveNodeArgs {
+        archive: old_archive,
+        source: Some(ledger),
+    };
+    rearchive(&bad_source).expect_err("the ledger serves no archived blocks");
+    assert!(!env.canister_exists(CanisterId::from_u64(2)));
+    assert_eq!(archives(&env, ledger), vec![old_archive]);
+
+    let new_archive = rearchive(&args).expect("failed to re-archive the node");
+
+    assert_ne!(new_archive, old_archive);
+    assert_eq!(archives(&env, ledger), vec![new_archive]);
+    assert_eq!(tip_certificate(&env, ledger).archive_of(0), Some(new_archive));
+    assert_eq!(get_encoded_blocks(&env, new_archive, 0, 100), archived_blocks);
+    assert_eq!(verify(controller, new_archive).unwrap(), Ok(()));
+
+    // The ledger keeps archiving to the new node.
+    for _ in 0..5 {
+        transfer(&env, ledger, p1, AccountIdentifier::new(p2, None), 10_000)
+            .expect("transfer failed");
+    }
+    assert_eq!(archives(&env, ledger), vec![new_archive]);
+    assert!(get_encoded_blocks(&env, new_archive, 0, 100).len() > archived_blocks.len());
+}
+
+fn archive_top_up_events(env: &StateMachine, ledger: CanisterId) -> Vec<ArchiveTopUpEvent> {
+    Decode!(
+        &env.query(ledger, "get_archive_top_up_events", Encode!().unwrap())
+            .expect("failed to get the archive top-up events")
+            .bytes(),
+        Vec<ArchiveTopUpEvent>
+    )
+    .expect("failed to decode the archive top-up events")
+}
+
+#[test]
+fn test_archive_top_ups() {
+    const TOP_UP_CYCLES: u64 = 1_000_000_000;
+    let env = StateMachine::new();
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let install_ledger = |pool: Option<u64>| {
+        let init_args = InitArgs::builder()
+            .minting_account(AccountIdentifier::new(PrincipalId::new_user_test_id(1000), None))
+            .initial_values(HashMap::from([(
+                AccountIdentifier::new(p1, None),
+                Tokens::from_e8s(10_000_000),
+            )]))
+            .archive_options(ArchiveOptions {
+                trigger_threshold: 10,
+                num_blocks_to_archive: 5,
+                node_max_memory_size_bytes: None,
+                max_message_size_bytes: None,
+                controller_id: PrincipalId::new_user_test_id(100),
+                cycles_for_archive_creation: None,
+                max_transactions_per_response: None,
+                max_archive_nodes: None,
+                cycles_for_archive_top_up: Some(TOP_UP_CYCLES),
+                cycles_for_archive_top_ups_pool: pool,
+            })
+            .build()
+            .unwrap();
+        env.install_canister_with_cycles(
+            ledger_wasm(),
+            Encode!(&init_args).unwrap(),
+            None,
+            Cycles::new(100 * TOP_UP_CYCLES as u128),
+        )
+        .expect("failed to install the ledger")
+    };
+    let ledger = install_ledger(None);
+    // The pool of this ledger is too small for a single top up.
+    let poor_ledger = install_ledger(Some(TOP_UP_CYCLES - 1));
+    for ledger in [ledger, poor_ledger] {
+        for _ in 0..10 {
+            transfer(&env, ledger, p1, AccountIdentifier::new(p2, None), 10_000)
+                .expect("transfer failed");
+        }
+    }
+
+    // The heartbeat checks the archive nodes once per hour.
+    env.advance_time(Duration::from_secs(60 * 60 + 1));
+    for _ in 0..5 {
+        env.tick();
+    }
+
+    let archive = archives(&env, ledger)[0];
+    assert!(env.cycle_balance(archive) >= TOP_UP_CYCLES as u128);
+    let events = archive_top_up_events(&env, ledger);
+    assert_eq!(events.len(), 1, "unexpected events: {:?}", events);
+    assert_eq!(events[0].node, archive);
+    assert_eq!(
+        events[0].kind,
+        ArchiveTopUpEventKind::ToppedUp {
+            balance: 0,
+            cycles: TOP_UP_CYCLES,
+        }
+    );
+
+    let poor_archive = archives(&env, poor_ledger)[0];
+    assert_eq!(env.cycle_balance(poor_archive), 0);
+    let events = archive_top_up_events(&env, poor_ledger);
+    assert!(!events.is_empty());
+    for event in events {
+        assert_eq!(event.node, poor_archive);
+        assert_eq!(event.kind, ArchiveTopUpEventKind::PoolExhausted { balance: 0 });
+    }
+}
+
+#[test]
+fn test_tip_certificate() {
+    let env = StateMachine::new();
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let init_args = InitArgs::builder()
+        .minting_account(AccountIdentifier::new(PrincipalId::new_user_test_id(1000), None))
+        .initial_values(HashMap::from([(
+            AccountIdentifier::new(p1, None),
+            Tokens::from_e8s(10_000_000),
+        )]))
+        .archive_options(ArchiveOptions {
+            trigger_threshold: 10,
+            num_blocks_to_archive: 5,
+            node_max_memory_size_bytes: None,
+            max_message_size_bytes: None,
+            controller_id: PrincipalId::new_user_test_id(100),
+            cycles_for_archive_creation: None,
+            max_transactions_per_response: None,
+            max_archive_nodes: None,
+            cycles_for_archive_top_up: None,
+            cycles_for_archive_top_ups_pool: None,
+        })
+        .build()
+        .unwrap();
+    let ledger = env
+        .install_canister(ledger_wasm(), Encode!(&init_args).unwrap(), None)
+        .expect("failed to install the ledger");
+
+    let tip = tip_certificate(&env, ledger);
+    assert_eq!(tip.last_block_index, 0);
+    assert_eq!(tip.archives, vec![]);
+
+    let mut last_block_index = 0;
+    for _ in 0..10 {
+        last_block_index = transfer(&env, ledger, p1, AccountIdentifier::new(p2, None), 10_000)
+            .expect("transfer failed");
+    }
+    let archive = match archives(&env, ledger)[..] {
+        [archive] => archive,
+        ref other => panic!("expected one archive, got {:?}", other),
+    };
+    let num_archived = get_encoded_blocks(&env, archive, 0, 100).len() as u64;
+    assert!(num_archived > 0);
+
+    // The certificate covers the blocks that the ledger archived after the
+    // last transfer.
+    let tip = tip_certificate(&env, ledger);
+    assert_eq!(tip.last_block_index, last_block_index);
+    assert_eq!(tip.archives, vec![((0, num_archived - 1), archive)]);
+    assert_eq!(tip.archive_of(num_archived - 1), Some(archive));
+    assert_eq!(tip.archive_of(num_archived), None);
+}
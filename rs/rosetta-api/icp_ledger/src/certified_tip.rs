@@ -0,0 +1,152 @@
+//! The ledger state that the ledger canister certifies.
+//!
+//! The certified data of the ledger is the root hash of the tree
+//!
+//! ```text
+//! archives
+//!   <start>
+//!     canister_id -> <the archive canister id>
+//!     end -> <the index of the last block in the archive>
+//! last_block_hash -> <the hash of the last block>
+//! last_block_index -> <the index of the last block>
+//! ```
+//!
+//! with an entry under `archives` for every archive canister. `<start>` is the
+//! index of the first block in the archive as 8 big-endian bytes, so the
+//! archives are sorted by their block ranges. Block indices in leaves are
+//! LEB128-encoded, as in ICRC-3. A ledger without blocks certifies the empty
+//! tree.
+//!
+//! The tree lets clients check with a single certificate both the tip of the
+//! chain and which canister serves which blocks.
+
+use candid::Nat;
+use ic_base_types::CanisterId;
+use ic_crypto_tree_hash::{FlatMap, Label, LabeledTree, MixedHashTree};
+use ic_ledger_core::block::{BlockIndex, EncodedBlock, HashOf};
+use std::convert::TryFrom;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CertifiedTip {
+    pub last_block_index: BlockIndex,
+    pub last_block_hash: HashOf<EncodedBlock>,
+    /// The inclusive block ranges of the archive canisters, ordered by block
+    /// index.
+    pub archives: Vec<((BlockIndex, BlockIndex), CanisterId)>,
+}
+
+fn leaf(label: impl AsRef<[u8]>, bytes: Vec<u8>) -> MixedHashTree {
+    labeled(label, MixedHashTree::Leaf(bytes))
+}
+
+fn labeled(label: impl AsRef<[u8]>, tree: MixedHashTree) -> MixedHashTree {
+    MixedHashTree::Labeled(Label::from(label), Box::new(tree))
+}
+
+/// Joins trees with sorted labels into one tree.
+fn fork_all(trees: Vec<MixedHashTree>) -> MixedHashTree {
+    trees
+        .into_iter()
+        .rev()
+        .reduce(|right, left| MixedHashTree::Fork(Box::new((left, right))))
+        .unwrap_or(MixedHashTree::Empty)
+}
+
+fn encode_index(index: BlockIndex) -> Vec<u8> {
+    let mut bytes = vec![];
+    Nat::from(index)
+        .encode(&mut bytes)
+        .expect("bug: failed to encode nat");
+    bytes
+}
+
+fn decode_index(bytes: &[u8]) -> Result<BlockIndex, String> {
+    let n = Nat::decode(&mut &bytes[..]).map_err(|e| format!("invalid block index: {}", e))?;
+    BlockIndex::try_from(n.0).map_err(|e| format!("invalid block index: {}", e))
+}
+
+type Children = FlatMap<Label, LabeledTree<Vec<u8>>>;
+
+fn subtree<'a>(tree: &'a LabeledTree<Vec<u8>>, name: &str) -> Result<&'a Children, String> {
+    match tree {
+        LabeledTree::SubTree(children) => Ok(children),
+        LabeledTree::Leaf(_) => Err(format!("expected a subtree at {}", name)),
+    }
+}
+
+fn child<'a>(children: &'a Children, label: &str) -> Result<&'a LabeledTree<Vec<u8>>, String> {
+    children
+        .get(&Label::from(label))
+        .ok_or_else(|| format!("the tree has no {} label", label))
+}
+
+fn child_leaf<'a>(children: &'a Children, label: &str) -> Result<&'a [u8], String> {
+    match child(children, label)? {
+        LabeledTree::Leaf(bytes) => Ok(bytes),
+        LabeledTree::SubTree(_) => Err(format!("expected a leaf at {}", label)),
+    }
+}
+
+impl CertifiedTip {
+    pub fn hash_tree(&self) -> MixedHashTree {
+        let archives = self
+            .archives
+            .iter()
+            .map(|((start, end), canister_id)| {
+                labeled(
+                    start.to_be_bytes(),
+                    fork_all(vec![
+                        leaf("canister_id", canister_id.get().as_slice().to_vec()),
+                        leaf("end", encode_index(*end)),
+                    ]),
+                )
+            })
+            .collect();
+        fork_all(vec![
+            labeled("archives", fork_all(archives)),
+            leaf("last_block_hash", self.last_block_hash.as_slice().to_vec()),
+            leaf("last_block_index", encode_index(self.last_block_index)),
+        ])
+    }
+
+    /// Reads the tip from the hash tree that the ledger certifies. The caller
+    /// must check that the root hash of the tree is the certified data.
+    pub fn from_hash_tree(tree: MixedHashTree) -> Result<Self, String> {
+        let tree = LabeledTree::try_from(tree)
+            .map_err(|e| format!("failed to convert the hash tree: {:?}", e))?;
+        let root = subtree(&tree, "the root")?;
+
+        let hash = child_leaf(root, "last_block_hash")?;
+        let last_block_hash = <[u8; 32]>::try_from(hash)
+            .map(HashOf::new)
+            .map_err(|_| format!("invalid block hash of length {}", hash.len()))?;
+        let last_block_index = decode_index(child_leaf(root, "last_block_index")?)?;
+
+        let mut archives = vec![];
+        for (start, archive) in subtree(child(root, "archives")?, "archives")?.iter() {
+            let start = <[u8; 8]>::try_from(start.as_bytes())
+                .map(u64::from_be_bytes)
+                .map_err(|_| format!("invalid archive start {}", start))?;
+            let archive = subtree(archive, "an archive")?;
+            let end = decode_index(child_leaf(archive, "end")?)?;
+            let canister_id = CanisterId::try_from(child_leaf(archive, "canister_id")?)
+                .map_err(|e| format!("invalid archive canister id: {}", e))?;
+            archives.push(((start, end), canister_id));
+        }
+
+        Ok(Self {
+            last_block_index,
+            last_block_hash,
+            archives,
+        })
+    }
+
+    /// Returns the archive canister that serves the block with the given
+    /// index, or None if the ledger itself serves the block.
+    pub fn archive_of(&self, block_index: BlockIndex) -> Option<CanisterId> {
+        self.archives
+            .iter()
+            .find(|((start, end), _)| (*start..=*end).contains(&block_index))
+            .map(|(_, canister_id)| *canister_id)
+    }
+}
@@ -2,12 +2,14 @@ use candid::CandidType;
 use dfn_protobuf::ProtoBuf;
 use ic_base_types::{CanisterId, PrincipalId};
 use ic_crypto_sha::Sha256;
-use ic_icrc1::Account;
-pub use ic_ledger_canister_core::archive::ArchiveOptions;
+use ic_icrc1::{icrc3, Account};
+pub use ic_ledger_canister_core::archive::{
+    ArchiveOptions, ArchiveTopUpEvent, ArchiveTopUpEventKind,
+};
 use ic_ledger_canister_core::ledger::LedgerTransaction;
 use ic_ledger_core::{
     balances::{BalanceError, Balances, BalancesStore},
-    block::{BlockType, EncodedBlock, HashOf, HASH_LENGTH},
+    block::{BlockType, EncodedBlock, FeeCollector, HashOf, HASH_LENGTH},
 };
 use on_wire::{FromWire, IntoWire};
 use serde::{Deserialize, Serialize};
@@ -25,11 +27,13 @@ pub use ic_ledger_core::{
 };
 
 pub mod account_identifier;
+pub mod certified_tip;
 #[allow(clippy::all)]
 #[path = "../gen/ic_ledger.pb.v1.rs"]
 pub mod protobuf;
 mod validate_endpoints;
 pub use account_identifier::{AccountIdentifier, Subaccount};
+pub use certified_tip::CertifiedTip;
 pub use validate_endpoints::{tokens_from_proto, tokens_into_proto};
 
 /// Note that the Ledger can be deployed with a
@@ -39,6 +43,11 @@ pub const DEFAULT_TRANSFER_FEE: Tokens = Tokens::from_e8s(10_000);
 
 pub const MAX_BLOCKS_PER_REQUEST: usize = 2000;
 
+/// The maximum size of the Candid-encoded blocks in a response of the blocks
+/// query endpoints. It leaves room for the rest of the response, e.g. the
+/// certificate and the archived block ranges, below the 2 MiB reply limit.
+pub const MAX_BLOCKS_RESPONSE_SIZE_BYTES: usize = 2 * 1024 * 1024 - 64 * 1024;
+
 pub type LedgerBalances = Balances<AccountIdentifier, HashMap<AccountIdentifier, Tokens>>;
 
 #[derive(
@@ -95,7 +104,7 @@ pub fn apply_operation<S>(
     operation: &Operation,
 ) -> Result<(), BalanceError>
 where
-    S: Default + BalancesStore<AccountIdentifier>,
+    S: Default + BalancesStore<AccountIdentifier, Tokens = Tokens>,
 {
     match operation {
         Operation::Transfer {
@@ -103,7 +112,7 @@ where
             to,
             amount,
             fee,
-        } => balances.transfer(from, to, *amount, *fee),
+        } => balances.transfer(from, to, *amount, *fee, None),
         Operation::Burn { from, amount, .. } => balances.burn(from, *amount),
         Operation::Mint { to, amount, .. } => balances.mint(to, *amount),
     }
@@ -123,6 +132,7 @@ pub struct Transaction {
 
 impl LedgerTransaction for Transaction {
     type AccountId = AccountIdentifier;
+    type Tokens = Tokens;
 
     fn burn(
         from: Self::AccountId,
@@ -147,9 +157,20 @@ impl LedgerTransaction for Transaction {
         HashOf::new(state.finish())
     }
 
-    fn apply<S>(&self, balances: &mut Balances<Self::AccountId, S>) -> Result<(), BalanceError>
+    fn fee(&self) -> Option<Tokens> {
+        match &self.operation {
+            Operation::Transfer { fee, .. } => Some(*fee),
+            _ => None,
+        }
+    }
+
+    fn apply<S>(
+        &self,
+        balances: &mut Balances<Self::AccountId, S>,
+        _fee_collector: Option<&Self::AccountId>,
+    ) -> Result<(), BalanceError>
     where
-        S: Default + BalancesStore<Self::AccountId>,
+        S: Default + BalancesStore<Self::AccountId, Tokens = Self::Tokens>,
     {
         apply_operation(balances, &self.operation)
     }
@@ -200,7 +221,12 @@ impl Block {
             memo,
             created_at_time: Some(created_at_time),
         };
-        Ok(Self::from_transaction(parent_hash, transaction, timestamp))
+        Ok(Self::from_transaction(
+            parent_hash,
+            transaction,
+            timestamp,
+            None,
+        ))
     }
 
     #[inline]
@@ -209,7 +235,7 @@ impl Block {
         transaction: Transaction,
         timestamp: TimeStamp,
     ) -> Self {
-        Self::from_transaction(parent_hash, transaction, timestamp)
+        Self::from_transaction(parent_hash, transaction, timestamp, None)
     }
 
     pub fn transaction(&self) -> Cow<Transaction> {
@@ -219,6 +245,7 @@ impl Block {
 
 impl BlockType for Block {
     type Transaction = Transaction;
+    type AccountId = AccountIdentifier;
 
     fn encode(self) -> EncodedBlock {
         EncodedBlock::from_vec(
@@ -250,6 +277,7 @@ impl BlockType for Block {
         parent_hash: Option<HashOf<EncodedBlock>>,
         transaction: Self::Transaction,
         timestamp: TimeStamp,
+        _fee_collector: Option<FeeCollector<Self::AccountId>>,
     ) -> Self {
         Self {
             parent_hash,
@@ -259,6 +287,60 @@ impl BlockType for Block {
     }
 }
 
+/// Encodes the block in the generic ICRC-3 value encoding following the
+/// schema of its block type in [ICRC3_BLOCK_SCHEMAS]. The blocks do not follow
+/// the ICRC-1 block schemas because accounts are account identifiers and the
+/// memo is a number.
+impl From<Block> for icrc3::Value {
+    fn from(block: Block) -> Self {
+        use icrc3::Value;
+
+        let mut tx = vec![];
+        let block_type = match block.transaction.operation {
+            Operation::Burn { from, amount } => {
+                tx.push(Value::entry("from", from.to_vec()));
+                tx.push(Value::entry("amt", amount.get_e8s()));
+                ICRC3_BURN_BLOCK_TYPE
+            }
+            Operation::Mint { to, amount } => {
+                tx.push(Value::entry("to", to.to_vec()));
+                tx.push(Value::entry("amt", amount.get_e8s()));
+                ICRC3_MINT_BLOCK_TYPE
+            }
+            Operation::Transfer {
+                from,
+                to,
+                amount,
+                fee,
+            } => {
+                tx.push(Value::entry("from", from.to_vec()));
+                tx.push(Value::entry("to", to.to_vec()));
+                tx.push(Value::entry("amt", amount.get_e8s()));
+                tx.push(Value::entry("fee", fee.get_e8s()));
+                ICRC3_TRANSFER_BLOCK_TYPE
+            }
+        };
+        tx.push(Value::entry("memo", block.transaction.memo.0));
+        if let Some(created_at_time) = block.transaction.created_at_time {
+            tx.push(Value::entry(
+                "ts",
+                created_at_time.as_nanos_since_unix_epoch(),
+            ));
+        }
+
+        let mut fields = vec![Value::entry("btype", block_type)];
+        if let Some(parent_hash) = block.parent_hash {
+            fields.push(Value::entry("phash", parent_hash.as_slice()));
+        }
+        fields.push(Value::entry("tx", tx));
+        fields.push(Value::entry(
+            "ts",
+            block.timestamp.as_nanos_since_unix_epoch(),
+        ));
+        Value::Map(fields)
+    }
+}
+
 #[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
 pub struct TransferFee {
     /// The fee to pay to perform a transfer
@@ -273,6 +355,39 @@ impl Default for TransferFee {
     }
 }
 
+/// A principal that sent many transfers in a time window.
+#[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+pub struct TransferHeavyHitter {
+    pub principal: PrincipalId,
+    /// The number of transfer calls of the principal in the window. It can
+    /// exceed the actual number by up to `max_overcount`.
+    pub calls: u64,
+    pub max_overcount: u64,
+}
+
+/// The principals that sent the most transfers in a time window, returned by
+/// the `get_transfer_heavy_hitters` endpoint.
+#[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+pub struct TransferHeavyHitters {
+    /// The start of the window in nanoseconds since the Unix epoch.
+    pub window_start: u64,
+    pub window_length_nanos: u64,
+    /// The number of transfer calls in the window.
+    pub total_calls: u64,
+    /// The principals with the most calls first.
+    pub heavy_hitters: Vec<TransferHeavyHitter>,
+}
+
+/// The arguments of the `rearchive_node` endpoint.
+#[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+pub struct RearchiveNodeArgs {
+    /// The archive node whose blocks move to a new node.
+    pub archive: CanisterId,
+    /// The canister to copy the blocks from through its `get_encoded_blocks`
+    /// endpoint. The archive node itself if not set.
+    pub source: Option<CanisterId>,
+}
+
 // This is how we pass arguments to 'init' in main.rs
 #[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
 pub struct LedgerCanisterInitPayload {
@@ -288,6 +403,19 @@ pub struct LedgerCanisterInitPayload {
     pub token_name: Option<String>,
 }
 
+/// The arguments that the ledger accepts on upgrade, encoded as
+/// `opt LedgerCanisterUpgradePayload`.
+#[derive(Serialize, Deserialize, CandidType, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LedgerCanisterUpgradePayload {
+    /// If true, disables the deprecated notifications for good and drops the
+    /// send whitelist and the record of notified blocks. Notifications cannot
+    /// be enabled again.
+    pub disable_notifications: Option<bool>,
+    /// If set, replaces the set of deprecated protobuf endpoints that reject
+    /// calls with a `MigrationError`.
+    pub disabled_protobuf_endpoints: Option<Vec<ProtobufEndpoint>>,
+}
+
 impl LedgerCanisterInitPayload {
     pub fn builder() -> LedgerCanisterInitPayloadBuilder {
         LedgerCanisterInitPayloadBuilder::new()
@@ -487,6 +615,163 @@ impl fmt::Display for TransferError {
     }
 }
 
+/// The reasons why the ledger refuses to notify a canister about a payment.
+///
+/// Notifications are deprecated: they will be removed once all clients moved
+/// to other ways of learning about payments.
+#[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+pub enum NotifyError {
+    /// The ledger was upgraded with notifications disabled.
+    NotificationsDisabled,
+    /// The caller is not allowed to send notifications.
+    CallerNotAllowed { caller: PrincipalId },
+    /// The notified canister is not in the send whitelist.
+    CanisterNotWhitelisted { canister_id: CanisterId },
+    BadFee { expected_fee: Tokens },
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotificationsDisabled => write!(
+                f,
+                "Notifications are deprecated and disabled on this ledger, use the transfer \
+                 endpoint and notify the recipient directly"
+            ),
+            Self::CallerNotAllowed { caller } => {
+                write!(f, "Notifying from {} is not allowed", caller)
+            }
+            Self::CanisterNotWhitelisted { canister_id } => write!(
+                f,
+                "Notifying non-whitelisted canister is not allowed: {}",
+                canister_id
+            ),
+            Self::BadFee { expected_fee } => write!(f, "Transfer fee should be {}", expected_fee),
+        }
+    }
+}
+
+/// The deprecated endpoints that take and return protobuf messages.
+#[derive(
+    Serialize, Deserialize, CandidType, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub enum ProtobufEndpoint {
+    SendPb,
+    NotifyPb,
+    BlockPb,
+    TipOfChainPb,
+    GetArchiveIndexPb,
+    AccountBalancePb,
+    TransferFeePb,
+    TotalSupplyPb,
+    IterBlocksPb,
+    GetBlocksPb,
+}
+
+impl ProtobufEndpoint {
+    pub const ALL: [ProtobufEndpoint; 10] = [
+        ProtobufEndpoint::SendPb,
+        ProtobufEndpoint::NotifyPb,
+        ProtobufEndpoint::BlockPb,
+        ProtobufEndpoint::TipOfChainPb,
+        ProtobufEndpoint::GetArchiveIndexPb,
+        ProtobufEndpoint::AccountBalancePb,
+        ProtobufEndpoint::TransferFeePb,
+        ProtobufEndpoint::TotalSupplyPb,
+        ProtobufEndpoint::IterBlocksPb,
+        ProtobufEndpoint::GetBlocksPb,
+    ];
+
+    /// The name of the canister method.
+    pub fn method_name(&self) -> &'static str {
+        match self {
+            Self::SendPb => "send_pb",
+            Self::NotifyPb => "notify_pb",
+            Self::BlockPb => "block_pb",
+            Self::TipOfChainPb => "tip_of_chain_pb",
+            Self::GetArchiveIndexPb => "get_archive_index_pb",
+            Self::AccountBalancePb => "account_balance_pb",
+            Self::TransferFeePb => "transfer_fee_pb",
+            Self::TotalSupplyPb => "total_supply_pb",
+            Self::IterBlocksPb => "iter_blocks_pb",
+            Self::GetBlocksPb => "get_blocks_pb",
+        }
+    }
+
+    /// The Candid method that clients should call instead.
+    pub fn replacement(&self) -> &'static str {
+        match self {
+            // Notifications have no replacement: senders transfer and then
+            // notify the recipient themselves.
+            Self::SendPb | Self::NotifyPb => "transfer",
+            Self::BlockPb | Self::IterBlocksPb | Self::GetBlocksPb => "query_blocks",
+            Self::TipOfChainPb => "icrc3_get_tip_certificate",
+            Self::GetArchiveIndexPb => "archives",
+            Self::AccountBalancePb => "account_balance",
+            Self::TransferFeePb => "transfer_fee",
+            Self::TotalSupplyPb => "icrc1_total_supply",
+        }
+    }
+
+    /// The warning that the ledger attaches to the responses of the endpoint
+    /// where the response message has room for it.
+    pub fn deprecation_warning(&self) -> protobuf::DeprecationWarning {
+        protobuf::DeprecationWarning {
+            endpoint: self.method_name().to_string(),
+            replacement: self.replacement().to_string(),
+            message: format!(
+                "The protobuf endpoint {} is deprecated and will be removed, use {} instead",
+                self.method_name(),
+                self.replacement()
+            ),
+        }
+    }
+}
+
+/// The reasons why the ledger refuses a call to a deprecated protobuf
+/// endpoint.
+#[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+pub enum MigrationError {
+    /// The endpoint was disabled on upgrade, see
+    /// `LedgerCanisterUpgradePayload::disabled_protobuf_endpoints`.
+    EndpointDisabled { endpoint: ProtobufEndpoint },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EndpointDisabled { endpoint } => write!(
+                f,
+                "The protobuf endpoint {} is disabled on this ledger, use {} instead",
+                endpoint.method_name(),
+                endpoint.replacement()
+            ),
+        }
+    }
+}
+
+/// A principal that called a deprecated protobuf endpoint.
+#[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+pub struct ProtobufEndpointCaller {
+    pub principal: PrincipalId,
+    pub calls: u64,
+}
+
+/// The calls to a deprecated protobuf endpoint since the last upgrade,
+/// returned by the `get_protobuf_endpoint_usage` endpoint.
+#[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+pub struct ProtobufEndpointUsage {
+    pub endpoint: ProtobufEndpoint,
+    pub calls: u64,
+    /// The callers with the most calls first.
+    pub callers: Vec<ProtobufEndpointCaller>,
+    /// The number of calls from callers that the ledger does not track
+    /// individually.
+    pub untracked_calls: u64,
+    /// Whether the endpoint rejects calls.
+    pub disabled: bool,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum PaymentError {
     Reject(String),
@@ -677,6 +962,10 @@ pub struct Decimals {
 #[derive(Serialize, Deserialize, CandidType, Clone, Hash, Debug, PartialEq, Eq)]
 pub struct ArchiveInfo {
     pub canister_id: CanisterId,
+    /// The number of bytes that the archive can still store as of the last
+    /// time the ledger checked, or `None` if the ledger hasn't checked yet.
+    #[serde(default)]
+    pub remaining_capacity: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, CandidType, Clone, Hash, Debug, PartialEq, Eq)]
@@ -767,6 +1056,200 @@ pub fn iter_blocks(blocks: &[EncodedBlock], offset: usize, length: usize) -> Ite
     IterBlocksRes(blocks)
 }
 
+/// Returns the longest prefix of `blocks` whose Candid encoding as a vector
+/// fits into `max_size_bytes`. The first block is always returned, so that
+/// callers paginating through the blocks make progress.
+pub fn take_blocks_within_size<T: CandidType>(
+    blocks: impl IntoIterator<Item = T>,
+    max_size_bytes: usize,
+) -> Vec<T> {
+    let encoded_size = |blocks: &[T]| {
+        candid::encode_one(blocks)
+            .expect("bug: failed to encode blocks")
+            .len()
+    };
+    let empty_size = encoded_size(&[]);
+    // The LEB128-encoded vector length takes up to 10 bytes instead of the
+    // single byte in the encoding of the empty vector.
+    let mut size = empty_size + 9;
+    let mut result = vec![];
+    for block in blocks {
+        // The encodings of an empty vector and of a vector with one block
+        // differ only by the encoding of the block value.
+        size += encoded_size(std::slice::from_ref(&block)) - empty_size;
+        if size > max_size_bytes && !result.is_empty() {
+            break;
+        }
+        result.push(block);
+    }
+    result
+}
+
+// A helper function for the ledger/icrc3_get_blocks and
+// archive_node/icrc3_get_blocks endpoints
+pub fn icrc3_blocks(
+    blocks: &[EncodedBlock],
+    first_block_index: BlockIndex,
+) -> Vec<icrc3::BlockWithId> {
+    blocks
+        .iter()
+        .zip(first_block_index..)
+        .map(|(encoded_block, id)| icrc3::BlockWithId {
+            id: candid::Nat::from(id),
+            block: Block::decode(encoded_block.clone())
+                .expect("bug: failed to decode encoded block")
+                .into(),
+        })
+        .collect()
+}
+
+pub const ICRC3_BURN_BLOCK_TYPE: &str = "icp_burn";
+pub const ICRC3_MINT_BLOCK_TYPE: &str = "icp_mint";
+pub const ICRC3_TRANSFER_BLOCK_TYPE: &str = "icp_xfer";
+
+/// The documentation of the ICRC-3 block types of the ICP ledger.
+pub const ICRC3_BLOCK_SCHEMA_URL: &str =
+    "https://github.com/dfinity/ic/blob/master/rs/rosetta-api/icp_ledger/README.md#icrc-3-blocks";
+
+/// The kind of a value in an ICRC-3 block of the ICP ledger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Icrc3ValueKind {
+    /// A 32-byte blob, i.e., an account identifier or a hash.
+    Blob32,
+    Nat,
+}
+
+impl Icrc3ValueKind {
+    fn matches(self, value: &icrc3::Value) -> bool {
+        match (self, value) {
+            (Self::Blob32, icrc3::Value::Blob(bytes)) => bytes.len() == 32,
+            (Self::Nat, icrc3::Value::Nat(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The schema of an ICRC-3 block type of the ICP ledger. A block is a map
+/// with the block type in `btype`, the timestamp in `ts`, the hash of the
+/// parent block in `phash` (except for the first block) and the transaction
+/// in `tx`.
+#[derive(Clone, Copy, Debug)]
+pub struct Icrc3BlockSchema {
+    pub block_type: &'static str,
+    /// The fields of the transaction map: the key, the kind of the value and
+    /// whether the field is required.
+    pub tx_fields: &'static [(&'static str, Icrc3ValueKind, bool)],
+}
+
+/// The schemas of the block types in the log of the ledger.
+pub const ICRC3_BLOCK_SCHEMAS: [Icrc3BlockSchema; 3] = [
+    Icrc3BlockSchema {
+        block_type: ICRC3_BURN_BLOCK_TYPE,
+        tx_fields: &[
+            ("from", Icrc3ValueKind::Blob32, true),
+            ("amt", Icrc3ValueKind::Nat, true),
+            ("memo", Icrc3ValueKind::Nat, true),
+            ("ts", Icrc3ValueKind::Nat, false),
+        ],
+    },
+    Icrc3BlockSchema {
+        block_type: ICRC3_MINT_BLOCK_TYPE,
+        tx_fields: &[
+            ("to", Icrc3ValueKind::Blob32, true),
+            ("amt", Icrc3ValueKind::Nat, true),
+            ("memo", Icrc3ValueKind::Nat, true),
+            ("ts", Icrc3ValueKind::Nat, false),
+        ],
+    },
+    Icrc3BlockSchema {
+        block_type: ICRC3_TRANSFER_BLOCK_TYPE,
+        tx_fields: &[
+            ("from", Icrc3ValueKind::Blob32, true),
+            ("to", Icrc3ValueKind::Blob32, true),
+            ("amt", Icrc3ValueKind::Nat, true),
+            ("fee", Icrc3ValueKind::Nat, true),
+            ("memo", Icrc3ValueKind::Nat, true),
+            ("ts", Icrc3ValueKind::Nat, false),
+        ],
+    },
+];
+
+/// The block types in the log of the ledger. The ICP ledger advertises its
+/// own block types because its blocks do not follow the ICRC-1 block schemas.
+pub fn icrc3_supported_block_types() -> Vec<icrc3::SupportedBlockType> {
+    ICRC3_BLOCK_SCHEMAS
+        .iter()
+        .map(|schema| icrc3::SupportedBlockType {
+            block_type: schema.block_type.to_string(),
+            url: ICRC3_BLOCK_SCHEMA_URL.to_string(),
+        })
+        .collect()
+}
+
+/// Checks that the block with the given index follows the schema of its
+/// block type in [ICRC3_BLOCK_SCHEMAS] and returns the block type.
+pub fn validate_icrc3_block(index: u64, block: &icrc3::Value) -> Result<String, String> {
+    fn check_fields(
+        value: &icrc3::Value,
+        fields: &[(&str, Icrc3ValueKind, bool)],
+    ) -> Result<(), String> {
+        let entries = match value {
+            icrc3::Value::Map(entries) => entries,
+            other => return Err(format!("expected a map, got {:?}", other)),
+        };
+        for (key, value) in entries {
+            match fields.iter().find(|(field, _, _)| field == key) {
+                Some((_, kind, _)) if kind.matches(value) => (),
+                Some((_, kind, _)) => {
+                    return Err(format!("field {} must be {:?}, got {:?}", key, kind, value))
+                }
+                None => return Err(format!("unexpected field {}", key)),
+            }
+        }
+        for (key, _, required) in fields {
+            if *required && value.get(key).is_none() {
+                return Err(format!("missing field {}", key));
+            }
+        }
+        Ok(())
+    }
+
+    let schema = match block.get("btype") {
+        Some(icrc3::Value::Text(block_type)) => ICRC3_BLOCK_SCHEMAS
+            .iter()
+            .find(|schema| &schema.block_type == block_type)
+            .ok_or_else(|| format!("block {} has an unknown type {}", index, block_type))?,
+        other => return Err(format!("block {} has an invalid btype {:?}", index, other)),
+    };
+    let entries = match block {
+        icrc3::Value::Map(entries) => entries,
+        other => return Err(format!("block {} must be a map, got {:?}", index, other)),
+    };
+    for (key, value) in entries {
+        let valid = match key.as_str() {
+            "btype" => true,
+            "phash" => index > 0 && Icrc3ValueKind::Blob32.matches(value),
+            "ts" => Icrc3ValueKind::Nat.matches(value),
+            "tx" => {
+                check_fields(value, schema.tx_fields)
+                    .map_err(|e| format!("block {} has an invalid tx: {}", index, e))?;
+                true
+            }
+            _ => false,
+        };
+        if !valid {
+            return Err(format!("block {} has an invalid field {}: {:?}", index, key, value));
+        }
+    }
+    for key in ["phash", "ts", "tx"] {
+        // Only the first block has no parent.
+        if block.get(key).is_none() && (key != "phash" || index > 0) {
+            return Err(format!("block {} has no field {}", index, key));
+        }
+    }
+    Ok(schema.block_type.to_string())
+}
+
 #[derive(CandidType, Deserialize)]
 pub enum CyclesResponse {
     CanisterCreated(CanisterId),
@@ -836,3 +1319,115 @@ pub struct QueryBlocksResponse {
     pub first_block_index: BlockIndex,
     pub archived_blocks: Vec<ArchivedBlocksRange>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_blocks(operations: Vec<Operation>) -> Vec<icrc3::Value> {
+        let mut parent_hash = None;
+        let mut blocks = vec![];
+        for (i, operation) in operations.into_iter().enumerate() {
+            let timestamp = TimeStamp::from_nanos_since_unix_epoch(1_000 + i as u64);
+            let block = Block::new(
+                parent_hash,
+                operation,
+                Memo(i as u64),
+                timestamp,
+                timestamp,
+            )
+            .unwrap();
+            parent_hash = Some(Block::block_hash(&block.clone().encode()));
+            blocks.push(icrc3::Value::from(block));
+        }
+        blocks
+    }
+
+    #[test]
+    fn icrc3_blocks_follow_the_advertised_schemas() {
+        let account = |i: u64| AccountIdentifier::new(PrincipalId::new_user_test_id(i), None);
+        let blocks = encode_blocks(vec![
+            Operation::Mint {
+                to: account(1),
+                amount: Tokens::from_e8s(1_000_000),
+            },
+            Operation::Transfer {
+                from: account(1),
+                to: account(2),
+                amount: Tokens::from_e8s(100_000),
+                fee: DEFAULT_TRANSFER_FEE,
+            },
+            Operation::Burn {
+                from: account(2),
+                amount: Tokens::from_e8s(50_000),
+            },
+        ]);
+
+        let supported_block_types: Vec<String> = icrc3_supported_block_types()
+            .into_iter()
+            .map(|block_type| block_type.block_type)
+            .collect();
+        let block_types: Vec<String> = blocks
+            .iter()
+            .zip(0..)
+            .map(|(block, index)| validate_icrc3_block(index, block).unwrap())
+            .collect();
+        assert_eq!(block_types, ["icp_mint", "icp_xfer", "icp_burn"]);
+        assert_eq!(supported_block_types, ["icp_burn", "icp_mint", "icp_xfer"]);
+    }
+
+    #[test]
+    fn icrc3_blocks_violating_the_schema_are_rejected() {
+        let account = AccountIdentifier::new(PrincipalId::new_user_test_id(1), None);
+        let blocks = encode_blocks(vec![Operation::Mint {
+            to: account,
+            amount: Tokens::from_e8s(1_000_000),
+        }]);
+        let block = &blocks[0];
+        assert!(validate_icrc3_block(0, block).is_ok());
+        // The first block has no parent.
+        assert!(validate_icrc3_block(1, block).is_err());
+
+        let with_tx_field = |key: &str, value: icrc3::Value| match block {
+            icrc3::Value::Map(entries) => icrc3::Value::Map(
+                entries
+                    .iter()
+                    .map(|(k, v)| match (k.as_str(), v) {
+                        ("tx", icrc3::Value::Map(tx)) => {
+                            let mut tx = tx.clone();
+                            tx.retain(|(k, _)| k != key);
+                            tx.push((key.to_string(), value.clone()));
+                            (k.clone(), icrc3::Value::Map(tx))
+                        }
+                        _ => (k.clone(), v.clone()),
+                    })
+                    .collect(),
+            ),
+            _ => unreachable!(),
+        };
+        // An ICRC-1 account instead of an account identifier.
+        let icrc1_account = icrc3::Value::Array(vec![icrc3::Value::from(vec![1_u8; 29])]);
+        assert!(validate_icrc3_block(0, &with_tx_field("to", icrc1_account)).is_err());
+        assert!(validate_icrc3_block(0, &with_tx_field("memo", vec![1_u8].into())).is_err());
+        assert!(validate_icrc3_block(0, &with_tx_field("op", "mint".into())).is_err());
+    }
+
+    #[test]
+    fn take_blocks_within_size_fills_the_response() {
+        let blocks: Vec<Vec<u8>> = (0..100).map(|i| vec![i as u8; 1000]).collect();
+        let max_size_bytes = 10_500;
+
+        let taken = take_blocks_within_size(blocks.clone(), max_size_bytes);
+        assert_eq!(taken[..], blocks[..taken.len()]);
+        assert!(candid::encode_one(&taken).unwrap().len() <= max_size_bytes);
+        let one_more = blocks[..taken.len() + 1].to_vec();
+        assert!(candid::encode_one(&one_more).unwrap().len() > max_size_bytes - 10);
+    }
+
+    #[test]
+    fn take_blocks_within_size_returns_at_least_one_block() {
+        let blocks = vec![vec![1_u8; 1000], vec![2_u8; 1000]];
+        assert_eq!(take_blocks_within_size(blocks.clone(), 0), blocks[..1]);
+        assert!(take_blocks_within_size(Vec::<Vec<u8>>::new(), 0).is_empty());
+    }
+}
@@ -78,6 +78,7 @@ impl ToProto for TransferFee {
     fn into_proto(self) -> Self::Proto {
         protobuf::TransferFeeResponse {
             transfer_fee: Some(tokens_into_proto(self.transfer_fee)),
+            deprecation_warning: None,
         }
     }
 }
@@ -104,6 +105,7 @@ impl ToProto for TipOfChainRes {
             chain_length: Some(protobuf::BlockIndex {
                 height: self.tip_index,
             }),
+            deprecation_warning: None,
         }
     }
 }
@@ -202,12 +204,14 @@ impl ToProto for GetBlocksRes {
                             protobuf::EncodedBlocks { blocks },
                         ),
                     ),
+                    deprecation_warning: None,
                 }
             }
             Err(err) => protobuf::GetBlocksResponse {
                 get_blocks_content: Some(protobuf::get_blocks_response::GetBlocksContent::Error(
                     err,
                 )),
+                deprecation_warning: None,
             },
         }
     }
@@ -256,7 +260,10 @@ impl ToProto for IterBlocksRes {
                 block: b.into_vec(),
             })
             .collect();
-        protobuf::IterBlocksResponse { blocks }
+        protobuf::IterBlocksResponse {
+            blocks,
+            deprecation_warning: None,
+        }
     }
 }
 
@@ -294,6 +301,7 @@ impl ToProto for BlockRes {
         match self.0 {
             None => protobuf::BlockResponse {
                 block_content: None,
+                deprecation_warning: None,
             },
             Some(Ok(block)) => protobuf::BlockResponse {
                 block_content: Some(protobuf::block_response::BlockContent::Block(
@@ -301,12 +309,16 @@ impl ToProto for BlockRes {
                         block: block.0.to_vec(),
                     },
                 )),
+                deprecation_warning: None,
             },
             Some(Err(canister_id)) => {
                 let block_content = Some(protobuf::block_response::BlockContent::CanisterId(
                     canister_id.get(),
                 ));
-                protobuf::BlockResponse { block_content }
+                protobuf::BlockResponse {
+                    block_content,
+                    deprecation_warning: None,
+                }
             }
         }
     }
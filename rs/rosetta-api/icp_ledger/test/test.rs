@@ -50,7 +50,7 @@ fn example_block() -> Block {
         Memo(456),
         TimeStamp::new(2_000_000_000, 123_456_789),
     );
-    Block::from_transaction(None, transaction, TimeStamp::new(1, 1))
+    Block::from_transaction(None, transaction, TimeStamp::new(1, 1), None)
 }
 
 async fn simple_send(
@@ -259,6 +259,9 @@ fn archive_blocks_small_test() {
             controller_id: CanisterId::from_u64(876).into(),
             cycles_for_archive_creation: Some(0),
             max_transactions_per_response: None,
+            max_archive_nodes: None,
+            cycles_for_archive_top_up: None,
+            cycles_for_archive_top_ups_pool: None,
         };
 
         println!("[test] installing ledger canister");
@@ -394,6 +397,9 @@ fn archive_blocks_large_test() {
             controller_id: CanisterId::from_u64(876).into(),
             cycles_for_archive_creation: Some(0),
             max_transactions_per_response: None,
+            max_archive_nodes: None,
+            cycles_for_archive_top_up: None,
+            cycles_for_archive_top_ups_pool: None,
         };
 
         println!("[test] installing ledger canister");
@@ -560,6 +566,9 @@ fn archived_blocks_ranges() {
             controller_id: CanisterId::from_u64(876).into(),
             cycles_for_archive_creation: Some(0),
             max_transactions_per_response: None,
+            max_archive_nodes: None,
+            cycles_for_archive_top_up: None,
+            cycles_for_archive_top_ups_pool: None,
         };
 
         let minting_account = create_sender(0);
@@ -723,6 +732,9 @@ fn notify_test() {
             num_blocks_to_archive: 3,
             cycles_for_archive_creation: Some(0),
             max_transactions_per_response: None,
+            max_archive_nodes: None,
+            cycles_for_archive_top_up: None,
+            cycles_for_archive_top_ups_pool: None,
         };
 
         let ledger_canister = proj
@@ -899,6 +911,9 @@ fn notify_disabled_test() {
             num_blocks_to_archive: 3,
             cycles_for_archive_creation: Some(0),
             max_transactions_per_response: None,
+            max_archive_nodes: None,
+            cycles_for_archive_top_up: None,
+            cycles_for_archive_top_ups_pool: None,
         };
 
         let ledger_canister = proj
@@ -1369,6 +1384,9 @@ fn get_block_test() {
             controller_id: CanisterId::from_u64(876).into(),
             cycles_for_archive_creation: Some(0),
             max_transactions_per_response: None,
+            max_archive_nodes: None,
+            cycles_for_archive_top_up: None,
+            cycles_for_archive_top_ups_pool: None,
         };
 
         println!(
@@ -1487,7 +1505,7 @@ fn get_block_test() {
 
         assert_eq!(blk(&block_from_ledger), blk(&block_from_archive));
 
-        let icp_ledger::protobuf::ArchiveIndexResponse { entries } =
+        let icp_ledger::protobuf::ArchiveIndexResponse { entries, .. } =
             ledger.query_("get_archive_index_pb", protobuf, ()).await?;
         println!("[test] archive_index: {:?}", entries);
 
@@ -1529,6 +1547,9 @@ fn get_multiple_blocks_test() {
             controller_id: CanisterId::from_u64(876).into(),
             cycles_for_archive_creation: Some(0),
             max_transactions_per_response: None,
+            max_archive_nodes: None,
+            cycles_for_archive_top_up: None,
+            cycles_for_archive_top_ups_pool: None,
         };
 
         println!(
@@ -1707,6 +1728,9 @@ fn only_ledger_can_append_blocks_to_archive_nodes() {
             controller_id: CanisterId::from_u64(876).into(),
             cycles_for_archive_creation: Some(0),
             max_transactions_per_response: None,
+            max_archive_nodes: None,
+            cycles_for_archive_top_up: None,
+            cycles_for_archive_top_ups_pool: None,
         };
 
         println!(
@@ -1745,7 +1769,7 @@ fn only_ledger_can_append_blocks_to_archive_nodes() {
             // Create a non-ledger sender
             let sender = create_sender(1234);
 
-            let icp_ledger::protobuf::ArchiveIndexResponse { entries } =
+            let icp_ledger::protobuf::ArchiveIndexResponse { entries, .. } =
                 ledger.query_("get_archive_index_pb", protobuf, ()).await?;
 
             let node_canister_id = CanisterId::try_from(entries[0].canister_id.unwrap()).unwrap();
@@ -1827,6 +1851,9 @@ fn test_archives_endpoint() {
                             controller_id: minting_canister_id.into(),
                             cycles_for_archive_creation: None,
                             max_transactions_per_response: None,
+                            max_archive_nodes: None,
+                            cycles_for_archive_top_up: None,
+                            cycles_for_archive_top_ups_pool: None,
                         })
                         .build()
                         .unwrap(),
@@ -5,6 +5,7 @@ use ic_icrc1::{
     Block,
 };
 use ic_ledger_core::block::{BlockIndex, BlockType, EncodedBlock};
+use ic_ledger_core::tokens_u256::U256;
 use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
 use ic_stable_structures::{
     cell::Cell as StableCell, log::Log as StableLog, memory_manager::MemoryManager,
@@ -115,7 +116,8 @@ fn with_blocks<R>(f: impl FnOnce(&BlockLog) -> R) -> R {
 }
 
 fn decode_transaction(txid: u64, bytes: Vec<u8>) -> Transaction {
-    Block::decode(EncodedBlock::from(bytes))
+    // The widest token type decodes the blocks of all ledgers.
+    Block::<U256>::decode(EncodedBlock::from(bytes))
         .unwrap_or_else(|e| ic_cdk::api::trap(&format!("failed to decode block {}: {}", txid, e)))
         .into()
 }
@@ -204,6 +206,12 @@ fn remaining_capacity() -> usize {
     })
 }
 
+#[query]
+#[candid_method(query)]
+fn cycles_balance() -> u64 {
+    ic_cdk::api::canister_balance()
+}
+
 #[query]
 #[candid_method(query)]
 fn get_transaction(index: BlockIndex) -> Option<Transaction> {
@@ -2,10 +2,14 @@ use async_trait::async_trait;
 use candid::types::number::Nat;
 use candid::utils::{ArgumentDecoder, ArgumentEncoder};
 use candid::Principal;
-use ic_icrc1::endpoints::{TransferArg, TransferError, Value};
+use ic_icrc1::endpoints::{
+    GetTransactionsRequest, GetTransactionsResponse, Transaction, TransactionRange, TransferArg,
+    TransferError, Value,
+};
 pub use ic_icrc1::Account;
 use ic_ledger_core::block::BlockIndex;
 use num_traits::ToPrimitive;
+use std::fmt;
 
 // Abstraction over the runtime. Implement this in terms of cdk call if you use
 // the cdk or dfn_* if you use dfn_* call.
@@ -30,6 +34,46 @@ fn nat_to_u64(n: Nat) -> u64 {
     n.0.to_u64().expect("nat does not fit into u64")
 }
 
+/// An error of [ICRC1Client::get_transactions_with_archives].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GetTransactionsError {
+    /// A call to the ledger or to an archive failed.
+    CallFailed {
+        canister_id: Principal,
+        method: String,
+        code: i32,
+        message: String,
+    },
+    /// The ledger or an archive returned transactions that don't match the
+    /// requested range.
+    InvalidResponse(String),
+}
+
+impl fmt::Display for GetTransactionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CallFailed {
+                canister_id,
+                method,
+                code,
+                message,
+            } => write!(
+                f,
+                "call to {} of canister {} failed with code {}: {}",
+                method, canister_id, code, message
+            ),
+            Self::InvalidResponse(message) => write!(f, "invalid response: {}", message),
+        }
+    }
+}
+
+/// Converts a transaction index that a canister returned to u64.
+fn index_to_u64(n: &Nat) -> Result<u64, GetTransactionsError> {
+    n.0.to_u64().ok_or_else(|| {
+        GetTransactionsError::InvalidResponse(format!("index {} is not a valid u64", n))
+    })
+}
+
 pub struct ICRC1Client<R: Runtime> {
     pub runtime: R,
     pub ledger_canister_id: Principal,
@@ -111,6 +155,101 @@ impl<R: Runtime> ICRC1Client<R> {
             .map(untuple)?;
         Ok(result.map(nat_to_u64))
     }
+
+    /// Returns the transactions that the ledger stores in the given range and
+    /// the ranges of archived transactions. See
+    /// [ICRC1Client::get_transactions_with_archives] for a function that
+    /// also fetches the archived transactions.
+    pub async fn get_transactions(
+        &self,
+        start: BlockIndex,
+        length: u64,
+    ) -> Result<GetTransactionsResponse, (i32, String)> {
+        let request = GetTransactionsRequest {
+            start: Nat::from(start),
+            length: Nat::from(length),
+        };
+        self.runtime
+            .call(self.ledger_canister_id, "get_transactions", (request,))
+            .await
+            .map(untuple)
+    }
+
+    /// Returns consecutive transactions starting at index `start`, both the
+    /// ones that the ledger stores and the ones that it moved to archives.
+    ///
+    /// The result contains fewer than `length` transactions if the log ends
+    /// earlier or if the ledger limits the size of its response. Callers that
+    /// need more transactions call this function again, starting after the
+    /// last returned transaction.
+    pub async fn get_transactions_with_archives(
+        &self,
+        start: BlockIndex,
+        length: u64,
+    ) -> Result<Vec<Transaction>, GetTransactionsError> {
+        let response = self
+            .get_transactions(start, length)
+            .await
+            .map_err(|(code, message)| GetTransactionsError::CallFailed {
+                canister_id: self.ledger_canister_id,
+                method: "get_transactions".to_string(),
+                code,
+                message,
+            })?;
+
+        let mut transactions = vec![];
+        let mut next_index = start;
+        for archived in response.archived_transactions {
+            let range_start = index_to_u64(&archived.start)?;
+            if range_start != next_index {
+                return Err(GetTransactionsError::InvalidResponse(format!(
+                    "expected an archived range starting at {}, got one starting at {}",
+                    next_index, range_start
+                )));
+            }
+            let range_end = range_start.saturating_add(index_to_u64(&archived.length)?);
+            let canister_id = archived.callback.canister_id.get().0;
+            // Archives limit the number of transactions in a response, so a
+            // range might take several calls.
+            while next_index < range_end {
+                let request = GetTransactionsRequest {
+                    start: Nat::from(next_index),
+                    length: Nat::from(range_end - next_index),
+                };
+                let range: TransactionRange = self
+                    .runtime
+                    .call(canister_id, &archived.callback.method, (request,))
+                    .await
+                    .map(untuple)
+                    .map_err(|(code, message)| GetTransactionsError::CallFailed {
+                        canister_id,
+                        method: archived.callback.method.clone(),
+                        code,
+                        message,
+                    })?;
+                if range.transactions.is_empty() {
+                    return Err(GetTransactionsError::InvalidResponse(format!(
+                        "archive {} returned no transactions starting at {}",
+                        canister_id, next_index
+                    )));
+                }
+                next_index += range.transactions.len() as u64;
+                transactions.extend(range.transactions);
+            }
+        }
+
+        if !response.transactions.is_empty() {
+            let first_index = index_to_u64(&response.first_index)?;
+            if first_index != next_index {
+                return Err(GetTransactionsError::InvalidResponse(format!(
+                    "expected the ledger transactions to start at {}, got {}",
+                    next_index, first_index
+                )));
+            }
+            transactions.extend(response.transactions);
+        }
+        Ok(transactions)
+    }
 }
 
 // extract the element from an unary tuple
@@ -4,11 +4,12 @@ use std::collections::{btree_map, BTreeMap};
 use candid::{CandidType, Nat};
 use ic_base_types::{CanisterId, PrincipalId};
 use ic_cdk::api::stable::{StableReader, StableWriter};
-use ic_icrc1::endpoints::{ArchivedTransactionRange, TransactionRange};
 use ic_icrc1::{
-    endpoints::{GetTransactionsRequest, GetTransactionsResponse, Transaction, Transfer},
+    endpoints::{Transaction, Transfer},
     Account, Subaccount,
 };
+use ic_icrc1_client::ICRC1Client;
+use ic_icrc1_client_cdk::CdkRuntime;
 use num_traits::cast::ToPrimitive;
 use serde::{Deserialize, Serialize};
 use std::ops::Bound::{Included, Unbounded};
@@ -172,74 +173,21 @@ pub async fn heartbeat() {
     }
 }
 
-async fn get_transactions_from_ledger(
-    start: u64,
-    length: usize,
-) -> Result<GetTransactionsResponse, String> {
-    let ledger_id = ledger_id();
-    let req = GetTransactionsRequest {
-        start: Nat::from(start),
-        length: Nat::from(length),
-    };
-    let (res,): (GetTransactionsResponse,) =
-        ic_cdk::call(ledger_id.get().0, "get_transactions", (req,))
-            .await
-            .map_err(|(code, str)| format!("code: {:#?} message: {}", code, str))?;
-    Ok(res)
-}
-
-async fn get_transactions_from_archive(
-    archived: &ArchivedTransactionRange,
-) -> Result<TransactionRange, String> {
-    let req = GetTransactionsRequest {
-        start: archived.start.clone(),
-        length: archived.length.clone(),
-    };
-    let (res,): (TransactionRange,) = ic_cdk::call(
-        archived.callback.canister_id.get().0,
-        &archived.callback.method,
-        (req,),
-    )
-    .await
-    .map_err(|(code, str)| format!("code: {:#?} message: {}", code, str))?;
-    Ok(res)
+fn ledger_client() -> ICRC1Client<CdkRuntime> {
+    ICRC1Client {
+        runtime: CdkRuntime,
+        ledger_canister_id: ledger_id().get().0,
+    }
 }
 
 async fn build_index() -> Result<(), String> {
     let next_txid = with_index(|idx| idx.next_txid);
-    let res = get_transactions_from_ledger(next_txid, MAX_TRANSACTIONS_PER_RESPONSE).await?;
-    for archived in res.archived_transactions {
-        // The archive node limits the number of transactions returned by a
-        // single get_transaction call.
-        let last_txid = archived.start.clone() + archived.length.clone();
-        let mut next_archived_txid = archived.start.clone();
-        while next_archived_txid < last_txid {
-            let archived = ArchivedTransactionRange {
-                start: next_archived_txid,
-                length: archived.length.clone(),
-                callback: archived.callback.clone(),
-            };
-            let res = get_transactions_from_archive(&archived).await?;
-            let mut idx = archived
-                .start
-                .0
-                .to_u64()
-                .ok_or("The Ledger returned an index that is not a valid u64")?;
-            for transaction in res.transactions {
-                index_transaction(idx, transaction)?;
-                idx += 1;
-            }
-            next_archived_txid = Nat::from(idx);
-        }
-    }
-    let mut idx = res
-        .first_index
-        .0
-        .to_u64()
-        .ok_or("The Ledger returned an index that is not a valid u64")?;
-    for transaction in res.transactions {
-        index_transaction(idx, transaction)?;
-        idx += 1;
+    let transactions = ledger_client()
+        .get_transactions_with_archives(next_txid, MAX_TRANSACTIONS_PER_RESPONSE as u64)
+        .await
+        .map_err(|e| e.to_string())?;
+    for (txid, transaction) in (next_txid..).zip(transactions) {
+        index_transaction(txid, transaction)?;
     }
     Ok(())
 }
@@ -270,6 +218,11 @@ fn index_transaction(txid: u64, transaction: Transaction) -> Result<(), String>
             add_tx(txid, to);
             Ok(())
         }
+        "upgrade" => {
+            // Configuration changes don't involve any account.
+            with_index_mut(|idx| idx.next_txid = txid + 1);
+            Ok(())
+        }
         kind => Err(format!("Found transaction of unknown kind {}", kind)),
     }
 }
@@ -349,33 +302,13 @@ pub async fn get_account_transactions(args: GetAccountTransactionsArgs) -> GetTr
     let txids = get_account_transactions_ids(args);
     let mut txs = vec![];
     for txid in &txids {
-        match get_transactions_from_ledger(*txid, 1).await {
+        match ledger_client().get_transactions_with_archives(*txid, 1).await {
             Ok(mut res) => {
-                if let Some(tx) = res.transactions.pop() {
+                if let Some(tx) = res.pop() {
                     txs.push(TransactionWithId {
                         id: Nat::from(*txid),
                         transaction: tx,
                     })
-                } else if let Some(archive) = res.archived_transactions.get(0) {
-                    match get_transactions_from_archive(archive).await {
-                        Ok(res) if !res.transactions.is_empty() => txs.push(TransactionWithId {
-                            id: Nat::from(*txid),
-                            transaction: res.transactions.get(0).unwrap().clone(),
-                        }),
-                        Ok(_) => {
-                            let message = format!("Error fetching transaction {} from archive {}: archive didn't   return the transaction!", txid, archive.callback.canister_id);
-                            ic_cdk::eprintln!("{}{}", LOG_PREFIX, message);
-                            return Err(GetTransactionsErr { message });
-                        }
-                        Err(e) => {
-                            let message = format!(
-                                "Error fetching transaction {}from archive {}: {}",
-                                txid, archive.callback.canister_id, e
-                            );
-                            ic_cdk::eprintln!("{}{}", LOG_PREFIX, message);
-                            return Err(GetTransactionsErr { message });
-                        }
-                    }
                 }
             }
             Err(e) => {
@@ -15,6 +15,7 @@ use ic_ledger_canister_core::archive::ArchiveOptions;
 use ic_ledger_core::{
     block::{BlockIndex, BlockType, EncodedBlock, HashOf},
     timestamp::TimeStamp,
+    tokens::Tokens,
 };
 use ic_state_machine_tests::{CanisterId, StateMachine};
 use num_traits::cast::ToPrimitive;
@@ -66,12 +67,13 @@ fn mint_block() -> EncodedBlock {
         Transaction {
             operation: Operation::Mint {
                 to: account(0),
-                amount: 1,
+                amount: Tokens::from_e8s(1),
             },
             created_at_time: Some(1),
             memo: Some(Memo::from([1; 32])),
         },
         TimeStamp::new(3, 4),
+        None,
     )
     .encode()
 }
@@ -85,6 +87,9 @@ fn default_archive_options() -> ArchiveOptions {
         controller_id: PrincipalId::new_user_test_id(100),
         cycles_for_archive_creation: None,
         max_transactions_per_response: None,
+        max_archive_nodes: None,
+        cycles_for_archive_top_up: None,
+        cycles_for_archive_top_ups_pool: None,
     }
 }
 
@@ -106,6 +111,8 @@ fn install_ledger(
             Value::entry(BLOB_META_KEY, BLOB_META_VALUE),
         ],
         archive_options,
+        fee_collector_account: None,
+        decimals: None,
     };
     env.install_canister(ledger_wasm(), Encode!(&args).unwrap(), None)
         .unwrap()
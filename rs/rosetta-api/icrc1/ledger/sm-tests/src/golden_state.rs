@@ -0,0 +1,227 @@
+//! Upgrade and downgrade tests that can start from a golden ledger state.
+//!
+//! A golden state is the stable memory of a ledger after `pre_upgrade`, e.g.,
+//! taken from a backup of a mainnet ledger. The harness loads it into a new
+//! `StateMachine`, upgrades the ledger to the new version, downgrades it to
+//! the previous version, and upgrades it again, with live transfers between
+//! the steps. Every step must keep the blocks, which contain the hashes of
+//! their parents, the balances, and the total supply.
+//!
+//! The archives of a golden state don't exist in the `StateMachine`, so the
+//! harness compares only the blocks of the ledger and of the archives that
+//! the ledger created during the test.
+
+use crate::{
+    balance_of, icrc3_get_blocks, install_ledger, minting_account, send_transfer, total_supply,
+    InitArgs,
+};
+use candid::{CandidType, Decode, Encode, Nat};
+use ic_base_types::PrincipalId;
+use ic_icrc1::endpoints::TransferArg;
+use ic_icrc1::{icrc3, Account};
+use ic_state_machine_tests::{CanisterId, StateMachine};
+use num_traits::ToPrimitive;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The number of blocks requested in a single `icrc3_get_blocks` call.
+const BLOCKS_BATCH_LEN: u64 = 1_000;
+
+/// The tokens minted to every traffic account in every round of traffic.
+const TRAFFIC_MINT_AMOUNT: u64 = 100_000_000;
+
+/// Installs a ledger with the given stable memory, as written by the
+/// `pre_upgrade` of `ledger_wasm` or of an earlier version.
+pub fn load_golden_state(
+    env: &StateMachine,
+    ledger_wasm: Vec<u8>,
+    stable_memory: &[u8],
+) -> CanisterId {
+    // An empty module has no pre_upgrade hook that would overwrite the
+    // stable memory on the upgrade to the ledger.
+    let canister_id = env.install_canister_wat("(module)", vec![], None);
+    env.set_stable_memory(canister_id, stable_memory);
+    env.upgrade_canister(canister_id, ledger_wasm, vec![])
+        .expect("failed to load the golden state");
+    canister_id
+}
+
+/// The state of a ledger that upgrades must keep.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LedgerSnapshot {
+    pub blocks: BTreeMap<u64, icrc3::Value>,
+    pub balances: BTreeMap<Account, u64>,
+    pub total_supply: u64,
+}
+
+fn get_blocks_from(
+    env: &StateMachine,
+    canister_id: CanisterId,
+    method: &str,
+    args: Vec<icrc3::GetBlocksArgs>,
+) -> icrc3::GetBlocksResult {
+    Decode!(
+        &env.query(canister_id, method, Encode!(&args).unwrap())
+            .expect("failed to get blocks")
+            .bytes(),
+        icrc3::GetBlocksResult
+    )
+    .expect("failed to decode the blocks")
+}
+
+/// Returns the blocks of the ledger and of the archives that exist.
+fn available_blocks(env: &StateMachine, ledger: CanisterId) -> BTreeMap<u64, icrc3::Value> {
+    let mut blocks = BTreeMap::new();
+    let mut add_blocks = |result: &icrc3::GetBlocksResult| {
+        for block in &result.blocks {
+            blocks.insert(block.id.0.to_u64().unwrap(), block.block.clone());
+        }
+    };
+    let mut start = 0;
+    loop {
+        let result = icrc3_get_blocks(
+            env,
+            ledger,
+            vec![icrc3::GetBlocksArgs::new(start, BLOCKS_BATCH_LEN)],
+        );
+        add_blocks(&result);
+        for archived in &result.archived_blocks {
+            let archive = archived.callback.canister_id;
+            if env.canister_exists(archive) {
+                let method = &archived.callback.method;
+                add_blocks(&get_blocks_from(env, archive, method, archived.args.clone()));
+            }
+        }
+        start += BLOCKS_BATCH_LEN;
+        if start >= result.log_length.0.to_u64().unwrap() {
+            break;
+        }
+    }
+    blocks
+}
+
+/// Returns the ICRC-1 accounts in the blocks. Accounts encoded as account
+/// identifiers are skipped.
+fn accounts_in_blocks(blocks: &BTreeMap<u64, icrc3::Value>) -> BTreeSet<Account> {
+    let mut accounts = BTreeSet::new();
+    for block in blocks.values() {
+        let tx = match block.get("tx") {
+            Some(tx) => tx,
+            None => continue,
+        };
+        for key in ["from", "to"] {
+            if let Some(icrc3::Value::Array(parts)) = tx.get(key) {
+                let owner = match parts.first() {
+                    Some(icrc3::Value::Blob(owner)) => PrincipalId::try_from(owner.as_slice()),
+                    _ => continue,
+                };
+                let subaccount = match parts.get(1) {
+                    Some(icrc3::Value::Blob(subaccount)) => {
+                        <[u8; 32]>::try_from(subaccount.as_slice()).ok()
+                    }
+                    _ => None,
+                };
+                if let Ok(owner) = owner {
+                    accounts.insert(Account { owner, subaccount });
+                }
+            }
+        }
+    }
+    accounts
+}
+
+/// Returns the available blocks and the balances of the accounts in them.
+pub fn take_snapshot(env: &StateMachine, ledger: CanisterId) -> LedgerSnapshot {
+    let blocks = available_blocks(env, ledger);
+    let balances = accounts_in_blocks(&blocks)
+        .into_iter()
+        .map(|account| (account.clone(), balance_of(env, ledger, account)))
+        .collect();
+    LedgerSnapshot {
+        blocks,
+        balances,
+        total_supply: total_supply(env, ledger),
+    }
+}
+
+fn check_same_state(step: &str, before: &LedgerSnapshot, after: &LedgerSnapshot) {
+    assert_eq!(
+        before.blocks.keys().collect::<Vec<_>>(),
+        after.blocks.keys().collect::<Vec<_>>(),
+        "the {} changed the available blocks",
+        step
+    );
+    for (index, block) in &before.blocks {
+        assert_eq!(Some(block), after.blocks.get(index), "the {} changed block {}", step, index);
+    }
+    assert_eq!(before.balances, after.balances, "the {} changed the balances", step);
+    assert_eq!(before.total_supply, after.total_supply, "the {} changed the total supply", step);
+}
+
+fn traffic_accounts() -> Vec<Account> {
+    (1..=3)
+        .map(|i| Account {
+            owner: PrincipalId::new_user_test_id(i),
+            subaccount: Some([i as u8; 32]),
+        })
+        .collect()
+}
+
+/// Mints tokens to the traffic accounts and sends transfers between them.
+fn send_traffic(env: &StateMachine, ledger: CanisterId) {
+    let minter = minting_account(env, ledger).expect("the ledger has no minting account");
+    let accounts = traffic_accounts();
+    let transfer = |from: &Account, to: &Account, amount: u64| {
+        let arg = TransferArg {
+            from_subaccount: from.subaccount,
+            to: to.clone(),
+            fee: None,
+            created_at_time: None,
+            memo: None,
+            amount: Nat::from(amount),
+        };
+        send_transfer(env, ledger, from, &arg)
+            .unwrap_or_else(|e| panic!("failed to transfer from {} to {}: {:?}", from, to, e));
+    };
+    for account in &accounts {
+        transfer(&minter, account, TRAFFIC_MINT_AMOUNT);
+    }
+    for (from, to) in accounts.iter().zip(accounts.iter().cycle().skip(1)) {
+        transfer(from, to, TRAFFIC_MINT_AMOUNT / 10);
+    }
+}
+
+/// Upgrades the ledger from `previous_ledger_wasm` to `ledger_wasm`,
+/// downgrades it, and upgrades it again, and checks that no step changes the
+/// ledger state. The ledger starts from `golden_state` if it is set, see
+/// `load_golden_state`, and from a new ledger otherwise.
+pub fn test_upgrade_downgrade<T>(
+    ledger_wasm: Vec<u8>,
+    previous_ledger_wasm: Vec<u8>,
+    encode_init_args: fn(InitArgs) -> T,
+    golden_state: Option<Vec<u8>>,
+) where
+    T: CandidType,
+{
+    let env = StateMachine::new();
+    let ledger = match golden_state {
+        Some(stable_memory) => {
+            load_golden_state(&env, previous_ledger_wasm.clone(), &stable_memory)
+        }
+        None => install_ledger(&env, previous_ledger_wasm.clone(), encode_init_args, vec![]),
+    };
+
+    let steps = [
+        ("upgrade", &ledger_wasm),
+        ("downgrade", &previous_ledger_wasm),
+        ("re-upgrade", &ledger_wasm),
+    ];
+    for (step, wasm) in steps {
+        send_traffic(&env, ledger);
+        let before = take_snapshot(&env, ledger);
+        env.upgrade_canister(ledger, wasm.clone(), vec![])
+            .unwrap_or_else(|e| panic!("the {} failed: {}", step, e));
+        check_same_state(step, &before, &take_snapshot(&env, ledger));
+    }
+    // The ledger keeps working after the last upgrade.
+    send_traffic(&env, ledger);
+}
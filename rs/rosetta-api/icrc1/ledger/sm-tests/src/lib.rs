@@ -1,14 +1,18 @@
 use candid::{CandidType, Decode, Encode, Nat};
 use ic_base_types::PrincipalId;
 use ic_icrc1::{
-    endpoints::{StandardRecord, Value},
-    Account,
+    endpoints::{StandardRecord, TransferArg, TransferError, Value},
+    icrc3, Account,
 };
 use ic_ledger_canister_core::archive::ArchiveOptions;
 use ic_state_machine_tests::{CanisterId, StateMachine};
 use num_traits::ToPrimitive;
+use proptest::test_runner::{Config as TestRunnerConfig, TestRunner};
 use std::{collections::BTreeMap, time::Duration};
 
+pub mod golden_state;
+pub mod scenario;
+
 pub const FEE: u64 = 10_000;
 pub const ARCHIVE_TRIGGER_THRESHOLD: u64 = 10;
 pub const NUM_BLOCKS_TO_ARCHIVE: u64 = 5;
@@ -55,6 +59,22 @@ pub fn total_supply(env: &StateMachine, ledger: CanisterId) -> u64 {
     .unwrap()
 }
 
+pub fn send_transfer(
+    env: &StateMachine,
+    ledger: CanisterId,
+    from: &Account,
+    arg: &TransferArg,
+) -> Result<u64, TransferError> {
+    Decode!(
+        &env.execute_ingress_as(from.owner, ledger, "icrc1_transfer", Encode!(arg).unwrap())
+            .expect("failed to transfer funds")
+            .bytes(),
+        Result<Nat, TransferError>
+    )
+    .expect("failed to decode transfer response")
+    .map(|n| n.0.to_u64().unwrap())
+}
+
 pub fn supported_standards(env: &StateMachine, ledger: CanisterId) -> Vec<StandardRecord> {
     Decode!(
         &env.query(ledger, "icrc1_supported_standards", Encode!().unwrap())
@@ -100,6 +120,127 @@ pub fn metadata(env: &StateMachine, ledger: CanisterId) -> BTreeMap<String, Valu
     .collect()
 }
 
+pub fn icrc3_get_blocks(
+    env: &StateMachine,
+    ledger: CanisterId,
+    args: Vec<icrc3::GetBlocksArgs>,
+) -> icrc3::GetBlocksResult {
+    Decode!(
+        &env.query(ledger, "icrc3_get_blocks", Encode!(&args).unwrap())
+            .expect("failed to query icrc3_get_blocks")
+            .bytes(),
+        icrc3::GetBlocksResult
+    )
+    .expect("failed to decode icrc3_get_blocks response")
+}
+
+pub fn icrc3_get_archives(
+    env: &StateMachine,
+    ledger: CanisterId,
+    from: Option<PrincipalId>,
+) -> Vec<icrc3::ArchiveInfo> {
+    Decode!(
+        &env.query(
+            ledger,
+            "icrc3_get_archives",
+            Encode!(&icrc3::GetArchivesArgs { from }).unwrap()
+        )
+        .expect("failed to query icrc3_get_archives")
+        .bytes(),
+        Vec<icrc3::ArchiveInfo>
+    )
+    .expect("failed to decode icrc3_get_archives response")
+}
+
+pub fn icrc3_supported_block_types(
+    env: &StateMachine,
+    ledger: CanisterId,
+) -> Vec<icrc3::SupportedBlockType> {
+    Decode!(
+        &env.query(ledger, "icrc3_supported_block_types", Encode!().unwrap())
+            .expect("failed to query icrc3_supported_block_types")
+            .bytes(),
+        Vec<icrc3::SupportedBlockType>
+    )
+    .expect("failed to decode icrc3_supported_block_types response")
+}
+
+/// Checks that the block with the given index follows the schema of its block
+/// type and returns the block type.
+pub type BlockValidator = fn(u64, &icrc3::Value) -> Result<String, String>;
+
+/// Checks that the block with the given index follows the schema of the
+/// ICRC-1 block types in the ICRC-3 standard and returns the block type,
+/// e.g., "1xfer".
+pub fn validate_icrc1_block(index: u64, block: &icrc3::Value) -> Result<String, String> {
+    fn field<'a>(value: &'a icrc3::Value, key: &str) -> Result<&'a icrc3::Value, String> {
+        value
+            .get(key)
+            .ok_or_else(|| format!("missing field {} in {:?}", key, value))
+    }
+    fn check_nat(value: &icrc3::Value, key: &str) -> Result<(), String> {
+        match field(value, key)? {
+            icrc3::Value::Nat(_) => Ok(()),
+            other => Err(format!("field {} must be a Nat, got {:?}", key, other)),
+        }
+    }
+    fn check_account(value: &icrc3::Value, key: &str) -> Result<(), String> {
+        match field(value, key)? {
+            icrc3::Value::Array(parts)
+                if (1..=2).contains(&parts.len())
+                    && parts.iter().all(|p| matches!(p, icrc3::Value::Blob(_))) =>
+            {
+                Ok(())
+            }
+            other => Err(format!("field {} must be an account, got {:?}", key, other)),
+        }
+    }
+
+    if !matches!(block, icrc3::Value::Map(_)) {
+        return Err(format!("block {} must be a map, got {:?}", index, block));
+    }
+    match block.get("phash") {
+        Some(icrc3::Value::Blob(hash)) if index > 0 && hash.len() == 32 => (),
+        None if index == 0 => (),
+        other => return Err(format!("block {} has an invalid phash {:?}", index, other)),
+    }
+    check_nat(block, "ts")?;
+
+    let tx = field(block, "tx")?;
+    // The block type is either in the btype field or, in blocks of the
+    // previous ICRC-3 revision, in the op field of the transaction.
+    let block_type = match (block.get("btype"), tx.get("op")) {
+        (Some(icrc3::Value::Text(block_type)), None) => block_type.clone(),
+        (None, Some(icrc3::Value::Text(op))) => format!("1{}", op),
+        (btype, op) => {
+            return Err(format!(
+                "block {} has an invalid type: btype {:?}, op {:?}",
+                index, btype, op
+            ))
+        }
+    };
+    match block_type.as_str() {
+        "1mint" => check_account(tx, "to")?,
+        "1burn" => check_account(tx, "from")?,
+        "1xfer" => {
+            check_account(tx, "from")?;
+            check_account(tx, "to")?;
+        }
+        _ => return Err(format!("block {} has an unknown type {}", index, block_type)),
+    }
+    check_nat(tx, "amt")?;
+    for optional_nat in ["fee", "ts"] {
+        if tx.get(optional_nat).is_some() {
+            check_nat(tx, optional_nat)?;
+        }
+    }
+    match tx.get("memo") {
+        None | Some(icrc3::Value::Blob(_)) => (),
+        Some(other) => return Err(format!("field memo must be a Blob, got {:?}", other)),
+    }
+    Ok(block_type)
+}
+
 fn init_args(initial_balances: Vec<(Account, u64)>) -> InitArgs {
     InitArgs {
         minting_account: MINTER.clone(),
@@ -121,6 +262,9 @@ fn init_args(initial_balances: Vec<(Account, u64)>) -> InitArgs {
             controller_id: PrincipalId::new_user_test_id(100),
             cycles_for_archive_creation: None,
             max_transactions_per_response: None,
+            max_archive_nodes: None,
+            cycles_for_archive_top_up: None,
+            cycles_for_archive_top_ups_pool: None,
         },
     }
 }
@@ -264,3 +408,128 @@ where
     let (env, canister_id) = setup(ledger_wasm, encode_init_args, vec![]);
     assert_eq!(Some(MINTER), minting_account(&env, canister_id));
 }
+
+pub fn test_icrc3_get_blocks<T>(
+    ledger_wasm: Vec<u8>,
+    encode_init_args: fn(InitArgs) -> T,
+    validate_block: BlockValidator,
+) where
+    T: CandidType,
+{
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let (env, canister_id) = setup(
+        ledger_wasm,
+        encode_init_args,
+        vec![
+            (Account::from(p1), 10_000_000),
+            (Account::from(p2), 5_000_000),
+        ],
+    );
+
+    let result = icrc3_get_blocks(&env, canister_id, vec![icrc3::GetBlocksArgs::new(0, 10)]);
+
+    assert_eq!(result.log_length, Nat::from(2));
+    assert!(result.archived_blocks.is_empty());
+    assert_eq!(result.blocks.len(), 2);
+    let mut amounts = vec![];
+    for (i, block) in result.blocks.iter().enumerate() {
+        assert_eq!(block.id, Nat::from(i as u64));
+        validate_block(i as u64, &block.block).unwrap();
+        let tx = block.block.get("tx").expect("block without a transaction");
+        amounts.push(tx.get("amt").cloned());
+    }
+    assert!(amounts.contains(&Some(icrc3::Value::from(10_000_000_u64))));
+    assert!(amounts.contains(&Some(icrc3::Value::from(5_000_000_u64))));
+}
+
+pub fn test_icrc3_get_blocks_ranges<T>(ledger_wasm: Vec<u8>, encode_init_args: fn(InitArgs) -> T)
+where
+    T: CandidType,
+{
+    let initial_balances = (1..=4)
+        .map(|i| (Account::from(PrincipalId::new_user_test_id(i)), i * 1_000_000))
+        .collect();
+    let (env, canister_id) = setup(ledger_wasm, encode_init_args, initial_balances);
+
+    let block_ids = |args: Vec<icrc3::GetBlocksArgs>| -> Vec<Nat> {
+        let result = icrc3_get_blocks(&env, canister_id, args);
+        assert_eq!(result.log_length, Nat::from(4));
+        result.blocks.into_iter().map(|block| block.id).collect()
+    };
+
+    assert_eq!(block_ids(vec![]), Vec::<Nat>::new());
+    assert_eq!(block_ids(vec![icrc3::GetBlocksArgs::new(1, 0)]), Vec::<Nat>::new());
+    assert_eq!(block_ids(vec![icrc3::GetBlocksArgs::new(4, 10)]), Vec::<Nat>::new());
+    assert_eq!(block_ids(vec![icrc3::GetBlocksArgs::new(2, 10)]), vec![Nat::from(2), Nat::from(3)]);
+    assert_eq!(
+        block_ids(vec![
+            icrc3::GetBlocksArgs::new(3, 1),
+            icrc3::GetBlocksArgs::new(0, 2),
+        ]),
+        vec![Nat::from(3), Nat::from(0), Nat::from(1)]
+    );
+}
+
+pub fn test_icrc3_supported_block_types<T>(
+    ledger_wasm: Vec<u8>,
+    encode_init_args: fn(InitArgs) -> T,
+    validate_block: BlockValidator,
+) where
+    T: CandidType,
+{
+    let p1 = PrincipalId::new_user_test_id(1);
+    let (env, canister_id) = setup(
+        ledger_wasm,
+        encode_init_args,
+        vec![(Account::from(p1), 10_000_000)],
+    );
+
+    let supported_block_types = icrc3_supported_block_types(&env, canister_id);
+    assert!(!supported_block_types.is_empty());
+    for block_type in &supported_block_types {
+        assert!(!block_type.url.is_empty(), "{:?} has no schema", block_type);
+    }
+    let block_types: Vec<String> = supported_block_types
+        .into_iter()
+        .map(|block_type| block_type.block_type)
+        .collect();
+
+    // Every block in the log follows the schema of one of the supported block
+    // types.
+    let result = icrc3_get_blocks(&env, canister_id, vec![icrc3::GetBlocksArgs::new(0, 10)]);
+    for block in result.blocks {
+        let id = block.id.0.to_u64().unwrap();
+        let block_type = validate_block(id, &block.block).unwrap();
+        assert!(
+            block_types.contains(&block_type),
+            "block type {} is not supported, supported types: {:?}",
+            block_type,
+            block_types
+        );
+    }
+}
+
+pub fn test_icrc3_get_archives_without_archives<T>(
+    ledger_wasm: Vec<u8>,
+    encode_init_args: fn(InitArgs) -> T,
+) where
+    T: CandidType,
+{
+    let (env, canister_id) = setup(ledger_wasm, encode_init_args, vec![]);
+    assert!(icrc3_get_archives(&env, canister_id, None).is_empty());
+}
+
+/// Checks the ledger against a model in randomly generated scenarios of
+/// transfers, mints, burns, resent transfers, time changes, and upgrades.
+pub fn test_generated_scenarios<T>(ledger_wasm: Vec<u8>, encode_init_args: fn(InitArgs) -> T)
+where
+    T: CandidType,
+{
+    let mut runner = TestRunner::new(TestRunnerConfig::with_cases(5));
+    runner
+        .run(&scenario::arb_scenario(40), |s| {
+            scenario::run_scenario(&ledger_wasm, encode_init_args, s)
+        })
+        .unwrap();
+}
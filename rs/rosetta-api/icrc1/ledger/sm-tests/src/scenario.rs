@@ -0,0 +1,322 @@
+//! Randomly generated scenarios of ledger operations and a model of the
+//! ledger that predicts their outcomes.
+//!
+//! A scenario is a sequence of transfers between a few accounts, including
+//! mints and burns through the minting account, transfers that resend the
+//! arguments of earlier transfers, time changes, and upgrades. The model
+//! predicts the result of every transfer and the balances after it, which
+//! checks that the ledger conserves the token supply, never lets a balance
+//! go negative, and deduplicates transactions correctly. Scenarios contain no
+//! approvals: the ledgers don't implement ICRC-2 yet.
+
+use crate::{balance_of, send_transfer, setup, total_supply, InitArgs, FEE, MINTER, TX_WINDOW};
+use candid::{CandidType, Nat};
+use ic_base_types::PrincipalId;
+use ic_icrc1::endpoints::{TransferArg, TransferError};
+use ic_icrc1::{Account, Memo};
+use ic_state_machine_tests::{CanisterId, StateMachine};
+use num_traits::ToPrimitive;
+use proptest::prelude::*;
+use proptest::sample::Index;
+use proptest::test_runner::TestCaseResult;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+/// The number of accounts in scenarios, see `account`.
+pub const NUM_ACCOUNTS: usize = 6;
+
+/// Returns the account with the given index. The account with index 0 is
+/// the minting account, the others belong to two principals.
+pub fn account(index: usize) -> Account {
+    if index == 0 {
+        return MINTER;
+    }
+    Account {
+        owner: PrincipalId::new_user_test_id(index as u64 % 2 + 1),
+        subaccount: Some([index as u8; 32]),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Step {
+    /// A transfer between the accounts with the given indices. Transfers
+    /// from the minting account are mints, transfers to it are burns.
+    Transfer {
+        from: usize,
+        to: usize,
+        amount: u64,
+        fee: Option<u64>,
+        memo: Option<u64>,
+        /// Whether to set the creation time, which enables deduplication.
+        dedup: bool,
+    },
+    /// Sends the arguments of one of the earlier transfers again.
+    Resend(Index),
+    AdvanceTime(Duration),
+    /// Upgrades the ledger to the same Wasm without arguments.
+    Upgrade,
+}
+
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    /// The initial balances by account index.
+    pub initial_balances: BTreeMap<usize, u64>,
+    pub steps: Vec<Step>,
+}
+
+fn arb_transfer() -> impl Strategy<Value = Step> {
+    (
+        0..NUM_ACCOUNTS,
+        0..NUM_ACCOUNTS,
+        prop_oneof![0..3 * FEE, 0..1_000 * FEE],
+        prop_oneof![
+            Just(None),
+            Just(Some(0)),
+            Just(Some(FEE)),
+            Just(Some(FEE + 1))
+        ],
+        proptest::option::of(0..3u64),
+        any::<bool>(),
+    )
+        .prop_map(|(from, to, amount, fee, memo, dedup)| Step::Transfer {
+            from,
+            // The minting account cannot send tokens to itself.
+            to: if from == 0 && to == 0 { 1 } else { to },
+            amount,
+            fee,
+            memo,
+            dedup,
+        })
+}
+
+fn arb_step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        10 => arb_transfer(),
+        3 => any::<Index>().prop_map(Step::Resend),
+        1 => (0..12 * 60 * 60u64).prop_map(|s| Step::AdvanceTime(Duration::from_secs(s))),
+        1 => Just(Step::Upgrade),
+    ]
+}
+
+/// Generates scenarios with up to `max_steps` steps.
+pub fn arb_scenario(max_steps: usize) -> impl Strategy<Value = Scenario> {
+    (
+        proptest::collection::btree_map(1..NUM_ACCOUNTS, 1..1_000 * FEE, 0..NUM_ACCOUNTS),
+        proptest::collection::vec(arb_step(), 1..max_steps),
+    )
+        .prop_map(|(initial_balances, steps)| Scenario {
+            initial_balances,
+            steps,
+        })
+}
+
+/// The sender, receiver, amount, creation time, and memo of a transaction.
+type TransactionKey = (Account, Account, u64, u64, Option<Memo>);
+
+/// A model of a ledger without a fee collector.
+#[derive(Clone, Debug, Default)]
+pub struct LedgerModel {
+    balances: BTreeMap<Account, u64>,
+    total_supply: u64,
+    num_blocks: u64,
+    /// The blocks of the transactions with a creation time.
+    transactions: BTreeMap<TransactionKey, u64>,
+}
+
+impl LedgerModel {
+    /// Returns the model of a ledger that minted the initial balances at
+    /// `now`, in nanoseconds since the Unix epoch.
+    pub fn new(initial_balances: &[(Account, u64)], now: u64) -> Self {
+        let mut model = Self::default();
+        for (account, amount) in initial_balances {
+            let arg = TransferArg {
+                from_subaccount: None,
+                to: account.clone(),
+                fee: None,
+                created_at_time: Some(now),
+                memo: None,
+                amount: Nat::from(*amount),
+            };
+            model
+                .transfer(&MINTER, &arg, now)
+                .expect("failed to mint the initial balances");
+        }
+        model
+    }
+
+    pub fn balance(&self, account: &Account) -> u64 {
+        self.balances.get(account).copied().unwrap_or_default()
+    }
+
+    pub fn total_supply(&self) -> u64 {
+        self.total_supply
+    }
+
+    /// Applies a transfer of `from` at `now` and returns the expected
+    /// result, in the order in which the ledger checks the arguments.
+    pub fn transfer(
+        &mut self,
+        from: &Account,
+        arg: &TransferArg,
+        now: u64,
+    ) -> Result<u64, TransferError> {
+        let amount = arg.amount.0.to_u64().expect("scenario amounts fit into u64");
+        let balance = self.balance(from);
+        let is_mint = from == &MINTER;
+        let is_burn = arg.to == MINTER;
+
+        let fee = if is_mint || is_burn { 0 } else { FEE };
+        if arg.fee.is_some() && arg.fee != Some(Nat::from(fee)) {
+            return Err(TransferError::BadFee {
+                expected_fee: Nat::from(fee),
+            });
+        }
+        if is_burn {
+            let min_burn_amount = FEE.min(balance);
+            if amount < min_burn_amount {
+                return Err(TransferError::BadBurn {
+                    min_burn_amount: Nat::from(min_burn_amount),
+                });
+            }
+            if amount == 0 {
+                return Err(TransferError::BadBurn {
+                    min_burn_amount: Nat::from(FEE),
+                });
+            }
+        }
+
+        let key = arg.created_at_time.map(|created_at_time| {
+            let to = arg.to.clone();
+            (from.clone(), to, amount, created_at_time, arg.memo.clone())
+        });
+        if let Some(key) = &key {
+            if key.3 + TX_WINDOW.as_nanos() as u64 < now {
+                return Err(TransferError::TooOld);
+            }
+            if let Some(block_index) = self.transactions.get(key) {
+                return Err(TransferError::Duplicate {
+                    duplicate_of: Nat::from(*block_index),
+                });
+            }
+        }
+
+        if !is_mint {
+            if balance < amount + fee {
+                return Err(TransferError::InsufficientFunds {
+                    balance: Nat::from(balance),
+                });
+            }
+            self.balances.insert(from.clone(), balance - amount - fee);
+        }
+        if is_mint {
+            self.total_supply += amount;
+        } else if is_burn {
+            self.total_supply -= amount;
+        } else {
+            self.total_supply -= fee;
+        }
+        if !is_burn {
+            *self.balances.entry(arg.to.clone()).or_default() += amount;
+        }
+
+        let block_index = self.num_blocks;
+        self.num_blocks += 1;
+        if let Some(key) = key {
+            self.transactions.insert(key, block_index);
+        }
+        Ok(block_index)
+    }
+}
+
+fn nanos_since_epoch(env: &StateMachine) -> u64 {
+    env.time()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+fn check_transfer(
+    env: &StateMachine,
+    ledger: CanisterId,
+    model: &mut LedgerModel,
+    from: &Account,
+    arg: &TransferArg,
+) -> TestCaseResult {
+    let expected = model.transfer(from, arg, nanos_since_epoch(env));
+    let actual = send_transfer(env, ledger, from, arg);
+    prop_assert_eq!(actual, expected, "unexpected result of {:?} from {}", arg, from);
+    Ok(())
+}
+
+fn check_balances(env: &StateMachine, ledger: CanisterId, model: &LedgerModel) -> TestCaseResult {
+    let mut sum = 0;
+    for index in 0..NUM_ACCOUNTS {
+        let account = account(index);
+        let balance = balance_of(env, ledger, account.clone());
+        prop_assert_eq!(balance, model.balance(&account), "balance of {}", account);
+        sum += balance;
+    }
+    prop_assert_eq!(sum, model.total_supply());
+    prop_assert_eq!(total_supply(env, ledger), model.total_supply());
+    Ok(())
+}
+
+/// Runs the scenario against a new ledger and checks the result of every
+/// transfer and the balances and the total supply after every step against
+/// the model.
+pub fn run_scenario<T>(
+    ledger_wasm: &[u8],
+    encode_init_args: fn(InitArgs) -> T,
+    scenario: Scenario,
+) -> TestCaseResult
+where
+    T: CandidType,
+{
+    let initial_balances: Vec<_> = scenario
+        .initial_balances
+        .iter()
+        .map(|(index, amount)| (account(*index), *amount))
+        .collect();
+    let (env, ledger) = setup(ledger_wasm.to_vec(), encode_init_args, initial_balances.clone());
+    let mut model = LedgerModel::new(&initial_balances, nanos_since_epoch(&env));
+    check_balances(&env, ledger, &model)?;
+
+    let mut sent: Vec<(Account, TransferArg)> = vec![];
+    for step in scenario.steps {
+        match step {
+            Step::Transfer {
+                from,
+                to,
+                amount,
+                fee,
+                memo,
+                dedup,
+            } => {
+                let from = account(from);
+                let arg = TransferArg {
+                    from_subaccount: from.subaccount,
+                    to: account(to),
+                    fee: fee.map(Nat::from),
+                    created_at_time: dedup.then(|| nanos_since_epoch(&env)),
+                    memo: memo.map(Memo::from),
+                    amount: Nat::from(amount),
+                };
+                check_transfer(&env, ledger, &mut model, &from, &arg)?;
+                sent.push((from, arg));
+            }
+            Step::Resend(index) => {
+                if sent.is_empty() {
+                    continue;
+                }
+                let (from, arg) = &sent[index.index(sent.len())];
+                check_transfer(&env, ledger, &mut model, from, arg)?;
+            }
+            Step::AdvanceTime(duration) => env.advance_time(duration),
+            Step::Upgrade => env
+                .upgrade_canister(ledger, ledger_wasm.to_vec(), vec![])
+                .expect("failed to upgrade the ledger"),
+        }
+        check_balances(&env, ledger, &model)?;
+    }
+    Ok(())
+}
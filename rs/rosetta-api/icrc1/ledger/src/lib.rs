@@ -5,29 +5,38 @@ use candid::{
     types::number::{Int, Nat},
     CandidType,
 };
+use ic_crypto_tree_hash::{Label, MixedHashTree};
 use ic_icrc1::endpoints::{
     ArchivedTransactionRange, GetTransactionsResponse, QueryArchiveFn, Transaction as Tx, Value,
 };
-use ic_icrc1::{Account, Block, LedgerBalances, Transaction};
+use ic_icrc1::icrc3::DataCertificate;
+use ic_icrc1::{
+    Account, Block, Memo, Operation, Transaction, BLOCK_SCHEMA_VERSION, MAX_MEMO_LENGTH,
+    MAX_MEMO_LENGTH_LIMIT,
+};
 use ic_ledger_canister_core::{
     archive::{ArchiveCanisterWasm, ArchiveOptions},
     blockchain::Blockchain,
-    ledger::{apply_transaction, block_locations, LedgerData, TransactionInfo},
+    ledger::{apply_transaction, block_locations, LedgerData, LedgerTransaction, TransactionInfo},
     range_utils,
 };
 use ic_ledger_core::{
     balances::Balances,
-    block::{BlockIndex, BlockType, HashOf},
+    block::{BlockIndex, BlockType, FeeCollector, HashOf},
     timestamp::TimeStamp,
-    tokens::Tokens,
+    tokens::TokensType,
 };
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 use std::borrow::Cow;
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::time::Duration;
 
 const TRANSACTION_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+/// The longest transaction window that upgrades can configure.
+const MAX_TRANSACTION_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// The largest permitted drift that upgrades can configure.
+const MAX_PERMITTED_DRIFT: Duration = Duration::from_secs(60 * 60);
 const MAX_ACCOUNTS: usize = 28_000_000;
 /// The maximum number of transactions the ledger should return for a single
 /// get_transactions request.
@@ -99,25 +108,113 @@ pub struct InitArgs {
     pub token_symbol: String,
     pub metadata: Vec<(String, Value)>,
     pub archive_options: ArchiveOptions,
+    /// The account that collects the transfer fees. The fees are burned if
+    /// it is not set.
+    pub fee_collector_account: Option<Account>,
+    /// The number of decimals of the token, 8 if not set. The amounts in the
+    /// arguments and blocks are numbers of the smallest units.
+    pub decimals: Option<u8>,
+}
+
+#[derive(Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+pub enum ChangeFeeCollector {
+    /// Burn the transfer fees from now on.
+    Unset,
+    /// Credit the transfer fees to the account from now on.
+    SetTo(Account),
+}
+
+/// The arguments that the ledger accepts on upgrade. Only the parameters
+/// that are set change, the others keep their current values.
+#[derive(Deserialize, CandidType, Clone, Debug, Default, PartialEq, Eq)]
+pub struct UpgradeArgs {
+    /// How long the ledger remembers transactions to detect duplicates, in
+    /// nanoseconds.
+    pub transaction_window: Option<u64>,
+    /// How far in the future the creation time of transactions can be, in
+    /// nanoseconds.
+    pub permitted_drift: Option<u64>,
+    /// The maximum length of memos in bytes. It cannot be smaller than the
+    /// 32 bytes that the ICRC-1 standard requires.
+    pub max_memo_length: Option<u16>,
+    pub change_fee_collector: Option<ChangeFeeCollector>,
+    /// The schema version of the blocks that the ledger creates from now on.
+    /// Upgrade the archives and indexes to a version that decodes the new
+    /// schema before setting it.
+    pub block_schema_version: Option<u32>,
+    /// Whether the ledger sets the creation time of transfers that don't set
+    /// it to the current time, which subjects all transfers to
+    /// deduplication.
+    pub auto_created_at_time: Option<bool>,
 }
 
+/// The fields that identify a transaction in `Ledger::find_transactions`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct TransactionKey {
+    from: Account,
+    created_at_time: u64,
+    memo: Option<Memo>,
+}
+
+/// The ledger state. `Tokens` is the type of the amounts, the default type
+/// holds up to `u64::MAX` units.
 #[derive(Serialize, Deserialize, Debug)]
-pub struct Ledger {
-    balances: LedgerBalances,
+pub struct Ledger<Tokens: TokensType = ic_ledger_core::Tokens> {
+    balances: Balances<Account, HashMap<Account, Tokens>>,
     blockchain: Blockchain<CdkRuntime, Icrc1ArchiveWasm>,
 
     minting_account: Account,
 
-    transactions_by_hash: BTreeMap<HashOf<Transaction>, BlockIndex>,
-    transactions_by_height: VecDeque<TransactionInfo<Transaction>>,
+    transactions_by_hash: BTreeMap<HashOf<Transaction<Tokens>>, BlockIndex>,
+    transactions_by_height: VecDeque<TransactionInfo<Transaction<Tokens>>>,
     transfer_fee: Tokens,
 
     token_symbol: String,
     token_name: String,
     metadata: Vec<(String, StoredValue)>,
+
+    #[serde(default = "default_transaction_window")]
+    transaction_window: Duration,
+    #[serde(default = "default_permitted_drift")]
+    permitted_drift: Duration,
+    #[serde(default = "default_max_memo_length")]
+    max_memo_length: u16,
+    #[serde(default)]
+    fee_collector: Option<FeeCollector<Account>>,
+    /// Ledgers that were created before the block schema was versioned keep
+    /// creating unversioned blocks until an upgrade sets the version.
+    #[serde(default)]
+    block_schema_version: u32,
+    #[serde(default = "default_decimals")]
+    decimals: u8,
+    /// The transactions in `transactions_by_height` by their sender,
+    /// creation time, and memo. Ledgers that were created before the index
+    /// only index the transactions since the upgrade that added it.
+    #[serde(default)]
+    transactions_by_key: BTreeSet<(TransactionKey, BlockIndex)>,
+    #[serde(default)]
+    transaction_keys_by_height: VecDeque<(BlockIndex, TransactionKey)>,
+    #[serde(default)]
+    auto_created_at_time: bool,
+}
+
+fn default_transaction_window() -> Duration {
+    TRANSACTION_WINDOW
 }
 
-impl Ledger {
+fn default_permitted_drift() -> Duration {
+    ic_constants::PERMITTED_DRIFT
+}
+
+fn default_max_memo_length() -> u16 {
+    MAX_MEMO_LENGTH as u16
+}
+
+fn default_decimals() -> u8 {
+    ic_ledger_core::tokens::DECIMAL_PLACES as u8
+}
+
+impl<Tokens: TokensType> Ledger<Tokens> {
     pub fn from_init_args(
         InitArgs {
             minting_account,
@@ -127,32 +224,46 @@ impl Ledger {
             token_symbol,
             metadata,
             archive_options,
+            fee_collector_account,
+            decimals,
         }: InitArgs,
         now: TimeStamp,
     ) -> Self {
+        if fee_collector_account.as_ref() == Some(&minting_account) {
+            panic!("the fee collector account cannot be the minting account");
+        }
         let mut ledger = Self {
-            balances: LedgerBalances::default(),
+            balances: Balances::default(),
             blockchain: Blockchain::new_with_archive(archive_options),
             transactions_by_hash: BTreeMap::new(),
             transactions_by_height: VecDeque::new(),
             minting_account,
-            transfer_fee: Tokens::from_e8s(transfer_fee),
+            transfer_fee: Tokens::from_u64(transfer_fee),
             token_symbol,
             token_name,
             metadata: metadata
                 .into_iter()
                 .map(|(k, v)| (k, StoredValue::from(v)))
                 .collect(),
+            transaction_window: default_transaction_window(),
+            permitted_drift: default_permitted_drift(),
+            max_memo_length: default_max_memo_length(),
+            fee_collector: fee_collector_account.map(FeeCollector::from),
+            block_schema_version: BLOCK_SCHEMA_VERSION,
+            decimals: decimals.unwrap_or_else(default_decimals),
+            transactions_by_key: BTreeSet::new(),
+            transaction_keys_by_height: VecDeque::new(),
+            auto_created_at_time: false,
         };
 
         for (account, balance) in initial_balances.into_iter() {
             apply_transaction(
                 &mut ledger,
-                Transaction::mint(account.clone(), Tokens::from_e8s(balance), Some(now), None),
+                Transaction::mint(account.clone(), Tokens::from_u64(balance), Some(now), None),
                 now,
             )
             .unwrap_or_else(|err| {
-                panic!("failed to mint {} e8s to {}: {:?}", balance, account, err)
+                panic!("failed to mint {} units to {}: {:?}", balance, account, err)
             });
         }
 
@@ -160,15 +271,21 @@ impl Ledger {
     }
 }
 
-impl LedgerData for Ledger {
+impl<Tokens: TokensType> LedgerData for Ledger<Tokens> {
     type AccountId = Account;
+    type Tokens = Tokens;
     type Runtime = CdkRuntime;
     type ArchiveWasm = Icrc1ArchiveWasm;
-    type Transaction = Transaction;
-    type Block = Block;
+    type Transaction = Transaction<Tokens>;
+    type Block = Block<Tokens>;
+    type BalancesStore = HashMap<Self::AccountId, Tokens>;
 
     fn transaction_window(&self) -> Duration {
-        TRANSACTION_WINDOW
+        self.transaction_window
+    }
+
+    fn permitted_drift(&self) -> Duration {
+        self.permitted_drift
     }
 
     fn max_transactions_in_window(&self) -> usize {
@@ -195,11 +312,23 @@ impl LedgerData for Ledger {
         &self.token_symbol
     }
 
-    fn balances(&self) -> &Balances<Self::AccountId, HashMap<Self::AccountId, Tokens>> {
+    fn fee_collector(&self) -> Option<&FeeCollector<Self::AccountId>> {
+        self.fee_collector.as_ref()
+    }
+
+    fn fee_collector_mut(&mut self) -> Option<&mut FeeCollector<Self::AccountId>> {
+        self.fee_collector.as_mut()
+    }
+
+    fn block_schema_version(&self) -> Option<u32> {
+        Some(self.block_schema_version)
+    }
+
+    fn balances(&self) -> &Balances<Self::AccountId, Self::BalancesStore> {
         &self.balances
     }
 
-    fn balances_mut(&mut self) -> &mut Balances<Self::AccountId, HashMap<Self::AccountId, Tokens>> {
+    fn balances_mut(&mut self) -> &mut Balances<Self::AccountId, Self::BalancesStore> {
         &mut self.balances
     }
 
@@ -227,10 +356,25 @@ impl LedgerData for Ledger {
         &mut self.transactions_by_height
     }
 
-    fn on_purged_transaction(&mut self, _height: BlockIndex) {}
+    fn on_recorded_transaction(&mut self, transaction: &Self::Transaction, height: BlockIndex) {
+        if let Some(key) = self.transaction_key(transaction) {
+            self.transactions_by_key.insert((key.clone(), height));
+            self.transaction_keys_by_height.push_back((height, key));
+        }
+    }
+
+    fn on_purged_transaction(&mut self, height: BlockIndex) {
+        while let Some((key_height, _)) = self.transaction_keys_by_height.front() {
+            if *key_height > height {
+                break;
+            }
+            let (key_height, key) = self.transaction_keys_by_height.pop_front().unwrap();
+            self.transactions_by_key.remove(&(key, key_height));
+        }
+    }
 }
 
-impl Ledger {
+impl<Tokens: TokensType> Ledger<Tokens> {
     pub fn minting_account(&self) -> &Account {
         &self.minting_account
     }
@@ -239,6 +383,172 @@ impl Ledger {
         self.transfer_fee
     }
 
+    /// Returns the account that collects the transfer fees, if any.
+    pub fn fee_collector_account(&self) -> Option<&Account> {
+        self.fee_collector.as_ref().map(|fc| &fc.fee_collector)
+    }
+
+    /// Returns the number of decimals of the token.
+    pub fn decimals(&self) -> u8 {
+        self.decimals
+    }
+
+    /// Returns the maximum length of memos in bytes that the ledger accepts.
+    pub fn max_memo_length(&self) -> u16 {
+        self.max_memo_length
+    }
+
+    /// Returns whether transfers without a creation time are created at the
+    /// time the ledger receives them.
+    pub fn auto_created_at_time(&self) -> bool {
+        self.auto_created_at_time
+    }
+
+    fn transaction_key(&self, transaction: &Transaction<Tokens>) -> Option<TransactionKey> {
+        let from = match &transaction.operation {
+            Operation::Transfer { from, .. } | Operation::Burn { from, .. } => from,
+            Operation::Mint { .. } => &self.minting_account,
+            Operation::Upgrade { .. } => return None,
+        };
+        Some(TransactionKey {
+            from: from.clone(),
+            created_at_time: transaction.created_at_time?,
+            memo: transaction.memo.clone(),
+        })
+    }
+
+    /// Returns the indices of the blocks of the transactions that `from`
+    /// created at `created_at_time` with `memo`, oldest first.
+    ///
+    /// The ledger only finds the transactions in the deduplication window,
+    /// i.e., the transactions that it would reject as duplicates.
+    pub fn find_transactions(
+        &self,
+        from: &Account,
+        created_at_time: u64,
+        memo: Option<&Memo>,
+    ) -> Vec<BlockIndex> {
+        let key = TransactionKey {
+            from: from.clone(),
+            created_at_time,
+            memo: memo.cloned(),
+        };
+        self.transactions_by_key
+            .range((key.clone(), 0)..=(key, BlockIndex::MAX))
+            .map(|(_, height)| *height)
+            .collect()
+    }
+
+    /// Returns the index and the timestamp of the block of the transaction
+    /// that the ledger would reject `transaction` as a duplicate of, if any.
+    pub fn find_duplicate(
+        &self,
+        transaction: &Transaction<Tokens>,
+    ) -> Option<(BlockIndex, TimeStamp)> {
+        let height = *self.transactions_by_hash.get(&transaction.hash())?;
+        // The ledger records the transactions in the order of their blocks,
+        // so the heights of `transactions_by_height` are increasing.
+        let pos = self
+            .transactions_by_height
+            .binary_search_by_key(&height, |info| {
+                self.transactions_by_hash[&info.transaction_hash]
+            })
+            .ok()?;
+        Some((height, self.transactions_by_height[pos].block_timestamp))
+    }
+
+    /// Applies the configuration changes from the upgrade arguments and
+    /// records them in a block. Returns an error and leaves the ledger
+    /// unchanged if any of the arguments is invalid.
+    pub fn upgrade(&mut self, args: UpgradeArgs, now: TimeStamp) -> Result<(), String> {
+        let transaction_window = args.transaction_window.map(Duration::from_nanos);
+        if let Some(window) = transaction_window {
+            if window.is_zero() || window > MAX_TRANSACTION_WINDOW {
+                return Err(format!(
+                    "transaction window must be between 1 and {} nanoseconds, got {}",
+                    MAX_TRANSACTION_WINDOW.as_nanos(),
+                    window.as_nanos()
+                ));
+            }
+        }
+        let permitted_drift = args.permitted_drift.map(Duration::from_nanos);
+        if let Some(drift) = permitted_drift {
+            if drift > MAX_PERMITTED_DRIFT {
+                return Err(format!(
+                    "permitted drift must be at most {} nanoseconds, got {}",
+                    MAX_PERMITTED_DRIFT.as_nanos(),
+                    drift.as_nanos()
+                ));
+            }
+        }
+        if let Some(max_memo_length) = args.max_memo_length {
+            let len = max_memo_length as usize;
+            if !(MAX_MEMO_LENGTH..=MAX_MEMO_LENGTH_LIMIT).contains(&len) {
+                return Err(format!(
+                    "max memo length must be between {} and {} bytes, got {}",
+                    MAX_MEMO_LENGTH, MAX_MEMO_LENGTH_LIMIT, len
+                ));
+            }
+        }
+        if let Some(version) = args.block_schema_version {
+            if version > BLOCK_SCHEMA_VERSION {
+                return Err(format!(
+                    "block schema version must be at most {}, got {}",
+                    BLOCK_SCHEMA_VERSION, version
+                ));
+            }
+        }
+        if let Some(ChangeFeeCollector::SetTo(fee_collector)) = &args.change_fee_collector {
+            if fee_collector == &self.minting_account {
+                return Err("the fee collector account cannot be the minting account".to_string());
+            }
+        }
+
+        // The fee collector changes are not recorded in the upgrade block:
+        // the next block that credits a fee mentions the new fee collector.
+        // The schema version and creation time changes are visible in the
+        // blocks themselves.
+        if args.transaction_window.is_some()
+            || args.permitted_drift.is_some()
+            || args.max_memo_length.is_some()
+        {
+            apply_transaction(
+                self,
+                Transaction::upgrade(
+                    args.transaction_window,
+                    args.permitted_drift,
+                    args.max_memo_length,
+                ),
+                now,
+            )
+            .map_err(|e| format!("failed to record the upgrade: {:?}", e))?;
+        }
+
+        if let Some(window) = transaction_window {
+            self.transaction_window = window;
+        }
+        if let Some(drift) = permitted_drift {
+            self.permitted_drift = drift;
+        }
+        if let Some(max_memo_length) = args.max_memo_length {
+            self.max_memo_length = max_memo_length;
+        }
+        if let Some(version) = args.block_schema_version {
+            self.block_schema_version = version;
+        }
+        if let Some(auto_created_at_time) = args.auto_created_at_time {
+            self.auto_created_at_time = auto_created_at_time;
+        }
+        match args.change_fee_collector {
+            Some(ChangeFeeCollector::Unset) => self.fee_collector = None,
+            Some(ChangeFeeCollector::SetTo(fee_collector)) => {
+                self.fee_collector = Some(FeeCollector::from(fee_collector))
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
     pub fn metadata(&self) -> Vec<(String, Value)> {
         let mut records: Vec<(String, Value)> = self
             .metadata
@@ -246,11 +556,11 @@ impl Ledger {
             .into_iter()
             .map(|(k, v)| (k, StoredValue::into(v)))
             .collect();
-        let decimals = ic_ledger_core::tokens::DECIMAL_PLACES as u64;
-        records.push(Value::entry("icrc1:decimals", decimals));
+        records.push(Value::entry("icrc1:decimals", self.decimals as u64));
         records.push(Value::entry("icrc1:name", self.token_name()));
         records.push(Value::entry("icrc1:symbol", self.token_symbol()));
-        records.push(Value::entry("icrc1:fee", self.transfer_fee().get_e8s()));
+        let fee = Value::Nat(self.transfer_fee().to_nat());
+        records.push(Value::entry("icrc1:fee", fee));
         records
     }
 
@@ -258,15 +568,44 @@ impl Ledger {
     /// The canister code must call set_certified_data with the value this function returns after
     /// each successful modification of the ledger.
     pub fn root_hash(&self) -> [u8; 32] {
-        use ic_crypto_tree_hash::{Label, MixedHashTree as T};
-        let tree = match self.blockchain().last_hash {
-            Some(hash) => T::Labeled(
-                Label::from("tip_hash"),
-                Box::new(T::Leaf(hash.as_slice().to_vec())),
-            ),
-            None => T::Empty,
+        self.construct_hash_tree().digest().0
+    }
+
+    /// Returns the certificate of the tip of the block log, given the data
+    /// certificate of the canister.
+    pub fn tip_certificate(&self, certificate: Vec<u8>) -> DataCertificate {
+        let mut hash_tree = vec![];
+        ciborium::ser::into_writer(&self.construct_hash_tree(), &mut hash_tree)
+            .expect("bug: failed to encode the hash tree");
+        DataCertificate {
+            certificate: ByteBuf::from(certificate),
+            hash_tree: ByteBuf::from(hash_tree),
+        }
+    }
+
+    fn construct_hash_tree(&self) -> MixedHashTree {
+        let hash = match self.blockchain().last_hash {
+            Some(hash) => hash,
+            None => return MixedHashTree::Empty,
+        };
+        let leaf = |label: &str, bytes: Vec<u8>| {
+            MixedHashTree::Labeled(Label::from(label), Box::new(MixedHashTree::Leaf(bytes)))
         };
-        tree.digest().0
+        let last_block_index = self.blockchain().chain_length() - 1;
+        let mut last_block_index_leb128 = vec![];
+        Nat::from(last_block_index)
+            .encode(&mut last_block_index_leb128)
+            .expect("bug: failed to encode nat");
+
+        // The labels are sorted, as the certificate format requires. The
+        // `tip_hash` label predates ICRC-3 and is kept for existing clients.
+        MixedHashTree::Fork(Box::new((
+            MixedHashTree::Fork(Box::new((
+                leaf("last_block_hash", hash.as_slice().to_vec()),
+                leaf("last_block_index", last_block_index_leb128),
+            ))),
+            leaf("tip_hash", hash.as_slice().to_vec()),
+        )))
     }
 
     /// Returns transactions in the specified range.
@@ -280,7 +619,7 @@ impl Ledger {
             .block_slice(local_blocks.clone())
             .iter()
             .map(|enc_block| -> Tx {
-                Block::decode(enc_block.clone())
+                Block::<Tokens>::decode(enc_block.clone())
                     .expect("bug: failed to decode encoded block")
                     .into()
             })
@@ -5,21 +5,30 @@ use ic_cdk::api::stable::{StableReader, StableWriter};
 use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
 use ic_icrc1::{
     endpoints::{
-        ArchiveInfo, GetTransactionsRequest, GetTransactionsResponse, StandardRecord, TransferArg,
-        TransferError, Value,
+        ArchiveInfo, DuplicateOf, FindDuplicateArgs, FindTransactionArgs, GetTransactionsRequest,
+        GetTransactionsResponse, StandardRecord, TransferArg, TransferError, Value,
     },
-    Account, Operation, Transaction,
+    icrc3, Account, Operation, Transaction,
 };
-use ic_icrc1_ledger::{InitArgs, Ledger};
+use ic_icrc1_ledger::{InitArgs, UpgradeArgs};
 use ic_ledger_canister_core::ledger::{
     apply_transaction, archive_blocks, LedgerAccess, LedgerData,
 };
-use ic_ledger_core::{timestamp::TimeStamp, tokens::Tokens};
+use ic_ledger_core::{timestamp::TimeStamp, tokens::TokensType};
 use num_traits::ToPrimitive;
 use std::cell::RefCell;
 
 const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
 
+/// The type of the amounts. Ledgers of tokens with many decimals are built
+/// with the `u256-tokens` feature.
+#[cfg(not(feature = "u256-tokens"))]
+type Tokens = ic_ledger_core::tokens::Tokens;
+#[cfg(feature = "u256-tokens")]
+type Tokens = ic_ledger_core::tokens_u256::U256;
+
+type Ledger = ic_icrc1_ledger::Ledger<Tokens>;
+
 thread_local! {
     static LEDGER: RefCell<Option<Ledger>> = RefCell::new(None);
 }
@@ -66,7 +75,24 @@ fn post_upgrade() {
             ciborium::de::from_reader(StableReader::default())
                 .expect("failed to decode ledger state"),
         );
-    })
+    });
+    // The new code may certify the tip differently from the old one, e.g.,
+    // with additional labels, so the tip is certified on every upgrade.
+    ic_cdk::api::set_certified_data(&Access::with_ledger(Ledger::root_hash));
+
+    // Upgrades without arguments keep the ledger configuration.
+    let arg_bytes = ic_cdk::api::call::arg_data_raw();
+    if arg_bytes.is_empty() {
+        return;
+    }
+    let args: Option<UpgradeArgs> = candid::decode_one(&arg_bytes).unwrap_or_else(|e| {
+        ic_cdk::api::trap(&format!("failed to decode upgrade arguments: {}", e))
+    });
+    if let Some(args) = args {
+        let now = TimeStamp::from_nanos_since_unix_epoch(ic_cdk::api::time());
+        Access::with_ledger_mut(|ledger| ledger.upgrade(args, now))
+            .unwrap_or_else(|e| ic_cdk::api::trap(&format!("invalid upgrade arguments: {}", e)));
+    }
 }
 
 fn encode_metrics(w: &mut ic_metrics_encoder::MetricsEncoder<Vec<u8>>) -> std::io::Result<()> {
@@ -103,7 +129,7 @@ fn encode_metrics(w: &mut ic_metrics_encoder::MetricsEncoder<Vec<u8>>) -> std::i
         )?;
         w.encode_gauge(
             "ledger_balances_token_pool",
-            ledger.balances().token_pool.get_tokens() as f64,
+            whole_tokens(ledger, ledger.balances().token_pool),
             "Total number of Tokens in the pool.",
         )?;
         w.encode_gauge(
@@ -124,6 +150,12 @@ fn encode_metrics(w: &mut ic_metrics_encoder::MetricsEncoder<Vec<u8>>) -> std::i
     })
 }
 
+/// Returns the amount in whole tokens, rounded.
+fn whole_tokens(ledger: &Ledger, amount: Tokens) -> f64 {
+    let units = amount.to_nat().0.to_f64().unwrap_or(f64::INFINITY);
+    units / 10f64.powi(ledger.decimals() as i32)
+}
+
 #[export_name = "canister_query http_request"]
 fn http_request() {
     dfn_http_metrics::serve_metrics(encode_metrics);
@@ -144,14 +176,13 @@ fn icrc1_symbol() -> String {
 #[query]
 #[candid_method(query)]
 fn icrc1_decimals() -> u8 {
-    debug_assert!(ic_ledger_core::tokens::DECIMAL_PLACES <= u8::MAX as u32);
-    ic_ledger_core::tokens::DECIMAL_PLACES as u8
+    Access::with_ledger(|ledger| ledger.decimals())
 }
 
 #[query]
 #[candid_method(query)]
 fn icrc1_fee() -> Nat {
-    Nat::from(Access::with_ledger(|ledger| ledger.transfer_fee()).get_e8s())
+    Access::with_ledger(|ledger| ledger.transfer_fee().to_nat())
 }
 
 #[query]
@@ -169,13 +200,13 @@ fn icrc1_minting_account() -> Option<Account> {
 #[query(name = "icrc1_balance_of")]
 #[candid_method(query, rename = "icrc1_balance_of")]
 fn icrc1_balance_of(account: Account) -> Nat {
-    Access::with_ledger(|ledger| Nat::from(ledger.balances().account_balance(&account).get_e8s()))
+    Access::with_ledger(|ledger| ledger.balances().account_balance(&account).to_nat())
 }
 
 #[query(name = "icrc1_total_supply")]
 #[candid_method(query, rename = "icrc1_total_supply")]
 fn icrc1_total_supply() -> Nat {
-    Access::with_ledger(|ledger| Nat::from(ledger.balances().total_supply().get_e8s()))
+    Access::with_ledger(|ledger| ledger.balances().total_supply().to_nat())
 }
 
 #[update]
@@ -183,74 +214,28 @@ fn icrc1_total_supply() -> Nat {
 async fn icrc1_transfer(arg: TransferArg) -> Result<Nat, TransferError> {
     let block_idx = Access::with_ledger_mut(|ledger| {
         let now = TimeStamp::from_nanos_since_unix_epoch(ic_cdk::api::time());
-        let created_at_time = arg
-            .created_at_time
-            .map(TimeStamp::from_nanos_since_unix_epoch);
+        let created_at_time = match arg.created_at_time {
+            Some(t) => Some(TimeStamp::from_nanos_since_unix_epoch(t)),
+            None => ledger.auto_created_at_time().then_some(now),
+        };
 
         let from_account = Account {
             owner: PrincipalId::from(ic_cdk::api::caller()),
             subaccount: arg.from_subaccount,
         };
 
-        let amount = match arg.amount.0.to_u64() {
-            Some(n) => Tokens::from_e8s(n),
-            None => {
-                // No one can have so many tokens
-                let balance = Nat::from(ledger.balances().account_balance(&from_account).get_e8s());
-                assert!(balance < arg.amount);
-                return Err(TransferError::InsufficientFunds { balance });
-            }
-        };
-
-        let tx = if &arg.to == ledger.minting_account() {
-            let expected_fee = Nat::from(0u64);
-            if arg.fee.is_some() && arg.fee.as_ref() != Some(&expected_fee) {
-                return Err(TransferError::BadFee { expected_fee });
-            }
-
-            let balance = ledger.balances().account_balance(&from_account);
-            let min_burn_amount = ledger.transfer_fee().min(balance);
-            if amount < min_burn_amount {
-                return Err(TransferError::BadBurn {
-                    min_burn_amount: Nat::from(min_burn_amount.get_e8s()),
-                });
-            }
-            if amount == Tokens::ZERO {
-                return Err(TransferError::BadBurn {
-                    min_burn_amount: Nat::from(ledger.transfer_fee().get_e8s()),
-                });
-            }
-
-            Transaction {
-                operation: Operation::Burn {
-                    from: from_account,
-                    amount: amount.get_e8s(),
-                },
-                created_at_time: created_at_time.map(|t| t.as_nanos_since_unix_epoch()),
-                memo: arg.memo,
-            }
-        } else if &from_account == ledger.minting_account() {
-            let expected_fee = Nat::from(0u64);
-            if arg.fee.is_some() && arg.fee.as_ref() != Some(&expected_fee) {
-                return Err(TransferError::BadFee { expected_fee });
+        if let Some(memo) = arg.memo.as_ref() {
+            let max_memo_length = ledger.max_memo_length() as usize;
+            if memo.as_ref().len() > max_memo_length {
+                ic_cdk::api::trap(&format!(
+                    "the memo field is {} bytes long, max allowed length is {}",
+                    memo.as_ref().len(),
+                    max_memo_length
+                ));
             }
-            Transaction::mint(arg.to, amount, created_at_time, arg.memo)
-        } else {
-            let expected_fee_tokens = ledger.transfer_fee();
-            let expected_fee = Nat::from(expected_fee_tokens.get_e8s());
-            if arg.fee.is_some() && arg.fee.as_ref() != Some(&expected_fee) {
-                return Err(TransferError::BadFee { expected_fee });
-            }
-            Transaction::transfer(
-                from_account,
-                arg.to,
-                amount,
-                expected_fee_tokens,
-                created_at_time,
-                arg.memo,
-            )
-        };
+        }
 
+        let tx = make_transaction(ledger, from_account, arg, created_at_time)?;
         let (block_idx, _) = apply_transaction(ledger, tx, now)?;
         Ok(block_idx)
     })?;
@@ -263,6 +248,75 @@ async fn icrc1_transfer(arg: TransferArg) -> Result<Nat, TransferError> {
     Ok(Nat::from(block_idx))
 }
 
+/// Builds the transaction of a transfer of `from_account`, checking the
+/// amount and the fee.
+fn make_transaction(
+    ledger: &Ledger,
+    from_account: Account,
+    arg: TransferArg,
+    created_at_time: Option<TimeStamp>,
+) -> Result<Transaction<Tokens>, TransferError> {
+    let amount = match Tokens::try_from_nat(&arg.amount) {
+        Ok(amount) => amount,
+        Err(_) => {
+            // No one can have so many tokens
+            let balance = ledger.balances().account_balance(&from_account).to_nat();
+            assert!(balance < arg.amount);
+            return Err(TransferError::InsufficientFunds { balance });
+        }
+    };
+
+    let tx = if &arg.to == ledger.minting_account() {
+        let expected_fee = Nat::from(0u64);
+        if arg.fee.is_some() && arg.fee.as_ref() != Some(&expected_fee) {
+            return Err(TransferError::BadFee { expected_fee });
+        }
+
+        let balance = ledger.balances().account_balance(&from_account);
+        let min_burn_amount = ledger.transfer_fee().min(balance);
+        if amount < min_burn_amount {
+            return Err(TransferError::BadBurn {
+                min_burn_amount: min_burn_amount.to_nat(),
+            });
+        }
+        if amount == Tokens::ZERO {
+            return Err(TransferError::BadBurn {
+                min_burn_amount: ledger.transfer_fee().to_nat(),
+            });
+        }
+
+        Transaction {
+            operation: Operation::Burn {
+                from: from_account,
+                amount,
+            },
+            created_at_time: created_at_time.map(|t| t.as_nanos_since_unix_epoch()),
+            memo: arg.memo,
+        }
+    } else if &from_account == ledger.minting_account() {
+        let expected_fee = Nat::from(0u64);
+        if arg.fee.is_some() && arg.fee.as_ref() != Some(&expected_fee) {
+            return Err(TransferError::BadFee { expected_fee });
+        }
+        Transaction::mint(arg.to, amount, created_at_time, arg.memo)
+    } else {
+        let expected_fee_tokens = ledger.transfer_fee();
+        let expected_fee = expected_fee_tokens.to_nat();
+        if arg.fee.is_some() && arg.fee.as_ref() != Some(&expected_fee) {
+            return Err(TransferError::BadFee { expected_fee });
+        }
+        Transaction::transfer(
+            from_account,
+            arg.to,
+            amount,
+            expected_fee_tokens,
+            created_at_time,
+            arg.memo,
+        )
+    };
+    Ok(tx)
+}
+
 #[query]
 fn archives() -> Vec<ArchiveInfo> {
     Access::with_ledger(|ledger| {
@@ -305,6 +359,81 @@ fn get_transactions(req: GetTransactionsRequest) -> GetTransactionsResponse {
     Access::with_ledger(|ledger| ledger.get_transactions(start, length))
 }
 
+/// Returns the indices of the blocks of the transactions with the given
+/// deduplication fields, so that clients that lost the response of a
+/// transfer can find its block.
+#[query]
+#[candid_method(query)]
+fn find_transaction_by(args: FindTransactionArgs) -> Vec<Nat> {
+    Access::with_ledger(|ledger| {
+        ledger
+            .find_transactions(&args.from, args.created_at_time, args.memo.as_ref())
+            .into_iter()
+            .map(Nat::from)
+            .collect()
+    })
+}
+
+/// Returns the block index and the timestamp of the transaction that the
+/// ledger rejected `args.transfer` as a duplicate of, to help clients find
+/// out why their retries fail. Returns None if the ledger would not reject
+/// the transfer as a duplicate, e.g., because it has no creation time or
+/// because the original transaction left the deduplication window.
+#[query]
+#[candid_method(query)]
+fn find_duplicate_transfer(args: FindDuplicateArgs) -> Option<DuplicateOf> {
+    let transfer = args.transfer;
+    let created_at_time = transfer.created_at_time?;
+    let from_account = Account {
+        owner: args.caller,
+        subaccount: transfer.from_subaccount,
+    };
+    let amount = Tokens::try_from_nat(&transfer.amount).ok()?;
+    Access::with_ledger(|ledger| {
+        // The transfer does not need to pass the checks of `icrc1_transfer`
+        // again: the original transaction passed them when the ledger
+        // recorded it, and the balances may have changed since.
+        let created_at = Some(TimeStamp::from_nanos_since_unix_epoch(created_at_time));
+        let tx = if &transfer.to == ledger.minting_account() {
+            Transaction {
+                operation: Operation::Burn {
+                    from: from_account,
+                    amount,
+                },
+                created_at_time: Some(created_at_time),
+                memo: transfer.memo,
+            }
+        } else if &from_account == ledger.minting_account() {
+            Transaction::mint(transfer.to, amount, created_at, transfer.memo)
+        } else {
+            let fee = match &transfer.fee {
+                Some(fee) => Tokens::try_from_nat(fee).ok()?,
+                None => ledger.transfer_fee(),
+            };
+            Transaction::transfer(
+                from_account,
+                transfer.to,
+                amount,
+                fee,
+                created_at,
+                transfer.memo,
+            )
+        };
+        let (block_index, timestamp) = ledger.find_duplicate(&tx)?;
+        Some(DuplicateOf {
+            block_index: Nat::from(block_index),
+            timestamp: timestamp.as_nanos_since_unix_epoch(),
+        })
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn icrc3_get_tip_certificate() -> Option<icrc3::DataCertificate> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    Some(Access::with_ledger(|ledger| ledger.tip_certificate(certificate)))
+}
+
 candid::export_service!();
 
 #[query]
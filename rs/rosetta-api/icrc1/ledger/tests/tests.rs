@@ -1,14 +1,18 @@
 use candid::types::number::Nat;
 use candid::{CandidType, Decode, Encode};
 use ic_base_types::PrincipalId;
+use ic_crypto_tree_hash::{LookupStatus, MixedHashTree};
+use ic_icrc1::icrc3::DataCertificate;
 use ic_icrc1::{
     endpoints::{
-        ArchiveInfo, GetTransactionsRequest, GetTransactionsResponse, StandardRecord,
-        Transaction as Tx, TransactionRange, Transfer, TransferArg, TransferError, Value,
+        ArchiveInfo, DuplicateOf, FindDuplicateArgs, FindTransactionArgs, GetTransactionsRequest,
+        GetTransactionsResponse, StandardRecord, Transaction as Tx, TransactionRange, Transfer,
+        TransferArg, TransferError, Value,
     },
-    Account, Block, Memo, Operation, Transaction,
+    Account, Block, Memo, Operation, Transaction, BLOCK_SCHEMA_VERSION,
+    MAX_SUPPORTED_BLOCK_SCHEMA_VERSION,
 };
-use ic_icrc1_ledger::InitArgs;
+use ic_icrc1_ledger::{ChangeFeeCollector, InitArgs, UpgradeArgs};
 use ic_icrc1_ledger_sm_tests::{
     balance_of, metadata, setup, supported_standards, total_supply, ARCHIVE_TRIGGER_THRESHOLD,
     BLOB_META_KEY, BLOB_META_VALUE, FEE, INT_META_KEY, INT_META_VALUE, MINTER, NAT_META_KEY,
@@ -16,7 +20,10 @@ use ic_icrc1_ledger_sm_tests::{
     TOKEN_SYMBOL, TX_WINDOW,
 };
 use ic_ledger_canister_core::archive::ArchiveOptions;
-use ic_ledger_core::block::{BlockIndex, BlockType, HashOf};
+use ic_ledger_canister_core::ledger::{apply_transaction, LedgerData};
+use ic_ledger_core::block::{BlockIndex, BlockType, EncodedBlock, HashOf};
+use ic_ledger_core::timestamp::TimeStamp;
+use ic_ledger_core::Tokens;
 use ic_state_machine_tests::{CanisterId, ErrorCode, StateMachine};
 use num_traits::ToPrimitive;
 use proptest::prelude::*;
@@ -34,6 +41,14 @@ fn ledger_wasm() -> Vec<u8> {
     )
 }
 
+fn ledger_wasm_u256() -> Vec<u8> {
+    ic_test_utilities_load_wasm::load_wasm(
+        std::env::var("CARGO_MANIFEST_DIR").unwrap(),
+        "ic-icrc1-ledger",
+        &["u256-tokens"],
+    )
+}
+
 fn archive_wasm() -> Vec<u8> {
     ic_test_utilities_load_wasm::load_wasm(
         PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
@@ -45,7 +60,30 @@ fn archive_wasm() -> Vec<u8> {
     )
 }
 
+fn default_archive_options() -> ArchiveOptions {
+    ArchiveOptions {
+        trigger_threshold: ARCHIVE_TRIGGER_THRESHOLD as usize,
+        num_blocks_to_archive: NUM_BLOCKS_TO_ARCHIVE as usize,
+        node_max_memory_size_bytes: None,
+        max_message_size_bytes: None,
+        controller_id: PrincipalId::new_user_test_id(100),
+        cycles_for_archive_creation: None,
+        max_transactions_per_response: None,
+        max_archive_nodes: None,
+        cycles_for_archive_top_up: None,
+        cycles_for_archive_top_ups_pool: None,
+    }
+}
+
 fn install_ledger(env: &StateMachine, initial_balances: Vec<(Account, u64)>) -> CanisterId {
+    install_ledger_with_archive_options(env, initial_balances, default_archive_options())
+}
+
+fn install_ledger_with_archive_options(
+    env: &StateMachine,
+    initial_balances: Vec<(Account, u64)>,
+    archive_options: ArchiveOptions,
+) -> CanisterId {
     let args = InitArgs {
         minting_account: MINTER.clone(),
         initial_balances,
@@ -58,15 +96,9 @@ fn install_ledger(env: &StateMachine, initial_balances: Vec<(Account, u64)>) ->
             Value::entry(TEXT_META_KEY, TEXT_META_VALUE),
             Value::entry(BLOB_META_KEY, BLOB_META_VALUE),
         ],
-        archive_options: ArchiveOptions {
-            trigger_threshold: ARCHIVE_TRIGGER_THRESHOLD as usize,
-            num_blocks_to_archive: NUM_BLOCKS_TO_ARCHIVE as usize,
-            node_max_memory_size_bytes: None,
-            max_message_size_bytes: None,
-            controller_id: PrincipalId::new_user_test_id(100),
-            cycles_for_archive_creation: None,
-            max_transactions_per_response: None,
-        },
+        archive_options,
+        fee_collector_account: None,
+        decimals: None,
     };
     env.install_canister(ledger_wasm(), Encode!(&args).unwrap(), None)
         .unwrap()
@@ -117,6 +149,42 @@ fn transfer(
     )
 }
 
+fn find_transaction_by(
+    env: &StateMachine,
+    ledger: CanisterId,
+    args: &FindTransactionArgs,
+) -> Vec<BlockIndex> {
+    Decode!(
+        &env.query(ledger, "find_transaction_by", Encode!(args).unwrap())
+            .expect("failed to find transactions")
+            .bytes(),
+        Vec<Nat>
+    )
+    .expect("failed to decode find_transaction_by response")
+    .into_iter()
+    .map(|n| n.0.to_u64().unwrap())
+    .collect()
+}
+
+fn find_duplicate_transfer(
+    env: &StateMachine,
+    ledger: CanisterId,
+    caller: PrincipalId,
+    transfer: &TransferArg,
+) -> Option<DuplicateOf> {
+    let args = FindDuplicateArgs {
+        caller,
+        transfer: transfer.clone(),
+    };
+    Decode!(
+        &env.query(ledger, "find_duplicate_transfer", Encode!(&args).unwrap())
+            .expect("failed to find the duplicate transfer")
+            .bytes(),
+        Option<DuplicateOf>
+    )
+    .expect("failed to decode find_duplicate_transfer response")
+}
+
 fn list_archives(env: &StateMachine, ledger: CanisterId) -> Vec<ArchiveInfo> {
     Decode!(
         &env.query(ledger, "archives", Encode!().unwrap())
@@ -200,6 +268,8 @@ fn encode_init_args(args: ic_icrc1_ledger_sm_tests::InitArgs) -> InitArgs {
         token_symbol: args.token_symbol,
         metadata: args.metadata,
         archive_options: args.archive_options,
+        fee_collector_account: None,
+        decimals: None,
     }
 }
 
@@ -383,6 +453,180 @@ fn test_tx_deduplication() {
     );
 }
 
+#[test]
+fn test_find_transaction_by() {
+    let env = StateMachine::new();
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let canister_id = install_ledger(&env, vec![(Account::from(p1), 10_000_000)]);
+
+    let now = system_time_to_nanos(env.time());
+    let memo = Memo::from(42);
+    let transfer_arg = |amount: u64| TransferArg {
+        from_subaccount: None,
+        to: p2.into(),
+        fee: None,
+        amount: Nat::from(amount),
+        created_at_time: Some(now),
+        memo: Some(memo.clone()),
+    };
+    let first =
+        send_transfer(&env, canister_id, p1, &transfer_arg(1_000)).expect("transfer failed");
+    // The amount is not a part of the lookup key.
+    let second =
+        send_transfer(&env, canister_id, p1, &transfer_arg(2_000)).expect("transfer failed");
+    // Transfers without a creation time are not indexed.
+    transfer(&env, canister_id, p1, p2, 3_000).expect("transfer failed");
+
+    let args = FindTransactionArgs {
+        from: p1.into(),
+        created_at_time: now,
+        memo: Some(memo.clone()),
+    };
+    assert_eq!(find_transaction_by(&env, canister_id, &args), vec![first, second]);
+
+    for args in [
+        FindTransactionArgs {
+            memo: None,
+            ..args.clone()
+        },
+        FindTransactionArgs {
+            created_at_time: now + 1,
+            ..args.clone()
+        },
+        FindTransactionArgs {
+            from: p2.into(),
+            ..args.clone()
+        },
+    ] {
+        assert_eq!(find_transaction_by(&env, canister_id, &args), vec![]);
+    }
+
+    // The ledger forgets the transactions that leave the deduplication window.
+    env.advance_time(TX_WINDOW + Duration::from_secs(5 * 60));
+    transfer(&env, canister_id, p1, p2, 4_000).expect("transfer failed");
+    assert_eq!(find_transaction_by(&env, canister_id, &args), vec![]);
+}
+
+#[test]
+fn test_find_duplicate_transfer() {
+    let env = StateMachine::new();
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let canister_id = install_ledger(&env, vec![(Account::from(p1), 10_000_000)]);
+
+    let now = system_time_to_nanos(env.time());
+    let transfer_arg = TransferArg {
+        from_subaccount: None,
+        to: p2.into(),
+        fee: None,
+        amount: Nat::from(1_000),
+        created_at_time: Some(now),
+        memo: Some(Memo::from(7)),
+    };
+    assert_eq!(find_duplicate_transfer(&env, canister_id, p1, &transfer_arg), None);
+
+    let block_index = send_transfer(&env, canister_id, p1, &transfer_arg).expect("transfer failed");
+    assert_eq!(
+        send_transfer(&env, canister_id, p1, &transfer_arg),
+        Err(TransferError::Duplicate {
+            duplicate_of: Nat::from(block_index)
+        })
+    );
+    assert_eq!(
+        find_duplicate_transfer(&env, canister_id, p1, &transfer_arg),
+        Some(DuplicateOf {
+            block_index: Nat::from(block_index),
+            timestamp: now
+        })
+    );
+
+    // Transfers that differ from the original are not duplicates.
+    for (caller, arg) in [
+        (p2, transfer_arg.clone()),
+        (
+            p1,
+            TransferArg {
+                amount: Nat::from(2_000),
+                ..transfer_arg.clone()
+            },
+        ),
+        (
+            p1,
+            TransferArg {
+                created_at_time: None,
+                ..transfer_arg.clone()
+            },
+        ),
+    ] {
+        assert_eq!(find_duplicate_transfer(&env, canister_id, caller, &arg), None);
+    }
+
+    // Burns and mints have duplicates too.
+    for (caller, arg) in [
+        (
+            p1,
+            TransferArg {
+                to: MINTER.clone(),
+                amount: Nat::from(FEE),
+                ..transfer_arg.clone()
+            },
+        ),
+        (MINTER.owner, transfer_arg.clone()),
+    ] {
+        let timestamp = system_time_to_nanos(env.time());
+        let block_index = send_transfer(&env, canister_id, caller, &arg).expect("transfer failed");
+        assert_eq!(
+            find_duplicate_transfer(&env, canister_id, caller, &arg),
+            Some(DuplicateOf {
+                block_index: Nat::from(block_index),
+                timestamp,
+            })
+        );
+    }
+
+    // The ledger forgets the transactions that leave the deduplication window.
+    env.advance_time(TX_WINDOW + Duration::from_secs(5 * 60));
+    transfer(&env, canister_id, p1, p2, 3_000).expect("transfer failed");
+    assert_eq!(find_duplicate_transfer(&env, canister_id, p1, &transfer_arg), None);
+}
+
+#[test]
+fn test_auto_created_at_time() {
+    let env = StateMachine::new();
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let canister_id = install_ledger(&env, vec![(Account::from(p1), 10_000_000)]);
+
+    env.upgrade_canister(
+        canister_id,
+        ledger_wasm(),
+        Encode!(&Some(UpgradeArgs {
+            auto_created_at_time: Some(true),
+            ..UpgradeArgs::default()
+        }))
+        .unwrap(),
+    )
+    .expect("failed to upgrade the ledger");
+
+    let now = system_time_to_nanos(env.time());
+    let block_index = transfer(&env, canister_id, p1, p2, 1_000).expect("transfer failed");
+    let tx = get_transactions(&env, canister_id, block_index, 1).transactions;
+    assert_eq!(tx[0].transfer.as_ref().unwrap().created_at_time, Some(now));
+
+    // Identical transfers received at the same time are duplicates.
+    assert_eq!(
+        transfer(&env, canister_id, p1, p2, 1_000),
+        Err(TransferError::Duplicate {
+            duplicate_of: Nat::from(block_index)
+        })
+    );
+
+    env.advance_time(Duration::from_secs(1));
+    transfer(&env, canister_id, p1, p2, 1_000).expect("transfer failed");
+    assert_eq!(2_000u64, balance_of(&env, canister_id, p2));
+}
+
 #[test]
 fn test_mint_burn() {
     let env = StateMachine::new();
@@ -605,6 +849,220 @@ fn test_memo_validation() {
     }
 }
 
+#[test]
+fn test_upgrade_args() {
+    let env = StateMachine::new();
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let canister_id = install_ledger(&env, vec![(Account::from(p1), 10_000_000)]);
+
+    let upgrade = |args: UpgradeArgs| {
+        env.upgrade_canister(canister_id, ledger_wasm(), Encode!(&Some(args)).unwrap())
+    };
+
+    // Invalid arguments must be rejected.
+    for args in [
+        UpgradeArgs {
+            transaction_window: Some(0),
+            ..UpgradeArgs::default()
+        },
+        UpgradeArgs {
+            permitted_drift: Some(Duration::from_secs(24 * 60 * 60).as_nanos() as u64),
+            ..UpgradeArgs::default()
+        },
+        UpgradeArgs {
+            max_memo_length: Some(16),
+            ..UpgradeArgs::default()
+        },
+        UpgradeArgs {
+            block_schema_version: Some(BLOCK_SCHEMA_VERSION + 1),
+            ..UpgradeArgs::default()
+        },
+    ] {
+        assert!(upgrade(args.clone()).is_err(), "upgrade with {:?} succeeded", args);
+    }
+
+    upgrade(UpgradeArgs {
+        transaction_window: Some(Duration::from_secs(60 * 60).as_nanos() as u64),
+        permitted_drift: Some(Duration::from_secs(10 * 60).as_nanos() as u64),
+        max_memo_length: Some(64),
+        change_fee_collector: None,
+        block_schema_version: Some(0),
+        auto_created_at_time: None,
+    })
+    .expect("failed to upgrade the ledger");
+
+    // The configuration change is recorded right after the initial mint.
+    let txs = get_transactions(&env, canister_id, 0, 10).transactions;
+    assert_eq!(txs.len(), 2);
+    assert_eq!(txs[1].kind, "upgrade");
+
+    // Upgrades without arguments keep the configuration.
+    env.upgrade_canister(canister_id, ledger_wasm(), vec![])
+        .expect("failed to upgrade the ledger");
+
+    let now = system_time_to_nanos(env.time());
+    let transfer_arg = |created_at_time: u64, memo_length: usize| TransferArg {
+        from_subaccount: None,
+        to: p2.into(),
+        fee: None,
+        amount: Nat::from(1_000_000),
+        created_at_time: Some(created_at_time),
+        memo: Some(Memo::try_from(vec![1u8; memo_length]).unwrap()),
+    };
+
+    assert_eq!(
+        Err(TransferError::TooOld),
+        send_transfer(
+            &env,
+            canister_id,
+            p1,
+            &transfer_arg(now - Duration::from_secs(2 * 60 * 60).as_nanos() as u64, 8)
+        )
+    );
+
+    let future = now + Duration::from_secs(5 * 60).as_nanos() as u64;
+    send_transfer(&env, canister_id, p1, &transfer_arg(future, 64))
+        .expect("transfer with a 64-byte memo failed");
+    assert_eq!(1_000_000u64, balance_of(&env, canister_id, p2));
+
+    // Memos longer than the configured maximum are rejected.
+    match env.execute_ingress_as(
+        p1,
+        canister_id,
+        "icrc1_transfer",
+        Encode!(&transfer_arg(now, 65)).unwrap(),
+    ) {
+        Err(user_error) => assert_eq!(
+            user_error.code(),
+            ErrorCode::CanisterCalledTrap,
+            "unexpected error: {}",
+            user_error
+        ),
+        Ok(result) => panic!(
+            "expected a reject for a 65-byte memo, got result {:?}",
+            result
+        ),
+    }
+}
+
+#[test]
+fn test_fee_collector() {
+    let env = StateMachine::new();
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let fee_collector = Account::from(PrincipalId::new_user_test_id(3));
+    let args = InitArgs {
+        minting_account: MINTER.clone(),
+        initial_balances: vec![(Account::from(p1), 10_000_000)],
+        transfer_fee: FEE,
+        token_name: TOKEN_NAME.to_string(),
+        token_symbol: TOKEN_SYMBOL.to_string(),
+        metadata: vec![],
+        archive_options: default_archive_options(),
+        fee_collector_account: Some(fee_collector.clone()),
+        decimals: None,
+    };
+    let canister_id = env
+        .install_canister(ledger_wasm(), Encode!(&args).unwrap(), None)
+        .unwrap();
+
+    let upgrade = |args: UpgradeArgs| {
+        env.upgrade_canister(canister_id, ledger_wasm(), Encode!(&Some(args)).unwrap())
+    };
+
+    // The fees are credited to the fee collector instead of being burned.
+    transfer(&env, canister_id, p1, p2, 1_000_000).expect("transfer failed");
+    transfer(&env, canister_id, p1, p2, 1_000_000).expect("transfer failed");
+    assert_eq!(2 * FEE, balance_of(&env, canister_id, fee_collector.clone()));
+    assert_eq!(10_000_000, total_supply(&env, canister_id));
+
+    // The minting account cannot collect fees.
+    assert!(upgrade(UpgradeArgs {
+        change_fee_collector: Some(ChangeFeeCollector::SetTo(MINTER.clone())),
+        ..UpgradeArgs::default()
+    })
+    .is_err());
+
+    // Changing only the fee collector doesn't record an upgrade block.
+    upgrade(UpgradeArgs {
+        change_fee_collector: Some(ChangeFeeCollector::Unset),
+        ..UpgradeArgs::default()
+    })
+    .expect("failed to upgrade the ledger");
+    assert_eq!(3, get_transactions(&env, canister_id, 0, 10).transactions.len());
+
+    transfer(&env, canister_id, p1, p2, 1_000_000).expect("transfer failed");
+    assert_eq!(2 * FEE, balance_of(&env, canister_id, fee_collector.clone()));
+    assert_eq!(10_000_000 - FEE, total_supply(&env, canister_id));
+
+    upgrade(UpgradeArgs {
+        change_fee_collector: Some(ChangeFeeCollector::SetTo(fee_collector.clone())),
+        ..UpgradeArgs::default()
+    })
+    .expect("failed to upgrade the ledger");
+    transfer(&env, canister_id, p1, p2, 1_000_000).expect("transfer failed");
+    assert_eq!(3 * FEE, balance_of(&env, canister_id, fee_collector));
+    assert_eq!(10_000_000 - FEE, total_supply(&env, canister_id));
+}
+
+#[test]
+fn test_fee_collector_blocks() {
+    let p1 = Account::from(PrincipalId::new_user_test_id(1));
+    let p2 = Account::from(PrincipalId::new_user_test_id(2));
+    let fee_collector = Account::from(PrincipalId::new_user_test_id(3));
+    let now = TimeStamp::from_nanos_since_unix_epoch(1_000_000_000);
+    let mut ledger = ic_icrc1_ledger::Ledger::<Tokens>::from_init_args(
+        InitArgs {
+            minting_account: MINTER.clone(),
+            initial_balances: vec![(p1.clone(), 10_000_000)],
+            transfer_fee: FEE,
+            token_name: TOKEN_NAME.to_string(),
+            token_symbol: TOKEN_SYMBOL.to_string(),
+            metadata: vec![],
+            archive_options: default_archive_options(),
+            fee_collector_account: Some(fee_collector.clone()),
+            decimals: None,
+        },
+        now,
+    );
+    let mut apply = |fee: u64| {
+        let tx = Transaction::transfer(
+            p1.clone(),
+            p2.clone(),
+            Tokens::from_e8s(1_000),
+            Tokens::from_e8s(fee),
+            None,
+            None,
+        );
+        apply_transaction(&mut ledger, tx, now).expect("failed to apply the transfer");
+    };
+    apply(0);
+    apply(FEE);
+    apply(FEE);
+
+    let fee_collectors: Vec<_> = ledger
+        .blockchain()
+        .blocks
+        .iter()
+        .map(|block| {
+            let block = Block::<Tokens>::decode(block.clone()).unwrap();
+            (block.fee_collector, block.fee_collector_block_index)
+        })
+        .collect();
+    // Neither the initial mint nor the transfer without a fee credit the fee
+    // collector, so the first transfer with a fee mentions it.
+    assert_eq!(
+        fee_collectors,
+        vec![
+            (None, None),
+            (None, None),
+            (Some(fee_collector), None),
+            (None, Some(2)),
+        ]
+    );
+}
+
 #[test]
 fn test_tx_time_bounds() {
     let env = StateMachine::new();
@@ -767,8 +1225,176 @@ fn test_archiving() {
     }
 }
 
-fn arb_amount() -> impl Strategy<Value = u64> {
-    any::<u64>()
+// Returns the hash tree of the tip certificate of the ledger after checking
+// that the certificate is valid and certifies the tree.
+fn verified_tip_hash_tree(env: &StateMachine, ledger: CanisterId) -> MixedHashTree {
+    let cert = Decode!(
+        &env.query(ledger, "icrc3_get_tip_certificate", Encode!().unwrap())
+            .expect("failed to query the tip certificate")
+            .bytes(),
+        Option<DataCertificate>
+    )
+    .expect("failed to decode icrc3_get_tip_certificate response")
+    .expect("the ledger returned no certificate");
+
+    let hash_tree: MixedHashTree =
+        ciborium::de::from_reader(&cert.hash_tree[..]).expect("failed to decode the hash tree");
+    ic_certification::verify_certified_data(
+        &cert.certificate,
+        &ledger,
+        &env.root_key(),
+        &hash_tree.digest().0,
+    )
+    .expect("the certificate does not certify the hash tree");
+    hash_tree
+}
+
+fn leaf(hash_tree: &MixedHashTree, label: &str) -> Vec<u8> {
+    match hash_tree.lookup(&[label]) {
+        LookupStatus::Found(MixedHashTree::Leaf(bytes)) => bytes.clone(),
+        status => panic!("unexpected lookup status for {}: {:?}", label, status),
+    }
+}
+
+#[test]
+fn test_tip_certificate() {
+    let env = StateMachine::new();
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let canister_id = install_ledger(&env, vec![(Account::from(p1), 10_000_000)]);
+    let block_index = transfer(&env, canister_id, p1, p2, 10_000).expect("transfer failed");
+
+    let hash_tree = verified_tip_hash_tree(&env, canister_id);
+    let last_block_index = leaf(&hash_tree, "last_block_index");
+    assert_eq!(
+        leb128::read::unsigned(&mut last_block_index.as_slice()).unwrap(),
+        block_index
+    );
+    let last_block_hash = leaf(&hash_tree, "last_block_hash");
+    assert_eq!(last_block_hash.len(), 32);
+    assert_eq!(last_block_hash, leaf(&hash_tree, "tip_hash"));
+}
+
+#[test]
+fn test_tip_certificate_after_upgrade_without_args() {
+    let env = StateMachine::new();
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    let canister_id = install_ledger(&env, vec![(Account::from(p1), 10_000_000)]);
+    let block_index = transfer(&env, canister_id, p1, p2, 10_000).expect("transfer failed");
+    let tip_hash = leaf(&verified_tip_hash_tree(&env, canister_id), "tip_hash");
+
+    env.upgrade_canister(
+        canister_id,
+        ledger_wasm(),
+        Encode!(&None::<UpgradeArgs>).unwrap(),
+    )
+    .expect("failed to upgrade the ledger");
+
+    let hash_tree = verified_tip_hash_tree(&env, canister_id);
+    let last_block_index = leaf(&hash_tree, "last_block_index");
+    assert_eq!(
+        leb128::read::unsigned(&mut last_block_index.as_slice()).unwrap(),
+        block_index
+    );
+    assert_eq!(leaf(&hash_tree, "tip_hash"), tip_hash);
+}
+
+#[test]
+fn test_max_archive_nodes() {
+    let env = StateMachine::new();
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+
+    // The blocks don't fit into a single archive node of this size.
+    let canister_id = install_ledger_with_archive_options(
+        &env,
+        vec![(Account::from(p1), 10_000_000)],
+        ArchiveOptions {
+            node_max_memory_size_bytes: Some(1024),
+            max_archive_nodes: Some(1),
+            ..default_archive_options()
+        },
+    );
+
+    for i in 0..3 * ARCHIVE_TRIGGER_THRESHOLD {
+        transfer(&env, canister_id, p1, p2, 10_000 + i).expect("transfer failed");
+    }
+    env.run_until_completion(/*max_ticks=*/ 10);
+
+    assert_eq!(list_archives(&env, canister_id).len(), 1);
+
+    // The blocks that didn't fit into the archive stay in the ledger.
+    let resp = get_transactions(&env, canister_id, 0, 1_000_000);
+    let num_archived = resp.first_index.0.to_u64().unwrap();
+    assert!(num_archived > 0);
+    assert_eq!(
+        num_archived + resp.transactions.len() as u64,
+        3 * ARCHIVE_TRIGGER_THRESHOLD + 1
+    );
+    assert_eq!(resp.log_length, Nat::from(3 * ARCHIVE_TRIGGER_THRESHOLD + 1));
+}
+
+#[test]
+fn test_u256_tokens() {
+    let env = StateMachine::new();
+    let args = InitArgs {
+        minting_account: MINTER.clone(),
+        initial_balances: vec![],
+        transfer_fee: FEE,
+        token_name: TOKEN_NAME.to_string(),
+        token_symbol: TOKEN_SYMBOL.to_string(),
+        metadata: vec![],
+        archive_options: default_archive_options(),
+        fee_collector_account: None,
+        decimals: Some(18),
+    };
+    let ledger = env
+        .install_canister(ledger_wasm_u256(), Encode!(&args).unwrap(), None)
+        .unwrap();
+    let query_nat = |method: &str, arg: Vec<u8>| {
+        Decode!(
+            &env.query(ledger, method, arg)
+                .expect("failed to query the ledger")
+                .bytes(),
+            Nat
+        )
+        .expect("failed to decode the response")
+    };
+    let transfer_arg = |to: PrincipalId, amount: u128| TransferArg {
+        from_subaccount: None,
+        to: Account::from(to),
+        fee: None,
+        created_at_time: None,
+        memo: None,
+        amount: Nat::from(amount),
+    };
+
+    let p1 = PrincipalId::new_user_test_id(1);
+    let p2 = PrincipalId::new_user_test_id(2);
+    // A million tokens with 18 decimals don't fit into 64 bits.
+    let minted = 1_000_000 * 10u128.pow(18);
+    send_transfer(&env, ledger, MINTER.owner, &transfer_arg(p1, minted))
+        .expect("failed to mint");
+    let sent = u64::MAX as u128 + 1;
+    send_transfer(&env, ledger, p1, &transfer_arg(p2, sent)).expect("failed to transfer");
+
+    let balance_of =
+        |p: PrincipalId| query_nat("icrc1_balance_of", Encode!(&Account::from(p)).unwrap());
+    assert_eq!(balance_of(p1), Nat::from(minted - sent - FEE as u128));
+    assert_eq!(balance_of(p2), Nat::from(sent));
+    assert_eq!(
+        query_nat("icrc1_total_supply", Encode!().unwrap()),
+        Nat::from(minted - FEE as u128)
+    );
+    assert_eq!(
+        metadata(&env, ledger).get("icrc1:decimals"),
+        Some(&Value::from(18u64))
+    );
+}
+
+fn arb_amount() -> impl Strategy<Value = Tokens> {
+    any::<u64>().prop_map(Tokens::from_e8s)
 }
 
 fn arb_account() -> impl Strategy<Value = Account> {
@@ -804,8 +1430,21 @@ fn arb_burn() -> impl Strategy<Value = Operation> {
     (arb_account(), arb_amount()).prop_map(|(from, amount)| Operation::Burn { from, amount })
 }
 
+fn arb_upgrade() -> impl Strategy<Value = Operation> {
+    (
+        any::<Option<u64>>(),
+        any::<Option<u64>>(),
+        any::<Option<u16>>(),
+    )
+        .prop_map(|(window, drift, max_memo_length)| Operation::Upgrade {
+            transaction_window_nanos: window,
+            permitted_drift_nanos: drift,
+            max_memo_length,
+        })
+}
+
 fn arb_operation() -> impl Strategy<Value = Operation> {
-    prop_oneof![arb_transfer(), arb_mint(), arb_burn()]
+    prop_oneof![arb_transfer(), arb_mint(), arb_burn(), arb_upgrade()]
 }
 
 fn arb_transaction() -> impl Strategy<Value = Transaction> {
@@ -822,13 +1461,26 @@ fn arb_transaction() -> impl Strategy<Value = Transaction> {
 }
 
 fn arb_block() -> impl Strategy<Value = Block> {
-    (any::<Option<[u8; 32]>>(), arb_transaction(), any::<u64>()).prop_map(
-        |(parent_hash, transaction, ts)| Block {
-            parent_hash: parent_hash.map(HashOf::new),
-            transaction,
-            timestamp: ts,
-        },
+    (
+        any::<Option<[u8; 32]>>(),
+        arb_transaction(),
+        any::<u64>(),
+        proptest::option::of(arb_account()),
+        any::<Option<u64>>(),
+        0..=MAX_SUPPORTED_BLOCK_SCHEMA_VERSION,
     )
+        .prop_map(
+            |(parent_hash, transaction, ts, fee_collector, fee_collector_block_index, version)| {
+                Block {
+                    parent_hash: parent_hash.map(HashOf::new),
+                    transaction,
+                    timestamp: ts,
+                    fee_collector,
+                    fee_collector_block_index,
+                    schema_version: version,
+                }
+            },
+        )
 }
 
 // Generate random blocks and check that their CBOR encoding complies with the CDDL spec.
@@ -881,8 +1533,8 @@ fn block_hashes_are_unique() {
         .run(&(arb_block(), arb_block()), |(lhs, rhs)| {
             prop_assume!(lhs != rhs);
 
-            let lhs_hash = Block::block_hash(&lhs.encode());
-            let rhs_hash = Block::block_hash(&rhs.encode());
+            let lhs_hash = Block::<Tokens>::block_hash(&lhs.encode());
+            let rhs_hash = Block::<Tokens>::block_hash(&rhs.encode());
 
             prop_assert_ne!(lhs_hash, rhs_hash);
             Ok(())
@@ -897,15 +1549,109 @@ fn block_hashes_are_stable() {
     runner
         .run(&arb_block(), |block| {
             let encoded_block = block.encode();
-            let hash1 = Block::block_hash(&encoded_block);
-            let decoded = Block::decode(encoded_block).unwrap();
-            let hash2 = Block::block_hash(&decoded.encode());
+            let hash1 = Block::<Tokens>::block_hash(&encoded_block);
+            let decoded = Block::<Tokens>::decode(encoded_block).unwrap();
+            let hash2 = Block::<Tokens>::block_hash(&decoded.encode());
             prop_assert_eq!(hash1, hash2);
             Ok(())
         })
         .unwrap();
 }
 
+// Check that blocks of the next schema version decode, ignoring the fields that
+// the version adds, and that later versions are rejected.
+#[test]
+fn decodes_the_next_block_schema_version() {
+    use ciborium::value::Value as CborValue;
+
+    let block = Block::from_transaction(
+        None,
+        Transaction::mint(
+            Account::from(PrincipalId::new_user_test_id(1)),
+            Tokens::from_e8s(1_000_000),
+            None,
+            None,
+        ),
+        SystemTime::UNIX_EPOCH.into(),
+        None,
+    );
+    assert_eq!(block.schema_version, BLOCK_SCHEMA_VERSION);
+
+    let encode_with_version = |version: u32| {
+        let mut value: CborValue = ciborium::de::from_reader(block.clone().encode().as_slice())
+            .expect("failed to decode the block as CBOR");
+        let fields = match &mut value {
+            CborValue::Tag(_, content) => match content.as_mut() {
+                CborValue::Map(fields) => fields,
+                other => panic!("expected a map, got {:?}", other),
+            },
+            other => panic!("expected a tagged value, got {:?}", other),
+        };
+        fields.retain(|(key, _)| key != &CborValue::Text("ver".to_string()));
+        fields.push((CborValue::Text("ver".to_string()), version.into()));
+        fields.push((CborValue::Text("new_field".to_string()), "new".into()));
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(&value, &mut bytes).unwrap();
+        EncodedBlock::from_vec(bytes)
+    };
+
+    let next = encode_with_version(MAX_SUPPORTED_BLOCK_SCHEMA_VERSION);
+    let decoded = Block::<Tokens>::decode(next)
+        .expect("failed to decode a block of the next schema version");
+    assert_eq!(decoded.schema_version, MAX_SUPPORTED_BLOCK_SCHEMA_VERSION);
+    assert_eq!(decoded.transaction, block.transaction);
+
+    let too_new = encode_with_version(MAX_SUPPORTED_BLOCK_SCHEMA_VERSION + 1);
+    assert!(Block::<Tokens>::decode(too_new).is_err());
+}
+
+// Check that blocks with 256-bit amounts comply with the CDDL spec and that
+// amounts that fit into 64 bits have the same encoding for all token types.
+#[test]
+fn encodes_256_bit_amounts() {
+    use ic_ledger_core::tokens_u256::U256;
+
+    let block_cddl_path =
+        PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap()).join("block.cddl");
+    let block_cddl = std::fs::read_to_string(block_cddl_path).expect("failed to read block.cddl");
+
+    let from = Account::from(PrincipalId::new_user_test_id(1));
+    let to = Account::from(PrincipalId::new_user_test_id(2));
+    let make_block = |amount: U256, fee: U256| {
+        Block::from_transaction(
+            None,
+            Transaction::transfer(from.clone(), to.clone(), amount, fee, None, None),
+            SystemTime::UNIX_EPOCH.into(),
+            None,
+        )
+    };
+
+    let block = make_block(U256::MAX, U256::from_words(1, 0));
+    let encoded = block.clone().encode();
+    cddl::validate_cbor_from_slice(&block_cddl, encoded.as_slice(), None)
+        .expect("the block does not comply with the CDDL spec");
+    assert_eq!(Block::<U256>::decode(encoded.clone()), Ok(block));
+    assert!(Block::<Tokens>::decode(encoded).is_err());
+
+    let narrow = Block::from_transaction(
+        None,
+        Transaction::transfer(
+            from.clone(),
+            to.clone(),
+            Tokens::from_e8s(u64::MAX),
+            Tokens::from_e8s(10_000),
+            None,
+            None,
+        ),
+        SystemTime::UNIX_EPOCH.into(),
+        None,
+    );
+    assert_eq!(
+        make_block(U256::from(u64::MAX), U256::from(10_000u64)).encode(),
+        narrow.encode()
+    );
+}
+
 #[test]
 fn check_transfer_model() {
     use proptest::collection::vec as pvec;
@@ -929,6 +1675,33 @@ fn check_transfer_model() {
         .unwrap();
 }
 
+#[test]
+fn test_generated_scenarios() {
+    ic_icrc1_ledger_sm_tests::test_generated_scenarios(ledger_wasm(), encode_init_args)
+}
+
+/// Runs the upgrade/downgrade round trip between the ledger and the Wasm in
+/// `IC_ICRC1_LEDGER_DEPLOYED_VERSION_WASM_PATH`, e.g., the version deployed on
+/// the mainnet, starting from the stable memory in
+/// `IC_ICRC1_LEDGER_GOLDEN_STATE_PATH` if it is set. Without the variables,
+/// the test round-trips between two copies of the current ledger.
+#[test]
+fn test_upgrade_downgrade_roundtrip() {
+    let read_file = |var: &str| {
+        std::env::var_os(var).map(|path| {
+            std::fs::read(&path).unwrap_or_else(|e| panic!("failed to read {}: {}", var, e))
+        })
+    };
+    let previous_ledger_wasm =
+        read_file("IC_ICRC1_LEDGER_DEPLOYED_VERSION_WASM_PATH").unwrap_or_else(ledger_wasm);
+    ic_icrc1_ledger_sm_tests::golden_state::test_upgrade_downgrade(
+        ledger_wasm(),
+        previous_ledger_wasm,
+        encode_init_args,
+        read_file("IC_ICRC1_LEDGER_GOLDEN_STATE_PATH"),
+    )
+}
+
 type BalancesModel = HashMap<Account, u64>;
 
 fn model_transfer(
@@ -1,8 +1,9 @@
 use crate::{Account, Block, Memo, Subaccount};
 use candid::types::number::{Int, Nat};
 use candid::CandidType;
-use ic_base_types::CanisterId;
+use ic_base_types::{CanisterId, PrincipalId};
 use ic_ledger_canister_core::ledger::TransferError as CoreTransferError;
+use ic_ledger_core::tokens::TokensType;
 use serde::Deserialize;
 use serde_bytes::ByteBuf;
 use std::convert::TryFrom;
@@ -22,17 +23,17 @@ pub enum TransferError {
     GenericError { error_code: Nat, message: String },
 }
 
-impl From<CoreTransferError> for TransferError {
-    fn from(err: CoreTransferError) -> Self {
+impl<Tokens: TokensType> From<CoreTransferError<Tokens>> for TransferError {
+    fn from(err: CoreTransferError<Tokens>) -> Self {
         use ic_ledger_canister_core::ledger::TransferError as LTE;
         use TransferError as TE;
 
         match err {
             LTE::BadFee { expected_fee } => TE::BadFee {
-                expected_fee: Nat::from(expected_fee.get_e8s()),
+                expected_fee: expected_fee.to_nat(),
             },
             LTE::InsufficientFunds { balance } => TE::InsufficientFunds {
-                balance: Nat::from(balance.get_e8s()),
+                balance: balance.to_nat(),
             },
             LTE::TxTooOld { .. } => TE::TooOld,
             LTE::TxCreatedInFuture { ledger_time } => TE::CreatedInFuture {
@@ -138,6 +139,36 @@ pub struct ArchiveInfo {
     pub block_range_end: BlockIndex,
 }
 
+/// The arguments of the `find_transaction_by` query: the fields of a transfer
+/// that identify it for deduplication, apart from the amounts and the
+/// receiver. `from` is the account that sent the transfer, i.e., the minting
+/// account for mints.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FindTransactionArgs {
+    pub from: Account,
+    pub created_at_time: u64,
+    #[serde(default)]
+    pub memo: Option<Memo>,
+}
+
+/// The arguments of the `find_duplicate_transfer` query: a transfer that the
+/// ledger rejected as a duplicate and the principal that sent it.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FindDuplicateArgs {
+    pub caller: PrincipalId,
+    pub transfer: TransferArg,
+}
+
+/// The transaction that a duplicate transfer repeats.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateOf {
+    /// The index of the block of the original transaction.
+    pub block_index: BlockIndex,
+    /// The time at which the ledger recorded the original transaction, in
+    /// nanoseconds since the UNIX epoch.
+    pub timestamp: u64,
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct GetTransactionsRequest {
     pub start: BlockIndex,
@@ -267,8 +298,8 @@ impl CandidType for QueryArchiveFn {
     }
 }
 
-impl From<Block> for Transaction {
-    fn from(b: Block) -> Transaction {
+impl<Tokens: TokensType> From<Block<Tokens>> for Transaction {
+    fn from(b: Block<Tokens>) -> Transaction {
         use crate::Operation;
 
         let mut tx = Transaction {
@@ -286,7 +317,7 @@ impl From<Block> for Transaction {
                 tx.kind = "mint".to_string();
                 tx.mint = Some(Mint {
                     to,
-                    amount: Nat::from(amount),
+                    amount: amount.to_nat(),
                     created_at_time,
                     memo,
                 });
@@ -295,7 +326,7 @@ impl From<Block> for Transaction {
                 tx.kind = "burn".to_string();
                 tx.burn = Some(Burn {
                     from,
-                    amount: Nat::from(amount),
+                    amount: amount.to_nat(),
                     created_at_time,
                     memo,
                 });
@@ -310,12 +341,17 @@ impl From<Block> for Transaction {
                 tx.transfer = Some(Transfer {
                     from,
                     to,
-                    amount: Nat::from(amount),
-                    fee: Some(Nat::from(fee)),
+                    amount: amount.to_nat(),
+                    fee: Some(fee.to_nat()),
                     created_at_time,
                     memo,
                 });
             }
+            Operation::Upgrade { .. } => {
+                // The transaction record has no details of configuration
+                // changes, clients can fetch them from the block.
+                tx.kind = "upgrade".to_string();
+            }
         }
 
         tx
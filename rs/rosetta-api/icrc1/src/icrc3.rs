@@ -0,0 +1,219 @@
+//! Types of the ICRC-3 block log endpoints `icrc3_get_blocks`,
+//! `icrc3_get_archives`, and `icrc3_supported_block_types`.
+//!
+//! ICRC-3 exposes blocks in a generic, self-describing `Value` encoding, so
+//! that indexers can process the blocks of any ledger without knowing the
+//! ledger-specific block encoding.
+
+use candid::types::number::{Int, Nat};
+use candid::CandidType;
+use ic_base_types::{CanisterId, PrincipalId};
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+use std::convert::TryFrom;
+
+/// The generic encoding of blocks.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Blob(ByteBuf),
+    Text(String),
+    Nat(Nat),
+    Int(Int),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn entry(key: impl ToString, val: impl Into<Value>) -> (String, Self) {
+        (key.to_string(), val.into())
+    }
+
+    /// Returns the value of the given key if `self` is a map.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self {
+        Value::Nat(Nat::from(n))
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(Int::from(n))
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Text(s)
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Self {
+        Value::Text(s.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Value {
+        Value::Blob(ByteBuf::from(bytes))
+    }
+}
+
+impl<'a> From<&'a [u8]> for Value {
+    fn from(bytes: &'a [u8]) -> Value {
+        Value::Blob(ByteBuf::from(bytes.to_vec()))
+    }
+}
+
+impl From<Vec<(String, Value)>> for Value {
+    fn from(entries: Vec<(String, Value)>) -> Value {
+        Value::Map(entries)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GetBlocksArgs {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+impl GetBlocksArgs {
+    pub fn new(start: u64, length: u64) -> Self {
+        Self {
+            start: Nat::from(start),
+            length: Nat::from(length),
+        }
+    }
+
+    pub fn as_start_and_length(&self) -> Result<(u64, usize), String> {
+        use num_traits::cast::ToPrimitive;
+
+        let start = self.start.0.to_u64().ok_or_else(|| {
+            format!(
+                "block index {} is too large, max allowed: {}",
+                self.start,
+                u64::MAX
+            )
+        })?;
+        let length = self.length.0.to_usize().ok_or_else(|| {
+            format!(
+                "requested length {} is too large, max allowed: {}",
+                self.length,
+                usize::MAX
+            )
+        })?;
+        Ok((start, length))
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BlockWithId {
+    pub id: Nat,
+    pub block: Value,
+}
+
+/// A range of blocks that is stored in an archive and can be fetched by
+/// calling `callback` with `args`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ArchivedBlocks {
+    pub args: Vec<GetBlocksArgs>,
+    pub callback: GetBlocksFn,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GetBlocksResult {
+    /// The total number of blocks in the log, including the archived blocks.
+    pub log_length: Nat,
+    pub blocks: Vec<BlockWithId>,
+    pub archived_blocks: Vec<ArchivedBlocks>,
+}
+
+/// A reference to a query method with the signature of `icrc3_get_blocks`.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "candid::types::reference::Func")]
+pub struct GetBlocksFn {
+    pub canister_id: CanisterId,
+    pub method: String,
+}
+
+impl From<GetBlocksFn> for candid::types::reference::Func {
+    fn from(archive_fn: GetBlocksFn) -> Self {
+        let p: &PrincipalId = archive_fn.canister_id.as_ref();
+        Self {
+            principal: p.0,
+            method: archive_fn.method,
+        }
+    }
+}
+
+impl TryFrom<candid::types::reference::Func> for GetBlocksFn {
+    type Error = String;
+    fn try_from(func: candid::types::reference::Func) -> Result<Self, Self::Error> {
+        let canister_id = CanisterId::try_from(func.principal.as_slice())
+            .map_err(|e| format!("principal is not a canister id: {}", e))?;
+        Ok(GetBlocksFn {
+            canister_id,
+            method: func.method,
+        })
+    }
+}
+
+impl CandidType for GetBlocksFn {
+    fn _ty() -> candid::types::Type {
+        // The result type refers back to this type, so we must go through
+        // `ty()`, which resolves the recursion.
+        candid::types::Type::Func(candid::types::Function {
+            modes: vec![candid::parser::types::FuncMode::Query],
+            args: vec![Vec::<GetBlocksArgs>::ty()],
+            rets: vec![GetBlocksResult::ty()],
+        })
+    }
+
+    fn idl_serialize<S>(&self, serializer: S) -> Result<(), S::Error>
+    where
+        S: candid::types::Serializer,
+    {
+        candid::types::reference::Func::from(self.clone()).idl_serialize(serializer)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GetArchivesArgs {
+    /// The last archive seen by the caller. If set, only the archives after
+    /// it are returned, which allows paginating through the archives.
+    pub from: Option<PrincipalId>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveInfo {
+    pub canister_id: PrincipalId,
+    /// The index of the first block in the archive.
+    pub start: Nat,
+    /// The index of the last block in the archive.
+    pub end: Nat,
+}
+
+/// A certificate of the tip of the block log. `hash_tree` is the CBOR encoding
+/// of a hash tree with the labels `last_block_index`, a LEB128-encoded block
+/// index, and `last_block_hash`. Its root hash is the certified data in the
+/// `certificate`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DataCertificate {
+    pub certificate: ByteBuf,
+    pub hash_tree: ByteBuf,
+}
+
+/// A block type that the ledger produces and the URL of its schema.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SupportedBlockType {
+    pub block_type: String,
+    pub url: String,
+}
@@ -1,16 +1,19 @@
 pub mod endpoints;
 pub mod hash;
+pub mod icrc3;
 
-use candid::CandidType;
+use candid::{CandidType, Nat};
 use ciborium::tag::Required;
 use ic_base_types::PrincipalId;
 use ic_ledger_canister_core::ledger::LedgerTransaction;
 use ic_ledger_core::{
     balances::{BalanceError, Balances, BalancesStore},
-    block::{BlockType, EncodedBlock, HashOf},
+    block::{BlockType, EncodedBlock, FeeCollector, HashOf},
     timestamp::TimeStamp,
-    tokens::Tokens,
+    tokens::{Tokens, TokensType},
 };
+use num_bigint::BigUint;
+use num_traits::ToPrimitive;
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 use std::collections::HashMap;
@@ -20,7 +23,26 @@ use std::fmt;
 pub type Subaccount = [u8; 32];
 
 pub const DEFAULT_SUBACCOUNT: &Subaccount = &[0; 32];
+/// The default maximum length of memos that ledgers accept in transfers.
 pub const MAX_MEMO_LENGTH: usize = 32;
+/// The maximum memo length that ledgers can be configured to accept.
+pub const MAX_MEMO_LENGTH_LIMIT: usize = 256;
+
+/// The latest version of the block schema.
+///
+/// Blocks created before the schema was versioned don't have a version and
+/// count as version 0. Version 1 has the same fields as version 0, plus the
+/// version itself.
+pub const BLOCK_SCHEMA_VERSION: u32 = 1;
+
+/// The latest block schema version that `Block::decode` accepts.
+///
+/// The decoder accepts the version after `BLOCK_SCHEMA_VERSION`: a new
+/// version can only add fields, which the decoder skips. This lets archives
+/// and indexes decode the blocks of a ledger that was upgraded before them.
+/// Note that such blocks must not be re-encoded, the encoding would miss the
+/// skipped fields.
+pub const MAX_SUPPORTED_BLOCK_SCHEMA_VERSION: u32 = BLOCK_SCHEMA_VERSION + 1;
 
 #[derive(Serialize, Deserialize, CandidType, Clone, Debug)]
 pub struct Account {
@@ -155,16 +177,57 @@ impl TryFrom<CompactAccount> for Account {
     }
 }
 
+/// An amount in the compact encoding: an unsigned integer if the amount fits
+/// into 64 bits and the big-endian bytes of the amount otherwise. Amounts that
+/// fit into 64 bits have the same encoding for all token types, so the blocks
+/// of ledgers with wide token types stay compatible with the other ledgers.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum CompactAmount {
+    U64(u64),
+    BigEndian(ByteBuf),
+}
+
+fn ser_compact_amount<Tokens, S>(amount: &Tokens, s: S) -> Result<S::Ok, S::Error>
+where
+    Tokens: TokensType,
+    S: serde::ser::Serializer,
+{
+    let n = amount.to_nat();
+    let compact = match n.0.to_u64() {
+        Some(n) => CompactAmount::U64(n),
+        None => CompactAmount::BigEndian(ByteBuf::from(n.0.to_bytes_be())),
+    };
+    compact.serialize(s)
+}
+
+fn de_compact_amount<'de, Tokens, D>(d: D) -> Result<Tokens, D::Error>
+where
+    Tokens: TokensType,
+    D: serde::de::Deserializer<'de>,
+{
+    use serde::de::Error;
+    let n = match CompactAmount::deserialize(d)? {
+        CompactAmount::U64(n) => Nat::from(n),
+        CompactAmount::BigEndian(bytes) => Nat(BigUint::from_bytes_be(&bytes)),
+    };
+    Tokens::try_from_nat(&n).map_err(D::Error::custom)
+}
+
+/// An operation on the ledger. The amounts have the token type of the ledger,
+/// see [TokensType].
 #[derive(Serialize, Deserialize, Clone, Hash, Debug, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(tag = "op")]
-pub enum Operation {
+pub enum Operation<Tokens: TokensType = ic_ledger_core::Tokens> {
     #[serde(rename = "mint")]
     Mint {
         #[serde(serialize_with = "ser_compact_account")]
         #[serde(deserialize_with = "de_compact_account")]
         to: Account,
         #[serde(rename = "amt")]
-        amount: u64,
+        #[serde(serialize_with = "ser_compact_amount")]
+        #[serde(deserialize_with = "de_compact_amount")]
+        amount: Tokens,
     },
     #[serde(rename = "xfer")]
     Transfer {
@@ -175,8 +238,12 @@ pub enum Operation {
         #[serde(deserialize_with = "de_compact_account")]
         to: Account,
         #[serde(rename = "amt")]
-        amount: u64,
-        fee: u64,
+        #[serde(serialize_with = "ser_compact_amount")]
+        #[serde(deserialize_with = "de_compact_amount")]
+        amount: Tokens,
+        #[serde(serialize_with = "ser_compact_amount")]
+        #[serde(deserialize_with = "de_compact_amount")]
+        fee: Tokens,
     },
     #[serde(rename = "burn")]
     Burn {
@@ -184,7 +251,26 @@ pub enum Operation {
         #[serde(deserialize_with = "de_compact_account")]
         from: Account,
         #[serde(rename = "amt")]
-        amount: u64,
+        #[serde(serialize_with = "ser_compact_amount")]
+        #[serde(deserialize_with = "de_compact_amount")]
+        amount: Tokens,
+    },
+    /// A change of the ledger configuration. Only the changed parameters
+    /// are set.
+    #[serde(rename = "upgrade")]
+    Upgrade {
+        #[serde(default)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(rename = "tx_window")]
+        transaction_window_nanos: Option<u64>,
+        #[serde(default)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(rename = "drift")]
+        permitted_drift_nanos: Option<u64>,
+        #[serde(default)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(rename = "max_memo")]
+        max_memo_length: Option<u16>,
     },
 }
 
@@ -196,7 +282,7 @@ impl fmt::Display for MemoTooLarge {
         write!(
             f,
             "Memo field is {} bytes long, max allowed length is {}",
-            self.0, MAX_MEMO_LENGTH
+            self.0, MAX_MEMO_LENGTH_LIMIT
         )
     }
 }
@@ -233,7 +319,7 @@ impl TryFrom<ByteBuf> for Memo {
     type Error = MemoTooLarge;
 
     fn try_from(b: ByteBuf) -> Result<Self, MemoTooLarge> {
-        if b.len() > MAX_MEMO_LENGTH {
+        if b.len() > MAX_MEMO_LENGTH_LIMIT {
             return Err(MemoTooLarge(b.len()));
         }
         Ok(Self(b))
@@ -254,10 +340,16 @@ impl From<Memo> for ByteBuf {
     }
 }
 
+impl AsRef<[u8]> for Memo {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Hash, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Transaction {
+pub struct Transaction<Tokens: TokensType = ic_ledger_core::Tokens> {
     #[serde(flatten)]
-    pub operation: Operation,
+    pub operation: Operation<Tokens>,
 
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -269,8 +361,9 @@ pub struct Transaction {
     pub memo: Option<Memo>,
 }
 
-impl LedgerTransaction for Transaction {
+impl<Tokens: TokensType> LedgerTransaction for Transaction<Tokens> {
     type AccountId = Account;
+    type Tokens = Tokens;
 
     fn burn(
         from: Account,
@@ -279,10 +372,7 @@ impl LedgerTransaction for Transaction {
         memo: Option<u64>,
     ) -> Self {
         Self {
-            operation: Operation::Burn {
-                from,
-                amount: amount.get_e8s(),
-            },
+            operation: Operation::Burn { from, amount },
             created_at_time: created_at_time.map(|t| t.as_nanos_since_unix_epoch()),
             memo: memo.map(Memo::from),
         }
@@ -308,9 +398,20 @@ impl LedgerTransaction for Transaction {
             })
     }
 
-    fn apply<S>(&self, balances: &mut Balances<Self::AccountId, S>) -> Result<(), BalanceError>
+    fn fee(&self) -> Option<Tokens> {
+        match &self.operation {
+            Operation::Transfer { fee, .. } => Some(*fee),
+            _ => None,
+        }
+    }
+
+    fn apply<S>(
+        &self,
+        balances: &mut Balances<Self::AccountId, S>,
+        fee_collector: Option<&Self::AccountId>,
+    ) -> Result<(), BalanceError<Tokens>>
     where
-        S: Default + BalancesStore<Self::AccountId>,
+        S: Default + BalancesStore<Self::AccountId, Tokens = Tokens>,
     {
         match &self.operation {
             Operation::Transfer {
@@ -318,14 +419,15 @@ impl LedgerTransaction for Transaction {
                 to,
                 amount,
                 fee,
-            } => balances.transfer(from, to, Tokens::from_e8s(*amount), Tokens::from_e8s(*fee)),
-            Operation::Burn { from, amount } => balances.burn(from, Tokens::from_e8s(*amount)),
-            Operation::Mint { to, amount } => balances.mint(to, Tokens::from_e8s(*amount)),
+            } => balances.transfer(from, to, *amount, *fee, fee_collector),
+            Operation::Burn { from, amount } => balances.burn(from, *amount),
+            Operation::Mint { to, amount } => balances.mint(to, *amount),
+            Operation::Upgrade { .. } => Ok(()),
         }
     }
 }
 
-impl Transaction {
+impl<Tokens: TokensType> Transaction<Tokens> {
     pub fn mint(
         to: Account,
         amount: Tokens,
@@ -333,10 +435,7 @@ impl Transaction {
         memo: Option<Memo>,
     ) -> Self {
         Self {
-            operation: Operation::Mint {
-                to,
-                amount: amount.get_e8s(),
-            },
+            operation: Operation::Mint { to, amount },
             created_at_time: created_at_time.map(|t| t.as_nanos_since_unix_epoch()),
             memo,
         }
@@ -354,43 +453,88 @@ impl Transaction {
             operation: Operation::Transfer {
                 from,
                 to,
-                amount: amount.get_e8s(),
-                fee: fee.get_e8s(),
+                amount,
+                fee,
             },
             created_at_time: created_at_time.map(|t| t.as_nanos_since_unix_epoch()),
             memo,
         }
     }
+
+    pub fn upgrade(
+        transaction_window_nanos: Option<u64>,
+        permitted_drift_nanos: Option<u64>,
+        max_memo_length: Option<u16>,
+    ) -> Self {
+        Self {
+            operation: Operation::Upgrade {
+                transaction_window_nanos,
+                permitted_drift_nanos,
+                max_memo_length,
+            },
+            created_at_time: None,
+            memo: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Hash, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Block {
+pub struct Block<Tokens: TokensType = ic_ledger_core::Tokens> {
     #[serde(rename = "phash")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub parent_hash: Option<HashOf<EncodedBlock>>,
     #[serde(rename = "tx")]
-    pub transaction: Transaction,
+    pub transaction: Transaction<Tokens>,
     #[serde(rename = "ts")]
     pub timestamp: u64,
+    /// The account that collected the fee of this block's transaction. Only
+    /// the first block that credited a fee to the fee collector after it was
+    /// set mentions the account.
+    #[serde(rename = "fee_col")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_collector: Option<Account>,
+    /// The index of the block that mentions the fee collector of this block.
+    #[serde(rename = "fee_col_block")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_collector_block_index: Option<u64>,
+    /// The version of the block schema, see `BLOCK_SCHEMA_VERSION`.
+    #[serde(rename = "ver")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_unversioned")]
+    pub schema_version: u32,
 }
 
-type TaggedBlock = Required<Block, 55799>;
+fn is_unversioned(schema_version: &u32) -> bool {
+    *schema_version == 0
+}
 
-impl BlockType for Block {
-    type Transaction = Transaction;
+type TaggedBlock<Tokens> = Required<Block<Tokens>, 55799>;
+
+impl<Tokens: TokensType> BlockType for Block<Tokens> {
+    type Transaction = Transaction<Tokens>;
+    type AccountId = Account;
 
     fn encode(self) -> EncodedBlock {
         let mut bytes = vec![];
-        let value: TaggedBlock = Required(self);
+        let value: TaggedBlock<Tokens> = Required(self);
         ciborium::ser::into_writer(&value, &mut bytes).expect("bug: failed to encode a block");
         EncodedBlock::from_vec(bytes)
     }
 
     fn decode(encoded_block: EncodedBlock) -> Result<Self, String> {
         let bytes = encoded_block.into_vec();
-        let tagged_block: TaggedBlock = ciborium::de::from_reader(&bytes[..])
+        let tagged_block: TaggedBlock<Tokens> = ciborium::de::from_reader(&bytes[..])
             .map_err(|e| format!("failed to decode a block: {}", e))?;
-        Ok(tagged_block.0)
+        let block = tagged_block.0;
+        if block.schema_version > MAX_SUPPORTED_BLOCK_SCHEMA_VERSION {
+            return Err(format!(
+                "block schema version {} is not supported, the latest supported version is {}",
+                block.schema_version, MAX_SUPPORTED_BLOCK_SCHEMA_VERSION
+            ));
+        }
+        Ok(block)
     }
 
     fn block_hash(encoded_block: &EncodedBlock) -> HashOf<EncodedBlock> {
@@ -413,15 +557,37 @@ impl BlockType for Block {
         TimeStamp::from_nanos_since_unix_epoch(self.timestamp)
     }
 
+    fn with_schema_version(self, schema_version: u32) -> Self {
+        Self {
+            schema_version,
+            ..self
+        }
+    }
+
     fn from_transaction(
         parent_hash: Option<HashOf<EncodedBlock>>,
         transaction: Self::Transaction,
         timestamp: TimeStamp,
+        fee_collector: Option<FeeCollector<Self::AccountId>>,
     ) -> Self {
+        let (fee_collector, fee_collector_block_index) = match fee_collector {
+            Some(FeeCollector {
+                fee_collector,
+                block_index: None,
+            }) => (Some(fee_collector), None),
+            Some(FeeCollector {
+                block_index: Some(block_index),
+                ..
+            }) => (None, Some(block_index)),
+            None => (None, None),
+        };
         Self {
             parent_hash,
             transaction,
             timestamp: timestamp.as_nanos_since_unix_epoch(),
+            fee_collector,
+            fee_collector_block_index,
+            schema_version: BLOCK_SCHEMA_VERSION,
         }
     }
 }
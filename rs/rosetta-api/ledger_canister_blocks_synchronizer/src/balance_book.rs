@@ -180,8 +180,12 @@ impl ClientBalancesStore {
 }
 
 impl BalancesStore<AccountIdentifier> for ClientBalancesStore {
-    fn get_balance(&self, k: &AccountIdentifier) -> Option<&Tokens> {
-        self.acc_to_hist.get(k).and_then(|hist| hist.get_last_ref())
+    type Tokens = Tokens;
+
+    fn get_balance(&self, k: &AccountIdentifier) -> Option<Tokens> {
+        self.acc_to_hist
+            .get(k)
+            .and_then(|hist| hist.get_last_ref().copied())
     }
 
     // In here, ledger removes zero amount accounts from it's map,
@@ -1,6 +1,7 @@
 use ic_ledger_core::block::{BlockIndex, BlockType, EncodedBlock, HashOf};
+use ic_types::CanisterId;
 use icp_ledger::{AccountIdentifier, Block, Tokens};
-use rusqlite::params;
+use rusqlite::{params, OpenFlags};
 use serde::{Deserialize, Serialize};
 use std::convert::TryInto;
 use std::path::Path;
@@ -322,7 +323,7 @@ mod database_access {
                 }
                 None => Ok(genesis_block.map_err(|e| BlockStoreError::Other(e.to_string()))?),
             },
-            None => Err(BlockStoreError::Other("Blockchain is empty".to_string())),
+            None => Err(BlockStoreError::Empty),
         }
     }
     // The option is left None if both verified and unverified blocks should be querried. It is set to False for only unverified blocks and True for only verified blocks
@@ -340,7 +341,7 @@ mod database_access {
             Some(first_block) => {
                 Ok(first_block.map_err(|e| BlockStoreError::Other(e.to_string()))?)
             }
-            None => Err(BlockStoreError::Other("Blockchain is empty".to_string())),
+            None => Err(BlockStoreError::Empty),
         }
     }
     pub fn get_account_balance(
@@ -602,6 +603,34 @@ mod database_access {
             None => Ok(false),
         }
     }
+
+    pub fn get_verification_info(
+        connection: &mut Connection,
+    ) -> Result<Option<(Vec<u8>, String)>, BlockStoreError> {
+        let mut stmt = connection
+            .prepare("SELECT root_key, canister_id FROM verification_info WHERE id = 0")
+            .map_err(|e| BlockStoreError::Other(e.to_string()))?;
+        let mut rows = stmt
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| BlockStoreError::Other(e.to_string()))?;
+        rows.next()
+            .transpose()
+            .map_err(|e| BlockStoreError::Other(e.to_string()))
+    }
+
+    pub fn set_verification_info(
+        connection: &mut Connection,
+        root_key: &[u8],
+        canister_id: &str,
+    ) -> Result<(), BlockStoreError> {
+        connection
+            .execute(
+                "INSERT INTO verification_info (id, root_key, canister_id) VALUES (0, ?, ?)",
+                params![root_key, canister_id],
+            )
+            .map_err(|e| BlockStoreError::Other(e.to_string()))?;
+        Ok(())
+    }
 }
 
 #[derive(candid::CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -631,6 +660,8 @@ impl HashedBlock {
 pub enum BlockStoreError {
     NotFound(BlockIndex),
     NotAvailable(BlockIndex),
+    /// The store has no block matching the query, e.g. no verified block.
+    Empty,
     Other(String),
 }
 
@@ -642,6 +673,9 @@ fn vec_into_array(v: Vec<u8>) -> [u8; 32] {
     *ba
 }
 
+/// The default memory budget of a store created with [Blocks::new_in_memory].
+pub const DEFAULT_IN_MEMORY_BUDGET_BYTES: u64 = 256 * 1024 * 1024;
+
 pub struct Blocks {
     connection: Mutex<rusqlite::Connection>,
 }
@@ -656,10 +690,45 @@ impl Blocks {
         Self::new(connection)
     }
 
-    /// Constructs a new SQLite in-memory store.
+    /// Opens the existing store at `location` for reading only, e.g. to copy
+    /// the blocks of another node's store. Unlike [Self::new_persistent], it
+    /// neither creates nor repairs the database.
+    pub fn open_read_only(location: &Path) -> Result<Self, BlockStoreError> {
+        let path = location.join("db.sqlite");
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY;
+        let connection = rusqlite::Connection::open_with_flags(&path, flags)
+            .map_err(|e| BlockStoreError::Other(format!("Cannot open {}: {}", path.display(), e)))?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Constructs a new SQLite in-memory store that uses up to
+    /// [DEFAULT_IN_MEMORY_BUDGET_BYTES] of memory.
     pub fn new_in_memory() -> Result<Self, BlockStoreError> {
-        let connection = rusqlite::Connection::open_in_memory()
-            .expect("Unable to open SQLite in-memory database connection");
+        Self::new_in_memory_with_budget(DEFAULT_IN_MEMORY_BUDGET_BYTES)
+    }
+
+    /// Constructs a new SQLite store that keeps up to `max_memory_bytes` of
+    /// its pages in memory.
+    ///
+    /// The store is a private temporary database: SQLite holds it in its page
+    /// cache and, once the cache exceeds the budget, spills the least recently
+    /// used pages (in practice, the older blocks) to a temporary file that is
+    /// deleted when the store is dropped. Small stores thus never touch the
+    /// disk, while big ones no longer run out of memory.
+    pub fn new_in_memory_with_budget(max_memory_bytes: u64) -> Result<Self, BlockStoreError> {
+        // An empty path opens a private temporary database.
+        let connection = rusqlite::Connection::open("")
+            .expect("Unable to open SQLite temporary database connection");
+        // A negative cache size is a limit in KiB rather than in pages.
+        let cache_size_kib = (max_memory_bytes / 1024).max(1);
+        connection
+            .execute_batch(&format!(
+                "PRAGMA cache_size = -{}; PRAGMA temp_store = FILE;",
+                cache_size_kib
+            ))
+            .map_err(|e| BlockStoreError::Other(e.to_string()))?;
         Self::new(connection)
     }
 
@@ -721,6 +790,16 @@ impl Blocks {
             "#,
             [],
         )?;
+        connection.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS verification_info (
+                id INTEGER NOT NULL PRIMARY KEY CHECK (id = 0),
+                root_key BLOB NOT NULL,
+                canister_id VARCHAR NOT NULL
+            )
+            "#,
+            [],
+        )?;
 
         Ok(())
     }
@@ -939,6 +1018,56 @@ impl Blocks {
         let mut connection = self.connection.lock().unwrap();
         database_access::get_latest_hashed_block(&mut connection, Some(true))
     }
+
+    /// Pins the store to the root key and ledger canister the blocks are
+    /// verified against. The first call records them; later calls, including
+    /// after reopening the store, fail if they differ from the recorded ones
+    /// so that blocks from different networks are never mixed in one store.
+    pub fn pin_verification_info(
+        &self,
+        root_key: &[u8],
+        canister_id: &CanisterId,
+    ) -> Result<(), BlockStoreError> {
+        let mut connection = self.connection.lock().unwrap();
+        let canister_id = canister_id.to_string();
+        match database_access::get_verification_info(&mut connection)? {
+            None => database_access::set_verification_info(&mut connection, root_key, &canister_id),
+            Some((stored_root_key, stored_canister_id)) => {
+                if stored_canister_id != canister_id {
+                    return Err(BlockStoreError::Other(format!(
+                        "The store contains blocks of ledger canister {} but the ledger canister is {}",
+                        stored_canister_id, canister_id
+                    )));
+                }
+                if stored_root_key != root_key {
+                    return Err(BlockStoreError::Other(
+                        "The store contains blocks verified with a different root key".to_string(),
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the range of indices of the blocks in the store that are not
+    /// verified yet. The range is empty if all the blocks are verified.
+    pub fn verified_gap(&self) -> Result<std::ops::Range<BlockIndex>, BlockStoreError> {
+        let mut connection = self.connection.lock().unwrap();
+        let latest = match database_access::get_latest_hashed_block(&mut connection, None) {
+            Ok(hb) => hb.index,
+            Err(BlockStoreError::Empty) => return Ok(0..0),
+            Err(err) => return Err(err),
+        };
+        let start = match database_access::get_latest_hashed_block(&mut connection, Some(true)) {
+            Ok(verified) => verified.index + 1,
+            // no block is verified yet
+            Err(BlockStoreError::Empty) => {
+                database_access::get_first_hashed_block(&mut connection, Some(false))?.index
+            }
+            Err(err) => return Err(err),
+        };
+        Ok(start..latest + 1)
+    }
     pub fn get_account_balance(
         &self,
         account: &AccountIdentifier,
@@ -1141,4 +1270,98 @@ impl Blocks {
             }
         }
     }
+
+    /// Marks the blocks in `range` as verified, leaving the blocks before
+    /// `range` unverified.
+    pub fn set_hashed_block_range_to_verified(
+        &self,
+        range: std::ops::Range<BlockIndex>,
+    ) -> Result<(), BlockStoreError> {
+        let connection = self.connection.lock().unwrap();
+        let mut stmt = connection
+            .prepare("UPDATE blocks SET verified = TRUE WHERE idx >= ?1 AND idx < ?2")
+            .map_err(|e| BlockStoreError::Other(e.to_string()))?;
+        stmt.execute(params![range.start, range.end])
+            .map_err(|e| BlockStoreError::Other(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Removes all the blocks, together with their transactions and the
+    /// account balances. The verification info is kept.
+    pub fn clear(&mut self) -> Result<(), BlockStoreError> {
+        let connection = self.connection.lock().unwrap();
+        connection
+            .execute_batch(
+                "BEGIN TRANSACTION;
+                DELETE FROM account_balances;
+                DELETE FROM transactions;
+                DELETE FROM blocks;
+                COMMIT TRANSACTION;",
+            )
+            .map_err(|e| BlockStoreError::Other(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::dummy_blocks;
+
+    fn store_with_blocks(max_memory_bytes: u64, n: usize) -> Blocks {
+        let mut store = Blocks::new_in_memory_with_budget(max_memory_bytes).unwrap();
+        let mut parent_hash = None;
+        let mut batch = vec![];
+        for (idx, eb) in dummy_blocks(n).into_iter().enumerate() {
+            let hb = HashedBlock::hash_block(eb, parent_hash, idx as u64);
+            parent_hash = Some(hb.hash);
+            batch.push(hb);
+        }
+        store.push_batch(batch).unwrap();
+        store
+    }
+
+    /// Returns the number of bytes of memory used by the page cache of
+    /// `store` and the size of its database in bytes.
+    fn cache_used_and_db_size(store: &Blocks) -> (u64, u64) {
+        let connection = store.connection.lock().unwrap();
+        let (mut used, mut highwater) = (0, 0);
+        // SAFETY: the handle stays valid while the connection is locked.
+        let rc = unsafe {
+            rusqlite::ffi::sqlite3_db_status(
+                connection.handle(),
+                rusqlite::ffi::SQLITE_DBSTATUS_CACHE_USED,
+                &mut used,
+                &mut highwater,
+                0,
+            )
+        };
+        assert_eq!(rc, rusqlite::ffi::SQLITE_OK);
+        let pragma = |name: &str| {
+            connection
+                .query_row(&format!("PRAGMA {}", name), [], |row| row.get::<_, i64>(0))
+                .unwrap() as u64
+        };
+        (used as u64, pragma("page_count") * pragma("page_size"))
+    }
+
+    #[test]
+    fn in_memory_store_spills_beyond_the_budget() {
+        const BUDGET: u64 = 128 * 1024;
+        const BLOCKS: usize = 5_000;
+
+        let store = store_with_blocks(BUDGET, BLOCKS);
+        let (cache_used, db_size) = cache_used_and_db_size(&store);
+        assert!(db_size > 8 * BUDGET, "database of {} bytes", db_size);
+        // The page cache holds the budget plus a small bookkeeping overhead
+        // per page, the other pages live in the temporary file.
+        assert!(cache_used <= 2 * BUDGET, "cache of {} bytes", cache_used);
+        let latest = store.get_latest_hashed_block().unwrap();
+        assert_eq!(latest.index, BLOCKS as u64 - 1);
+        assert_eq!(store.get_hashed_block(&0).unwrap().index, 0);
+
+        // Without a tight budget, the whole database stays in memory.
+        let store = store_with_blocks(DEFAULT_IN_MEMORY_BUDGET_BYTES, BLOCKS);
+        let (cache_used, _) = cache_used_and_db_size(&store);
+        assert!(cache_used > 2 * BUDGET, "cache of {} bytes", cache_used);
+    }
 }
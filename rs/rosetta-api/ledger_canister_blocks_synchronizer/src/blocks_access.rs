@@ -2,7 +2,9 @@ use std::ops::Range;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use ic_icrc1::icrc3::DataCertificate;
 use ic_ledger_core::block::{BlockIndex, EncodedBlock};
+use ic_types::CanisterId;
 use icp_ledger::TipOfChainRes;
 
 use crate::canister_access::CanisterAccess;
@@ -12,6 +14,10 @@ use crate::canister_access::CanisterAccess;
 pub trait BlocksAccess {
     async fn query_raw_block(&self, height: BlockIndex) -> Result<Option<EncodedBlock>, String>;
     async fn query_tip(&self) -> Result<TipOfChainRes, String>;
+    async fn query_tip_certificate(&self) -> Result<Option<DataCertificate>, String>;
+    /// Sets the archive canisters and their block ranges as certified by the
+    /// ledger. Blocks are then fetched from these archives only.
+    async fn set_certified_archives(&self, archives: Vec<((BlockIndex, BlockIndex), CanisterId)>);
     async fn multi_query_blocks(
         self: Arc<Self>,
         range: Range<BlockIndex>,
@@ -28,6 +34,14 @@ impl BlocksAccess for CanisterAccess {
         self.query_tip().await
     }
 
+    async fn query_tip_certificate(&self) -> Result<Option<DataCertificate>, String> {
+        self.query_tip_certificate().await
+    }
+
+    async fn set_certified_archives(&self, archives: Vec<((BlockIndex, BlockIndex), CanisterId)>) {
+        self.set_certified_archives(archives).await
+    }
+
     async fn multi_query_blocks(
         self: Arc<Self>,
         range: Range<BlockIndex>,
@@ -1,7 +1,9 @@
+use candid::{Decode, Encode};
 use dfn_protobuf::{ProtoBuf, ToProto};
 use ic_agent::agent::http_transport::ReqwestHttpReplicaV2Transport;
 use ic_agent::identity::AnonymousIdentity;
 use ic_agent::{Agent, AgentError, NonceGenerator};
+use ic_icrc1::icrc3::DataCertificate;
 use ic_ledger_core::block::EncodedBlock;
 use ic_types::CanisterId;
 use icp_ledger::protobuf::{ArchiveIndexEntry, ArchiveIndexResponse, TipOfChainRequest};
@@ -10,10 +12,121 @@ use log::{debug, trace, warn};
 use on_wire::{FromWire, IntoWire};
 use std::collections::VecDeque;
 use std::convert::TryFrom;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::task::{spawn, JoinHandle};
 use url::Url;
 
+use crate::rate_limiter::RateLimiter;
+
+/// The timeout applied to a single query if none is configured.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The number of blocks asked for in a single query if none is configured.
+pub const DEFAULT_BLOCKS_BATCH_LEN: u64 = 2000;
+
+/// The maximum size of a query response. The ledger and the archives
+/// truncate the blocks they return to fit in it.
+const MAX_RESPONSE_BYTES: u64 = 2 * 1024 * 1024;
+
+/// The response size the batch length is adjusted to, leaving headroom for
+/// blocks larger than the ones seen so far.
+const TARGET_RESPONSE_BYTES: u64 = MAX_RESPONSE_BYTES * 3 / 4;
+
+/// Returns the number of blocks to ask for in the next `get_blocks` query,
+/// given that the last one returned `received` blocks in a response of
+/// `response_bytes` bytes.
+///
+/// The batch length shrinks right away to what fits in
+/// [TARGET_RESPONSE_BYTES] and grows back at most twofold per response, and
+/// is always between 1 and `max_batch_len`.
+fn next_batch_len(current: u64, max_batch_len: u64, received: u64, response_bytes: u64) -> u64 {
+    if received == 0 || response_bytes == 0 {
+        return current;
+    }
+    let bytes_per_block = (response_bytes + received - 1) / received;
+    let fitting = TARGET_RESPONSE_BYTES / bytes_per_block;
+    let next = if fitting < current {
+        fitting
+    } else {
+        fitting.min(current.saturating_mul(2))
+    };
+    next.clamp(1, max_batch_len)
+}
+
+/// How long to stop querying when the replica answers 429 or 503.
+const OVERLOAD_BACKOFF: Duration = Duration::from_secs(5);
+
+/// How often to poll for the response to an update call.
+const UPDATE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Returns true if the replica or boundary node rejected the request because
+/// it is overloaded or is rate limiting us.
+fn is_overloaded(e: &AgentError) -> bool {
+    matches!(e, AgentError::HttpError(payload) if payload.status == 429 || payload.status == 503)
+}
+
+/// Runs `op` with the endpoint at index `active` and, if it fails or times
+/// out, with each of the other endpoints in turn. The endpoint that succeeds
+/// is stored in `active`, so that the next call starts with it.
+async fn failover<'a, E, T, F, Fut>(
+    endpoints: &'a [(Url, E)],
+    active: &AtomicUsize,
+    request_timeout: Duration,
+    rate_limiter: Option<&RateLimiter>,
+    method: &str,
+    op: F,
+) -> Result<T, String>
+where
+    F: Fn(&'a E) -> Fut,
+    Fut: Future<Output = Result<T, AgentError>>,
+{
+    let first = active.load(Ordering::Relaxed);
+    let mut last_error = String::new();
+    for attempt in 0..endpoints.len() {
+        let idx = (first + attempt) % endpoints.len();
+        let (url, endpoint) = &endpoints[idx];
+        if let Some(rate_limiter) = rate_limiter {
+            rate_limiter.acquire().await;
+        }
+        match tokio::time::timeout(request_timeout, op(endpoint)).await {
+            Ok(Ok(res)) => {
+                if idx != first {
+                    debug!("Switching canister access to {}", url);
+                    active.store(idx, Ordering::Relaxed);
+                }
+                return Ok(res);
+            }
+            Ok(Err(e)) => {
+                if is_overloaded(&e) {
+                    warn!(
+                        "Replica at {} is overloaded, pausing queries for {:?}",
+                        url, OVERLOAD_BACKOFF
+                    );
+                    if let Some(rate_limiter) = rate_limiter {
+                        rate_limiter.pause(OVERLOAD_BACKOFF).await;
+                    } else {
+                        tokio::time::sleep(OVERLOAD_BACKOFF).await;
+                    }
+                }
+                last_error = format!("{}", e)
+            }
+            Err(_) => {
+                last_error = format!(
+                    "Query {} to {} timed out after {:?}",
+                    method, url, request_timeout
+                )
+            }
+        }
+        if endpoints.len() > 1 {
+            warn!("Query {} to {} failed: {}", method, url, last_error);
+        }
+    }
+    Err(last_error)
+}
+
 #[derive(Default)]
 pub struct TimestampBlob {}
 impl NonceGenerator for TimestampBlob {
@@ -28,10 +141,63 @@ impl NonceGenerator for TimestampBlob {
     }
 }
 
+/// Connection settings used to build a [`CanisterAccess`].
+#[derive(Clone, Debug)]
+pub struct CanisterAccessConfig {
+    /// The URLs of the replicas or boundary nodes to query. The first one is
+    /// the primary, the others are tried in order if a query fails.
+    pub urls: Vec<Url>,
+    pub canister_id: CanisterId,
+    /// The DER encoded root key. If it is not set then the key is fetched
+    /// from the primary replica, which must only be done on testnets.
+    pub root_key: Option<Vec<u8>>,
+    /// The maximum time a single query may take before it is abandoned and
+    /// the next URL is tried.
+    pub request_timeout: Duration,
+    /// The maximum number of queries per second sent to the replicas, or
+    /// None for no limit. Bursts of up to `max_concurrent_block_queries`
+    /// queries are allowed.
+    pub max_queries_per_second: Option<u32>,
+    /// The maximum number of blocks asked for in a single `get_blocks`
+    /// query. The actual number is lowered if the responses get close to the
+    /// response size limit.
+    pub blocks_batch_len: u64,
+    /// The maximum number of `get_blocks` queries in flight at once.
+    pub max_concurrent_block_queries: usize,
+}
+
+impl CanisterAccessConfig {
+    pub fn new(url: Url, canister_id: CanisterId) -> Self {
+        Self {
+            urls: vec![url],
+            canister_id,
+            root_key: None,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            max_queries_per_second: None,
+            blocks_batch_len: DEFAULT_BLOCKS_BATCH_LEN,
+            max_concurrent_block_queries: 5,
+        }
+    }
+}
+
 pub struct CanisterAccess {
-    pub agent: Agent,
     pub canister_id: CanisterId,
+    /// All the agents, in failover order. The first one is connected to the
+    /// primary URL.
+    agents: Vec<(Url, Agent)>,
+    /// The index in `agents` of the agent that answered the last query.
+    active_agent: AtomicUsize,
+    request_timeout: Duration,
+    rate_limiter: Option<RateLimiter>,
+    blocks_batch_len: u64,
+    /// The number of blocks currently asked for in a single `get_blocks`
+    /// query, adjusted after every response.
+    current_blocks_batch_len: AtomicU64,
+    max_concurrent_block_queries: usize,
     archive_list: Arc<tokio::sync::Mutex<Option<ArchiveIndexResponse>>>,
+    /// True if `archive_list` holds the archives certified by the ledger
+    /// rather than the response of `get_archive_index_pb`.
+    archive_list_certified: AtomicBool,
     #[allow(clippy::type_complexity)]
     ongoing_block_queries: tokio::sync::Mutex<
         VecDeque<(
@@ -43,33 +209,73 @@ pub struct CanisterAccess {
 }
 
 impl CanisterAccess {
-    const BLOCKS_BATCH_LEN: u64 = 2000;
-    const MAX_BLOCK_QUERIES: usize = 5;
-
     pub async fn new(
         url: Url,
         canister_id: CanisterId,
         root_key: Option<Vec<u8>>,
     ) -> Result<Self, AgentError> {
-        let agent = Agent::builder()
-            .with_identity(AnonymousIdentity)
-            .with_transport(ReqwestHttpReplicaV2Transport::create(url)?)
-            .with_nonce_generator(TimestampBlob::default())
-            .build()
-            .unwrap();
-
-        match root_key {
-            Some(root_key) => agent.set_root_key(root_key)?,
-            None => {
-                warn!("Fetching the root key from the replica because it was not set");
-                agent.fetch_root_key().await?
-            }
-        };
+        Self::new_with_config(CanisterAccessConfig {
+            root_key,
+            ..CanisterAccessConfig::new(url, canister_id)
+        })
+        .await
+    }
+
+    pub async fn new_with_config(config: CanisterAccessConfig) -> Result<Self, AgentError> {
+        if config.urls.is_empty() {
+            return Err(AgentError::MessageError(
+                "At least one URL is required to access the canister".to_string(),
+            ));
+        }
+        if config.blocks_batch_len == 0 || config.max_concurrent_block_queries == 0 {
+            return Err(AgentError::MessageError(
+                "The blocks batch length and number of concurrent queries must be positive"
+                    .to_string(),
+            ));
+        }
+        if config.max_queries_per_second == Some(0) {
+            return Err(AgentError::MessageError(
+                "The maximum number of queries per second must be positive".to_string(),
+            ));
+        }
+
+        let mut root_key = config.root_key;
+        let mut agents = Vec::with_capacity(config.urls.len());
+        for url in config.urls {
+            let agent = Agent::builder()
+                .with_identity(AnonymousIdentity)
+                .with_transport(ReqwestHttpReplicaV2Transport::create(url.clone())?)
+                .with_nonce_generator(TimestampBlob::default())
+                .build()
+                .unwrap();
+
+            match &root_key {
+                Some(root_key) => agent.set_root_key(root_key.clone())?,
+                None => {
+                    // Fetch the key once, from the primary, and pin it for all
+                    // the other agents so that every URL is verified against
+                    // the same key.
+                    warn!("Fetching the root key from the replica because it was not set");
+                    agent.fetch_root_key().await?;
+                    root_key = Some(agent.read_root_key()?);
+                }
+            };
+            agents.push((url, agent));
+        }
 
         Ok(Self {
-            agent,
-            canister_id,
+            canister_id: config.canister_id,
+            agents,
+            active_agent: AtomicUsize::new(0),
+            request_timeout: config.request_timeout,
+            rate_limiter: config.max_queries_per_second.map(|qps| {
+                RateLimiter::new(qps, config.max_concurrent_block_queries as u32)
+            }),
+            blocks_batch_len: config.blocks_batch_len,
+            current_blocks_batch_len: AtomicU64::new(config.blocks_batch_len),
+            max_concurrent_block_queries: config.max_concurrent_block_queries,
             archive_list: Arc::new(tokio::sync::Mutex::new(None)),
+            archive_list_certified: AtomicBool::new(false),
             ongoing_block_queries: Default::default(),
         })
     }
@@ -89,22 +295,111 @@ impl CanisterAccess {
         payload: Payload,
     ) -> Result<Res, String> {
         let arg = ProtoBuf(payload).into_bytes()?;
-        let bytes = self
-            .agent
-            .query(&canister_id.get().0, method)
-            .with_arg(arg)
-            .call()
-            .await
-            .map_err(|e| format!("{}", e))?;
+        let bytes = self.query_with_failover(canister_id, method, arg).await?;
         ProtoBuf::from_bytes(bytes).map(|c| c.0)
     }
 
+    /// Returns the number of blocks currently asked for in a single
+    /// `get_blocks` query.
+    pub fn blocks_batch_len(&self) -> u64 {
+        self.current_blocks_batch_len.load(Ordering::Relaxed)
+    }
+
+    /// Sends the query to the agent that answered last and, if it fails or
+    /// times out, to each of the other agents in turn.
+    pub async fn query_with_failover(
+        &self,
+        canister_id: CanisterId,
+        method: &str,
+        arg: Vec<u8>,
+    ) -> Result<Vec<u8>, String> {
+        failover(
+            &self.agents,
+            &self.active_agent,
+            self.request_timeout,
+            self.rate_limiter.as_ref(),
+            method,
+            |agent| {
+                agent
+                    .query(&canister_id.get().0, method)
+                    .with_arg(arg.clone())
+                    .call()
+            },
+        )
+        .await
+    }
+
+    /// Same as `query_with_failover()`, but sends an update call and waits
+    /// for its certified response. The call may be executed by several
+    /// replicas if an earlier one timed out, so it must only be used for
+    /// methods without side effects.
+    pub async fn update_with_failover(
+        &self,
+        canister_id: CanisterId,
+        method: &str,
+        arg: Vec<u8>,
+    ) -> Result<Vec<u8>, String> {
+        let request_timeout = self.request_timeout;
+        failover(
+            &self.agents,
+            &self.active_agent,
+            request_timeout,
+            self.rate_limiter.as_ref(),
+            method,
+            |agent| {
+                agent
+                    .update(&canister_id.get().0, method)
+                    .with_arg(arg.clone())
+                    .call_and_wait(
+                        garcon::Delay::builder()
+                            .throttle(UPDATE_POLL_INTERVAL)
+                            .timeout(request_timeout)
+                            .build(),
+                    )
+            },
+        )
+        .await
+    }
+
     pub async fn query_tip(&self) -> Result<TipOfChainRes, String> {
         self.query("tip_of_chain_pb", TipOfChainRequest {})
             .await
             .map_err(|e| format!("In tip: {}", e))
     }
 
+    /// Returns the certificate of the tip of the chain and of the archive
+    /// ranges, see [icp_ledger::certified_tip].
+    pub async fn query_tip_certificate(&self) -> Result<Option<DataCertificate>, String> {
+        let arg = Encode!().map_err(|e| format!("In tip certificate: {}", e))?;
+        let bytes = self
+            .query_with_failover(self.canister_id, "icrc3_get_tip_certificate", arg)
+            .await
+            .map_err(|e| format!("In tip certificate: {}", e))?;
+        Decode!(&bytes, Option<DataCertificate>).map_err(|e| format!("In tip certificate: {}", e))
+    }
+
+    /// Replaces the archive list with the archives certified by the ledger.
+    /// From then on blocks are only fetched from these archives and the
+    /// uncertified `get_archive_index_pb` is no longer queried.
+    pub async fn set_certified_archives(
+        &self,
+        archives: Vec<((BlockIndex, BlockIndex), CanisterId)>,
+    ) {
+        let entries = archives
+            .into_iter()
+            .map(|((height_from, height_to), canister_id)| ArchiveIndexEntry {
+                height_from,
+                height_to,
+                canister_id: Some(canister_id.get()),
+            })
+            .collect();
+        *self.archive_list.lock().await = Some(ArchiveIndexResponse {
+            entries,
+            deprecation_warning: None,
+        });
+        self.archive_list_certified.store(true, Ordering::Relaxed);
+    }
+
     pub async fn query_raw_block(
         &self,
         height: BlockIndex,
@@ -136,19 +431,40 @@ impl CanisterAccess {
         start: BlockIndex,
         end: BlockIndex,
     ) -> Result<Vec<EncodedBlock>, String> {
-        let blocks: GetBlocksRes = self
-            .query_canister(
-                can_id,
-                "get_blocks_pb",
-                GetBlocksArgs {
-                    start,
-                    length: (end - start) as usize,
-                },
-            )
+        let arg = ProtoBuf(GetBlocksArgs {
+            start,
+            length: (end - start) as usize,
+        })
+        .into_bytes()
+        .map_err(|e| format!("In blocks: {}", e))?;
+        let bytes = self
+            .query_with_failover(can_id, "get_blocks_pb", arg)
             .await
             .map_err(|e| format!("In blocks: {}", e))?;
+        let response_bytes = bytes.len() as u64;
+        let blocks: GetBlocksRes = ProtoBuf::from_bytes(bytes)
+            .map(|c| c.0)
+            .map_err(|e| format!("In blocks: {}", e))?;
+        let blocks = blocks.0.map_err(|e| format!("In blocks response: {}", e))?;
 
-        blocks.0.map_err(|e| format!("In blocks response: {}", e))
+        let current = self.blocks_batch_len();
+        let next = next_batch_len(
+            current,
+            self.blocks_batch_len,
+            blocks.len() as u64,
+            response_bytes,
+        );
+        if next != current {
+            debug!(
+                "Adjusting the blocks batch length from {} to {} after receiving {} blocks in {} bytes",
+                current,
+                next,
+                blocks.len(),
+                response_bytes
+            );
+            self.current_blocks_batch_len.store(next, Ordering::Relaxed);
+        }
+        Ok(blocks)
     }
 
     pub async fn clear_outstanding_queries(&self) {
@@ -181,8 +497,9 @@ impl CanisterAccess {
         let (a, b, jh) = {
             // schedule queries
             let mut qstart = ongoing.back().map(|(_, b, _)| *b).unwrap_or(start);
-            while ongoing.len() < Self::MAX_BLOCK_QUERIES && qstart < end {
-                let qend = (qstart + Self::BLOCKS_BATCH_LEN).min(end);
+            let batch_len = self.blocks_batch_len();
+            while ongoing.len() < self.max_concurrent_block_queries && qstart < end {
+                let qend = (qstart + batch_len).min(end);
                 let slf = self.clone();
                 let jh = spawn(async move { slf.query_blocks(qstart, qend).await });
                 ongoing.push_back((qstart, qend, jh));
@@ -213,7 +530,7 @@ impl CanisterAccess {
     ) -> Result<Vec<EncodedBlock>, String> {
         // asking for a low number of blocks means we are close to the tip
         // so we can try fetching from ledger first
-        if end - start < Self::BLOCKS_BATCH_LEN {
+        if end - start < self.blocks_batch_len() {
             let blocks = self.call_query_blocks(self.canister_id, start, end).await;
             if blocks.is_ok() {
                 return blocks;
@@ -245,7 +562,9 @@ impl CanisterAccess {
         {
             let mut alist = self.archive_list.lock().await;
             archive_entry = locate_archive(&alist, start);
-            if archive_entry.is_none() {
+            // The certified archives cover all the blocks that the ledger
+            // doesn't serve, so there is nothing to look up.
+            if archive_entry.is_none() && !self.archive_list_certified.load(Ordering::Relaxed) {
                 let al: ArchiveIndexResponse = self
                     .query("get_archive_index_pb", ())
                     .await
@@ -272,3 +591,78 @@ impl CanisterAccess {
         self.call_query_blocks(can_id, start, end).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU8;
+
+    #[test]
+    fn next_batch_len_test() {
+        // Small blocks: the batch length is capped by the configured maximum.
+        assert_eq!(next_batch_len(2000, 2000, 2000, 2000 * 200), 2000);
+        // Blocks too large for the current batch length: shrink right away.
+        let fitting = TARGET_RESPONSE_BYTES / 4096;
+        assert_eq!(next_batch_len(2000, 2000, 512, 512 * 4096), fitting);
+        // Smaller blocks again: grow back at most twofold.
+        assert_eq!(next_batch_len(100, 2000, 100, 100 * 200), 200);
+        // Huge blocks: never go below one block per query.
+        assert_eq!(next_batch_len(10, 2000, 1, MAX_RESPONSE_BYTES), 1);
+        // Empty responses don't tell anything about the block size.
+        assert_eq!(next_batch_len(10, 2000, 0, 0), 10);
+    }
+
+    // The states of the fake endpoints in `failover_test`.
+    const UP: u8 = 0;
+    const DOWN: u8 = 1;
+    const HANGING: u8 = 2;
+
+    async fn call(
+        endpoints: &[(Url, (usize, AtomicU8))],
+        active: &AtomicUsize,
+    ) -> Result<usize, String> {
+        let timeout = Duration::from_millis(10);
+        failover(endpoints, active, timeout, None, "test", |(i, state)| async move {
+            match state.load(Ordering::Relaxed) {
+                UP => Ok(*i),
+                DOWN => Err(AgentError::MessageError(format!("endpoint {} is down", i))),
+                _ => std::future::pending().await,
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn failover_test() {
+        let endpoints: Vec<(Url, (usize, AtomicU8))> = (0..3)
+            .map(|i| {
+                let url = Url::parse(&format!("http://replica-{}", i)).unwrap();
+                (url, (i, AtomicU8::new(UP)))
+            })
+            .collect();
+        let set = |i: usize, state: u8| endpoints[i].1 .1.store(state, Ordering::Relaxed);
+        let active = AtomicUsize::new(0);
+
+        // The primary answers while it is up.
+        assert_eq!(call(&endpoints, &active).await, Ok(0));
+
+        // The next endpoint answers when the primary is down, and keeps
+        // answering after the primary is back.
+        set(0, DOWN);
+        assert_eq!(call(&endpoints, &active).await, Ok(1));
+        set(0, UP);
+        assert_eq!(call(&endpoints, &active).await, Ok(1));
+
+        // An endpoint that doesn't respond times out.
+        set(1, HANGING);
+        assert_eq!(call(&endpoints, &active).await, Ok(2));
+
+        // The last error is returned if all endpoints fail, and the active
+        // endpoint doesn't change.
+        set(0, DOWN);
+        set(2, DOWN);
+        let err = call(&endpoints, &active).await.unwrap_err();
+        assert!(err.contains("timed out"), "unexpected error: {}", err);
+        assert_eq!(active.load(Ordering::Relaxed), 2);
+    }
+}
@@ -1,12 +1,40 @@
 use ic_certification::verify_certified_data;
+use ic_crypto_tree_hash::MixedHashTree;
+use ic_icrc1::icrc3::DataCertificate;
 use ic_ledger_core::block::{EncodedBlock, HashOf};
 use ic_types::{crypto::threshold_sig::ThresholdSigPublicKey, CanisterId};
+use icp_ledger::CertifiedTip;
 
 pub struct VerificationInfo {
     pub root_key: ThresholdSigPublicKey,
     pub canister_id: CanisterId,
 }
 
+/// Verifies the certificate of the ledger state and returns the certified tip
+/// of the chain, including the archive canisters that serve the older blocks.
+pub fn verify_tip_certificate(
+    cert: &Option<DataCertificate>,
+    info: &VerificationInfo,
+) -> Result<CertifiedTip, String> {
+    let cert = cert
+        .as_ref()
+        .ok_or("verify tip failed: no data certificate present")?;
+    let hash_tree: MixedHashTree = ciborium::de::from_reader(&cert.hash_tree[..])
+        .map_err(|e| format!("verify tip failed: cannot decode the hash tree: {}", e))?;
+    verify_certified_data(
+        &cert.certificate,
+        &info.canister_id,
+        &info.root_key,
+        &hash_tree.digest().0,
+    )
+    .map_err(|e| format!("Certification error: {:?}", e))?;
+    CertifiedTip::from_hash_tree(hash_tree)
+        .map_err(|e| format!("verify tip failed: invalid hash tree: {}", e))
+}
+
+/// Verifies the certificate returned by `tip_of_chain_pb` of a ledger that
+/// certifies only the hash of the tip, i.e., one that predates
+/// `icrc3_get_tip_certificate`.
 pub(crate) fn verify_block_hash(
     cert: &icp_ledger::Certification,
     hash: HashOf<EncodedBlock>,
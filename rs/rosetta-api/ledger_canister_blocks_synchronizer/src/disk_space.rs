@@ -0,0 +1,65 @@
+use std::path::{Path, PathBuf};
+
+use crate::errors::Error;
+
+/// Reports the number of bytes available to unprivileged users on the file
+/// system holding `path`.
+pub trait AvailableSpace: Send + Sync {
+    fn available_bytes(&self, path: &Path) -> Result<u64, String>;
+}
+
+/// Queries the file system with `statvfs`.
+pub struct Statvfs;
+
+impl AvailableSpace for Statvfs {
+    fn available_bytes(&self, path: &Path) -> Result<u64, String> {
+        let stat = nix::sys::statvfs::statvfs(path)
+            .map_err(|e| format!("Failed to query the free space of {}: {}", path.display(), e))?;
+        Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+    }
+}
+
+/// Watches the free space of the file system holding the block store.
+///
+/// The synchronizer checks the watchdog before every write and stops syncing
+/// while less than `min_available_bytes` are available, so that a write
+/// never fails half-way through a batch because the disk is full.
+pub struct DiskSpaceWatchdog {
+    location: PathBuf,
+    min_available_bytes: u64,
+    available_space: Box<dyn AvailableSpace>,
+}
+
+impl DiskSpaceWatchdog {
+    pub fn new(location: &Path, min_available_bytes: u64) -> Self {
+        Self::with_available_space(location, min_available_bytes, Box::new(Statvfs))
+    }
+
+    pub fn with_available_space(
+        location: &Path,
+        min_available_bytes: u64,
+        available_space: Box<dyn AvailableSpace>,
+    ) -> Self {
+        Self {
+            location: location.to_path_buf(),
+            min_available_bytes,
+            available_space,
+        }
+    }
+
+    /// Returns the number of available bytes, or
+    /// [Error::InsufficientDiskSpace] if it is below the minimum.
+    pub fn check(&self) -> Result<u64, Error> {
+        let available = self
+            .available_space
+            .available_bytes(&self.location)
+            .map_err(Error::StoreError)?;
+        if available < self.min_available_bytes {
+            return Err(Error::InsufficientDiskSpace {
+                available,
+                required: self.min_available_bytes,
+            });
+        }
+        Ok(available)
+    }
+}
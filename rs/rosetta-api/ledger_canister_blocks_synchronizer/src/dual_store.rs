@@ -0,0 +1,145 @@
+//! Support for writing the synced blocks to a second store while migrating
+//! between storage backends.
+//!
+//! During a migration window, the synchronizer writes every block to both the
+//! primary and the secondary store and compares them after every sync. Once
+//! the stores agree for long enough, operators can switch to the secondary
+//! store, and switch back without resyncing if something goes wrong.
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use ic_ledger_core::block::BlockType;
+use icp_ledger::{AccountIdentifier, Block, BlockIndex, Operation, Tokens};
+
+use crate::blocks::{Blocks, HashedBlock};
+use crate::errors::Error;
+
+// Number of blocks copied at once when catching the secondary store up.
+const BACKFILL_BATCH_SIZE: u64 = 10000;
+
+/// A difference between the primary and the secondary store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StoreMismatch {
+    /// The stores hold different blocks at `index`.
+    Block {
+        index: BlockIndex,
+        primary: Option<HashedBlock>,
+        secondary: Option<HashedBlock>,
+    },
+    /// The stores disagree on whether the block at `index` is verified.
+    Verified {
+        index: BlockIndex,
+        primary: bool,
+        secondary: bool,
+    },
+    /// The stores report different balances of `account` after the block at
+    /// `index`.
+    Balance {
+        index: BlockIndex,
+        account: AccountIdentifier,
+        primary: Option<Tokens>,
+        secondary: Option<Tokens>,
+    },
+}
+
+/// Copies the blocks that `secondary` is missing from `primary`, so that a
+/// secondary store can be added to an already synced primary store.
+///
+/// Fails if `secondary` holds blocks that are not in `primary`.
+pub fn backfill(primary: &Blocks, secondary: &mut Blocks) -> Result<(), Error> {
+    let primary_latest = match primary.get_latest_hashed_block() {
+        Ok(hb) => hb,
+        // nothing to copy
+        Err(_) => return Ok(()),
+    };
+    let start = match secondary.get_latest_hashed_block() {
+        Ok(hb) => {
+            if primary.get_hashed_block(&hb.index).ok().as_ref() != Some(&hb) {
+                return Err(Error::StoreError(format!(
+                    "The secondary store diverges from the primary store at block {}",
+                    hb.index
+                )));
+            }
+            hb.index + 1
+        }
+        Err(_) => 0,
+    };
+
+    let mut i = start;
+    while i <= primary_latest.index {
+        let end = (i + BACKFILL_BATCH_SIZE).min(primary_latest.index + 1);
+        secondary.push_batch(primary.get_hashed_block_range(i..end)?)?;
+        i = end;
+    }
+
+    if let Ok(verified) = primary.get_latest_verified_hashed_block() {
+        let secondary_verified = secondary.get_latest_verified_hashed_block().ok();
+        if secondary_verified.map_or(true, |hb| hb.index < verified.index) {
+            secondary.set_hashed_block_to_verified(&verified.index)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compares the blocks in `range` and the balances after the last block in
+/// `range` of the accounts that these blocks touch.
+pub fn cross_verify(
+    primary: &Blocks,
+    secondary: &Blocks,
+    range: Range<BlockIndex>,
+) -> Result<Vec<StoreMismatch>, Error> {
+    let mut mismatches = vec![];
+    if range.is_empty() {
+        return Ok(mismatches);
+    }
+
+    let mut accounts = BTreeSet::new();
+    for hb in primary.get_hashed_block_range(range.clone())? {
+        let block = Block::decode(hb.block.clone()).map_err(Error::InternalError)?;
+        match block.transaction.operation {
+            Operation::Burn { from, .. } => {
+                accounts.insert(from);
+            }
+            Operation::Mint { to, .. } => {
+                accounts.insert(to);
+            }
+            Operation::Transfer { from, to, .. } => {
+                accounts.insert(from);
+                accounts.insert(to);
+            }
+        }
+        let secondary_hb = secondary.get_hashed_block(&hb.index).ok();
+        if secondary_hb.as_ref() != Some(&hb) {
+            mismatches.push(StoreMismatch::Block {
+                index: hb.index,
+                primary: Some(hb),
+                secondary: secondary_hb,
+            });
+        }
+    }
+
+    let last = range.end - 1;
+    let primary_verified = primary.is_verified_by_idx(&last).unwrap_or(false);
+    let secondary_verified = secondary.is_verified_by_idx(&last).unwrap_or(false);
+    if primary_verified != secondary_verified {
+        mismatches.push(StoreMismatch::Verified {
+            index: last,
+            primary: primary_verified,
+            secondary: secondary_verified,
+        });
+    } else if primary_verified {
+        for account in accounts {
+            let primary_balance = primary.get_account_balance(&account, &last).ok();
+            let secondary_balance = secondary.get_account_balance(&account, &last).ok();
+            if primary_balance != secondary_balance {
+                mismatches.push(StoreMismatch::Balance {
+                    index: last,
+                    account,
+                    primary: primary_balance,
+                    secondary: secondary_balance,
+                });
+            }
+        }
+    }
+    Ok(mismatches)
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+
+use ic_ledger_core::block::{EncodedBlock, HashOf};
 use icp_ledger::{Block, BlockIndex};
 
 use crate::blocks::BlockStoreError;
@@ -7,6 +10,26 @@ pub enum Error {
     InvalidBlockId(String),
     InvalidTipOfChain(String),
     InternalError(String),
+    /// Querying the ledger or one of its archives failed. `height` is the
+    /// index of the first block that was requested, or None if the tip of
+    /// the chain was requested.
+    FetchFailed {
+        height: Option<BlockIndex>,
+        source: String,
+    },
+    /// The block at `height` doesn't point to the block before it.
+    ParentHashMismatch {
+        height: BlockIndex,
+        expected: Option<HashOf<EncodedBlock>>,
+        got: Option<HashOf<EncodedBlock>>,
+    },
+    /// Reading from or writing to the local block store failed.
+    StoreError(String),
+    /// The certificate returned by the ledger doesn't certify the tip.
+    CertificationFailed(String),
+    /// Syncing is paused because the file system holding the block store has
+    /// less than `required` bytes available.
+    InsufficientDiskSpace { available: u64, required: u64 },
 }
 
 impl Error {
@@ -15,8 +38,53 @@ impl Error {
                         index, expected, found);
         Error::InvalidTipOfChain(msg)
     }
+
+    pub fn fetch_failed(height: Option<BlockIndex>, source: String) -> Error {
+        Error::FetchFailed { height, source }
+    }
+
+    /// Returns true if the operation that failed can succeed if retried
+    /// later without any intervention, e.g. after a network error.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::FetchFailed { .. } | Error::InsufficientDiskSpace { .. }
+        )
+    }
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidBlockId(msg)
+            | Error::InvalidTipOfChain(msg)
+            | Error::InternalError(msg)
+            | Error::StoreError(msg)
+            | Error::CertificationFailed(msg) => write!(f, "{}", msg),
+            Error::FetchFailed { source, .. } => write!(f, "{}", source),
+            Error::ParentHashMismatch {
+                height,
+                expected,
+                got,
+            } => write!(
+                f,
+                "Block at {}: parent hash mismatch. Expected: {:?}, got: {:?}",
+                height, expected, got
+            ),
+            Error::InsufficientDiskSpace {
+                available,
+                required,
+            } => write!(
+                f,
+                "Syncing paused: {} bytes available on the block store disk, {} required",
+                available, required
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 impl From<BlockStoreError> for Error {
     fn from(e: BlockStoreError) -> Self {
         match e {
@@ -26,7 +94,8 @@ impl From<BlockStoreError> for Error {
             BlockStoreError::NotAvailable(idx) => {
                 Error::InvalidBlockId(format!("Block not available for query: {}", idx))
             }
-            BlockStoreError::Other(msg) => Error::InternalError(msg),
+            BlockStoreError::Empty => Error::StoreError("Blockchain is empty".to_string()),
+            BlockStoreError::Other(msg) => Error::StoreError(msg),
         }
     }
 }
@@ -1,6 +1,6 @@
 use std::ops::Range;
 use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use core::ops::Deref;
 use std::time::Instant;
@@ -8,13 +8,16 @@ use std::time::Instant;
 use ic_ledger_core::block::{BlockIndex, BlockType, EncodedBlock, HashOf};
 use icp_ledger::{Block, TipOfChainRes};
 use log::{debug, error, info, trace, warn};
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
 
 use crate::blocks::BlockStoreError;
 use crate::blocks::{Blocks, HashedBlock};
 use crate::blocks_access::BlocksAccess;
-use crate::certification::{verify_block_hash, VerificationInfo};
+use crate::certification::{verify_block_hash, verify_tip_certificate, VerificationInfo};
+use crate::disk_space::DiskSpaceWatchdog;
+use crate::dual_store::{self, StoreMismatch};
 use crate::errors::Error;
+use crate::snapshot;
 
 // If pruning is enabled, instead of pruning after each new block
 // we'll wait for PRUNE_DELAY blocks to accumulate and prune them in one go
@@ -26,17 +29,75 @@ const DATABASE_WRITE_BLOCKS_BATCH_SIZE: u64 = 500000;
 // Max number of retry in case of query failure while retrieving blocks.
 const MAX_RETRY: u8 = 5;
 
+// Number of verified blocks that can be buffered for a subscriber before it
+// starts lagging behind and missing blocks.
+const VERIFIED_BLOCKS_CHANNEL_CAPACITY: usize = 10000;
+
+// Number of blocks read back from the store at once when notifying subscribers.
+const NOTIFY_BLOCKS_BATCH_SIZE: u64 = 1000;
+
+// Number of blocks imported from a snapshot that are read back from the store
+// and verified at once.
+const SNAPSHOT_VERIFICATION_BATCH_SIZE: u64 = 100000;
+
 struct BlockWithIndex {
     block: Block,
+    hash: HashOf<EncodedBlock>,
     index: BlockIndex,
 }
 
+/// A consistent snapshot of how far the local copy of the chain is synced
+/// and verified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncStatus {
+    /// The index of the tip of the chain as last reported by the ledger, if
+    /// the ledger was queried.
+    pub target_index: Option<BlockIndex>,
+    /// The genesis block, if the store holds it.
+    pub genesis: Option<HashedBlock>,
+    /// The latest block in the store, verified or not.
+    pub latest_synced: Option<HashedBlock>,
+    /// The latest verified block in the store.
+    pub latest_verified: Option<HashedBlock>,
+    /// The oldest verified block still retained in the store after pruning.
+    pub oldest_verified: Option<HashedBlock>,
+    /// The indices of the blocks that are synced but not verified yet.
+    pub verified_gap: Range<BlockIndex>,
+}
+
+impl SyncStatus {
+    /// Reads the sync status from the store. Blocks that are not in the store
+    /// are `None`; all other store errors are returned.
+    pub fn from_store(blocks: &Blocks, target_index: Option<BlockIndex>) -> Result<Self, Error> {
+        Ok(Self {
+            target_index,
+            genesis: found(blocks.get_hashed_block(&0))?,
+            latest_synced: found(blocks.get_latest_hashed_block())?,
+            latest_verified: found(blocks.get_latest_verified_hashed_block())?,
+            oldest_verified: found(blocks.get_first_verified_hashed_block())?,
+            verified_gap: blocks.verified_gap()?,
+        })
+    }
+}
+
+/// Maps the errors for blocks that are not in the store to `None`.
+fn found(result: Result<HashedBlock, BlockStoreError>) -> Result<Option<HashedBlock>, Error> {
+    match result {
+        Ok(block) => Ok(Some(block)),
+        Err(BlockStoreError::NotFound(_)) | Err(BlockStoreError::Empty) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
 /// The LedgerBlocksSynchronizer will use this to output the metrics while
 /// synchronizing with the Ledger
 pub trait LedgerBlocksSynchronizerMetrics {
     fn set_target_height(&self, height: u64);
     fn set_synced_height(&self, height: u64);
     fn set_verified_height(&self, height: u64);
+    fn set_available_disk_space(&self, bytes: u64);
+    fn set_sync_paused(&self, paused: bool);
+    fn add_store_mismatches(&self, count: u64);
 }
 
 struct NopMetrics {}
@@ -45,6 +106,9 @@ impl LedgerBlocksSynchronizerMetrics for NopMetrics {
     fn set_target_height(&self, _height: u64) {}
     fn set_synced_height(&self, _height: u64) {}
     fn set_verified_height(&self, _height: u64) {}
+    fn set_available_disk_space(&self, _bytes: u64) {}
+    fn set_sync_paused(&self, _paused: bool) {}
+    fn add_store_mismatches(&self, _count: u64) {}
 }
 
 /// Downloads the blocks of the Ledger to either an in-memory store or to
@@ -59,6 +123,13 @@ where
     store_max_blocks: Option<u64>,
     verification_info: Option<VerificationInfo>,
     metrics: Box<dyn LedgerBlocksSynchronizerMetrics + Send + Sync>,
+    verified_blocks_sender: broadcast::Sender<HashedBlock>,
+    target_index: Mutex<Option<BlockIndex>>,
+    disk_space_watchdog: Option<DiskSpaceWatchdog>,
+    secondary_store: Option<Mutex<Blocks>>,
+    // Whether the store holds blocks imported from a snapshot that the next
+    // sync verifies against the certified tip.
+    from_snapshot: AtomicBool,
 }
 
 impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
@@ -74,6 +145,11 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
             None => Blocks::new_in_memory()?,
         };
 
+        if let Some(info) = &verification_info {
+            // refuse to mix blocks from different networks in the same store
+            blocks.pin_verification_info(&info.root_key.into_bytes(), &info.canister_id)?;
+        }
+
         if let Some(blocks_access) = &blocks_access {
             Self::verify_store(&blocks, blocks_access).await?;
             if let Some(verification_info) = &verification_info {
@@ -109,15 +185,218 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
 
         blocks.try_prune(&store_max_blocks, PRUNE_DELAY)?;
 
+        let (verified_blocks_sender, _) = broadcast::channel(VERIFIED_BLOCKS_CHANNEL_CAPACITY);
+
         Ok(Self {
             blockchain: RwLock::new(blocks),
             blocks_access,
             store_max_blocks,
             verification_info,
             metrics,
+            verified_blocks_sender,
+            target_index: Mutex::new(None),
+            disk_space_watchdog: None,
+            secondary_store: None,
+            from_snapshot: AtomicBool::new(false),
         })
     }
 
+    /// Initializes the empty store with the blocks of `snapshot`, so that
+    /// only the blocks after the snapshot are synced from the ledger. The
+    /// imported blocks stay unverified until the next sync checks them
+    /// against the certified tip. If the store already holds blocks, e.g.
+    /// after a restart, nothing is imported but the remaining unverified
+    /// blocks are still verified.
+    pub fn with_snapshot(mut self, snapshot: &Blocks) -> Result<Self, Error> {
+        if self.store_max_blocks.is_some() {
+            return Err(Error::InternalError(
+                "Syncing from a snapshot is not supported for pruned stores".to_string(),
+            ));
+        }
+        let blockchain = self.blockchain.get_mut();
+        if blockchain.get_latest_hashed_block().is_err() {
+            let imported = snapshot::import(snapshot, blockchain)?;
+            info!("Imported blocks {:?} from the snapshot", imported);
+        }
+        *self.from_snapshot.get_mut() = true;
+        Ok(self)
+    }
+
+    /// Verifies the blocks imported from a snapshot against the certified
+    /// `tip`. If the snapshot ends before the tip, the blocks up to the tip
+    /// are synced first, so that the hash chain of the tip reaches the
+    /// snapshot. The snapshot blocks are then checked backwards down to the
+    /// genesis block.
+    async fn verify_snapshot(
+        &self,
+        tip: &BlockWithIndex,
+        stopped: Arc<AtomicBool>,
+        blockchain: &mut Blocks,
+    ) -> Result<(), Error> {
+        if blockchain.get_latest_verified_hashed_block().is_err() {
+            let latest = match blockchain.get_latest_hashed_block() {
+                Ok(hb) => hb,
+                // the snapshot was empty
+                Err(_) => {
+                    self.from_snapshot.store(false, Relaxed);
+                    return Ok(());
+                }
+            };
+            if latest.index > tip.index {
+                // The tip comes from a lagging replica, try again later.
+                return Ok(());
+            }
+            if latest.index < tip.index {
+                self.sync_range_of_blocks(
+                    latest.index + 1..tip.index + 1,
+                    Some(latest.hash),
+                    stopped,
+                    tip,
+                    blockchain,
+                )
+                .await?;
+            } else if latest.hash == tip.hash {
+                let tip_range = tip.index..tip.index + 1;
+                self.update_secondary_store(|secondary| {
+                    secondary.set_hashed_block_range_to_verified(tip_range.clone())
+                })?;
+                blockchain.set_hashed_block_range_to_verified(tip_range)?;
+                self.metrics.set_verified_height(tip.index);
+            } else {
+                let block = Block::decode(latest.block).map_err(Error::InternalError)?;
+                return Err(Error::invalid_tip_of_chain(tip.index, tip.block.clone(), block));
+            }
+        }
+        loop {
+            let verified =
+                snapshot::verify_backwards(blockchain, SNAPSHOT_VERIFICATION_BATCH_SIZE)?;
+            if verified.is_empty() {
+                break;
+            }
+            self.update_secondary_store(|secondary| {
+                secondary.set_hashed_block_range_to_verified(verified.clone())
+            })?;
+            info!("Verified snapshot blocks {:?}", verified);
+        }
+        self.from_snapshot.store(false, Relaxed);
+        Ok(())
+    }
+
+    /// Writes every synced block to `secondary` as well and compares the two
+    /// stores after every sync. The blocks already in the primary store are
+    /// copied to `secondary` first.
+    pub fn with_secondary_store(mut self, mut secondary: Blocks) -> Result<Self, Error> {
+        if let Some(info) = &self.verification_info {
+            secondary.pin_verification_info(&info.root_key.into_bytes(), &info.canister_id)?;
+        }
+        dual_store::backfill(self.blockchain.get_mut(), &mut secondary)?;
+        self.secondary_store = Some(Mutex::new(secondary));
+        Ok(self)
+    }
+
+    /// Compares the blocks in `range` in the primary and the secondary store.
+    /// Returns no mismatches if there is no secondary store.
+    pub async fn cross_verify_stores(
+        &self,
+        range: Range<BlockIndex>,
+    ) -> Result<Vec<StoreMismatch>, Error> {
+        let blockchain = self.blockchain.read().await;
+        match &self.secondary_store {
+            Some(secondary) => {
+                dual_store::cross_verify(&blockchain, &secondary.lock().unwrap(), range)
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    fn update_secondary_store(
+        &self,
+        update: impl FnOnce(&mut Blocks) -> Result<(), BlockStoreError>,
+    ) -> Result<(), Error> {
+        if let Some(secondary) = &self.secondary_store {
+            update(&mut secondary.lock().unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// Pauses syncing while the watchdog reports that the disk is running out
+    /// of space. Syncing resumes once enough space is available again.
+    pub fn with_disk_space_watchdog(mut self, watchdog: DiskSpaceWatchdog) -> Self {
+        self.disk_space_watchdog = Some(watchdog);
+        self
+    }
+
+    /// Checks that there is enough disk space for the next write to the
+    /// store. If there is not, prunes the store down to `store_max_blocks`
+    /// and checks again.
+    fn ensure_disk_space(&self, blockchain: &mut Blocks) -> Result<(), Error> {
+        let watchdog = match &self.disk_space_watchdog {
+            Some(watchdog) => watchdog,
+            None => return Ok(()),
+        };
+        let result = match watchdog.check() {
+            Err(err @ Error::InsufficientDiskSpace { .. }) if self.store_max_blocks.is_some() => {
+                warn!("{}. Pruning the store", err);
+                blockchain.try_prune(&self.store_max_blocks, 0)?;
+                watchdog.check()
+            }
+            result => result,
+        };
+        match result {
+            Ok(available) => {
+                self.metrics.set_available_disk_space(available);
+                self.metrics.set_sync_paused(false);
+                Ok(())
+            }
+            Err(err) => {
+                if let Error::InsufficientDiskSpace { available, .. } = err {
+                    self.metrics.set_available_disk_space(available);
+                    self.metrics.set_sync_paused(true);
+                    warn!("{}", err);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns the status of the local copy of the chain. The store is
+    /// locked for reading while the status is computed, so that the values
+    /// are consistent with each other.
+    pub async fn sync_status(&self) -> Result<SyncStatus, Error> {
+        let blocks = self.blockchain.read().await;
+        let target_index = *self.target_index.lock().unwrap();
+        SyncStatus::from_store(&blocks, target_index)
+    }
+
+    /// Returns a receiver of the blocks synced from now on, in order of index,
+    /// as soon as they are verified.
+    ///
+    /// A receiver that falls more than [VERIFIED_BLOCKS_CHANNEL_CAPACITY]
+    /// blocks behind gets [broadcast::error::RecvError::Lagged] and must
+    /// catch up on the missing blocks using [Self::read_blocks].
+    pub fn subscribe(&self) -> broadcast::Receiver<HashedBlock> {
+        self.verified_blocks_sender.subscribe()
+    }
+
+    fn notify_verified_blocks(
+        &self,
+        blockchain: &Blocks,
+        range: Range<BlockIndex>,
+    ) -> Result<(), Error> {
+        let mut start = range.start;
+        while start < range.end && self.verified_blocks_sender.receiver_count() > 0 {
+            let end = (start + NOTIFY_BLOCKS_BATCH_SIZE).min(range.end);
+            for hb in blockchain.get_hashed_block_range(start..end)? {
+                // An error means that all the receivers have been dropped.
+                if self.verified_blocks_sender.send(hb).is_err() {
+                    return Ok(());
+                }
+            }
+            start = end;
+        }
+        Ok(())
+    }
+
     async fn verify_store(blocks: &Blocks, canister_access: &B) -> Result<(), Error> {
         debug!("Verifying store...");
         let first_block = blocks.get_first_hashed_block().ok();
@@ -126,7 +405,7 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
                 let genesis = canister_access
                     .query_raw_block(0)
                     .await
-                    .map_err(Error::InternalError)?
+                    .map_err(|e| Error::fetch_failed(Some(0), e))?
                     .expect("Blockchain in the ledger canister is empty");
 
                 if store_genesis.hash != Block::block_hash(&genesis) {
@@ -159,7 +438,7 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
             let queried_block = canister_access
                 .query_raw_block(first_block.index)
                 .await
-                .map_err(Error::InternalError)?;
+                .map_err(|e| Error::fetch_failed(Some(first_block.index), e))?;
             if queried_block.is_none() {
                 let msg = format!(
                     "Oldest block snapshot does not match the block on \
@@ -191,25 +470,95 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
         canister_access: &B,
         verification_info: &VerificationInfo,
     ) -> Result<(), Error> {
-        let TipOfChainRes {
-            tip_index,
-            certification,
-        } = canister_access
-            .query_tip()
-            .await
-            .map_err(Error::InternalError)?;
-        let tip_block = canister_access
+        Self::query_tip_block(canister_access, Some(verification_info)).await?;
+        Ok(())
+    }
+
+    /// Returns the tip of the chain with its index. If `verification_info` is
+    /// set, the tip is the one that the ledger certifies and its hash is
+    /// checked against the certificate. The certified archives are then
+    /// passed on to `canister_access`, so that blocks are only fetched from
+    /// them.
+    ///
+    /// Ledgers without `icrc3_get_tip_certificate` certify only the hash of
+    /// the tip, which is checked against the certificate of `tip_of_chain_pb`
+    /// instead.
+    async fn query_tip_block(
+        canister_access: &B,
+        verification_info: Option<&VerificationInfo>,
+    ) -> Result<BlockWithIndex, Error> {
+        let mut certificate_error = None;
+        let certified_tip = match verification_info {
+            Some(info) => match canister_access.query_tip_certificate().await {
+                Ok(certificate) => Some(
+                    verify_tip_certificate(&certificate, info)
+                        .map_err(Error::CertificationFailed)?,
+                ),
+                Err(e) => {
+                    warn!(
+                        "Failed to get the tip certificate, verifying the tip of chain instead: {}",
+                        e
+                    );
+                    certificate_error = Some(e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let (tip_index, tip_certification) = match &certified_tip {
+            Some(tip) => (tip.last_block_index, None),
+            None => {
+                let TipOfChainRes {
+                    tip_index,
+                    certification,
+                } = canister_access
+                    .query_tip()
+                    .await
+                    .map_err(|e| Error::fetch_failed(None, e))?;
+                (tip_index, certification)
+            }
+        };
+        let encoded_block = canister_access
             .query_raw_block(tip_index)
             .await
-            .map_err(Error::InternalError)?
-            .expect("Blockchain in the ledger canister is empty");
-        verify_block_hash(
-            &certification,
-            Block::block_hash(&tip_block),
-            verification_info,
-        )
-        .map_err(Error::InternalError)?;
-        Ok(())
+            .map_err(|e| Error::fetch_failed(Some(tip_index), e))?
+            .ok_or_else(|| {
+                Error::InternalError(format!(
+                    "Tip of the chain has index {} but no block found at that index!",
+                    tip_index
+                ))
+            })?;
+        let block = Block::decode(encoded_block.clone()).map_err(Error::InternalError)?;
+        let hash = HashedBlock::hash_block(encoded_block, block.parent_hash, tip_index).hash;
+        match (certified_tip, verification_info) {
+            (Some(tip), _) => {
+                if hash != tip.last_block_hash {
+                    return Err(Error::CertificationFailed(format!(
+                        "The hash {} of the tip at index {} is not the certified hash {}",
+                        hash, tip_index, tip.last_block_hash
+                    )));
+                }
+                canister_access.set_certified_archives(tip.archives).await;
+            }
+            (None, Some(info)) => {
+                // Only a ledger without the tip certificate certifies its tip
+                // this way. For any other ledger the failed query of the
+                // certificate is what went wrong.
+                verify_block_hash(&tip_certification, hash, info).map_err(|e| {
+                    Error::CertificationFailed(format!(
+                        "{}, and the tip certificate could not be fetched: {}",
+                        e,
+                        certificate_error.unwrap_or_default()
+                    ))
+                })?;
+            }
+            (None, None) => {}
+        }
+        Ok(BlockWithIndex {
+            block,
+            hash,
+            index: tip_index,
+        })
     }
 
     pub async fn read_blocks(&self) -> Box<dyn Deref<Target = Blocks> + '_> {
@@ -220,25 +569,9 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
     ///
     /// Note that self.verification_info must be set in order to verify the tip. If it's
     /// not set then this method will return the tip without verifying it.
-    async fn query_verified_tip(&self) -> Result<BlockWithIndex, String> {
+    async fn query_verified_tip(&self) -> Result<BlockWithIndex, Error> {
         let canister = self.blocks_access.as_ref().unwrap();
-        let TipOfChainRes {
-            tip_index,
-            certification,
-        } = canister.query_tip().await?;
-        let encoded_block = canister.query_raw_block(tip_index).await?.ok_or(format!(
-            "Tip of the chain has index {} but no block found at that index!",
-            tip_index
-        ))?;
-        let block = Block::decode(encoded_block.clone())?;
-        if let Some(info) = &self.verification_info {
-            let hash = HashedBlock::hash_block(encoded_block, block.parent_hash, tip_index).hash;
-            verify_block_hash(&certification, hash, info)?;
-        }
-        Ok(BlockWithIndex {
-            block,
-            index: tip_index,
-        })
+        Self::query_tip_block(canister, self.verification_info.as_ref()).await
     }
 
     pub async fn sync_blocks(
@@ -246,10 +579,7 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
         stopped: Arc<AtomicBool>,
         up_to_block_included: Option<BlockIndex>,
     ) -> Result<(), Error> {
-        let tip = self
-            .query_verified_tip()
-            .await
-            .map_err(Error::InternalError)?;
+        let tip = self.query_verified_tip().await?;
         if tip.index == u64::MAX {
             error!("Bogus value of tip index: {}", tip.index);
             return Err(Error::InternalError(
@@ -257,9 +587,29 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
             ));
         }
         self.metrics.set_target_height(tip.index);
+        *self.target_index.lock().unwrap() = Some(tip.index);
 
         let mut blockchain = self.blockchain.write().await;
 
+        if self.from_snapshot.load(Relaxed) {
+            match self
+                .verify_snapshot(&tip, stopped.clone(), &mut blockchain)
+                .await
+            {
+                Ok(()) => {}
+                Err(err @ (Error::ParentHashMismatch { .. } | Error::InvalidTipOfChain(_))) => {
+                    error!(
+                        "The snapshot does not match the ledger, resyncing the store: {}",
+                        err
+                    );
+                    self.update_secondary_store(|secondary| secondary.clear())?;
+                    blockchain.clear()?;
+                    self.from_snapshot.store(false, Relaxed);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
         let latest_hb_opt = blockchain.get_latest_hashed_block();
         let (last_block_hash, next_block_index) = match latest_hb_opt {
             Ok(hb) => (Some(hb.hash), hb.index + 1),
@@ -299,7 +649,7 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
             },
             last_block_hash,
             stopped,
-            tip,
+            &tip,
             &mut blockchain,
         )
         .await?;
@@ -309,9 +659,12 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
             blockchain.get_latest_hashed_block()?.index
         );
 
+        self.update_secondary_store(|secondary| {
+            secondary.try_prune(&self.store_max_blocks, PRUNE_DELAY)
+        })?;
         blockchain
             .try_prune(&self.store_max_blocks, PRUNE_DELAY)
-            .map_err(|_| Error::InternalError("Failed to prune store".to_string()))
+            .map_err(|_| Error::StoreError("Failed to prune store".to_string()))
     }
 
     async fn sync_range_of_blocks(
@@ -319,7 +672,7 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
         range: Range<BlockIndex>,
         first_block_parent_hash: Option<HashOf<EncodedBlock>>,
         stopped: Arc<AtomicBool>,
-        tip: BlockWithIndex,
+        tip: &BlockWithIndex,
         blockchain: &mut Blocks,
     ) -> Result<(), Error> {
         let t_total = Instant::now();
@@ -337,6 +690,9 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
             false
         };
 
+        // Don't fetch blocks that we can't store anyway.
+        self.ensure_disk_space(blockchain)?;
+
         let canister = self.blocks_access.as_ref().unwrap();
         let mut i = range.start;
         let mut last_block_hash = first_block_parent_hash;
@@ -356,7 +712,7 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
                         end: range.end,
                     })
                     .await
-                    .map_err(Error::InternalError);
+                    .map_err(|e| Error::fetch_failed(Some(i), e));
                 if batch.is_ok() || retry == MAX_RETRY {
                     break batch;
                 }
@@ -371,24 +727,28 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
 
             debug!("Got batch of len: {}", batch.len());
             if batch.is_empty() {
-                return Err(Error::InternalError(format!(
-                    "Couldn't fetch blocks [{},{}) (batch result empty)",
-                    i, range.end
-                )));
+                return Err(Error::fetch_failed(
+                    Some(i),
+                    format!(
+                        "Couldn't fetch blocks [{},{}) (batch result empty)",
+                        i, range.end
+                    ),
+                ));
             }
             for raw_block in batch {
                 let block = Block::decode(raw_block.clone())
                     .map_err(|err| Error::InternalError(format!("Cannot decode block: {}", err)))?;
                 if block.parent_hash != last_block_hash {
-                    let err_msg = format!(
-                        "Block at {}: parent hash mismatch. Expected: {:?}, got: {:?}",
-                        i, last_block_hash, block.parent_hash
-                    );
-                    error!("{}", err_msg);
-                    return Err(Error::InternalError(err_msg));
+                    let err = Error::ParentHashMismatch {
+                        height: i,
+                        expected: last_block_hash,
+                        got: block.parent_hash,
+                    };
+                    error!("{}", err);
+                    return Err(err);
                 }
                 if i == tip.index && block != tip.block {
-                    return Err(Error::invalid_tip_of_chain(tip.index, tip.block, block));
+                    return Err(Error::invalid_tip_of_chain(tip.index, tip.block.clone(), block));
                 }
                 let hb = HashedBlock::hash_block(raw_block, last_block_hash, i);
                 last_block_hash = Some(hb.hash);
@@ -397,6 +757,8 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
             }
             self.metrics.set_synced_height(i - 1);
             if (i - range.start) % DATABASE_WRITE_BLOCKS_BATCH_SIZE == 0 {
+                self.ensure_disk_space(blockchain)?;
+                self.update_secondary_store(|secondary| secondary.push_batch(block_batch.clone()))?;
                 blockchain.push_batch(block_batch)?;
                 if print_progress {
                     info!("Synced up to {}", i - 1);
@@ -404,10 +766,33 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
                 block_batch = Vec::new();
             }
         }
+        self.ensure_disk_space(blockchain)?;
+        self.update_secondary_store(|secondary| secondary.push_batch(block_batch.clone()))?;
         blockchain.push_batch(block_batch)?;
         info!("Synced took {} seconds", t_total.elapsed().as_secs_f64());
-        blockchain.set_hashed_block_to_verified(&(range.end - 1))?;
+        let set_verified = |blocks: &mut Blocks| {
+            // Blocks imported from a snapshot are only verified by
+            // verify_snapshot, which needs a verified block to start from.
+            if self.from_snapshot.load(Relaxed)
+                && blocks.get_latest_verified_hashed_block().is_err()
+            {
+                blocks.set_hashed_block_range_to_verified(range.clone())
+            } else {
+                blocks.set_hashed_block_to_verified(&(range.end - 1))
+            }
+        };
+        self.update_secondary_store(set_verified)?;
+        set_verified(blockchain)?;
         self.metrics.set_verified_height(range.end - 1);
+        if let Some(secondary) = &self.secondary_store {
+            let mismatches =
+                dual_store::cross_verify(blockchain, &secondary.lock().unwrap(), range.clone())?;
+            for mismatch in &mismatches {
+                error!("The primary and the secondary store differ: {:?}", mismatch);
+            }
+            self.metrics.add_store_mismatches(mismatches.len() as u64);
+        }
+        self.notify_verified_blocks(blockchain, range)?;
         Ok(())
     }
 }
@@ -416,28 +801,47 @@ impl<B: BlocksAccess> LedgerBlocksSynchronizer<B> {
 mod test {
 
     use std::ops::Range;
-    use std::sync::atomic::AtomicBool;
-    use std::sync::Arc;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
 
     use async_trait::async_trait;
-    use ic_ledger_core::block::{BlockType, EncodedBlock, HashOf};
-    use ic_ledger_core::timestamp::TimeStamp;
-    use ic_ledger_core::Tokens;
-    use ic_types::PrincipalId;
-    use icp_ledger::{AccountIdentifier, Block, BlockIndex, Memo, TipOfChainRes};
+    use ic_certification_test_utils::{CertificateBuilder, CertificateData};
+    use ic_crypto_tree_hash::Digest;
+    use ic_icrc1::icrc3::DataCertificate;
+    use ic_ledger_core::block::{BlockType, EncodedBlock};
+    use ic_types::CanisterId;
+    use icp_ledger::{Block, BlockIndex, CertifiedTip, TipOfChainRes};
+    use serde_bytes::ByteBuf;
 
+    use crate::blocks::{Blocks, HashedBlock};
     use crate::blocks_access::BlocksAccess;
-    use crate::ledger_blocks_sync::LedgerBlocksSynchronizer;
+    use crate::certification::VerificationInfo;
+    use crate::disk_space::{AvailableSpace, DiskSpaceWatchdog};
+    use crate::dual_store::{cross_verify, StoreMismatch};
+    use crate::errors::Error;
+    use crate::ledger_blocks_sync::{LedgerBlocksSynchronizer, SyncStatus};
+    use crate::test_fixtures::{diverge, dummy_blocks, with_wrong_parent_hash};
 
     use super::NopMetrics;
 
     struct RangeOfBlocks {
         pub blocks: Vec<EncodedBlock>,
+        /// The response to `query_tip_certificate`.
+        pub tip_certificate: Result<Option<DataCertificate>, String>,
+        /// The certification returned by `query_tip`.
+        pub tip_certification: Option<Vec<u8>>,
+        pub certified_archives: Mutex<Option<Vec<((BlockIndex, BlockIndex), CanisterId)>>>,
     }
 
     impl RangeOfBlocks {
         pub fn new(blocks: Vec<EncodedBlock>) -> Self {
-            Self { blocks }
+            Self {
+                blocks,
+                tip_certificate: Ok(None),
+                tip_certification: None,
+                certified_archives: Mutex::new(None),
+            }
         }
     }
 
@@ -455,12 +859,23 @@ mod test {
                 Err("Not tip".to_string())
             } else {
                 Ok(TipOfChainRes {
-                    certification: None,
+                    certification: self.tip_certification.clone(),
                     tip_index: (self.blocks.len() - 1) as u64,
                 })
             }
         }
 
+        async fn query_tip_certificate(&self) -> Result<Option<DataCertificate>, String> {
+            self.tip_certificate.clone()
+        }
+
+        async fn set_certified_archives(
+            &self,
+            archives: Vec<((BlockIndex, BlockIndex), CanisterId)>,
+        ) {
+            *self.certified_archives.lock().unwrap() = Some(archives);
+        }
+
         async fn multi_query_blocks(
             self: Arc<Self>,
             range: Range<BlockIndex>,
@@ -483,45 +898,6 @@ mod test {
         .unwrap()
     }
 
-    fn dummy_block(parent_hash: Option<HashOf<EncodedBlock>>) -> EncodedBlock {
-        let operation = match parent_hash {
-            Some(_) => {
-                let from = AccountIdentifier::new(PrincipalId::new_anonymous(), None);
-                let to = AccountIdentifier::new(PrincipalId::new_node_test_id(1), None);
-                let amount = Tokens::from_e8s(100_000);
-                let fee = Tokens::from_e8s(10_000);
-                icp_ledger::Operation::Transfer {
-                    from,
-                    to,
-                    amount,
-                    fee,
-                }
-            }
-            None => {
-                let to = AccountIdentifier::new(PrincipalId::new_anonymous(), None);
-                let amount = Tokens::from_e8s(100_000_000_000_000);
-                icp_ledger::Operation::Mint { amount, to }
-            }
-        };
-        let timestamp = TimeStamp::from_nanos_since_unix_epoch(
-            1656347498000000000, /* 27 June 2022 18:31:38 GMT+02:00 DST */
-        );
-        Block::new(parent_hash, operation, Memo(0), timestamp, timestamp)
-            .unwrap()
-            .encode()
-    }
-
-    fn dummy_blocks(n: usize) -> Vec<EncodedBlock> {
-        let mut res = vec![];
-        let mut parent_hash = None;
-        for _i in 0..n {
-            let block = dummy_block(parent_hash);
-            parent_hash = Some(Block::block_hash(&block));
-            res.push(block);
-        }
-        res
-    }
-
     #[tokio::test]
     async fn sync_empty_range_of_blocks() {
         let blocks_sync = new_ledger_blocks_synchronizer(vec![]).await;
@@ -587,4 +963,363 @@ mod test {
             );
         }
     }
+
+    #[tokio::test]
+    async fn subscribers_receive_verified_blocks() {
+        let blocks = dummy_blocks(3);
+        let blocks_sync = new_ledger_blocks_synchronizer(blocks.clone()).await;
+        let mut receiver = blocks_sync.subscribe();
+
+        blocks_sync
+            .sync_blocks(Arc::new(AtomicBool::new(false)), Some(1))
+            .await
+            .unwrap();
+        blocks_sync
+            .sync_blocks(Arc::new(AtomicBool::new(false)), None)
+            .await
+            .unwrap();
+
+        for (idx, eb) in blocks.iter().enumerate() {
+            let hb = receiver.try_recv().unwrap();
+            assert_eq!(idx as u64, hb.index);
+            assert_eq!(Block::block_hash(eb), hb.hash);
+        }
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn sync_status_reports_synced_and_verified_blocks() {
+        let blocks = dummy_blocks(3);
+        let blocks_sync = new_ledger_blocks_synchronizer(blocks.clone()).await;
+
+        let status = blocks_sync.sync_status().await.unwrap();
+        assert_eq!(status.target_index, None);
+        assert_eq!(status.genesis, None);
+        assert_eq!(status.latest_synced, None);
+        assert!(status.verified_gap.is_empty());
+
+        blocks_sync
+            .sync_blocks(Arc::new(AtomicBool::new(false)), Some(1))
+            .await
+            .unwrap();
+        let status = blocks_sync.sync_status().await.unwrap();
+        assert_eq!(status.target_index, Some(2));
+        assert_eq!(status.genesis.map(|hb| hb.index), Some(0));
+        assert_eq!(status.latest_synced.map(|hb| hb.index), Some(1));
+        assert_eq!(status.latest_verified.map(|hb| hb.index), Some(1));
+        assert_eq!(status.oldest_verified.map(|hb| hb.index), Some(0));
+        assert!(status.verified_gap.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sync_fails_on_parent_hash_mismatch() {
+        let blocks = with_wrong_parent_hash(&dummy_blocks(4), 2);
+        let blocks_sync = new_ledger_blocks_synchronizer(blocks).await;
+        let res = blocks_sync
+            .sync_blocks(Arc::new(AtomicBool::new(false)), None)
+            .await;
+        assert!(
+            matches!(res, Err(Error::ParentHashMismatch { height: 2, .. })),
+            "{:?}",
+            res
+        );
+    }
+
+    struct FakeAvailableSpace(Arc<AtomicU64>);
+
+    impl AvailableSpace for FakeAvailableSpace {
+        fn available_bytes(&self, _path: &Path) -> Result<u64, String> {
+            Ok(self.0.load(Ordering::Relaxed))
+        }
+    }
+
+    #[tokio::test]
+    async fn sync_pauses_while_disk_space_is_low() {
+        let blocks = dummy_blocks(3);
+        let available = Arc::new(AtomicU64::new(10));
+        let watchdog = DiskSpaceWatchdog::with_available_space(
+            Path::new("/unused"),
+            100,
+            Box::new(FakeAvailableSpace(available.clone())),
+        );
+        let blocks_sync = new_ledger_blocks_synchronizer(blocks)
+            .await
+            .with_disk_space_watchdog(watchdog);
+
+        let res = blocks_sync
+            .sync_blocks(Arc::new(AtomicBool::new(false)), None)
+            .await;
+        assert_eq!(
+            res,
+            Err(Error::InsufficientDiskSpace {
+                available: 10,
+                required: 100
+            })
+        );
+        assert!(res.unwrap_err().is_transient());
+        assert_eq!(blocks_sync.sync_status().await.unwrap().latest_synced, None);
+
+        available.store(1000, Ordering::Relaxed);
+        blocks_sync
+            .sync_blocks(Arc::new(AtomicBool::new(false)), None)
+            .await
+            .unwrap();
+        let status = blocks_sync.sync_status().await.unwrap();
+        assert_eq!(status.latest_verified.map(|hb| hb.index), Some(2));
+    }
+
+    #[tokio::test]
+    async fn secondary_store_receives_synced_blocks() {
+        let blocks = dummy_blocks(5);
+        let blocks_sync = new_ledger_blocks_synchronizer(blocks.clone()).await;
+        blocks_sync
+            .sync_blocks(Arc::new(AtomicBool::new(false)), Some(2))
+            .await
+            .unwrap();
+
+        // The blocks synced so far are copied to the secondary store.
+        let blocks_sync = blocks_sync
+            .with_secondary_store(Blocks::new_in_memory().unwrap())
+            .unwrap();
+        assert_eq!(blocks_sync.cross_verify_stores(0..3).await.unwrap(), vec![]);
+
+        blocks_sync
+            .sync_blocks(Arc::new(AtomicBool::new(false)), None)
+            .await
+            .unwrap();
+        assert_eq!(blocks_sync.cross_verify_stores(0..5).await.unwrap(), vec![]);
+
+        let secondary = blocks_sync.secondary_store.as_ref().unwrap().lock().unwrap();
+        for (idx, eb) in blocks.iter().enumerate() {
+            let hb = secondary.get_hashed_block(&(idx as u64)).unwrap();
+            assert!(secondary.is_verified_by_idx(&(idx as u64)).unwrap());
+            assert_eq!(Block::block_hash(eb), hb.hash);
+        }
+    }
+
+    fn hash_chain(blocks: &[EncodedBlock]) -> Vec<HashedBlock> {
+        let mut parent_hash = None;
+        let mut res = vec![];
+        for (idx, eb) in blocks.iter().enumerate() {
+            let hb = HashedBlock::hash_block(eb.clone(), parent_hash, idx as u64);
+            parent_hash = Some(hb.hash);
+            res.push(hb);
+        }
+        res
+    }
+
+    #[test]
+    fn cross_verify_reports_diverging_stores() {
+        let blocks = dummy_blocks(4);
+        let mut primary = Blocks::new_in_memory().unwrap();
+        primary.push_batch(hash_chain(&blocks)).unwrap();
+        primary.set_hashed_block_to_verified(&3).unwrap();
+        let mut secondary = Blocks::new_in_memory().unwrap();
+        secondary.push_batch(hash_chain(&diverge(&blocks, 2))).unwrap();
+        secondary.set_hashed_block_to_verified(&3).unwrap();
+
+        assert_eq!(cross_verify(&primary, &secondary, 0..2).unwrap(), vec![]);
+
+        let mismatches = cross_verify(&primary, &secondary, 0..4).unwrap();
+        let mismatched_blocks: Vec<_> = mismatches
+            .iter()
+            .filter_map(|m| match m {
+                StoreMismatch::Block { index, .. } => Some(*index),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(mismatched_blocks, vec![2, 3]);
+    }
+
+    #[test]
+    fn sync_status_maps_missing_blocks_to_none() {
+        let mut store = Blocks::new_in_memory().unwrap();
+        let status = SyncStatus::from_store(&store, None).unwrap();
+        assert!(status.genesis.is_none());
+        assert!(status.latest_synced.is_none());
+        assert!(status.latest_verified.is_none());
+        assert!(status.oldest_verified.is_none());
+        assert_eq!(status.verified_gap, 0..0);
+
+        store.push_batch(hash_chain(&dummy_blocks(4))).unwrap();
+        let status = SyncStatus::from_store(&store, Some(3)).unwrap();
+        assert_eq!(status.genesis.unwrap().index, 0);
+        assert_eq!(status.latest_synced.unwrap().index, 3);
+        assert!(status.latest_verified.is_none());
+        assert!(status.oldest_verified.is_none());
+        assert_eq!(status.verified_gap, 0..4);
+
+        store.set_hashed_block_to_verified(&1).unwrap();
+        let status = SyncStatus::from_store(&store, Some(3)).unwrap();
+        assert_eq!(status.oldest_verified.unwrap().index, 0);
+        assert_eq!(status.latest_verified.unwrap().index, 1);
+        assert_eq!(status.verified_gap, 2..4);
+    }
+
+    fn snapshot_store(blocks: &[EncodedBlock]) -> Blocks {
+        let mut snapshot = Blocks::new_in_memory().unwrap();
+        snapshot.push_batch(hash_chain(blocks)).unwrap();
+        snapshot
+    }
+
+    fn assert_verified_blocks(actual_blocks: &Blocks, blocks: &[EncodedBlock]) {
+        assert_eq!(
+            actual_blocks.get_latest_hashed_block().unwrap().index,
+            blocks.len() as u64 - 1
+        );
+        for (idx, eb) in blocks.iter().enumerate() {
+            let hb = actual_blocks.get_hashed_block(&(idx as u64)).unwrap();
+            assert!(actual_blocks.is_verified_by_idx(&(idx as u64)).unwrap());
+            assert_eq!(Block::block_hash(eb), hb.hash);
+        }
+    }
+
+    #[tokio::test]
+    async fn snapshot_blocks_are_verified_by_the_first_sync() {
+        let blocks = dummy_blocks(5);
+        let blocks_sync = new_ledger_blocks_synchronizer(blocks.clone())
+            .await
+            .with_snapshot(&snapshot_store(&blocks[..3]))
+            .unwrap();
+        let status = blocks_sync.sync_status().await.unwrap();
+        assert_eq!(status.latest_synced.map(|hb| hb.index), Some(2));
+        assert_eq!(status.latest_verified, None);
+
+        blocks_sync
+            .sync_blocks(Arc::new(AtomicBool::new(false)), None)
+            .await
+            .unwrap();
+        let status = blocks_sync.sync_status().await.unwrap();
+        assert_eq!(status.latest_verified.map(|hb| hb.index), Some(4));
+        assert_eq!(status.oldest_verified.map(|hb| hb.index), Some(0));
+        assert_verified_blocks(&*blocks_sync.read_blocks().await, &blocks);
+    }
+
+    #[tokio::test]
+    async fn snapshot_reaching_the_tip_is_verified() {
+        let blocks = dummy_blocks(5);
+        let blocks_sync = new_ledger_blocks_synchronizer(blocks.clone())
+            .await
+            .with_snapshot(&snapshot_store(&blocks))
+            .unwrap();
+        blocks_sync
+            .sync_blocks(Arc::new(AtomicBool::new(false)), None)
+            .await
+            .unwrap();
+        assert_verified_blocks(&*blocks_sync.read_blocks().await, &blocks);
+    }
+
+    #[tokio::test]
+    async fn tampered_snapshot_is_replaced_by_the_ledger_blocks() {
+        let blocks = dummy_blocks(5);
+        // A snapshot behind the tip with a tampered block in the middle, and
+        // a snapshot reaching the tip with a tampered tip.
+        for (snapshot_len, tampered_idx) in [(3, 1), (5, 4)] {
+            let mut tampered = blocks[..snapshot_len].to_vec();
+            tampered[tampered_idx] = diverge(&blocks, tampered_idx)[tampered_idx].clone();
+            let blocks_sync = new_ledger_blocks_synchronizer(blocks.clone())
+                .await
+                .with_snapshot(&snapshot_store(&tampered))
+                .unwrap();
+            blocks_sync
+                .sync_blocks(Arc::new(AtomicBool::new(false)), None)
+                .await
+                .unwrap();
+            assert_verified_blocks(&*blocks_sync.read_blocks().await, &blocks);
+        }
+    }
+
+    const LEDGER_ID: CanisterId = CanisterId::from_u64(2);
+    const ARCHIVE_ID: CanisterId = CanisterId::from_u64(3);
+
+    /// Returns a certificate of `certified_data` by the ledger and the
+    /// verification info to check it with.
+    fn certify(certified_data: Digest) -> (Vec<u8>, VerificationInfo) {
+        let (_cert, root_key, cbor) = CertificateBuilder::new(CertificateData::CanisterData {
+            canister_id: LEDGER_ID,
+            certified_data,
+        })
+        .build();
+        let info = VerificationInfo {
+            root_key,
+            canister_id: LEDGER_ID,
+        };
+        (cbor, info)
+    }
+
+    async fn new_verifying_synchronizer(
+        blocks_access: RangeOfBlocks,
+        verification_info: VerificationInfo,
+    ) -> Result<LedgerBlocksSynchronizer<RangeOfBlocks>, Error> {
+        LedgerBlocksSynchronizer::new(
+            Some(Arc::new(blocks_access)),
+            /* store_location = */ None,
+            /* store_max_blocks = */ None,
+            Some(verification_info),
+            Box::new(NopMetrics {}),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn certified_archives_are_used_to_fetch_blocks() {
+        let blocks = dummy_blocks(3);
+        let tip = CertifiedTip {
+            last_block_index: 2,
+            last_block_hash: Block::block_hash(&blocks[2]),
+            archives: vec![((0, 1), ARCHIVE_ID)],
+        };
+        let hash_tree = tip.hash_tree();
+        let (certificate, info) = certify(hash_tree.digest());
+        let mut encoded_tree = vec![];
+        ciborium::ser::into_writer(&hash_tree, &mut encoded_tree).unwrap();
+        let mut blocks_access = RangeOfBlocks::new(blocks);
+        blocks_access.tip_certificate = Ok(Some(DataCertificate {
+            certificate: ByteBuf::from(certificate),
+            hash_tree: ByteBuf::from(encoded_tree),
+        }));
+
+        let blocks_sync = new_verifying_synchronizer(blocks_access, info)
+            .await
+            .unwrap();
+        blocks_sync
+            .sync_blocks(Arc::new(AtomicBool::new(false)), None)
+            .await
+            .unwrap();
+        let blocks_access = blocks_sync.blocks_access.as_ref().unwrap();
+        assert_eq!(
+            *blocks_access.certified_archives.lock().unwrap(),
+            Some(tip.archives)
+        );
+    }
+
+    #[tokio::test]
+    async fn ledger_without_tip_certificate_is_verified_with_tip_of_chain() {
+        let blocks = dummy_blocks(3);
+        let tip_hash = Block::block_hash(&blocks[2]);
+        let (certificate, info) = certify(Digest(tip_hash.into_bytes()));
+        let mut blocks_access = RangeOfBlocks::new(blocks.clone());
+        blocks_access.tip_certificate = Err("no query method icrc3_get_tip_certificate".into());
+        blocks_access.tip_certification = Some(certificate);
+
+        let blocks_sync = new_verifying_synchronizer(blocks_access, info)
+            .await
+            .unwrap();
+        blocks_sync
+            .sync_blocks(Arc::new(AtomicBool::new(false)), None)
+            .await
+            .unwrap();
+        let status = blocks_sync.sync_status().await.unwrap();
+        assert_eq!(status.latest_verified.map(|hb| hb.index), Some(2));
+        let blocks_access = blocks_sync.blocks_access.as_ref().unwrap();
+        assert_eq!(*blocks_access.certified_archives.lock().unwrap(), None);
+
+        // A certificate of another tip is rejected.
+        let (certificate, info) = certify(Digest(Block::block_hash(&blocks[1]).into_bytes()));
+        let mut blocks_access = RangeOfBlocks::new(blocks);
+        blocks_access.tip_certificate = Err("no query method icrc3_get_tip_certificate".into());
+        blocks_access.tip_certification = Some(certificate);
+        let res = new_verifying_synchronizer(blocks_access, info).await;
+        assert!(matches!(res, Err(Error::CertificationFailed(_))));
+    }
 }
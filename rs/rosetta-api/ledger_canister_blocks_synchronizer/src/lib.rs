@@ -3,5 +3,11 @@ pub mod blocks;
 pub mod blocks_access;
 pub mod canister_access;
 pub mod certification;
+pub mod disk_space;
+pub mod dual_store;
 pub mod errors;
 pub mod ledger_blocks_sync;
+pub mod rate_limiter;
+pub mod snapshot;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_fixtures;
@@ -0,0 +1,122 @@
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+/// A token bucket limiting the rate of queries sent to the ledger.
+///
+/// The bucket holds up to `burst` tokens and is refilled at
+/// `queries_per_second`. Every query takes one token and waits until one is
+/// available. The bucket can additionally be paused, e.g. when the replica
+/// answers that it is overloaded.
+pub struct RateLimiter {
+    queries_per_second: f64,
+    burst: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    tokens: f64,
+    last_refill: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(queries_per_second: u32, burst: u32) -> Self {
+        assert!(queries_per_second > 0, "queries_per_second must be positive");
+        let burst = burst.max(1) as f64;
+        Self {
+            queries_per_second: queries_per_second as f64,
+            burst,
+            state: Mutex::new(BucketState {
+                tokens: burst,
+                last_refill: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Waits until a query may be sent.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().await;
+                let now = Instant::now();
+                match state.paused_until {
+                    Some(until) if until > now => until - now,
+                    _ => {
+                        state.paused_until = None;
+                        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+                        state.tokens =
+                            (state.tokens + elapsed * self.queries_per_second).min(self.burst);
+                        state.last_refill = now;
+                        if state.tokens >= 1.0 {
+                            state.tokens -= 1.0;
+                            return;
+                        }
+                        Duration::from_secs_f64((1.0 - state.tokens) / self.queries_per_second)
+                    }
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Stops handing out tokens for `duration`. The bucket is empty when the
+    /// pause ends and only starts refilling then.
+    pub async fn pause(&self, duration: Duration) {
+        let mut state = self.state.lock().await;
+        let until = Instant::now() + duration;
+        let until = state.paused_until.map_or(until, |current| current.max(until));
+        state.paused_until = Some(until);
+        state.last_refill = until;
+        state.tokens = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn burst_is_not_delayed() {
+        let limiter = RateLimiter::new(1, 5);
+        let start = Instant::now();
+        for _ in 0..5 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn queries_beyond_burst_are_delayed() {
+        let limiter = RateLimiter::new(20, 1);
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        // The first token is free, the other two take 50ms each.
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn pause_delays_queries() {
+        let limiter = RateLimiter::new(1000, 10);
+        limiter.pause(Duration::from_millis(100)).await;
+        let start = Instant::now();
+        limiter.acquire().await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn bucket_refills_only_after_the_pause() {
+        let limiter = RateLimiter::new(10, 10);
+        limiter.pause(Duration::from_millis(100)).await;
+        let start = Instant::now();
+        limiter.acquire().await;
+        // The pause takes 100ms and the first token another 100ms.
+        assert!(start.elapsed() >= Duration::from_millis(190));
+        // The bucket fills at the normal rate after the pause.
+        limiter.acquire().await;
+        assert!(start.elapsed() >= Duration::from_millis(290));
+    }
+}
@@ -0,0 +1,128 @@
+//! Fast sync from a snapshot of another node's block store.
+//!
+//! Syncing a new node block by block from the ledger takes a long time. The
+//! synchronizer can instead import the blocks of a snapshot into its empty
+//! store and only sync the blocks after the snapshot from the ledger. The
+//! snapshot is not trusted: its blocks stay unverified, and so cannot be
+//! queried, until they are checked against the hash chain of the certified
+//! tip. The first sync links the snapshot to the tip and then walks the hash
+//! chain backwards from the oldest verified block down to the genesis block.
+use std::ops::Range;
+
+use ic_ledger_core::block::BlockType;
+use icp_ledger::{Block, BlockIndex};
+use log::error;
+
+use crate::blocks::{Blocks, HashedBlock};
+use crate::errors::Error;
+
+// Number of blocks copied at once from the snapshot.
+const IMPORT_BATCH_SIZE: u64 = 10000;
+
+/// Copies the blocks of `snapshot` to the empty store `blocks` without
+/// verifying them, and returns the range of the imported blocks.
+///
+/// The hashes are recomputed from the blocks rather than copied, so that a
+/// tampered block is caught by the hash chain check of [verify_backwards].
+pub fn import(snapshot: &Blocks, blocks: &mut Blocks) -> Result<Range<BlockIndex>, Error> {
+    if let Ok(hb) = blocks.get_latest_hashed_block() {
+        return Err(Error::StoreError(format!(
+            "Cannot import a snapshot into a store that holds blocks up to {}",
+            hb.index
+        )));
+    }
+    let latest = match snapshot.get_latest_hashed_block() {
+        Ok(hb) => hb,
+        // nothing to import
+        Err(_) => return Ok(0..0),
+    };
+    let first = snapshot.get_first_hashed_block()?;
+    if first.index != 0 {
+        return Err(Error::InternalError(format!(
+            "The snapshot starts at block {} instead of the genesis block",
+            first.index
+        )));
+    }
+
+    let mut i = 0;
+    while i <= latest.index {
+        let end = (i + IMPORT_BATCH_SIZE).min(latest.index + 1);
+        let batch = snapshot
+            .get_hashed_block_range(i..end)?
+            .into_iter()
+            .map(|hb| {
+                let block = Block::decode(hb.block.clone()).map_err(Error::InternalError)?;
+                Ok(HashedBlock::hash_block(hb.block, block.parent_hash, hb.index))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        if batch.len() as u64 != end - i {
+            return Err(Error::InternalError(format!(
+                "The snapshot is missing blocks between {} and {}",
+                i, end
+            )));
+        }
+        blocks.push_batch(batch)?;
+        i = end;
+    }
+    Ok(0..latest.index + 1)
+}
+
+/// Verifies up to `max_blocks` unverified blocks below the oldest verified
+/// block in `blocks`, newest first, and marks them as verified. Every block
+/// must hash to the parent hash of the verified block after it.
+///
+/// Returns the range of the newly verified blocks. The range is empty once
+/// all the blocks in the store are verified, or if no block is verified yet.
+pub fn verify_backwards(blocks: &Blocks, max_blocks: u64) -> Result<Range<BlockIndex>, Error> {
+    let oldest_verified = match blocks.get_first_verified_hashed_block() {
+        Ok(hb) => hb,
+        // nothing to verify against yet
+        Err(_) => return Ok(0..0),
+    };
+    let end = oldest_verified.index;
+    let start = end
+        .saturating_sub(max_blocks)
+        .max(blocks.get_first_hashed_block()?.index);
+    if start == end {
+        return Ok(end..end);
+    }
+
+    let batch = blocks.get_hashed_block_range(start..end)?;
+    if batch.len() as u64 != end - start {
+        return Err(Error::StoreError(format!(
+            "The store is missing blocks between {} and {}",
+            start, end
+        )));
+    }
+    let mut expected = oldest_verified.parent_hash;
+    for hb in batch.into_iter().rev() {
+        let hash = Block::block_hash(&hb.block);
+        if expected != Some(hash) || hb.hash != hash {
+            let err = Error::ParentHashMismatch {
+                height: hb.index + 1,
+                expected: Some(hash),
+                got: expected,
+            };
+            error!("Snapshot verification failed: {}", err);
+            return Err(err);
+        }
+        let block = Block::decode(hb.block).map_err(Error::InternalError)?;
+        if block.parent_hash != hb.parent_hash {
+            return Err(Error::StoreError(format!(
+                "The stored parent hash of block {} does not match the block",
+                hb.index
+            )));
+        }
+        expected = block.parent_hash;
+    }
+    if start == 0 && expected.is_some() {
+        return Err(Error::ParentHashMismatch {
+            height: 0,
+            expected: None,
+            got: expected,
+        });
+    }
+
+    blocks.set_hashed_block_range_to_verified(start..end)?;
+    Ok(start..end)
+}
@@ -0,0 +1,163 @@
+//! Deterministic generators of ledger chains, for testing code that syncs
+//! blocks from the ledger.
+//!
+//! The ICP ledger has no approval operation, so chains are made of mints,
+//! burns and transfers.
+
+use std::time::Duration;
+
+use ic_ledger_core::block::{BlockType, EncodedBlock, HashOf};
+use ic_ledger_core::timestamp::TimeStamp;
+use ic_ledger_core::Tokens;
+use ic_types::PrincipalId;
+use icp_ledger::{AccountIdentifier, Block, Memo, Operation};
+
+/// The timestamp, in nanoseconds since the Unix epoch, of the first block
+/// generated by a [ChainBuilder], unless configured otherwise.
+/// 27 June 2022 18:31:38 GMT+02:00 DST.
+pub const DEFAULT_START_TIMESTAMP_NANOS: u64 = 1656347498000000000;
+
+/// The fee used by the transfers generated by [dummy_blocks].
+pub const DEFAULT_FEE: Tokens = Tokens::from_e8s(10_000);
+
+/// Returns the n-th test account.
+pub fn account(n: u64) -> AccountIdentifier {
+    AccountIdentifier::new(PrincipalId::new_user_test_id(n), None)
+}
+
+/// Builds a valid chain of blocks, one operation at a time.
+///
+/// Each block gets the next memo and a timestamp `timestamp_step` after the
+/// previous one, so the same sequence of operations always produces the same
+/// blocks. Clone a builder to fork the chain.
+#[derive(Clone)]
+pub struct ChainBuilder {
+    blocks: Vec<EncodedBlock>,
+    parent_hash: Option<HashOf<EncodedBlock>>,
+    next_timestamp: TimeStamp,
+    timestamp_step: Duration,
+}
+
+impl Default for ChainBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChainBuilder {
+    pub fn new() -> Self {
+        Self {
+            blocks: vec![],
+            parent_hash: None,
+            next_timestamp: TimeStamp::from_nanos_since_unix_epoch(
+                DEFAULT_START_TIMESTAMP_NANOS,
+            ),
+            timestamp_step: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets the timestamp of the next block.
+    pub fn with_timestamp(mut self, timestamp: TimeStamp) -> Self {
+        self.next_timestamp = timestamp;
+        self
+    }
+
+    /// Sets the time between two consecutive blocks.
+    pub fn with_timestamp_step(mut self, step: Duration) -> Self {
+        self.timestamp_step = step;
+        self
+    }
+
+    /// Appends a block with the given operation.
+    pub fn push(mut self, operation: Operation) -> Self {
+        let timestamp = self.next_timestamp;
+        let memo = Memo(self.blocks.len() as u64);
+        let block = Block::new(self.parent_hash, operation, memo, timestamp, timestamp)
+            .unwrap()
+            .encode();
+        self.parent_hash = Some(Block::block_hash(&block));
+        self.next_timestamp = timestamp + self.timestamp_step;
+        self.blocks.push(block);
+        self
+    }
+
+    pub fn mint(self, to: AccountIdentifier, amount: Tokens) -> Self {
+        self.push(Operation::Mint { to, amount })
+    }
+
+    pub fn burn(self, from: AccountIdentifier, amount: Tokens) -> Self {
+        self.push(Operation::Burn { from, amount })
+    }
+
+    pub fn transfer(
+        self,
+        from: AccountIdentifier,
+        to: AccountIdentifier,
+        amount: Tokens,
+        fee: Tokens,
+    ) -> Self {
+        self.push(Operation::Transfer {
+            from,
+            to,
+            amount,
+            fee,
+        })
+    }
+
+    /// Returns the number of blocks built so far.
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn build(self) -> Vec<EncodedBlock> {
+        self.blocks
+    }
+}
+
+/// Returns a chain of `n` blocks: a mint to `account(0)` followed by
+/// transfers from `account(0)` to `account(1)`.
+pub fn dummy_blocks(n: usize) -> Vec<EncodedBlock> {
+    let mut builder = ChainBuilder::new();
+    for i in 0..n {
+        builder = if i == 0 {
+            builder.mint(account(0), Tokens::from_e8s(100_000_000_000_000))
+        } else {
+            builder.transfer(account(0), account(1), Tokens::from_e8s(100_000), DEFAULT_FEE)
+        };
+    }
+    builder.build()
+}
+
+/// Returns a copy of `blocks` that diverges from it starting at `index`:
+/// the blocks before `index` are the same and the blocks from `index` on
+/// have different content and hashes, as in a fork of the chain.
+pub fn diverge(blocks: &[EncodedBlock], index: usize) -> Vec<EncodedBlock> {
+    let mut parent_hash = index
+        .checked_sub(1)
+        .map(|parent| Block::block_hash(&blocks[parent]));
+    let mut res = blocks[..index].to_vec();
+    for encoded in &blocks[index..] {
+        let mut block = Block::decode(encoded.clone()).unwrap();
+        block.parent_hash = parent_hash;
+        block.timestamp = block.timestamp + Duration::from_nanos(1);
+        let encoded = block.encode();
+        parent_hash = Some(Block::block_hash(&encoded));
+        res.push(encoded);
+    }
+    res
+}
+
+/// Returns a copy of `blocks` where the block at `index` points to a bogus
+/// parent, so that the chain is broken between `index - 1` and `index` and
+/// between `index` and `index + 1`.
+pub fn with_wrong_parent_hash(blocks: &[EncodedBlock], index: usize) -> Vec<EncodedBlock> {
+    let mut res = blocks.to_vec();
+    let mut block = Block::decode(res[index].clone()).unwrap();
+    block.parent_hash = Some(HashOf::new([0xff; 32]));
+    res[index] = block.encode();
+    res
+}
@@ -110,7 +110,7 @@ impl Scribe {
         let parent_hash = self.blockchain.back().map(|hb| hb.hash);
         let index = self.next_index();
 
-        let block = Block::from_transaction(parent_hash, transaction, self.time().into());
+        let block = Block::from_transaction(parent_hash, transaction, self.time().into(), None);
 
         self.blockchain
             .push_back(HashedBlock::hash_block(block.encode(), parent_hash, index));
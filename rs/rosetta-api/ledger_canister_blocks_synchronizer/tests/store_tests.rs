@@ -7,6 +7,7 @@ use ic_ledger_canister_blocks_synchronizer_test_utils::{
 };
 use ic_ledger_canister_core::ledger::LedgerTransaction;
 use ic_ledger_core::{balances::BalancesStore, block::BlockType, Tokens};
+use ic_types::CanisterId;
 use icp_ledger::{apply_operation, AccountIdentifier, Block, Operation};
 use rusqlite::params;
 use std::path::Path;
@@ -46,6 +47,63 @@ async fn store_smoke_test() {
     );
 }
 
+#[actix_rt::test]
+async fn store_read_only_test() {
+    init_test_logger();
+    let tmpdir = create_tmp_dir();
+    let scribe = Scribe::new_with_sample_data(10, 100);
+    {
+        let mut store = sqlite_on_disk_store(tmpdir.path());
+        for hb in &scribe.blockchain {
+            store.push(hb).unwrap();
+        }
+    }
+
+    let mut store = Blocks::open_read_only(tmpdir.path()).unwrap();
+    for hb in &scribe.blockchain {
+        assert_eq!(store.get_hashed_block(&hb.index).unwrap(), *hb);
+    }
+    let next = Scribe::new_with_sample_data(10, 101);
+    assert!(store.push(next.blockchain.back().unwrap()).is_err());
+    assert_eq!(
+        store.get_latest_hashed_block().unwrap(),
+        *scribe.blockchain.back().unwrap()
+    );
+
+    // Unlike a persistent store, a read-only one is never created.
+    let empty_dir = create_tmp_dir();
+    assert!(Blocks::open_read_only(empty_dir.path()).is_err());
+    assert!(!empty_dir.path().join("db.sqlite").exists());
+}
+
+#[actix_rt::test]
+async fn store_in_memory_spill_test() {
+    init_test_logger();
+    // A budget of a few pages makes the store spill most blocks to disk.
+    let mut store = Blocks::new_in_memory_with_budget(16 * 1024).unwrap();
+    let scribe = Scribe::new_with_sample_data(10, 1000);
+
+    for hb in &scribe.blockchain {
+        store.push(hb).unwrap();
+    }
+    let last_idx = scribe.blockchain.back().unwrap().index;
+    store.set_hashed_block_to_verified(&last_idx).unwrap();
+
+    for hb in &scribe.blockchain {
+        assert_eq!(store.get_hashed_block(&hb.index).unwrap(), *hb);
+        assert_eq!(
+            store.get_transaction(&hb.index).unwrap(),
+            Block::decode((*hb).clone().block).unwrap().transaction
+        );
+    }
+    for (account, tokens) in scribe.balance_history.back().unwrap() {
+        assert_eq!(
+            store.get_account_balance(account, &last_idx).unwrap(),
+            *tokens
+        );
+    }
+}
+
 #[actix_rt::test]
 async fn store_coherance_test() {
     init_test_logger();
@@ -123,13 +181,13 @@ async fn store_account_balances_test() {
         if let Some(acc_str) = from_account {
             let id = AccountIdentifier::from_hex(acc_str.as_str()).unwrap();
             let amount_from = store.get_account_balance(&id, &hb.index).unwrap();
-            let amount_local = *account_balances.store.get_balance(&id).unwrap();
+            let amount_local = account_balances.store.get_balance(&id).unwrap();
             assert_eq!(amount_from, amount_local);
         }
         if let Some(acc_str) = to_account {
             let id = AccountIdentifier::from_hex(acc_str.as_str()).unwrap();
             let amount_to = store.get_account_balance(&id, &hb.index).unwrap();
-            let amount_local = *account_balances.store.get_balance(&id).unwrap();
+            let amount_local = account_balances.store.get_balance(&id).unwrap();
             assert_eq!(amount_to, amount_local);
         }
     }
@@ -327,3 +385,67 @@ fn verify_balance_snapshot(scribe: &Scribe, store: &mut Blocks, prune_at: u64) {
     }
     assert_eq!(sum_icpt, total);
 }
+
+#[actix_rt::test]
+async fn store_verified_gap_test() {
+    init_test_logger();
+    let tmpdir = create_tmp_dir();
+    let mut store = sqlite_on_disk_store(tmpdir.path());
+    let scribe = Scribe::new_with_sample_data(10, 100);
+    assert!(store.verified_gap().unwrap().is_empty());
+
+    for hb in &scribe.blockchain {
+        store.push(hb).unwrap();
+    }
+    let last_idx = scribe.blockchain.back().unwrap().index;
+    assert_eq!(store.verified_gap().unwrap(), 0..last_idx + 1);
+
+    store.set_hashed_block_to_verified(&49).unwrap();
+    assert_eq!(store.verified_gap().unwrap(), 50..last_idx + 1);
+
+    store.set_hashed_block_to_verified(&last_idx).unwrap();
+    assert!(store.verified_gap().unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn store_pin_verification_info_test() {
+    init_test_logger();
+    let tmpdir = create_tmp_dir();
+    let root_key = [1u8; 96];
+    let canister_id = CanisterId::from_u64(2);
+    {
+        let store = sqlite_on_disk_store(tmpdir.path());
+        store.pin_verification_info(&root_key, &canister_id).unwrap();
+        store.pin_verification_info(&root_key, &canister_id).unwrap();
+    }
+
+    // the pinned info survives reopening the store
+    let store = sqlite_on_disk_store(tmpdir.path());
+    store.pin_verification_info(&root_key, &canister_id).unwrap();
+    assert!(matches!(
+        store.pin_verification_info(&[2u8; 96], &canister_id),
+        Err(BlockStoreError::Other(_))
+    ));
+    assert!(matches!(
+        store.pin_verification_info(&root_key, &CanisterId::from_u64(3)),
+        Err(BlockStoreError::Other(_))
+    ));
+}
+
+#[actix_rt::test]
+async fn store_in_memory_pin_verification_info_test() {
+    init_test_logger();
+    let store = Blocks::new_in_memory().unwrap();
+    let root_key = [1u8; 96];
+    let canister_id = CanisterId::from_u64(2);
+    store.pin_verification_info(&root_key, &canister_id).unwrap();
+    store.pin_verification_info(&root_key, &canister_id).unwrap();
+    assert!(matches!(
+        store.pin_verification_info(&[2u8; 96], &canister_id),
+        Err(BlockStoreError::Other(_))
+    ));
+    assert!(matches!(
+        store.pin_verification_info(&root_key, &CanisterId::from_u64(3)),
+        Err(BlockStoreError::Other(_))
+    ));
+}
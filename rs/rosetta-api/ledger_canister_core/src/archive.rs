@@ -1,14 +1,21 @@
 use crate::{runtime::Runtime, spawn};
 use candid::{CandidType, Encode};
 use ic_base_types::{CanisterId, PrincipalId};
-use ic_ic00_types::IC_00;
+use ic_ic00_types::{CanisterIdRecord, IC_00};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::marker::PhantomData;
 use std::sync::{Arc, RwLock};
 
-use ic_ledger_core::block::EncodedBlock;
+use ic_ledger_core::block::{BlockType, EncodedBlock, HashOf};
+
+/// The maximum number of blocks that an archive node verifies in one call of
+/// its `verify_blocks` endpoint. It bounds the instructions of the call.
+pub const MAX_BLOCKS_TO_VERIFY: u64 = 1_000;
+
+/// The maximum number of top-up events that the ledger keeps.
+const MAX_TOP_UP_EVENTS: usize = 100;
 
 fn default_cycles_for_archive_creation() -> u64 {
     0
@@ -30,6 +37,39 @@ pub struct ArchiveOptions {
     // Max transactions returned by the [get_transactions] endpoint
     #[serde(default)]
     pub max_transactions_per_response: Option<usize>,
+    // The maximum number of archive nodes to create. Once the last node is
+    // full, the blocks stay in the ledger.
+    #[serde(default)]
+    pub max_archive_nodes: Option<usize>,
+    // Archive nodes whose cycle balance falls below this amount get topped up
+    // with this amount of cycles from the ledger balance.
+    #[serde(default)]
+    pub cycles_for_archive_top_up: Option<u64>,
+    // The total amount of cycles that the ledger may send to archive nodes in
+    // top ups. Top ups are unlimited if not set.
+    #[serde(default)]
+    pub cycles_for_archive_top_ups_pool: Option<u64>,
+}
+
+/// An event of the monitoring of the cycle balances of the archive nodes.
+#[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveTopUpEvent {
+    /// The time of the event in nanoseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub node: CanisterId,
+    pub kind: ArchiveTopUpEventKind,
+}
+
+#[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+pub enum ArchiveTopUpEventKind {
+    /// The ledger sent `cycles` to the node, whose balance was `balance`.
+    ToppedUp { balance: u64, cycles: u64 },
+    /// The ledger failed to send cycles to the node.
+    TopUpFailed { balance: u64, error: String },
+    /// The ledger failed to get the cycle balance of the node.
+    BalanceCheckFailed { error: String },
+    /// The node needs a top up, but the pool doesn't have enough cycles left.
+    PoolExhausted { balance: u64 },
 }
 
 /// A scope guard for block archiving.
@@ -124,6 +164,40 @@ pub struct Archive<Rt: Runtime, Wasm: ArchiveCanisterWasm> {
     #[serde(default)]
     pub max_transactions_per_response: Option<usize>,
 
+    /// The maximum number of archive nodes to create.
+    #[serde(default)]
+    pub max_archive_nodes: Option<usize>,
+    /// The cycle balance below which archive nodes get topped up, and the
+    /// amount of cycles to top them up with. Zero disables top ups.
+    #[serde(default)]
+    pub cycles_for_archive_top_up: u64,
+    /// The total amount of cycles that the ledger may send in top ups, or
+    /// `None` if top ups are unlimited.
+    #[serde(default)]
+    pub cycles_for_archive_top_ups_pool: Option<u64>,
+
+    /// The remaining capacity in bytes of archive nodes as of the last time
+    /// the ledger checked.
+    #[serde(default)]
+    nodes_remaining_capacity: BTreeMap<CanisterId, usize>,
+    /// The number of times the ledger topped up an archive node.
+    #[serde(default)]
+    num_top_ups: u64,
+    /// The total amount of cycles sent to archive nodes in top ups.
+    #[serde(default)]
+    cycles_sent_in_top_ups: u64,
+    /// The number of times the ledger failed to check the cycle balance of or
+    /// to top up an archive node.
+    #[serde(default)]
+    num_failed_top_ups: u64,
+    /// The cycle balance of archive nodes as of the last time the ledger
+    /// checked.
+    #[serde(default)]
+    nodes_cycles_balance: BTreeMap<CanisterId, u64>,
+    /// The most recent top-up events, oldest first.
+    #[serde(default)]
+    top_up_events: VecDeque<ArchiveTopUpEvent>,
+
     /// Whether there are outstanding calls to the archive at the moment.
     // We do not need to persist this flag because we cannot have any oustanding calls
     // on upgrade.
@@ -149,6 +223,15 @@ impl<Rt: Runtime, Wasm: ArchiveCanisterWasm> Archive<Rt, Wasm> {
             num_blocks_to_archive: options.num_blocks_to_archive,
             cycles_for_archive_creation: options.cycles_for_archive_creation.unwrap_or(0),
             max_transactions_per_response: options.max_transactions_per_response,
+            max_archive_nodes: options.max_archive_nodes,
+            cycles_for_archive_top_up: options.cycles_for_archive_top_up.unwrap_or(0),
+            cycles_for_archive_top_ups_pool: options.cycles_for_archive_top_ups_pool,
+            nodes_remaining_capacity: BTreeMap::new(),
+            num_top_ups: 0,
+            cycles_sent_in_top_ups: 0,
+            num_failed_top_ups: 0,
+            nodes_cycles_balance: BTreeMap::new(),
+            top_up_events: VecDeque::new(),
             archiving_in_progress: false,
             _marker: PhantomData,
         }
@@ -169,6 +252,323 @@ impl<Rt: Runtime, Wasm: ArchiveCanisterWasm> Archive<Rt, Wasm> {
     pub fn nodes(&self) -> &[CanisterId] {
         &self.nodes
     }
+
+    /// Returns the remaining capacity in bytes of the given archive node as of
+    /// the last time the ledger checked, or `None` if it is not known.
+    pub fn remaining_capacity(&self, node: &CanisterId) -> Option<usize> {
+        self.nodes_remaining_capacity.get(node).copied()
+    }
+
+    /// Returns the number of times the ledger topped up an archive node.
+    pub fn num_top_ups(&self) -> u64 {
+        self.num_top_ups
+    }
+
+    /// Returns the total amount of cycles sent to archive nodes in top ups.
+    pub fn cycles_sent_in_top_ups(&self) -> u64 {
+        self.cycles_sent_in_top_ups
+    }
+
+    /// Returns the number of times the ledger failed to check the cycle
+    /// balance of or to top up an archive node.
+    pub fn num_failed_top_ups(&self) -> u64 {
+        self.num_failed_top_ups
+    }
+
+    /// Returns the amount of cycles left in the top-up pool, or `None` if top
+    /// ups are unlimited.
+    pub fn remaining_top_up_pool(&self) -> Option<u64> {
+        self.cycles_for_archive_top_ups_pool
+            .map(|pool| pool.saturating_sub(self.cycles_sent_in_top_ups))
+    }
+
+    /// Returns the cycle balance of the given archive node as of the last time
+    /// the ledger checked, or `None` if it is not known.
+    pub fn cycles_balance(&self, node: &CanisterId) -> Option<u64> {
+        self.nodes_cycles_balance.get(node).copied()
+    }
+
+    /// Returns the most recent top-up events, oldest first.
+    pub fn top_up_events(&self) -> impl Iterator<Item = &ArchiveTopUpEvent> {
+        self.top_up_events.iter()
+    }
+
+    fn record_top_up_event(&mut self, event: ArchiveTopUpEvent) {
+        if let ArchiveTopUpEventKind::TopUpFailed { .. }
+        | ArchiveTopUpEventKind::BalanceCheckFailed { .. } = event.kind
+        {
+            self.num_failed_top_ups += 1;
+        }
+        self.top_up_events.push_back(event);
+        while self.top_up_events.len() > MAX_TOP_UP_EVENTS {
+            self.top_up_events.pop_front();
+        }
+    }
+
+    /// Returns the inclusive range of blocks stored in the given archive
+    /// node, or `None` if the node is not in the index or stores no blocks.
+    pub fn node_block_range(&self, node: &CanisterId) -> Option<(u64, u64)> {
+        let node_index = self.nodes.iter().position(|n| n == node)?;
+        self.nodes_block_ranges.get(node_index).copied()
+    }
+
+    /// Returns the archive node that stores the given block.
+    pub fn node_of_block(&self, block_index: u64) -> Option<CanisterId> {
+        self.index()
+            .into_iter()
+            .find(|((from, to), _)| (*from..=*to).contains(&block_index))
+            .map(|(_, node)| node)
+    }
+
+    /// Assigns the block range of the `old` archive node to the `new` node.
+    fn reassign_range(&mut self, old: CanisterId, new: CanisterId) {
+        let node = self
+            .nodes
+            .iter_mut()
+            .find(|n| **n == old)
+            .expect("bug: the archive node to replace is not in the index");
+        *node = new;
+        self.nodes_remaining_capacity.remove(&old);
+        self.nodes_cycles_balance.remove(&old);
+    }
+}
+
+/// The arguments of the `verify_blocks` endpoint of archive nodes.
+#[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+pub struct VerifyBlocksArgs {
+    /// The index of the first block to verify.
+    pub start: u64,
+    /// The number of blocks to verify.
+    pub length: u64,
+    /// The hash that the last block to verify must have.
+    pub last_hash: HashOf<EncodedBlock>,
+}
+
+/// A block whose hash does not match the parent hash of the block after it.
+#[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+pub struct VerifyBlocksError {
+    pub block_index: u64,
+    pub message: String,
+}
+
+/// The result of the `verify_blocks` endpoint of archive nodes: the parent
+/// hash of the first verified block, i.e., the hash that the block before
+/// the verified range must have.
+pub type VerifyBlocksResult = Result<Option<HashOf<EncodedBlock>>, VerifyBlocksError>;
+
+/// Checks that `blocks`, the blocks starting at index `start`, form a hash
+/// chain that ends in a block with hash `last_hash`. The blocks are checked
+/// from the last to the first, so the error points at the last block whose
+/// hash does not match.
+pub fn verify_block_chain<B: BlockType>(
+    blocks: &[EncodedBlock],
+    start: u64,
+    last_hash: HashOf<EncodedBlock>,
+) -> VerifyBlocksResult {
+    let mut expected_hash = Some(last_hash);
+    for (offset, block) in blocks.iter().enumerate().rev() {
+        let block_index = start + offset as u64;
+        let error = |message: String| VerifyBlocksError {
+            block_index,
+            message,
+        };
+        let hash = B::block_hash(block);
+        match expected_hash {
+            Some(expected) if expected == hash => (),
+            Some(expected) => {
+                return Err(error(format!("expected hash {}, got {}", expected, hash)))
+            }
+            None => return Err(error("the block after has no parent hash".to_string())),
+        }
+        expected_hash = B::decode(block.clone())
+            .map_err(|e| error(format!("failed to decode the block: {}", e)))?
+            .parent_hash();
+    }
+    Ok(expected_hash)
+}
+
+#[derive(Serialize, Deserialize, CandidType, Clone, Debug, PartialEq, Eq)]
+struct GetEncodedBlocksArgs {
+    start: u64,
+    length: u64,
+}
+
+/// Fetches a block from the `get_encoded_blocks` endpoint of an archive node.
+pub async fn get_encoded_block<Rt: Runtime>(
+    node: CanisterId,
+    block_index: u64,
+) -> Result<EncodedBlock, String> {
+    let args = GetEncodedBlocksArgs {
+        start: block_index,
+        length: 1,
+    };
+    let (blocks,): (Vec<EncodedBlock>,) = Rt::call(node, "get_encoded_blocks", 0, (args,))
+        .await
+        .map_err(|(code, msg)| format!("failed to fetch block {}: {} {}", block_index, code, msg))?;
+    blocks
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("archive node {} does not store block {}", node, block_index))
+}
+
+/// Asks an archive node to verify its blocks in chunks, from the last block
+/// to the first, starting from `last_hash`, the hash that the last block of
+/// the node must have according to the block after it.
+async fn verify_node_blocks<Rt: Runtime>(
+    node: CanisterId,
+    (from, to): (u64, u64),
+    last_hash: HashOf<EncodedBlock>,
+) -> Result<(), String> {
+    let mut end = to + 1;
+    let mut last_hash = Some(last_hash);
+    while end > from {
+        let start = end.saturating_sub(MAX_BLOCKS_TO_VERIFY).max(from);
+        let last_hash_of_chunk =
+            last_hash.ok_or_else(|| format!("block {} has no parent hash", end))?;
+        let args = VerifyBlocksArgs {
+            start,
+            length: end - start,
+            last_hash: last_hash_of_chunk,
+        };
+        let (result,): (VerifyBlocksResult,) = Rt::call(node, "verify_blocks", 0, (args,))
+            .await
+            .map_err(|(code, msg)| {
+                format!("failed to verify the blocks of {}: {} {}", node, code, msg)
+            })?;
+        last_hash = result.map_err(|e| {
+            format!(
+                "block {} of archive node {} is invalid: {}",
+                e.block_index, node, e.message
+            )
+        })?;
+        end = start;
+    }
+    Ok(())
+}
+
+/// Verifies the blocks of an archive node against `last_hash`, the parent
+/// hash of the block after the node's range. Returns the inclusive range of
+/// the verified blocks.
+///
+/// NB. The block after the node's range is only trustworthy if it is stored
+/// in the ledger or in a node that was verified before, so nodes should be
+/// verified from the newest to the oldest.
+pub async fn verify_node<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
+    archive: &Arc<RwLock<Option<Archive<Rt, Wasm>>>>,
+    node: CanisterId,
+    last_hash: HashOf<EncodedBlock>,
+) -> Result<(u64, u64), String> {
+    let range = inspect_archive(archive, |archive| archive.node_block_range(&node))
+        .ok_or_else(|| format!("{} is not an archive node with blocks", node))?;
+    verify_node_blocks::<Rt>(node, range, last_hash).await?;
+    Ok(range)
+}
+
+/// Copies the blocks of an archive node to a new archive node, verifies the
+/// copy against `last_hash` like `verify_node`, and assigns the node's block
+/// range to the new node. Returns the new node, the controller can delete the
+/// old one afterwards.
+///
+/// The blocks are read from the `get_encoded_blocks` endpoint of `source`,
+/// which is the node itself when it is decommissioned. If the node is
+/// corrupted, `source` can be any canister that serves a good copy of the
+/// node's blocks. The index is left unchanged if the copy is invalid.
+pub async fn rearchive_node<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
+    archive: &Arc<RwLock<Option<Archive<Rt, Wasm>>>>,
+    node: CanisterId,
+    source: CanisterId,
+    last_hash: HashOf<EncodedBlock>,
+) -> Result<CanisterId, String> {
+    let range = inspect_archive(archive, |archive| archive.node_block_range(&node))
+        .ok_or_else(|| format!("{} is not an archive node with blocks", node))?;
+
+    let new_node = create_empty_node_canister(archive)
+        .await
+        .map_err(|FailedToArchiveBlocks(msg)| msg)?;
+    // The ledger controls the new node until the copy is verified, so that it
+    // can delete the node if anything goes wrong.
+    if let Err(msg) = fill_node(archive, new_node, source, range, last_hash).await {
+        inspect_archive(archive, |archive| {
+            archive.nodes_remaining_capacity.remove(&new_node);
+        });
+        delete_node_canister::<Rt>(new_node).await;
+        return Err(msg);
+    }
+
+    inspect_archive(archive, |archive| archive.reassign_range(node, new_node));
+    Rt::print(format!(
+        "[archive] assigned blocks {}..={} from {} to {}",
+        range.0, range.1, node, new_node
+    ));
+    Ok(new_node)
+}
+
+// Helper function to install the archive node Wasm on `new_node`, copy the
+// blocks in `(from, to)` from `source` to it, verify the copy, and hand the
+// node over to the archive controller.
+async fn fill_node<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
+    archive: &Arc<RwLock<Option<Archive<Rt, Wasm>>>>,
+    new_node: CanisterId,
+    source: CanisterId,
+    (from, to): (u64, u64),
+    last_hash: HashOf<EncodedBlock>,
+) -> Result<(), String> {
+    install_node_code(archive, new_node, from)
+        .await
+        .map_err(|FailedToArchiveBlocks(msg)| msg)?;
+    Rt::print(format!(
+        "[archive] copying blocks {}..={} from {} to the new node {}",
+        from, to, source, new_node
+    ));
+
+    let mut start = from;
+    while start <= to {
+        let args = GetEncodedBlocksArgs {
+            start,
+            length: to + 1 - start,
+        };
+        let (blocks,): (Vec<EncodedBlock>,) =
+            Rt::call(source, "get_encoded_blocks", 0, (args,))
+                .await
+                .map_err(|(code, msg)| {
+                    format!("failed to fetch the blocks from {}: {} {}", source, code, msg)
+                })?;
+        if blocks.is_empty() {
+            return Err(format!("{} does not store block {}", source, start));
+        }
+        let num_blocks = blocks.len() as u64;
+        let res: Result<(), (i32, String)> =
+            Rt::call(new_node, "append_blocks", 0, (blocks,)).await;
+        res.map_err(|(code, msg)| {
+            format!("failed to append the blocks to {}: {} {}", new_node, code, msg)
+        })?;
+        start += num_blocks;
+    }
+
+    verify_node_blocks::<Rt>(new_node, (from, to), last_hash).await?;
+    fetch_remaining_capacity(archive, new_node)
+        .await
+        .map_err(|FailedToArchiveBlocks(msg)| msg)?;
+    set_node_controller(archive, new_node)
+        .await
+        .map_err(|FailedToArchiveBlocks(msg)| msg)
+}
+
+// Helper function to stop and delete an archive node canister that the
+// ledger still controls. Failures are only logged: the node is not in the
+// index, so the controller can delete it later.
+async fn delete_node_canister<Rt: Runtime>(node: CanisterId) {
+    Rt::print(format!("[archive] deleting the archive node {}", node));
+    let result = match spawn::stop_canister::<Rt>(node).await {
+        Ok(()) => spawn::delete_canister::<Rt>(node).await,
+        Err(err) => Err(err),
+    };
+    if let Err((code, msg)) = result {
+        Rt::print(format!(
+            "[archive] failed to delete the archive node {}: {} {}",
+            node, code, msg
+        ));
+    }
 }
 
 /// Grabs a write lock on the archive and executes a synchronous function under the lock.
@@ -236,6 +636,7 @@ pub async fn send_blocks_to_archive<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
         while !first_blocks.is_empty() {
             let chunk = take_prefix(&mut first_blocks, max_chunk_size);
             let chunk_len = chunk.len() as u64;
+            let chunk_size_bytes: usize = chunk.iter().map(EncodedBlock::size_bytes).sum();
             if chunk.is_empty() {
                 return Err((num_sent_blocks, FailedToArchiveBlocks("empty chunk".into())));
             }
@@ -250,6 +651,10 @@ pub async fn send_blocks_to_archive<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
 
             // Keep track of BlockIndexs
             let heights = inspect_archive(&archive, |archive| {
+                if let Some(capacity) = archive.nodes_remaining_capacity.get_mut(&node_canister_id)
+                {
+                    *capacity = capacity.saturating_sub(chunk_size_bytes);
+                }
                 let heights = archive.nodes_block_ranges.get_mut(node_index);
                 match heights {
                     // We haven't inserted any Blocks into this archive node yet.
@@ -291,35 +696,80 @@ pub async fn send_blocks_to_archive<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
 async fn create_and_initialize_node_canister<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
     archive: &Arc<RwLock<Option<Archive<Rt, Wasm>>>>,
 ) -> Result<(CanisterId, usize, usize), FailedToArchiveBlocks> {
-    Rt::print("[archive] calling create_canister()");
+    let (num_nodes, max_archive_nodes) =
+        inspect_archive(archive, |archive| (archive.nodes.len(), archive.max_archive_nodes));
+    if let Some(max_archive_nodes) = max_archive_nodes {
+        if num_nodes >= max_archive_nodes {
+            return Err(FailedToArchiveBlocks(format!(
+                "cannot create a new archive node: reached the maximum of {} archive nodes",
+                max_archive_nodes
+            )));
+        }
+    }
 
-    let (
-        cycles_for_archive_creation,
-        node_block_height_offset,
-        node_max_memory_size_bytes,
-        controller_id,
-        max_transactions_per_response,
-    ) = inspect_archive(archive, |archive| {
-        let node_block_height_offset: u64 = archive
+    let node_block_height_offset: u64 = inspect_archive(archive, |archive| {
+        archive
             .nodes_block_ranges
             .last()
             .map(|(_, height_to)| *height_to + 1)
-            .unwrap_or(0);
-        (
-            archive.cycles_for_archive_creation,
-            node_block_height_offset,
-            archive.node_max_memory_size_bytes,
-            archive.controller_id,
-            archive.max_transactions_per_response,
-        )
+            .unwrap_or(0)
     });
 
-    let node_canister_id: CanisterId = spawn::create_canister::<Rt>(cycles_for_archive_creation)
+    let node_canister_id = create_node_canister(archive, node_block_height_offset).await?;
+
+    let node_index = inspect_archive(archive, |archive| {
+        archive.nodes.push(node_canister_id);
+        archive.last_node_index()
+    });
+
+    let remaining_capacity = fetch_remaining_capacity(archive, node_canister_id).await?;
+
+    Ok((node_canister_id, node_index, remaining_capacity))
+}
+
+// Helper function to create an archive node canister for the blocks starting
+// at `node_block_height_offset`, without adding it to the index.
+async fn create_node_canister<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
+    archive: &Arc<RwLock<Option<Archive<Rt, Wasm>>>>,
+    node_block_height_offset: u64,
+) -> Result<CanisterId, FailedToArchiveBlocks> {
+    let node_canister_id = create_empty_node_canister(archive).await?;
+    install_node_code(archive, node_canister_id, node_block_height_offset).await?;
+    set_node_controller(archive, node_canister_id).await?;
+    Ok(node_canister_id)
+}
+
+// Helper function to create an empty canister for an archive node.
+async fn create_empty_node_canister<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
+    archive: &Arc<RwLock<Option<Archive<Rt, Wasm>>>>,
+) -> Result<CanisterId, FailedToArchiveBlocks> {
+    Rt::print("[archive] calling create_canister()");
+
+    let cycles_for_archive_creation =
+        inspect_archive(archive, |archive| archive.cycles_for_archive_creation);
+
+    spawn::create_canister::<Rt>(cycles_for_archive_creation)
         .await
-        .map_err(|(code, msg)| FailedToArchiveBlocks(format!("{} {}", code, msg)))?;
+        .map_err(|(code, msg)| FailedToArchiveBlocks(format!("{} {}", code, msg)))
+}
 
+// Helper function to install the archive node Wasm for the blocks starting at
+// `node_block_height_offset`.
+async fn install_node_code<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
+    archive: &Arc<RwLock<Option<Archive<Rt, Wasm>>>>,
+    node_canister_id: CanisterId,
+    node_block_height_offset: u64,
+) -> Result<(), FailedToArchiveBlocks> {
     Rt::print("[archive] calling install_code()");
 
+    let (node_max_memory_size_bytes, max_transactions_per_response) =
+        inspect_archive(archive, |archive| {
+            (
+                archive.node_max_memory_size_bytes,
+                archive.max_transactions_per_response,
+            )
+        });
+
     spawn::install_code::<Rt>(
         node_canister_id,
         Wasm::archive_wasm().into_owned(),
@@ -339,7 +789,16 @@ async fn create_and_initialize_node_canister<Rt: Runtime, Wasm: ArchiveCanisterW
             "install_code failed; reject_code={}, message={}",
             reject_code, message
         ))
-    })?;
+    })
+}
+
+// Helper function to hand an archive node over to the archive controller.
+// The ledger cannot manage the node afterwards.
+async fn set_node_controller<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
+    archive: &Arc<RwLock<Option<Archive<Rt, Wasm>>>>,
+    node_canister_id: CanisterId,
+) -> Result<(), FailedToArchiveBlocks> {
+    let controller_id = inspect_archive(archive, |archive| archive.controller_id);
 
     Rt::print(format!(
         "[archive] setting controller_id for archive node: {}",
@@ -363,18 +822,7 @@ async fn create_and_initialize_node_canister<Rt: Runtime, Wasm: ArchiveCanisterW
             code, msg
         );
         FailedToArchiveBlocks(s)
-    })?;
-
-    let node_index = inspect_archive(archive, |archive| {
-        archive.nodes.push(node_canister_id);
-        archive.last_node_index()
-    });
-
-    let (remaining_capacity,): (usize,) = Rt::call(node_canister_id, "remaining_capacity", 0, ())
-        .await
-        .map_err(|(_, msg)| FailedToArchiveBlocks(msg))?;
-
-    Ok((node_canister_id, node_index, remaining_capacity))
+    })
 }
 
 /// Helper function to find the CanisterId of the node that can accept
@@ -402,10 +850,8 @@ async fn node_and_capacity<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
         // Some archive node exists. Use it, or, if already full, create a
         // new node.
         Some(last_node_canister_id) => {
-            let (remaining_capacity,): (usize,) =
-                Rt::call(last_node_canister_id, "remaining_capacity", 0, ())
-                    .await
-                    .map_err(|(_, msg)| FailedToArchiveBlocks(msg))?;
+            let remaining_capacity =
+                fetch_remaining_capacity(archive, last_node_canister_id).await?;
 
             if remaining_capacity < needed {
                 Rt::print("[archive] last node is full. creating a new archive node");
@@ -428,6 +874,130 @@ async fn node_and_capacity<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
     }
 }
 
+/// Helper function to fetch the remaining capacity of an archive node and
+/// remember it in the archive metadata.
+async fn fetch_remaining_capacity<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
+    archive: &Arc<RwLock<Option<Archive<Rt, Wasm>>>>,
+    node_canister_id: CanisterId,
+) -> Result<usize, FailedToArchiveBlocks> {
+    let (remaining_capacity,): (usize,) = Rt::call(node_canister_id, "remaining_capacity", 0, ())
+        .await
+        .map_err(|(_, msg)| FailedToArchiveBlocks(msg))?;
+    inspect_archive(archive, |archive| {
+        archive
+            .nodes_remaining_capacity
+            .insert(node_canister_id, remaining_capacity);
+    });
+    Ok(remaining_capacity)
+}
+
+/// Tops up the archive nodes whose cycle balance is below
+/// `cycles_for_archive_top_up` from the top-up pool, and records the outcome
+/// as top-up events at time `now`. Failures, e.g. of nodes that don't report
+/// their balance yet, are only recorded and retried at the next check.
+///
+/// The balances come from the `cycles_balance` endpoint of the nodes: the
+/// ledger is not a controller of the nodes, so it cannot read their status
+/// from the management canister.
+pub async fn top_up_nodes<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
+    archive: &Arc<RwLock<Option<Archive<Rt, Wasm>>>>,
+    now: u64,
+) {
+    let (nodes, top_up_cycles) = inspect_archive(archive, |archive| {
+        (archive.nodes.clone(), archive.cycles_for_archive_top_up)
+    });
+    if top_up_cycles == 0 {
+        return;
+    }
+
+    for node_canister_id in nodes {
+        let balance: Result<(u64,), (i32, String)> =
+            Rt::call(node_canister_id, "cycles_balance", 0, ()).await;
+        let kind = match balance {
+            Ok((balance,)) => {
+                let remaining_pool = inspect_archive(archive, |archive| {
+                    archive
+                        .nodes_cycles_balance
+                        .insert(node_canister_id, balance);
+                    archive.remaining_top_up_pool()
+                });
+                if balance >= top_up_cycles {
+                    continue;
+                }
+                if remaining_pool.map_or(false, |pool| pool < top_up_cycles) {
+                    Rt::print(format!(
+                        "[archive] cannot top up node {} with balance {}: the pool is exhausted",
+                        node_canister_id, balance
+                    ));
+                    ArchiveTopUpEventKind::PoolExhausted { balance }
+                } else {
+                    top_up_node(archive, node_canister_id, balance, top_up_cycles).await
+                }
+            }
+            Err((code, msg)) => {
+                Rt::print(format!(
+                    "[archive] failed to get the cycle balance of node {}: {} {}",
+                    node_canister_id, code, msg
+                ));
+                ArchiveTopUpEventKind::BalanceCheckFailed {
+                    error: format!("{} {}", code, msg),
+                }
+            }
+        };
+        inspect_archive(archive, |archive| {
+            archive.record_top_up_event(ArchiveTopUpEvent {
+                timestamp: now,
+                node: node_canister_id,
+                kind,
+            })
+        });
+    }
+}
+
+// Helper function to send `cycles` to an archive node with the given
+// `balance`.
+async fn top_up_node<Rt: Runtime, Wasm: ArchiveCanisterWasm>(
+    archive: &Arc<RwLock<Option<Archive<Rt, Wasm>>>>,
+    node_canister_id: CanisterId,
+    balance: u64,
+    cycles: u64,
+) -> ArchiveTopUpEventKind {
+    Rt::print(format!(
+        "[archive] topping up node {} with balance {} with {} cycles",
+        node_canister_id, balance, cycles
+    ));
+    let res: Result<(), (i32, String)> = Rt::call(
+        IC_00,
+        "deposit_cycles",
+        cycles,
+        (CanisterIdRecord::from(node_canister_id),),
+    )
+    .await;
+    match res {
+        Ok(()) => {
+            inspect_archive(archive, |archive| {
+                archive.num_top_ups += 1;
+                archive.cycles_sent_in_top_ups =
+                    archive.cycles_sent_in_top_ups.saturating_add(cycles);
+                archive
+                    .nodes_cycles_balance
+                    .insert(node_canister_id, balance.saturating_add(cycles));
+            });
+            ArchiveTopUpEventKind::ToppedUp { balance, cycles }
+        }
+        Err((code, msg)) => {
+            Rt::print(format!(
+                "[archive] failed to top up node {}: {} {}",
+                node_canister_id, code, msg
+            ));
+            ArchiveTopUpEventKind::TopUpFailed {
+                balance,
+                error: format!("{} {}", code, msg),
+            }
+        }
+    }
+}
+
 /// Extract longest prefix from `blocks` which fits in `max_size`
 fn take_prefix(blocks: &mut VecDeque<EncodedBlock>, mut max_size: usize) -> Vec<EncodedBlock> {
     let mut result = vec![];
@@ -1,14 +1,16 @@
 use crate::{archive::ArchiveCanisterWasm, blockchain::Blockchain, range_utils, runtime::Runtime};
 use ic_base_types::CanisterId;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{BTreeMap, VecDeque};
 use std::ops::Range;
 use std::time::Duration;
 
-use ic_ledger_core::balances::{BalanceError, Balances, BalancesStore};
-use ic_ledger_core::block::{BlockIndex, BlockType, EncodedBlock, HashOf};
+use ic_ledger_core::balances::{
+    BalanceError, Balances, BalancesStore, InspectableBalancesStore,
+};
+use ic_ledger_core::block::{BlockIndex, BlockType, EncodedBlock, FeeCollector, HashOf};
 use ic_ledger_core::timestamp::TimeStamp;
-use ic_ledger_core::tokens::Tokens;
+use ic_ledger_core::tokens::{Tokens, TokensType};
 
 /// The memo to use for balances burned during trimming
 const TRIMMED_MEMO: u64 = u64::MAX;
@@ -21,12 +23,13 @@ pub struct TransactionInfo<TransactionType> {
 
 pub trait LedgerTransaction: Sized {
     type AccountId: std::hash::Hash + Eq;
+    type Tokens: TokensType;
 
     /// Constructs a new "burn" transaction that removes the specified `amount` of tokens from the
     /// `from` account.
     fn burn(
         from: Self::AccountId,
-        amount: Tokens,
+        amount: Self::Tokens,
         at: Option<TimeStamp>,
         memo: Option<u64>,
     ) -> Self;
@@ -37,10 +40,19 @@ pub trait LedgerTransaction: Sized {
     /// Returns the hash of this transaction.
     fn hash(&self) -> HashOf<Self>;
 
-    /// Applies this transaction to the balance book.
-    fn apply<S>(&self, balances: &mut Balances<Self::AccountId, S>) -> Result<(), BalanceError>
+    /// Returns the fee that this transaction pays, if any.
+    fn fee(&self) -> Option<Self::Tokens>;
+
+    /// Applies this transaction to the balance book. If `fee_collector` is
+    /// set, the transaction fee is credited to that account instead of being
+    /// burned.
+    fn apply<S>(
+        &self,
+        balances: &mut Balances<Self::AccountId, S>,
+        fee_collector: Option<&Self::AccountId>,
+    ) -> Result<(), BalanceError<Self::Tokens>>
     where
-        S: Default + BalancesStore<Self::AccountId>;
+        S: Default + BalancesStore<Self::AccountId, Tokens = Self::Tokens>;
 }
 
 pub trait LedgerAccess {
@@ -63,16 +75,26 @@ pub trait LedgerAccess {
 
 pub trait LedgerData {
     type AccountId: std::hash::Hash + Ord + Eq + Clone;
+    type Tokens: TokensType;
     type ArchiveWasm: ArchiveCanisterWasm;
     type Runtime: Runtime;
-    type Block: BlockType<Transaction = Self::Transaction>;
-    type Transaction: LedgerTransaction<AccountId = Self::AccountId> + Ord + Clone;
+    type Block: BlockType<Transaction = Self::Transaction, AccountId = Self::AccountId>;
+    type Transaction: LedgerTransaction<AccountId = Self::AccountId, Tokens = Self::Tokens>
+        + Ord
+        + Clone;
+    type BalancesStore: InspectableBalancesStore<Self::AccountId, Tokens = Self::Tokens> + Default;
 
     // Purge configuration
 
     /// How long the ledger needs to remembered transactions to detect duplicates.
     fn transaction_window(&self) -> Duration;
 
+    /// How far in the future the creation time of a transaction can be, to
+    /// account for clock skew between the ledger and its clients.
+    fn permitted_drift(&self) -> Duration {
+        ic_constants::PERMITTED_DRIFT
+    }
+
     /// Maximum number of transactions that this ledger will accept
     /// within the [transaction_window].
     fn max_transactions_in_window(&self) -> usize;
@@ -95,10 +117,27 @@ pub trait LedgerData {
     /// Token symbol (e.g., BTC).
     fn token_symbol(&self) -> &str;
 
+    /// The account that collects the transfer fees. The fees are burned if
+    /// the ledger has no fee collector.
+    fn fee_collector(&self) -> Option<&FeeCollector<Self::AccountId>> {
+        None
+    }
+
+    fn fee_collector_mut(&mut self) -> Option<&mut FeeCollector<Self::AccountId>> {
+        None
+    }
+
+    /// The schema version of the blocks that the ledger creates, see
+    /// [BlockType::with_schema_version]. If not set, the blocks use the
+    /// default schema of the block type.
+    fn block_schema_version(&self) -> Option<u32> {
+        None
+    }
+
     // Ledger data structures
 
-    fn balances(&self) -> &Balances<Self::AccountId, HashMap<Self::AccountId, Tokens>>;
-    fn balances_mut(&mut self) -> &mut Balances<Self::AccountId, HashMap<Self::AccountId, Tokens>>;
+    fn balances(&self) -> &Balances<Self::AccountId, Self::BalancesStore>;
+    fn balances_mut(&mut self) -> &mut Balances<Self::AccountId, Self::BalancesStore>;
 
     fn blockchain(&self) -> &Blockchain<Self::Runtime, Self::ArchiveWasm>;
     fn blockchain_mut(&mut self) -> &mut Blockchain<Self::Runtime, Self::ArchiveWasm>;
@@ -109,26 +148,46 @@ pub trait LedgerData {
     fn transactions_by_height(&self) -> &VecDeque<TransactionInfo<Self::Transaction>>;
     fn transactions_by_height_mut(&mut self) -> &mut VecDeque<TransactionInfo<Self::Transaction>>;
 
+    /// The callback that the ledger framework calls when it records a
+    /// transaction for deduplication, after it added the transaction's block
+    /// at `height`.
+    fn on_recorded_transaction(&mut self, _transaction: &Self::Transaction, _height: BlockIndex) {}
+
     /// The callback that the ledger framework calls when it purges a transaction.
     fn on_purged_transaction(&mut self, height: BlockIndex);
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
-pub enum TransferError {
-    BadFee { expected_fee: Tokens },
-    InsufficientFunds { balance: Tokens },
+pub enum TransferError<T = Tokens> {
+    BadFee { expected_fee: T },
+    InsufficientFunds { balance: T },
     TxTooOld { allowed_window_nanos: u64 },
     TxCreatedInFuture { ledger_time: TimeStamp },
     TxThrottled,
     TxDuplicate { duplicate_of: BlockIndex },
 }
 
+/// Constructs a block with the ledger's block schema version.
+fn new_block<L: LedgerData>(
+    ledger: &L,
+    parent_hash: Option<HashOf<EncodedBlock>>,
+    transaction: L::Transaction,
+    now: TimeStamp,
+    fee_collector: Option<FeeCollector<L::AccountId>>,
+) -> L::Block {
+    let block = L::Block::from_transaction(parent_hash, transaction, now, fee_collector);
+    match ledger.block_schema_version() {
+        Some(version) => block.with_schema_version(version),
+        None => block,
+    }
+}
+
 /// Adds a new block with the specified transaction to the ledger.
 pub fn apply_transaction<L: LedgerData>(
     ledger: &mut L,
     transaction: L::Transaction,
     now: TimeStamp,
-) -> Result<(BlockIndex, HashOf<EncodedBlock>), TransferError> {
+) -> Result<(BlockIndex, HashOf<EncodedBlock>), TransferError<L::Tokens>> {
     let num_pruned = purge_old_transactions(ledger, now);
 
     // If we pruned some transactions, let this one through
@@ -149,7 +208,7 @@ pub fn apply_transaction<L: LedgerData>(
             });
         }
 
-        if created_at_time > now + ic_constants::PERMITTED_DRIFT {
+        if created_at_time > now + ledger.permitted_drift() {
             return Err(TransferError::TxCreatedInFuture { ledger_time: now });
         }
 
@@ -160,15 +219,33 @@ pub fn apply_transaction<L: LedgerData>(
         }
     }
 
+    // Only the blocks that credit a fee mention the fee collector.
+    let pays_fee = transaction.fee().map_or(false, |fee| fee != L::Tokens::ZERO);
+    let fee_collector = ledger.fee_collector().cloned().filter(|_| pays_fee);
+    let credits_new_fee_collector = fee_collector
+        .as_ref()
+        .map_or(false, |fee_collector| fee_collector.block_index.is_none());
     transaction
-        .apply(ledger.balances_mut())
+        .apply(
+            ledger.balances_mut(),
+            fee_collector.as_ref().map(|fc| &fc.fee_collector),
+        )
         .map_err(|e| match e {
             BalanceError::InsufficientFunds { balance } => {
                 TransferError::InsufficientFunds { balance }
             }
         })?;
 
-    let block = L::Block::from_transaction(ledger.blockchain().last_hash, transaction, now);
+    // The callback gets the transaction once its block is in the chain.
+    let recorded_transaction = maybe_time_and_hash.map(|_| transaction.clone());
+
+    let block = new_block(
+        ledger,
+        ledger.blockchain().last_hash,
+        transaction,
+        now,
+        fee_collector,
+    );
     let block_timestamp = block.timestamp();
 
     let height = ledger
@@ -176,6 +253,14 @@ pub fn apply_transaction<L: LedgerData>(
         .add_block(block)
         .expect("failed to add block");
 
+    if credits_new_fee_collector {
+        // Subsequent blocks refer to the first block that credited a fee to
+        // the fee collector instead of repeating the account.
+        if let Some(fee_collector) = ledger.fee_collector_mut() {
+            fee_collector.block_index = Some(height);
+        }
+    }
+
     if let Some((_, tx_hash)) = maybe_time_and_hash {
         // The caller requested deduplication, so we have to remember this
         // transaction within the dedup window.
@@ -188,6 +273,9 @@ pub fn apply_transaction<L: LedgerData>(
                 transaction_hash: tx_hash,
             });
     }
+    if let Some(transaction) = recorded_transaction {
+        ledger.on_recorded_transaction(&transaction, height);
+    }
 
     let to_trim = if ledger.balances().store.len()
         >= ledger.max_number_of_accounts() + ledger.accounts_overflow_trim_quantity()
@@ -201,15 +289,13 @@ pub fn apply_transaction<L: LedgerData>(
         let burn_tx = L::Transaction::burn(account, balance, Some(now), Some(TRIMMED_MEMO));
 
         burn_tx
-            .apply(ledger.balances_mut())
+            .apply(ledger.balances_mut(), None)
             .expect("failed to burn funds that must have existed");
 
         let parent_hash = ledger.blockchain().last_hash;
+        let block = new_block(ledger, parent_hash, burn_tx, now, None);
 
-        ledger
-            .blockchain_mut()
-            .add_block(L::Block::from_transaction(parent_hash, burn_tx, now))
-            .unwrap();
+        ledger.blockchain_mut().add_block(block).unwrap();
     }
 
     Ok((height, ledger.blockchain().last_hash.unwrap()))
@@ -279,7 +365,7 @@ pub fn purge_old_transactions<L: LedgerData>(ledger: &mut L, now: TimeStamp) ->
     let mut num_tx_purged = 0usize;
 
     while let Some(tx_info) = ledger.transactions_by_height().front() {
-        if tx_info.block_timestamp + ledger.transaction_window() + ic_constants::PERMITTED_DRIFT
+        if tx_info.block_timestamp + ledger.transaction_window() + ledger.permitted_drift()
             >= now
         {
             // Stop at a sufficiently recent block.
@@ -311,28 +397,27 @@ pub fn purge_old_transactions<L: LedgerData>(ledger: &mut L, now: TimeStamp) ->
 
 // Find the specified number of accounts with lowest balances so that their
 // balances can be reclaimed.
-fn select_accounts_to_trim<L: LedgerData>(ledger: &L) -> Vec<(Tokens, L::AccountId)> {
-    let mut to_trim: std::collections::BinaryHeap<(Tokens, L::AccountId)> =
+fn select_accounts_to_trim<L: LedgerData>(ledger: &L) -> Vec<(L::Tokens, L::AccountId)> {
+    let mut to_trim: std::collections::BinaryHeap<(L::Tokens, L::AccountId)> =
         std::collections::BinaryHeap::new();
 
     let num_accounts = ledger.accounts_overflow_trim_quantity();
-    let mut iter = ledger.balances().store.iter();
-
-    // Accumulate up to `trim_quantity` accounts
-    for (account, balance) in iter.by_ref().take(num_accounts) {
-        to_trim.push((*balance, account.clone()));
-    }
 
-    for (account, balance) in iter {
+    ledger.balances().store.for_each_balance(|account, balance| {
+        // Accumulate up to `trim_quantity` accounts
+        if to_trim.len() < num_accounts {
+            to_trim.push((balance, account.clone()));
+            return;
+        }
         // If any account's balance is lower than the maximum in our set,
         // include that account, and remove the current maximum
         if let Some((greatest_balance, _)) = to_trim.peek() {
-            if balance < greatest_balance {
-                to_trim.push((*balance, account.clone()));
+            if balance < *greatest_balance {
+                to_trim.push((balance, account.clone()));
                 to_trim.pop();
             }
         }
-    }
+    });
 
     to_trim.into_vec()
 }
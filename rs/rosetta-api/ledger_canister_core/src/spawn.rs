@@ -50,3 +50,33 @@ where
     Rt::print(format!("[spawn] create_canister() = {:?}", result));
     result.map(|r| r.get_canister_id())
 }
+
+pub async fn stop_canister<Rt>(canister_id: CanisterId) -> Result<(), (i32, String)>
+where
+    Rt: Runtime,
+{
+    Rt::print(format!("[spawn] stop_canister({})", canister_id));
+
+    Rt::call(
+        IC_00,
+        "stop_canister",
+        /*cycles=*/ 0,
+        (CanisterIdRecord::from(canister_id),),
+    )
+    .await
+}
+
+pub async fn delete_canister<Rt>(canister_id: CanisterId) -> Result<(), (i32, String)>
+where
+    Rt: Runtime,
+{
+    Rt::print(format!("[spawn] delete_canister({})", canister_id));
+
+    Rt::call(
+        IC_00,
+        "delete_canister",
+        /*cycles=*/ 0,
+        (CanisterIdRecord::from(canister_id),),
+    )
+    .await
+}
@@ -1,4 +1,4 @@
-use crate::tokens::Tokens;
+use crate::tokens::{Tokens, TokensType};
 use serde::{Deserialize, Serialize};
 use std::collections::{
     hash_map::Entry::{Occupied, Vacant},
@@ -7,30 +7,53 @@ use std::collections::{
 use std::marker::PhantomData;
 
 pub trait BalancesStore<AccountId> {
+    /// The type of the balances.
+    type Tokens: TokensType;
+
     /// Returns the balance on the specified account.
-    fn get_balance(&self, k: &AccountId) -> Option<&Tokens>;
+    fn get_balance(&self, k: &AccountId) -> Option<Self::Tokens>;
 
     /// Update balance for an account using function f.
     /// Its arg is previous balance or None if not found and
     /// return value is the new balance.
-    fn update<F, E>(&mut self, acc: AccountId, action_on_acc: F) -> Result<Tokens, E>
+    fn update<F, E>(&mut self, acc: AccountId, action_on_acc: F) -> Result<Self::Tokens, E>
     where
-        F: FnMut(Option<&Tokens>) -> Result<Tokens, E>;
+        F: FnMut(Option<&Self::Tokens>) -> Result<Self::Tokens, E>;
 }
 
-impl<AccountId: std::hash::Hash + Eq> BalancesStore<AccountId> for HashMap<AccountId, Tokens> {
-    fn get_balance(&self, k: &AccountId) -> Option<&Tokens> {
-        self.get(k)
+/// A balances store that can enumerate the accounts it holds, e.g. to find
+/// the accounts with the lowest balances when the ledger has too many accounts.
+pub trait InspectableBalancesStore<AccountId>: BalancesStore<AccountId> {
+    /// Returns the number of accounts with a non-zero balance.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    fn update<F, E>(&mut self, k: AccountId, mut f: F) -> Result<Tokens, E>
+    /// Calls `f` on every account and its balance, in no particular order.
+    fn for_each_balance(&self, f: impl FnMut(&AccountId, Self::Tokens));
+}
+
+impl<AccountId, T> BalancesStore<AccountId> for HashMap<AccountId, T>
+where
+    AccountId: std::hash::Hash + Eq,
+    T: TokensType,
+{
+    type Tokens = T;
+
+    fn get_balance(&self, k: &AccountId) -> Option<T> {
+        self.get(k).copied()
+    }
+
+    fn update<F, E>(&mut self, k: AccountId, mut f: F) -> Result<T, E>
     where
-        F: FnMut(Option<&Tokens>) -> Result<Tokens, E>,
+        F: FnMut(Option<&T>) -> Result<T, E>,
     {
         match self.entry(k) {
             Occupied(mut entry) => {
                 let new_v = f(Some(entry.get()))?;
-                if new_v != Tokens::ZERO {
+                if new_v != T::ZERO {
                     *entry.get_mut() = new_v;
                 } else {
                     entry.remove_entry();
@@ -39,7 +62,7 @@ impl<AccountId: std::hash::Hash + Eq> BalancesStore<AccountId> for HashMap<Accou
             }
             Vacant(entry) => {
                 let new_v = f(None)?;
-                if new_v != Tokens::ZERO {
+                if new_v != T::ZERO {
                     entry.insert(new_v);
                 }
                 Ok(new_v)
@@ -48,12 +71,28 @@ impl<AccountId: std::hash::Hash + Eq> BalancesStore<AccountId> for HashMap<Accou
     }
 }
 
+impl<AccountId, T> InspectableBalancesStore<AccountId> for HashMap<AccountId, T>
+where
+    AccountId: std::hash::Hash + Eq,
+    T: TokensType,
+{
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn for_each_balance(&self, mut f: impl FnMut(&AccountId, T)) {
+        for (account, balance) in self.iter() {
+            f(account, *balance)
+        }
+    }
+}
+
 /// An error returned by `Balances` if the debit operation fails.
 #[derive(Debug)]
-pub enum BalanceError {
+pub enum BalanceError<T = Tokens> {
     /// An error indicating that the account doesn't hold enough funds for
     /// completing the transaction.
-    InsufficientFunds { balance: Tokens },
+    InsufficientFunds { balance: T },
 }
 
 /// Describes the state of users accounts at the tip of the chain
@@ -63,7 +102,7 @@ pub struct Balances<AccountId, S: BalancesStore<AccountId>> {
     // account balances at the tip of the chain
     pub store: S,
     #[serde(alias = "icpt_pool")]
-    pub token_pool: Tokens,
+    pub token_pool: S::Tokens,
     #[serde(skip)]
     _marker: PhantomData<AccountId>,
 }
@@ -86,7 +125,7 @@ where
     pub fn new() -> Self {
         Self {
             store: S::default(),
-            token_pool: Tokens::MAX,
+            token_pool: S::Tokens::MAX,
             _marker: PhantomData,
         }
     }
@@ -95,44 +134,70 @@ where
         &mut self,
         from: &AccountId,
         to: &AccountId,
-        amount: Tokens,
-        fee: Tokens,
-    ) -> Result<(), BalanceError> {
-        let debit_amount = (amount + fee).map_err(|_| {
-            // No account can hold more than u64::MAX.
+        amount: S::Tokens,
+        fee: S::Tokens,
+        fee_collector: Option<&AccountId>,
+    ) -> Result<(), BalanceError<S::Tokens>> {
+        let debit_amount = amount.checked_add(&fee).ok_or_else(|| {
+            // No account can hold more than S::Tokens::MAX.
             let balance = self.account_balance(from);
             BalanceError::InsufficientFunds { balance }
         })?;
         self.debit(from, debit_amount)?;
         self.credit(to, amount);
-        // NB. integer overflow is not possible here unless there is a
-        // severe bug in the system: total amount of tokens in the
-        // circulation cannot exceed u64::MAX.
-        self.token_pool += fee;
+        match fee_collector {
+            Some(fee_collector) => self.credit(fee_collector, fee),
+            // NB. integer overflow is not possible here unless there is a
+            // severe bug in the system: total amount of tokens in the
+            // circulation cannot exceed S::Tokens::MAX.
+            None => self.add_to_pool(fee),
+        }
         Ok(())
     }
 
-    pub fn burn(&mut self, from: &AccountId, amount: Tokens) -> Result<(), BalanceError> {
+    pub fn burn(
+        &mut self,
+        from: &AccountId,
+        amount: S::Tokens,
+    ) -> Result<(), BalanceError<S::Tokens>> {
         self.debit(from, amount)?;
-        self.token_pool += amount;
+        self.add_to_pool(amount);
         Ok(())
     }
 
-    pub fn mint(&mut self, to: &AccountId, amount: Tokens) -> Result<(), BalanceError> {
-        self.token_pool = (self.token_pool - amount).expect("total token supply exceeded");
+    pub fn mint(
+        &mut self,
+        to: &AccountId,
+        amount: S::Tokens,
+    ) -> Result<(), BalanceError<S::Tokens>> {
+        self.token_pool = self
+            .token_pool
+            .checked_sub(&amount)
+            .expect("total token supply exceeded");
         self.credit(to, amount);
         Ok(())
     }
 
+    fn add_to_pool(&mut self, amount: S::Tokens) {
+        self.token_pool = self
+            .token_pool
+            .checked_add(&amount)
+            .expect("bug: overflow in the token pool");
+    }
+
     // Debiting an account will automatically remove it from the `inner`
     // HashMap if the balance reaches zero.
-    pub fn debit(&mut self, from: &AccountId, amount: Tokens) -> Result<Tokens, BalanceError> {
+    pub fn debit(
+        &mut self,
+        from: &AccountId,
+        amount: S::Tokens,
+    ) -> Result<S::Tokens, BalanceError<S::Tokens>> {
         self.store.update(from.clone(), |prev| {
-            let mut balance = match prev {
+            let balance = match prev {
                 Some(x) => *x,
                 None => {
                     return Err(BalanceError::InsufficientFunds {
-                        balance: Tokens::ZERO,
+                        balance: S::Tokens::ZERO,
                     });
                 }
             };
@@ -140,44 +205,44 @@ where
                 return Err(BalanceError::InsufficientFunds { balance });
             }
 
-            balance -= amount;
-            Ok(balance)
+            Ok(balance.checked_sub(&amount).expect("bug: underflow in debit"))
         })
     }
 
     // Crediting an account will automatically add it to the `inner` HashMap if
     // not already present.
-    pub fn credit(&mut self, to: &AccountId, amount: Tokens) {
+    pub fn credit(&mut self, to: &AccountId, amount: S::Tokens) {
         self.store
             .update(
                 to.clone(),
-                |prev| -> Result<Tokens, std::convert::Infallible> {
+                |prev| -> Result<S::Tokens, std::convert::Infallible> {
                     // NB. credit cannot overflow unless there is a bug in the
                     // system: the total amount of tokens in the circulation cannot
-                    // exceed u64::MAX, so it's impossible to have more than
-                    // u64::MAX tokens on a single account.
-                    Ok((amount + *prev.unwrap_or(&Tokens::ZERO)).expect("bug: overflow in credit"))
+                    // exceed S::Tokens::MAX, so it's impossible to have more than
+                    // S::Tokens::MAX tokens on a single account.
+                    Ok(amount
+                        .checked_add(prev.unwrap_or(&S::Tokens::ZERO))
+                        .expect("bug: overflow in credit"))
                 },
             )
             .unwrap();
     }
 
-    pub fn account_balance(&self, account: &AccountId) -> Tokens {
-        self.store
-            .get_balance(account)
-            .cloned()
-            .unwrap_or(Tokens::ZERO)
+    pub fn account_balance(&self, account: &AccountId) -> S::Tokens {
+        self.store.get_balance(account).unwrap_or(S::Tokens::ZERO)
     }
 
     /// Returns the total quantity of Tokens that are "in existence" -- that
     /// is, excluding un-minted "potential" Tokens.
-    pub fn total_supply(&self) -> Tokens {
-        (Tokens::MAX - self.token_pool).unwrap_or_else(|e| {
-            panic!(
-                "It is expected that the token_pool is always smaller than \
-            or equal to Tokens::MAX, yet subtracting it lead to the following error: {}",
-                e
-            )
-        })
+    pub fn total_supply(&self) -> S::Tokens {
+        S::Tokens::MAX
+            .checked_sub(&self.token_pool)
+            .unwrap_or_else(|| {
+                panic!(
+                    "It is expected that the token_pool is always smaller than \
+            or equal to the maximum amount, yet it is {}",
+                    self.token_pool
+                )
+            })
     }
 }
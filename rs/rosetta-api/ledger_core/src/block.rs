@@ -169,22 +169,43 @@ impl EncodedBlock {
     }
 }
 
+/// The account that collects the transfer fees instead of burning them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FeeCollector<AccountId> {
+    pub fee_collector: AccountId,
+    /// The index of the first block that credited fees to `fee_collector`.
+    /// The blocks after it refer to that block instead of repeating the
+    /// account.
+    pub block_index: Option<BlockIndex>,
+}
+
+impl<AccountId> From<AccountId> for FeeCollector<AccountId> {
+    fn from(fee_collector: AccountId) -> Self {
+        Self {
+            fee_collector,
+            block_index: None,
+        }
+    }
+}
+
 pub trait BlockType: Sized {
     type Transaction;
+    type AccountId;
 
     /// Constructs a new block containing the given transaction.
     ///
     /// Law:
     ///
     /// ```text
-    /// forall PH, TX, TS:
-    ///     from_transaction(PH, TX, TS).parent_hash() = PH
-    ///   ∧ from_transaction(PH, TX, TS).timestamp() = TS
+    /// forall PH, TX, TS, FC:
+    ///     from_transaction(PH, TX, TS, FC).parent_hash() = PH
+    ///   ∧ from_transaction(PH, TX, TS, FC).timestamp() = TS
     /// ```
     fn from_transaction(
         parent_hash: Option<HashOf<EncodedBlock>>,
         tx: Self::Transaction,
         block_timestamp: TimeStamp,
+        fee_collector: Option<FeeCollector<Self::AccountId>>,
     ) -> Self;
 
     /// Encodes this block into a binary representation.
@@ -225,4 +246,12 @@ pub trait BlockType: Sized {
 
     /// Returns the time at which the ledger constructed this block.
     fn timestamp(&self) -> TimeStamp;
+
+    /// Returns this block with the given version of the block schema, which
+    /// determines how the block is encoded.
+    ///
+    /// Block types that have a single encoding ignore the version.
+    fn with_schema_version(self, _version: u32) -> Self {
+        self
+    }
 }
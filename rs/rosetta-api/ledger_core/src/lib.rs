@@ -2,5 +2,6 @@ pub mod balances;
 pub mod block;
 pub mod timestamp;
 pub mod tokens;
+pub mod tokens_u256;
 
 pub use tokens::Tokens;
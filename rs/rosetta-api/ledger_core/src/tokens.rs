@@ -1,8 +1,44 @@
-use candid::CandidType;
+use candid::{CandidType, Nat};
 use core::ops::{Add, AddAssign, Sub, SubAssign};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::fmt;
 
+/// A type of token amounts that ledgers can hold.
+///
+/// The ledger code is generic over the amount type, so that ledgers of tokens
+/// with many decimals can use a wider type than [Tokens]: with 18 decimals,
+/// `u64::MAX` units are only about 18.4 tokens. See
+/// [U256](crate::tokens_u256::U256).
+pub trait TokensType:
+    Copy
+    + fmt::Debug
+    + fmt::Display
+    + Default
+    + Eq
+    + Ord
+    + std::hash::Hash
+    + Serialize
+    + DeserializeOwned
+{
+    const ZERO: Self;
+    /// The largest amount, which is also the total supply limit of ledgers.
+    const MAX: Self;
+
+    /// Constructs an amount from the number of the smallest units.
+    fn from_u64(units: u64) -> Self;
+
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+
+    fn checked_sub(&self, other: &Self) -> Option<Self>;
+
+    /// Returns the number of the smallest units.
+    fn to_nat(&self) -> Nat;
+
+    /// Constructs an amount from the number of the smallest units. Returns an
+    /// error if the number does not fit into the type.
+    fn try_from_nat(n: &Nat) -> Result<Self, String>;
+}
+
 #[derive(
     Serialize,
     Deserialize,
@@ -159,6 +195,43 @@ impl SubAssign for Tokens {
     }
 }
 
+/// ```
+/// # use ic_ledger_core::tokens::{Tokens, TokensType};
+/// # use candid::Nat;
+/// let tokens = Tokens::from_e8s(1200000200);
+/// assert_eq!(tokens.to_nat(), Nat::from(1200000200u64));
+/// assert_eq!(Tokens::try_from_nat(&tokens.to_nat()), Ok(tokens));
+/// assert!(Tokens::try_from_nat(&Nat(Nat::from(u64::MAX).0 + 1u32)).is_err());
+/// ```
+impl TokensType for Tokens {
+    const ZERO: Self = Tokens::ZERO;
+    const MAX: Self = Tokens::MAX;
+
+    fn from_u64(units: u64) -> Self {
+        Tokens::from_e8s(units)
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        self.e8s.checked_add(other.e8s).map(Tokens::from_e8s)
+    }
+
+    fn checked_sub(&self, other: &Self) -> Option<Self> {
+        self.e8s.checked_sub(other.e8s).map(Tokens::from_e8s)
+    }
+
+    fn to_nat(&self) -> Nat {
+        Nat::from(self.e8s)
+    }
+
+    fn try_from_nat(n: &Nat) -> Result<Self, String> {
+        match n.0.to_u64_digits()[..] {
+            [] => Ok(Tokens::ZERO),
+            [e8s] => Ok(Tokens::from_e8s(e8s)),
+            _ => Err(format!("amount {} does not fit into 64 bits", n)),
+        }
+    }
+}
+
 /// ```
 /// # use ic_ledger_core::Tokens;
 /// let token = Tokens::new(12, 200).unwrap();
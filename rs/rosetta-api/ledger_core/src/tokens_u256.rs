@@ -0,0 +1,133 @@
+use crate::tokens::TokensType;
+use candid::Nat;
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// An amount of tokens as a 256-bit number of the smallest units.
+///
+/// Ledgers of tokens with many decimals, e.g., 18 like ETH, use this type
+/// instead of [Tokens](crate::Tokens), which overflows at about 18.4 such
+/// tokens.
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Hash, Debug, PartialEq, Eq, PartialOrd, Ord, Default,
+)]
+pub struct U256 {
+    // The field order makes the derived ordering numeric.
+    hi: u128,
+    lo: u128,
+}
+
+impl U256 {
+    pub const ZERO: Self = U256 { hi: 0, lo: 0 };
+    pub const MAX: Self = U256 {
+        hi: u128::MAX,
+        lo: u128::MAX,
+    };
+
+    /// ```
+    /// # use ic_ledger_core::tokens_u256::U256;
+    /// let amount = U256::from_words(1, 2);
+    /// assert_eq!(amount.to_string(), "340282366920938463463374607431768211458");
+    /// ```
+    pub const fn from_words(hi: u128, lo: u128) -> Self {
+        U256 { hi, lo }
+    }
+
+    pub const fn from_u128(n: u128) -> Self {
+        U256 { hi: 0, lo: n }
+    }
+
+    /// Returns the amount if it fits into 64 bits.
+    pub fn try_as_u64(&self) -> Option<u64> {
+        if self.hi == 0 {
+            u64::try_from(self.lo).ok()
+        } else {
+            None
+        }
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        bytes[..16].copy_from_slice(&self.hi.to_be_bytes());
+        bytes[16..].copy_from_slice(&self.lo.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let (hi, lo) = bytes.split_at(16);
+        U256 {
+            hi: u128::from_be_bytes(hi.try_into().unwrap()),
+            lo: u128::from_be_bytes(lo.try_into().unwrap()),
+        }
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(n: u64) -> Self {
+        U256::from_u128(n as u128)
+    }
+}
+
+impl From<u128> for U256 {
+    fn from(n: u128) -> Self {
+        U256::from_u128(n)
+    }
+}
+
+/// ```
+/// # use ic_ledger_core::tokens::TokensType;
+/// # use ic_ledger_core::tokens_u256::U256;
+/// # use candid::Nat;
+/// let amount = U256::from(u128::MAX);
+/// let sum = amount.checked_add(&U256::from(1u64)).unwrap();
+/// assert_eq!(sum, U256::from_words(1, 0));
+/// assert_eq!(sum.checked_sub(&U256::from(1u64)), Some(amount));
+/// assert_eq!(U256::MAX.checked_add(&U256::from(1u64)), None);
+/// assert_eq!(U256::ZERO.checked_sub(&U256::from(1u64)), None);
+///
+/// assert_eq!(U256::try_from_nat(&sum.to_nat()), Ok(sum));
+/// let too_large = Nat(U256::MAX.to_nat().0 + 1u32);
+/// assert!(U256::try_from_nat(&too_large).is_err());
+/// ```
+impl TokensType for U256 {
+    const ZERO: Self = U256::ZERO;
+    const MAX: Self = U256::MAX;
+
+    fn from_u64(units: u64) -> Self {
+        U256::from(units)
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        let (lo, carry) = self.lo.overflowing_add(other.lo);
+        let hi = self.hi.checked_add(other.hi)?.checked_add(carry as u128)?;
+        Some(U256 { hi, lo })
+    }
+
+    fn checked_sub(&self, other: &Self) -> Option<Self> {
+        let (lo, borrow) = self.lo.overflowing_sub(other.lo);
+        let hi = self.hi.checked_sub(other.hi)?.checked_sub(borrow as u128)?;
+        Some(U256 { hi, lo })
+    }
+
+    fn to_nat(&self) -> Nat {
+        Nat(BigUint::from_bytes_be(&self.to_be_bytes()))
+    }
+
+    fn try_from_nat(n: &Nat) -> Result<Self, String> {
+        let bytes = n.0.to_bytes_be();
+        if bytes.len() > 32 {
+            return Err(format!("amount {} does not fit into 256 bits", n));
+        }
+        let mut padded = [0u8; 32];
+        padded[32 - bytes.len()..].copy_from_slice(&bytes);
+        Ok(U256::from_be_bytes(padded))
+    }
+}
+
+/// Displays the number of the smallest units.
+impl fmt::Display for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_nat().0)
+    }
+}
@@ -111,6 +111,7 @@ impl From<BlockStoreError> for ApiError {
             BlockStoreError::NotAvailable(idx) => {
                 ApiError::invalid_block_id(format!("Block not available for query: {}", idx))
             }
+            BlockStoreError::Empty => ApiError::internal_error("Blockchain is empty"),
             BlockStoreError::Other(msg) => ApiError::internal_error(msg),
         }
     }
@@ -123,6 +124,12 @@ impl From<ic_ledger_canister_blocks_synchronizer::errors::Error> for ApiError {
             Error::InvalidBlockId(err) => ApiError::invalid_block_id(err),
             Error::InternalError(err) => ApiError::internal_error(err),
             Error::InvalidTipOfChain(err) => ApiError::invalid_tip_of_chain(err),
+            Error::FetchFailed { .. } | Error::InsufficientDiskSpace { .. } => {
+                ApiError::InternalError(true, e.to_string().into())
+            }
+            Error::ParentHashMismatch { .. }
+            | Error::StoreError(_)
+            | Error::CertificationFailed(_) => ApiError::internal_error(e.to_string()),
         }
     }
 }
@@ -12,7 +12,7 @@ mod handle_start_dissolve;
 mod handle_stop_dissolve;
 mod neuron_response;
 
-use core::ops::Deref;
+use core::ops::{Deref, Range};
 use std::convert::TryFrom;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
@@ -27,14 +27,19 @@ use dfn_candid::CandidOne;
 use ic_ledger_canister_blocks_synchronizer::blocks::Blocks;
 use ic_ledger_canister_blocks_synchronizer::canister_access::CanisterAccess;
 use ic_ledger_canister_blocks_synchronizer::certification::VerificationInfo;
+use ic_ledger_canister_blocks_synchronizer::disk_space::DiskSpaceWatchdog;
 use ic_ledger_canister_blocks_synchronizer::ledger_blocks_sync::{
-    LedgerBlocksSynchronizer, LedgerBlocksSynchronizerMetrics,
+    LedgerBlocksSynchronizer, LedgerBlocksSynchronizerMetrics, SyncStatus,
 };
+use ic_ledger_core::block::EncodedBlock;
 use ic_nns_governance::pb::v1::{manage_neuron::NeuronIdOrSubaccount, GovernanceError, NeuronInfo};
 use ic_types::messages::{HttpCallContent, MessageId};
 use ic_types::CanisterId;
 use ic_types::{crypto::threshold_sig::ThresholdSigPublicKey, messages::SignedRequestBytes};
-use icp_ledger::{BlockIndex, Symbol, TransferFee, TransferFeeArgs, DEFAULT_TRANSFER_FEE};
+use icp_ledger::{
+    protobuf, tokens_from_proto, AccountBalanceArgs, AccountIdentifier, BlockIndex, Symbol,
+    Tokens, TransferFee, TransferFeeArgs, DEFAULT_TRANSFER_FEE,
+};
 use on_wire::{FromWire, IntoWire};
 
 use crate::convert;
@@ -55,13 +60,6 @@ use crate::request::Request;
 use crate::request_types::{RequestType, Status};
 use crate::transaction_id::TransactionIdentifier;
 
-fn waiter() -> garcon::Delay {
-    garcon::Delay::builder()
-        .throttle(std::time::Duration::from_millis(500))
-        .timeout(std::time::Duration::from_secs(60 * 5))
-        .build()
-}
-
 struct LedgerBlocksSynchronizerMetricsImpl {}
 
 impl LedgerBlocksSynchronizerMetrics for LedgerBlocksSynchronizerMetricsImpl {
@@ -76,6 +74,18 @@ impl LedgerBlocksSynchronizerMetrics for LedgerBlocksSynchronizerMetricsImpl {
     fn set_verified_height(&self, height: u64) {
         crate::rosetta_server::VERIFIED_HEIGHT.set(height as i64);
     }
+
+    fn set_available_disk_space(&self, bytes: u64) {
+        crate::rosetta_server::STORE_AVAILABLE_DISK_SPACE.set(bytes as i64);
+    }
+
+    fn set_sync_paused(&self, paused: bool) {
+        crate::rosetta_server::SYNC_PAUSED.set(paused as i64);
+    }
+
+    fn add_store_mismatches(&self, count: u64) {
+        crate::rosetta_server::STORE_MISMATCHES.inc_by(count);
+    }
 }
 
 #[async_trait]
@@ -83,6 +93,10 @@ pub trait LedgerAccess {
     // Maybe we should just return RwLockReadGuard explicitly and drop the Box
     async fn read_blocks<'a>(&'a self) -> Box<dyn Deref<Target = Blocks> + 'a>;
     async fn sync_blocks(&self, stopped: Arc<AtomicBool>) -> Result<(), ApiError>;
+    async fn sync_status(&self) -> Result<SyncStatus, ApiError> {
+        let blocks = self.read_blocks().await;
+        SyncStatus::from_store(&blocks, None).map_err(ApiError::from)
+    }
     fn ledger_canister_id(&self) -> &CanisterId;
     fn governance_canister_id(&self) -> &CanisterId;
     fn token_symbol(&self) -> &str;
@@ -94,6 +108,23 @@ pub trait LedgerAccess {
         verified: bool,
     ) -> Result<NeuronInfo, ApiError>;
     async fn transfer_fee(&self) -> Result<TransferFee, ApiError>;
+    /// Queries the index of the tip of the chain from the ledger canister.
+    async fn ledger_tip_index(&self) -> Result<BlockIndex, ApiError> {
+        Err(ApiError::NotAvailableOffline(false, Details::default()))
+    }
+    /// Queries the current balance of `account` from the ledger canister,
+    /// bypassing the local store.
+    async fn ledger_account_balance(&self, _account: AccountIdentifier) -> Result<Tokens, ApiError> {
+        Err(ApiError::NotAvailableOffline(false, Details::default()))
+    }
+    /// Queries the blocks in `range` from the ledger canister or its
+    /// archives. Might return fewer blocks than requested.
+    async fn query_archived_blocks(
+        &self,
+        _range: Range<BlockIndex>,
+    ) -> Result<Vec<EncodedBlock>, ApiError> {
+        Err(ApiError::NotAvailableOffline(false, Details::default()))
+    }
 }
 
 pub struct LedgerClient {
@@ -127,6 +158,9 @@ impl LedgerClient {
         governance_canister_id: CanisterId,
         store_location: Option<&std::path::Path>,
         store_max_blocks: Option<u64>,
+        store_min_available_bytes: Option<u64>,
+        secondary_store_location: Option<&std::path::Path>,
+        store_snapshot_location: Option<&std::path::Path>,
         offline: bool,
         root_key: Option<ThresholdSigPublicKey>,
     ) -> Result<LedgerClient, ApiError> {
@@ -155,6 +189,21 @@ impl LedgerClient {
             Box::new(LedgerBlocksSynchronizerMetricsImpl {}),
         )
         .await?;
+        let ledger_blocks_synchronizer = match (store_location, store_min_available_bytes) {
+            (Some(location), Some(min_available_bytes)) => ledger_blocks_synchronizer
+                .with_disk_space_watchdog(DiskSpaceWatchdog::new(location, min_available_bytes)),
+            _ => ledger_blocks_synchronizer,
+        };
+        let ledger_blocks_synchronizer = match store_snapshot_location {
+            Some(location) => ledger_blocks_synchronizer
+                .with_snapshot(&Blocks::open_read_only(location)?)?,
+            None => ledger_blocks_synchronizer,
+        };
+        let ledger_blocks_synchronizer = match secondary_store_location {
+            Some(location) => ledger_blocks_synchronizer
+                .with_secondary_store(Blocks::new_persistent(location)?)?,
+            None => ledger_blocks_synchronizer,
+        };
 
         Ok(Self {
             ledger_blocks_synchronizer,
@@ -177,12 +226,8 @@ impl LedgerClient {
             .map_err(|e| ApiError::internal_error(format!("Serialization failed: {:?}", e)))?;
 
         let symbol_res: Result<Symbol, String> = canister_access
-            .agent
-            .query(&canister_access.canister_id.get().0, "symbol")
-            .with_arg(arg)
-            .call()
+            .query_with_failover(canister_access.canister_id, "symbol", arg)
             .await
-            .map_err(|e| format!("{}", e))
             .and_then(|bytes| CandidOne::from_bytes(bytes).map(|c| c.0));
 
         match symbol_res {
@@ -225,6 +270,13 @@ impl LedgerAccess for LedgerClient {
             .map_err(ApiError::from)
     }
 
+    async fn sync_status(&self) -> Result<SyncStatus, ApiError> {
+        self.ledger_blocks_synchronizer
+            .sync_status()
+            .await
+            .map_err(ApiError::from)
+    }
+
     fn ledger_canister_id(&self) -> &CanisterId {
         &self.canister_id
     }
@@ -292,31 +344,22 @@ impl LedgerAccess for LedgerClient {
             return Err(ApiError::NotAvailableOffline(false, Details::default()));
         }
 
-        let agent = &self.canister_access.as_ref().unwrap().agent;
+        let canister = self.canister_access.as_ref().unwrap();
 
         let arg = CandidOne(acc_id)
             .into_bytes()
             .map_err(|e| ApiError::internal_error(format!("Serialization failed: {:?}", e)))?;
+        let method = "get_neuron_info_by_id_or_subaccount";
         let bytes = if verified {
-            agent
-                .update(
-                    &self.governance_canister_id.get().0,
-                    "get_neuron_info_by_id_or_subaccount",
-                )
-                .with_arg(arg)
-                .call_and_wait(waiter())
+            canister
+                .update_with_failover(self.governance_canister_id, method, arg)
                 .await
         } else {
-            agent
-                .query(
-                    &self.governance_canister_id.get().0,
-                    "get_neuron_info_by_id_or_subaccount",
-                )
-                .with_arg(arg)
-                .call()
+            canister
+                .query_with_failover(self.governance_canister_id, method, arg)
                 .await
         }
-        .map_err(|e| ApiError::invalid_request(format!("{}", e)))?;
+        .map_err(ApiError::invalid_request)?;
         let ninfo: Result<Result<NeuronInfo, GovernanceError>, _> =
             CandidOne::from_bytes(bytes).map(|c| c.0);
         let ninfo = ninfo.map_err(|e| {
@@ -342,15 +385,13 @@ impl LedgerAccess for LedgerClient {
     }
 
     async fn transfer_fee(&self) -> Result<TransferFee, ApiError> {
-        let agent = &self.canister_access.as_ref().unwrap().agent;
+        let canister = self.canister_access.as_ref().unwrap();
         let arg = CandidOne(TransferFeeArgs {})
             .into_bytes()
             .map_err(|e| ApiError::internal_error(format!("Serialization failed: {:?}", e)))?;
 
-        let res = agent
-            .query(&self.canister_id.get().0, "transfer_fee")
-            .with_arg(arg)
-            .call()
+        let res = canister
+            .query_with_failover(self.canister_id, "transfer_fee", arg)
             .await;
 
         // Older Ledger versions may not have the transfer_fee method. Ideally
@@ -377,6 +418,46 @@ impl LedgerAccess for LedgerClient {
             }),
         }
     }
+
+    async fn ledger_tip_index(&self) -> Result<BlockIndex, ApiError> {
+        if self.offline {
+            return Err(ApiError::NotAvailableOffline(false, Details::default()));
+        }
+        let canister = self.canister_access.as_ref().unwrap();
+        let tip = canister.query_tip().await.map_err(|e| {
+            ApiError::internal_error(format!("Error querying the tip of the chain: {}", e))
+        })?;
+        Ok(tip.tip_index)
+    }
+
+    async fn ledger_account_balance(&self, account: AccountIdentifier) -> Result<Tokens, ApiError> {
+        if self.offline {
+            return Err(ApiError::NotAvailableOffline(false, Details::default()));
+        }
+        let canister = self.canister_access.as_ref().unwrap();
+        canister
+            .query::<AccountBalanceArgs, protobuf::Tokens>(
+                "account_balance_pb",
+                AccountBalanceArgs::new(account),
+            )
+            .await
+            .map(tokens_from_proto)
+            .map_err(|e| ApiError::internal_error(format!("Error querying account_balance: {}", e)))
+    }
+
+    async fn query_archived_blocks(
+        &self,
+        range: Range<BlockIndex>,
+    ) -> Result<Vec<EncodedBlock>, ApiError> {
+        if self.offline {
+            return Err(ApiError::NotAvailableOffline(false, Details::default()));
+        }
+        let canister = self.canister_access.as_ref().unwrap();
+        canister
+            .query_blocks(range.start, range.end)
+            .await
+            .map_err(|e| ApiError::internal_error(format!("Error querying blocks: {}", e)))
+    }
 }
 
 impl LedgerClient {
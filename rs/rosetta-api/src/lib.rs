@@ -2,6 +2,7 @@ pub mod convert;
 pub mod errors;
 pub mod ledger_client;
 pub mod models;
+pub mod reconciliation;
 pub mod request;
 pub mod request_handler;
 pub mod request_types;
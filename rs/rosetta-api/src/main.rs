@@ -6,7 +6,7 @@ use ic_rosetta_api::rosetta_server::{RosettaApiServer, RosettaApiServerOpt};
 use ic_rosetta_api::{ledger_client, DEFAULT_BLOCKCHAIN, DEFAULT_TOKEN_SYMBOL};
 use ic_types::crypto::threshold_sig::ThresholdSigPublicKey;
 use ic_types::{CanisterId, PrincipalId};
-use std::{path::Path, path::PathBuf, str::FromStr, sync::Arc};
+use std::{path::Path, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
 use url::Url;
 
 #[derive(Debug, Parser)]
@@ -40,6 +40,22 @@ struct Opt {
     store_location: PathBuf,
     #[clap(long = "store-max-blocks")]
     store_max_blocks: Option<u64>,
+    /// Pause syncing while fewer bytes are available on the file system
+    /// holding the store. Syncing resumes once space is freed.
+    #[clap(long = "store-min-available-bytes")]
+    store_min_available_bytes: Option<u64>,
+    /// Also write the synced blocks to a second sqlite store at this location
+    /// and compare both stores after every sync. Used to migrate between
+    /// stores without resyncing.
+    #[clap(long = "secondary-store-location")]
+    secondary_store_location: Option<PathBuf>,
+    /// Initialize an empty store with the blocks of the sqlite store at this
+    /// location and only sync the newer blocks from the ledger. The first sync
+    /// verifies the imported blocks against the certified tip, and syncs all
+    /// the blocks from the ledger instead if they don't match. The snapshot
+    /// store is only read. Not supported with --store-max-blocks.
+    #[clap(long = "store-snapshot-location")]
+    store_snapshot_location: Option<PathBuf>,
     #[clap(long = "exit-on-sync")]
     exit_on_sync: bool,
     #[clap(long = "offline")]
@@ -53,6 +69,11 @@ struct Opt {
     not_whitelisted: bool,
     #[clap(long = "expose-metrics")]
     expose_metrics: bool,
+    /// Periodically recompute the balances of all accounts from the synced
+    /// blocks and compare them with the store and the ledger. Requires an
+    /// unpruned store.
+    #[clap(long = "reconciliation-interval-secs")]
+    reconciliation_interval_secs: Option<u64>,
 }
 
 #[actix_web::main]
@@ -155,12 +176,16 @@ async fn main() -> std::io::Result<()> {
 
     let Opt {
         store_max_blocks,
+        store_min_available_bytes,
+        secondary_store_location,
+        store_snapshot_location,
         offline,
         exit_on_sync,
         mainnet,
         not_whitelisted,
         expose_metrics,
         blockchain,
+        reconciliation_interval_secs,
         ..
     } = opt;
     let client = ledger_client::LedgerClient::new(
@@ -170,6 +195,9 @@ async fn main() -> std::io::Result<()> {
         governance_canister_id,
         store_location,
         store_max_blocks,
+        store_min_available_bytes,
+        secondary_store_location.as_deref(),
+        store_snapshot_location.as_deref(),
         offline,
         root_key,
     )
@@ -196,6 +224,7 @@ async fn main() -> std::io::Result<()> {
         offline,
         mainnet,
         not_whitelisted,
+        reconciliation_interval: reconciliation_interval_secs.map(Duration::from_secs),
     })
     .await
     .unwrap();
@@ -0,0 +1,437 @@
+//! Checks the balances stored by Rosetta against balances recomputed from
+//! the synced blocks and against the balances reported by the ledger.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use ic_ledger_core::block::BlockType;
+use icp_ledger::{AccountIdentifier, Block, BlockIndex, Operation, Tokens};
+use log::{info, warn};
+
+use crate::errors::ApiError;
+use crate::ledger_client::LedgerAccess;
+
+// Number of blocks read from the store at once while replaying the chain.
+const REPLAY_BATCH_SIZE: u64 = 10000;
+
+// Number of balances queried from the ledger between two checks that the
+// tip of the ledger didn't move.
+const LEDGER_QUERY_BATCH_SIZE: usize = 100;
+
+/// A balance reported by the ledger.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LedgerBalance {
+    /// The tip of the ledger while the balance was queried.
+    pub block_index: BlockIndex,
+    /// The balance reported by the ledger.
+    pub balance: Tokens,
+    /// The balance computed by replaying all the blocks up to `block_index`.
+    pub recomputed: Tokens,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BalanceMismatch {
+    pub account: AccountIdentifier,
+    /// The balance computed by replaying all the blocks up to the tip.
+    pub recomputed: Tokens,
+    /// The balance at the tip according to the local store.
+    pub stored: Tokens,
+    /// The balance reported by the ledger, if it was compared.
+    pub ledger: Option<LedgerBalance>,
+    /// The first block at which the balance history in the store diverges
+    /// from the recomputed one, if it does.
+    pub first_divergent_block: Option<BlockIndex>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReconciliationReport {
+    /// The index of the latest verified block that was reconciled.
+    pub tip_index: BlockIndex,
+    pub accounts_checked: usize,
+    /// The number of accounts that were compared against the ledger.
+    pub accounts_compared_with_ledger: usize,
+    /// The number of accounts that were not compared against the ledger
+    /// because its tip moved while their balances were queried or the store
+    /// didn't have the blocks up to the tip of the ledger yet.
+    pub accounts_skipped_for_ledger: usize,
+    pub mismatches: Vec<BalanceMismatch>,
+}
+
+/// Recomputes the balances of all the accounts from the verified blocks in
+/// the store and compares them to the balances in the store and to the
+/// balances reported by the ledger.
+///
+/// The balances of the ledger are queried in batches. Every batch is pinned
+/// to the tip of the ledger before the batch and is compared with the
+/// balances recomputed up to that block. A batch is skipped if the tip of
+/// the ledger moves while it is queried or if the store doesn't have the
+/// verified blocks up to the tip of the ledger yet. The reconciliation fails
+/// if it couldn't compare any account with the ledger.
+///
+/// The store must not be pruned. The tip and the stored balances are read
+/// under one read lock of the store. The verified blocks up to the tip don't
+/// change afterwards, so the replay only locks the store while it reads a
+/// batch of blocks.
+pub async fn reconcile_balances(
+    ledger: &(dyn LedgerAccess + Send + Sync),
+) -> Result<ReconciliationReport, ApiError> {
+    let (tip_index, stored) = {
+        let blocks = ledger.read_blocks().await;
+        let first = blocks.get_first_verified_hashed_block()?;
+        if first.index != 0 {
+            return Err(ApiError::internal_error(format!(
+                "Cannot reconcile balances: the store is pruned up to block {}",
+                first.index
+            )));
+        }
+        let tip_index = blocks.get_latest_verified_hashed_block()?.index;
+
+        let mut stored = HashMap::new();
+        for account in blocks.get_all_accounts()? {
+            stored.insert(account, blocks.get_account_balance(&account, &tip_index)?);
+        }
+        (tip_index, stored)
+    };
+    let recomputed = replay_balances(ledger, tip_index).await?;
+
+    let mut all_accounts: Vec<AccountIdentifier> = recomputed
+        .keys()
+        .chain(stored.keys())
+        .cloned()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    all_accounts.sort();
+
+    let comparison = query_ledger_balances(ledger, tip_index, &recomputed, &all_accounts).await?;
+    let ledger_balances = comparison.balances;
+
+    let mut mismatches = vec![];
+    for account in &all_accounts {
+        let recomputed = recomputed.get(account).cloned().unwrap_or(Tokens::ZERO);
+        let stored = stored.get(account).cloned().unwrap_or(Tokens::ZERO);
+        let ledger = ledger_balances.get(account).cloned();
+        if recomputed != stored || ledger.as_ref().map_or(false, |l| l.balance != l.recomputed) {
+            mismatches.push(BalanceMismatch {
+                account: *account,
+                recomputed,
+                stored,
+                ledger,
+                first_divergent_block: None,
+            });
+        }
+    }
+
+    let diverging: HashSet<AccountIdentifier> = mismatches
+        .iter()
+        .filter(|m| m.recomputed != m.stored)
+        .map(|m| m.account)
+        .collect();
+    if !diverging.is_empty() {
+        let histories = replay_histories(ledger, tip_index, &diverging).await?;
+        for mismatch in mismatches.iter_mut() {
+            if !diverging.contains(&mismatch.account) {
+                continue;
+            }
+            let stored_history: BTreeMap<BlockIndex, Tokens> = ledger
+                .read_blocks()
+                .await
+                .get_account_balance_history(&mismatch.account, Some(tip_index))?
+                .into_iter()
+                .collect();
+            let empty = BTreeMap::new();
+            let recomputed_history = histories.get(&mismatch.account).unwrap_or(&empty);
+            mismatch.first_divergent_block =
+                first_divergence(recomputed_history, &stored_history);
+        }
+    }
+
+    let report = ReconciliationReport {
+        tip_index,
+        accounts_checked: all_accounts.len(),
+        accounts_compared_with_ledger: ledger_balances.len(),
+        accounts_skipped_for_ledger: comparison.skipped,
+        mismatches,
+    };
+    info!(
+        "Reconciled {} accounts at block {} ({} compared with the ledger, {} skipped): {} mismatches",
+        report.accounts_checked,
+        report.tip_index,
+        report.accounts_compared_with_ledger,
+        report.accounts_skipped_for_ledger,
+        report.mismatches.len()
+    );
+    for mismatch in &report.mismatches {
+        warn!("Balance mismatch: {:?}", mismatch);
+    }
+    if report.accounts_checked > 0 && report.accounts_compared_with_ledger == 0 {
+        return Err(ApiError::internal_error(format!(
+            "Cannot reconcile balances: none of the {} accounts was compared with the ledger",
+            report.accounts_checked
+        )));
+    }
+    Ok(report)
+}
+
+/// Calls `f` with the index and operation of every block from `start` up to
+/// `tip_index`. The store is locked for reading while a batch of blocks is
+/// read.
+async fn for_each_operation(
+    ledger: &(dyn LedgerAccess + Send + Sync),
+    mut start: BlockIndex,
+    tip_index: BlockIndex,
+    mut f: impl FnMut(BlockIndex, Operation) -> Result<(), ApiError>,
+) -> Result<(), ApiError> {
+    while start <= tip_index {
+        let end = (start + REPLAY_BATCH_SIZE).min(tip_index + 1);
+        let batch = ledger
+            .read_blocks()
+            .await
+            .get_hashed_block_range(start..end)?;
+        if batch.len() as u64 != end - start {
+            return Err(ApiError::internal_error(format!(
+                "Cannot reconcile balances: blocks {} to {} are no longer in the store",
+                start,
+                end - 1
+            )));
+        }
+        for hb in batch {
+            let block = Block::decode(hb.block).map_err(ApiError::internal_error)?;
+            f(hb.index, block.transaction.operation)?;
+        }
+        start = end;
+    }
+    Ok(())
+}
+
+/// Returns the accounts whose balance `operation` changes.
+fn operation_accounts(operation: &Operation) -> Vec<AccountIdentifier> {
+    match operation {
+        Operation::Mint { to, .. } => vec![*to],
+        Operation::Burn { from, .. } => vec![*from],
+        Operation::Transfer { from, to, .. } => vec![*from, *to],
+    }
+}
+
+fn apply(
+    balances: &mut HashMap<AccountIdentifier, Tokens>,
+    index: BlockIndex,
+    operation: &Operation,
+) -> Result<Vec<AccountIdentifier>, ApiError> {
+    match operation {
+        Operation::Mint { to, amount } => {
+            credit(balances, index, *to, *amount)?;
+        }
+        Operation::Burn { from, amount } => {
+            debit(balances, index, *from, *amount)?;
+        }
+        Operation::Transfer {
+            from,
+            to,
+            amount,
+            fee,
+        } => {
+            let payable = (*amount + *fee).map_err(|e| {
+                ApiError::internal_error(format!("Invalid transfer at block {}: {}", index, e))
+            })?;
+            debit(balances, index, *from, payable)?;
+            credit(balances, index, *to, *amount)?;
+        }
+    }
+    Ok(operation_accounts(operation))
+}
+
+fn credit(
+    balances: &mut HashMap<AccountIdentifier, Tokens>,
+    index: BlockIndex,
+    account: AccountIdentifier,
+    amount: Tokens,
+) -> Result<(), ApiError> {
+    let balance = balances.entry(account).or_insert(Tokens::ZERO);
+    *balance = (*balance + amount).map_err(|e| {
+        ApiError::internal_error(format!("Invalid credit at block {}: {}", index, e))
+    })?;
+    Ok(())
+}
+
+fn debit(
+    balances: &mut HashMap<AccountIdentifier, Tokens>,
+    index: BlockIndex,
+    account: AccountIdentifier,
+    amount: Tokens,
+) -> Result<(), ApiError> {
+    let balance = balances.entry(account).or_insert(Tokens::ZERO);
+    let current = *balance;
+    *balance = (current - amount).map_err(|_| {
+        ApiError::internal_error(format!(
+            "Block {} debits {} from account {} which only holds {}",
+            index, amount, account, current
+        ))
+    })?;
+    Ok(())
+}
+
+async fn replay_balances(
+    ledger: &(dyn LedgerAccess + Send + Sync),
+    tip_index: BlockIndex,
+) -> Result<HashMap<AccountIdentifier, Tokens>, ApiError> {
+    let mut balances = HashMap::new();
+    for_each_operation(ledger, 0, tip_index, |index, operation| {
+        apply(&mut balances, index, &operation).map(|_| ())
+    })
+    .await?;
+    Ok(balances)
+}
+
+/// Returns the balance after every block that touched one of `accounts`.
+async fn replay_histories(
+    ledger: &(dyn LedgerAccess + Send + Sync),
+    tip_index: BlockIndex,
+    accounts: &HashSet<AccountIdentifier>,
+) -> Result<HashMap<AccountIdentifier, BTreeMap<BlockIndex, Tokens>>, ApiError> {
+    let mut balances = HashMap::new();
+    let mut histories: HashMap<AccountIdentifier, BTreeMap<BlockIndex, Tokens>> = HashMap::new();
+    for_each_operation(ledger, 0, tip_index, |index, operation| {
+        for account in apply(&mut balances, index, &operation)? {
+            if accounts.contains(&account) {
+                histories
+                    .entry(account)
+                    .or_default()
+                    .insert(index, balances[&account]);
+            }
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(histories)
+}
+
+/// Returns the first block index at which the two balance histories differ.
+fn first_divergence(
+    expected: &BTreeMap<BlockIndex, Tokens>,
+    actual: &BTreeMap<BlockIndex, Tokens>,
+) -> Option<BlockIndex> {
+    let mut expected = expected.iter();
+    let mut actual = actual.iter();
+    loop {
+        match (expected.next(), actual.next()) {
+            (None, None) => return None,
+            (Some((idx, _)), None) | (None, Some((idx, _))) => return Some(*idx),
+            (Some(e), Some(a)) if e == a => continue,
+            (Some((e, _)), Some((a, _))) => return Some(*e.min(a)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct LedgerComparison {
+    balances: HashMap<AccountIdentifier, LedgerBalance>,
+    /// The number of accounts whose balance wasn't compared.
+    skipped: usize,
+}
+
+/// Queries the balances of `accounts` from the ledger in batches. Every batch
+/// is pinned to the tip of the ledger before the batch. If the ledger is
+/// ahead of `tip_index`, the balances recomputed up to `tip_index` are
+/// advanced with the blocks from the store up to the tip of the ledger. The
+/// batch is skipped if the tip moves while the batch is queried or if the
+/// store doesn't have these blocks yet.
+async fn query_ledger_balances(
+    ledger: &(dyn LedgerAccess + Send + Sync),
+    tip_index: BlockIndex,
+    recomputed: &HashMap<AccountIdentifier, Tokens>,
+    accounts: &[AccountIdentifier],
+) -> Result<LedgerComparison, ApiError> {
+    let mut res = LedgerComparison::default();
+    // The balances after `tip_index` of the accounts touched up to
+    // `pinned_index`.
+    let mut changed = HashMap::new();
+    let mut pinned_index = tip_index;
+    for batch in accounts.chunks(LEDGER_QUERY_BATCH_SIZE) {
+        let ledger_tip = ledger.ledger_tip_index().await?;
+        if ledger_tip < pinned_index {
+            res.skipped += batch.len();
+            continue;
+        }
+        if ledger_tip > pinned_index {
+            let local_tip = ledger
+                .read_blocks()
+                .await
+                .get_latest_verified_hashed_block()?
+                .index;
+            if local_tip < ledger_tip {
+                res.skipped += batch.len();
+                continue;
+            }
+            for_each_operation(ledger, pinned_index + 1, ledger_tip, |index, operation| {
+                for account in operation_accounts(&operation) {
+                    changed.entry(account).or_insert_with(|| {
+                        recomputed.get(&account).cloned().unwrap_or(Tokens::ZERO)
+                    });
+                }
+                apply(&mut changed, index, &operation).map(|_| ())
+            })
+            .await?;
+            pinned_index = ledger_tip;
+        }
+
+        let mut batch_balances = Vec::with_capacity(batch.len());
+        for account in batch {
+            match ledger.ledger_account_balance(*account).await {
+                Ok(balance) => batch_balances.push((*account, balance)),
+                Err(e) => {
+                    warn!("Failed to query the balance of {}: {:?}", account, e);
+                    break;
+                }
+            }
+        }
+        if batch_balances.len() < batch.len() || ledger.ledger_tip_index().await? != pinned_index {
+            res.skipped += batch.len();
+            continue;
+        }
+        for (account, balance) in batch_balances {
+            let recomputed = changed
+                .get(&account)
+                .or_else(|| recomputed.get(&account))
+                .cloned()
+                .unwrap_or(Tokens::ZERO);
+            res.balances.insert(
+                account,
+                LedgerBalance {
+                    block_index: pinned_index,
+                    balance,
+                    recomputed,
+                },
+            );
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_divergence_test() {
+        let history = |entries: &[(u64, u64)]| -> BTreeMap<BlockIndex, Tokens> {
+            entries
+                .iter()
+                .map(|(idx, e8s)| (*idx, Tokens::from_e8s(*e8s)))
+                .collect()
+        };
+        let expected = history(&[(0, 100), (3, 50), (7, 20)]);
+        assert_eq!(first_divergence(&expected, &expected), None);
+        assert_eq!(
+            first_divergence(&expected, &history(&[(0, 100), (3, 60), (7, 30)])),
+            Some(3)
+        );
+        assert_eq!(
+            first_divergence(&expected, &history(&[(0, 100), (3, 50)])),
+            Some(7)
+        );
+        assert_eq!(
+            first_divergence(&expected, &history(&[(0, 100), (2, 50), (7, 20)])),
+            Some(2)
+        );
+    }
+}
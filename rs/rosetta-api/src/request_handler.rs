@@ -6,8 +6,10 @@ mod construction_parse;
 mod construction_payloads;
 mod construction_preprocess;
 mod construction_submit;
+mod historical_balance;
 
 use crate::{convert, models, API_VERSION, NODE_VERSION};
+use ic_ledger_canister_blocks_synchronizer::blocks::BlockStoreError;
 use ic_ledger_canister_blocks_synchronizer::blocks::Blocks;
 use ic_ledger_canister_blocks_synchronizer::blocks::HashedBlock;
 use ic_ledger_core::block::BlockType;
@@ -24,6 +26,7 @@ use strum::IntoEnumIterator;
 use crate::convert::{from_model_account_identifier, neuron_account_from_public_key};
 use crate::errors::ApiError;
 use crate::ledger_client::LedgerAccess;
+use crate::request_handler::historical_balance::ArchivedBlocksCache;
 use crate::models::amount::tokens_to_amount;
 use crate::models::{
     AccountBalanceRequest, AccountBalanceResponse, Allow, BalanceAccountType, BlockIdentifier,
@@ -41,6 +44,7 @@ const MAX_SEARCH_LIMIT: usize = 10_000;
 pub struct RosettaRequestHandler {
     blockchain: String,
     ledger: Arc<dyn LedgerAccess + Send + Sync>,
+    archived_blocks: Arc<ArchivedBlocksCache>,
 }
 
 // construction requests are implemented in their own module.
@@ -49,7 +53,11 @@ impl RosettaRequestHandler {
         blockchain: String,
         ledger: Arc<T>,
     ) -> Self {
-        Self { blockchain, ledger }
+        Self {
+            blockchain,
+            ledger,
+            archived_blocks: Arc::new(ArchivedBlocksCache::default()),
+        }
     }
 
     pub fn new_with_default_blockchain<T: 'static + LedgerAccess + Send + Sync>(
@@ -142,10 +150,36 @@ impl RosettaRequestHandler {
                 &msg.account_identifier.address, e,
             ))
         })?;
-        let blocks = self.ledger.read_blocks().await;
-        let block = get_block(&blocks, msg.block_identifier)?;
+        let pruned_height = {
+            let blocks = self.ledger.read_blocks().await;
+            height_below_horizon(&blocks, &msg.block_identifier)
+        };
+        let (block, tokens) = match pruned_height {
+            Some(height) => {
+                let (block, tokens) = historical_balance::balance_below_horizon(
+                    self.ledger.as_ref(),
+                    &self.archived_blocks,
+                    &account_id,
+                    height,
+                )
+                .await?;
+                if let Some(hash) = msg.block_identifier.and_then(|id| id.hash) {
+                    let hash: ic_ledger_core::block::HashOf<ic_ledger_core::block::EncodedBlock> =
+                        convert::to_hash(&hash)?;
+                    if hash != block.hash {
+                        return Err(ApiError::InvalidBlockId(false, Default::default()));
+                    }
+                }
+                (block, tokens)
+            }
+            None => {
+                let blocks = self.ledger.read_blocks().await;
+                let block = get_block(&blocks, msg.block_identifier)?;
+                let tokens = blocks.get_account_balance(&account_id, &block.index)?;
+                (block, tokens)
+            }
+        };
 
-        let tokens = blocks.get_account_balance(&account_id, &block.index)?;
         let amount = tokens_to_amount(tokens, self.ledger.token_symbol())?;
         let b = convert::block_id(&block)?;
         Ok(AccountBalanceResponse {
@@ -288,15 +322,15 @@ impl RosettaRequestHandler {
         msg: models::NetworkRequest,
     ) -> Result<NetworkStatusResponse, ApiError> {
         verify_network_id(self.ledger.ledger_canister_id(), &msg.network_identifier)?;
-        let blocks = self.ledger.read_blocks().await;
-        let first = blocks.get_first_verified_hashed_block()?;
-        let tip = blocks.get_latest_verified_hashed_block()?;
+        let status = self.ledger.sync_status().await?;
+        let first = status.oldest_verified.ok_or(BlockStoreError::Empty)?;
+        let tip = status.latest_verified.ok_or(BlockStoreError::Empty)?;
         let tip_id = convert::block_id(&tip)?;
         let tip_timestamp = models::timestamp::from_system_time(
             Block::decode(tip.block).unwrap().timestamp.into(),
         )?;
 
-        let genesis_block = blocks.get_hashed_block(&0)?;
+        let genesis_block = status.genesis.ok_or(BlockStoreError::Empty)?;
         let genesis_block_id = convert::block_id(&genesis_block)?;
         let peers = vec![];
         let oldest_block_id = if first.index != 0 {
@@ -306,10 +340,7 @@ impl RosettaRequestHandler {
         };
 
         let mut sync_status = SyncStatus::new(tip.index as i64, None);
-        let target = crate::rosetta_server::TARGET_HEIGHT.get();
-        if target != 0 {
-            sync_status.target_index = Some(crate::rosetta_server::TARGET_HEIGHT.get());
-        }
+        sync_status.target_index = status.target_index.map(|target| target as i64);
 
         Ok(NetworkStatusResponse::new(
             tip_id,
@@ -580,6 +611,17 @@ fn block_height_to_index(height: BlockIndex) -> Result<i128, ApiError> {
     i128::try_from(height).map_err(|e| ApiError::InternalError(true, e.to_string().into()))
 }
 
+/// Returns the height requested by `block_id` if it is below the oldest block
+/// in the store, in which case we have to look it up in the archives.
+fn height_below_horizon(
+    blocks: &Blocks,
+    block_id: &Option<PartialBlockIdentifier>,
+) -> Option<BlockIndex> {
+    let height = u64::try_from(block_id.as_ref()?.index?).ok()?;
+    let first = blocks.get_first_verified_hashed_block().ok()?;
+    (height < first.index).then_some(height)
+}
+
 fn get_block(
     blocks: &Blocks,
     block_id: Option<PartialBlockIdentifier>,
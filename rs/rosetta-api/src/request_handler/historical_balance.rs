@@ -0,0 +1,234 @@
+//! Resolution of account balances at heights older than the oldest block in
+//! a pruned store.
+//!
+//! The store keeps the balance of every account at the oldest block it holds
+//! (the pruning horizon). To answer a query for an older height, we fetch the
+//! blocks between the queried height and the horizon from the ledger and its
+//! archives and undo their effect on the account, newest block first. The
+//! fetched blocks are checked against the hash chain of the (verified) block at
+//! the horizon, so they are as trustworthy as the blocks in the store.
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use ic_ledger_canister_blocks_synchronizer::blocks::{Blocks, HashedBlock};
+use ic_ledger_core::block::{BlockType, EncodedBlock, HashOf};
+use icp_ledger::{AccountIdentifier, Block, BlockIndex, Operation, Tokens};
+
+use crate::errors::ApiError;
+use crate::ledger_client::LedgerAccess;
+
+/// The maximum distance between a queried height and the pruning horizon for
+/// which we fall back to the archives.
+pub const MAX_ARCHIVE_FALLBACK_BLOCKS: u64 = 100_000;
+
+/// The number of blocks fetched from the archives at once. Chunks start at
+/// multiples of this length, so that queries for nearby heights share chunks.
+const ARCHIVE_CHUNK_LEN: u64 = 1_000;
+
+/// The maximum number of chunks kept in the cache.
+const MAX_CACHED_CHUNKS: usize = 100;
+
+/// A least-recently-used cache of block chunks fetched from the archives.
+#[derive(Default)]
+pub struct ArchivedBlocksCache {
+    inner: Mutex<CacheInner>,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    chunks: BTreeMap<BlockIndex, Arc<Vec<EncodedBlock>>>,
+    // chunk starts, least recently used first
+    usage: VecDeque<BlockIndex>,
+}
+
+impl ArchivedBlocksCache {
+    fn get(&self, chunk_start: BlockIndex, index: BlockIndex) -> Option<Arc<Vec<EncodedBlock>>> {
+        let mut inner = self.inner.lock().unwrap();
+        let chunk = inner.chunks.get(&chunk_start).cloned()?;
+        if chunk.len() as u64 <= index - chunk_start {
+            // a chunk cut short by an older horizon
+            return None;
+        }
+        inner.usage.retain(|s| *s != chunk_start);
+        inner.usage.push_back(chunk_start);
+        Some(chunk)
+    }
+
+    fn insert(&self, chunk_start: BlockIndex, chunk: Arc<Vec<EncodedBlock>>) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.chunks.insert(chunk_start, chunk).is_none() {
+            inner.usage.push_back(chunk_start);
+        }
+        while inner.usage.len() > MAX_CACHED_CHUNKS {
+            if let Some(evicted) = inner.usage.pop_front() {
+                inner.chunks.remove(&evicted);
+            }
+        }
+    }
+
+    /// Returns the block at `index`, fetching the chunk containing it if
+    /// necessary. `end` is the index of the first block that we do not need.
+    async fn get_block(
+        &self,
+        ledger: &(dyn LedgerAccess + Send + Sync),
+        index: BlockIndex,
+        end: BlockIndex,
+    ) -> Result<EncodedBlock, ApiError> {
+        let chunk_start = index - index % ARCHIVE_CHUNK_LEN;
+        let chunk = match self.get(chunk_start, index) {
+            Some(chunk) => chunk,
+            None => {
+                let chunk_end = (chunk_start + ARCHIVE_CHUNK_LEN).min(end);
+                let chunk = Arc::new(fetch_blocks(ledger, chunk_start..chunk_end).await?);
+                self.insert(chunk_start, chunk.clone());
+                chunk
+            }
+        };
+        chunk
+            .get((index - chunk_start) as usize)
+            .cloned()
+            .ok_or_else(|| {
+                ApiError::internal_error(format!("Block {} missing from the archives", index))
+            })
+    }
+}
+
+async fn fetch_blocks(
+    ledger: &(dyn LedgerAccess + Send + Sync),
+    range: Range<BlockIndex>,
+) -> Result<Vec<EncodedBlock>, ApiError> {
+    let mut blocks = Vec::with_capacity((range.end - range.start) as usize);
+    let mut start = range.start;
+    while start < range.end {
+        let batch = ledger.query_archived_blocks(start..range.end).await?;
+        if batch.is_empty() {
+            return Err(ApiError::internal_error(format!(
+                "The ledger returned no blocks for the range {}..{}",
+                start, range.end
+            )));
+        }
+        start += batch.len() as u64;
+        blocks.extend(batch);
+    }
+    blocks.truncate((range.end - range.start) as usize);
+    Ok(blocks)
+}
+
+/// Returns the block at `height` and the balance of `account` after that
+/// block, where `height` is below the oldest block in `blocks`.
+pub async fn balance_below_horizon(
+    ledger: &(dyn LedgerAccess + Send + Sync),
+    cache: &ArchivedBlocksCache,
+    account: &AccountIdentifier,
+    height: BlockIndex,
+) -> Result<(HashedBlock, Tokens), ApiError> {
+    let (horizon, horizon_balance) = {
+        let blocks = ledger.read_blocks().await;
+        horizon_balance(&blocks, account)?
+    };
+    if height >= horizon.index {
+        return Err(ApiError::internal_error(format!(
+            "Block {} is not below the oldest block in the store ({})",
+            height, horizon.index
+        )));
+    }
+    if horizon.index - height > MAX_ARCHIVE_FALLBACK_BLOCKS {
+        return Err(ApiError::invalid_block_id(format!(
+            "Block {} is more than {} blocks older than the oldest block in the store ({})",
+            height, MAX_ARCHIVE_FALLBACK_BLOCKS, horizon.index
+        )));
+    }
+
+    let mut balance = horizon_balance.get_e8s();
+    let mut block = decode(&horizon.block)?;
+    let mut index = horizon.index;
+    loop {
+        balance = match undo_operation(account, &block.transaction.operation, balance) {
+            Some(balance) => balance,
+            None => {
+                return Err(ApiError::internal_error(format!(
+                    "Inconsistent balance of {} while undoing block {}",
+                    account, index
+                )))
+            }
+        };
+
+        let expected_hash = block.parent_hash.ok_or_else(|| {
+            ApiError::internal_error(format!("Block {} has no parent", index))
+        })?;
+        index -= 1;
+        let encoded = cache.get_block(ledger, index, horizon.index).await?;
+        check_hash(&encoded, expected_hash, index)?;
+        block = decode(&encoded)?;
+        if index == height {
+            let hb = HashedBlock::hash_block(encoded, block.parent_hash, index);
+            return Ok((hb, Tokens::from_e8s(balance)));
+        }
+    }
+}
+
+fn horizon_balance(
+    blocks: &Blocks,
+    account: &AccountIdentifier,
+) -> Result<(HashedBlock, Tokens), ApiError> {
+    let horizon = blocks.get_first_verified_hashed_block()?;
+    let balance = blocks.get_account_balance(account, &horizon.index)?;
+    Ok((horizon, balance))
+}
+
+fn decode(encoded: &EncodedBlock) -> Result<Block, ApiError> {
+    Block::decode(encoded.clone())
+        .map_err(|e| ApiError::internal_error(format!("Cannot decode block: {}", e)))
+}
+
+fn check_hash(
+    encoded: &EncodedBlock,
+    expected: HashOf<EncodedBlock>,
+    index: BlockIndex,
+) -> Result<(), ApiError> {
+    if Block::block_hash(encoded) != expected {
+        return Err(ApiError::internal_error(format!(
+            "The hash of archived block {} does not match the parent hash of block {}",
+            index,
+            index + 1
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the balance of `account` before `operation`, given the balance
+/// after it, or None if the balances are inconsistent.
+fn undo_operation(
+    account: &AccountIdentifier,
+    operation: &Operation,
+    mut balance: u64,
+) -> Option<u64> {
+    match operation {
+        Operation::Mint { to, amount } => {
+            if to == account {
+                balance = balance.checked_sub(amount.get_e8s())?;
+            }
+        }
+        Operation::Burn { from, amount } => {
+            if from == account {
+                balance = balance.checked_add(amount.get_e8s())?;
+            }
+        }
+        Operation::Transfer {
+            from,
+            to,
+            amount,
+            fee,
+        } => {
+            // undo the debit first, so that self-transfers do not underflow
+            if from == account {
+                balance = balance.checked_add(amount.get_e8s())?.checked_add(fee.get_e8s())?;
+            }
+            if to == account {
+                balance = balance.checked_sub(amount.get_e8s())?;
+            }
+        }
+    }
+    Some(balance)
+}
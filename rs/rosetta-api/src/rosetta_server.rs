@@ -8,6 +8,7 @@ use crate::{
     errors::{self, ApiError},
     ledger_client::LedgerAccess,
     models::*,
+    reconciliation::reconcile_balances,
     request_handler::RosettaRequestHandler,
 };
 
@@ -65,6 +66,21 @@ lazy_static! {
         register_int_gauge!("rosetta_synched_block_height", "Synced block height").unwrap();
     pub static ref TARGET_HEIGHT: IntGauge =
         register_int_gauge!("rosetta_target_block_height", "Target height (tip)").unwrap();
+    pub static ref STORE_AVAILABLE_DISK_SPACE: IntGauge = register_int_gauge!(
+        "rosetta_store_available_disk_space_bytes",
+        "Bytes available on the file system holding the block store"
+    )
+    .unwrap();
+    pub static ref SYNC_PAUSED: IntGauge = register_int_gauge!(
+        "rosetta_sync_paused_insufficient_disk_space",
+        "1 if syncing is paused because the block store is running out of disk space"
+    )
+    .unwrap();
+    pub static ref STORE_MISMATCHES: IntCounter = register_int_counter!(
+        "rosetta_store_mismatches_total",
+        "Number of differences found between the primary and the secondary block store"
+    )
+    .unwrap();
     pub static ref SYNC_ERR_COUNTER: IntCounter = register_int_counter!(
         "blockchain_sync_errors_total",
         "Number of times synchronization failed"
@@ -81,6 +97,31 @@ lazy_static! {
         vec![0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 5.0, 10.0, 15.0]
     )
     .unwrap();
+    pub static ref RECONCILIATION_ERR_COUNTER: IntCounter = register_int_counter!(
+        "rosetta_reconciliation_errors_total",
+        "Number of times the balance reconciliation failed to run"
+    )
+    .unwrap();
+    pub static ref RECONCILIATION_MISMATCHES: IntGauge = register_int_gauge!(
+        "rosetta_reconciliation_mismatches",
+        "Number of accounts whose balance didn't match in the last reconciliation"
+    )
+    .unwrap();
+    pub static ref RECONCILIATION_HEIGHT: IntGauge = register_int_gauge!(
+        "rosetta_reconciliation_block_height",
+        "Block height at which the balances were last reconciled"
+    )
+    .unwrap();
+    pub static ref RECONCILIATION_LEDGER_COMPARED: IntGauge = register_int_gauge!(
+        "rosetta_reconciliation_ledger_compared_accounts",
+        "Number of accounts compared with the ledger in the last reconciliation"
+    )
+    .unwrap();
+    pub static ref RECONCILIATION_LEDGER_SKIPPED: IntGauge = register_int_gauge!(
+        "rosetta_reconciliation_ledger_skipped_accounts",
+        "Number of accounts not compared with the ledger in the last reconciliation"
+    )
+    .unwrap();
 }
 
 #[post("/account/balance")]
@@ -388,6 +429,7 @@ impl RosettaApiServer {
             offline,
             mainnet,
             not_whitelisted,
+            reconciliation_interval,
         } = options;
 
         let mut server_lock = self.server.lock().await;
@@ -408,6 +450,30 @@ impl RosettaApiServer {
                 ServerState::OfflineStarted
             }
             ServerState::Unstarted(server) => {
+                if let Some(reconciliation_interval) = reconciliation_interval {
+                    let ledger = self.ledger.clone();
+                    let stopped = self.stopped.clone();
+                    tokio::task::spawn(async move {
+                        while !stopped.load(Relaxed) {
+                            tokio::time::sleep(reconciliation_interval).await;
+                            match reconcile_balances(&*ledger).await {
+                                Ok(report) => {
+                                    RECONCILIATION_HEIGHT.set(report.tip_index as i64);
+                                    RECONCILIATION_MISMATCHES.set(report.mismatches.len() as i64);
+                                    RECONCILIATION_LEDGER_COMPARED
+                                        .set(report.accounts_compared_with_ledger as i64);
+                                    RECONCILIATION_LEDGER_SKIPPED
+                                        .set(report.accounts_skipped_for_ledger as i64);
+                                }
+                                Err(err) => {
+                                    error!("Error in reconciling balances: {:?}", err);
+                                    RECONCILIATION_ERR_COUNTER.inc();
+                                }
+                            }
+                        }
+                    });
+                }
+
                 let ledger = self.ledger.clone();
                 let stopped = self.stopped.clone();
                 let server_handle = self.server_handle.clone();
@@ -479,4 +545,6 @@ pub struct RosettaApiServerOpt {
     pub offline: bool,
     pub mainnet: bool,
     pub not_whitelisted: bool,
+    /// How often to reconcile the stored balances, if at all.
+    pub reconciliation_interval: Option<Duration>,
 }
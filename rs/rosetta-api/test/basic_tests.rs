@@ -14,6 +14,7 @@ use ic_rosetta_api::models::{
     NetworkRequest, NetworkStatusResponse, SearchTransactionsRequest, SearchTransactionsResponse,
     SyncStatus,
 };
+use ic_rosetta_api::reconciliation::{reconcile_balances, BalanceMismatch, LedgerBalance};
 use ic_rosetta_api::request_handler::RosettaRequestHandler;
 use ic_rosetta_api::transaction_id::TransactionIdentifier;
 use ic_rosetta_api::{models, API_VERSION, NODE_VERSION};
@@ -429,6 +430,73 @@ async fn balances_test() {
     );
 }
 
+#[actix_rt::test]
+async fn pruned_balances_test() {
+    init_test_logger();
+
+    let mut scribe = Scribe::new();
+    let num_accounts = 10;
+    scribe.gen_accounts(num_accounts, 1_000_000);
+    for _i in 0..300 {
+        scribe.gen_transaction();
+    }
+
+    let ledger = Arc::new(TestLedger {
+        archived_blocks: scribe.blockchain.iter().cloned().collect(),
+        ..Default::default()
+    });
+    let req_handler = RosettaRequestHandler::new_with_default_blockchain(ledger.clone());
+    for b in &scribe.blockchain {
+        ledger.add_block(b.clone()).await.ok();
+    }
+    ledger.blockchain.write().await.try_prune(&Some(10), 0).ok();
+    let first_idx = ledger
+        .read_blocks()
+        .await
+        .get_first_verified_hashed_block()
+        .unwrap()
+        .index as usize;
+    assert!(first_idx > 0);
+
+    for height in [0, 1, first_idx / 2, first_idx - 1, first_idx] {
+        for i in 0..num_accounts {
+            let acc = acc_id(i);
+            let expected = scribe.balance_history[height]
+                .get(&acc)
+                .cloned()
+                .unwrap_or(Tokens::ZERO);
+            assert_eq!(
+                get_balance(&req_handler, Some(height), acc).await.unwrap(),
+                expected
+            );
+        }
+    }
+
+    // the response identifies the archived block
+    let mut msg = AccountBalanceRequest::new(
+        req_handler.network_id(),
+        to_model_account_identifier(&acc_id(0)),
+    );
+    msg.block_identifier = Some(PartialBlockIdentifier {
+        index: Some(1),
+        hash: None,
+    });
+    let res = req_handler.account_balance(msg).await.unwrap();
+    assert_eq!(
+        res.block_identifier,
+        block_id(scribe.blockchain.get(1).unwrap()).unwrap()
+    );
+
+    // without archives, pruned heights are not available
+    let ledger = Arc::new(TestLedger::new());
+    for b in &scribe.blockchain {
+        ledger.add_block(b.clone()).await.ok();
+    }
+    ledger.blockchain.write().await.try_prune(&Some(10), 0).ok();
+    let req_handler = RosettaRequestHandler::new_with_default_blockchain(ledger.clone());
+    assert!(get_balance(&req_handler, Some(0), acc_id(0)).await.is_err());
+}
+
 fn verify_balances(scribe: &Scribe, blocks: &Blocks, start_idx: usize) {
     for hb in scribe.blockchain.iter().skip(start_idx) {
         assert_eq!(
@@ -775,3 +843,54 @@ async fn store_batch_test() {
     blocks.set_hashed_block_to_verified(&last_idx).unwrap();
     verify_balances(&scribe, &blocks, 0);
 }
+
+#[actix_rt::test]
+async fn reconcile_balances_test() {
+    init_test_logger();
+
+    let ledger = TestLedger::new();
+    let mut scribe = Scribe::new();
+    scribe.gen_accounts(3, 1_000_000);
+    for _ in 0..20 {
+        scribe.gen_transaction();
+    }
+    for b in &scribe.blockchain {
+        ledger.add_block(b.clone()).await.unwrap();
+    }
+    let tip_index = scribe.blockchain.back().unwrap().index;
+
+    // The store and the ledger agree with the replayed blocks.
+    *ledger.ledger_balances.lock().unwrap() = Some(scribe.balance_book.clone());
+    let report = reconcile_balances(&ledger).await.unwrap();
+    assert_eq!(report.tip_index, tip_index);
+    assert_eq!(report.accounts_checked, scribe.balance_book.len());
+    assert_eq!(report.accounts_compared_with_ledger, report.accounts_checked);
+    assert_eq!(report.accounts_skipped_for_ledger, 0);
+    assert_eq!(report.mismatches, vec![]);
+
+    // The ledger reports a different balance for one account.
+    let acc = acc_id(1);
+    let expected = *scribe.balance_book.get(&acc).unwrap();
+    let mut ledger_balances = scribe.balance_book.clone();
+    ledger_balances.insert(acc, (expected + Tokens::from_e8s(1)).unwrap());
+    *ledger.ledger_balances.lock().unwrap() = Some(ledger_balances);
+    let report = reconcile_balances(&ledger).await.unwrap();
+    assert_eq!(
+        report.mismatches,
+        vec![BalanceMismatch {
+            account: acc,
+            recomputed: expected,
+            stored: expected,
+            ledger: Some(LedgerBalance {
+                block_index: tip_index,
+                balance: (expected + Tokens::from_e8s(1)).unwrap(),
+                recomputed: expected,
+            }),
+            first_divergent_block: None,
+        }]
+    );
+
+    // A reconciliation that couldn't compare any account with the ledger fails.
+    *ledger.ledger_balances.lock().unwrap() = None;
+    assert!(reconcile_balances(&ledger).await.is_err());
+}
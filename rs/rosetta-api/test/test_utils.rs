@@ -3,7 +3,7 @@ mod rosetta_cli_tests;
 
 use ic_ledger_canister_blocks_synchronizer_test_utils::sample_data::{acc_id, Scribe};
 use ic_ledger_canister_core::ledger::LedgerTransaction;
-use ic_ledger_core::block::BlockType;
+use ic_ledger_core::block::{BlockType, EncodedBlock};
 use ic_ledger_core::timestamp::TimeStamp;
 use ic_rosetta_api::errors::ApiError;
 use ic_rosetta_api::models::{
@@ -31,7 +31,7 @@ use ic_types::{
 };
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
-use std::ops::Deref;
+use std::ops::{Deref, Range};
 use std::process::Command;
 use std::str::FromStr;
 use std::sync::atomic::AtomicBool;
@@ -50,6 +50,10 @@ pub struct TestLedger {
     pub governance_canister_id: CanisterId,
     pub submit_queue: RwLock<Vec<HashedBlock>>,
     pub transfer_fee: Tokens,
+    /// The blocks served to archive fallback queries.
+    pub archived_blocks: Vec<HashedBlock>,
+    /// The balances reported by the ledger canister, if it is reachable.
+    pub ledger_balances: Mutex<Option<BTreeMap<AccountIdentifier, Tokens>>>,
     next_block_timestamp: Mutex<TimeStamp>,
 }
 
@@ -64,6 +68,8 @@ impl TestLedger {
             governance_canister_id: ic_nns_constants::GOVERNANCE_CANISTER_ID,
             submit_queue: RwLock::new(Vec::new()),
             transfer_fee: DEFAULT_TRANSFER_FEE,
+            archived_blocks: Vec::new(),
+            ledger_balances: Mutex::new(None),
             next_block_timestamp: Mutex::new(TimeStamp::from_nanos_since_unix_epoch(
                 FIRST_BLOCK_TIMESTAMP_NANOS_SINCE_EPOC,
             )),
@@ -231,6 +237,35 @@ impl LedgerAccess for TestLedger {
             transfer_fee: self.transfer_fee,
         })
     }
+
+    async fn ledger_tip_index(&self) -> Result<BlockIndex, ApiError> {
+        if self.ledger_balances.lock().unwrap().is_none() {
+            return Err(ApiError::NotAvailableOffline(false, Default::default()));
+        }
+        let tip = self.read_blocks().await.get_latest_verified_hashed_block()?;
+        Ok(tip.index)
+    }
+
+    async fn ledger_account_balance(&self, account: AccountIdentifier) -> Result<Tokens, ApiError> {
+        match self.ledger_balances.lock().unwrap().as_ref() {
+            Some(balances) => Ok(balances.get(&account).cloned().unwrap_or(Tokens::ZERO)),
+            None => Err(ApiError::NotAvailableOffline(false, Default::default())),
+        }
+    }
+
+    async fn query_archived_blocks(
+        &self,
+        range: Range<BlockIndex>,
+    ) -> Result<Vec<EncodedBlock>, ApiError> {
+        // serve short batches, like an archive boundary would
+        Ok(self
+            .archived_blocks
+            .iter()
+            .filter(|hb| range.contains(&hb.index))
+            .take(100)
+            .map(|hb| hb.block.clone())
+            .collect())
+    }
 }
 
 pub async fn get_balance(
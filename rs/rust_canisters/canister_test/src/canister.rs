@@ -673,6 +673,10 @@ impl<'a> Canister<'a> {
                         compute_allocation: None,
                         memory_allocation: None,
                         freezing_threshold: None,
+                        log_visibility: None,
+                        wasm_memory_threshold: None,
+                        reserved_cycles_limit: None,
+                        wasm_memory_limit: None,
                     },
                 },),
             )
@@ -255,7 +255,12 @@ impl SnsInitPayload {
                 // 10 Trillion cycles
                 cycles_for_archive_creation: Some(10_000_000_000_000),
                 max_transactions_per_response: None,
+                max_archive_nodes: None,
+                cycles_for_archive_top_up: None,
+                cycles_for_archive_top_ups_pool: None,
             },
+            fee_collector_account: None,
+            decimals: None,
         };
 
         Ok(payload)
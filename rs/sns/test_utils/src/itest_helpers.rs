@@ -135,11 +135,16 @@ impl SnsTestsInitPayloadBuilder {
                 controller_id: CanisterId::from_u64(0).into(),
                 cycles_for_archive_creation: Some(0),
                 max_transactions_per_response: None,
+                max_archive_nodes: None,
+                cycles_for_archive_top_up: None,
+                cycles_for_archive_top_ups_pool: None,
             },
             transfer_fee: DEFAULT_TRANSFER_FEE.get_e8s(),
             token_symbol: "TKX".to_string(),
             token_name: "Token Example".to_string(),
             metadata: vec![],
+            fee_collector_account: None,
+            decimals: None,
         };
 
         let swap = SwapInit {
@@ -3,6 +3,7 @@ use crate::utils::do_copy;
 
 use bitcoin::{hashes::Hash, Network, OutPoint, Script, TxOut, Txid};
 use ic_base_types::{NumBytes, NumSeconds};
+use ic_ic00_types::LogVisibility;
 use ic_logger::{error, ReplicaLogger};
 use ic_protobuf::{
     bitcoin::v1 as pb_bitcoin,
@@ -14,7 +15,8 @@ use ic_protobuf::{
 };
 use ic_replicated_state::{
     bitcoin_state, canister_state::execution_state::WasmMetadata, CallContextManager,
-    CanisterStatus, ExecutionTask, ExportedFunctions, Global, NumWasmPages,
+    CanisterLog, CanisterStatus, ExecutedInstructions, ExecutionTask, ExportedFunctions, Global,
+    NumWasmPages, QueryStats,
 };
 use ic_sys::mmap::ScopedMmap;
 use ic_types::{
@@ -117,6 +119,8 @@ pub struct CanisterStateBits {
     pub freeze_threshold: NumSeconds,
     pub cycles_balance: Cycles,
     pub cycles_debit: Cycles,
+    pub reserved_balance: Cycles,
+    pub reserved_balance_limit: Option<Cycles>,
     pub status: CanisterStatus,
     pub scheduled_as_first: u64,
     pub skipped_round_due_to_no_messages: u64,
@@ -131,6 +135,13 @@ pub struct CanisterStateBits {
     pub time_of_last_allocation_charge_nanos: u64,
     pub global_timer_nanos: Option<u64>,
     pub canister_version: u64,
+    pub log_visibility: LogVisibility,
+    pub canister_log: CanisterLog,
+    pub wasm_memory_threshold: NumBytes,
+    pub on_low_wasm_memory_hook_executed: bool,
+    pub wasm_memory_limit: Option<NumBytes>,
+    pub executed_instructions: ExecutedInstructions,
+    pub query_stats: BTreeMap<String, QueryStats>,
 }
 
 /// This struct contains bits of the `BitcoinState` that are not already
@@ -184,6 +195,7 @@ impl Default for BitcoinStateBits {
 /// │           ├── vmemory_0.bin
 /// │           ├── canister.pbuf
 /// │           ├── stable_memory.(pbuf|bin)
+/// │           ├── wasm_chunk_store.pbuf
 /// │           └── software.wasm
 /// │
 /// ├── [checkpoints, backups, diverged_checkpoints]
@@ -202,6 +214,7 @@ impl Default for BitcoinStateBits {
 /// │              ├── vmemory_0.bin
 /// │              ├── canister.pbuf
 /// │              ├── stable_memory.(pbuf|bin)
+/// │              ├── wasm_chunk_store.pbuf
 /// │              └── software.wasm
 /// │
 /// └── diverged_state_markers
@@ -1038,6 +1051,14 @@ impl<Permissions: AccessPolicy> CanisterLayout<Permissions> {
         self.canister_root.join("canister.pbuf").into()
     }
 
+    /// The chunks of the Wasm chunk store. The file is missing in checkpoints
+    /// written before the chunk store was introduced.
+    pub fn wasm_chunk_store(
+        &self,
+    ) -> ProtoFileWith<pb_canister_state_bits::WasmChunkStore, Permissions> {
+        self.canister_root.join("wasm_chunk_store.pbuf").into()
+    }
+
     pub fn vmemory_0(&self) -> PathBuf {
         self.canister_root.join("vmemory_0.bin")
     }
@@ -1293,6 +1314,8 @@ impl From<CanisterStateBits> for pb_canister_state_bits::CanisterStateBits {
             freeze_threshold: item.freeze_threshold.get(),
             cycles_balance: Some(item.cycles_balance.into()),
             cycles_debit: Some(item.cycles_debit.into()),
+            reserved_balance: Some(item.reserved_balance.into()),
+            reserved_balance_limit: item.reserved_balance_limit.map(|limit| limit.into()),
             canister_status: Some((&item.status).into()),
             scheduled_as_first: item.scheduled_as_first,
             skipped_round_due_to_no_messages: item.skipped_round_due_to_no_messages,
@@ -1309,6 +1332,31 @@ impl From<CanisterStateBits> for pb_canister_state_bits::CanisterStateBits {
             task_queue: item.task_queue.iter().map(|v| v.into()).collect(),
             global_timer_nanos: item.global_timer_nanos,
             canister_version: item.canister_version,
+            log_visibility: pb_canister_state_bits::LogVisibility::from(&item.log_visibility)
+                .into(),
+            canister_log_records: item
+                .canister_log
+                .records()
+                .iter()
+                .map(|record| record.into())
+                .collect(),
+            next_canister_log_record_idx: item.canister_log.next_idx(),
+            wasm_memory_threshold: item.wasm_memory_threshold.get(),
+            on_low_wasm_memory_hook_executed: item.on_low_wasm_memory_hook_executed,
+            wasm_memory_limit: item.wasm_memory_limit.map(|limit| limit.get()),
+            update_instructions_executed: item.executed_instructions.update.get(),
+            query_instructions_executed: item.executed_instructions.query.get(),
+            system_task_instructions_executed: item.executed_instructions.system_task.get(),
+            query_stats: item
+                .query_stats
+                .iter()
+                .map(|(method_name, stats)| pb_canister_state_bits::QueryStats {
+                    method_name: method_name.clone(),
+                    num_calls: stats.num_calls,
+                    num_instructions: stats.num_instructions.get(),
+                    egress_payload_size: stats.egress_payload_size.get(),
+                })
+                .collect(),
         }
     }
 }
@@ -1348,12 +1396,39 @@ impl TryFrom<pb_canister_state_bits::CanisterStateBits> for CanisterStateBits {
             .transpose()?
             .unwrap_or_else(Cycles::zero);
 
+        let reserved_balance = value
+            .reserved_balance
+            .map(|c| c.try_into())
+            .transpose()?
+            .unwrap_or_else(Cycles::zero);
+
+        let reserved_balance_limit = value
+            .reserved_balance_limit
+            .map(|c| c.try_into())
+            .transpose()?;
+
         let task_queue = value
             .task_queue
             .into_iter()
             .map(|v| v.try_into())
             .collect::<Result<_, _>>()?;
 
+        let log_visibility = pb_canister_state_bits::LogVisibility::from_i32(value.log_visibility)
+            .ok_or_else(|| ProxyDecodeError::ValueOutOfRange {
+                typ: "LogVisibility",
+                err: format!("Unknown log visibility {}", value.log_visibility),
+            })?
+            .into();
+
+        let canister_log = CanisterLog::new(
+            value.next_canister_log_record_idx,
+            value
+                .canister_log_records
+                .into_iter()
+                .map(|record| record.into())
+                .collect(),
+        );
+
         Ok(Self {
             controllers,
             last_full_execution_round: value.last_full_execution_round.into(),
@@ -1374,6 +1449,8 @@ impl TryFrom<pb_canister_state_bits::CanisterStateBits> for CanisterStateBits {
             freeze_threshold: NumSeconds::from(value.freeze_threshold),
             cycles_balance,
             cycles_debit,
+            reserved_balance,
+            reserved_balance_limit,
             status: try_from_option_field(
                 value.canister_status,
                 "CanisterStateBits::canister_status",
@@ -1394,6 +1471,30 @@ impl TryFrom<pb_canister_state_bits::CanisterStateBits> for CanisterStateBits {
             task_queue,
             global_timer_nanos: value.global_timer_nanos,
             canister_version: value.canister_version,
+            log_visibility,
+            canister_log,
+            wasm_memory_threshold: NumBytes::from(value.wasm_memory_threshold),
+            on_low_wasm_memory_hook_executed: value.on_low_wasm_memory_hook_executed,
+            wasm_memory_limit: value.wasm_memory_limit.map(NumBytes::from),
+            executed_instructions: ExecutedInstructions {
+                update: NumInstructions::from(value.update_instructions_executed),
+                query: NumInstructions::from(value.query_instructions_executed),
+                system_task: NumInstructions::from(value.system_task_instructions_executed),
+            },
+            query_stats: value
+                .query_stats
+                .into_iter()
+                .map(|stats| {
+                    (
+                        stats.method_name,
+                        QueryStats {
+                            num_calls: stats.num_calls,
+                            num_instructions: NumInstructions::from(stats.num_instructions),
+                            egress_payload_size: NumBytes::from(stats.egress_payload_size),
+                        },
+                    )
+                })
+                .collect(),
         })
     }
 }
@@ -1810,6 +1911,8 @@ mod test {
             freeze_threshold: NumSeconds::from(0),
             cycles_balance: Cycles::zero(),
             cycles_debit: Cycles::zero(),
+            reserved_balance: Cycles::zero(),
+            reserved_balance_limit: None,
             status: CanisterStatus::Stopped,
             scheduled_as_first: 0,
             skipped_round_due_to_no_messages: 0,
@@ -1824,6 +1927,13 @@ mod test {
             task_queue: vec![],
             global_timer_nanos: None,
             canister_version: 0,
+            log_visibility: LogVisibility::default(),
+            canister_log: CanisterLog::default(),
+            wasm_memory_threshold: NumBytes::from(0),
+            on_low_wasm_memory_hook_executed: false,
+            wasm_memory_limit: None,
+            executed_instructions: ExecutedInstructions::default(),
+            query_stats: BTreeMap::new(),
         }
     }
 
@@ -1923,4 +2033,108 @@ mod test {
         let canister_state_bits = CanisterStateBits::try_from(pb_bits).unwrap();
         assert_eq!(canister_state_bits.task_queue, task_queue);
     }
+
+    #[test]
+    fn test_encode_decode_canister_log() {
+        let mut canister_log = CanisterLog::default();
+        canister_log.add_record(1, b"dropped".to_vec());
+        canister_log.clear();
+        canister_log.add_record(2, b"debug print".to_vec());
+        canister_log.add_record(3, b"[TRAP]: trap message".to_vec());
+        let canister_state_bits = CanisterStateBits {
+            log_visibility: LogVisibility::Public,
+            canister_log: canister_log.clone(),
+            ..default_canister_state_bits()
+        };
+
+        let pb_bits = pb_canister_state_bits::CanisterStateBits::from(canister_state_bits);
+        let canister_state_bits = CanisterStateBits::try_from(pb_bits).unwrap();
+        assert_eq!(canister_state_bits.log_visibility, LogVisibility::Public);
+        assert_eq!(canister_state_bits.canister_log, canister_log);
+        assert_eq!(canister_state_bits.canister_log.next_idx(), 3);
+    }
+
+    #[test]
+    fn test_encode_decode_wasm_memory_threshold() {
+        let canister_state_bits = CanisterStateBits {
+            wasm_memory_threshold: NumBytes::from(1 << 20),
+            on_low_wasm_memory_hook_executed: true,
+            ..default_canister_state_bits()
+        };
+
+        let pb_bits = pb_canister_state_bits::CanisterStateBits::from(canister_state_bits);
+        let canister_state_bits = CanisterStateBits::try_from(pb_bits).unwrap();
+        assert_eq!(canister_state_bits.wasm_memory_threshold, NumBytes::from(1 << 20));
+        assert!(canister_state_bits.on_low_wasm_memory_hook_executed);
+    }
+
+    #[test]
+    fn test_encode_decode_wasm_memory_limit() {
+        for wasm_memory_limit in [None, Some(NumBytes::from(1 << 30))] {
+            let canister_state_bits = CanisterStateBits {
+                wasm_memory_limit,
+                ..default_canister_state_bits()
+            };
+
+            let pb_bits = pb_canister_state_bits::CanisterStateBits::from(canister_state_bits);
+            let canister_state_bits = CanisterStateBits::try_from(pb_bits).unwrap();
+            assert_eq!(canister_state_bits.wasm_memory_limit, wasm_memory_limit);
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_executed_instructions() {
+        let executed_instructions = ExecutedInstructions {
+            update: NumInstructions::from(1),
+            query: NumInstructions::from(2),
+            system_task: NumInstructions::from(3),
+        };
+        let canister_state_bits = CanisterStateBits {
+            executed_instructions,
+            ..default_canister_state_bits()
+        };
+
+        let pb_bits = pb_canister_state_bits::CanisterStateBits::from(canister_state_bits);
+        let canister_state_bits = CanisterStateBits::try_from(pb_bits).unwrap();
+        assert_eq!(canister_state_bits.executed_instructions, executed_instructions);
+    }
+
+    #[test]
+    fn test_encode_decode_query_stats() {
+        let query_stats = BTreeMap::from([
+            (
+                "get".to_string(),
+                QueryStats {
+                    num_calls: 2,
+                    num_instructions: NumInstructions::from(1_000),
+                    egress_payload_size: NumBytes::from(100),
+                },
+            ),
+            ("list".to_string(), QueryStats::default()),
+        ]);
+        let canister_state_bits = CanisterStateBits {
+            query_stats: query_stats.clone(),
+            ..default_canister_state_bits()
+        };
+
+        let pb_bits = pb_canister_state_bits::CanisterStateBits::from(canister_state_bits);
+        let canister_state_bits = CanisterStateBits::try_from(pb_bits).unwrap();
+        assert_eq!(canister_state_bits.query_stats, query_stats);
+    }
+
+    #[test]
+    fn test_encode_decode_reserved_balance() {
+        for reserved_balance_limit in [None, Some(Cycles::new(2_000))] {
+            let canister_state_bits = CanisterStateBits {
+                reserved_balance: Cycles::new(1_000),
+                reserved_balance_limit,
+                ..default_canister_state_bits()
+            };
+
+            let pb_bits = pb_canister_state_bits::CanisterStateBits::from(canister_state_bits);
+            let canister_state_bits = CanisterStateBits::try_from(pb_bits).unwrap();
+            assert_eq!(canister_state_bits.reserved_balance, Cycles::new(1_000));
+            assert_eq!(canister_state_bits.reserved_balance_limit, reserved_balance_limit);
+        }
+    }
 }
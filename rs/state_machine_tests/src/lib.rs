@@ -1,26 +1,34 @@
 use ic_config::flag_status::FlagStatus;
 use ic_config::{
-    execution_environment::Config as HypervisorConfig,
+    execution_environment::{ChaosConfig, Config as HypervisorConfig},
     subnet_config::{SubnetConfig, SubnetConfigs},
 };
 use ic_constants::SMALL_APP_SUBNET_MAX_SIZE;
+use ic_crypto_internal_basic_sig_ecdsa_secp256k1 as ecdsa_secp256k1;
 use ic_crypto_internal_seed::Seed;
 use ic_crypto_internal_threshold_sig_bls12381::api::{
     combine_signatures, combined_public_key, keygen, sign_message,
 };
 use ic_crypto_internal_threshold_sig_bls12381::types::SecretKeyBytes;
+use ic_crypto_internal_threshold_sig_ecdsa::{
+    derive_public_key, DerivationPath, EccCurveType, EccPoint, EccScalar, EcdsaPublicKey,
+};
 use ic_crypto_internal_types::sign::threshold_sig::public_key::CspThresholdSigPublicKey;
 use ic_crypto_tree_hash::{flatmap, Label, LabeledTree, LabeledTree::SubTree};
 use ic_cycles_account_manager::CyclesAccountManager;
 pub use ic_error_types::{ErrorCode, UserError};
 use ic_execution_environment::ExecutionServices;
-use ic_ic00_types::{self as ic00, CanisterIdRecord, InstallCodeArgs, Method, Payload};
+use ic_ic00_types::{
+    self as ic00, CanisterIdRecord, InstallCodeArgs, Method, Payload, SignWithECDSAReply,
+};
 pub use ic_ic00_types::{
     CanisterInstallMode, CanisterSettingsArgs, EcdsaKeyId, UpdateSettingsArgs,
 };
 use ic_interfaces::{
     certification::{Verifier, VerifierError},
-    execution_environment::{IngressHistoryReader, QueryHandler},
+    execution_environment::{
+        IngressFilterService, IngressHistoryReader, InstructionProfile, QueryHandler,
+    },
     messaging::MessageRouting,
     validation::ValidationResult,
 };
@@ -66,7 +74,8 @@ use ic_types::consensus::certification::CertificationContent;
 use ic_types::crypto::threshold_sig::ni_dkg::{NiDkgId, NiDkgTag, NiDkgTargetSubnet};
 pub use ic_types::crypto::threshold_sig::ThresholdSigPublicKey;
 use ic_types::crypto::{
-    canister_threshold_sig::MasterEcdsaPublicKey, AlgorithmId, CombinedThresholdSig,
+    canister_threshold_sig::{ExtendedDerivationPath, MasterEcdsaPublicKey},
+    AlgorithmId, CombinedThresholdSig,
     CombinedThresholdSigOf, Signable, Signed,
 };
 use ic_types::messages::{CallbackId, Certificate};
@@ -76,20 +85,23 @@ use ic_types::{
     canister_http::CanisterHttpRequestContext,
     consensus::certification::Certification,
     messages::{
-        Blob, HttpCallContent, HttpCanisterUpdate, HttpRequestEnvelope, SignedIngress, UserQuery,
+        Blob, HttpCallContent, HttpCanisterUpdate, HttpRequestEnvelope, Payload as ResponsePayload,
+        Response, SignedIngress, UserQuery,
     },
     time::current_time_and_expiry_time,
-    CryptoHashOfPartialState, Height, NodeId, NumberOfNodes, Randomness, RegistryVersion,
+    CanisterTimer, Height, NodeId, NumberOfNodes, Randomness, RegistryVersion,
 };
 pub use ic_types::{
     ingress::{IngressState, IngressStatus, WasmResult},
     messages::MessageId,
     time::Time,
-    CanisterId, CryptoHashOfState, Cycles, PrincipalId, SubnetId, UserId,
+    CanisterId, CryptoHashOfPartialState, CryptoHashOfState, Cycles, PrincipalId, SubnetId,
+    UserId,
 };
 use serde::Serialize;
 pub use slog::Level;
 use std::fmt;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::string::ToString;
 use std::sync::Arc;
@@ -112,6 +124,35 @@ impl Verifier for FakeVerifier {
 
 const GENESIS: Time = Time::from_nanos_since_unix_epoch(1_620_328_630_000_000_000);
 
+/// Returns the secret key of the given ECDSA key held by the subnet. The key
+/// is derived from its name to keep tests reproducible.
+fn ecdsa_master_secret_key(key_id: &EcdsaKeyId) -> EccScalar {
+    EccScalar::from_seed(EccCurveType::K256, Seed::from_bytes(key_id.name.as_bytes()))
+}
+
+/// Signs the message hash of the given `sign_with_ecdsa` request with the key
+/// derived for the calling canister and the requested derivation path.
+fn ecdsa_sign(context: &SignWithEcdsaContext) -> Vec<u8> {
+    let master_secret_key = ecdsa_master_secret_key(&context.key_id);
+    let master_public_key = EccPoint::mul_by_g(&master_secret_key).unwrap();
+    let derivation_path = DerivationPath::from(&ExtendedDerivationPath {
+        caller: context.request.sender.get(),
+        derivation_path: context.derivation_path.clone(),
+    });
+    let (key_tweak, _chain_key) = derivation_path.derive_tweak(&master_public_key).unwrap();
+    let secret_key = master_secret_key.add(&key_tweak).unwrap();
+    let public_key = EccPoint::mul_by_g(&secret_key).unwrap();
+    let secret_key = ecdsa_secp256k1::secret_key_from_components(
+        &secret_key.serialize(),
+        &ecdsa_secp256k1::types::PublicKeyBytes(public_key.serialize()),
+    )
+    .expect("failed to construct the derived ECDSA secret key");
+    ecdsa_secp256k1::sign(&context.message_hash, &secret_key)
+        .expect("failed to sign with the derived ECDSA key")
+        .0
+        .to_vec()
+}
+
 /// Constructs the initial version of the registry containing a subnet with the
 /// specified SUBNET_ID, with the nodes with the specified NODE_IDs.
 fn make_nodes_registry(
@@ -224,6 +265,71 @@ fn make_nodes_registry(
     (data_provider, registry_client)
 }
 
+/// Copies the latest checkpoint and the registry of the NNS state backup in
+/// `backup_dir` and returns the id of the NNS subnet and the registry.
+#[cfg(feature = "nns-state")]
+fn load_nns_state(
+    backup_dir: &std::path::Path,
+    state_dir: &std::path::Path,
+) -> (SubnetId, Arc<ProtoRegistryDataProvider>, Arc<FakeRegistryClient>) {
+    use ic_registry_client_helpers::subnet::SubnetRegistry;
+    use ic_registry_local_store::{LocalStoreImpl, LocalStoreReader};
+
+    let changelog = LocalStoreImpl::new(backup_dir.join("ic_registry_local_store"))
+        .get_changelog_since_version(RegistryVersion::from(0))
+        .expect("failed to read the registry local store of the backup");
+    let data_provider = Arc::new(ProtoRegistryDataProvider::new());
+    for (i, entry) in changelog.into_iter().enumerate() {
+        let version = RegistryVersion::from(i as u64 + 1);
+        for mutation in entry {
+            data_provider
+                .add_encoded(&mutation.key, version, mutation.value)
+                .unwrap();
+        }
+    }
+    let registry_client = Arc::new(FakeRegistryClient::new(Arc::clone(&data_provider) as _));
+    registry_client.update_to_latest_version();
+
+    let subnet_id = registry_client
+        .get_root_subnet_id(registry_client.get_latest_version())
+        .expect("malformed root subnet id")
+        .expect("missing root subnet id");
+
+    // Only the checkpoints are needed, the state manager recreates the rest.
+    // Checkpoints are named after their height in fixed-width hex, so the
+    // greatest name is the latest checkpoint.
+    let checkpoints = backup_dir.join("ic_state").join("checkpoints");
+    let latest_checkpoint = std::fs::read_dir(&checkpoints)
+        .unwrap_or_else(|err| panic!("failed to read {}: {}", checkpoints.display(), err))
+        .map(|entry| entry.expect("failed to read a checkpoint").path())
+        .max()
+        .unwrap_or_else(|| panic!("no checkpoints in {}", checkpoints.display()));
+    let target = state_dir
+        .join("checkpoints")
+        .join(latest_checkpoint.file_name().unwrap());
+    copy_dir(&latest_checkpoint, &target);
+
+    (subnet_id, data_provider, registry_client)
+}
+
+#[cfg(feature = "nns-state")]
+fn copy_dir(source: &std::path::Path, target: &std::path::Path) {
+    std::fs::create_dir_all(target)
+        .unwrap_or_else(|err| panic!("failed to create {}: {}", target.display(), err));
+    for entry in std::fs::read_dir(source)
+        .unwrap_or_else(|err| panic!("failed to read {}: {}", source.display(), err))
+    {
+        let path = entry.expect("failed to read a directory entry").path();
+        let target = target.join(path.file_name().unwrap());
+        if path.is_dir() {
+            copy_dir(&path, &target);
+        } else {
+            std::fs::copy(&path, &target)
+                .unwrap_or_else(|err| panic!("failed to copy {}: {}", path.display(), err));
+        }
+    }
+}
+
 /// Convert an object into CBOR binary.
 fn into_cbor<R: Serialize>(r: &R) -> Vec<u8> {
     let mut ser = serde_cbor::Serializer::new(Vec::new());
@@ -232,6 +338,10 @@ fn into_cbor<R: Serialize>(r: &R) -> Vec<u8> {
     ser.into_inner()
 }
 
+fn to_system_time(time: Time) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_nanos(time.as_nanos_since_unix_epoch())
+}
+
 /// Bundles the configuration of a `StateMachine`.
 pub struct StateMachineConfig {
     subnet_config: SubnetConfig,
@@ -260,14 +370,26 @@ pub struct StateMachine {
     metrics_registry: MetricsRegistry,
     ingress_history_reader: Box<dyn IngressHistoryReader>,
     query_handler: Arc<dyn QueryHandler<State = ReplicatedState>>,
-    _runtime: Runtime,
+    ingress_filter: std::cell::RefCell<IngressFilterService>,
+    runtime: Runtime,
     state_dir: TempDir,
     checkpoints_enabled: std::cell::Cell<bool>,
     nonce: std::cell::Cell<u64>,
     time: std::cell::Cell<Time>,
+    strictly_monotonic_time: bool,
     ecdsa_subnet_public_keys: BTreeMap<EcdsaKeyId, MasterEcdsaPublicKey>,
 }
 
+/// An error of [`StateMachine::try_set_time`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum TimeError {
+    /// The requested time is before the time of the last executed block.
+    BeforeLastBlock {
+        requested: SystemTime,
+        last_block: SystemTime,
+    },
+}
+
 impl Default for StateMachine {
     fn default() -> Self {
         Self::new()
@@ -287,6 +409,7 @@ pub struct StateMachineBuilder {
     state_dir: TempDir,
     nonce: u64,
     time: Time,
+    strictly_monotonic_time: bool,
     config: Option<StateMachineConfig>,
     checkpoints_enabled: bool,
     subnet_type: SubnetType,
@@ -294,6 +417,9 @@ pub struct StateMachineBuilder {
     use_cost_scaling_flag: bool,
     ecdsa_keys: Vec<EcdsaKeyId>,
     features: SubnetFeatures,
+    instruction_profiling: bool,
+    chaos_mode: Option<ChaosConfig>,
+    nns_state_dir: Option<PathBuf>,
 }
 
 impl StateMachineBuilder {
@@ -302,6 +428,7 @@ impl StateMachineBuilder {
             state_dir: TempDir::new().expect("failed to create a temporary directory"),
             nonce: 0,
             time: GENESIS,
+            strictly_monotonic_time: false,
             config: None,
             checkpoints_enabled: false,
             subnet_type: SubnetType::System,
@@ -309,6 +436,9 @@ impl StateMachineBuilder {
             subnet_size: SMALL_APP_SUBNET_MAX_SIZE,
             ecdsa_keys: Vec::new(),
             features: SubnetFeatures::default(),
+            instruction_profiling: false,
+            chaos_mode: None,
+            nns_state_dir: None,
         }
     }
 
@@ -335,6 +465,16 @@ impl StateMachineBuilder {
         }
     }
 
+    /// Makes the time of every block strictly greater than the time of the
+    /// previous block, as on the IC: a block executed without advancing the
+    /// time is 1ns later than the previous one.
+    pub fn with_strictly_monotonic_time(self, strictly_monotonic_time: bool) -> Self {
+        Self {
+            strictly_monotonic_time,
+            ..self
+        }
+    }
+
     pub fn with_subnet_type(self, subnet_type: SubnetType) -> Self {
         Self {
             subnet_type,
@@ -366,11 +506,51 @@ impl StateMachineBuilder {
         Self { features, ..self }
     }
 
+    /// Enables counting the instructions executed by each canister function.
+    /// The profile of the last message execution of a canister is returned by
+    /// [`StateMachine::instruction_profile`].
+    pub fn with_instruction_profiling(self, instruction_profiling: bool) -> Self {
+        Self {
+            instruction_profiling,
+            ..self
+        }
+    }
+
+    /// Enables the chaos mode of the execution environment: canister traps,
+    /// delayed responses, and reorderings of messages from different
+    /// canisters are injected with the configured probabilities. The same
+    /// seed and the same sequence of calls inject the same faults.
+    pub fn with_chaos_mode(self, chaos_mode: ChaosConfig) -> Self {
+        Self {
+            chaos_mode: Some(chaos_mode),
+            ..self
+        }
+    }
+
+    /// Initializes the state machine from the NNS state backup in
+    /// `backup_dir`, which must contain the `ic_state` and the
+    /// `ic_registry_local_store` directories of an NNS node.
+    ///
+    /// The state machine loads the latest checkpoint of the backup, takes the
+    /// id of the NNS subnet and uses the registry of the backup, so the NNS
+    /// canisters keep their ids and calls are routed like on the NNS. The
+    /// backup directory is not modified. Restarting or upgrading the state
+    /// machine drops the registry of the backup, so it is not supported.
+    #[cfg(feature = "nns-state")]
+    pub fn with_nns_state(self, backup_dir: PathBuf) -> Self {
+        Self {
+            nns_state_dir: Some(backup_dir),
+            subnet_type: SubnetType::System,
+            ..self
+        }
+    }
+
     pub fn build(self) -> StateMachine {
         StateMachine::setup_from_dir(
             self.state_dir,
             self.nonce,
             self.time,
+            self.strictly_monotonic_time,
             self.config,
             self.checkpoints_enabled,
             self.subnet_type,
@@ -378,6 +558,9 @@ impl StateMachineBuilder {
             self.use_cost_scaling_flag,
             self.ecdsa_keys,
             self.features,
+            self.instruction_profiling,
+            self.chaos_mode,
+            self.nns_state_dir,
         )
     }
 }
@@ -408,6 +591,7 @@ impl StateMachine {
         state_dir: TempDir,
         nonce: u64,
         time: Time,
+        strictly_monotonic_time: bool,
         config: Option<StateMachineConfig>,
         checkpoints_enabled: bool,
         subnet_type: SubnetType,
@@ -415,6 +599,9 @@ impl StateMachine {
         use_cost_scaling_flag: bool,
         ecdsa_keys: Vec<EcdsaKeyId>,
         features: SubnetFeatures,
+        instruction_profiling: bool,
+        chaos_mode: Option<ChaosConfig>,
+        nns_state_dir: Option<PathBuf>,
     ) -> Self {
         use slog::Drain;
 
@@ -445,16 +632,23 @@ impl StateMachine {
             ),
         };
 
-        let nns_subnet_id = SubnetId::from(PrincipalId::new_subnet_test_id(1));
-        let subnet_id = SubnetId::from(PrincipalId::new_subnet_test_id(2));
-        let (registry_data_provider, registry_client) = make_nodes_registry(
-            nns_subnet_id,
-            subnet_id,
-            subnet_type,
-            &node_ids,
-            &ecdsa_keys,
-            features,
-        );
+        let (subnet_id, registry_data_provider, registry_client) = match nns_state_dir {
+            #[cfg(feature = "nns-state")]
+            Some(backup_dir) => load_nns_state(&backup_dir, state_dir.path()),
+            _ => {
+                let nns_subnet_id = SubnetId::from(PrincipalId::new_subnet_test_id(1));
+                let subnet_id = SubnetId::from(PrincipalId::new_subnet_test_id(2));
+                let (registry_data_provider, registry_client) = make_nodes_registry(
+                    nns_subnet_id,
+                    subnet_id,
+                    subnet_type,
+                    &node_ids,
+                    &ecdsa_keys,
+                    features,
+                );
+                (subnet_id, registry_data_provider, registry_client)
+            }
+        };
 
         let sm_config = ic_config::state_manager::Config::new(state_dir.path().to_path_buf());
 
@@ -463,6 +657,14 @@ impl StateMachine {
             hypervisor_config.deterministic_time_slicing = FlagStatus::Disabled;
         }
 
+        if instruction_profiling {
+            hypervisor_config.instruction_profiling = FlagStatus::Enabled;
+        }
+
+        if chaos_mode.is_some() {
+            hypervisor_config.chaos_mode = chaos_mode;
+        }
+
         let mut cycles_account_manager = CyclesAccountManager::new(
             subnet_config.scheduler_config.max_instructions_per_message,
             subnet_type,
@@ -482,6 +684,11 @@ impl StateMachine {
             ic_types::malicious_flags::MaliciousFlags::default(),
         ));
 
+        // The loaded state can be newer than `time`, e.g. if it comes from a
+        // backup, and the batch time must not go backwards.
+        let batch_time = state_manager.get_latest_state().take().metadata.batch_time;
+        let time = time.max(batch_time);
+
         let runtime = tokio::runtime::Builder::new_current_thread()
             .build()
             .expect("failed to create a tokio runtime");
@@ -533,11 +740,14 @@ impl StateMachine {
 
         let mut ecdsa_subnet_public_keys = BTreeMap::new();
         for ecdsa_key in ecdsa_keys {
+            let public_key = EccPoint::mul_by_g(&ecdsa_master_secret_key(&ecdsa_key))
+                .expect("failed to compute the master ECDSA public key")
+                .serialize();
             ecdsa_subnet_public_keys.insert(
                 ecdsa_key,
                 MasterEcdsaPublicKey {
                     algorithm_id: AlgorithmId::EcdsaSecp256k1,
-                    public_key: b"master_ecdsa_public_key".to_vec(),
+                    public_key,
                 },
             );
         }
@@ -553,22 +763,25 @@ impl StateMachine {
             message_routing,
             metrics_registry,
             query_handler: execution_services.sync_query_handler,
-            _runtime: runtime,
+            ingress_filter: std::cell::RefCell::new(execution_services.ingress_filter),
+            runtime,
             state_dir,
             // Note: state machine tests are commonly used for testing
             // canisters, such tests usually don't rely on any persistence.
             checkpoints_enabled: std::cell::Cell::new(checkpoints_enabled),
             nonce: std::cell::Cell::new(nonce),
             time: std::cell::Cell::new(time),
+            strictly_monotonic_time,
             ecdsa_subnet_public_keys,
         }
     }
 
-    fn into_components(self) -> (TempDir, u64, Time, bool) {
+    fn into_components(self) -> (TempDir, u64, Time, bool, bool) {
         (
             self.state_dir,
             self.nonce.get(),
             self.time.get(),
+            self.strictly_monotonic_time,
             self.checkpoints_enabled.get(),
         )
     }
@@ -577,12 +790,14 @@ impl StateMachine {
     pub fn restart_node(self) -> Self {
         // We must drop self before setup_form_dir so that we don't have two StateManagers pointing
         // to the same root.
-        let (state_dir, nonce, time, checkpoints_enabled) = self.into_components();
+        let (state_dir, nonce, time, strictly_monotonic_time, checkpoints_enabled) =
+            self.into_components();
 
         StateMachineBuilder::new()
             .with_state_dir(state_dir)
             .with_nonce(nonce)
             .with_time(time)
+            .with_strictly_monotonic_time(strictly_monotonic_time)
             .with_checkpoints_enabled(checkpoints_enabled)
             .build()
     }
@@ -592,17 +807,47 @@ impl StateMachine {
     pub fn restart_node_with_config(self, config: StateMachineConfig) -> Self {
         // We must drop self before setup_form_dir so that we don't have two StateManagers pointing
         // to the same root.
-        let (state_dir, nonce, time, checkpoints_enabled) = self.into_components();
+        let (state_dir, nonce, time, strictly_monotonic_time, checkpoints_enabled) =
+            self.into_components();
 
         StateMachineBuilder::new()
             .with_state_dir(state_dir)
             .with_nonce(nonce)
             .with_time(time)
+            .with_strictly_monotonic_time(strictly_monotonic_time)
             .with_config(Some(config))
             .with_checkpoints_enabled(checkpoints_enabled)
             .build()
     }
 
+    /// Emulates an upgrade of the replica to a new version: executes a
+    /// checkpoint round, as the subnet does at the upgrade height, and
+    /// restarts from the checkpoint of that round.
+    ///
+    /// Only what is in the checkpoint survives the upgrade. The messages in
+    /// the queues and the open call contexts do, so calls that are in flight
+    /// complete after the upgrade, but paused long executions are aborted
+    /// and start over. The upgraded state machine has the default
+    /// configuration, or `config` to emulate a replica version that comes
+    /// with a different configuration.
+    pub fn upgrade_replica(self, config: Option<StateMachineConfig>) -> Self {
+        let checkpoints_enabled = self.checkpoints_enabled.get();
+        self.set_checkpoints_enabled(true);
+        self.tick();
+        // We must drop self before setup_form_dir so that we don't have two StateManagers pointing
+        // to the same root.
+        let (state_dir, nonce, time, strictly_monotonic_time, _) = self.into_components();
+
+        StateMachineBuilder::new()
+            .with_state_dir(state_dir)
+            .with_nonce(nonce)
+            .with_time(time)
+            .with_strictly_monotonic_time(strictly_monotonic_time)
+            .with_config(config)
+            .with_checkpoints_enabled(checkpoints_enabled)
+            .build()
+    }
+
     /// If the argument is true, the state machine will create an on-disk
     /// checkpoint for each new state it creates.
     ///
@@ -616,13 +861,13 @@ impl StateMachine {
     /// Creates a new batch containing a single ingress message and sends it for
     /// processing to the replicated state machine.
     fn send_signed_ingress(&self, msg: SignedIngress) {
-        self.execute_block_with_ingress_payload(IngressPayload::from(vec![msg]))
+        self.execute_block(IngressPayload::from(vec![msg]), vec![])
     }
 
     /// Triggers a single round of execution without any new inputs.  The state
     /// machine will invoke hearbeats and make progress on pending async calls.
     pub fn tick(&self) {
-        self.execute_block_with_ingress_payload(IngressPayload::default())
+        self.execute_block(IngressPayload::default(), vec![])
     }
 
     /// Makes the state machine tick until there are no more messages in the system.
@@ -655,9 +900,16 @@ impl StateMachine {
         }
     }
 
-    fn execute_block_with_ingress_payload(&self, ingress: IngressPayload) {
+    fn execute_block(&self, ingress: IngressPayload, consensus_responses: Vec<Response>) {
         let batch_number = self.message_routing.expected_batch_height();
 
+        if self.strictly_monotonic_time {
+            let last_block_time = self.last_block_time();
+            if self.time.get() <= last_block_time {
+                self.time.set(last_block_time + Duration::from_nanos(1));
+            }
+        }
+
         let mut seed = [0u8; 32];
         // use the batch number to seed randomness
         seed[..8].copy_from_slice(batch_number.get().to_le_bytes().as_slice());
@@ -673,7 +925,7 @@ impl StateMachine {
             ecdsa_subnet_public_keys: self.ecdsa_subnet_public_keys.clone(),
             registry_version: self.registry_client.get_latest_version(),
             time: self.time.get(),
-            consensus_responses: vec![],
+            consensus_responses,
         };
         self.message_routing
             .deliver_batch(batch)
@@ -707,6 +959,16 @@ impl StateMachine {
         &self.metrics_registry
     }
 
+    /// Returns the number of execution rounds this state machine executed.
+    pub fn rounds_executed(&self) -> u64 {
+        fetch_histogram_stats(
+            &self.metrics_registry,
+            "scheduler_instructions_consumed_per_round",
+        )
+        .map(|stats| stats.count)
+        .unwrap_or(0)
+    }
+
     /// Returns the total number of Wasm instructions this state machine consumed in replicated
     /// message execution (ingress messages, inter-canister messages, and heartbeats).
     pub fn instructions_consumed(&self) -> f64 {
@@ -749,9 +1011,47 @@ impl StateMachine {
         ));
     }
 
+    /// Sets the time like [`set_time`](Self::set_time), but fails instead of
+    /// setting a time before the time of the last executed block, which would
+    /// make the time of the next block go backwards.
+    pub fn try_set_time(&self, time: SystemTime) -> Result<(), TimeError> {
+        let last_block = to_system_time(self.last_block_time());
+        if time < last_block {
+            return Err(TimeError::BeforeLastBlock {
+                requested: time,
+                last_block,
+            });
+        }
+        self.set_time(time);
+        Ok(())
+    }
+
     /// Returns the current state machine time.
     pub fn time(&self) -> SystemTime {
-        SystemTime::UNIX_EPOCH + Duration::from_nanos(self.time.get().as_nanos_since_unix_epoch())
+        to_system_time(self.time.get())
+    }
+
+    /// Returns the earliest time at which the global timer of a canister
+    /// expires, or `None` if no canister has an active timer. Advancing the
+    /// time to it and ticking runs the timer.
+    pub fn time_of_next_timer_expiration(&self) -> Option<SystemTime> {
+        let state = self.state_manager.get_latest_state().take();
+        state
+            .canisters_iter()
+            .filter_map(|canister| match canister.system_state.global_timer {
+                CanisterTimer::Active(time) => Some(time),
+                CanisterTimer::Inactive => None,
+            })
+            .min()
+            .map(to_system_time)
+    }
+
+    fn last_block_time(&self) -> Time {
+        self.state_manager
+            .get_latest_state()
+            .take()
+            .metadata
+            .batch_time
     }
 
     /// Advances the state machine time by the given amount.
@@ -1056,12 +1356,7 @@ impl StateMachine {
         method: impl ToString,
         method_payload: Vec<u8>,
     ) -> Result<WasmResult, UserError> {
-        if self.state_manager.latest_state_height() > self.state_manager.latest_certified_height() {
-            let state_hashes = self.state_manager.list_state_hashes_to_certify();
-            let (height, hash) = state_hashes.last().unwrap();
-            self.state_manager
-                .deliver_state_certification(self.certify_hash(height, hash));
-        }
+        self.certify_latest_state();
 
         let path = SubTree(flatmap! {
             Label::from("canister") => SubTree(
@@ -1092,6 +1387,35 @@ impl StateMachine {
         )
     }
 
+    /// Certifies the latest state and returns its certified state hash.
+    ///
+    /// The randomness of each round is derived from the batch number, so two
+    /// state machines that process the same sequence of calls end up with
+    /// the same hash. Comparing the hashes after each round detects the round
+    /// in which the executions diverged.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if no round has been executed yet.
+    pub fn certified_state_hash(&self) -> CryptoHashOfPartialState {
+        self.certify_latest_state();
+        let path = SubTree(flatmap!(Label::from("time") => LabeledTree::Leaf(())));
+        let (_state, _tree, certification) = self
+            .state_manager
+            .read_certified_state(&path)
+            .expect("failed to read the certified state");
+        certification.signed.content.hash
+    }
+
+    fn certify_latest_state(&self) {
+        if self.state_manager.latest_state_height() > self.state_manager.latest_certified_height() {
+            let state_hashes = self.state_manager.list_state_hashes_to_certify();
+            let (height, hash) = state_hashes.last().unwrap();
+            self.state_manager
+                .deliver_state_certification(self.certify_hash(height, hash));
+        }
+    }
+
     fn certify_hash(&self, height: &Height, hash: &CryptoHashOfPartialState) -> Certification {
         let signature_bytes = Some(
             sign_message(
@@ -1135,6 +1459,21 @@ impl StateMachine {
         )
     }
 
+    /// Returns the number of instructions executed by each function of the
+    /// specified canister during its last message execution. The profile is
+    /// empty unless the state machine was built with instruction profiling.
+    pub fn instruction_profile(&self, canister_id: CanisterId) -> Option<InstructionProfile> {
+        let state = self.state_manager.get_latest_state().take();
+        let canister_state = state.canister_state(&canister_id)?;
+        Some(
+            canister_state
+                .execution_state
+                .as_ref()?
+                .last_instruction_profile
+                .clone(),
+        )
+    }
+
     /// Executes an ingress message on the canister with the specified ID.
     ///
     /// This function is synchronous, it blocks until the result of the ingress
@@ -1176,8 +1515,47 @@ impl StateMachine {
         method: impl ToString,
         payload: Vec<u8>,
     ) -> MessageId {
+        let msg = self.signed_ingress(sender, canister_id, method, payload);
+        let msg_id = msg.id();
+        self.send_signed_ingress(msg);
+        msg_id
+    }
+
+    /// Checks whether the ingress message would be accepted for execution,
+    /// like the HTTP endpoint does before it sends the message to consensus.
+    /// For messages to canisters this runs `canister_inspect_message`, so the
+    /// error contains the reject message of the canister, if any.
+    pub fn should_accept_ingress_message(
+        &self,
+        sender: PrincipalId,
+        canister_id: CanisterId,
+        method: impl ToString,
+        payload: Vec<u8>,
+    ) -> Result<(), UserError> {
+        use tower::{Service, ServiceExt};
+
+        let msg = self.signed_ingress(sender, canister_id, method, payload);
+        let mut ingress_filter = self.ingress_filter.borrow_mut();
+        self.runtime.block_on(async {
+            ingress_filter
+                .ready()
+                .await
+                .expect("The service must always be able to process requests")
+                .call((ProvisionalWhitelist::new_empty(), msg.content().clone()))
+                .await
+                .expect("The service must always be able to process requests")
+        })
+    }
+
+    fn signed_ingress(
+        &self,
+        sender: PrincipalId,
+        canister_id: CanisterId,
+        method: impl ToString,
+        payload: Vec<u8>,
+    ) -> SignedIngress {
         self.nonce.set(self.nonce.get() + 1);
-        let msg = SignedIngress::try_from(HttpRequestEnvelope::<HttpCallContent> {
+        SignedIngress::try_from(HttpRequestEnvelope::<HttpCallContent> {
             content: HttpCallContent::Call {
                 update: HttpCanisterUpdate {
                     canister_id: Blob(canister_id.get().into_vec()),
@@ -1192,11 +1570,7 @@ impl StateMachine {
             sender_sig: None,
             sender_delegation: None,
         })
-        .unwrap();
-
-        let msg_id = msg.id();
-        self.send_signed_ingress(msg);
-        msg_id
+        .unwrap()
     }
 
     /// Returns the status of the ingress message with the specified ID.
@@ -1459,6 +1833,74 @@ impl StateMachine {
             .clone()
     }
 
+    /// Returns the public key that the `ecdsa_public_key` method of the
+    /// management canister returns to the given canister for the given
+    /// derivation path.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the subnet does not hold the specified key.
+    pub fn ecdsa_public_key(
+        &self,
+        key_id: &EcdsaKeyId,
+        canister_id: CanisterId,
+        derivation_path: Vec<Vec<u8>>,
+    ) -> EcdsaPublicKey {
+        let master_public_key = self
+            .ecdsa_subnet_public_keys
+            .get(key_id)
+            .unwrap_or_else(|| panic!("ECDSA key {} not found", key_id));
+        let derivation_path = DerivationPath::from(&ExtendedDerivationPath {
+            caller: canister_id.get(),
+            derivation_path,
+        });
+        derive_public_key(master_public_key, &derivation_path)
+            .expect("failed to derive the ECDSA public key")
+    }
+
+    /// Signs the message hashes of all pending `sign_with_ecdsa` requests with
+    /// the keys derived for the calling canisters and executes a round that
+    /// delivers the signatures to the callers.
+    ///
+    /// The signatures can be checked with [`Self::verify_ecdsa_signature`]
+    /// against the keys returned by [`Self::ecdsa_public_key`].
+    pub fn sign_pending_ecdsa_requests(&self) {
+        let responses = self
+            .sign_with_ecdsa_contexts()
+            .into_iter()
+            .map(|(callback_id, context)| Response {
+                originator: context.request.sender,
+                respondent: CanisterId::ic_00(),
+                originator_reply_callback: callback_id,
+                refund: context.request.payment,
+                response_payload: ResponsePayload::Data(
+                    SignWithECDSAReply {
+                        signature: ecdsa_sign(&context),
+                    }
+                    .encode(),
+                ),
+                deadline: context.request.deadline,
+            })
+            .collect();
+        self.execute_block(IngressPayload::default(), responses);
+    }
+
+    /// Returns true if `signature` is a valid ECDSA signature of
+    /// `message_hash` for the SEC1-encoded secp256k1 `public_key`.
+    pub fn verify_ecdsa_signature(
+        &self,
+        public_key: &[u8],
+        message_hash: &[u8],
+        signature: &[u8],
+    ) -> bool {
+        let signature = match signature.try_into() {
+            Ok(signature) => ecdsa_secp256k1::types::SignatureBytes(signature),
+            Err(_) => return false,
+        };
+        let public_key = ecdsa_secp256k1::types::PublicKeyBytes(public_key.to_vec());
+        ecdsa_secp256k1::verify(&signature, message_hash, &public_key).is_ok()
+    }
+
     /// Returns canister HTTP request contexts from internal subnet call context manager.
     pub fn canister_http_request_contexts(
         &self,
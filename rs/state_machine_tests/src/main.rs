@@ -2,12 +2,14 @@ use clap::Parser;
 use ic_crypto::threshold_sig_public_key_to_der;
 use ic_error_types::UserError;
 use ic_ic00_types::{CanisterIdRecord, CanisterInstallMode, InstallCodeArgs};
-use ic_state_machine_tests::StateMachine;
+use ic_state_machine_tests::{StateMachine, StateMachineBuilder};
 use ic_types::ingress::WasmResult;
 use ic_types::{CanisterId, PrincipalId};
 use serde::{Deserialize, Serialize};
-use std::io::{stdin, stdout, Read, Write};
-use std::time::Duration;
+use std::fs::File;
+use std::io::{stdin, stdout, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 use Request::*;
 
 macro_rules! debug_print {
@@ -23,6 +25,8 @@ enum Request {
     RootKey,
     Time,
     AdvanceTime(Duration),
+    SetTime(SystemTime),
+    GetTimeOfNextTimerExpiration,
     CanisterUpdateCall(CanisterCall),
     CanisterQueryCall(CanisterCall),
     CanisterExists(RawCanisterId),
@@ -30,6 +34,66 @@ enum Request {
     AddCycles(AddCyclesArg),
     SetStableMemory(SetStableMemoryArg),
     ReadStableMemory(RawCanisterId),
+    Tick,
+    StateHash,
+    UpgradeReplica,
+}
+
+impl Request {
+    fn name(&self) -> &'static str {
+        match self {
+            RootKey => "RootKey",
+            Time => "Time",
+            AdvanceTime(_) => "AdvanceTime",
+            SetTime(_) => "SetTime",
+            GetTimeOfNextTimerExpiration => "GetTimeOfNextTimerExpiration",
+            CanisterUpdateCall(_) => "CanisterUpdateCall",
+            CanisterQueryCall(_) => "CanisterQueryCall",
+            CanisterExists(_) => "CanisterExists",
+            CyclesBalance(_) => "CyclesBalance",
+            AddCycles(_) => "AddCycles",
+            SetStableMemory(_) => "SetStableMemory",
+            ReadStableMemory(_) => "ReadStableMemory",
+            Tick => "Tick",
+            StateHash => "StateHash",
+            UpgradeReplica => "UpgradeReplica",
+        }
+    }
+}
+
+/// A line of the JSON trace written with `--trace-json`.
+#[derive(Serialize)]
+struct TraceEntry {
+    request: &'static str,
+    /// The name of the called method for canister calls.
+    method: Option<String>,
+    duration_micros: u128,
+    rounds_executed: u64,
+    instructions_executed: u64,
+    instructions_per_round: u64,
+}
+
+/// Records the handling of each request as one JSON object per line.
+struct Tracer {
+    writer: BufWriter<File>,
+}
+
+impl Tracer {
+    fn create(path: &Path) -> Self {
+        let file = File::create(path)
+            .unwrap_or_else(|err| panic!("failed to create trace file {:?}: {}", path, err));
+        Self {
+            writer: BufWriter::new(file),
+        }
+    }
+
+    fn record(&mut self, entry: &TraceEntry) {
+        serde_json::to_writer(&mut self.writer, entry).expect("failed to write trace entry");
+        self.writer
+            .write_all(b"\n")
+            .expect("failed to write trace entry");
+        self.writer.flush().expect("failed to flush trace file");
+    }
 }
 
 #[derive(Deserialize)]
@@ -103,11 +167,32 @@ struct Opts {
     /// Prints additional debug information to stderr (to not interfere with data sent over stdin/stdout).
     #[clap(short, long)]
     debug: bool,
+
+    /// Writes the handling time, the executed rounds, and the executed
+    /// instructions of each request as JSON lines to the given file.
+    #[clap(long)]
+    trace_json: Option<PathBuf>,
+
+    /// Initializes the state machine from the NNS state backup in the given
+    /// directory, which contains the `ic_state` and `ic_registry_local_store`
+    /// directories of an NNS node.
+    #[cfg(feature = "nns-state")]
+    #[clap(long)]
+    nns_state: Option<PathBuf>,
 }
 
 fn main() {
     let opts: Opts = Opts::parse();
-    let env = StateMachine::new();
+    // Every server has its own state machine and thus its own time, which
+    // only moves forward.
+    let builder = StateMachineBuilder::new().with_strictly_monotonic_time(true);
+    #[cfg(feature = "nns-state")]
+    let builder = match &opts.nns_state {
+        Some(backup_dir) => builder.with_nns_state(backup_dir.clone()),
+        None => builder,
+    };
+    let mut env = builder.build();
+    let mut tracer = opts.trace_json.as_deref().map(Tracer::create);
     loop {
         debug_print!(&opts, "enter request loop");
         let size =
@@ -117,6 +202,14 @@ fn main() {
         let payload = read_bytes(size);
         debug_print!(&opts, "payload received: {:?}", hex::encode(&payload));
         let data: Request = ciborium::from_reader(&payload[..]).unwrap();
+        let request = data.name();
+        let method = match &data {
+            CanisterUpdateCall(call) | CanisterQueryCall(call) => Some(call.method.clone()),
+            _ => None,
+        };
+        let started_at = Instant::now();
+        let rounds_before = env.rounds_executed();
+        let instructions_before = env.instructions_consumed();
         match data {
             RootKey => send_response(
                 threshold_sig_public_key_to_der(env.root_key()).unwrap(),
@@ -127,6 +220,10 @@ fn main() {
                 env.advance_time(amount);
                 send_response((), &opts);
             }
+            SetTime(time) => send_response(env.try_set_time(time), &opts),
+            GetTimeOfNextTimerExpiration => {
+                send_response(env.time_of_next_timer_expiration(), &opts)
+            }
             CanisterUpdateCall(call) => {
                 let call = ParsedCanisterCall::from(call);
                 if call.canister_id == CanisterId::ic_00() {
@@ -161,6 +258,15 @@ fn main() {
             CyclesBalance(canister_id) => {
                 send_response(env.cycle_balance(CanisterId::from(canister_id)), &opts)
             }
+            Tick => {
+                env.tick();
+                send_response((), &opts);
+            }
+            StateHash => send_response(env.certified_state_hash().get().0, &opts),
+            UpgradeReplica => {
+                env = env.upgrade_replica(None);
+                send_response((), &opts);
+            }
             AddCycles(arg) => send_response(
                 env.add_cycles(
                     CanisterId::try_from(arg.canister_id).expect("invalid canister id"),
@@ -169,6 +275,21 @@ fn main() {
                 &opts,
             ),
         }
+        if let Some(tracer) = tracer.as_mut() {
+            // The metrics start from scratch after a replica upgrade.
+            let rounds_executed = env.rounds_executed().saturating_sub(rounds_before);
+            let instructions_executed = (env.instructions_consumed() - instructions_before) as u64;
+            tracer.record(&TraceEntry {
+                request,
+                method,
+                duration_micros: started_at.elapsed().as_micros(),
+                rounds_executed,
+                instructions_executed,
+                instructions_per_round: instructions_executed
+                    .checked_div(rounds_executed)
+                    .unwrap_or(0),
+            });
+        }
     }
 }
 
@@ -203,8 +324,17 @@ fn management_call(env: &StateMachine, call: &ParsedCanisterCall, opts: &Opts) {
                 opts,
             );
         }
-        other => {
-            panic!("unsupported management canister call: {}", other)
+        _ => {
+            // All other methods are executed by the management canister of
+            // the state machine, so new management APIs work without changes
+            // to the server.
+            let result = env.execute_ingress_as(
+                call.sender,
+                CanisterId::ic_00(),
+                &call.method,
+                call.arg.clone(),
+            );
+            send_response(result, opts);
         }
     }
 }
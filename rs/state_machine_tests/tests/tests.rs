@@ -1,3 +1,8 @@
+use ic_error_types::{ErrorCode, UserError};
+use ic_ic00_types::{
+    CanisterIdRecord, Method, Payload, ProvisionalCreateCanisterWithCyclesArgs, IC_00,
+};
+use ic_types::{ingress::WasmResult, PrincipalId};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
@@ -8,6 +13,29 @@ use std::time::{Duration, SystemTime};
 enum Request {
     Time,
     AdvanceTime(Duration),
+    SetTime(SystemTime),
+    GetTimeOfNextTimerExpiration,
+    Tick,
+    StateHash,
+    UpgradeReplica,
+    CanisterUpdateCall(CanisterCall),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CanisterCall {
+    sender: Vec<u8>,
+    canister_id: Vec<u8>,
+    method: String,
+    arg: Vec<u8>,
+}
+
+fn management_call(sender: PrincipalId, method: Method, arg: Vec<u8>) -> Request {
+    Request::CanisterUpdateCall(CanisterCall {
+        sender: sender.to_vec(),
+        canister_id: IC_00.get().to_vec(),
+        method: method.to_string(),
+        arg,
+    })
 }
 
 #[derive(Debug, Deserialize, PartialEq, Eq)]
@@ -16,8 +44,15 @@ enum Response {
     Time(SystemTime),
 }
 
-#[test]
-fn test() {
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+enum TimeError {
+    BeforeLastBlock {
+        requested: SystemTime,
+        last_block: SystemTime,
+    },
+}
+
+fn start_state_machine() -> (ChildStdin, ChildStdout) {
     let state_machine_binary =
         std::env::var_os("STATE_MACHINE_BIN").expect("missing state machine binary binary");
     let mut child = Command::new(&state_machine_binary)
@@ -25,9 +60,12 @@ fn test() {
         .stdout(Stdio::piped())
         .spawn()
         .expect("failed to start test state machine");
+    (child.stdin.take().unwrap(), child.stdout.take().unwrap())
+}
 
-    let mut child_in = child.stdin.take().unwrap();
-    let mut child_out = child.stdout.take().unwrap();
+#[test]
+fn test() {
+    let (mut child_in, mut child_out) = start_state_machine();
 
     call_state_machine::<()>(
         Request::AdvanceTime(Duration::from_secs(1000)),
@@ -42,6 +80,123 @@ fn test() {
     );
 }
 
+#[test]
+fn state_hash_is_deterministic() {
+    let (mut first_in, mut first_out) = start_state_machine();
+    let (mut second_in, mut second_out) = start_state_machine();
+    call_state_machine::<()>(Request::Tick, &mut first_in, &mut first_out);
+    call_state_machine::<()>(Request::Tick, &mut second_in, &mut second_out);
+
+    let first: Vec<u8> = call_state_machine(Request::StateHash, &mut first_in, &mut first_out);
+    let second: Vec<u8> = call_state_machine(Request::StateHash, &mut second_in, &mut second_out);
+    assert_eq!(first.len(), 32);
+    assert_eq!(first, second);
+
+    // Querying the hash does not change the state.
+    let again: Vec<u8> = call_state_machine(Request::StateHash, &mut first_in, &mut first_out);
+    assert_eq!(first, again);
+}
+
+#[test]
+fn state_survives_replica_upgrade() {
+    let (mut child_in, mut child_out) = start_state_machine();
+    call_state_machine::<()>(
+        Request::AdvanceTime(Duration::from_secs(1000)),
+        &mut child_in,
+        &mut child_out,
+    );
+    let before: SystemTime = call_state_machine(Request::Time, &mut child_in, &mut child_out);
+
+    call_state_machine::<()>(Request::UpgradeReplica, &mut child_in, &mut child_out);
+
+    let after: SystemTime = call_state_machine(Request::Time, &mut child_in, &mut child_out);
+    assert_eq!(before, after);
+    call_state_machine::<()>(Request::Tick, &mut child_in, &mut child_out);
+    let hash: Vec<u8> = call_state_machine(Request::StateHash, &mut child_in, &mut child_out);
+    assert_eq!(hash.len(), 32);
+}
+
+#[test]
+fn time_is_strictly_monotonic() {
+    let (mut child_in, mut child_out) = start_state_machine();
+    let genesis: SystemTime = call_state_machine(Request::Time, &mut child_in, &mut child_out);
+
+    // Blocks executed without advancing the time are 1ns apart.
+    call_state_machine::<()>(Request::Tick, &mut child_in, &mut child_out);
+    let first: SystemTime = call_state_machine(Request::Time, &mut child_in, &mut child_out);
+    call_state_machine::<()>(Request::Tick, &mut child_in, &mut child_out);
+    let time: SystemTime = call_state_machine(Request::Time, &mut child_in, &mut child_out);
+    assert_eq!(time, first + Duration::from_nanos(1));
+
+    let result: Result<(), TimeError> =
+        call_state_machine(Request::SetTime(genesis), &mut child_in, &mut child_out);
+    assert_eq!(
+        result,
+        Err(TimeError::BeforeLastBlock {
+            requested: genesis,
+            last_block: time,
+        })
+    );
+    let later = time + Duration::from_secs(10);
+    let result: Result<(), TimeError> =
+        call_state_machine(Request::SetTime(later), &mut child_in, &mut child_out);
+    assert_eq!(result, Ok(()));
+    let time: SystemTime = call_state_machine(Request::Time, &mut child_in, &mut child_out);
+    assert_eq!(time, later);
+
+    // No canister has a timer.
+    let next_timer: Option<SystemTime> = call_state_machine(
+        Request::GetTimeOfNextTimerExpiration,
+        &mut child_in,
+        &mut child_out,
+    );
+    assert_eq!(next_timer, None);
+}
+
+#[test]
+fn management_calls_are_executed_by_the_management_canister() {
+    let (mut child_in, mut child_out) = start_state_machine();
+    let controller = PrincipalId::new_user_test_id(1);
+    let other = PrincipalId::new_user_test_id(2);
+
+    // The server has no special handling for these methods.
+    let result: Result<WasmResult, UserError> = call_state_machine(
+        management_call(
+            controller,
+            Method::ProvisionalCreateCanisterWithCycles,
+            ProvisionalCreateCanisterWithCyclesArgs::new(Some(1 << 40), None).encode(),
+        ),
+        &mut child_in,
+        &mut child_out,
+    );
+    let canister_id = match result {
+        Ok(WasmResult::Reply(bytes)) => CanisterIdRecord::decode(&bytes).unwrap(),
+        result => panic!("unexpected result of canister creation: {:?}", result),
+    };
+
+    let result: Result<WasmResult, UserError> = call_state_machine(
+        management_call(controller, Method::CanisterStatus, canister_id.encode()),
+        &mut child_in,
+        &mut child_out,
+    );
+    assert!(matches!(result, Ok(WasmResult::Reply(_))), "{:?}", result);
+
+    // The management canister rejects the ingress message of a principal
+    // that does not control the canister.
+    let result: Result<WasmResult, UserError> = call_state_machine(
+        management_call(other, Method::CanisterStatus, canister_id.encode()),
+        &mut child_in,
+        &mut child_out,
+    );
+    match result {
+        Err(err) => assert_eq!(err.code(), ErrorCode::CanisterInvalidController),
+        Ok(result) => panic!("unexpected reply to a non-controller: {:?}", result),
+    }
+
+    // The server keeps serving requests after the rejection.
+    call_state_machine::<()>(Request::Tick, &mut child_in, &mut child_out);
+}
+
 fn call_state_machine<T: DeserializeOwned>(
     request: Request,
     stdin: &mut ChildStdin,
@@ -11,6 +11,7 @@ use ic_replicated_state::{
     canister_state::execution_state::WasmBinary,
     page_map::PageMap,
     CanisterMetrics, CanisterState, ExecutionState, ReplicatedState, SchedulerState, SystemState,
+    WasmChunkStore,
 };
 use ic_state_layout::{
     BitcoinStateBits, CanisterLayout, CanisterStateBits, CheckpointLayout, ReadOnly, ReadPolicy,
@@ -314,6 +315,21 @@ pub fn load_canister_state<P: ReadPolicy>(
             })?;
     durations.insert("canister_queues", starting_time.elapsed());
 
+    let starting_time = Instant::now();
+    let wasm_chunk_store = WasmChunkStore::try_from(
+        canister_layout
+            .wasm_chunk_store()
+            .deserialize_opt()?
+            .unwrap_or_default(),
+    )
+    .map_err(|err| {
+        into_checkpoint_error(
+            format!("canister_states[{}]::system_state::wasm_chunk_store", canister_id),
+            err,
+        )
+    })?;
+    durations.insert("wasm_chunk_store", starting_time.elapsed());
+
     let canister_metrics = CanisterMetrics {
         scheduled_as_first: canister_state_bits.scheduled_as_first,
         skipped_round_due_to_no_messages: canister_state_bits.skipped_round_due_to_no_messages,
@@ -333,9 +349,17 @@ pub fn load_canister_state<P: ReadPolicy>(
         canister_metrics,
         canister_state_bits.cycles_balance,
         canister_state_bits.cycles_debit,
+        canister_state_bits.reserved_balance,
+        canister_state_bits.reserved_balance_limit,
         canister_state_bits.task_queue.into_iter().collect(),
         CanisterTimer::from_nanos_since_unix_epoch(canister_state_bits.global_timer_nanos),
         canister_state_bits.canister_version,
+        wasm_chunk_store,
+        canister_state_bits.log_visibility,
+        canister_state_bits.canister_log,
+        canister_state_bits.wasm_memory_threshold,
+        canister_state_bits.on_low_wasm_memory_hook_executed,
+        canister_state_bits.wasm_memory_limit,
     );
 
     let canister_state = CanisterState {
@@ -354,6 +378,8 @@ pub fn load_canister_state<P: ReadPolicy>(
             time_of_last_allocation_charge: Time::from_nanos_since_unix_epoch(
                 canister_state_bits.time_of_last_allocation_charge_nanos,
             ),
+            executed_instructions: canister_state_bits.executed_instructions,
+            query_stats: canister_state_bits.query_stats,
         },
     };
 
@@ -536,6 +562,7 @@ mod tests {
                 checkpoint_path.join("system_metadata.pbuf"),
                 canister_path.join("queues.pbuf"),
                 canister_path.join("canister.pbuf"),
+                canister_path.join("wasm_chunk_store.pbuf"),
             ];
 
             for path in expected_paths {
@@ -301,6 +301,9 @@ fn serialize_canister_to_tip(
     canister_layout
         .queues()
         .serialize(canister_state.system_state.queues().into())?;
+    canister_layout
+        .wasm_chunk_store()
+        .serialize((&canister_state.system_state.wasm_chunk_store).into())?;
 
     let execution_state_bits = match &canister_state.execution_state {
         Some(execution_state) => {
@@ -364,6 +367,8 @@ fn serialize_canister_to_tip(
                 freeze_threshold: canister_state.system_state.freeze_threshold,
                 cycles_balance: canister_state.system_state.balance(),
                 cycles_debit: canister_state.system_state.cycles_debit(),
+                reserved_balance: canister_state.system_state.reserved_balance(),
+                reserved_balance_limit: canister_state.system_state.reserved_balance_limit(),
                 execution_state_bits,
                 status: canister_state.system_state.status.clone(),
                 scheduled_as_first: canister_state
@@ -406,6 +411,15 @@ fn serialize_canister_to_tip(
                     .global_timer
                     .to_nanos_since_unix_epoch(),
                 canister_version: canister_state.system_state.canister_version,
+                log_visibility: canister_state.system_state.log_visibility,
+                canister_log: canister_state.system_state.canister_log.clone(),
+                wasm_memory_threshold: canister_state.system_state.wasm_memory_threshold,
+                on_low_wasm_memory_hook_executed: canister_state
+                    .system_state
+                    .on_low_wasm_memory_hook_executed,
+                wasm_memory_limit: canister_state.system_state.wasm_memory_limit,
+                executed_instructions: canister_state.scheduler_state.executed_instructions,
+                query_stats: canister_state.scheduler_state.query_stats.clone(),
             }
             .into(),
         )
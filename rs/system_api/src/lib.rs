@@ -6,6 +6,7 @@ mod stable_memory;
 pub mod system_api_empty;
 
 use ic_config::flag_status::FlagStatus;
+use ic_cycles_account_manager::ResourceSaturation;
 use ic_error_types::RejectCode;
 use ic_interfaces::execution_environment::{
     ExecutionComplexity, ExecutionMode,
@@ -16,12 +17,16 @@ use ic_interfaces::execution_environment::{
 };
 use ic_logger::{error, ReplicaLogger};
 use ic_registry_subnet_type::SubnetType;
-use ic_replicated_state::{memory_required_to_push_request, Memory, NumWasmPages, PageIndex};
+use ic_replicated_state::{
+    canister_state::WASM_PAGE_SIZE_IN_BYTES, memory_required_to_push_request, Memory,
+    MemoryReservation, NumWasmPages, PageIndex,
+};
 use ic_sys::PageBytes;
 use ic_types::{
     ingress::WasmResult,
     messages::{CallContextId, RejectContext, Request, MAX_INTER_CANISTER_PAYLOAD_IN_BYTES},
     methods::{Callback, SystemMethod, WasmClosure},
+    time::{CoarseTime, NO_DEADLINE},
     CanisterId, CanisterTimer, ComputeAllocation, Cycles, NumBytes, NumInstructions, NumPages,
     PrincipalId, SubnetId, Time,
 };
@@ -38,6 +43,9 @@ use std::{
 const MULTIPLIER_MAX_SIZE_LOCAL_SUBNET: u64 = 5;
 const MAX_NON_REPLICATED_QUERY_REPLY_SIZE: NumBytes = NumBytes::new(3 << 20);
 const CERTIFIED_DATA_MAX_LENGTH: u32 = 32;
+/// The maximum size of the message with which `canister_inspect_message` can
+/// reject an ingress message.
+const MAX_INSPECT_MESSAGE_REJECT_SIZE: u32 = 8 * 1024;
 
 // Enables tracing of system calls for local debugging.
 const TRACE_SYSCALLS: bool = false;
@@ -84,6 +92,54 @@ fn summarize(heap: &[u8], start: u32, size: u32) -> u64 {
     }
 }
 
+/// Returns the message of a debug print.
+fn debug_print_message(src: u32, size: u32, heap: &[u8]) -> String {
+    const MAX_DEBUG_MESSAGE_SIZE: u32 = 32 * 1024;
+    let size = size.min(MAX_DEBUG_MESSAGE_SIZE);
+    match valid_subslice("ic0.debug_print", src, size, heap) {
+        Ok(bytes) => String::from_utf8_lossy(bytes).to_string(),
+        Err(_) => {
+            // Do not trap here!
+            // debug.print should never fail, so if the specified memory range
+            // is invalid, we ignore it and print the error message
+            "(debug message out of memory bounds)".to_string()
+        }
+    }
+}
+
+/// Rejects the ingress message in `canister_inspect_message` with the message
+/// in `heap[src..src + size]`.
+fn reject_inspected_message(
+    message_accepted: bool,
+    reject_message: &mut Option<String>,
+    src: u32,
+    size: u32,
+    heap: &[u8],
+) -> HypervisorResult<()> {
+    if message_accepted {
+        return Err(ContractViolation(
+            "ic0.msg_reject: the message was already accepted".to_string(),
+        ));
+    }
+    if reject_message.is_some() {
+        return Err(ContractViolation(
+            "ic0.msg_reject: the message was already rejected".to_string(),
+        ));
+    }
+    if size > MAX_INSPECT_MESSAGE_REJECT_SIZE {
+        return Err(ContractViolation(format!(
+            "ic0.msg_reject: reject message size ({}) cannot be larger than {}",
+            size, MAX_INSPECT_MESSAGE_REJECT_SIZE
+        )));
+    }
+    let msg_bytes = valid_subslice("ic0.msg_reject", src, size, heap)?;
+    let msg = String::from_utf8(msg_bytes.to_vec()).map_err(|_| {
+        ContractViolation("ic0.msg_reject: invalid UTF-8 string provided".to_string())
+    })?;
+    *reject_message = Some(msg);
+    Ok(())
+}
+
 /// Keeps the message instruction limit and the maximum slice instruction limit.
 /// Supports operations to reduce the message limit while keeping the maximum
 /// slice limit the same, which is useful for messages that have multiple
@@ -147,6 +203,7 @@ pub struct ExecutionParameters {
     pub compute_allocation: ComputeAllocation,
     pub subnet_type: SubnetType,
     pub execution_mode: ExecutionMode,
+    pub subnet_memory_saturation: ResourceSaturation,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -212,6 +269,9 @@ pub enum ApiType {
         incoming_cycles: Cycles,
         caller: PrincipalId,
         call_context_id: CallContextId,
+        /// The deadline of the incoming request, `NO_DEADLINE` for ingress
+        /// messages and guaranteed response calls.
+        deadline: CoarseTime,
         /// Begins as empty and used to accumulate data for sending replies.
         #[serde(with = "serde_bytes")]
         response_data: Vec<u8>,
@@ -257,6 +317,9 @@ pub enum ApiType {
         incoming_payload: Vec<u8>,
         incoming_cycles: Cycles,
         call_context_id: CallContextId,
+        /// The deadline of the incoming response, `NO_DEADLINE` for
+        /// guaranteed response calls.
+        deadline: CoarseTime,
         // Begins as empty and used to accumulate data for sending replies.
         #[serde(with = "serde_bytes")]
         response_data: Vec<u8>,
@@ -274,6 +337,9 @@ pub enum ApiType {
         reject_context: RejectContext,
         incoming_cycles: Cycles,
         call_context_id: CallContextId,
+        /// The deadline of the incoming response, `NO_DEADLINE` for
+        /// guaranteed response calls.
+        deadline: CoarseTime,
         // Begins as empty and used to accumulate data for sending replies.
         #[serde(with = "serde_bytes")]
         response_data: Vec<u8>,
@@ -300,12 +366,17 @@ pub enum ApiType {
         incoming_payload: Vec<u8>,
         time: Time,
         message_accepted: bool,
+        /// The message with which the canister explicitly rejected the
+        /// ingress message by calling `ic0.msg_reject`, if any.
+        reject_message: Option<String>,
     },
 
-    // For executing the `canister_heartbeat` or `canister_global_timer` methods
+    // For executing the `canister_heartbeat`, `canister_global_timer`, or
+    // `canister_on_low_wasm_memory` methods
     SystemTask {
         /// System task to execute.
-        /// Only `canister_heartbeat` and `canister_global_timer` are allowed.
+        /// Only `canister_heartbeat`, `canister_global_timer`, and
+        /// `canister_on_low_wasm_memory` are allowed.
         system_task: SystemMethod,
         time: Time,
         call_context_id: CallContextId,
@@ -358,6 +429,7 @@ impl ApiType {
         incoming_cycles: Cycles,
         caller: PrincipalId,
         call_context_id: CallContextId,
+        deadline: CoarseTime,
     ) -> Self {
         Self::Update {
             time,
@@ -365,6 +437,7 @@ impl ApiType {
             incoming_cycles,
             caller,
             call_context_id,
+            deadline,
             response_data: vec![],
             response_status: ResponseStatus::NotRepliedYet,
             outgoing_request: None,
@@ -417,6 +490,7 @@ impl ApiType {
         incoming_payload: Vec<u8>,
         incoming_cycles: Cycles,
         call_context_id: CallContextId,
+        deadline: CoarseTime,
         replied: bool,
         execution_mode: ExecutionMode,
     ) -> Self {
@@ -425,6 +499,7 @@ impl ApiType {
             incoming_payload,
             incoming_cycles,
             call_context_id,
+            deadline,
             response_data: vec![],
             response_status: if replied {
                 ResponseStatus::AlreadyReplied
@@ -443,6 +518,7 @@ impl ApiType {
         reject_context: RejectContext,
         incoming_cycles: Cycles,
         call_context_id: CallContextId,
+        deadline: CoarseTime,
         replied: bool,
         execution_mode: ExecutionMode,
     ) -> Self {
@@ -451,6 +527,7 @@ impl ApiType {
             reject_context,
             incoming_cycles,
             call_context_id,
+            deadline,
             response_data: vec![],
             response_status: if replied {
                 ResponseStatus::AlreadyReplied
@@ -479,6 +556,7 @@ impl ApiType {
             incoming_payload,
             time,
             message_accepted: false,
+            reject_message: None,
         }
     }
 
@@ -507,6 +585,27 @@ impl ApiType {
         }
     }
 
+    /// Returns true if executions of this type must keep the Wasm memory
+    /// within the `wasm_memory_limit` of the canister. Queries, the inspection
+    /// of ingress messages, system tasks, `canister_pre_upgrade`, and cleanup
+    /// callbacks are exempt.
+    pub fn enforces_wasm_memory_limit(&self) -> bool {
+        match self {
+            ApiType::Start | ApiType::Init { .. } | ApiType::Update { .. } => true,
+            ApiType::ReplyCallback { execution_mode, .. }
+            | ApiType::RejectCallback { execution_mode, .. } => match execution_mode {
+                ExecutionMode::Replicated => true,
+                ExecutionMode::NonReplicated => false,
+            },
+            ApiType::ReplicatedQuery { .. }
+            | ApiType::NonReplicatedQuery { .. }
+            | ApiType::PreUpgrade { .. }
+            | ApiType::InspectMessage { .. }
+            | ApiType::SystemTask { .. }
+            | ApiType::Cleanup { .. } => false,
+        }
+    }
+
     /// Returns a string slice representation of the enum variant name for use
     /// e.g. as a metric label.
     pub fn as_str(&self) -> &'static str {
@@ -516,7 +615,11 @@ impl ApiType {
             ApiType::SystemTask { system_task, .. } => match system_task {
                 SystemMethod::CanisterHeartbeat => "heartbeat",
                 SystemMethod::CanisterGlobalTimer => "global timer",
-                _ => panic!("Only `canister_heartbeat` and `canister_global_timer` are allowed."),
+                SystemMethod::CanisterOnLowWasmMemory => "on low wasm memory",
+                _ => panic!(
+                    "Only `canister_heartbeat`, `canister_global_timer`, and \
+                     `canister_on_low_wasm_memory` are allowed."
+                ),
             },
             ApiType::Update { .. } => "update",
             ApiType::ReplicatedQuery { .. } => "replicated query",
@@ -545,24 +648,11 @@ impl std::fmt::Display for ApiType {
 }
 
 /// A struct to gather the relevant fields that correspond to a canister's
-/// memory consumption.
+/// memory consumption. The checks against the canister memory limit and the
+/// subnet available memory are done by the underlying `MemoryReservation`.
 struct MemoryUsage {
-    /// Upper limit on how much the memory the canister could use.
-    limit: NumBytes,
-
-    /// The current amount of memory that the canister is using.
-    current_usage: NumBytes,
-
-    // This is the amount of memory that the subnet has available. Any
-    // expansions in the canister's memory need to be deducted from here.
-    subnet_available_memory: SubnetAvailableMemory,
-
-    /// Total memory allocated during this message execution. Note that
-    /// `total_allocated_memory` should always be `>= allocated_message_memory`.
-    total_allocated_memory: NumBytes,
-
-    /// Message memory allocated during this message execution.
-    allocated_message_memory: NumBytes,
+    /// The memory reserved during this message execution.
+    reservation: MemoryReservation,
 
     log: ReplicaLogger,
 }
@@ -589,35 +679,44 @@ impl MemoryUsage {
             );
         }
         Self {
-            limit,
-            current_usage,
-            subnet_available_memory,
-            total_allocated_memory: NumBytes::from(0),
-            allocated_message_memory: NumBytes::from(0),
+            reservation: MemoryReservation::new(limit, current_usage, subnet_available_memory),
             log,
         }
     }
 
+    /// The current amount of memory that the canister is using.
+    fn current_usage(&self) -> NumBytes {
+        self.reservation.current_usage()
+    }
+
+    /// Total memory allocated during this message execution. It is always
+    /// `>= allocated_message_memory()`.
+    fn total_allocated_memory(&self) -> NumBytes {
+        self.reservation.reserved_memory()
+    }
+
+    /// Message memory allocated during this message execution.
+    fn allocated_message_memory(&self) -> NumBytes {
+        self.reservation.reserved_message_memory()
+    }
+
     /// Tries to allocate the requested number of Wasm pages.
     ///
     /// Returns `Err(HypervisorError::OutOfMemory)` and leaves `self` unchanged
     /// if either the canister memory limit or the subnet memory limit would be
     /// exceeded.
     fn allocate_pages(&mut self, pages: usize) -> HypervisorResult<()> {
-        let bytes = ic_replicated_state::num_bytes_try_from(NumWasmPages::from(pages))
-            .map_err(|_| HypervisorError::OutOfMemory)?;
-        self.allocate_memory(bytes, NumBytes::from(0))
+        self.reservation
+            .reserve_wasm_pages(NumWasmPages::from(pages))
+            .map_err(HypervisorError::OutOfMemory)
     }
 
     /// Unconditionally deallocates the given number of Wasm pages. Should only
     /// be called immediately after `allocate_pages()`, with the same number of
     /// pages, in case growing the heap failed.
     fn deallocate_pages(&mut self, pages: usize) {
-        // Expected to work as we have converted `pages` to bytes when `increase_usage`
-        // was called and if it would have failed, we wouldn't call `decrease_usage`.
-        let bytes = ic_replicated_state::num_bytes_try_from(NumWasmPages::from(pages))
-            .expect("could not convert wasm pages to bytes");
-        self.deallocate_memory(bytes, NumBytes::from(0))
+        self.reservation
+            .release_wasm_pages(NumWasmPages::from(pages))
     }
 
     /// Validates that `total_bytes >= message_bytes` holds. In debug build it
@@ -650,24 +749,15 @@ impl MemoryUsage {
         message_bytes: NumBytes,
     ) -> HypervisorResult<()> {
         self.validate_requested_memory(total_bytes, message_bytes);
+        self.reservation
+            .reserve(total_bytes, message_bytes)
+            .map_err(HypervisorError::OutOfMemory)
+    }
 
-        let (new_usage, overflow) = self.current_usage.get().overflowing_add(total_bytes.get());
-        if overflow || new_usage > self.limit.get() {
-            return Err(HypervisorError::OutOfMemory);
-        }
-        match self
-            .subnet_available_memory
-            .try_decrement(total_bytes, message_bytes)
-        {
-            Ok(()) => {
-                self.current_usage = NumBytes::from(new_usage);
-                self.total_allocated_memory += total_bytes;
-                self.allocated_message_memory += message_bytes;
-                debug_assert!(self.total_allocated_memory >= self.allocated_message_memory);
-                Ok(())
-            }
-            Err(_err) => Err(HypervisorError::OutOfMemory),
-        }
+    /// Unconditionally deallocates all memory allocated during this message
+    /// execution, e.g. in case the execution failed.
+    fn deallocate_all(&mut self) {
+        self.reservation.release_all()
     }
 
     /// Unconditionally deallocates the given number of bytes and message bytes. Should
@@ -675,18 +765,7 @@ impl MemoryUsage {
     /// in case growing the heap failed or upon clean up.
     fn deallocate_memory(&mut self, total_bytes: NumBytes, message_bytes: NumBytes) {
         self.validate_requested_memory(total_bytes, message_bytes);
-
-        self.subnet_available_memory
-            .increment(total_bytes, message_bytes);
-
-        debug_assert!(self.current_usage >= total_bytes);
-        debug_assert!(self.total_allocated_memory >= total_bytes);
-        debug_assert!(self.allocated_message_memory >= message_bytes);
-        self.current_usage -= total_bytes;
-        self.total_allocated_memory -= total_bytes;
-        self.allocated_message_memory -= message_bytes;
-
-        debug_assert!(self.total_allocated_memory >= self.allocated_message_memory);
+        self.reservation.release(total_bytes, message_bytes)
     }
 }
 
@@ -729,6 +808,10 @@ pub struct SystemApiImpl {
 
     /// Tracks the total execution complexity.
     total_execution_complexity: ExecutionComplexity,
+
+    /// The debug prints and trap messages of the execution that go into the
+    /// canister log.
+    canister_log_records: Vec<Vec<u8>>,
 }
 
 impl SystemApiImpl {
@@ -764,9 +847,16 @@ impl SystemApiImpl {
             current_slice_instruction_limit: i64::try_from(slice_limit).unwrap_or(i64::MAX),
             instructions_executed_before_current_slice: 0,
             total_execution_complexity: ExecutionComplexity::new(),
+            canister_log_records: vec![],
         }
     }
 
+    /// Returns the debug prints and trap messages of the execution that go
+    /// into the canister log.
+    pub fn take_canister_log_records(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.canister_log_records)
+    }
+
     /// Gets the result of execution, assuming there is no error from
     /// running the canister. Returns any cycles used for an outgoing request
     /// that doesn't get sent and returns allocated memory to the subnet if the
@@ -806,10 +896,7 @@ impl SystemApiImpl {
             .or_else(|| self.execution_error.take())
         {
             // Return allocated memory in case of failed message execution.
-            self.memory_usage.deallocate_memory(
-                self.memory_usage.total_allocated_memory,
-                self.memory_usage.allocated_message_memory,
-            );
+            self.memory_usage.deallocate_all();
             return Err(err);
         }
         match &mut self.api_type {
@@ -819,12 +906,16 @@ impl SystemApiImpl {
             | ApiType::Cleanup { .. }
             | ApiType::SystemTask { .. } => Ok(None),
             ApiType::InspectMessage {
-                message_accepted, ..
+                message_accepted,
+                reject_message,
+                ..
             } => {
                 if *message_accepted {
                     Ok(None)
                 } else {
-                    Err(HypervisorError::MessageRejected)
+                    Err(HypervisorError::MessageRejected {
+                        reject_message: reject_message.take(),
+                    })
                 }
             }
             ApiType::Update {
@@ -851,17 +942,17 @@ impl SystemApiImpl {
     /// Note that this function is made public only for the tests
     #[doc(hidden)]
     pub fn get_current_memory_usage(&self) -> NumBytes {
-        self.memory_usage.current_usage
+        self.memory_usage.current_usage()
     }
 
     /// Bytes allocated in the Wasm/stable memory and messages.
     pub fn get_allocated_bytes(&self) -> NumBytes {
-        self.memory_usage.total_allocated_memory
+        self.memory_usage.total_allocated_memory()
     }
 
     /// Bytes allocated in messages.
     pub fn get_allocated_message_bytes(&self) -> NumBytes {
-        self.memory_usage.allocated_message_memory
+        self.memory_usage.allocated_message_memory()
     }
 
     fn error_for(&self, method_name: &str) -> HypervisorError {
@@ -992,7 +1083,7 @@ impl SystemApiImpl {
                 Some(request) => {
                     self.sandbox_safe_system_state
                         .withdraw_cycles_for_transfer(
-                            self.memory_usage.current_usage,
+                            self.memory_usage.current_usage(),
                             self.execution_parameters.compute_allocation,
                             amount,
                         )?;
@@ -1003,6 +1094,25 @@ impl SystemApiImpl {
         }
     }
 
+    /// Returns an error if the Wasm memory of the given number of pages
+    /// exceeds the `wasm_memory_limit` of the canister and the current
+    /// execution has to respect the limit.
+    fn check_wasm_memory_limit(&self, wasm_memory_pages: u64) -> HypervisorResult<()> {
+        if !self.api_type.enforces_wasm_memory_limit() {
+            return Ok(());
+        }
+        match self.sandbox_safe_system_state.wasm_memory_limit() {
+            Some(limit) => {
+                let bytes = NumBytes::from(wasm_memory_pages * WASM_PAGE_SIZE_IN_BYTES as u64);
+                if bytes > limit {
+                    return Err(WasmMemoryLimitExceeded { bytes, limit });
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
     fn ic0_canister_cycles_balance_helper(&self, method_name: &str) -> HypervisorResult<Cycles> {
         match &self.api_type {
             ApiType::Start {} => Err(self.error_for(method_name)),
@@ -1149,7 +1259,7 @@ impl SystemApiImpl {
         }
 
         match self.sandbox_safe_system_state.push_output_request(
-            self.memory_usage.current_usage,
+            self.memory_usage.current_usage(),
             self.execution_parameters.compute_allocation,
             req,
             prepayment_for_response_execution,
@@ -1427,12 +1537,18 @@ impl SystemApi for SystemApiImpl {
             | ApiType::NonReplicatedQuery { .. }
             | ApiType::Init { .. } => Err(self.error_for("ic0_accept_message")),
             ApiType::InspectMessage {
-                message_accepted, ..
+                message_accepted,
+                reject_message,
+                ..
             } => {
                 if *message_accepted {
                     Err(ContractViolation(
                         "ic0.accept_message: the function was already called.".to_string(),
                     ))
+                } else if reject_message.is_some() {
+                    Err(ContractViolation(
+                        "ic0.accept_message: the message was already rejected.".to_string(),
+                    ))
                 } else {
                     *message_accepted = true;
                     Ok(())
@@ -1503,6 +1619,17 @@ impl SystemApi for SystemApiImpl {
     }
 
     fn ic0_msg_reject(&mut self, src: u32, size: u32, heap: &[u8]) -> HypervisorResult<()> {
+        if let ApiType::InspectMessage {
+            message_accepted,
+            reject_message,
+            ..
+        } = &mut self.api_type
+        {
+            let result =
+                reject_inspected_message(*message_accepted, reject_message, src, size, heap);
+            trace_syscall!(self, ic0_msg_reject, result, src, size, summarize(heap, src, size));
+            return result;
+        }
         let result = match self.get_response_info() {
             None => Err(self.error_for("ic0_msg_reject")),
             Some((_, max_reply_size, response_status)) => match response_status {
@@ -1590,6 +1717,26 @@ impl SystemApi for SystemApiImpl {
         result
     }
 
+    fn ic0_msg_deadline(&self) -> HypervisorResult<u64> {
+        let result = match &self.api_type {
+            ApiType::Start { .. }
+            | ApiType::Init { .. }
+            | ApiType::SystemTask { .. }
+            | ApiType::Cleanup { .. }
+            | ApiType::PreUpgrade { .. }
+            | ApiType::InspectMessage { .. } => Err(self.error_for("ic0_msg_deadline")),
+            // Best-effort queries are not supported.
+            ApiType::ReplicatedQuery { .. } | ApiType::NonReplicatedQuery { .. } => Ok(0),
+            ApiType::Update { deadline, .. }
+            | ApiType::ReplyCallback { deadline, .. }
+            | ApiType::RejectCallback { deadline, .. } => {
+                Ok(deadline.as_time().as_nanos_since_unix_epoch())
+            }
+        };
+        trace_syscall!(self, ic0_msg_deadline, result);
+        result
+    }
+
     fn ic0_canister_self_size(&self) -> HypervisorResult<usize> {
         let result = match &self.api_type {
             ApiType::Start { .. } => Err(self.error_for("ic0_canister_self_size")),
@@ -1796,6 +1943,7 @@ impl SystemApi for SystemApiImpl {
                             on_reply,
                             on_reject,
                             None,
+                            NO_DEADLINE,
                         ))?;
 
                 let msg = Request {
@@ -1805,6 +1953,7 @@ impl SystemApi for SystemApiImpl {
                     method_payload: payload,
                     sender_reply_callback: callback_id,
                     payment: Cycles::zero(),
+                    deadline: NO_DEADLINE,
                 };
                 self.push_output_request(
                     msg,
@@ -2001,6 +2150,40 @@ impl SystemApi for SystemApiImpl {
         result
     }
 
+    fn ic0_call_with_best_effort_response(&mut self, timeout_seconds: u32) -> HypervisorResult<()> {
+        let result = match &mut self.api_type {
+            ApiType::Start { .. }
+            | ApiType::Init { .. }
+            | ApiType::ReplicatedQuery { .. }
+            | ApiType::NonReplicatedQuery { .. }
+            | ApiType::Cleanup { .. }
+            | ApiType::PreUpgrade { .. }
+            | ApiType::InspectMessage { .. } => {
+                Err(self.error_for("ic0_call_with_best_effort_response"))
+            }
+            ApiType::Update {
+                outgoing_request, ..
+            }
+            | ApiType::SystemTask {
+                outgoing_request, ..
+            }
+            | ApiType::ReplyCallback {
+                outgoing_request, ..
+            }
+            | ApiType::RejectCallback {
+                outgoing_request, ..
+            } => match outgoing_request {
+                None => Err(HypervisorError::ContractViolation(
+                    "ic0.call_with_best_effort_response called when no call is under construction."
+                        .to_string(),
+                )),
+                Some(request) => request.set_timeout(timeout_seconds),
+            },
+        };
+        trace_syscall!(self, ic0_call_with_best_effort_response, result, timeout_seconds);
+        result
+    }
+
     fn ic0_call_cycles_add(&mut self, amount: u64) -> HypervisorResult<()> {
         let result = self.ic0_call_cycles_add_helper("ic0_call_cycles_add", Cycles::from(amount));
         trace_syscall!(self, ic0_call_cycles_add, result, amount);
@@ -2035,26 +2218,31 @@ impl SystemApi for SystemApiImpl {
             | ApiType::PreUpgrade { .. }
             | ApiType::InspectMessage { .. } => Err(self.error_for("ic0_call_perform")),
             ApiType::Update {
+                time,
                 call_context_id,
                 outgoing_request,
                 ..
             }
             | ApiType::SystemTask {
+                time,
                 call_context_id,
                 outgoing_request,
                 ..
             }
             | ApiType::ReplyCallback {
+                time,
                 call_context_id,
                 outgoing_request,
                 ..
             }
             | ApiType::RejectCallback {
+                time,
                 call_context_id,
                 outgoing_request,
                 ..
             }
             | ApiType::NonReplicatedQuery {
+                time,
                 query_kind:
                     NonReplicatedQueryKind::Stateful {
                         call_context_id,
@@ -2071,6 +2259,7 @@ impl SystemApi for SystemApiImpl {
                 let req = into_request(
                     req_in_prep,
                     *call_context_id,
+                    *time,
                     &mut self.sandbox_safe_system_state,
                     &self.log,
                 )?;
@@ -2086,6 +2275,42 @@ impl SystemApi for SystemApiImpl {
         result
     }
 
+    fn ic0_cost_call(
+        &self,
+        method_name_size: u64,
+        payload_size: u64,
+        dst: u32,
+        heap: &mut [u8],
+    ) -> HypervisorResult<()> {
+        let request_size = NumBytes::from(method_name_size.saturating_add(payload_size));
+        let cost = self.sandbox_safe_system_state.call_cost(request_size);
+        let result = copy_cycles_to_heap(cost, dst, heap, "ic0_cost_call");
+        trace_syscall!(self, ic0_cost_call, result, method_name_size, payload_size, dst);
+        result
+    }
+
+    fn ic0_cost_create_canister(&self, dst: u32, heap: &mut [u8]) -> HypervisorResult<()> {
+        let cost = self.sandbox_safe_system_state.create_canister_cost();
+        let result = copy_cycles_to_heap(cost, dst, heap, "ic0_cost_create_canister");
+        trace_syscall!(self, ic0_cost_create_canister, result, dst);
+        result
+    }
+
+    fn ic0_cost_http_request(
+        &self,
+        request_size: u64,
+        max_res_bytes: u64,
+        dst: u32,
+        heap: &mut [u8],
+    ) -> HypervisorResult<()> {
+        let cost = self
+            .sandbox_safe_system_state
+            .http_request_cost(NumBytes::from(request_size), NumBytes::from(max_res_bytes));
+        let result = copy_cycles_to_heap(cost, dst, heap, "ic0_cost_http_request");
+        trace_syscall!(self, ic0_cost_http_request, result, request_size, max_res_bytes, dst);
+        result
+    }
+
     fn ic0_stable_size(&self) -> HypervisorResult<u32> {
         let result = match &self.api_type {
             ApiType::Start {} => Err(self.error_for("ic0_stable_size")),
@@ -2434,10 +2659,11 @@ impl SystemApi for SystemApiImpl {
             if native_memory_grow_res == -1 {
                 return Ok(-1);
             }
-            match self.memory_usage.allocate_pages(additional_pages as usize) {
-                Ok(()) => Ok(native_memory_grow_res),
-                Err(_err) => Err(HypervisorError::OutOfMemory),
-            }
+            // The result of `memory.grow` is the size before growing.
+            let wasm_memory_pages = native_memory_grow_res as u64 + additional_pages as u64;
+            self.check_wasm_memory_limit(wasm_memory_pages)
+                .and_then(|()| self.memory_usage.allocate_pages(additional_pages as usize))
+                .map(|()| native_memory_grow_res)
         };
         trace_syscall!(
             self,
@@ -2787,33 +3013,31 @@ impl SystemApi for SystemApiImpl {
         result
     }
 
-    fn ic0_debug_print(&self, src: u32, size: u32, heap: &[u8]) -> HypervisorResult<()> {
-        const MAX_DEBUG_MESSAGE_SIZE: u32 = 32 * 1024;
-        let size = size.min(MAX_DEBUG_MESSAGE_SIZE);
-        let msg = match valid_subslice("ic0.debug_print", src, size, heap) {
-            Ok(bytes) => String::from_utf8_lossy(bytes).to_string(),
-            Err(_) => {
-                // Do not trap here!
-                // debug.print should never fail, so if the specified memory range
-                // is invalid, we ignore it and print the error message
-                "(debug message out of memory bounds)".to_string()
-            }
-        };
+    fn ic0_debug_print(&mut self, src: u32, size: u32, heap: &[u8]) -> HypervisorResult<()> {
+        let msg = debug_print_message(src, size, heap);
         eprintln!(
             "[Canister {}] {}",
             self.sandbox_safe_system_state.canister_id, msg
         );
+        self.canister_log_records.push(msg.into_bytes());
         trace_syscall!(self, ic0_debug_print, src, size, summarize(heap, src, size));
         Ok(())
     }
 
-    fn ic0_trap(&self, src: u32, size: u32, heap: &[u8]) -> HypervisorResult<()> {
+    fn save_debug_print(&mut self, src: u32, size: u32, heap: &[u8]) {
+        let msg = debug_print_message(src, size, heap);
+        self.canister_log_records.push(msg.into_bytes());
+    }
+
+    fn ic0_trap(&mut self, src: u32, size: u32, heap: &[u8]) -> HypervisorResult<()> {
         const MAX_ERROR_MESSAGE_SIZE: u32 = 16 * 1024;
         let size = size.min(MAX_ERROR_MESSAGE_SIZE);
         let result = {
             let msg = valid_subslice("trap", src, size, heap)
                 .map(|bytes| String::from_utf8_lossy(bytes).to_string())
                 .unwrap_or_else(|_| "(trap message out of memory bounds)".to_string());
+            self.canister_log_records
+                .push(format!("[TRAP]: {}", msg).into_bytes());
             CalledTrap(msg)
         };
         trace_syscall!(self, ic0_trap, src, size, summarize(heap, src, size));
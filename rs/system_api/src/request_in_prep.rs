@@ -4,11 +4,16 @@ use ic_logger::ReplicaLogger;
 use ic_types::{
     messages::{CallContextId, Request},
     methods::{Callback, WasmClosure},
-    CanisterId, Cycles, NumBytes, PrincipalId,
+    time::{CoarseTime, NO_DEADLINE},
+    CanisterId, Cycles, NumBytes, PrincipalId, Time,
 };
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 
+/// The maximum timeout of a best-effort call. Larger timeouts passed to
+/// `ic0.call_with_best_effort_response` are capped to this value.
+pub(crate) const MAX_CALL_TIMEOUT_SECONDS: u32 = 300;
+
 /// Represents an under construction `Request`.
 ///
 /// The main differences from a `Request` are:
@@ -37,6 +42,10 @@ pub struct RequestInPrep {
     cycles: Cycles,
     method_name: String,
     method_payload: Vec<u8>,
+    /// The timeout of a best-effort call, set by
+    /// `ic0.call_with_best_effort_response`. `None` for guaranteed response
+    /// calls.
+    timeout_seconds: Option<u32>,
     /// The maximum size of a message that will go to a canister on another
     /// subnet.
     max_size_remote_subnet: NumBytes,
@@ -94,6 +103,7 @@ impl RequestInPrep {
             cycles: Cycles::zero(),
             method_name,
             method_payload: Vec::new(),
+            timeout_seconds: None,
             max_size_remote_subnet,
             multiplier_max_size_local_subnet,
         })
@@ -111,6 +121,18 @@ impl RequestInPrep {
         }
     }
 
+    pub(crate) fn set_timeout(&mut self, timeout_seconds: u32) -> HypervisorResult<()> {
+        if self.timeout_seconds.is_some() {
+            Err(HypervisorError::ContractViolation(
+                "ic0.call_with_best_effort_response can be called at most once between `ic0.call_new` and `ic0.call_perform`"
+                    .to_string(),
+            ))
+        } else {
+            self.timeout_seconds = Some(timeout_seconds.min(MAX_CALL_TIMEOUT_SECONDS));
+            Ok(())
+        }
+    }
+
     pub(crate) fn take_cycles(self) -> Cycles {
         self.cycles
     }
@@ -159,16 +181,34 @@ pub(crate) fn into_request(
         cycles,
         method_name,
         method_payload,
+        timeout_seconds,
         max_size_remote_subnet,
         multiplier_max_size_local_subnet,
     }: RequestInPrep,
     call_context_id: CallContextId,
+    time: Time,
     sandbox_safe_system_state: &mut SandboxSafeSystemState,
     _logger: &ReplicaLogger,
 ) -> HypervisorResult<RequestWithPrepayment> {
     let destination_canister =
         CanisterId::new(callee).map_err(HypervisorError::InvalidCanisterId)?;
 
+    // The management canister only handles guaranteed response calls.
+    let deadline = match timeout_seconds {
+        Some(_) if destination_canister == CanisterId::ic_00() => {
+            return Err(HypervisorError::ContractViolation(
+                "ic0.call_with_best_effort_response: best-effort calls to the management canister are not supported"
+                    .to_string(),
+            ));
+        }
+        Some(timeout_seconds) => CoarseTime::from_secs_since_unix_epoch(
+            CoarseTime::floor(time)
+                .as_secs_since_unix_epoch()
+                .saturating_add(timeout_seconds),
+        ),
+        None => NO_DEADLINE,
+    };
+
     let payload_size = (method_name.len() + method_payload.len()) as u64;
     {
         let max_size_local_subnet = max_size_remote_subnet * multiplier_max_size_local_subnet;
@@ -195,6 +235,7 @@ pub(crate) fn into_request(
         on_reply,
         on_reject,
         on_cleanup,
+        deadline,
     ))?;
 
     let req = Request {
@@ -204,6 +245,7 @@ pub(crate) fn into_request(
         method_payload,
         sender_reply_callback: callback_id,
         payment: cycles,
+        deadline,
     };
     // We cannot call `Request::payload_size_bytes()` before constructing the
     // request, so ensure our separate calculation matches the actual size.
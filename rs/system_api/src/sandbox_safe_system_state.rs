@@ -149,7 +149,7 @@ impl SystemStateChanges {
                 })?;
                 if (*amount_taken).get() > LOG_CANISTER_OPERATION_CYCLES_THRESHOLD {
                     match call_context.call_origin() {
-                        CallOrigin::CanisterUpdate(origin_canister_id, _)
+                        CallOrigin::CanisterUpdate(origin_canister_id, _, _)
                         | CallOrigin::CanisterQuery(origin_canister_id, _) => info!(
                             logger,
                             "Canister {} accepted {} cycles from canister {}.",
@@ -305,6 +305,7 @@ pub struct SandboxSafeSystemState {
     ic00_aliases: BTreeSet<CanisterId>,
     global_timer: CanisterTimer,
     canister_version: u64,
+    wasm_memory_limit: Option<NumBytes>,
 }
 
 impl SandboxSafeSystemState {
@@ -328,6 +329,7 @@ impl SandboxSafeSystemState {
         dirty_page_overhead: NumInstructions,
         global_timer: CanisterTimer,
         canister_version: u64,
+        wasm_memory_limit: Option<NumBytes>,
     ) -> Self {
         Self {
             canister_id,
@@ -348,6 +350,7 @@ impl SandboxSafeSystemState {
             ic00_aliases,
             global_timer,
             canister_version,
+            wasm_memory_limit,
         }
     }
 
@@ -408,6 +411,7 @@ impl SandboxSafeSystemState {
             dirty_page_overhead,
             system_state.global_timer,
             system_state.canister_version,
+            system_state.wasm_memory_limit,
         )
     }
 
@@ -419,6 +423,11 @@ impl SandboxSafeSystemState {
         self.global_timer
     }
 
+    /// Returns the upper limit of the Wasm memory set by the controllers.
+    pub fn wasm_memory_limit(&self) -> Option<NumBytes> {
+        self.wasm_memory_limit
+    }
+
     pub fn canister_version(&self) -> u64 {
         self.canister_version
     }
@@ -570,6 +579,33 @@ impl SandboxSafeSystemState {
             .prepayment_for_response_transmission(self.subnet_size)
     }
 
+    /// Returns the amount of cycles that a call with a request of the given
+    /// size costs, including the prepayment for the response.
+    pub(super) fn call_cost(&self, payload_size: NumBytes) -> Cycles {
+        self.cycles_account_manager
+            .xnet_call_total_fee(payload_size, self.subnet_size)
+    }
+
+    /// Returns the fee for creating a canister.
+    pub(super) fn create_canister_cost(&self) -> Cycles {
+        self.cycles_account_manager
+            .canister_creation_fee(self.subnet_size)
+    }
+
+    /// Returns the fee for an HTTP outcall with the given request size and
+    /// maximum response size.
+    pub(super) fn http_request_cost(
+        &self,
+        request_size: NumBytes,
+        max_response_size: NumBytes,
+    ) -> Cycles {
+        self.cycles_account_manager.http_request_fee(
+            request_size,
+            Some(max_response_size),
+            self.subnet_size,
+        )
+    }
+
     pub(super) fn withdraw_cycles_for_transfer(
         &mut self,
         canister_current_memory_usage: NumBytes,
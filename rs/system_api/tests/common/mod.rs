@@ -2,7 +2,7 @@ use std::{convert::TryFrom, sync::Arc};
 
 use ic_base_types::{CanisterId, NumBytes, SubnetId};
 use ic_config::{flag_status::FlagStatus, subnet_config::SchedulerConfig};
-use ic_cycles_account_manager::CyclesAccountManager;
+use ic_cycles_account_manager::{CyclesAccountManager, ResourceSaturation};
 use ic_interfaces::execution_environment::{ExecutionMode, SubnetAvailableMemory};
 use ic_logger::replica_logger::no_op_logger;
 use ic_nns_constants::CYCLES_MINTING_CANISTER_ID;
@@ -21,6 +21,7 @@ use ic_test_utilities::{state::SystemStateBuilder, types::ids::canister_test_id}
 use ic_types::{
     messages::{CallContextId, CallbackId, RejectContext},
     methods::SystemMethod,
+    time::NO_DEADLINE,
     ComputeAllocation, Cycles, NumInstructions, Time,
 };
 use maplit::btreemap;
@@ -38,6 +39,7 @@ pub fn execution_parameters() -> ExecutionParameters {
         compute_allocation: ComputeAllocation::default(),
         subnet_type: SubnetType::Application,
         execution_mode: ExecutionMode::Replicated,
+        subnet_memory_saturation: ResourceSaturation::default(),
     }
 }
 
@@ -76,6 +78,7 @@ impl ApiTypeBuilder {
             Cycles::zero(),
             user_test_id(1).get(),
             CallContextId::from(1),
+            NO_DEADLINE,
         )
     }
 
@@ -93,6 +96,7 @@ impl ApiTypeBuilder {
             vec![],
             incoming_cycles,
             CallContextId::new(1),
+            NO_DEADLINE,
             false,
             ExecutionMode::Replicated,
         )
@@ -104,6 +108,7 @@ impl ApiTypeBuilder {
             reject_context,
             Cycles::zero(),
             call_context_test_id(1),
+            NO_DEADLINE,
             false,
             ExecutionMode::Replicated,
         )
@@ -139,7 +144,7 @@ pub fn get_system_state() -> SystemState {
         .call_context_manager_mut()
         .unwrap()
         .new_call_context(
-            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5)),
+            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5), NO_DEADLINE),
             Cycles::new(50),
             Time::from_nanos_since_unix_epoch(0),
         );
@@ -1,10 +1,11 @@
+use assert_matches::assert_matches;
 use ic_base_types::NumSeconds;
 use ic_config::subnet_config::SchedulerConfig;
 use ic_constants::SMALL_APP_SUBNET_MAX_SIZE;
 use ic_error_types::RejectCode;
 use ic_interfaces::execution_environment::{
-    CanisterOutOfCyclesError, HypervisorError, HypervisorResult, PerformanceCounterType,
-    SubnetAvailableMemory, SystemApi, TrapCode,
+    CanisterOutOfCyclesError, HypervisorError, HypervisorResult, MemoryReservationError,
+    PerformanceCounterType, SubnetAvailableMemory, SystemApi, TrapCode,
 };
 use ic_logger::replica_logger::no_op_logger;
 use ic_registry_subnet_type::SubnetType;
@@ -28,7 +29,8 @@ use ic_test_utilities::{
 use ic_types::{
     messages::{CallContextId, CallbackId, RejectContext, MAX_RESPONSE_COUNT_BYTES},
     methods::{Callback, WasmClosure},
-    time, CanisterTimer, CountBytes, Cycles, NumBytes, NumInstructions, Time,
+    time::{self, CoarseTime, NO_DEADLINE},
+    CanisterTimer, CountBytes, Cycles, NumBytes, NumInstructions, Time,
 };
 use std::{
     convert::{From, TryInto},
@@ -883,7 +885,7 @@ fn test_inspect_message_support() {
     assert_api_supported(api.ic0_accept_message());
     assert_api_not_supported(api.ic0_msg_reply());
     assert_api_not_supported(api.ic0_msg_reply_data_append(0, 0, &[]));
-    assert_api_not_supported(api.ic0_msg_reject(0, 0, &[]));
+    assert_api_supported(api.ic0_msg_reject(0, 0, &[]));
     assert_api_not_supported(api.ic0_msg_reject_code());
     assert_api_not_supported(api.ic0_msg_reject_msg_size());
     assert_api_not_supported(api.ic0_msg_reject_msg_copy(0, 0, 0, &mut []));
@@ -1208,7 +1210,7 @@ fn test_canister_balance() {
         .call_context_manager_mut()
         .unwrap()
         .new_call_context(
-            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5)),
+            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5), NO_DEADLINE),
             Cycles::new(50),
             Time::from_nanos_since_unix_epoch(0),
         );
@@ -1236,7 +1238,7 @@ fn test_canister_cycle_balance() {
         .call_context_manager_mut()
         .unwrap()
         .new_call_context(
-            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5)),
+            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5), NO_DEADLINE),
             Cycles::new(50),
             Time::from_nanos_since_unix_epoch(0),
         );
@@ -1270,7 +1272,7 @@ fn test_msg_cycles_available_traps() {
         .call_context_manager_mut()
         .unwrap()
         .new_call_context(
-            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5)),
+            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5), NO_DEADLINE),
             available_cycles,
             Time::from_nanos_since_unix_epoch(0),
         );
@@ -1431,7 +1433,7 @@ fn msg_cycles_accept_all_cycles_in_call_context() {
         .call_context_manager_mut()
         .unwrap()
         .new_call_context(
-            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5)),
+            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5), NO_DEADLINE),
             Cycles::from(amount),
             Time::from_nanos_since_unix_epoch(0),
         );
@@ -1454,7 +1456,7 @@ fn msg_cycles_accept_all_cycles_in_call_context_when_more_asked() {
         .call_context_manager_mut()
         .unwrap()
         .new_call_context(
-            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5)),
+            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5), NO_DEADLINE),
             Cycles::new(40),
             Time::from_nanos_since_unix_epoch(0),
         );
@@ -1485,7 +1487,7 @@ fn call_perform_not_enough_cycles_resets_state() {
         .call_context_manager_mut()
         .unwrap()
         .new_call_context(
-            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5)),
+            CallOrigin::CanisterUpdate(canister_test_id(33), CallbackId::from(5), NO_DEADLINE),
             Cycles::new(40),
             Time::from_nanos_since_unix_epoch(0),
         );
@@ -1617,7 +1619,13 @@ fn update_available_memory_updates_subnet_available_memory() {
     assert_eq!(api.get_allocated_bytes().get() as i64, wasm_page_size);
     assert_eq!(api.get_allocated_message_bytes().get() as i64, 0);
 
-    api.update_available_memory(0, 10).unwrap_err();
+    assert_eq!(
+        api.update_available_memory(0, 10).unwrap_err(),
+        HypervisorError::OutOfMemory(MemoryReservationError::SubnetMemoryCapacityExceeded {
+            requested: NumBytes::from(10 * wasm_page_size as u64),
+            available: wasm_page_size,
+        })
+    );
     assert_eq!(api.get_allocated_bytes().get() as i64, wasm_page_size);
     assert_eq!(api.get_allocated_message_bytes().get() as i64, 0);
 }
@@ -1645,6 +1653,7 @@ fn take_execution_result_properly_frees_memory() {
             WasmClosure::new(0, 0),
             WasmClosure::new(0, 0),
             None,
+            NO_DEADLINE,
         ))
         .unwrap();
     let mut api = SystemApiImpl::new(
@@ -1673,11 +1682,11 @@ fn take_execution_result_properly_frees_memory() {
 
     assert!(api.get_allocated_bytes().get() > 0);
     assert!(api.get_allocated_message_bytes().get() > 0);
-    assert_eq!(
-        api.take_execution_result(Some(&HypervisorError::OutOfMemory))
-            .unwrap_err(),
-        HypervisorError::OutOfMemory
-    );
+    let err = HypervisorError::OutOfMemory(MemoryReservationError::SubnetMemoryCapacityExceeded {
+        requested: NumBytes::from(1),
+        available: 0,
+    });
+    assert_eq!(api.take_execution_result(Some(&err)).unwrap_err(), err);
     assert_eq!(api.get_allocated_bytes().get(), 0);
     assert_eq!(api.get_allocated_message_bytes().get(), 0);
 }
@@ -1709,6 +1718,7 @@ fn push_output_request_respects_memory_limits() {
                 WasmClosure::new(0, 0),
                 WasmClosure::new(0, 0),
                 None,
+                NO_DEADLINE,
             ))
             .unwrap();
         let mut api = SystemApiImpl::new(
@@ -1812,6 +1822,7 @@ fn push_output_request_oversized_request_memory_limits() {
             WasmClosure::new(0, 0),
             WasmClosure::new(0, 0),
             None,
+            NO_DEADLINE,
         ))
         .unwrap();
     let mut api = SystemApiImpl::new(
@@ -1921,3 +1932,91 @@ fn ic0_global_timer_set_is_propagated_from_sandbox() {
         CanisterTimer::Active(Time::from_nanos_since_unix_epoch(2))
     );
 }
+
+#[test]
+fn msg_deadline_returns_deadline_of_message() {
+    let cycles_account_manager = CyclesAccountManagerBuilder::new().build();
+    let deadline = CoarseTime::from_secs_since_unix_epoch(42);
+    let api = get_system_api(
+        ApiType::update(
+            mock_time(),
+            vec![],
+            Cycles::zero(),
+            user_test_id(1).get(),
+            CallContextId::from(1),
+            deadline,
+        ),
+        &get_system_state(),
+        cycles_account_manager,
+    );
+    assert_eq!(api.ic0_msg_deadline().unwrap(), 42_000_000_000);
+
+    let api = get_system_api(
+        ApiTypeBuilder::build_update_api(),
+        &get_system_state(),
+        CyclesAccountManagerBuilder::new().build(),
+    );
+    assert_eq!(api.ic0_msg_deadline().unwrap(), 0);
+
+    let api = get_system_api(
+        ApiTypeBuilder::build_system_task_api(),
+        &get_system_state(),
+        CyclesAccountManagerBuilder::new().build(),
+    );
+    assert_api_not_supported(api.ic0_msg_deadline());
+}
+
+#[test]
+fn cost_system_apis_return_fees() {
+    let cycles_account_manager = CyclesAccountManagerBuilder::new().build();
+    let subnet_size = SMALL_APP_SUBNET_MAX_SIZE;
+    let api = get_system_api(
+        ApiTypeBuilder::build_update_api(),
+        &get_system_state(),
+        cycles_account_manager,
+    );
+    let mut heap = vec![0; 16];
+
+    api.ic0_cost_create_canister(0, &mut heap).unwrap();
+    let fee = cycles_account_manager.canister_creation_fee(subnet_size);
+    assert_eq!(heap, fee.get().to_le_bytes());
+
+    api.ic0_cost_call(10, 90, 0, &mut heap).unwrap();
+    let fee = cycles_account_manager.xnet_call_total_fee(NumBytes::from(100), subnet_size);
+    assert_eq!(heap, fee.get().to_le_bytes());
+
+    api.ic0_cost_http_request(100, 1000, 0, &mut heap).unwrap();
+    let fee = cycles_account_manager.http_request_fee(
+        NumBytes::from(100),
+        Some(NumBytes::from(1000)),
+        subnet_size,
+    );
+    assert_eq!(heap, fee.get().to_le_bytes());
+}
+
+#[test]
+fn inspect_message_rejects_with_message() {
+    let cycles_account_manager = CyclesAccountManagerBuilder::new().build();
+    let mut api = get_system_api(
+        ApiType::inspect_message(
+            user_test_id(1).get(),
+            "hello".to_string(),
+            vec![],
+            mock_time(),
+        ),
+        &get_system_state(),
+        cycles_account_manager,
+    );
+
+    api.ic0_msg_reject(0, 5, b"nope!").unwrap();
+    assert_matches!(
+        api.ic0_accept_message(),
+        Err(HypervisorError::ContractViolation(_))
+    );
+    assert_eq!(
+        api.take_execution_result(None),
+        Err(HypervisorError::MessageRejected {
+            reject_message: Some("nope!".to_string())
+        })
+    );
+}
@@ -26,7 +26,7 @@ use ic_replicated_state::{
 };
 use ic_types::messages::CallbackId;
 use ic_types::methods::{Callback, WasmClosure};
-use ic_types::time::UNIX_EPOCH;
+use ic_types::time::{NO_DEADLINE, UNIX_EPOCH};
 use ic_types::{
     messages::{Ingress, Request, RequestOrResponse},
     xnet::{StreamHeader, StreamIndex, StreamIndexedQueue},
@@ -474,6 +474,7 @@ pub fn initial_execution_state() -> ExecutionState {
         exports: ExportedFunctions::new(BTreeSet::new()),
         metadata: wasm_metadata,
         last_executed_round: ExecutionRound::from(0),
+        last_instruction_profile: Default::default(),
     }
 }
 
@@ -676,7 +677,7 @@ pub fn register_callback(
         .call_context_manager_mut()
         .unwrap();
     let call_context_id = call_context_manager.new_call_context(
-        CallOrigin::CanisterUpdate(originator, callback_id),
+        CallOrigin::CanisterUpdate(originator, callback_id, NO_DEADLINE),
         Cycles::zero(),
         Time::from_nanos_since_unix_epoch(0),
     );
@@ -691,6 +692,7 @@ pub fn register_callback(
         WasmClosure::new(0, 2),
         WasmClosure::new(0, 2),
         None,
+        NO_DEADLINE,
     ));
 }
 
@@ -2,6 +2,7 @@ use std::convert::TryFrom;
 use std::sync::Arc;
 
 use ic_config::{flag_status::FlagStatus, subnet_config::SchedulerConfig};
+use ic_cycles_account_manager::ResourceSaturation;
 use ic_embedders::{wasm_utils::compile, wasmtime_embedder::WasmtimeInstance, WasmtimeEmbedder};
 use ic_interfaces::execution_environment::{ExecutionMode, SubnetAvailableMemory, SystemApi};
 use ic_logger::replica_logger::no_op_logger;
@@ -117,6 +118,7 @@ impl WasmtimeInstanceBuilder {
                 compute_allocation: ComputeAllocation::default(),
                 subnet_type: self.subnet_type,
                 execution_mode: ExecutionMode::Replicated,
+                subnet_memory_saturation: ResourceSaturation::default(),
             },
             SubnetAvailableMemory::new(i64::MAX / 2, i64::MAX / 2),
             Memory::default(),
@@ -242,7 +242,12 @@ pub(crate) async fn install_ledger(
             controller_id: minting_user,
             cycles_for_archive_creation: None,
             max_transactions_per_response: None,
+            max_archive_nodes: None,
+            cycles_for_archive_top_up: None,
+            cycles_for_archive_top_ups_pool: None,
         },
+        fee_collector_account: None,
+        decimals: None,
     };
     install_icrc1_ledger(env, canister, &init_args).await;
     canister.canister_id()
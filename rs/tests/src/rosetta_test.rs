@@ -423,6 +423,9 @@ pub fn test_everything(env: TestEnv) {
         controller_id: CanisterId::from_u64(876).into(),
         cycles_for_archive_creation: Some(0),
         max_transactions_per_response: None,
+        max_archive_nodes: None,
+        cycles_for_archive_top_up: None,
+        cycles_for_archive_top_ups_pool: None,
     };
 
     let ledger_canister_for_governance_payload = LedgerCanisterInitPayload::builder()
@@ -22,6 +22,9 @@ pub enum RejectCode {
     DestinationInvalid = 3,
     CanisterReject = 4,
     CanisterError = 5,
+    /// The outcome of a best-effort call is unknown, e.g., because its deadline
+    /// expired before a response arrived.
+    SysUnknown = 6,
 }
 
 impl ToString for RejectCode {
@@ -38,6 +41,7 @@ impl RejectCode {
             RejectCode::DestinationInvalid => "DESTINATION_INVALID",
             RejectCode::CanisterReject => "CANISTER_REJECT",
             RejectCode::CanisterError => "CANISTER_ERROR",
+            RejectCode::SysUnknown => "SYS_UNKNOWN",
         }
     }
 }
@@ -51,6 +55,7 @@ impl TryFrom<u64> for RejectCode {
             3 => Ok(RejectCode::DestinationInvalid),
             4 => Ok(RejectCode::CanisterReject),
             5 => Ok(RejectCode::CanisterError),
+            6 => Ok(RejectCode::SysUnknown),
             _ => Err(TryFromError::ValueOutOfRange(code)),
         }
     }
@@ -100,7 +105,11 @@ impl From<ErrorCode> for RejectCode {
             QueryCallGraphTooDeep => CanisterError,
             QueryCallGraphTotalInstructionLimitExceeded => CanisterError,
             CompositeQueryCalledInReplicatedMode => CanisterError,
+            ReservedCyclesLimitExceededInMemoryGrow => CanisterError,
+            InsufficientCyclesInMemoryGrow => CanisterError,
+            CanisterWasmMemoryLimitExceeded => CanisterError,
             CanisterNotHostedBySubnet => CanisterReject,
+            CanisterCallContextDeleted => CanisterReject,
         }
     }
 }
@@ -126,6 +135,7 @@ pub enum ErrorCode {
     InsufficientCyclesForCreateCanister = 403,
     SubnetNotFound = 404,
     CanisterNotHostedBySubnet = 405,
+    CanisterCallContextDeleted = 406,
     CanisterOutOfCycles = 501,
     CanisterTrapped = 502,
     CanisterCalledTrap = 503,
@@ -153,6 +163,9 @@ pub enum ErrorCode {
     QueryCallGraphTooDeep = 525,
     QueryCallGraphTotalInstructionLimitExceeded = 526,
     CompositeQueryCalledInReplicatedMode = 527,
+    ReservedCyclesLimitExceededInMemoryGrow = 528,
+    InsufficientCyclesInMemoryGrow = 529,
+    CanisterWasmMemoryLimitExceeded = 530,
 }
 
 impl TryFrom<u64> for ErrorCode {
@@ -172,6 +185,7 @@ impl TryFrom<u64> for ErrorCode {
             403 => Ok(ErrorCode::InsufficientCyclesForCreateCanister),
             404 => Ok(ErrorCode::SubnetNotFound),
             405 => Ok(ErrorCode::CanisterNotHostedBySubnet),
+            406 => Ok(ErrorCode::CanisterCallContextDeleted),
             501 => Ok(ErrorCode::CanisterOutOfCycles),
             502 => Ok(ErrorCode::CanisterTrapped),
             503 => Ok(ErrorCode::CanisterCalledTrap),
@@ -199,6 +213,9 @@ impl TryFrom<u64> for ErrorCode {
             525 => Ok(ErrorCode::QueryCallGraphTooDeep),
             526 => Ok(ErrorCode::QueryCallGraphTotalInstructionLimitExceeded),
             527 => Ok(ErrorCode::CompositeQueryCalledInReplicatedMode),
+            528 => Ok(ErrorCode::ReservedCyclesLimitExceededInMemoryGrow),
+            529 => Ok(ErrorCode::InsufficientCyclesInMemoryGrow),
+            530 => Ok(ErrorCode::CanisterWasmMemoryLimitExceeded),
             _ => Err(TryFromError::ValueOutOfRange(err)),
         }
     }
@@ -7,6 +7,7 @@ use ic_base_types::{CanisterId, NodeId, NumBytes, PrincipalId, RegistryVersion,
 use ic_error_types::{ErrorCode, UserError};
 use ic_protobuf::registry::crypto::v1::PublicKey;
 use ic_protobuf::registry::subnet::v1::{InitialIDkgDealings, InitialNiDkgTranscriptRecord};
+use ic_protobuf::state::canister_state_bits::v1 as pb_canister_state_bits;
 use ic_protobuf::{proxy::ProxyDecodeError, registry::crypto::v1 as pb_registry_crypto};
 use num_traits::cast::ToPrimitive;
 use serde::Serialize;
@@ -33,6 +34,7 @@ pub enum Method {
     HttpRequest,
     ECDSAPublicKey,
     InstallCode,
+    InstallChunkedCode,
     RawRand,
     SetController,
     SetupInitialDKG,
@@ -43,6 +45,17 @@ pub enum Method {
     UpdateSettings,
     ComputeInitialEcdsaDealings,
 
+    // Wasm chunk store.
+    UploadChunk,
+    ClearChunkStore,
+    StoredChunks,
+
+    // Canister logs.
+    FetchCanisterLogs,
+
+    // The call contexts that keep a stopping canister from stopping.
+    CanisterStoppingStatus,
+
     // Bitcoin Interface.
     BitcoinGetBalance,
     BitcoinGetUtxos,
@@ -94,6 +107,41 @@ impl From<CanisterId> for CanisterIdRecord {
     }
 }
 
+/// Struct used for encoding/decoding
+/// `(record {
+///     canister_id : principal;
+///     force: opt bool;
+/// })`
+///
+/// A `(record {canister_id})` payload decodes into this struct with `force`
+/// unset, so existing callers of `stop_canister` keep working.
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+pub struct StopCanisterArgs {
+    canister_id: PrincipalId,
+    force: Option<bool>,
+}
+
+impl StopCanisterArgs {
+    pub fn new(canister_id: CanisterId, force: Option<bool>) -> Self {
+        Self {
+            canister_id: canister_id.into(),
+            force,
+        }
+    }
+
+    pub fn get_canister_id(&self) -> CanisterId {
+        // Safe as this was converted from CanisterId when Self was constructed.
+        CanisterId::new(self.canister_id).unwrap()
+    }
+
+    /// Whether the call contexts that block the stop should be rejected.
+    pub fn force(&self) -> bool {
+        self.force.unwrap_or(false)
+    }
+}
+
+impl Payload<'_> for StopCanisterArgs {}
+
 /// Struct used for encoding/decoding
 /// `(record {
 ///     controller : principal;
@@ -193,6 +241,149 @@ impl CanisterStatusResult {
 
 impl Payload<'_> for CanisterStatusResult {}
 
+/// Struct used for encoding/decoding
+/// `(record {
+///     wasm_memory_size: nat;
+///     stable_memory_size: nat;
+///     global_memory_size: nat;
+///     wasm_binary_size: nat;
+///     message_memory_size: nat;
+/// })`
+#[derive(CandidType, Debug, Deserialize, Eq, PartialEq)]
+pub struct MemoryMetrics {
+    wasm_memory_size: candid::Nat,
+    stable_memory_size: candid::Nat,
+    global_memory_size: candid::Nat,
+    wasm_binary_size: candid::Nat,
+    message_memory_size: candid::Nat,
+}
+
+impl MemoryMetrics {
+    pub fn new(
+        wasm_memory_size: NumBytes,
+        stable_memory_size: NumBytes,
+        global_memory_size: NumBytes,
+        wasm_binary_size: NumBytes,
+        message_memory_size: NumBytes,
+    ) -> Self {
+        Self {
+            wasm_memory_size: candid::Nat::from(wasm_memory_size.get()),
+            stable_memory_size: candid::Nat::from(stable_memory_size.get()),
+            global_memory_size: candid::Nat::from(global_memory_size.get()),
+            wasm_binary_size: candid::Nat::from(wasm_binary_size.get()),
+            message_memory_size: candid::Nat::from(message_memory_size.get()),
+        }
+    }
+
+    pub fn wasm_memory_size(&self) -> NumBytes {
+        NumBytes::from(self.wasm_memory_size.0.to_u64().unwrap())
+    }
+
+    pub fn stable_memory_size(&self) -> NumBytes {
+        NumBytes::from(self.stable_memory_size.0.to_u64().unwrap())
+    }
+
+    pub fn global_memory_size(&self) -> NumBytes {
+        NumBytes::from(self.global_memory_size.0.to_u64().unwrap())
+    }
+
+    pub fn wasm_binary_size(&self) -> NumBytes {
+        NumBytes::from(self.wasm_binary_size.0.to_u64().unwrap())
+    }
+
+    pub fn message_memory_size(&self) -> NumBytes {
+        NumBytes::from(self.message_memory_size.0.to_u64().unwrap())
+    }
+}
+
+/// Struct used for encoding/decoding
+/// `(record {
+///     update_instructions_executed: nat;
+///     query_instructions_executed: nat;
+///     system_task_instructions_executed: nat;
+/// })`
+#[derive(CandidType, Debug, Deserialize, Eq, PartialEq)]
+pub struct InstructionMetrics {
+    update_instructions_executed: candid::Nat,
+    query_instructions_executed: candid::Nat,
+    system_task_instructions_executed: candid::Nat,
+}
+
+impl InstructionMetrics {
+    pub fn new(
+        update_instructions_executed: u64,
+        query_instructions_executed: u64,
+        system_task_instructions_executed: u64,
+    ) -> Self {
+        Self {
+            update_instructions_executed: candid::Nat::from(update_instructions_executed),
+            query_instructions_executed: candid::Nat::from(query_instructions_executed),
+            system_task_instructions_executed: candid::Nat::from(
+                system_task_instructions_executed,
+            ),
+        }
+    }
+
+    pub fn update_instructions_executed(&self) -> u64 {
+        self.update_instructions_executed.0.to_u64().unwrap()
+    }
+
+    pub fn query_instructions_executed(&self) -> u64 {
+        self.query_instructions_executed.0.to_u64().unwrap()
+    }
+
+    pub fn system_task_instructions_executed(&self) -> u64 {
+        self.system_task_instructions_executed.0.to_u64().unwrap()
+    }
+}
+
+/// Struct used for encoding/decoding
+/// `(record {
+///     method_name: text;
+///     num_calls_total: nat;
+///     num_instructions_total: nat;
+///     response_payload_bytes_total: nat;
+/// })`
+#[derive(CandidType, Debug, Deserialize, Eq, PartialEq)]
+pub struct MethodQueryStats {
+    method_name: String,
+    num_calls_total: candid::Nat,
+    num_instructions_total: candid::Nat,
+    response_payload_bytes_total: candid::Nat,
+}
+
+impl MethodQueryStats {
+    pub fn new(
+        method_name: String,
+        num_calls_total: u64,
+        num_instructions_total: u64,
+        response_payload_bytes_total: u64,
+    ) -> Self {
+        Self {
+            method_name,
+            num_calls_total: candid::Nat::from(num_calls_total),
+            num_instructions_total: candid::Nat::from(num_instructions_total),
+            response_payload_bytes_total: candid::Nat::from(response_payload_bytes_total),
+        }
+    }
+
+    pub fn method_name(&self) -> &str {
+        &self.method_name
+    }
+
+    pub fn num_calls_total(&self) -> u64 {
+        self.num_calls_total.0.to_u64().unwrap()
+    }
+
+    pub fn num_instructions_total(&self) -> u64 {
+        self.num_instructions_total.0.to_u64().unwrap()
+    }
+
+    pub fn response_payload_bytes_total(&self) -> u64 {
+        self.response_payload_bytes_total.0.to_u64().unwrap()
+    }
+}
+
 /// Struct used for encoding/decoding
 /// `(record {
 ///     status : variant { running; stopping; stopped };
@@ -200,7 +391,13 @@ impl Payload<'_> for CanisterStatusResult {}
 ///     module_hash: opt blob;
 ///     controller: principal;
 ///     memory_size: nat;
+///     memory_metrics: opt memory_metrics;
+///     instruction_metrics: opt instruction_metrics;
+///     query_stats: opt vec method_query_stats;
 ///     cycles: nat;
+///     reserved_cycles: opt nat;
+///     wasm_memory_limit: opt nat;
+///     blocking_call_contexts: opt vec blocking_call_context;
 ///     idle_cycles_burned_per_day: nat;
 /// })`
 #[derive(CandidType, Debug, Deserialize, Eq, PartialEq)]
@@ -210,7 +407,29 @@ pub struct CanisterStatusResultV2 {
     controller: candid::Principal,
     settings: DefiniteCanisterSettingsArgs,
     memory_size: candid::Nat,
+    // The breakdown of `memory_size` by component. It is optional so that
+    // the results of replicas that don't report it can still be decoded.
+    memory_metrics: Option<MemoryMetrics>,
+    // The instructions that the canister executed, by kind of execution.
+    // Optional for the same reason as `memory_metrics`.
+    instruction_metrics: Option<InstructionMetrics>,
+    // The statistics of the queries that the canister executed in replicated
+    // mode, by method. Queries sent to the query endpoint execute on a single
+    // replica and are not included. Optional for the same reason as
+    // `memory_metrics`.
+    query_stats: Option<Vec<MethodQueryStats>>,
     cycles: candid::Nat,
+    // The cycles reserved for future storage payments. Optional for the same
+    // reason as `memory_metrics`.
+    reserved_cycles: Option<candid::Nat>,
+    // The Wasm memory limit of the canister, if its controllers set one.
+    wasm_memory_limit: Option<candid::Nat>,
+    // The call contexts that keep the canister from stopping. Only set while
+    // the canister is stopping.
+    blocking_call_contexts: Option<Vec<BlockingCallContext>>,
+    // The time at which the global timer of the canister is next due, in
+    // nanoseconds since the Unix epoch. Not set if the timer is inactive.
+    global_timer_deadline: Option<u64>,
     // this is for compat with Spec 0.12/0.13
     balance: Vec<(Vec<u8>, candid::Nat)>,
     freezing_threshold: candid::Nat,
@@ -236,7 +455,14 @@ impl CanisterStatusResultV2 {
             module_hash,
             controller: candid::Principal::from_text(controller.to_string()).unwrap(),
             memory_size: candid::Nat::from(memory_size.get()),
+            memory_metrics: None,
+            instruction_metrics: None,
+            query_stats: None,
             cycles: candid::Nat::from(cycles),
+            reserved_cycles: None,
+            wasm_memory_limit: None,
+            blocking_call_contexts: None,
+            global_timer_deadline: None,
             // the following is spec 0.12/0.13 compat;
             // "\x00" denotes cycles
             balance: vec![(vec![0], candid::Nat::from(cycles))],
@@ -272,10 +498,89 @@ impl CanisterStatusResultV2 {
         NumBytes::from(self.memory_size.0.to_u64().unwrap())
     }
 
+    /// Sets the breakdown of the memory size by component.
+    pub fn with_memory_metrics(mut self, memory_metrics: MemoryMetrics) -> Self {
+        self.memory_metrics = Some(memory_metrics);
+        self
+    }
+
+    pub fn memory_metrics(&self) -> Option<&MemoryMetrics> {
+        self.memory_metrics.as_ref()
+    }
+
+    /// Sets the instructions that the canister executed by kind of execution.
+    pub fn with_instruction_metrics(mut self, instruction_metrics: InstructionMetrics) -> Self {
+        self.instruction_metrics = Some(instruction_metrics);
+        self
+    }
+
+    pub fn instruction_metrics(&self) -> Option<&InstructionMetrics> {
+        self.instruction_metrics.as_ref()
+    }
+
+    /// Sets the statistics of the queries executed in replicated mode. These
+    /// exclude the non-replicated queries sent to the query endpoint.
+    pub fn with_query_stats(mut self, query_stats: Vec<MethodQueryStats>) -> Self {
+        self.query_stats = Some(query_stats);
+        self
+    }
+
+    pub fn query_stats(&self) -> Option<&[MethodQueryStats]> {
+        self.query_stats.as_deref()
+    }
+
     pub fn cycles(&self) -> u128 {
         self.cycles.0.to_u128().unwrap()
     }
 
+    /// Sets the cycles reserved for future storage payments.
+    pub fn with_reserved_cycles(mut self, reserved_cycles: u128) -> Self {
+        self.reserved_cycles = Some(candid::Nat::from(reserved_cycles));
+        self
+    }
+
+    pub fn reserved_cycles(&self) -> Option<u128> {
+        self.reserved_cycles
+            .as_ref()
+            .map(|cycles| cycles.0.to_u128().unwrap())
+    }
+
+    /// Sets the Wasm memory limit of the canister.
+    pub fn with_wasm_memory_limit(mut self, wasm_memory_limit: u64) -> Self {
+        self.wasm_memory_limit = Some(candid::Nat::from(wasm_memory_limit));
+        self
+    }
+
+    pub fn wasm_memory_limit(&self) -> Option<u64> {
+        self.wasm_memory_limit
+            .as_ref()
+            .map(|limit| limit.0.to_u64().unwrap())
+    }
+
+    /// Sets the call contexts that keep the stopping canister from stopping.
+    pub fn with_blocking_call_contexts(
+        mut self,
+        blocking_call_contexts: Vec<BlockingCallContext>,
+    ) -> Self {
+        self.blocking_call_contexts = Some(blocking_call_contexts);
+        self
+    }
+
+    pub fn blocking_call_contexts(&self) -> Option<&[BlockingCallContext]> {
+        self.blocking_call_contexts.as_deref()
+    }
+
+    /// Sets the time at which the global timer is next due, in nanoseconds
+    /// since the Unix epoch.
+    pub fn with_global_timer_deadline(mut self, global_timer_deadline: u64) -> Self {
+        self.global_timer_deadline = Some(global_timer_deadline);
+        self
+    }
+
+    pub fn global_timer_deadline(&self) -> Option<u64> {
+        self.global_timer_deadline
+    }
+
     pub fn freezing_threshold(&self) -> u64 {
         self.freezing_threshold.0.to_u64().unwrap()
     }
@@ -285,6 +590,63 @@ impl CanisterStatusResultV2 {
     }
 }
 
+/// A call context that keeps a stopping canister from stopping.
+/// `(record {
+///     caller: opt principal;
+///     age_seconds: opt nat64;
+///     outstanding_callees: vec principal;
+/// })`
+#[derive(Clone, CandidType, Debug, Deserialize, Eq, PartialEq)]
+pub struct BlockingCallContext {
+    /// The principal that made the call, unless it was a system task.
+    caller: Option<PrincipalId>,
+    /// The time since the call context was created. Not known for call
+    /// contexts that were created before their creation time was recorded.
+    age_seconds: Option<u64>,
+    /// The principals that the calls still awaiting a response were sent to.
+    outstanding_callees: Vec<PrincipalId>,
+}
+
+impl BlockingCallContext {
+    pub fn new(
+        caller: Option<PrincipalId>,
+        age_seconds: Option<u64>,
+        outstanding_callees: Vec<PrincipalId>,
+    ) -> Self {
+        Self {
+            caller,
+            age_seconds,
+            outstanding_callees,
+        }
+    }
+
+    pub fn caller(&self) -> Option<PrincipalId> {
+        self.caller
+    }
+
+    pub fn age_seconds(&self) -> Option<u64> {
+        self.age_seconds
+    }
+
+    pub fn outstanding_callees(&self) -> &[PrincipalId] {
+        &self.outstanding_callees
+    }
+}
+
+/// The reply of `canister_stopping_status`.
+/// `(record {
+///     status : variant { running; stopping; stopped };
+///     blocking_call_contexts: vec blocking_call_context;
+/// })`
+#[derive(CandidType, Deserialize, Debug, PartialEq, Eq)]
+pub struct CanisterStoppingStatusResponse {
+    pub status: CanisterStatusType,
+    /// Empty unless the canister is stopping.
+    pub blocking_call_contexts: Vec<BlockingCallContext>,
+}
+
+impl Payload<'_> for CanisterStoppingStatusResponse {}
+
 /// Indicates whether the canister is running, stopping, or stopped.
 ///
 /// Unlike `CanisterStatus`, it contains no additional metadata.
@@ -463,6 +825,216 @@ impl InstallCodeArgs {
     }
 }
 
+/// Struct used for encoding/decoding
+/// `(record {
+///     canister_id: principal;
+///     chunk: blob;
+/// })`
+#[derive(CandidType, Deserialize, Debug)]
+pub struct UploadChunkArgs {
+    pub canister_id: PrincipalId,
+    #[serde(with = "serde_bytes")]
+    pub chunk: Vec<u8>,
+}
+
+impl Payload<'_> for UploadChunkArgs {}
+
+impl UploadChunkArgs {
+    pub fn new(canister_id: CanisterId, chunk: Vec<u8>) -> Self {
+        Self {
+            canister_id: canister_id.into(),
+            chunk,
+        }
+    }
+
+    pub fn get_canister_id(&self) -> CanisterId {
+        // Safe as this was converted from CanisterId when Self was constructed.
+        CanisterId::new(self.canister_id).unwrap()
+    }
+}
+
+/// The hash of a chunk in the Wasm chunk store of a canister. It is the reply
+/// of `upload_chunk`.
+/// `(record {
+///     hash: blob;
+/// })`
+#[derive(Clone, CandidType, Deserialize, Debug, PartialEq, Eq)]
+pub struct ChunkHash {
+    #[serde(with = "serde_bytes")]
+    pub hash: Vec<u8>,
+}
+
+impl Payload<'_> for ChunkHash {}
+
+/// The reply of `stored_chunks`.
+/// `(vec chunk_hash)`
+#[derive(CandidType, Deserialize, Debug, PartialEq, Eq)]
+pub struct StoredChunksReply(pub Vec<ChunkHash>);
+
+impl Payload<'_> for StoredChunksReply {}
+
+/// Who may fetch the logs of a canister.
+/// `(variant {
+///     controllers;
+///     public;
+/// })`
+#[derive(Clone, Copy, CandidType, Deserialize, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum LogVisibility {
+    /// Only the controllers of the canister.
+    #[default]
+    #[serde(rename = "controllers")]
+    Controllers,
+    /// Everyone.
+    #[serde(rename = "public")]
+    Public,
+}
+
+impl From<&LogVisibility> for pb_canister_state_bits::LogVisibility {
+    fn from(item: &LogVisibility) -> Self {
+        match item {
+            LogVisibility::Controllers => pb_canister_state_bits::LogVisibility::Controllers,
+            LogVisibility::Public => pb_canister_state_bits::LogVisibility::Public,
+        }
+    }
+}
+
+impl From<pb_canister_state_bits::LogVisibility> for LogVisibility {
+    fn from(item: pb_canister_state_bits::LogVisibility) -> Self {
+        match item {
+            // Canisters that were checkpointed before logs existed have an
+            // unspecified visibility.
+            pb_canister_state_bits::LogVisibility::Unspecified
+            | pb_canister_state_bits::LogVisibility::Controllers => LogVisibility::Controllers,
+            pb_canister_state_bits::LogVisibility::Public => LogVisibility::Public,
+        }
+    }
+}
+
+/// A record in the log of a canister.
+/// `(record {
+///     idx: nat64;
+///     timestamp_nanos: nat64;
+///     content: blob;
+/// })`
+#[derive(Clone, CandidType, Deserialize, Debug, PartialEq, Eq, Serialize)]
+pub struct CanisterLogRecord {
+    /// The index of the record in the log. The indices of consecutive records
+    /// are consecutive, even after older records are dropped.
+    pub idx: u64,
+    /// The time of the execution that added the record.
+    pub timestamp_nanos: u64,
+    #[serde(with = "serde_bytes")]
+    pub content: Vec<u8>,
+}
+
+impl From<&CanisterLogRecord> for pb_canister_state_bits::CanisterLogRecord {
+    fn from(item: &CanisterLogRecord) -> Self {
+        Self {
+            idx: item.idx,
+            timestamp_nanos: item.timestamp_nanos,
+            content: item.content.clone(),
+        }
+    }
+}
+
+impl From<pb_canister_state_bits::CanisterLogRecord> for CanisterLogRecord {
+    fn from(item: pb_canister_state_bits::CanisterLogRecord) -> Self {
+        Self {
+            idx: item.idx,
+            timestamp_nanos: item.timestamp_nanos,
+            content: item.content,
+        }
+    }
+}
+
+/// The reply of `fetch_canister_logs`.
+/// `(record {
+///     canister_log_records: vec canister_log_record;
+/// })`
+#[derive(CandidType, Deserialize, Debug, PartialEq, Eq)]
+pub struct FetchCanisterLogsResponse {
+    pub canister_log_records: Vec<CanisterLogRecord>,
+}
+
+impl Payload<'_> for FetchCanisterLogsResponse {}
+
+/// Struct used for encoding/decoding
+/// `(record {
+///     mode : variant { install; reinstall; upgrade };
+///     target_canister: principal;
+///     store_canister: opt principal;
+///     chunk_hashes_list: vec chunk_hash;
+///     wasm_module_hash: blob;
+///     arg: blob;
+/// })`
+///
+/// The Wasm module is the concatenation of the chunks in `chunk_hashes_list`
+/// taken from the chunk store of `store_canister`, which defaults to
+/// `target_canister`. Its hash must be `wasm_module_hash`.
+#[derive(Clone, CandidType, Deserialize, Debug)]
+pub struct InstallChunkedCodeArgs {
+    pub mode: CanisterInstallMode,
+    pub target_canister: PrincipalId,
+    pub store_canister: Option<PrincipalId>,
+    pub chunk_hashes_list: Vec<ChunkHash>,
+    #[serde(with = "serde_bytes")]
+    pub wasm_module_hash: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub arg: Vec<u8>,
+}
+
+impl std::fmt::Display for InstallChunkedCodeArgs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "InstallChunkedCodeArgs {{")?;
+        writeln!(f, "  mode: {:?}", &self.mode)?;
+        writeln!(f, "  target_canister: {:?}", &self.target_canister)?;
+        writeln!(f, "  store_canister: {:?}", &self.store_canister)?;
+        writeln!(f, "  chunk_hashes_list: <{:?} chunks>", self.chunk_hashes_list.len())?;
+        writeln!(f, "  arg: <{:?} bytes>", self.arg.len())?;
+        writeln!(f, "}}")
+    }
+}
+
+impl Payload<'_> for InstallChunkedCodeArgs {}
+
+impl InstallChunkedCodeArgs {
+    pub fn new(
+        mode: CanisterInstallMode,
+        target_canister: CanisterId,
+        store_canister: Option<CanisterId>,
+        chunk_hashes_list: Vec<Vec<u8>>,
+        wasm_module_hash: Vec<u8>,
+        arg: Vec<u8>,
+    ) -> Self {
+        Self {
+            mode,
+            target_canister: target_canister.into(),
+            store_canister: store_canister.map(|canister_id| canister_id.into()),
+            chunk_hashes_list: chunk_hashes_list
+                .into_iter()
+                .map(|hash| ChunkHash { hash })
+                .collect(),
+            wasm_module_hash,
+            arg,
+        }
+    }
+
+    pub fn get_target_canister(&self) -> CanisterId {
+        // Safe as this was converted from CanisterId when Self was constructed.
+        CanisterId::new(self.target_canister).unwrap()
+    }
+
+    /// Returns the canister that holds the chunks: the store canister if
+    /// specified, otherwise the target canister.
+    pub fn get_store_canister(&self) -> CanisterId {
+        match self.store_canister {
+            // Safe as this was converted from CanisterId when Self was constructed.
+            Some(store_canister) => CanisterId::new(store_canister).unwrap(),
+            None => self.get_target_canister(),
+        }
+    }
+}
+
 /// Represents the empty blob.
 #[derive(CandidType, Deserialize)]
 pub struct EmptyBlob;
@@ -503,6 +1075,11 @@ impl Payload<'_> for UpdateSettingsArgs {}
 ///     controllers: opt vec principal;
 ///     compute_allocation: opt nat;
 ///     memory_allocation: opt nat;
+///     freezing_threshold: opt nat;
+///     log_visibility: opt log_visibility;
+///     wasm_memory_threshold: opt nat;
+///     reserved_cycles_limit: opt nat;
+///     wasm_memory_limit: opt nat;
 /// })`
 #[derive(Default, Clone, CandidType, Deserialize, Debug)]
 pub struct CanisterSettingsArgs {
@@ -511,6 +1088,10 @@ pub struct CanisterSettingsArgs {
     pub compute_allocation: Option<candid::Nat>,
     pub memory_allocation: Option<candid::Nat>,
     pub freezing_threshold: Option<candid::Nat>,
+    pub log_visibility: Option<LogVisibility>,
+    pub wasm_memory_threshold: Option<candid::Nat>,
+    pub reserved_cycles_limit: Option<candid::Nat>,
+    pub wasm_memory_limit: Option<candid::Nat>,
 }
 
 impl Payload<'_> for CanisterSettingsArgs {}
@@ -529,6 +1110,10 @@ impl CanisterSettingsArgs {
             compute_allocation: compute_allocation.map(candid::Nat::from),
             memory_allocation: memory_allocation.map(candid::Nat::from),
             freezing_threshold: freezing_threshold.map(candid::Nat::from),
+            log_visibility: None,
+            wasm_memory_threshold: None,
+            reserved_cycles_limit: None,
+            wasm_memory_limit: None,
         }
     }
 }
@@ -458,7 +458,10 @@ pub enum CanisterHttpResponseAttribute {
 
 #[cfg(test)]
 mod tests {
-    use crate::{time::UNIX_EPOCH, Cycles};
+    use crate::{
+        time::{NO_DEADLINE, UNIX_EPOCH},
+        Cycles,
+    };
 
     use super::*;
 
@@ -484,6 +487,7 @@ mod tests {
                 payment: Cycles::new(10),
                 method_name: "tansform".to_string(),
                 method_payload: Vec::new(),
+                deadline: NO_DEADLINE,
             },
             time: UNIX_EPOCH,
         };
@@ -524,6 +528,7 @@ mod tests {
                 payment: Cycles::new(10),
                 method_name: "tansform".to_string(),
                 method_payload: Vec::new(),
+                deadline: NO_DEADLINE,
             },
             time: UNIX_EPOCH,
         };
@@ -25,7 +25,8 @@ pub use ingress_messages::{
     extract_effective_canister_id, Ingress, ParseIngressError, SignedIngress, SignedIngressContent,
 };
 pub use inter_canister::{
-    CallContextId, CallbackId, Payload, RejectContext, Request, RequestOrResponse, Response,
+    truncate_reject_message, CallContextId, CallbackId, Payload, RejectContext, Request,
+    RequestOrResponse, Response, REJECT_MESSAGE_TRUNCATION_SUFFIX,
 };
 pub use message_id::{MessageId, MessageIdError, EXPECTED_MESSAGE_ID_LENGTH};
 pub use query::{AnonymousQuery, AnonymousQueryResponse, AnonymousQueryResponseReply, UserQuery};
@@ -258,6 +259,7 @@ impl SignedRequestBytes {
 mod tests {
     use super::*;
     use crate::time::current_time_and_expiry_time;
+    use ic_error_types::RejectCode;
     use maplit::btreemap;
     use serde_cbor::Value;
     use std::{convert::TryFrom, io::Cursor};
@@ -491,4 +493,33 @@ mod tests {
         let signed_ingress1: SignedIngress = bincode::deserialize_from(&mut buffer).unwrap();
         assert_eq!(signed_ingress, signed_ingress1);
     }
+
+    #[test]
+    fn test_reject_message_truncation() {
+        let reject = |message: &str| RejectContext::new(RejectCode::CanisterReject, message.into());
+
+        let mut short = reject("short");
+        assert!(!short.truncate_message(5));
+        assert_eq!("short", short.message());
+
+        let long_message = "x".repeat(100);
+        let mut long = reject(&long_message);
+        assert!(long.truncate_message(50));
+        assert_eq!(50, long.message().len());
+        assert!(long.message().ends_with(REJECT_MESSAGE_TRUNCATION_SUFFIX));
+
+        // Not even the suffix fits.
+        let mut long = reject(&long_message);
+        assert!(long.truncate_message(3));
+        assert_eq!("xxx", long.message());
+
+        // The message is cut at a character boundary.
+        let mut multibyte = reject(&"ü".repeat(50));
+        let max_len = REJECT_MESSAGE_TRUNCATION_SUFFIX.len() + 5;
+        assert!(multibyte.truncate_message(max_len));
+        assert_eq!(
+            format!("üü{}", REJECT_MESSAGE_TRUNCATION_SUFFIX),
+            multibyte.message()
+        );
+    }
 }
@@ -1,8 +1,12 @@
-use crate::{ingress::WasmResult, CanisterId, CountBytes, Cycles, Funds, NumBytes};
+use crate::{
+    ingress::WasmResult,
+    time::{CoarseTime, NO_DEADLINE},
+    CanisterId, CountBytes, Cycles, Funds, NumBytes, Time,
+};
 use ic_error_types::{RejectCode, TryFromError, UserError};
 use ic_ic00_types::{
-    CanisterIdRecord, InstallCodeArgs, Method, Payload as _, ProvisionalTopUpCanisterArgs,
-    SetControllerArgs, UpdateSettingsArgs,
+    CanisterIdRecord, InstallChunkedCodeArgs, InstallCodeArgs, Method, Payload as _,
+    ProvisionalTopUpCanisterArgs, SetControllerArgs, UpdateSettingsArgs, UploadChunkArgs,
 };
 use ic_protobuf::{
     proxy::{try_from_option_field, ProxyDecodeError},
@@ -44,6 +48,9 @@ pub struct Request {
     pub method_name: String,
     #[serde(with = "serde_bytes")]
     pub method_payload: Vec<u8>,
+    /// The deadline of a best-effort request. `NO_DEADLINE` for guaranteed
+    /// response requests.
+    pub deadline: CoarseTime,
 }
 
 impl Request {
@@ -62,6 +69,17 @@ impl Request {
         &self.method_payload
     }
 
+    /// Returns true if this is a best-effort request, i.e. it has a deadline.
+    pub fn is_best_effort(&self) -> bool {
+        self.deadline != NO_DEADLINE
+    }
+
+    /// Returns true if this is a best-effort request whose deadline is not
+    /// later than `current_time`.
+    pub fn has_expired(&self, current_time: Time) -> bool {
+        self.is_best_effort() && self.deadline.as_time() <= current_time
+    }
+
     /// Returns the size of the user-controlled part of this `Request`,
     /// in bytes.
     pub fn payload_size_bytes(&self) -> NumBytes {
@@ -78,6 +96,10 @@ impl Request {
             | Ok(Method::DeleteCanister)
             | Ok(Method::UninstallCode)
             | Ok(Method::DepositCycles)
+            | Ok(Method::ClearChunkStore)
+            | Ok(Method::StoredChunks)
+            | Ok(Method::FetchCanisterLogs)
+            | Ok(Method::CanisterStoppingStatus)
             | Ok(Method::StopCanister) => match CanisterIdRecord::decode(&self.method_payload) {
                 Ok(record) => Some(record.get_canister_id()),
                 Err(_) => None,
@@ -94,6 +116,16 @@ impl Request {
                 Ok(record) => Some(record.get_canister_id()),
                 Err(_) => None,
             },
+            Ok(Method::InstallChunkedCode) => {
+                match InstallChunkedCodeArgs::decode(&self.method_payload) {
+                    Ok(record) => Some(record.get_target_canister()),
+                    Err(_) => None,
+                }
+            }
+            Ok(Method::UploadChunk) => match UploadChunkArgs::decode(&self.method_payload) {
+                Ok(record) => Some(record.get_canister_id()),
+                Err(_) => None,
+            },
             Ok(Method::ProvisionalTopUpCanister) => {
                 match ProvisionalTopUpCanisterArgs::decode(&self.method_payload) {
                     Ok(record) => Some(record.get_canister_id()),
@@ -131,6 +163,9 @@ impl std::fmt::Debug for Request {
             self.sender_reply_callback
         )?;
         write!(f, "payment: {:?}, ", self.payment)?;
+        if self.is_best_effort() {
+            write!(f, "deadline: {:?}, ", self.deadline)?;
+        }
         if self.method_name.len() <= 103 {
             write!(f, "method_name: {:?}, ", self.method_name)?;
         } else {
@@ -149,6 +184,29 @@ impl std::fmt::Debug for Request {
     }
 }
 
+/// The suffix that marks a reject message as truncated, see
+/// [truncate_reject_message].
+pub const REJECT_MESSAGE_TRUNCATION_SUFFIX: &str = "... [truncated]";
+
+/// Truncates the reject `message` to at most `max_len` bytes, including the
+/// [REJECT_MESSAGE_TRUNCATION_SUFFIX] that is appended to tell the receiver
+/// that the message is incomplete. Returns whether the message was truncated.
+pub fn truncate_reject_message(message: &mut String, max_len: usize) -> bool {
+    if message.len() <= max_len {
+        return false;
+    }
+    let suffix = REJECT_MESSAGE_TRUNCATION_SUFFIX;
+    if max_len < suffix.len() {
+        let len = message.safe_truncate(max_len).len();
+        message.truncate(len);
+    } else {
+        let len = message.safe_truncate(max_len - suffix.len()).len();
+        message.truncate(len);
+        message.push_str(suffix);
+    }
+    true
+}
+
 /// The context attached when an inter-canister message is rejected.
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct RejectContext {
@@ -169,6 +227,12 @@ impl RejectContext {
         Self::new(code, message.safe_truncate(max_msg_len).to_string())
     }
 
+    /// Truncates the message to at most `max_len` bytes, see
+    /// [truncate_reject_message]. Returns whether the message was truncated.
+    pub fn truncate_message(&mut self, max_len: usize) -> bool {
+        truncate_reject_message(&mut self.message, max_len)
+    }
+
     pub fn code(&self) -> RejectCode {
         self.code
     }
@@ -205,7 +269,7 @@ pub enum Payload {
 
 impl Payload {
     /// Returns the size of this `Payload` in bytes.
-    fn size_bytes(&self) -> NumBytes {
+    pub fn size_bytes(&self) -> NumBytes {
         match self {
             Payload::Data(data) => NumBytes::from(data.len() as u64),
             Payload::Reject(context) => context.size_bytes(),
@@ -270,6 +334,9 @@ pub struct Response {
     pub originator_reply_callback: CallbackId,
     pub refund: Cycles,
     pub response_payload: Payload,
+    /// The deadline of the request that this is a response to.
+    /// `NO_DEADLINE` for guaranteed responses.
+    pub deadline: CoarseTime,
 }
 
 impl Response {
@@ -277,6 +344,11 @@ impl Response {
     pub fn payload_size_bytes(&self) -> NumBytes {
         self.response_payload.size_bytes()
     }
+
+    /// Returns true if this is a response to a best-effort request.
+    pub fn is_best_effort(&self) -> bool {
+        self.deadline != NO_DEADLINE
+    }
 }
 
 /// Canister-to-canister message.
@@ -381,6 +453,7 @@ impl From<&Request> for pb_queues::Request {
             method_name: req.method_name.clone(),
             method_payload: req.method_payload.clone(),
             cycles_payment: Some((req.payment).into()),
+            deadline_seconds: req.deadline.as_secs_since_unix_epoch(),
         }
     }
 }
@@ -404,6 +477,7 @@ impl TryFrom<pb_queues::Request> for Request {
             payment,
             method_name: req.method_name,
             method_payload: req.method_payload,
+            deadline: CoarseTime::from_secs_since_unix_epoch(req.deadline_seconds),
         })
     }
 }
@@ -446,6 +520,7 @@ impl From<&Response> for pb_queues::Response {
             refund: Some((&Funds::new(rep.refund)).into()),
             response_payload: Some(p),
             cycles_refund: Some((rep.refund).into()),
+            deadline_seconds: rep.deadline.as_secs_since_unix_epoch(),
         }
     }
 }
@@ -476,6 +551,7 @@ impl TryFrom<pb_queues::Response> for Response {
             originator_reply_callback: rep.originator_reply_callback.into(),
             refund,
             response_payload,
+            deadline: CoarseTime::from_secs_since_unix_epoch(rep.deadline_seconds),
         })
     }
 }
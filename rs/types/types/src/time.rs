@@ -104,6 +104,41 @@ impl fmt::Display for Time {
     }
 }
 
+/// Time since UNIX_EPOCH, in whole seconds. Used for the deadlines of
+/// best-effort messages, where a coarser and more compact representation than
+/// [Time] is sufficient.
+#[derive(
+    Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Serialize, Deserialize,
+)]
+#[cfg_attr(test, derive(Arbitrary))]
+pub struct CoarseTime(u32);
+
+/// The deadline of a guaranteed response message, i.e. no deadline.
+pub const NO_DEADLINE: CoarseTime = CoarseTime(0);
+
+impl CoarseTime {
+    pub const fn from_secs_since_unix_epoch(secs: u32) -> Self {
+        CoarseTime(secs)
+    }
+
+    /// Number of seconds since UNIX EPOCH.
+    pub fn as_secs_since_unix_epoch(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the largest `CoarseTime` that is not later than `time`,
+    /// saturating at `u32::MAX` seconds.
+    pub fn floor(time: Time) -> Self {
+        let secs = time.as_nanos_since_unix_epoch() / 1_000_000_000;
+        CoarseTime(secs.min(u32::MAX as u64) as u32)
+    }
+
+    /// Converts the `CoarseTime` into a [Time].
+    pub fn as_time(self) -> Time {
+        Time::from_nanos_since_unix_epoch(self.0 as u64 * 1_000_000_000)
+    }
+}
+
 /// Returns the current time.
 ///
 /// WARNING: this function should not be used in any deterministic part of the